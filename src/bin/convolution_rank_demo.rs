@@ -93,6 +93,7 @@ fn load_recent_rows(
                 trade_date: row.get(1)?,
                 total_score: row.get::<_, Option<f64>>(2)?.unwrap_or(f64::NAN),
                 rank: row.get(3)?,
+                ..ScoreSummary::default()
             })
         })
         .map_err(|e| format!("查询评分数据失败: {e}"))?;
@@ -0,0 +1,135 @@
+use std::env;
+use std::fs;
+
+use duckdb::Connection;
+use lianghua_rs::data::{ScoreRule, resolve_strategy_path, source_db_path};
+use lianghua_rs::simulate::{
+    rule::{RuleLayerConfig, load_rule_score_matrix_from_db},
+    weight_fit::{
+        WeightFitConfig, WeightFitMethod, format_weight_fit_report, run_weight_fit,
+        write_proposed_strategy,
+    },
+};
+
+const DEFAULT_METHOD: &str = "ridge";
+const DEFAULT_LAMBDA: f64 = 0.1;
+const DEFAULT_FOLDS: usize = 4;
+const DEFAULT_HOLDING_DAYS: usize = 5;
+const DEFAULT_INDEX: &str = "000001.SH";
+const DEFAULT_OUTPUT: &str = "score_rule.proposed.toml";
+const STOCK_ADJ_TYPE: &str = "qfq";
+const INDEX_BETA: f64 = 0.5;
+const CONCEPT_BETA: f64 = 0.2;
+const INDUSTRY_BETA: f64 = 0.0;
+
+fn usage() -> &'static str {
+    "用法: cargo run --bin rule_weight_fit -- \\
+     <source_dir> <start_date> <end_date> [method] [lambda] [folds] [holding_days] [index_ts_code] [output_file]\n\
+     method: ridge / lasso / ic\n\
+     示例: cargo run --bin rule_weight_fit -- \\
+     /path/to/source 20230101 20241231 ridge 0.1 4 5 000001.SH score_rule.proposed.toml"
+}
+
+fn parse_arg<T>(args: &[String], index: usize, default: T, label: &str) -> Result<T, String>
+where
+    T: std::str::FromStr,
+    T::Err: std::fmt::Display,
+{
+    args.get(index)
+        .map(|value| {
+            value
+                .parse::<T>()
+                .map_err(|e| format!("解析{label}失败: {value}, err={e}"))
+        })
+        .transpose()
+        .map(|value| value.unwrap_or(default))
+}
+
+fn main() -> Result<(), String> {
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    if args.len() < 3 || args[0] == "--help" || args[0] == "-h" {
+        println!("{}", usage());
+        return Ok(());
+    }
+
+    let source_dir = &args[0];
+    let start_date = &args[1];
+    let end_date = &args[2];
+    let method = WeightFitMethod::parse(args.get(3).map(String::as_str).unwrap_or(DEFAULT_METHOD))?;
+    let lambda = parse_arg(&args, 4, DEFAULT_LAMBDA, "lambda")?;
+    let folds = parse_arg(&args, 5, DEFAULT_FOLDS, "走步折数")?;
+    let holding_days = parse_arg(&args, 6, DEFAULT_HOLDING_DAYS, "持有交易日数")?;
+    let index_ts_code = args.get(7).map(String::as_str).unwrap_or(DEFAULT_INDEX);
+    let output_file = args.get(8).map(String::as_str).unwrap_or(DEFAULT_OUTPUT);
+    if holding_days == 0 {
+        return Err("holding_days必须>=1".to_string());
+    }
+    if output_file.contains('/') || output_file.contains('\\') || !output_file.ends_with(".toml") {
+        return Err("输出文件名必须以 .toml 结尾且不能包含路径分隔符".to_string());
+    }
+
+    let strategy_path = resolve_strategy_path(source_dir, None);
+    let rule_names = ScoreRule::load_rules(source_dir)?
+        .into_iter()
+        .map(|rule| rule.name)
+        .collect::<Vec<_>>();
+    let source_db = source_db_path(source_dir);
+    let conn = Connection::open(&source_db)
+        .map_err(|e| format!("打开原始库失败: {}, err={e}", source_db.display()))?;
+    let layer_config = RuleLayerConfig {
+        backtest_period: holding_days,
+        ..RuleLayerConfig::default()
+    };
+
+    println!("正在加载规则明细和未来收益……");
+    let days = load_rule_score_matrix_from_db(
+        &conn,
+        source_dir,
+        &rule_names,
+        STOCK_ADJ_TYPE,
+        index_ts_code,
+        INDEX_BETA,
+        CONCEPT_BETA,
+        INDUSTRY_BETA,
+        start_date,
+        end_date,
+        &layer_config,
+    )?;
+    println!(
+        "规则: {}, 交易日: {}, 持有: {holding_days}日, 基准: {index_ts_code}",
+        rule_names.len(),
+        days.len()
+    );
+
+    let report = run_weight_fit(
+        &rule_names,
+        &days,
+        &WeightFitConfig {
+            method,
+            lambda,
+            folds,
+            embargo_days: holding_days,
+        },
+    )?;
+    let report_text = format_weight_fit_report(&report);
+    println!("\n{report_text}");
+
+    let output_path = strategy_path.with_file_name(output_file);
+    let skipped = write_proposed_strategy(&strategy_path, &output_path, &report.rules)?;
+    let report_path = output_path.with_extension("report.txt");
+    fs::write(&report_path, &report_text)
+        .map_err(|e| format!("写入拟合报告失败: path={}, err={e}", report_path.display()))?;
+    if !skipped.is_empty() {
+        println!(
+            "\n以下规则使用截面标准化,points不参与计分,未改写: {}",
+            skipped.join(", ")
+        );
+    }
+    println!(
+        "\n建议策略: {}\n拟合报告: {}",
+        output_path.display(),
+        report_path.display()
+    );
+
+    Ok(())
+}
@@ -425,6 +425,7 @@ mod tests {
                 "20260407".to_string(),
             ],
             cols,
+            text_cols: HashMap::new(),
         }
     }
 
@@ -9,13 +9,15 @@ use crate::{
     data::{
         RowData, RuntimeKeyCollectOptions, chip_change_rule_path,
         collect_assigned_names_from_expr_program, collect_runtime_keys_from_expr_programs,
-        scoring_data::row_into_rt,
+        load_expression_prelude, scoring_data::row_into_rt, stock_expression_type_env,
     },
     expr::{
         eval::{Runtime, Value},
+        func::UserFunctions,
         parser::{Expr, Stmt, Stmts},
         validation::{
-            estimate_expression_warmup, parse_expression_program, validate_expression_functions,
+            check_expression_types, estimate_expression_warmup,
+            parse_expression_program_with_functions, validate_expression_functions,
         },
     },
     utils::utils::round_f64_to_scale,
@@ -252,23 +254,39 @@ impl ChipChangeConfig {
                 path.display()
             )
         })?;
-        Self::from_toml_str(&text)
+        let functions = load_expression_prelude(source_dir)?;
+        Self::from_toml_str_with_functions(&text, &functions)
     }
 
     pub fn from_toml_str(text: &str) -> Result<Self, String> {
+        Self::from_toml_str_with_functions(text, &UserFunctions::default())
+    }
+
+    /// 解析策略文本，`when` 中可以调用公共函数文件里的 `FUNC` 定义。
+    pub fn from_toml_str_with_functions(
+        text: &str,
+        functions: &UserFunctions,
+    ) -> Result<Self, String> {
         let mut config: ChipChangeConfig =
             toml::from_str(text).map_err(|error| format!("筹码变化策略文件格式错误: {error}"))?;
-        config.normalize_and_validate()?;
+        config.normalize_and_validate(functions)?;
         Ok(config)
     }
 
     pub fn compile(&self) -> Result<CompiledChipChangeConfig, String> {
-        self.validate()?;
+        self.compile_with_functions(&UserFunctions::default())
+    }
+
+    pub fn compile_with_functions(
+        &self,
+        functions: &UserFunctions,
+    ) -> Result<CompiledChipChangeConfig, String> {
+        self.validate(functions)?;
 
         let mut strategies = Vec::with_capacity(self.strategy.len());
         for (index, strategy) in self.strategy.iter().enumerate() {
             let n = index + 1;
-            let when_ast = parse_strategy_expression(&strategy.when, n, &strategy.name)?;
+            let when_ast = parse_strategy_expression(&strategy.when, n, &strategy.name, functions)?;
             let direction = strategy.direction;
             let (optimized_when_ast, cached_exprs) =
                 optimize_strategy_program(&when_ast, direction, index);
@@ -298,15 +316,15 @@ impl ChipChangeConfig {
         })
     }
 
-    fn normalize_and_validate(&mut self) -> Result<(), String> {
+    fn normalize_and_validate(&mut self, functions: &UserFunctions) -> Result<(), String> {
         for strategy in &mut self.strategy {
             strategy.name = strategy.name.trim().to_string();
             strategy.when = strategy.when.trim().to_string();
         }
-        self.validate()
+        self.validate(functions)
     }
 
-    fn validate(&self) -> Result<(), String> {
+    fn validate(&self, functions: &UserFunctions) -> Result<(), String> {
         if self.version != 1 {
             return Err(format!(
                 "筹码变化策略文件 version 只支持 1，当前为 {}",
@@ -325,7 +343,7 @@ impl ChipChangeConfig {
             if !strategy.bias.is_finite() {
                 return Err(format!("第{n}个strategy的bias必须是有限数值"));
             }
-            parse_strategy_expression(strategy.when.trim(), n, strategy.name.trim())?;
+            parse_strategy_expression(strategy.when.trim(), n, strategy.name.trim(), functions)?;
         }
 
         Ok(())
@@ -339,7 +357,8 @@ fn round_ratio(value: f64) -> f64 {
 pub fn load_compiled_chip_change_config(
     source_dir: &str,
 ) -> Result<CompiledChipChangeConfig, String> {
-    ChipChangeConfig::load(source_dir)?.compile()
+    let functions = load_expression_prelude(source_dir)?;
+    ChipChangeConfig::load(source_dir)?.compile_with_functions(&functions)
 }
 
 pub fn collect_chen_chip_runtime_keys(chip_config: &CompiledChipChangeConfig) -> HashSet<String> {
@@ -593,15 +612,25 @@ fn parse_strategy_expression(
     expression: &str,
     strategy_index: usize,
     strategy_name: &str,
+    functions: &UserFunctions,
 ) -> Result<Stmts, String> {
-    let program = parse_expression_program(expression).map_err(|error| {
-        format!(
-            "第{strategy_index}个strategy({strategy_name})表达式解析错误在{}:{}",
-            error.idx, error.msg
-        )
-    })?;
+    let program =
+        parse_expression_program_with_functions(expression, functions).map_err(|error| {
+            format!(
+                "第{strategy_index}个strategy({strategy_name})表达式解析错误在{}:{}",
+                error.idx, error.msg
+            )
+        })?;
     validate_expression_functions(&program)
         .map_err(|error| format!("第{strategy_index}个strategy({strategy_name}){error}"))?;
+    check_expression_types(expression, functions, &stock_expression_type_env()).map_err(
+        |error| {
+            format!(
+                "第{strategy_index}个strategy({strategy_name})表达式类型错误在{}..{}:{}",
+                error.start, error.end, error.msg
+            )
+        },
+    )?;
     Ok(program)
 }
 
@@ -689,7 +718,7 @@ fn optimize_strategy_expr(
     }
 
     match expr {
-        Expr::Number(_) | Expr::Ident(_) => expr.clone(),
+        Expr::Number(_) | Expr::Str(_) | Expr::Ident(_) => expr.clone(),
         Expr::Call { name, args } => Expr::Call {
             name: name.clone(),
             args: args
@@ -744,7 +773,7 @@ fn expr_contains_call(expr: &Expr) -> bool {
         Expr::Call { .. } => true,
         Expr::Unary { rhs, .. } => expr_contains_call(rhs),
         Expr::Binary { lhs, rhs, .. } => expr_contains_call(lhs) || expr_contains_call(rhs),
-        Expr::Number(_) | Expr::Ident(_) => false,
+        Expr::Number(_) | Expr::Str(_) | Expr::Ident(_) => false,
     }
 }
 
@@ -754,7 +783,7 @@ fn expr_depends_on_dynamic(
     dynamic_runtime_keys: &[&str],
 ) -> bool {
     match expr {
-        Expr::Number(_) => false,
+        Expr::Number(_) | Expr::Str(_) => false,
         Expr::Ident(name) => local_dynamic.get(name).copied().unwrap_or_else(|| {
             let runtime_key = name.to_ascii_uppercase();
             dynamic_runtime_keys.contains(&runtime_key.as_str())
@@ -2133,6 +2162,7 @@ bias = 1.0
                 "20240108".to_string(),
             ],
             cols,
+            text_cols: HashMap::new(),
         }
     }
 
@@ -2513,6 +2543,7 @@ bias = 1.0
                 ("C".to_string(), vec![Some(10.1)]),
                 ("TOR".to_string(), vec![Some(10.0)]),
             ]),
+            text_cols: HashMap::new(),
         };
         let bars = vec![Some(ChenChipBar {
             trade_date: "20240102".to_string(),
@@ -2592,6 +2623,7 @@ bias = 1.0
                 ("C".to_string(), vec![Some(10.0)]),
                 ("TOR".to_string(), vec![Some(1.0)]),
             ]),
+            text_cols: HashMap::new(),
         };
         let bars = vec![Some(ChenChipBar {
             trade_date: "20240102".to_string(),
@@ -2661,6 +2693,27 @@ bias = 1.0
         assert!(error.contains("表达式解析错误"));
     }
 
+    #[test]
+    fn chip_change_config_rejects_series_window() {
+        let error = ChipChangeConfig::from_toml_str(
+            r#"
+version = 1
+
+[[strategy]]
+name = "dynamic window"
+holder = "retail"
+direction = "sell"
+when = "C > HHV(H, BARSLAST(C > O))"
+bias = 1.0
+"#,
+        )
+        .expect_err("series window should fail");
+        assert_eq!(
+            error,
+            "第1个strategy(dynamic window)表达式类型错误在11..26:HHV的第2个参数需要常量，但拿到数值序列，逐K线变化的周期请改用HHVD"
+        );
+    }
+
     #[test]
     fn chen_chip_returns_empty_when_warmup_is_insufficient() {
         let snapshots = compute_chen_chip_snapshots_from_row_data(
@@ -34,7 +34,10 @@ use crate::{
         cyq_chen_db_path, load_trade_date_list, source_db_path,
     },
     download::runner::{DownloadProgress, DownloadProgressCallback},
-    scoring::tools::{inject_stock_extra_fields, load_st_list, load_total_share_map},
+    scoring::tools::{
+        StockProfile, inject_stock_extra_fields, load_st_list, load_stock_profile_map,
+        load_total_share_map,
+    },
 };
 
 const CYQ_CHEN_SNAPSHOT_TABLE: &str = "cyq_chen_snapshot";
@@ -850,6 +853,7 @@ fn compute_cyq_chen_stock(
     config: ChenChipConfig,
     st_list: &HashSet<String>,
     total_share_map: &HashMap<String, f64>,
+    profile_map: &HashMap<String, StockProfile>,
 ) -> Result<ComputedCyqChenStock, String> {
     if row_data.trade_dates.is_empty() {
         return Ok(ComputedCyqChenStock {
@@ -863,6 +867,7 @@ fn compute_cyq_chen_stock(
         ts_code,
         st_list.contains(ts_code),
         total_share_map.get(ts_code).copied(),
+        profile_map.get(ts_code),
     )?;
 
     let Some(output_start_date) =
@@ -955,6 +960,7 @@ fn compute_cyq_chen_stock_group_batch(
     config: ChenChipConfig,
     st_list: &HashSet<String>,
     total_share_map: &HashMap<String, f64>,
+    profile_map: &HashMap<String, StockProfile>,
     ts_group: &[String],
     on_stock_done: Option<&dyn Fn(&str)>,
 ) -> Result<CyqChenWriteBatch, String> {
@@ -975,6 +981,7 @@ fn compute_cyq_chen_stock_group_batch(
                     RowData {
                         trade_dates: Vec::new(),
                         cols: HashMap::new(),
+                        text_cols: HashMap::new(),
                     }
                 } else {
                     tail
@@ -1033,6 +1040,7 @@ fn compute_cyq_chen_stock_group_batch(
             config,
             st_list,
             total_share_map,
+            profile_map,
         )?;
         if !stock.snapshots.is_empty() {
             batch.stocks.push(stock);
@@ -1729,6 +1737,7 @@ pub fn maintain_cyq_chen_incremental_if_db_exists(
     let required_runtime_keys = collect_chen_chip_runtime_keys(&chip_config);
     let st_list = load_st_list(source_dir).unwrap_or_default();
     let total_share_map = load_total_share_map(source_dir).unwrap_or_default();
+    let profile_map = load_stock_profile_map(source_dir).unwrap_or_default();
     let reader = DataReader::new_with_runtime_keys(source_dir, &required_runtime_keys)?;
     let ts_codes = reader.list_ts_code(DEFAULT_ADJ_TYPE, &load_start_date, &end_date)?;
     let cyq_chen_db_str = cyq_chen_db
@@ -1800,6 +1809,7 @@ pub fn maintain_cyq_chen_incremental_if_db_exists(
                 config,
                 &st_list,
                 &total_share_map,
+                &profile_map,
                 ts_group,
                 Some(&progress_stock_done),
             )?;
@@ -1971,6 +1981,7 @@ pub fn repair_cyq_chen_stocks_if_db_exists(
     let required_runtime_keys = collect_chen_chip_runtime_keys(&chip_config);
     let st_list = load_st_list(source_dir).unwrap_or_default();
     let total_share_map = load_total_share_map(source_dir).unwrap_or_default();
+    let profile_map = load_stock_profile_map(source_dir).unwrap_or_default();
     let cyq_chen_db_str = cyq_chen_db
         .to_str()
         .ok_or_else(|| "筹码库路径不是有效UTF-8".to_string())?
@@ -2040,6 +2051,7 @@ pub fn repair_cyq_chen_stocks_if_db_exists(
                 config,
                 &st_list,
                 &total_share_map,
+                &profile_map,
                 ts_group,
                 Some(&progress_stock_done),
             )?;
@@ -2165,6 +2177,7 @@ pub fn rebuild_cyq_chen_all_with_progress(
     let required_runtime_keys = collect_chen_chip_runtime_keys(&chip_config);
     let st_list = load_st_list(source_dir).unwrap_or_default();
     let total_share_map = load_total_share_map(source_dir).unwrap_or_default();
+    let profile_map = load_stock_profile_map(source_dir).unwrap_or_default();
     let reader = DataReader::new_with_runtime_keys(source_dir, &required_runtime_keys)?;
     let ts_codes = reader.list_ts_code(DEFAULT_ADJ_TYPE, &load_start_date, &end_date)?;
     let rebuild_db = cyq_chen_rebuild_temp_path(&cyq_chen_db)?;
@@ -2232,6 +2245,7 @@ pub fn rebuild_cyq_chen_all_with_progress(
                 config,
                 &st_list,
                 &total_share_map,
+                &profile_map,
                 ts_group,
                 Some(&progress_stock_done),
             )?;
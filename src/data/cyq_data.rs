@@ -454,6 +454,7 @@ fn compute_cyq_stock_group_batch(
                     RowData {
                         trade_dates: Vec::new(),
                         cols: HashMap::new(),
+                        text_cols: HashMap::new(),
                     }
                 } else {
                     tail
@@ -8,6 +8,7 @@ pub mod dragon_tiger_data;
 pub mod scoring_data;
 pub mod simulate;
 mod stock_data_fields;
+pub mod strategy_file;
 
 pub(crate) use stock_data_fields::{STOCK_DATA_KEY_COLUMN_DEFS, STOCK_DATA_RUNTIME_FIELDS};
 
@@ -15,14 +16,28 @@ use std::{
     collections::{HashMap, HashSet},
     fs,
     path::{Path, PathBuf},
+    sync::{Arc, Mutex, OnceLock},
+    time::SystemTime,
 };
 
 use duckdb::{Connection, params};
 use serde::{Deserialize, Deserializer, de};
 
-use crate::expr::{
-    parser::{Expr, Stmt, Stmts},
-    validation::{parse_expression_program, validate_expression_functions},
+use crate::{
+    data::strategy_file::{RuleSource, resolve_strategy_file},
+    expr::{
+        eval::implicit_runtime_keys,
+        func::UserFunctions,
+        parser::{Expr, Stmt, Stmts},
+        validation::{
+            ExprType, ExprTypeEnv, parse_expression_program_with_functions,
+            validate_expression_functions,
+        },
+    },
+    scoring::{
+        graph::{StrategyGraph, rule_expression_sources},
+        rank_order::parse_rank_order,
+    },
 };
 
 pub fn source_db_path(source_dir: &str) -> PathBuf {
@@ -57,6 +72,10 @@ pub fn ths_concepts_path(source_dir: &str) -> PathBuf {
     Path::new(source_dir).join("stock_concepts.csv")
 }
 
+pub fn index_constituents_path(source_dir: &str) -> PathBuf {
+    Path::new(source_dir).join("index_constituents.csv")
+}
+
 pub fn stock_list_path(source_dir: &str) -> PathBuf {
     Path::new(source_dir).join("stock_list.csv")
 }
@@ -85,10 +104,18 @@ pub fn resolve_strategy_path(source_dir: &str, strategy_path: Option<&str>) -> P
     }
 }
 
+pub fn trade_cost_path(source_dir: &str) -> PathBuf {
+    Path::new(source_dir).join("trade_cost.toml")
+}
+
 pub fn ind_toml_path(source_dir: &str) -> PathBuf {
     Path::new(source_dir).join("ind.toml")
 }
 
+pub fn expr_prelude_path(source_dir: &str) -> PathBuf {
+    Path::new(source_dir).join("expr_prelude.txt")
+}
+
 pub fn load_stock_list(source_dir: &str) -> Result<Vec<Vec<String>>, String> {
     let path = stock_list_path(source_dir);
     let mut reader = csv::ReaderBuilder::new()
@@ -138,6 +165,29 @@ pub fn load_ths_concepts_list(source_dir: &str) -> Result<Vec<Vec<String>>, Stri
     Ok(concept_list)
 }
 
+// index_constituents.csv: 表头 index_code,ts_code,一行一只成分股
+pub fn load_index_constituents(source_dir: &str, index_code: &str) -> Result<Vec<String>, String> {
+    let path = index_constituents_path(source_dir);
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(true)
+        .from_path(&path)
+        .map_err(|e| format!("打开index_constituents.csv失败:路径:{:?},错误:{e}", path))?;
+
+    let index_code = index_code.trim();
+    let mut out = Vec::new();
+    for row_result in reader.records() {
+        let row = row_result.map_err(|e| format!("解析index_constituents.csv失败:{e}"))?;
+        let (Some(row_index), Some(ts_code)) = (row.get(0), row.get(1)) else {
+            continue;
+        };
+        if row_index.trim().eq_ignore_ascii_case(index_code) && !ts_code.trim().is_empty() {
+            out.push(ts_code.trim().to_ascii_uppercase());
+        }
+    }
+
+    Ok(out)
+}
+
 pub fn load_ths_concepts_named_map(
     source_dir: &str,
     value_column_names: &[&str],
@@ -193,10 +243,24 @@ pub fn load_ths_concepts_named_map(
 
 // ============================================ 原数据部分 ================================================
 
+// 按股票注入的文本字段,进运行时后是字符串标量
+pub const STOCK_TEXT_RUNTIME_KEYS: [&str; 4] = ["INDUSTRY", "AREA", "BOARD", "CONCEPT"];
+
+// 按股票注入的数值标记,ST/退市股为 1,其余为 0
+pub const STOCK_FLAG_RUNTIME_KEYS: [&str; 1] = ["IS_ST"];
+
+// 表达式静态类型检查用的变量表: 文本字段是字符串, 其余字段按数值序列处理
+pub fn stock_expression_type_env() -> ExprTypeEnv {
+    STOCK_TEXT_RUNTIME_KEYS
+        .iter()
+        .fold(ExprTypeEnv::default(), |env, key| env.with_var(*key, ExprType::STR_SCALAR))
+}
+
 #[derive(Debug, Clone)]
 pub struct RowData {
     pub trade_dates: Vec<String>,
     pub cols: HashMap<String, Vec<Option<f64>>>,
+    pub text_cols: HashMap<String, String>,
 }
 
 impl RowData {
@@ -300,7 +364,7 @@ fn collect_expr_runtime_keys(
     keys: &mut HashSet<String>,
 ) {
     match expr {
-        Expr::Number(_) => {}
+        Expr::Number(_) | Expr::Str(_) => {}
         Expr::Ident(name) => {
             if locals.contains(name) {
                 return;
@@ -311,6 +375,11 @@ fn collect_expr_runtime_keys(
                 keys.insert("C".to_string());
                 return;
             }
+            if STOCK_TEXT_RUNTIME_KEYS.contains(&runtime_key.as_str())
+                || STOCK_FLAG_RUNTIME_KEYS.contains(&runtime_key.as_str())
+            {
+                return;
+            }
             if let Some((_, db_key)) = options
                 .aliases
                 .iter()
@@ -328,7 +397,10 @@ fn collect_expr_runtime_keys(
             }
             keys.insert(runtime_key);
         }
-        Expr::Call { args, .. } => {
+        Expr::Call { name, args } => {
+            for key in implicit_runtime_keys(name, args) {
+                keys.insert((*key).to_string());
+            }
             for arg in args {
                 collect_expr_runtime_keys(arg, locals, options, keys);
             }
@@ -343,11 +415,14 @@ fn collect_expr_runtime_keys(
 
 fn expr_uses_runtime_key(expr: &Expr, locals: &HashSet<String>, target_key: &str) -> bool {
     match expr {
-        Expr::Number(_) => false,
+        Expr::Number(_) | Expr::Str(_) => false,
         Expr::Ident(name) => !locals.contains(name) && name == target_key,
-        Expr::Call { args, .. } => args
-            .iter()
-            .any(|arg| expr_uses_runtime_key(arg, locals, target_key)),
+        Expr::Call { name, args } => {
+            implicit_runtime_keys(name, args).contains(&target_key)
+                || args
+                    .iter()
+                    .any(|arg| expr_uses_runtime_key(arg, locals, target_key))
+        }
         Expr::Unary { rhs, .. } => expr_uses_runtime_key(rhs, locals, target_key),
         Expr::Binary { lhs, rhs, .. } => {
             expr_uses_runtime_key(lhs, locals, target_key)
@@ -572,7 +647,7 @@ impl DataReader {
             }
         }
 
-        let mut out = RowData { trade_dates, cols };
+        let mut out = RowData { trade_dates, cols, text_cols: HashMap::new() };
         self.inject_runtime_index_pct(&mut out)?;
         out.validate()?;
         Ok(out)
@@ -621,7 +696,7 @@ impl DataReader {
             series.reverse();
         }
 
-        let mut out = RowData { trade_dates, cols };
+        let mut out = RowData { trade_dates, cols, text_cols: HashMap::new() };
         self.inject_runtime_index_pct(&mut out)?;
         out.validate()?;
         Ok(out)
@@ -687,6 +762,7 @@ impl DataReader {
                 RowData {
                     trade_dates: Vec::new(),
                     cols,
+                    text_cols: HashMap::new(),
                 }
             });
 
@@ -936,6 +1012,75 @@ pub struct ScoreConfig {
     pub version: u32,
     pub scene: Vec<ScoreScene>,
     pub rule: Vec<ScoreRule>,
+    // 写 rank 时的排序键,如 ["total_score desc", "AMOUNT desc", "J asc"];为空时按J值同分排序
+    #[serde(default)]
+    pub rank_order: Vec<String>,
+    #[serde(default)]
+    pub weighting: ScoreWeighting,
+    // [[define]] 共享的命名序列,规则里直接写名字引用
+    #[serde(default)]
+    pub define: Vec<ScoreDefine>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ScoreDefine {
+    pub name: String,
+    pub expr: String,
+}
+
+// 规则得分计入总分前的处理方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NormalizeMode {
+    // 直接用 points 原始分
+    #[default]
+    Raw,
+    // 当日全市场截面标准分
+    Zscore,
+    // 当日全市场截面百分位(0~1)
+    Percentile,
+}
+
+impl NormalizeMode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Raw => "raw",
+            Self::Zscore => "zscore",
+            Self::Percentile => "percentile",
+        }
+    }
+}
+
+// [weighting] 段: 规则默认的标准化方式,标准化后的放大倍数,以及单条规则贡献的封顶衰减
+#[derive(Debug, Clone, Deserialize)]
+pub struct ScoreWeighting {
+    #[serde(default)]
+    pub normalize: NormalizeMode,
+    #[serde(default = "default_weighting_scale")]
+    pub scale: f64,
+    // 单条规则贡献绝对值超过 cap 的部分乘 decay,decay=0 即硬封顶
+    pub cap: Option<f64>,
+    #[serde(default)]
+    pub decay: f64,
+}
+
+fn default_weighting_scale() -> f64 {
+    1.0
+}
+
+fn default_scene_weight() -> f64 {
+    1.0
+}
+
+impl Default for ScoreWeighting {
+    fn default() -> Self {
+        Self {
+            normalize: NormalizeMode::Raw,
+            scale: default_weighting_scale(),
+            cap: None,
+            decay: 0.0,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -994,6 +1139,9 @@ pub struct ScoreScene {
     pub trigger_threshold: f64,
     pub confirm_threshold: f64,
     pub fail_threshold: f64,
+    // 场景下规则计入总分时的权重,不影响场景阶段判断
+    #[serde(default = "default_scene_weight")]
+    pub weight: f64,
 }
 
 #[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, serde::Serialize)]
@@ -1052,9 +1200,14 @@ pub struct ScoreRule {
     pub points_by_hits: Option<Vec<f64>>,
     pub max_points: Option<f64>,
     pub max_bonus_points: Option<f64>,
+    // 覆盖 [weighting] 里的默认标准化方式
+    pub normalize: Option<NormalizeMode>,
     pub explain: String,
     #[serde(default, skip_deserializing)]
     pub tag: RuleTag,
+    // extends/include 展开后这条规则来自哪个文件
+    #[serde(default, skip_deserializing)]
+    pub source: RuleSource,
 }
 
 impl ScoreConfig {
@@ -1067,19 +1220,32 @@ impl ScoreConfig {
         strategy_path: Option<&str>,
     ) -> Result<ScoreConfig, String> {
         let rule_path = resolve_strategy_path(source_dir, strategy_path);
-        let rule_toml = fs::read_to_string(&rule_path).map_err(|e| {
-            format!(
-                "规则文件不存在或不可读: path={}, err={e}",
-                rule_path.display()
-            )
-        })?;
-        let cfg: ScoreConfig =
-            toml::from_str(&rule_toml).map_err(|e| format!("规则文件格式错误: {e}"))?;
-        Self::validate(&cfg)?;
+        let cfg = Self::load_resolved(&rule_path)?;
+        let functions = load_expression_prelude(source_dir)?;
+        Self::validate_with_functions(&cfg, &functions)?;
+        Ok(cfg)
+    }
+
+    // 展开 extends/include 后的配置,不做校验;每条规则带上来源文件
+    pub fn load_resolved(rule_path: &Path) -> Result<ScoreConfig, String> {
+        let resolved = resolve_strategy_file(rule_path)?;
+        let mut cfg: ScoreConfig = toml::Value::Table(resolved.table)
+            .try_into()
+            .map_err(|e| format!("规则文件格式错误: {e}"))?;
+        for rule in &mut cfg.rule {
+            if let Some(source) = resolved.rule_sources.get(rule.name.trim()) {
+                rule.source = source.clone();
+            }
+        }
         Ok(cfg)
     }
 
+    #[cfg(test)]
     fn validate(cfg: &ScoreConfig) -> Result<(), String> {
+        Self::validate_with_functions(cfg, &UserFunctions::default())
+    }
+
+    fn validate_with_functions(cfg: &ScoreConfig, functions: &UserFunctions) -> Result<(), String> {
         let mut scene_name_set = HashSet::new();
         for (i, scene) in cfg.scene.iter().enumerate() {
             let n = i + 1;
@@ -1110,6 +1276,9 @@ impl ScoreConfig {
             if scene.fail_threshold <= 0.0 {
                 return Err(format!("第{n}个scene的fail_threshold必须>0"));
             }
+            if !scene.weight.is_finite() || scene.weight < 0.0 {
+                return Err(format!("第{n}个scene的weight必须>=0"));
+            }
             if !scene_name_set.insert(scene.name.trim().to_string()) {
                 return Err(format!("scene名称重复: {}", scene.name));
             }
@@ -1139,20 +1308,61 @@ impl ScoreConfig {
             };
 
             match r.kind {
-                RuleKind::Single => validate_single_score_rule(r, n)?,
-                RuleKind::Combination => validate_combination_score_rule(r, n)?,
+                RuleKind::Single => validate_single_score_rule(r, n, functions)?,
+                RuleKind::Combination => validate_combination_score_rule(r, n, functions)?,
+            }
+        }
+
+        let weighting = &cfg.weighting;
+        if !weighting.scale.is_finite() || weighting.scale <= 0.0 {
+            return Err("weighting.scale必须>0".to_string());
+        }
+        if weighting
+            .cap
+            .is_some_and(|cap| !cap.is_finite() || cap <= 0.0)
+        {
+            return Err("weighting.cap必须>0".to_string());
+        }
+        if !(0.0..=1.0).contains(&weighting.decay) {
+            return Err("weighting.decay必须在0到1之间".to_string());
+        }
+
+        // 共享定义和 RULE("规则名") 引用不能成环
+        let graph = StrategyGraph::build(cfg, functions)?;
+        // 类型检查要用共享定义的类型,和规则缓存走同一个入口
+        for (i, r) in rules.iter().enumerate() {
+            for source in rule_expression_sources(r) {
+                graph
+                    .check_expression(source.trim(), functions)
+                    .map_err(|error| format!("第{}条规则({}){error}", i + 1, r.name.trim()))?;
+            }
+        }
+        for key in parse_rank_order(&cfg.rank_order)? {
+            if key.is_total_score() {
+                continue;
             }
+            graph
+                .check_expression(&key.source, functions)
+                .map_err(|error| format!("rank_order排序键({}){error}", key.source))?;
         }
         Ok(())
     }
 }
 
-fn validate_single_score_rule(rule: &ScoreRule, rule_index: usize) -> Result<(), String> {
+fn validate_single_score_rule(
+    rule: &ScoreRule,
+    rule_index: usize,
+    functions: &UserFunctions,
+) -> Result<(), String> {
     if rule.when.trim().is_empty() {
         return Err(format!("第{rule_index}个表达式when字段为空"));
     }
-    let _ =
-        parse_and_validate_score_rule_expression(rule.when.trim(), rule_index, rule.name.trim())?;
+    let _ = parse_and_validate_score_rule_expression(
+        rule.when.trim(),
+        rule_index,
+        rule.name.trim(),
+        functions,
+    )?;
     if !rule.points.is_finite() {
         return Err(format!("第{rule_index}条规则 points 非法"));
     }
@@ -1208,7 +1418,11 @@ fn validate_single_score_rule(rule: &ScoreRule, rule_index: usize) -> Result<(),
     Ok(())
 }
 
-fn validate_combination_score_rule(rule: &ScoreRule, rule_index: usize) -> Result<(), String> {
+fn validate_combination_score_rule(
+    rule: &ScoreRule,
+    rule_index: usize,
+    functions: &UserFunctions,
+) -> Result<(), String> {
     if !rule.when.trim().is_empty() || rule.points != 0.0 || rule.dist_points.is_some() {
         return Err(format!(
             "第{rule_index}条组合规则不能配置 when、points 或 dist_points"
@@ -1276,6 +1490,7 @@ fn validate_combination_score_rule(rule: &ScoreRule, rule_index: usize) -> Resul
             condition.when.trim(),
             rule_index,
             &expression_name,
+            functions,
         )?;
         if !condition.bonus_points.is_finite() {
             return Err(format!(
@@ -1306,13 +1521,15 @@ fn parse_and_validate_score_rule_expression(
     expression: &str,
     rule_index: usize,
     rule_name: &str,
+    functions: &UserFunctions,
 ) -> Result<Stmts, String> {
-    let stmts = parse_expression_program(expression).map_err(|error| {
-        format!(
-            "第{rule_index}条规则({rule_name})表达式解析错误在{}:{}",
-            error.idx, error.msg
-        )
-    })?;
+    let stmts =
+        parse_expression_program_with_functions(expression, functions).map_err(|error| {
+            format!(
+                "第{rule_index}条规则({rule_name})表达式解析错误在{}:{}",
+                error.idx, error.msg
+            )
+        })?;
     validate_expression_functions(&stmts)
         .map_err(|error| format!("第{rule_index}条规则({rule_name}){error}"))?;
     Ok(stmts)
@@ -1416,7 +1633,7 @@ pub struct IndsData {
     pub ind: Vec<IndData>,
 }
 
-#[derive(serde::Deserialize)]
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
 pub struct IndData {
     pub name: String,
     pub expr: String,
@@ -1488,6 +1705,84 @@ impl IndsData {
     }
 }
 
+// ============================================ 公共函数部分 ================================================
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ExprPreludeFileStamp {
+    modified: Option<SystemTime>,
+    len: u64,
+}
+
+struct ExprPreludeCacheEntry {
+    stamp: Option<ExprPreludeFileStamp>,
+    functions: Arc<UserFunctions>,
+}
+
+static EXPR_PRELUDE_CACHE: OnceLock<Mutex<HashMap<PathBuf, ExprPreludeCacheEntry>>> =
+    OnceLock::new();
+
+fn expr_prelude_cache() -> &'static Mutex<HashMap<PathBuf, ExprPreludeCacheEntry>> {
+    EXPR_PRELUDE_CACHE.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+fn expr_prelude_file_stamp(path: &Path) -> Option<ExprPreludeFileStamp> {
+    let metadata = fs::metadata(path).ok()?;
+    Some(ExprPreludeFileStamp {
+        modified: metadata.modified().ok(),
+        len: metadata.len(),
+    })
+}
+
+/// Load the shared `FUNC` definitions stored next to `score_rule.toml`.
+///
+/// A missing prelude file yields an empty function set. The parsed set is
+/// cached per file stamp, so callers can compare the returned `Arc` to tell
+/// whether the prelude changed since they compiled.
+pub fn load_expression_prelude(source_dir: &str) -> Result<Arc<UserFunctions>, String> {
+    let path = expr_prelude_path(source_dir);
+    let stamp = expr_prelude_file_stamp(&path);
+
+    {
+        let cache = expr_prelude_cache()
+            .lock()
+            .map_err(|_| "公共函数缓存锁已中毒".to_string())?;
+        if let Some(entry) = cache.get(&path)
+            && entry.stamp == stamp
+        {
+            return Ok(entry.functions.clone());
+        }
+    }
+
+    let functions = match stamp {
+        None => UserFunctions::default(),
+        Some(_) => {
+            let text = fs::read_to_string(&path)
+                .map_err(|e| format!("公共函数文件不可读: path={}, err={e}", path.display()))?;
+            UserFunctions::parse_prelude(&text).map_err(|error| {
+                format!(
+                    "公共函数文件解析错误在{}:{}, path={}",
+                    error.idx,
+                    error.msg,
+                    path.display()
+                )
+            })?
+        }
+    };
+    let functions = Arc::new(functions);
+
+    expr_prelude_cache()
+        .lock()
+        .map_err(|_| "公共函数缓存锁已中毒".to_string())?
+        .insert(
+            path,
+            ExprPreludeCacheEntry {
+                stamp,
+                functions: functions.clone(),
+            },
+        );
+    Ok(functions)
+}
+
 #[cfg(test)]
 mod tests {
     use std::collections::HashSet;
@@ -1497,6 +1792,7 @@ mod tests {
 
     use super::{
         DataReader, RuleTag, ScopeWay, ScoreConfig, collect_assigned_names_from_expr_program,
+        load_expression_prelude,
     };
     use duckdb::{Connection, params};
 
@@ -1603,6 +1899,131 @@ explain = "test"
         assert!(error.contains("第1条规则(未知函数)表达式引用未知函数"));
     }
 
+    #[test]
+    fn score_config_type_checks_rules_like_rule_cache() {
+        let text = |when: &str| {
+            format!(
+                r#"
+version = 1
+
+[[scene]]
+name = "趋势启动"
+direction = "long"
+observe_threshold = 1.0
+trigger_threshold = 2.0
+confirm_threshold = 3.0
+fail_threshold = 1.0
+
+[[define]]
+name = "UP"
+expr = "C > O"
+
+[[rule]]
+name = "类型测试"
+scene = "趋势启动"
+stage = "base"
+scope_windows = 1
+scope_way = "LAST"
+when = "{when}"
+points = 2.0
+explain = "test"
+"#
+            )
+        };
+
+        // 布尔按 0/1 和数值比较, 与运行期一致
+        let cfg = parse_score_config(&text("CROSS(C, MA(C, 5)) > 0 AND UP == 1"));
+        ScoreConfig::validate(&cfg).expect("bool compared with number should validate");
+
+        let cfg = parse_score_config(&text("UP == 'Y'"));
+        let error = ScoreConfig::validate(&cfg).expect_err("string comparison should fail");
+        assert!(error.contains("第1条规则(类型测试)类型错误在0..9"), "{error}");
+    }
+
+    #[test]
+    fn score_config_validates_rank_order_expressions() {
+        let text = |rank_order: &str| {
+            format!(
+                r#"
+version = 1
+rank_order = {rank_order}
+
+[[scene]]
+name = "趋势启动"
+direction = "long"
+observe_threshold = 1.0
+trigger_threshold = 2.0
+confirm_threshold = 3.0
+fail_threshold = 1.0
+
+[[rule]]
+name = "启动测试"
+scene = "趋势启动"
+stage = "base"
+scope_windows = 1
+scope_way = "LAST"
+when = "C > O"
+points = 2.0
+explain = "test"
+"#
+            )
+        };
+
+        let cfg = parse_score_config(&text(r#"["total_score desc", "AMOUNT desc", "J asc"]"#));
+        assert_eq!(cfg.rank_order.len(), 3);
+        ScoreConfig::validate(&cfg).expect("rank order should validate");
+
+        let cfg = parse_score_config(&text(r#"["NOT_A_FUNCTION(C) desc"]"#));
+        let error = ScoreConfig::validate(&cfg).expect_err("unknown function should fail");
+        assert!(error.contains("rank_order排序键(NOT_A_FUNCTION(C))"));
+    }
+
+    #[test]
+    fn score_config_resolves_functions_from_expression_prelude() {
+        let source_dir = temp_dir_path("expr-prelude");
+        create_dir_all(&source_dir).expect("create temp dir");
+        std::fs::write(
+            source_dir.join("score_rule.toml"),
+            r#"
+version = 1
+
+[[scene]]
+name = "趋势启动"
+direction = "long"
+observe_threshold = 1.0
+trigger_threshold = 2.0
+confirm_threshold = 3.0
+fail_threshold = 1.0
+
+[[rule]]
+name = "放量突破"
+scene = "趋势启动"
+stage = "base"
+scope_windows = 1
+scope_way = "LAST"
+when = "VBREAK(5)"
+points = 2.0
+explain = "test"
+"#,
+        )
+        .expect("write strategy");
+        let source = source_dir.to_str().expect("utf8 path");
+
+        let error = ScoreConfig::load(source).expect_err("missing prelude leaves call unknown");
+        assert!(error.contains("表达式引用未知函数: VBREAK"));
+
+        std::fs::write(
+            source_dir.join("expr_prelude.txt"),
+            "FUNC VBREAK(N) := V > MA(V, N) * 2;\n",
+        )
+        .expect("write prelude");
+        let functions = load_expression_prelude(source).expect("prelude should load");
+        assert!(functions.contains("vbreak"));
+        ScoreConfig::load(source).expect("prelude function should resolve");
+
+        remove_dir_all(&source_dir).expect("cleanup temp dir");
+    }
+
     #[test]
     fn score_config_allows_max_points_only_for_single_each_rule() {
         let mut cfg = parse_score_config(
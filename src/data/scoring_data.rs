@@ -5,9 +5,9 @@ use duckdb::{
         datatypes::{DataType, Field, Schema},
         record_batch::RecordBatch,
     },
-    params,
+    params, params_from_iter,
 };
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::fs::create_dir_all;
 use std::path::Path;
 use std::sync::{Arc, mpsc::Receiver};
@@ -15,12 +15,23 @@ use std::time;
 // use std::fs::File;
 // use std::io::{BufWriter, Write};
 
-use crate::data::{RowData, RuleKind, ScoreRule, collect_assigned_names_from_expr_program};
+use crate::data::{
+    NormalizeMode, RowData, RuleKind, SceneDirection, ScoreConfig,
+    collect_assigned_names_from_expr_program, load_expression_prelude,
+};
 use crate::expr::eval::{Runtime, Value};
-use crate::expr::validation::{parse_expression_program, validate_expression_functions};
+use crate::expr::func::UserFunctions;
 use crate::scoring::{
     CachedCombinationCondition, CachedCombinationRule, CachedRule, CachedRuleExpression,
-    RuleScoreSeries, SceneScoreSeries, TieBreakWay,
+    RuleSceneMeta, RuleScoreSeries, SceneScoreSeries, ScoreTotals, TieBreakWay,
+    build_scene_transitions,
+    graph::StrategyGraph,
+    incremental::{RuleFingerprint, SceneFingerprint, ScoringState, StrategyFingerprint},
+    rank_order::{
+        RankKeySpec, RankKeyValues, RankOrder, net_score_sql, parse_rank_order, rank_key_joins_sql,
+        rank_order_by_sql,
+    },
+    weighting::RuleWeighting,
 };
 
 #[derive(Debug, Default, Clone)]
@@ -29,6 +40,10 @@ pub struct ScoreSummary {
     pub trade_date: String,
     pub total_score: f64,
     pub rank: Option<i64>,
+    // 空头场景规则取反后的风险分,净分 = total_score - risk_score
+    pub risk_score: f64,
+    // 只给风险分大于0的股票排名
+    pub risk_rank: Option<i64>,
 }
 
 #[derive(Debug, Default, Clone)]
@@ -54,11 +69,61 @@ pub struct SceneDetails {
     pub scene_rank: Option<i64>,
 }
 
+// 场景阶段变化事件,rule_names 是推动本次变化的规则,逗号分隔
+#[derive(Debug, Default, Clone)]
+pub struct SceneTransition {
+    pub ts_code: String,
+    pub trade_date: String,
+    pub scene_name: String,
+    pub direction: String,
+    pub from_stage: Option<String>,
+    pub to_stage: String,
+    pub prev_stage_days: i32,
+    pub rule_names: String,
+}
+
+// 策略 rank_order 里表达式排序键的逐日取值,key_index 对应 score_rank_order 的下标
+#[derive(Debug, Default, Clone)]
+pub struct ScoreRankKey {
+    pub ts_code: String,
+    pub trade_date: String,
+    pub key_index: i32,
+    pub key_value: f64,
+}
+
+impl ScoreRankKey {
+    // key_values 是整段序列,从 keep_from 起和 trade_dates 对齐;空值不落库
+    pub fn build(
+        ts_code: &str,
+        trade_dates: &[String],
+        keep_from: usize,
+        key_values: &[RankKeyValues],
+    ) -> Vec<Self> {
+        let mut out = Vec::new();
+        for (key_index, values) in key_values {
+            for (trade_date, value) in trade_dates.iter().zip(values.iter().skip(keep_from)) {
+                let Some(key_value) = value.filter(|value| value.is_finite()) else {
+                    continue;
+                };
+                out.push(Self {
+                    ts_code: ts_code.to_string(),
+                    trade_date: trade_date.clone(),
+                    key_index: *key_index as i32,
+                    key_value,
+                });
+            }
+        }
+        out
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct ScoreBatch {
     pub summary_rows: Vec<ScoreSummary>,
     pub detail_rows: Vec<ScoreDetails>,
     pub scene_rows: Vec<SceneDetails>,
+    pub transition_rows: Vec<SceneTransition>,
+    pub rank_key_rows: Vec<ScoreRankKey>,
 }
 
 impl ScoreBatch {
@@ -66,6 +131,8 @@ impl ScoreBatch {
         self.summary_rows.extend(other.summary_rows);
         self.detail_rows.extend(other.detail_rows);
         self.scene_rows.extend(other.scene_rows);
+        self.transition_rows.extend(other.transition_rows);
+        self.rank_key_rows.extend(other.rank_key_rows);
     }
 }
 
@@ -92,21 +159,126 @@ pub struct ScoreWriteProfile {
 const SCORE_SUMMARY_TABLE: &str = "score_summary";
 const RULE_DETAILS_TABLE: &str = "rule_details";
 const SCENE_DETAILS_TABLE: &str = "scene_details";
+const SCENE_TRANSITIONS_TABLE: &str = "scene_transitions";
+const SCORE_RULE_STATE_TABLE: &str = "score_rule_state";
+const SCORE_SCENE_STATE_TABLE: &str = "score_scene_state";
+const SCORE_STOCK_STATE_TABLE: &str = "score_stock_state";
+const SCORE_RANK_ORDER_TABLE: &str = "score_rank_order";
+const SCORE_RULE_WEIGHTING_TABLE: &str = "score_rule_weighting";
+const SCORE_RANK_KEYS_TABLE: &str = "score_rank_keys";
+const SCORE_RUN_TABLE: &str = "score_run";
+const SCORE_RUN_SUMMARY_TABLE: &str = "score_run_summary";
+const SCORE_RUN_RULE_DETAILS_TABLE: &str = "score_run_rule_details";
+
+// 一次评分的版本信息,结果按run_id另存一份,供不同策略版本对比
+#[derive(Debug, Clone, Default)]
+pub struct ScoreRunMeta {
+    pub run_id: String,
+    pub strategy_hash: String,
+    pub strategy_path: String,
+    pub adj_type: String,
+    pub start_date: String,
+    pub end_date: String,
+    pub created_at: String,
+}
+
+// 两个版本在同一只股票同一天的排名对比
+#[derive(Debug, Clone, Default)]
+pub struct ScoreRunRankDiff {
+    pub ts_code: String,
+    pub trade_date: String,
+    pub base_score: Option<f64>,
+    pub target_score: Option<f64>,
+    pub base_rank: Option<i64>,
+    pub target_rank: Option<i64>,
+}
+
+// 两个版本在同一只股票同一天命中的规则差异
+#[derive(Debug, Clone, Default)]
+pub struct ScoreRunRuleDiff {
+    pub ts_code: String,
+    pub trade_date: String,
+    pub gained_rules: Vec<String>,
+    pub lost_rules: Vec<String>,
+}
+
+// 规则在两个版本对比区间内的命中次数
+#[derive(Debug, Clone, Default)]
+pub struct ScoreRunRuleCount {
+    pub rule_name: String,
+    pub base_trigger_count: i64,
+    pub target_trigger_count: i64,
+}
+
+// 结果库的写入范围
+#[derive(Debug, Clone)]
+pub enum ScoreWriteScope {
+    // 整段区间先删后写,排名只看本次写入的行
+    Range {
+        start_date: String,
+        end_date: String,
+    },
+    // 每只股票从各自的起始日重写到end_date,其它股票的旧行保留并一起重排
+    Stocks {
+        stock_starts: Vec<(String, String)>,
+        end_date: String,
+    },
+    // 只重写部分规则和场景的明细,总分按新旧规则得分差额修正
+    Rules {
+        rule_names: Vec<String>,
+        scene_names: Vec<String>,
+        start_date: String,
+        end_date: String,
+    },
+}
+
+impl ScoreWriteScope {
+    fn date_range(&self) -> (&str, &str) {
+        match self {
+            Self::Range {
+                start_date,
+                end_date,
+            }
+            | Self::Rules {
+                start_date,
+                end_date,
+                ..
+            } => (start_date, end_date),
+            Self::Stocks {
+                stock_starts,
+                end_date,
+            } => {
+                let start_date = stock_starts
+                    .iter()
+                    .map(|(_, start_date)| start_date.as_str())
+                    .min()
+                    .unwrap_or(end_date.as_str());
+                (start_date, end_date)
+            }
+        }
+    }
+}
 
 impl ScoreSummary {
-    pub fn build(ts_code: &str, trade_dates: &[String], total_scores: &[f64]) -> Vec<Self> {
+    pub fn build(ts_code: &str, trade_dates: &[String], totals: &ScoreTotals) -> Vec<Self> {
         let mut sum: Vec<Self> = Vec::new();
-        for i in 0..trade_dates.len() {
+        for (i, trade_date) in trade_dates.iter().enumerate() {
             let mut score = Self::default();
             score.ts_code = ts_code.to_string();
-            score.trade_date = trade_dates[i].clone();
-            score.total_score = total_scores[i];
+            score.trade_date = trade_date.clone();
+            score.total_score = totals.total[i];
             score.rank = None;
+            score.risk_score = totals.risk[i];
+            score.risk_rank = None;
             sum.push(score);
         }
         sum
     }
 
+    pub fn net_score(&self) -> f64 {
+        self.total_score - self.risk_score
+    }
+
     // pub fn write_csv(path: &str, rows: &[ScoreSummary]) -> Result<(), String> {
     //     let file = File::create(path).map_err(|e| format!("创建文件失败: {e}"))?;
 
@@ -273,6 +445,38 @@ impl SceneDetails {
     }
 }
 
+impl SceneTransition {
+    // trade_dates 和各序列都是含预热段的整段,keep_from 之前只用来判断前一阶段
+    pub fn build(
+        ts_code: &str,
+        trade_dates: &[String],
+        keep_from: usize,
+        rule_scene_meta: &[RuleSceneMeta],
+        rule_details: &[RuleScoreSeries],
+        scene_score_series: &[SceneScoreSeries],
+    ) -> Vec<SceneTransition> {
+        let mut out = Vec::new();
+        for scene in scene_score_series {
+            if trade_dates.len() != scene.stage.len() {
+                continue;
+            }
+            for point in build_scene_transitions(rule_scene_meta, rule_details, scene, keep_from) {
+                out.push(SceneTransition {
+                    ts_code: ts_code.to_string(),
+                    trade_date: trade_dates[point.index].clone(),
+                    scene_name: scene.name.clone(),
+                    direction: scene.direction.as_str().to_string(),
+                    from_stage: point.from_stage,
+                    to_stage: point.to_stage,
+                    prev_stage_days: point.prev_stage_days as i32,
+                    rule_names: point.rule_names.join(","),
+                });
+            }
+        }
+        out
+    }
+}
+
 pub fn init_result_db(db_path: &Path) -> Result<(), String> {
     let db_file = Path::new(db_path);
     if let Some(parent_dir) = db_file.parent() {
@@ -286,6 +490,16 @@ pub fn init_result_db(db_path: &Path) -> Result<(), String> {
     ensure_result_table_schema(&conn, SCORE_SUMMARY_TABLE)?;
     ensure_result_table_schema(&conn, RULE_DETAILS_TABLE)?;
     ensure_result_table_schema(&conn, SCENE_DETAILS_TABLE)?;
+    ensure_result_table_schema(&conn, SCENE_TRANSITIONS_TABLE)?;
+    ensure_result_table_schema(&conn, SCORE_RULE_STATE_TABLE)?;
+    ensure_result_table_schema(&conn, SCORE_SCENE_STATE_TABLE)?;
+    ensure_result_table_schema(&conn, SCORE_STOCK_STATE_TABLE)?;
+    ensure_result_table_schema(&conn, SCORE_RANK_ORDER_TABLE)?;
+    ensure_result_table_schema(&conn, SCORE_RULE_WEIGHTING_TABLE)?;
+    ensure_result_table_schema(&conn, SCORE_RANK_KEYS_TABLE)?;
+    ensure_result_table_schema(&conn, SCORE_RUN_TABLE)?;
+    ensure_result_table_schema(&conn, SCORE_RUN_SUMMARY_TABLE)?;
+    ensure_result_table_schema(&conn, SCORE_RUN_RULE_DETAILS_TABLE)?;
 
     Ok(())
 }
@@ -299,6 +513,9 @@ fn create_result_table(conn: &Connection, table_name: &str) -> Result<(), String
                 trade_date VARCHAR,
                 total_score DOUBLE,
                 rank INTEGER,
+                risk_score DOUBLE,
+                net_score DOUBLE,
+                risk_rank INTEGER,
                 PRIMARY KEY (ts_code, trade_date)
             )
             "#
@@ -331,6 +548,122 @@ fn create_result_table(conn: &Connection, table_name: &str) -> Result<(), String
             )
             "#
         ),
+        SCENE_TRANSITIONS_TABLE => format!(
+            r#"
+            CREATE TABLE IF NOT EXISTS {table_name} (
+                ts_code VARCHAR,
+                trade_date VARCHAR,
+                scene_name VARCHAR,
+                direction VARCHAR,
+                from_stage VARCHAR,
+                to_stage VARCHAR,
+                prev_stage_days INTEGER,
+                rule_names VARCHAR,
+                PRIMARY KEY (ts_code, trade_date, scene_name)
+            )
+            "#
+        ),
+        SCORE_RULE_STATE_TABLE => format!(
+            r#"
+            CREATE TABLE IF NOT EXISTS {table_name} (
+                rule_name VARCHAR,
+                scene_name VARCHAR,
+                fingerprint VARCHAR,
+                PRIMARY KEY (rule_name)
+            )
+            "#
+        ),
+        SCORE_SCENE_STATE_TABLE => format!(
+            r#"
+            CREATE TABLE IF NOT EXISTS {table_name} (
+                scene_name VARCHAR,
+                fingerprint VARCHAR,
+                PRIMARY KEY (scene_name)
+            )
+            "#
+        ),
+        SCORE_STOCK_STATE_TABLE => format!(
+            r#"
+            CREATE TABLE IF NOT EXISTS {table_name} (
+                ts_code VARCHAR,
+                last_trade_date VARCHAR,
+                PRIMARY KEY (ts_code)
+            )
+            "#
+        ),
+        SCORE_RANK_ORDER_TABLE => format!(
+            r#"
+            CREATE TABLE IF NOT EXISTS {table_name} (
+                key_index INTEGER,
+                source VARCHAR,
+                descending BOOLEAN,
+                PRIMARY KEY (key_index)
+            )
+            "#
+        ),
+        SCORE_RULE_WEIGHTING_TABLE => format!(
+            r#"
+            CREATE TABLE IF NOT EXISTS {table_name} (
+                rule_name VARCHAR,
+                normalize VARCHAR,
+                weight DOUBLE,
+                scale DOUBLE,
+                cap DOUBLE,
+                decay DOUBLE,
+                direction VARCHAR,
+                PRIMARY KEY (rule_name)
+            )
+            "#
+        ),
+        SCORE_RANK_KEYS_TABLE => format!(
+            r#"
+            CREATE TABLE IF NOT EXISTS {table_name} (
+                ts_code VARCHAR,
+                trade_date VARCHAR,
+                key_index INTEGER,
+                key_value DOUBLE,
+                PRIMARY KEY (ts_code, trade_date, key_index)
+            )
+            "#
+        ),
+        SCORE_RUN_TABLE => format!(
+            r#"
+            CREATE TABLE IF NOT EXISTS {table_name} (
+                run_id VARCHAR,
+                strategy_hash VARCHAR,
+                strategy_path VARCHAR,
+                adj_type VARCHAR,
+                start_date VARCHAR,
+                end_date VARCHAR,
+                created_at VARCHAR,
+                PRIMARY KEY (run_id)
+            )
+            "#
+        ),
+        SCORE_RUN_SUMMARY_TABLE => format!(
+            r#"
+            CREATE TABLE IF NOT EXISTS {table_name} (
+                run_id VARCHAR,
+                ts_code VARCHAR,
+                trade_date VARCHAR,
+                total_score DOUBLE,
+                rank INTEGER,
+                PRIMARY KEY (run_id, ts_code, trade_date)
+            )
+            "#
+        ),
+        SCORE_RUN_RULE_DETAILS_TABLE => format!(
+            r#"
+            CREATE TABLE IF NOT EXISTS {table_name} (
+                run_id VARCHAR,
+                ts_code VARCHAR,
+                trade_date VARCHAR,
+                rule_name VARCHAR,
+                rule_score DOUBLE,
+                PRIMARY KEY (run_id, ts_code, trade_date, rule_name)
+            )
+            "#
+        ),
         _ => return Err(format!("不支持的结果表:{table_name}")),
     };
 
@@ -341,7 +674,15 @@ fn create_result_table(conn: &Connection, table_name: &str) -> Result<(), String
 
 fn result_table_expected_columns(table_name: &str) -> Result<Vec<&'static str>, String> {
     match table_name {
-        SCORE_SUMMARY_TABLE => Ok(vec!["ts_code", "trade_date", "total_score", "rank"]),
+        SCORE_SUMMARY_TABLE => Ok(vec![
+            "ts_code",
+            "trade_date",
+            "total_score",
+            "rank",
+            "risk_score",
+            "net_score",
+            "risk_rank",
+        ]),
         RULE_DETAILS_TABLE => Ok(vec!["ts_code", "trade_date", "rule_name", "rule_score"]),
         SCENE_DETAILS_TABLE => Ok(vec![
             "ts_code",
@@ -355,6 +696,53 @@ fn result_table_expected_columns(table_name: &str) -> Result<Vec<&'static str>,
             "risk_intensity",
             "scene_rank",
         ]),
+        SCENE_TRANSITIONS_TABLE => Ok(vec![
+            "ts_code",
+            "trade_date",
+            "scene_name",
+            "direction",
+            "from_stage",
+            "to_stage",
+            "prev_stage_days",
+            "rule_names",
+        ]),
+        SCORE_RULE_STATE_TABLE => Ok(vec!["rule_name", "scene_name", "fingerprint"]),
+        SCORE_SCENE_STATE_TABLE => Ok(vec!["scene_name", "fingerprint"]),
+        SCORE_STOCK_STATE_TABLE => Ok(vec!["ts_code", "last_trade_date"]),
+        SCORE_RANK_ORDER_TABLE => Ok(vec!["key_index", "source", "descending"]),
+        SCORE_RANK_KEYS_TABLE => Ok(vec!["ts_code", "trade_date", "key_index", "key_value"]),
+        SCORE_RULE_WEIGHTING_TABLE => Ok(vec![
+            "rule_name",
+            "normalize",
+            "weight",
+            "scale",
+            "cap",
+            "decay",
+            "direction",
+        ]),
+        SCORE_RUN_TABLE => Ok(vec![
+            "run_id",
+            "strategy_hash",
+            "strategy_path",
+            "adj_type",
+            "start_date",
+            "end_date",
+            "created_at",
+        ]),
+        SCORE_RUN_SUMMARY_TABLE => Ok(vec![
+            "run_id",
+            "ts_code",
+            "trade_date",
+            "total_score",
+            "rank",
+        ]),
+        SCORE_RUN_RULE_DETAILS_TABLE => Ok(vec![
+            "run_id",
+            "ts_code",
+            "trade_date",
+            "rule_name",
+            "rule_score",
+        ]),
         _ => Err(format!("不支持的结果表:{table_name}")),
     }
 }
@@ -460,6 +848,11 @@ fn drop_result_db_indexes(conn: &Connection) -> Result<(), String> {
         .map_err(|e| format!("删除旧score_summary索引失败:{e}"))?;
     conn.execute("DROP INDEX IF EXISTS idx_scene_details_scene_date_ts", [])
         .map_err(|e| format!("删除scene_details索引失败:{e}"))?;
+    conn.execute(
+        "DROP INDEX IF EXISTS idx_scene_transitions_trade_date_scene",
+        [],
+    )
+    .map_err(|e| format!("删除scene_transitions索引失败:{e}"))?;
     Ok(())
 }
 
@@ -494,6 +887,11 @@ fn ensure_result_db_indexes(conn: &Connection) -> Result<(), String> {
         [],
     )
     .map_err(|e| format!("创建scene_details索引失败:{e}"))?;
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS idx_scene_transitions_trade_date_scene ON scene_transitions(trade_date, scene_name, to_stage)",
+        [],
+    )
+    .map_err(|e| format!("创建scene_transitions索引失败:{e}"))?;
     Ok(())
 }
 
@@ -517,6 +915,25 @@ fn delete_score_range(
         params![start_date, end_date],
     )
     .map_err(|e| format!("删除scene_details旧数据失败:{e}"))?;
+    tx.execute(
+        "DELETE FROM scene_transitions WHERE trade_date >= ? AND trade_date <= ?",
+        params![start_date, end_date],
+    )
+    .map_err(|e| format!("删除scene_transitions旧数据失败:{e}"))?;
+    tx.execute(
+        "DELETE FROM score_rank_keys WHERE trade_date >= ? AND trade_date <= ?",
+        params![start_date, end_date],
+    )
+    .map_err(|e| format!("删除score_rank_keys旧数据失败:{e}"))?;
+    delete_convolution_rank_range(tx, start_date, end_date)
+}
+
+// 总榜排名变了,区间内的卷积排名跟着失效
+fn delete_convolution_rank_range(
+    tx: &Transaction<'_>,
+    start_date: &str,
+    end_date: &str,
+) -> Result<(), String> {
     let convolution_rank_exists = tx
         .query_row(
             "SELECT COUNT(*) FROM information_schema.tables WHERE table_name = 'convolution_rank'",
@@ -534,26 +951,222 @@ fn delete_score_range(
     Ok(())
 }
 
-fn create_score_summary_stage(tx: &Transaction<'_>) -> Result<(), String> {
+fn delete_score_stock_starts(
+    tx: &Transaction<'_>,
+    stock_starts: &[(String, String)],
+    end_date: &str,
+) -> Result<(), String> {
+    tx.execute(
+        "CREATE TEMP TABLE score_write_scope (ts_code VARCHAR, start_date VARCHAR)",
+        [],
+    )
+    .map_err(|e| format!("创建增量范围临时表失败:{e}"))?;
+    {
+        let mut app = tx
+            .appender("score_write_scope")
+            .map_err(|e| format!("增量范围临时表appender创建失败:{e}"))?;
+        for (ts_code, start_date) in stock_starts {
+            app.append_row(params![ts_code, start_date])
+                .map_err(|e| format!("写入增量范围失败:{e}"))?;
+        }
+        app.flush().map_err(|e| format!("刷新增量范围失败:{e}"))?;
+    }
+    for table_name in [
+        SCORE_SUMMARY_TABLE,
+        RULE_DETAILS_TABLE,
+        SCENE_DETAILS_TABLE,
+        SCENE_TRANSITIONS_TABLE,
+        SCORE_RANK_KEYS_TABLE,
+    ] {
+        tx.execute(
+            &format!(
+                r#"
+                DELETE FROM {table_name} AS t
+                USING score_write_scope AS s
+                WHERE t.ts_code = s.ts_code
+                  AND t.trade_date >= s.start_date
+                  AND t.trade_date <= ?
+                "#
+            ),
+            params![end_date],
+        )
+        .map_err(|e| format!("删除{table_name}增量旧数据失败:{e}"))?;
+    }
+    Ok(())
+}
+
+fn create_rule_refresh_stage(
+    tx: &Transaction<'_>,
+    rule_names: &[String],
+    scene_names: &[String],
+) -> Result<(), String> {
     tx.execute(
         r#"
-        CREATE TEMP TABLE score_summary_stage (
+        CREATE TEMP TABLE rule_details_stage (
             ts_code VARCHAR,
             trade_date VARCHAR,
-            total_score DOUBLE
+            rule_name VARCHAR,
+            rule_score DOUBLE
         )
         "#,
         [],
     )
-    .map_err(|e| format!("创建score_summary临时表失败:{e}"))?;
+    .map_err(|e| format!("创建rule_details临时表失败:{e}"))?;
+    tx.execute(
+        "CREATE TEMP TABLE score_refresh_rules (rule_name VARCHAR)",
+        [],
+    )
+    .map_err(|e| format!("创建重算规则临时表失败:{e}"))?;
+    tx.execute(
+        "CREATE TEMP TABLE score_refresh_scenes (scene_name VARCHAR)",
+        [],
+    )
+    .map_err(|e| format!("创建重算场景临时表失败:{e}"))?;
+    {
+        let mut app = tx
+            .appender("score_refresh_rules")
+            .map_err(|e| format!("重算规则临时表appender创建失败:{e}"))?;
+        for rule_name in rule_names {
+            app.append_row(params![rule_name])
+                .map_err(|e| format!("写入重算规则失败:{e}"))?;
+        }
+        app.flush().map_err(|e| format!("刷新重算规则失败:{e}"))?;
+    }
+    {
+        let mut app = tx
+            .appender("score_refresh_scenes")
+            .map_err(|e| format!("重算场景临时表appender创建失败:{e}"))?;
+        for scene_name in scene_names {
+            app.append_row(params![scene_name])
+                .map_err(|e| format!("写入重算场景失败:{e}"))?;
+        }
+        app.flush().map_err(|e| format!("刷新重算场景失败:{e}"))?;
+    }
     Ok(())
 }
 
-fn score_string_array(values: StringArray) -> ArrayRef {
-    Arc::new(values)
+// 未触发的规则得分恒为0,净分 = 旧净分 - 旧明细 + 新明细;
+// 规则可能换了场景方向,风险分按新明细和 score_rule_weighting 重新汇总,总分 = 净分 + 风险分
+fn apply_rule_refresh_stage(
+    tx: &Transaction<'_>,
+    start_date: &str,
+    end_date: &str,
+) -> Result<(), String> {
+    tx.execute(
+        r#"
+        UPDATE score_summary AS s
+        SET net_score = s.net_score + d.delta
+        FROM (
+            SELECT ts_code, trade_date, SUM(rule_score) AS delta
+            FROM (
+                SELECT ts_code, trade_date, rule_score
+                FROM rule_details_stage
+                UNION ALL
+                SELECT ts_code, trade_date, -rule_score AS rule_score
+                FROM rule_details
+                WHERE trade_date >= ?
+                  AND trade_date <= ?
+                  AND rule_name IN (SELECT rule_name FROM score_refresh_rules)
+            )
+            GROUP BY ts_code, trade_date
+        ) AS d
+        WHERE s.ts_code = d.ts_code
+          AND s.trade_date = d.trade_date
+        "#,
+        params![start_date, end_date],
+    )
+    .map_err(|e| format!("修正score_summary净分失败:{e}"))?;
+    tx.execute(
+        r#"
+        DELETE FROM rule_details
+        WHERE trade_date >= ?
+          AND trade_date <= ?
+          AND rule_name IN (SELECT rule_name FROM score_refresh_rules)
+        "#,
+        params![start_date, end_date],
+    )
+    .map_err(|e| format!("删除rule_details旧规则明细失败:{e}"))?;
+    tx.execute(
+        r#"
+        INSERT INTO rule_details (ts_code, trade_date, rule_name, rule_score)
+        SELECT ts_code, trade_date, rule_name, rule_score
+        FROM rule_details_stage
+        "#,
+        [],
+    )
+    .map_err(|e| format!("写入rule_details新规则明细失败:{e}"))?;
+    tx.execute(
+        r#"
+        UPDATE score_summary AS s
+        SET risk_score = COALESCE((
+            SELECT -SUM(d.rule_score)
+            FROM rule_details AS d
+            JOIN score_rule_weighting AS w
+              ON d.rule_name = w.rule_name
+            WHERE w.direction = 'short'
+              AND d.ts_code = s.ts_code
+              AND d.trade_date = s.trade_date
+        ), 0.0)
+        WHERE s.trade_date >= ?
+          AND s.trade_date <= ?
+        "#,
+        params![start_date, end_date],
+    )
+    .map_err(|e| format!("重算score_summary风险分失败:{e}"))?;
+    tx.execute(
+        r#"
+        UPDATE score_summary
+        SET total_score = net_score + risk_score
+        WHERE trade_date >= ?
+          AND trade_date <= ?
+        "#,
+        params![start_date, end_date],
+    )
+    .map_err(|e| format!("修正score_summary总分失败:{e}"))?;
+    tx.execute(
+        r#"
+        DELETE FROM scene_details
+        WHERE trade_date >= ?
+          AND trade_date <= ?
+          AND scene_name IN (SELECT scene_name FROM score_refresh_scenes)
+        "#,
+        params![start_date, end_date],
+    )
+    .map_err(|e| format!("删除scene_details旧场景明细失败:{e}"))?;
+    tx.execute(
+        r#"
+        DELETE FROM scene_transitions
+        WHERE trade_date >= ?
+          AND trade_date <= ?
+          AND scene_name IN (SELECT scene_name FROM score_refresh_scenes)
+        "#,
+        params![start_date, end_date],
+    )
+    .map_err(|e| format!("删除scene_transitions旧场景事件失败:{e}"))?;
+    Ok(())
 }
 
-fn score_float64_array(values: Vec<f64>) -> ArrayRef {
+fn create_score_summary_stage(tx: &Transaction<'_>) -> Result<(), String> {
+    tx.execute(
+        r#"
+        CREATE TEMP TABLE score_summary_stage (
+            ts_code VARCHAR,
+            trade_date VARCHAR,
+            total_score DOUBLE,
+            risk_score DOUBLE
+        )
+        "#,
+        [],
+    )
+    .map_err(|e| format!("创建score_summary临时表失败:{e}"))?;
+    Ok(())
+}
+
+fn score_string_array(values: StringArray) -> ArrayRef {
+    Arc::new(values)
+}
+
+fn score_float64_array(values: Vec<f64>) -> ArrayRef {
     Arc::new(Float64Array::from(values))
 }
 
@@ -575,11 +1188,17 @@ fn append_summary_rows(app: &mut Appender<'_>, rows: &[ScoreSummary]) -> Result<
     let mut trade_date = StringBuilder::with_capacity(rows.len(), rows.len().saturating_mul(8));
     let mut total_score = Vec::with_capacity(rows.len());
     let mut rank = Vec::with_capacity(rows.len());
+    let mut risk_score = Vec::with_capacity(rows.len());
+    let mut net_score = Vec::with_capacity(rows.len());
+    let mut risk_rank = Vec::with_capacity(rows.len());
     for row in rows {
         ts_code.append_value(&row.ts_code);
         trade_date.append_value(&row.trade_date);
         total_score.push(row.total_score);
         rank.push(None);
+        risk_score.push(row.risk_score);
+        net_score.push(row.net_score());
+        risk_rank.push(None);
     }
 
     let schema = Schema::new(vec![
@@ -587,6 +1206,9 @@ fn append_summary_rows(app: &mut Appender<'_>, rows: &[ScoreSummary]) -> Result<
         Field::new("trade_date", DataType::Utf8, false),
         Field::new("total_score", DataType::Float64, false),
         Field::new("rank", DataType::Int32, true),
+        Field::new("risk_score", DataType::Float64, false),
+        Field::new("net_score", DataType::Float64, false),
+        Field::new("risk_rank", DataType::Int32, true),
     ]);
     let batch = RecordBatch::try_new(
         Arc::new(schema),
@@ -595,6 +1217,9 @@ fn append_summary_rows(app: &mut Appender<'_>, rows: &[ScoreSummary]) -> Result<
             score_string_array(trade_date.finish()),
             score_float64_array(total_score),
             score_int32_opt_array(rank),
+            score_float64_array(risk_score),
+            score_float64_array(net_score),
+            score_int32_opt_array(risk_rank),
         ],
     )
     .map_err(|e| format!("创建score_summary批次失败:{e}"))?;
@@ -610,16 +1235,19 @@ fn append_summary_stage_rows(app: &mut Appender<'_>, rows: &[ScoreSummary]) -> R
     let mut ts_code = StringBuilder::with_capacity(rows.len(), rows.len().saturating_mul(12));
     let mut trade_date = StringBuilder::with_capacity(rows.len(), rows.len().saturating_mul(8));
     let mut total_score = Vec::with_capacity(rows.len());
+    let mut risk_score = Vec::with_capacity(rows.len());
     for row in rows {
         ts_code.append_value(&row.ts_code);
         trade_date.append_value(&row.trade_date);
         total_score.push(row.total_score);
+        risk_score.push(row.risk_score);
     }
 
     let schema = Schema::new(vec![
         Field::new("ts_code", DataType::Utf8, false),
         Field::new("trade_date", DataType::Utf8, false),
         Field::new("total_score", DataType::Float64, false),
+        Field::new("risk_score", DataType::Float64, false),
     ]);
     let batch = RecordBatch::try_new(
         Arc::new(schema),
@@ -627,6 +1255,7 @@ fn append_summary_stage_rows(app: &mut Appender<'_>, rows: &[ScoreSummary]) -> R
             score_string_array(ts_code.finish()),
             score_string_array(trade_date.finish()),
             score_float64_array(total_score),
+            score_float64_array(risk_score),
         ],
     )
     .map_err(|e| format!("创建score_summary临时批次失败:{e}"))?;
@@ -634,60 +1263,206 @@ fn append_summary_stage_rows(app: &mut Appender<'_>, rows: &[ScoreSummary]) -> R
         .map_err(|e| format!("批量插入score_summary临时表失败:{e}"))
 }
 
+// 风险榜只给风险分大于0的股票排名
+fn risk_rank_columns_sql(alias: &str) -> String {
+    format!(
+        r#"
+                    {alias}.risk_score,
+                    {alias}.total_score - {alias}.risk_score AS net_score,
+                    CASE WHEN {alias}.risk_score > 0 THEN CAST(
+                        ROW_NUMBER() OVER (
+                            PARTITION BY {alias}.trade_date, {alias}.risk_score > 0
+                            ORDER BY {alias}.risk_score DESC, {alias}.ts_code ASC
+                        ) AS INTEGER
+                    ) END AS risk_rank"#
+    )
+}
+
 fn insert_ranked_summary_from_stage(
     tx: &Transaction<'_>,
     tie_break: TieBreakWay,
     adj_type: &str,
 ) -> Result<(), String> {
+    let risk_columns = risk_rank_columns_sql("st");
     match tie_break {
         TieBreakWay::TsCode => {
             tx.execute(
-                r#"
-                INSERT INTO score_summary (ts_code, trade_date, total_score, rank)
-                SELECT
-                    ts_code,
-                    trade_date,
-                    total_score,
-                    CAST(
-                        ROW_NUMBER() OVER (
-                            PARTITION BY trade_date
-                            ORDER BY total_score DESC, ts_code ASC
-                        ) AS INTEGER
-                    ) AS rank
-                FROM score_summary_stage
-                "#,
+                &format!(
+                    r#"
+                    INSERT INTO score_summary (
+                        ts_code, trade_date, total_score, rank, risk_score, net_score, risk_rank
+                    )
+                    SELECT
+                        st.ts_code,
+                        st.trade_date,
+                        st.total_score,
+                        CAST(
+                            ROW_NUMBER() OVER (
+                                PARTITION BY st.trade_date
+                                ORDER BY st.total_score - st.risk_score DESC, st.ts_code ASC
+                            ) AS INTEGER
+                        ) AS rank,{risk_columns}
+                    FROM score_summary_stage AS st
+                    "#
+                ),
                 [],
             )
             .map_err(|e| format!("写入总榜排名失败:{e}"))?;
         }
         TieBreakWay::KdjJ => {
             tx.execute(
-                r#"
-                INSERT INTO score_summary (ts_code, trade_date, total_score, rank)
-                SELECT
-                    st.ts_code,
-                    st.trade_date,
-                    st.total_score,
-                    CAST(
-                        ROW_NUMBER() OVER (
-                            PARTITION BY st.trade_date
-                            ORDER BY st.total_score DESC, src.j ASC NULLS LAST, st.ts_code ASC
-                        ) AS INTEGER
-                    ) AS rank
-                FROM score_summary_stage AS st
-                LEFT JOIN src_db.stock_data AS src
-                  ON st.ts_code = src.ts_code
-                 AND st.trade_date = src.trade_date
-                 AND src.adj_type = ?
-                "#,
+                &format!(
+                    r#"
+                    INSERT INTO score_summary (
+                        ts_code, trade_date, total_score, rank, risk_score, net_score, risk_rank
+                    )
+                    SELECT
+                        st.ts_code,
+                        st.trade_date,
+                        st.total_score,
+                        CAST(
+                            ROW_NUMBER() OVER (
+                                PARTITION BY st.trade_date
+                                ORDER BY st.total_score - st.risk_score DESC, src.j ASC NULLS LAST, st.ts_code ASC
+                            ) AS INTEGER
+                        ) AS rank,{risk_columns}
+                    FROM score_summary_stage AS st
+                    LEFT JOIN src_db.stock_data AS src
+                      ON st.ts_code = src.ts_code
+                     AND st.trade_date = src.trade_date
+                     AND src.adj_type = ?
+                    "#
+                ),
                 params![adj_type],
             )
             .map_err(|e| format!("写入J值同分总榜排名失败:{e}"))?;
         }
+        TieBreakWay::RankOrder => {
+            let rank_keys = load_rank_order(tx)?;
+            let key_joins = rank_key_joins_sql(&rank_keys, "st", "rk");
+            let order_by = rank_order_by_sql(&rank_keys, &net_score_sql("st"), "rk");
+            tx.execute(
+                &format!(
+                    r#"
+                    INSERT INTO score_summary (
+                        ts_code, trade_date, total_score, rank, risk_score, net_score, risk_rank
+                    )
+                    SELECT
+                        st.ts_code,
+                        st.trade_date,
+                        st.total_score,
+                        CAST(
+                            ROW_NUMBER() OVER (
+                                PARTITION BY st.trade_date
+                                ORDER BY {order_by}, st.ts_code ASC
+                            ) AS INTEGER
+                        ) AS rank,{risk_columns}
+                    FROM score_summary_stage AS st{key_joins}
+                    "#
+                ),
+                [],
+            )
+            .map_err(|e| format!("写入排序键总榜排名失败:{e}"))?;
+        }
     }
     Ok(())
 }
 
+// 区间内已有的总榜行并入临时表,和本次新写的行一起重排
+fn rerank_summary_range(
+    tx: &Transaction<'_>,
+    tie_break: TieBreakWay,
+    adj_type: &str,
+    start_date: &str,
+    end_date: &str,
+) -> Result<(), String> {
+    tx.execute(
+        r#"
+        INSERT INTO score_summary_stage (ts_code, trade_date, total_score, risk_score)
+        SELECT ts_code, trade_date, total_score, risk_score
+        FROM score_summary
+        WHERE trade_date >= ? AND trade_date <= ?
+        "#,
+        params![start_date, end_date],
+    )
+    .map_err(|e| format!("读取score_summary旧数据失败:{e}"))?;
+    tx.execute(
+        "DELETE FROM score_summary WHERE trade_date >= ? AND trade_date <= ?",
+        params![start_date, end_date],
+    )
+    .map_err(|e| format!("删除score_summary旧排名失败:{e}"))?;
+    insert_ranked_summary_from_stage(tx, tie_break, adj_type)
+}
+
+fn rerank_scene_range(
+    tx: &Transaction<'_>,
+    start_date: &str,
+    end_date: &str,
+) -> Result<(), String> {
+    let mut rows = Vec::new();
+    {
+        let mut stmt = tx
+            .prepare(
+                r#"
+                SELECT
+                    sd.ts_code,
+                    sd.trade_date,
+                    sd.scene_name,
+                    sd.direction,
+                    sd.stage,
+                    sd.stage_score,
+                    sd.risk_score,
+                    sd.confirm_strength,
+                    sd.risk_intensity,
+                    COALESCE(ss.total_score, 0.0) AS total_score
+                FROM scene_details AS sd
+                LEFT JOIN score_summary AS ss
+                  ON sd.ts_code = ss.ts_code
+                 AND sd.trade_date = ss.trade_date
+                WHERE sd.trade_date >= ? AND sd.trade_date <= ?
+                "#,
+            )
+            .map_err(|e| format!("预编译scene_details重排查询失败:{e}"))?;
+        let mut query = stmt
+            .query(params![start_date, end_date])
+            .map_err(|e| format!("查询scene_details旧数据失败:{e}"))?;
+        while let Some(row) = query
+            .next()
+            .map_err(|e| format!("读取scene_details旧数据失败:{e}"))?
+        {
+            rows.push(SceneDetails {
+                ts_code: row.get(0).map_err(|e| format!("读取ts_code失败:{e}"))?,
+                trade_date: row.get(1).map_err(|e| format!("读取trade_date失败:{e}"))?,
+                scene_name: row.get(2).map_err(|e| format!("读取scene_name失败:{e}"))?,
+                direction: row.get(3).map_err(|e| format!("读取direction失败:{e}"))?,
+                stage: row.get(4).map_err(|e| format!("读取stage失败:{e}"))?,
+                stage_score: row.get(5).map_err(|e| format!("读取stage_score失败:{e}"))?,
+                risk_score: row.get(6).map_err(|e| format!("读取risk_score失败:{e}"))?,
+                confirm_strength: row
+                    .get(7)
+                    .map_err(|e| format!("读取confirm_strength失败:{e}"))?,
+                risk_intensity: row
+                    .get(8)
+                    .map_err(|e| format!("读取risk_intensity失败:{e}"))?,
+                total_score: row.get(9).map_err(|e| format!("读取total_score失败:{e}"))?,
+                scene_rank: None,
+            });
+        }
+    }
+    tx.execute(
+        "DELETE FROM scene_details WHERE trade_date >= ? AND trade_date <= ?",
+        params![start_date, end_date],
+    )
+    .map_err(|e| format!("删除scene_details旧排名失败:{e}"))?;
+    rank_scene_rows(&mut rows);
+    let mut app = tx
+        .appender("scene_details")
+        .map_err(|e| format!("scene_details appender创建失败:{e}"))?;
+    append_scene_rows(&mut app, &rows)?;
+    app.flush()
+        .map_err(|e| format!("刷新scene_details失败:{e}"))
+}
+
 fn append_detail_rows(app: &mut Appender<'_>, rows: &[ScoreDetails]) -> Result<(), String> {
     if rows.is_empty() {
         return Ok(());
@@ -724,6 +1499,42 @@ fn append_detail_rows(app: &mut Appender<'_>, rows: &[ScoreDetails]) -> Result<(
         .map_err(|e| format!("批量插入rule_details失败:{e}"))
 }
 
+fn append_rank_key_rows(app: &mut Appender<'_>, rows: &[ScoreRankKey]) -> Result<(), String> {
+    if rows.is_empty() {
+        return Ok(());
+    }
+
+    let mut ts_code = StringBuilder::with_capacity(rows.len(), rows.len().saturating_mul(12));
+    let mut trade_date = StringBuilder::with_capacity(rows.len(), rows.len().saturating_mul(8));
+    let mut key_index = Vec::with_capacity(rows.len());
+    let mut key_value = Vec::with_capacity(rows.len());
+    for row in rows {
+        ts_code.append_value(&row.ts_code);
+        trade_date.append_value(&row.trade_date);
+        key_index.push(Some(row.key_index));
+        key_value.push(row.key_value);
+    }
+
+    let schema = Schema::new(vec![
+        Field::new("ts_code", DataType::Utf8, false),
+        Field::new("trade_date", DataType::Utf8, false),
+        Field::new("key_index", DataType::Int32, true),
+        Field::new("key_value", DataType::Float64, false),
+    ]);
+    let batch = RecordBatch::try_new(
+        Arc::new(schema),
+        vec![
+            score_string_array(ts_code.finish()),
+            score_string_array(trade_date.finish()),
+            score_int32_opt_array(key_index),
+            score_float64_array(key_value),
+        ],
+    )
+    .map_err(|e| format!("创建score_rank_keys批次失败:{e}"))?;
+    app.append_record_batch(batch)
+        .map_err(|e| format!("批量插入score_rank_keys失败:{e}"))
+}
+
 fn append_scene_rows(app: &mut Appender<'_>, rows: &[SceneDetails]) -> Result<(), String> {
     if rows.is_empty() {
         return Ok(());
@@ -784,6 +1595,23 @@ fn append_scene_rows(app: &mut Appender<'_>, rows: &[SceneDetails]) -> Result<()
         .map_err(|e| format!("批量插入scene_details失败:{e}"))
 }
 
+fn append_transition_rows(app: &mut Appender<'_>, rows: &[SceneTransition]) -> Result<(), String> {
+    for row in rows {
+        app.append_row(params![
+            row.ts_code,
+            row.trade_date,
+            row.scene_name,
+            row.direction,
+            row.from_stage,
+            row.to_stage,
+            row.prev_stage_days,
+            row.rule_names,
+        ])
+        .map_err(|e| format!("批量插入scene_transitions失败:{e}"))?;
+    }
+    Ok(())
+}
+
 fn duckdb_string_literal(value: &str) -> String {
     format!("'{}'", value.replace('\'', "''"))
 }
@@ -799,13 +1627,14 @@ fn scene_stage_rank_weight(stage: Option<&str>) -> i32 {
 }
 
 pub(crate) fn rank_summary_rows_by_score(rows: &mut [ScoreSummary]) {
+    // 总榜按净分排,和落库时的 rank 口径一致
     rows.sort_by(|left, right| {
         left.trade_date
             .cmp(&right.trade_date)
             .then_with(|| {
                 right
-                    .total_score
-                    .partial_cmp(&left.total_score)
+                    .net_score()
+                    .partial_cmp(&left.net_score())
                     .unwrap_or(std::cmp::Ordering::Equal)
             })
             .then_with(|| left.ts_code.cmp(&right.ts_code))
@@ -813,7 +1642,7 @@ pub(crate) fn rank_summary_rows_by_score(rows: &mut [ScoreSummary]) {
 
     let mut current_trade_date: Option<&str> = None;
     let mut current_rank = 0i64;
-    for row in rows {
+    for row in rows.iter_mut() {
         if current_trade_date != Some(row.trade_date.as_str()) {
             current_trade_date = Some(row.trade_date.as_str());
             current_rank = 1;
@@ -822,6 +1651,34 @@ pub(crate) fn rank_summary_rows_by_score(rows: &mut [ScoreSummary]) {
         }
         row.rank = Some(current_rank);
     }
+
+    // 风险排名只覆盖有空头风险的行,和落库时的 risk_rank 口径一致
+    let mut risk_order = (0..rows.len())
+        .filter(|&index| rows[index].risk_score > 0.0)
+        .collect::<Vec<_>>();
+    risk_order.sort_by(|&left, &right| {
+        rows[left]
+            .trade_date
+            .cmp(&rows[right].trade_date)
+            .then_with(|| {
+                rows[right]
+                    .risk_score
+                    .partial_cmp(&rows[left].risk_score)
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            })
+            .then_with(|| rows[left].ts_code.cmp(&rows[right].ts_code))
+    });
+    let mut current_trade_date: Option<String> = None;
+    let mut current_rank = 0i64;
+    for index in risk_order {
+        if current_trade_date.as_deref() != Some(rows[index].trade_date.as_str()) {
+            current_trade_date = Some(rows[index].trade_date.clone());
+            current_rank = 1;
+        } else {
+            current_rank += 1;
+        }
+        rows[index].risk_rank = Some(current_rank);
+    }
 }
 
 pub(crate) fn rank_scene_rows(rows: &mut [SceneDetails]) {
@@ -887,10 +1744,19 @@ mod tests {
     use duckdb::Connection;
 
     use super::{
-        SceneDetails, ScoreBatch, ScoreDetails, ScoreSummary, ScoreWriteMessage, init_result_db,
-        rank_scene_rows, write_score_batches_from_channel,
+        SceneDetails, SceneTransition, ScoreBatch, ScoreDetails, ScoreRankKey, ScoreRunMeta,
+        ScoreSummary, ScoreWriteMessage, ScoreWriteScope, archive_score_run, delete_score_run,
+        init_result_db, list_score_runs, load_rank_order, load_score_run_summary_rows,
+        load_scoring_state, query_scene_transitions, query_score_run_rank_diffs,
+        query_score_run_rule_counts, query_score_run_rule_diffs, rank_scene_rows,
+        rank_summary_rows_by_score, save_rank_order, save_scoring_state,
+        write_score_batches_from_channel, write_score_batches_with_scope,
+    };
+    use crate::scoring::{
+        TieBreakWay,
+        incremental::{RuleFingerprint, StrategyFingerprint},
+        rank_order::parse_rank_order,
     };
-    use crate::scoring::TieBreakWay;
 
     fn scene_row(
         ts_code: &str,
@@ -970,6 +1836,7 @@ mod tests {
                 trade_date: "20240102".to_string(),
                 total_score: 1.0,
                 rank: Some(7),
+                ..ScoreSummary::default()
             }],
         )
         .expect("write summary");
@@ -1052,30 +1919,35 @@ mod tests {
                     trade_date: "20240102".to_string(),
                     total_score: 1.0,
                     rank: None,
+                    ..ScoreSummary::default()
                 },
                 ScoreSummary {
                     ts_code: "000003.SZ".to_string(),
                     trade_date: "20240102".to_string(),
                     total_score: 3.0,
                     rank: None,
+                    ..ScoreSummary::default()
                 },
                 ScoreSummary {
                     ts_code: "000002.SZ".to_string(),
                     trade_date: "20240102".to_string(),
                     total_score: 3.0,
                     rank: None,
+                    ..ScoreSummary::default()
                 },
                 ScoreSummary {
                     ts_code: "000001.SZ".to_string(),
                     trade_date: "20240103".to_string(),
                     total_score: 2.0,
                     rank: None,
+                    ..ScoreSummary::default()
                 },
                 ScoreSummary {
                     ts_code: "000002.SZ".to_string(),
                     trade_date: "20240103".to_string(),
                     total_score: 5.0,
                     rank: None,
+                    ..ScoreSummary::default()
                 },
             ],
             detail_rows: vec![ScoreDetails {
@@ -1112,6 +1984,17 @@ mod tests {
                     scene_rank: None,
                 },
             ],
+            transition_rows: vec![SceneTransition {
+                ts_code: "000001.SZ".to_string(),
+                trade_date: "20240102".to_string(),
+                scene_name: "场景A".to_string(),
+                direction: "long".to_string(),
+                from_stage: Some("trigger".to_string()),
+                to_stage: "confirm".to_string(),
+                prev_stage_days: 3,
+                rule_names: "确认规则".to_string(),
+            }],
+            rank_key_rows: Vec::new(),
         }))
         .expect("send batch");
         drop(tx);
@@ -1185,146 +2068,1383 @@ mod tests {
             )
             .expect("count ranked scene rows");
         assert_eq!(ranked_scene_count, 2);
+        let transitions = query_scene_transitions(&conn, "20240102", None, Some("confirm"))
+            .expect("query scene transitions");
+        assert_eq!(transitions.len(), 1);
+        assert_eq!(transitions[0].from_stage.as_deref(), Some("trigger"));
+        assert_eq!(transitions[0].prev_stage_days, 3);
+        assert_eq!(transitions[0].rule_names, "确认规则");
+        assert!(
+            query_scene_transitions(&conn, "20240102", Some("场景B"), None)
+                .expect("query other scene")
+                .is_empty()
+        );
 
         drop(conn);
         fs::remove_dir_all(temp_dir).expect("remove temp dir");
     }
-}
 
-pub fn write_score_batches_from_channel(
-    db_path: &str,
-    source_db_path: Option<&str>,
-    adj_type: &str,
-    tie_break: TieBreakWay,
-    start_date: &str,
-    end_date: &str,
-    rx: Receiver<ScoreWriteMessage>,
-) -> Result<ScoreWriteProfile, String> {
-    let total_started_at = time::Instant::now();
-    let mut profile = ScoreWriteProfile::default();
-    let mut conn = Connection::open(db_path).map_err(|e| format!("结果库连接失败:{e}"))?;
+    fn summary_row(ts_code: &str, trade_date: &str, total_score: f64) -> ScoreSummary {
+        ScoreSummary {
+            ts_code: ts_code.to_string(),
+            trade_date: trade_date.to_string(),
+            total_score,
+            rank: None,
+            ..ScoreSummary::default()
+        }
+    }
 
-    let drop_indexes_started_at = time::Instant::now();
-    drop_result_db_indexes(&conn)?;
-    profile.drop_indexes_ms = drop_indexes_started_at.elapsed().as_millis() as u64;
+    fn detail_row(ts_code: &str, trade_date: &str, rule_score: f64) -> ScoreDetails {
+        ScoreDetails {
+            ts_code: ts_code.to_string(),
+            trade_date: trade_date.to_string(),
+            rule_name: "r1".to_string(),
+            rule_score,
+        }
+    }
 
-    let mut source_db_attached = false;
-    if let TieBreakWay::KdjJ = tie_break {
-        let source_db_path =
-            source_db_path.ok_or_else(|| "J值同分排名需要原始库路径".to_string())?;
-        let attach_started_at = time::Instant::now();
-        let attach_sql = format!("ATTACH {} AS src_db", duckdb_string_literal(source_db_path));
-        conn.execute(&attach_sql, [])
-            .map_err(|e| format!("附加原始库失败:{e}"))?;
-        source_db_attached = true;
-        profile.attach_source_db_ms = Some(attach_started_at.elapsed().as_millis() as u64);
+    fn write_batch(db_path: &str, scope: ScoreWriteScope, batch: ScoreBatch) {
+        let (tx, rx) = channel();
+        tx.send(ScoreWriteMessage::Batch(batch))
+            .expect("send batch");
+        drop(tx);
+        write_score_batches_with_scope(db_path, None, "qfq", TieBreakWay::TsCode, &scope, rx)
+            .expect("write score batches");
     }
 
-    let write_result = (|| -> Result<(), String> {
-        let tx = conn
-            .transaction()
-            .map_err(|e| format!("创建数据库事务失败:{e}"))?;
+    fn summary_ranks(conn: &Connection) -> Vec<(String, String, f64, i64)> {
+        let mut stmt = conn
+            .prepare(
+                r#"
+                SELECT ts_code, trade_date, total_score, CAST(rank AS BIGINT)
+                FROM score_summary
+                ORDER BY trade_date ASC, rank ASC
+                "#,
+            )
+            .expect("prepare query");
+        stmt.query_map([], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+        })
+        .expect("query rows")
+        .collect::<Result<Vec<_>, _>>()
+        .expect("collect rows")
+    }
 
-        let delete_started_at = time::Instant::now();
-        delete_score_range(&tx, start_date, end_date)?;
-        profile.delete_range_ms = delete_started_at.elapsed().as_millis() as u64;
-        create_score_summary_stage(&tx)?;
+    #[test]
+    fn incremental_scopes_patch_rows_and_rerank_whole_dates() {
+        let unique = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("system time")
+            .as_nanos();
+        let temp_dir = std::env::temp_dir().join(format!("lianghua_score_incremental_{unique}"));
+        fs::create_dir_all(&temp_dir).expect("create temp dir");
+        let db_path = temp_dir.join("scoring_result.db");
+        init_result_db(&db_path).expect("init db");
+        let db_path_str = db_path.to_str().expect("db path utf8");
 
-        let receive_and_append_started_at = time::Instant::now();
-        let mut batch_count = 0usize;
-        let mut scene_rows = Vec::new();
-        {
-            let mut summary_app = tx
-                .appender("score_summary_stage")
-                .map_err(|e| format!("score_summary临时表appender创建失败:{e}"))?;
-            let mut detail_app = tx
-                .appender("rule_details")
-                .map_err(|e| format!("rule_details appender创建失败:{e}"))?;
+        write_batch(
+            db_path_str,
+            ScoreWriteScope::Range {
+                start_date: "20240102".to_string(),
+                end_date: "20240102".to_string(),
+            },
+            ScoreBatch {
+                summary_rows: vec![
+                    summary_row("000001.SZ", "20240102", 52.0),
+                    summary_row("000002.SZ", "20240102", 51.0),
+                ],
+                detail_rows: vec![
+                    detail_row("000001.SZ", "20240102", 2.0),
+                    detail_row("000002.SZ", "20240102", 1.0),
+                ],
+                scene_rows: Vec::new(),
+                transition_rows: Vec::new(),
+                rank_key_rows: Vec::new(),
+            },
+        );
+        write_batch(
+            db_path_str,
+            ScoreWriteScope::Stocks {
+                stock_starts: vec![("000002.SZ".to_string(), "20240103".to_string())],
+                end_date: "20240103".to_string(),
+            },
+            ScoreBatch {
+                summary_rows: vec![summary_row("000002.SZ", "20240103", 51.0)],
+                detail_rows: vec![detail_row("000002.SZ", "20240103", 1.0)],
+                scene_rows: Vec::new(),
+                transition_rows: Vec::new(),
+                rank_key_rows: Vec::new(),
+            },
+        );
+        write_batch(
+            db_path_str,
+            ScoreWriteScope::Rules {
+                rule_names: vec!["r1".to_string()],
+                scene_names: Vec::new(),
+                start_date: "20240102".to_string(),
+                end_date: "20240103".to_string(),
+            },
+            ScoreBatch {
+                summary_rows: Vec::new(),
+                detail_rows: vec![
+                    detail_row("000002.SZ", "20240102", 5.0),
+                    detail_row("000002.SZ", "20240103", 3.0),
+                ],
+                scene_rows: Vec::new(),
+                transition_rows: Vec::new(),
+                rank_key_rows: Vec::new(),
+            },
+        );
 
-            for message in rx {
-                let batch = match message {
-                    ScoreWriteMessage::Batch(batch) => batch,
-                    ScoreWriteMessage::Abort(reason) => {
-                        return Err(format!("评分计算中断，结果库回滚:{reason}"));
-                    }
-                };
+        let conn = Connection::open(&db_path).expect("open result db");
+        assert_eq!(
+            summary_ranks(&conn),
+            vec![
+                ("000002.SZ".to_string(), "20240102".to_string(), 55.0, 1),
+                ("000001.SZ".to_string(), "20240102".to_string(), 50.0, 2),
+                ("000002.SZ".to_string(), "20240103".to_string(), 53.0, 1),
+            ]
+        );
+        let detail_count = conn
+            .query_row("SELECT COUNT(*) FROM rule_details", [], |row| {
+                row.get::<_, i64>(0)
+            })
+            .expect("count details");
+        assert_eq!(detail_count, 2);
+        drop(conn);
 
-                append_summary_stage_rows(&mut summary_app, &batch.summary_rows)?;
-                append_detail_rows(&mut detail_app, &batch.detail_rows)?;
-                scene_rows.extend(batch.scene_rows);
-                batch_count += 1;
+        let fingerprint = StrategyFingerprint {
+            rules: vec![RuleFingerprint {
+                rule_name: "r1".to_string(),
+                scene_name: "s1".to_string(),
+                fingerprint: "abc".to_string(),
+            }],
+            scenes: Vec::new(),
+        };
+        save_scoring_state(&db_path, Some(&fingerprint)).expect("save state");
+        let state = load_scoring_state(&db_path).expect("load state");
+        assert_eq!(state.strategy.rules, fingerprint.rules);
+        assert_eq!(
+            state.stock_last_dates.get("000001.SZ").map(String::as_str),
+            Some("20240102")
+        );
+        assert_eq!(
+            state.stock_last_dates.get("000002.SZ").map(String::as_str),
+            Some("20240103")
+        );
+        assert_eq!(
+            state.scored_range,
+            Some(("20240102".to_string(), "20240103".to_string()))
+        );
 
-                if batch_count % 32 == 0 {
-                    summary_app
-                        .flush()
-                        .map_err(|e| format!("刷新score_summary失败:{e}"))?;
-                    detail_app
-                        .flush()
-                        .map_err(|e| format!("刷新rule_details失败:{e}"))?;
-                }
-            }
+        fs::remove_dir_all(temp_dir).expect("remove temp dir");
+    }
 
-            summary_app
-                .flush()
-                .map_err(|e| format!("刷新score_summary失败:{e}"))?;
-            detail_app
-                .flush()
-                .map_err(|e| format!("刷新rule_details失败:{e}"))?;
-        }
-        rank_scene_rows(&mut scene_rows);
-        {
-            let mut scene_app = tx
-                .appender("scene_details")
-                .map_err(|e| format!("scene_details appender创建失败:{e}"))?;
+    #[test]
+    fn short_scene_risk_gets_net_score_and_separate_rank() {
+        let unique = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("system time")
+            .as_nanos();
+        let temp_dir = std::env::temp_dir().join(format!("lianghua_score_risk_{unique}"));
+        fs::create_dir_all(&temp_dir).expect("create temp dir");
+        let db_path = temp_dir.join("scoring_result.db");
+        init_result_db(&db_path).expect("init db");
+        let db_path_str = db_path.to_str().expect("db path utf8");
+
+        let risky = |ts_code: &str, total_score: f64, risk_score: f64| ScoreSummary {
+            risk_score,
+            ..summary_row(ts_code, "20240102", total_score)
+        };
+        write_batch(
+            db_path_str,
+            ScoreWriteScope::Range {
+                start_date: "20240102".to_string(),
+                end_date: "20240102".to_string(),
+            },
+            ScoreBatch {
+                summary_rows: vec![
+                    risky("000001.SZ", 55.0, 1.0),
+                    risky("000002.SZ", 52.0, 4.0),
+                    risky("000003.SZ", 51.0, 0.0),
+                ],
+                detail_rows: Vec::new(),
+                scene_rows: Vec::new(),
+                transition_rows: Vec::new(),
+                rank_key_rows: Vec::new(),
+            },
+        );
+
+        let conn = Connection::open(&db_path).expect("open result db");
+        let mut stmt = conn
+            .prepare(
+                r#"
+                SELECT ts_code, CAST(rank AS BIGINT), net_score, CAST(risk_rank AS BIGINT)
+                FROM score_summary
+                ORDER BY ts_code ASC
+                "#,
+            )
+            .expect("prepare query");
+        let rows = stmt
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, i64>(1)?,
+                    row.get::<_, f64>(2)?,
+                    row.get::<_, Option<i64>>(3)?,
+                ))
+            })
+            .expect("query rows")
+            .collect::<Result<Vec<_>, _>>()
+            .expect("collect rows");
+        assert_eq!(
+            rows,
+            vec![
+                ("000001.SZ".to_string(), 1, 54.0, Some(2)),
+                ("000002.SZ".to_string(), 3, 48.0, Some(1)),
+                ("000003.SZ".to_string(), 2, 51.0, None),
+            ]
+        );
+
+        let mut memory_rows = vec![
+            risky("000001.SZ", 55.0, 1.0),
+            risky("000002.SZ", 52.0, 4.0),
+            risky("000003.SZ", 51.0, 0.0),
+        ];
+        rank_summary_rows_by_score(&mut memory_rows);
+        assert_eq!(
+            memory_rows
+                .iter()
+                .map(|row| (row.rank, row.risk_rank))
+                .collect::<Vec<_>>(),
+            vec![(Some(1), Some(2)), (Some(2), None), (Some(3), Some(1))]
+        );
+
+        drop(stmt);
+        drop(conn);
+        fs::remove_dir_all(temp_dir).expect("remove temp dir");
+    }
+
+    #[test]
+    fn rank_order_keys_break_score_ties_on_write() {
+        let unique = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("system time")
+            .as_nanos();
+        let temp_dir = std::env::temp_dir().join(format!("lianghua_score_rank_order_{unique}"));
+        fs::create_dir_all(&temp_dir).expect("create temp dir");
+        let db_path = temp_dir.join("scoring_result.db");
+        init_result_db(&db_path).expect("init db");
+        let db_path_str = db_path.to_str().expect("db path utf8");
+        let rank_keys = parse_rank_order(&["AMOUNT desc".to_string(), "J asc".to_string()])
+            .expect("parse rank order");
+        save_rank_order(&db_path, &rank_keys).expect("save rank order");
+
+        let key = |ts_code: &str, key_index: i32, key_value: f64| ScoreRankKey {
+            ts_code: ts_code.to_string(),
+            trade_date: "20240102".to_string(),
+            key_index,
+            key_value,
+        };
+        let (tx, rx) = channel();
+        tx.send(ScoreWriteMessage::Batch(ScoreBatch {
+            summary_rows: vec![
+                summary_row("000001.SZ", "20240102", 55.0),
+                summary_row("000002.SZ", "20240102", 55.0),
+                summary_row("000003.SZ", "20240102", 55.0),
+                summary_row("000004.SZ", "20240102", 60.0),
+            ],
+            rank_key_rows: vec![
+                key("000001.SZ", 1, 100.0),
+                key("000002.SZ", 1, 300.0),
+                key("000003.SZ", 1, 300.0),
+                key("000002.SZ", 2, 80.0),
+                key("000003.SZ", 2, 20.0),
+            ],
+            ..ScoreBatch::default()
+        }))
+        .expect("send batch");
+        drop(tx);
+        write_score_batches_with_scope(
+            db_path_str,
+            None,
+            "qfq",
+            TieBreakWay::RankOrder,
+            &ScoreWriteScope::Range {
+                start_date: "20240102".to_string(),
+                end_date: "20240102".to_string(),
+            },
+            rx,
+        )
+        .expect("write score batches");
+
+        let conn = Connection::open(&db_path).expect("open result db");
+        assert_eq!(load_rank_order(&conn).expect("load rank order"), rank_keys);
+        let ranked = summary_ranks(&conn)
+            .into_iter()
+            .map(|(ts_code, _, _, rank)| (ts_code, rank))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            ranked,
+            vec![
+                ("000004.SZ".to_string(), 1),
+                ("000003.SZ".to_string(), 2),
+                ("000002.SZ".to_string(), 3),
+                ("000001.SZ".to_string(), 4),
+            ]
+        );
+
+        drop(conn);
+        fs::remove_dir_all(temp_dir).expect("remove temp dir");
+    }
+
+    #[test]
+    fn archived_score_runs_diff_ranks_and_rules() {
+        let unique = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("system time")
+            .as_nanos();
+        let temp_dir = std::env::temp_dir().join(format!("lianghua_score_runs_{unique}"));
+        fs::create_dir_all(&temp_dir).expect("create temp dir");
+        let db_path = temp_dir.join("scoring_result.db");
+        init_result_db(&db_path).expect("init db");
+        let db_path_str = db_path.to_str().expect("db path utf8");
+        let scope = ScoreWriteScope::Range {
+            start_date: "20240102".to_string(),
+            end_date: "20240102".to_string(),
+        };
+        let run_meta = |run_id: &str| ScoreRunMeta {
+            run_id: run_id.to_string(),
+            strategy_hash: format!("hash_{run_id}"),
+            adj_type: "qfq".to_string(),
+            start_date: "20240102".to_string(),
+            end_date: "20240102".to_string(),
+            ..ScoreRunMeta::default()
+        };
+
+        write_batch(
+            db_path_str,
+            scope.clone(),
+            ScoreBatch {
+                summary_rows: vec![
+                    summary_row("000001.SZ", "20240102", 52.0),
+                    summary_row("000002.SZ", "20240102", 50.0),
+                ],
+                detail_rows: vec![detail_row("000001.SZ", "20240102", 2.0)],
+                scene_rows: Vec::new(),
+                transition_rows: Vec::new(),
+                rank_key_rows: Vec::new(),
+            },
+        );
+        archive_score_run(&db_path, &run_meta("base"), 5).expect("archive base");
+        write_batch(
+            db_path_str,
+            scope,
+            ScoreBatch {
+                summary_rows: vec![
+                    summary_row("000001.SZ", "20240102", 50.0),
+                    summary_row("000002.SZ", "20240102", 53.0),
+                ],
+                detail_rows: vec![ScoreDetails {
+                    rule_name: "r2".to_string(),
+                    ..detail_row("000002.SZ", "20240102", 3.0)
+                }],
+                scene_rows: Vec::new(),
+                transition_rows: Vec::new(),
+                rank_key_rows: Vec::new(),
+            },
+        );
+        archive_score_run(&db_path, &run_meta("target"), 5).expect("archive target");
+
+        let runs = list_score_runs(&db_path).expect("list runs");
+        assert_eq!(runs.len(), 2);
+        let base_rows = load_score_run_summary_rows(&db_path, "base", "20240101", "20240131")
+            .expect("load base rows");
+        assert_eq!(base_rows[0].rank, Some(1));
+        assert_eq!(base_rows[0].ts_code, "000001.SZ");
+
+        let rank_diffs =
+            query_score_run_rank_diffs(&db_path, "base", "target", "20240102").expect("ranks");
+        assert_eq!(
+            rank_diffs
+                .iter()
+                .map(|item| (item.ts_code.as_str(), item.base_rank, item.target_rank))
+                .collect::<Vec<_>>(),
+            vec![
+                ("000001.SZ", Some(1), Some(2)),
+                ("000002.SZ", Some(2), Some(1))
+            ]
+        );
+        let rule_diffs =
+            query_score_run_rule_diffs(&db_path, "base", "target", "20240102").expect("rules");
+        assert_eq!(rule_diffs.len(), 2);
+        assert_eq!(rule_diffs[0].lost_rules, vec!["r1".to_string()]);
+        assert_eq!(rule_diffs[1].gained_rules, vec!["r2".to_string()]);
+        let rule_counts =
+            query_score_run_rule_counts(&db_path, "base", "target", "20240102", "20240102")
+                .expect("rule counts");
+        assert_eq!(
+            rule_counts
+                .iter()
+                .map(|item| (
+                    item.rule_name.as_str(),
+                    item.base_trigger_count,
+                    item.target_trigger_count
+                ))
+                .collect::<Vec<_>>(),
+            vec![("r1", 1, 0), ("r2", 0, 1)]
+        );
+
+        // 只留最近两个版本, 最早的 base 连同明细一起清掉
+        archive_score_run(&db_path, &run_meta("third"), 2).expect("archive third");
+        assert_eq!(
+            list_score_runs(&db_path)
+                .expect("list runs")
+                .into_iter()
+                .map(|run| run.run_id)
+                .collect::<Vec<_>>(),
+            vec!["third".to_string(), "target".to_string()]
+        );
+        assert!(
+            load_score_run_summary_rows(&db_path, "base", "20240101", "20240131")
+                .expect("load pruned rows")
+                .is_empty()
+        );
+        assert!(archive_score_run(&db_path, &run_meta("fourth"), 0).is_err());
+
+        delete_score_run(&db_path, "target").expect("delete run");
+        assert_eq!(list_score_runs(&db_path).expect("list runs").len(), 1);
+        fs::remove_dir_all(temp_dir).expect("remove temp dir");
+    }
+}
+
+pub fn write_score_batches_from_channel(
+    db_path: &str,
+    source_db_path: Option<&str>,
+    adj_type: &str,
+    tie_break: TieBreakWay,
+    start_date: &str,
+    end_date: &str,
+    rx: Receiver<ScoreWriteMessage>,
+) -> Result<ScoreWriteProfile, String> {
+    write_score_batches_with_scope(
+        db_path,
+        source_db_path,
+        adj_type,
+        tie_break,
+        &ScoreWriteScope::Range {
+            start_date: start_date.to_string(),
+            end_date: end_date.to_string(),
+        },
+        rx,
+    )
+}
+
+pub fn write_score_batches_with_scope(
+    db_path: &str,
+    source_db_path: Option<&str>,
+    adj_type: &str,
+    tie_break: TieBreakWay,
+    scope: &ScoreWriteScope,
+    rx: Receiver<ScoreWriteMessage>,
+) -> Result<ScoreWriteProfile, String> {
+    let total_started_at = time::Instant::now();
+    let mut profile = ScoreWriteProfile::default();
+    let mut conn = Connection::open(db_path).map_err(|e| format!("结果库连接失败:{e}"))?;
+    let (start_date, end_date) = scope.date_range();
+
+    let drop_indexes_started_at = time::Instant::now();
+    drop_result_db_indexes(&conn)?;
+    profile.drop_indexes_ms = drop_indexes_started_at.elapsed().as_millis() as u64;
+
+    let mut source_db_attached = false;
+    if let TieBreakWay::KdjJ = tie_break {
+        let source_db_path =
+            source_db_path.ok_or_else(|| "J值同分排名需要原始库路径".to_string())?;
+        let attach_started_at = time::Instant::now();
+        let attach_sql = format!("ATTACH {} AS src_db", duckdb_string_literal(source_db_path));
+        conn.execute(&attach_sql, [])
+            .map_err(|e| format!("附加原始库失败:{e}"))?;
+        source_db_attached = true;
+        profile.attach_source_db_ms = Some(attach_started_at.elapsed().as_millis() as u64);
+    }
+
+    let write_result = (|| -> Result<(), String> {
+        let tx = conn
+            .transaction()
+            .map_err(|e| format!("创建数据库事务失败:{e}"))?;
+
+        let delete_started_at = time::Instant::now();
+        let detail_table = match scope {
+            ScoreWriteScope::Range { .. } => {
+                delete_score_range(&tx, start_date, end_date)?;
+                "rule_details"
+            }
+            ScoreWriteScope::Stocks { stock_starts, .. } => {
+                delete_score_stock_starts(&tx, stock_starts, end_date)?;
+                delete_convolution_rank_range(&tx, start_date, end_date)?;
+                "rule_details"
+            }
+            ScoreWriteScope::Rules {
+                rule_names,
+                scene_names,
+                ..
+            } => {
+                create_rule_refresh_stage(&tx, rule_names, scene_names)?;
+                delete_convolution_rank_range(&tx, start_date, end_date)?;
+                "rule_details_stage"
+            }
+        };
+        profile.delete_range_ms = delete_started_at.elapsed().as_millis() as u64;
+        create_score_summary_stage(&tx)?;
+
+        let receive_and_append_started_at = time::Instant::now();
+        let mut batch_count = 0usize;
+        let mut scene_rows = Vec::new();
+        let mut transition_rows = Vec::new();
+        {
+            let mut summary_app = tx
+                .appender("score_summary_stage")
+                .map_err(|e| format!("score_summary临时表appender创建失败:{e}"))?;
+            let mut detail_app = tx
+                .appender(detail_table)
+                .map_err(|e| format!("{detail_table} appender创建失败:{e}"))?;
+            let mut rank_key_app = tx
+                .appender(SCORE_RANK_KEYS_TABLE)
+                .map_err(|e| format!("score_rank_keys appender创建失败:{e}"))?;
+
+            for message in rx {
+                let batch = match message {
+                    ScoreWriteMessage::Batch(batch) => batch,
+                    ScoreWriteMessage::Abort(reason) => {
+                        return Err(format!("评分计算中断，结果库回滚:{reason}"));
+                    }
+                };
+
+                append_summary_stage_rows(&mut summary_app, &batch.summary_rows)?;
+                append_detail_rows(&mut detail_app, &batch.detail_rows)?;
+                // 规则重算不改排序键,只有新写入的行带排序键取值
+                if !matches!(scope, ScoreWriteScope::Rules { .. }) {
+                    append_rank_key_rows(&mut rank_key_app, &batch.rank_key_rows)?;
+                }
+                scene_rows.extend(batch.scene_rows);
+                transition_rows.extend(batch.transition_rows);
+                batch_count += 1;
+
+                if batch_count % 32 == 0 {
+                    summary_app
+                        .flush()
+                        .map_err(|e| format!("刷新score_summary失败:{e}"))?;
+                    detail_app
+                        .flush()
+                        .map_err(|e| format!("刷新{detail_table}失败:{e}"))?;
+                    rank_key_app
+                        .flush()
+                        .map_err(|e| format!("刷新score_rank_keys失败:{e}"))?;
+                }
+            }
+
+            summary_app
+                .flush()
+                .map_err(|e| format!("刷新score_summary失败:{e}"))?;
+            detail_app
+                .flush()
+                .map_err(|e| format!("刷新{detail_table}失败:{e}"))?;
+            rank_key_app
+                .flush()
+                .map_err(|e| format!("刷新score_rank_keys失败:{e}"))?;
+        }
+        if let ScoreWriteScope::Rules { .. } = scope {
+            apply_rule_refresh_stage(&tx, start_date, end_date)?;
+        }
+        if let ScoreWriteScope::Range { .. } = scope {
+            rank_scene_rows(&mut scene_rows);
+        }
+        {
+            let mut scene_app = tx
+                .appender("scene_details")
+                .map_err(|e| format!("scene_details appender创建失败:{e}"))?;
             append_scene_rows(&mut scene_app, &scene_rows)?;
             scene_app
                 .flush()
                 .map_err(|e| format!("刷新scene_details失败:{e}"))?;
+            let mut transition_app = tx
+                .appender(SCENE_TRANSITIONS_TABLE)
+                .map_err(|e| format!("scene_transitions appender创建失败:{e}"))?;
+            append_transition_rows(&mut transition_app, &transition_rows)?;
+            transition_app
+                .flush()
+                .map_err(|e| format!("刷新scene_transitions失败:{e}"))?;
         }
         profile.receive_and_append_batches_ms =
             receive_and_append_started_at.elapsed().as_millis() as u64;
         profile.batch_count = batch_count;
 
-        let summary_rank_started_at = time::Instant::now();
-        insert_ranked_summary_from_stage(&tx, tie_break, adj_type)?;
-        profile.summary_rank_ms = summary_rank_started_at.elapsed().as_millis() as u64;
+        let summary_rank_started_at = time::Instant::now();
+        match scope {
+            ScoreWriteScope::Range { .. } => {
+                insert_ranked_summary_from_stage(&tx, tie_break, adj_type)?;
+            }
+            ScoreWriteScope::Stocks { .. } | ScoreWriteScope::Rules { .. } => {
+                rerank_summary_range(&tx, tie_break, adj_type, start_date, end_date)?;
+                rerank_scene_range(&tx, start_date, end_date)?;
+            }
+        }
+        profile.summary_rank_ms = summary_rank_started_at.elapsed().as_millis() as u64;
+
+        let commit_started_at = time::Instant::now();
+        tx.commit().map_err(|e| format!("事务提交错误:{e}"))?;
+        profile.commit_ms = commit_started_at.elapsed().as_millis() as u64;
+
+        Ok::<(), String>(())
+    })();
+
+    let detach_source_result = if source_db_attached {
+        let detach_started_at = time::Instant::now();
+        let result = conn
+            .execute("DETACH src_db", [])
+            .map(|_| ())
+            .map_err(|e| format!("卸载原始库失败:{e}"));
+        profile.detach_source_db_ms = Some(detach_started_at.elapsed().as_millis() as u64);
+        result
+    } else {
+        Ok(())
+    };
+    let recreate_indexes_started_at = time::Instant::now();
+    let recreate_indexes_result = ensure_result_db_indexes(&conn);
+    profile.recreate_indexes_ms = recreate_indexes_started_at.elapsed().as_millis() as u64;
+
+    write_result?;
+    detach_source_result?;
+    recreate_indexes_result?;
+    profile.total_ms = total_started_at.elapsed().as_millis() as u64;
+
+    Ok(profile)
+}
+
+// 读取上次评分记下的策略指纹和每只股票的最后评分日;
+// 旧库没有指纹时用明细里出现过的规则和场景名占位,空指纹会被当成全部变化
+pub fn load_scoring_state(db_path: &Path) -> Result<ScoringState, String> {
+    let conn = Connection::open(db_path).map_err(|e| format!("结果库连接失败:{e}"))?;
+    let mut rules = query_string_rows(
+        &conn,
+        "SELECT rule_name, scene_name, fingerprint FROM score_rule_state ORDER BY rule_name",
+        3,
+    )?
+    .into_iter()
+    .map(|mut row| RuleFingerprint {
+        fingerprint: row.pop().unwrap_or_default(),
+        scene_name: row.pop().unwrap_or_default(),
+        rule_name: row.pop().unwrap_or_default(),
+    })
+    .collect::<Vec<_>>();
+    if rules.is_empty() {
+        rules = query_string_rows(
+            &conn,
+            "SELECT DISTINCT rule_name FROM rule_details ORDER BY rule_name",
+            1,
+        )?
+        .into_iter()
+        .map(|mut row| RuleFingerprint {
+            rule_name: row.pop().unwrap_or_default(),
+            scene_name: String::new(),
+            fingerprint: String::new(),
+        })
+        .collect();
+    }
+
+    let mut scenes = query_string_rows(
+        &conn,
+        "SELECT scene_name, fingerprint FROM score_scene_state ORDER BY scene_name",
+        2,
+    )?
+    .into_iter()
+    .map(|mut row| SceneFingerprint {
+        fingerprint: row.pop().unwrap_or_default(),
+        scene_name: row.pop().unwrap_or_default(),
+    })
+    .collect::<Vec<_>>();
+    if scenes.is_empty() {
+        scenes = query_string_rows(
+            &conn,
+            "SELECT DISTINCT scene_name FROM scene_details ORDER BY scene_name",
+            1,
+        )?
+        .into_iter()
+        .map(|mut row| SceneFingerprint {
+            scene_name: row.pop().unwrap_or_default(),
+            fingerprint: String::new(),
+        })
+        .collect();
+    }
+
+    let mut stock_rows = query_string_rows(
+        &conn,
+        "SELECT ts_code, last_trade_date FROM score_stock_state",
+        2,
+    )?;
+    if stock_rows.is_empty() {
+        stock_rows = query_string_rows(
+            &conn,
+            "SELECT ts_code, MAX(trade_date) FROM score_summary GROUP BY ts_code",
+            2,
+        )?;
+    }
+    let stock_last_dates = stock_rows
+        .into_iter()
+        .map(|mut row| {
+            let last_trade_date = row.pop().unwrap_or_default();
+            (row.pop().unwrap_or_default(), last_trade_date)
+        })
+        .collect::<HashMap<_, _>>();
+
+    let scored_range = conn
+        .query_row(
+            "SELECT MIN(trade_date), MAX(trade_date) FROM score_summary",
+            [],
+            |row| {
+                Ok((
+                    row.get::<_, Option<String>>(0)?,
+                    row.get::<_, Option<String>>(1)?,
+                ))
+            },
+        )
+        .map_err(|e| format!("查询score_summary日期范围失败:{e}"))?;
+    let scored_range = match scored_range {
+        (Some(min_date), Some(max_date)) => Some((min_date, max_date)),
+        _ => None,
+    };
+
+    Ok(ScoringState {
+        strategy: StrategyFingerprint { rules, scenes },
+        stock_last_dates,
+        scored_range,
+    })
+}
+
+// 记下本次评分后的状态; strategy为None表示结果库里混有不同策略的结果,清空指纹
+pub fn save_scoring_state(
+    db_path: &Path,
+    strategy: Option<&StrategyFingerprint>,
+) -> Result<(), String> {
+    let mut conn = Connection::open(db_path).map_err(|e| format!("结果库连接失败:{e}"))?;
+    let tx = conn
+        .transaction()
+        .map_err(|e| format!("创建数据库事务失败:{e}"))?;
+    tx.execute("DELETE FROM score_rule_state", [])
+        .map_err(|e| format!("清空score_rule_state失败:{e}"))?;
+    tx.execute("DELETE FROM score_scene_state", [])
+        .map_err(|e| format!("清空score_scene_state失败:{e}"))?;
+    if let Some(strategy) = strategy {
+        let mut app = tx
+            .appender(SCORE_RULE_STATE_TABLE)
+            .map_err(|e| format!("score_rule_state appender创建失败:{e}"))?;
+        for rule in &strategy.rules {
+            app.append_row(params![rule.rule_name, rule.scene_name, rule.fingerprint])
+                .map_err(|e| format!("写入score_rule_state失败:{e}"))?;
+        }
+        app.flush()
+            .map_err(|e| format!("刷新score_rule_state失败:{e}"))?;
+        drop(app);
+
+        let mut app = tx
+            .appender(SCORE_SCENE_STATE_TABLE)
+            .map_err(|e| format!("score_scene_state appender创建失败:{e}"))?;
+        for scene in &strategy.scenes {
+            app.append_row(params![scene.scene_name, scene.fingerprint])
+                .map_err(|e| format!("写入score_scene_state失败:{e}"))?;
+        }
+        app.flush()
+            .map_err(|e| format!("刷新score_scene_state失败:{e}"))?;
+    }
+    tx.execute("DELETE FROM score_stock_state", [])
+        .map_err(|e| format!("清空score_stock_state失败:{e}"))?;
+    tx.execute(
+        r#"
+        INSERT INTO score_stock_state (ts_code, last_trade_date)
+        SELECT ts_code, MAX(trade_date)
+        FROM score_summary
+        GROUP BY ts_code
+        "#,
+        [],
+    )
+    .map_err(|e| format!("写入score_stock_state失败:{e}"))?;
+    tx.commit().map_err(|e| format!("事务提交错误:{e}"))?;
+    Ok(())
+}
+
+// 记下本次结果每条规则的标准化和加权方式
+pub fn save_rule_weighting(db_path: &Path, rules_cache: &[CachedRule]) -> Result<(), String> {
+    let mut conn = Connection::open(db_path).map_err(|e| format!("结果库连接失败:{e}"))?;
+    let tx = conn
+        .transaction()
+        .map_err(|e| format!("创建数据库事务失败:{e}"))?;
+    tx.execute("DELETE FROM score_rule_weighting", [])
+        .map_err(|e| format!("清空score_rule_weighting失败:{e}"))?;
+    {
+        let mut app = tx
+            .appender(SCORE_RULE_WEIGHTING_TABLE)
+            .map_err(|e| format!("score_rule_weighting appender创建失败:{e}"))?;
+        for rule in rules_cache {
+            let weighting = &rule.weighting;
+            app.append_row(params![
+                rule.name,
+                weighting.normalize.as_str(),
+                weighting.weight,
+                weighting.scale,
+                weighting.cap,
+                weighting.decay,
+                weighting.direction.as_str()
+            ])
+            .map_err(|e| format!("写入score_rule_weighting失败:{e}"))?;
+        }
+        app.flush()
+            .map_err(|e| format!("刷新score_rule_weighting失败:{e}"))?;
+    }
+    tx.commit().map_err(|e| format!("事务提交错误:{e}"))?;
+    Ok(())
+}
+
+pub fn load_rule_weighting(conn: &Connection) -> Result<Vec<(String, RuleWeighting)>, String> {
+    let mut stmt = conn
+        .prepare(
+            r#"
+            SELECT rule_name, normalize, weight, scale, cap, decay, direction
+            FROM score_rule_weighting
+            ORDER BY rule_name ASC
+            "#,
+        )
+        .map_err(|e| format!("预编译规则加权查询失败:{e}"))?;
+    let mut rows = stmt
+        .query([])
+        .map_err(|e| format!("查询规则加权失败:{e}"))?;
+    let mut out = Vec::new();
+    while let Some(row) = rows.next().map_err(|e| format!("读取规则加权失败:{e}"))? {
+        let normalize: String = row.get(1).map_err(|e| format!("读取normalize失败:{e}"))?;
+        let normalize = match normalize.as_str() {
+            "zscore" => NormalizeMode::Zscore,
+            "percentile" => NormalizeMode::Percentile,
+            _ => NormalizeMode::Raw,
+        };
+        let direction: String = row.get(6).map_err(|e| format!("读取direction失败:{e}"))?;
+        let direction = match direction.as_str() {
+            "short" => SceneDirection::Short,
+            _ => SceneDirection::Long,
+        };
+        out.push((
+            row.get(0).map_err(|e| format!("读取rule_name失败:{e}"))?,
+            RuleWeighting {
+                normalize,
+                weight: row.get(2).map_err(|e| format!("读取weight失败:{e}"))?,
+                scale: row.get(3).map_err(|e| format!("读取scale失败:{e}"))?,
+                cap: row.get(4).map_err(|e| format!("读取cap失败:{e}"))?,
+                decay: row.get(5).map_err(|e| format!("读取decay失败:{e}"))?,
+                direction,
+            },
+        ));
+    }
+    Ok(out)
+}
+
+// 结果库记下的排序键;没有记录时返回空,调用方按原来的同分规则排
+pub fn load_rank_order(conn: &Connection) -> Result<Vec<RankKeySpec>, String> {
+    let table_exists = conn
+        .query_row(
+            "SELECT COUNT(*) FROM information_schema.tables WHERE table_name = 'score_rank_order'",
+            [],
+            |row| row.get::<_, i64>(0),
+        )
+        .map_err(|e| format!("检查score_rank_order表失败:{e}"))?;
+    if table_exists == 0 {
+        return Ok(Vec::new());
+    }
+    let mut stmt = conn
+        .prepare("SELECT source, descending FROM score_rank_order ORDER BY key_index ASC")
+        .map_err(|e| format!("预编译排序键查询失败:{e}"))?;
+    let mut rows = stmt.query([]).map_err(|e| format!("查询排序键失败:{e}"))?;
+    let mut out = Vec::new();
+    while let Some(row) = rows.next().map_err(|e| format!("读取排序键失败:{e}"))? {
+        out.push(RankKeySpec {
+            source: row.get(0).map_err(|e| format!("读取source失败:{e}"))?,
+            descending: row.get(1).map_err(|e| format!("读取descending失败:{e}"))?,
+        });
+    }
+    Ok(out)
+}
+
+pub fn save_rank_order(db_path: &Path, rank_keys: &[RankKeySpec]) -> Result<(), String> {
+    let mut conn = Connection::open(db_path).map_err(|e| format!("结果库连接失败:{e}"))?;
+    let tx = conn
+        .transaction()
+        .map_err(|e| format!("创建数据库事务失败:{e}"))?;
+    tx.execute("DELETE FROM score_rank_order", [])
+        .map_err(|e| format!("清空score_rank_order失败:{e}"))?;
+    for (index, key) in rank_keys.iter().enumerate() {
+        tx.execute(
+            "INSERT INTO score_rank_order (key_index, source, descending) VALUES (?, ?, ?)",
+            params![index as i32, key.source, key.descending],
+        )
+        .map_err(|e| format!("写入score_rank_order失败:{e}"))?;
+    }
+    tx.commit().map_err(|e| format!("事务提交错误:{e}"))?;
+    Ok(())
+}
+
+// 把当前结果表里本次区间的总榜和规则明细按run_id另存一份,再只保留最近 keep_last_n 个版本
+// 版本对比只用总榜和规则明细; 场景明细每只股票每天每个场景一行,体积大且对比用不到,不另存
+pub fn archive_score_run(
+    db_path: &Path,
+    meta: &ScoreRunMeta,
+    keep_last_n: usize,
+) -> Result<(), String> {
+    if keep_last_n == 0 {
+        return Err("评分版本保留个数必须>=1".to_string());
+    }
+    let mut conn = Connection::open(db_path).map_err(|e| format!("结果库连接失败:{e}"))?;
+    let tx = conn
+        .transaction()
+        .map_err(|e| format!("创建数据库事务失败:{e}"))?;
+    for table_name in [
+        SCORE_RUN_TABLE,
+        SCORE_RUN_SUMMARY_TABLE,
+        SCORE_RUN_RULE_DETAILS_TABLE,
+    ] {
+        tx.execute(
+            &format!("DELETE FROM {table_name} WHERE run_id = ?"),
+            params![meta.run_id],
+        )
+        .map_err(|e| format!("删除{table_name}旧版本失败:{e}"))?;
+    }
+    tx.execute(
+        r#"
+        INSERT INTO score_run (
+            run_id, strategy_hash, strategy_path, adj_type, start_date, end_date, created_at
+        )
+        VALUES (?, ?, ?, ?, ?, ?, ?)
+        "#,
+        params![
+            meta.run_id,
+            meta.strategy_hash,
+            meta.strategy_path,
+            meta.adj_type,
+            meta.start_date,
+            meta.end_date,
+            meta.created_at
+        ],
+    )
+    .map_err(|e| format!("写入score_run失败:{e}"))?;
+    tx.execute(
+        r#"
+        INSERT INTO score_run_summary (run_id, ts_code, trade_date, total_score, rank)
+        SELECT ?, ts_code, trade_date, total_score, rank
+        FROM score_summary
+        WHERE trade_date >= ? AND trade_date <= ?
+        "#,
+        params![meta.run_id, meta.start_date, meta.end_date],
+    )
+    .map_err(|e| format!("另存score_summary失败:{e}"))?;
+    tx.execute(
+        r#"
+        INSERT INTO score_run_rule_details (run_id, ts_code, trade_date, rule_name, rule_score)
+        SELECT ?, ts_code, trade_date, rule_name, rule_score
+        FROM rule_details
+        WHERE trade_date >= ? AND trade_date <= ?
+        "#,
+        params![meta.run_id, meta.start_date, meta.end_date],
+    )
+    .map_err(|e| format!("另存rule_details失败:{e}"))?;
+    prune_score_runs(&tx, keep_last_n)?;
+    tx.commit().map_err(|e| format!("事务提交错误:{e}"))?;
+    Ok(())
+}
+
+// 按创建时间从新到旧,删掉第 keep_last_n 个之后的版本
+fn prune_score_runs(tx: &Transaction, keep_last_n: usize) -> Result<(), String> {
+    let stale_run_ids = query_string_rows(
+        tx,
+        r#"
+        SELECT run_id
+        FROM score_run
+        ORDER BY created_at DESC, run_id DESC
+        "#,
+        1,
+    )?
+    .into_iter()
+    .skip(keep_last_n)
+    .filter_map(|row| row.into_iter().next())
+    .collect::<Vec<_>>();
+    for run_id in &stale_run_ids {
+        for table_name in [
+            SCORE_RUN_SUMMARY_TABLE,
+            SCORE_RUN_RULE_DETAILS_TABLE,
+            SCORE_RUN_TABLE,
+        ] {
+            tx.execute(
+                &format!("DELETE FROM {table_name} WHERE run_id = ?"),
+                params![run_id],
+            )
+            .map_err(|e| format!("清理{table_name}旧版本失败:{e}"))?;
+        }
+    }
+    Ok(())
+}
+
+pub fn list_score_runs(db_path: &Path) -> Result<Vec<ScoreRunMeta>, String> {
+    let conn = Connection::open(db_path).map_err(|e| format!("结果库连接失败:{e}"))?;
+    Ok(query_string_rows(
+        &conn,
+        r#"
+        SELECT run_id, strategy_hash, strategy_path, adj_type, start_date, end_date, created_at
+        FROM score_run
+        ORDER BY created_at DESC, run_id DESC
+        "#,
+        7,
+    )?
+    .into_iter()
+    .map(score_run_meta_from_row)
+    .collect())
+}
 
-        let commit_started_at = time::Instant::now();
-        tx.commit().map_err(|e| format!("事务提交错误:{e}"))?;
-        profile.commit_ms = commit_started_at.elapsed().as_millis() as u64;
+pub fn load_score_run(db_path: &Path, run_id: &str) -> Result<ScoreRunMeta, String> {
+    list_score_runs(db_path)?
+        .into_iter()
+        .find(|run| run.run_id == run_id)
+        .ok_or_else(|| format!("评分版本不存在:{run_id}"))
+}
 
-        Ok::<(), String>(())
-    })();
+pub fn delete_score_run(db_path: &Path, run_id: &str) -> Result<(), String> {
+    let mut conn = Connection::open(db_path).map_err(|e| format!("结果库连接失败:{e}"))?;
+    let tx = conn
+        .transaction()
+        .map_err(|e| format!("创建数据库事务失败:{e}"))?;
+    for table_name in [
+        SCORE_RUN_TABLE,
+        SCORE_RUN_SUMMARY_TABLE,
+        SCORE_RUN_RULE_DETAILS_TABLE,
+    ] {
+        tx.execute(
+            &format!("DELETE FROM {table_name} WHERE run_id = ?"),
+            params![run_id],
+        )
+        .map_err(|e| format!("删除{table_name}失败:{e}"))?;
+    }
+    tx.commit().map_err(|e| format!("事务提交错误:{e}"))?;
+    Ok(())
+}
 
-    let detach_source_result = if source_db_attached {
-        let detach_started_at = time::Instant::now();
-        let result = conn
-            .execute("DETACH src_db", [])
-            .map(|_| ())
-            .map_err(|e| format!("卸载原始库失败:{e}"));
-        profile.detach_source_db_ms = Some(detach_started_at.elapsed().as_millis() as u64);
-        result
-    } else {
-        Ok(())
-    };
-    let recreate_indexes_started_at = time::Instant::now();
-    let recreate_indexes_result = ensure_result_db_indexes(&conn);
-    profile.recreate_indexes_ms = recreate_indexes_started_at.elapsed().as_millis() as u64;
+fn score_run_meta_from_row(row: Vec<String>) -> ScoreRunMeta {
+    let mut values = row.into_iter();
+    let mut next = || values.next().unwrap_or_default();
+    ScoreRunMeta {
+        run_id: next(),
+        strategy_hash: next(),
+        strategy_path: next(),
+        adj_type: next(),
+        start_date: next(),
+        end_date: next(),
+        created_at: next(),
+    }
+}
 
-    write_result?;
-    detach_source_result?;
-    recreate_indexes_result?;
-    profile.total_ms = total_started_at.elapsed().as_millis() as u64;
+pub fn load_score_run_summary_rows(
+    db_path: &Path,
+    run_id: &str,
+    start_date: &str,
+    end_date: &str,
+) -> Result<Vec<ScoreSummary>, String> {
+    let conn = Connection::open(db_path).map_err(|e| format!("结果库连接失败:{e}"))?;
+    let mut stmt = conn
+        .prepare(
+            r#"
+            SELECT ts_code, trade_date, total_score, CAST(rank AS BIGINT)
+            FROM score_run_summary
+            WHERE run_id = ?
+              AND trade_date >= ?
+              AND trade_date <= ?
+            ORDER BY trade_date ASC, ts_code ASC
+            "#,
+        )
+        .map_err(|e| format!("预编译评分版本总榜查询失败:{e}"))?;
+    let mut rows = stmt
+        .query(params![run_id, start_date, end_date])
+        .map_err(|e| format!("查询评分版本总榜失败:{e}"))?;
+    let mut out = Vec::new();
+    while let Some(row) = rows
+        .next()
+        .map_err(|e| format!("读取评分版本总榜失败:{e}"))?
+    {
+        out.push(ScoreSummary {
+            ts_code: row.get(0).map_err(|e| format!("读取ts_code失败:{e}"))?,
+            trade_date: row.get(1).map_err(|e| format!("读取trade_date失败:{e}"))?,
+            total_score: row.get(2).map_err(|e| format!("读取total_score失败:{e}"))?,
+            rank: row.get(3).map_err(|e| format!("读取rank失败:{e}"))?,
+            ..ScoreSummary::default()
+        });
+    }
+    Ok(out)
+}
 
-    Ok(profile)
+// 同一交易日两个版本的排名,任一版本有分数的股票都列出来
+pub fn query_score_run_rank_diffs(
+    db_path: &Path,
+    base_run_id: &str,
+    target_run_id: &str,
+    trade_date: &str,
+) -> Result<Vec<ScoreRunRankDiff>, String> {
+    let conn = Connection::open(db_path).map_err(|e| format!("结果库连接失败:{e}"))?;
+    let mut stmt = conn
+        .prepare(
+            r#"
+            SELECT
+                COALESCE(b.ts_code, t.ts_code) AS ts_code,
+                b.total_score,
+                t.total_score,
+                CAST(b.rank AS BIGINT),
+                CAST(t.rank AS BIGINT)
+            FROM (
+                SELECT * FROM score_run_summary WHERE run_id = ? AND trade_date = ?
+            ) AS b
+            FULL OUTER JOIN (
+                SELECT * FROM score_run_summary WHERE run_id = ? AND trade_date = ?
+            ) AS t
+              ON b.ts_code = t.ts_code
+            ORDER BY ts_code ASC
+            "#,
+        )
+        .map_err(|e| format!("预编译版本排名对比失败:{e}"))?;
+    let mut rows = stmt
+        .query(params![base_run_id, trade_date, target_run_id, trade_date])
+        .map_err(|e| format!("查询版本排名对比失败:{e}"))?;
+    let mut out = Vec::new();
+    while let Some(row) = rows
+        .next()
+        .map_err(|e| format!("读取版本排名对比失败:{e}"))?
+    {
+        out.push(ScoreRunRankDiff {
+            ts_code: row.get(0).map_err(|e| format!("读取ts_code失败:{e}"))?,
+            trade_date: trade_date.to_string(),
+            base_score: row.get(1).map_err(|e| format!("读取base_score失败:{e}"))?,
+            target_score: row
+                .get(2)
+                .map_err(|e| format!("读取target_score失败:{e}"))?,
+            base_rank: row.get(3).map_err(|e| format!("读取base_rank失败:{e}"))?,
+            target_rank: row.get(4).map_err(|e| format!("读取target_rank失败:{e}"))?,
+        });
+    }
+    Ok(out)
+}
+
+// 同一交易日每只股票在新版本多命中和少命中的规则
+pub fn query_score_run_rule_diffs(
+    db_path: &Path,
+    base_run_id: &str,
+    target_run_id: &str,
+    trade_date: &str,
+) -> Result<Vec<ScoreRunRuleDiff>, String> {
+    let conn = Connection::open(db_path).map_err(|e| format!("结果库连接失败:{e}"))?;
+    let mut stmt = conn
+        .prepare(
+            r#"
+            SELECT
+                COALESCE(b.ts_code, t.ts_code) AS ts_code,
+                COALESCE(b.rule_name, t.rule_name) AS rule_name,
+                b.rule_name IS NULL AS gained
+            FROM (
+                SELECT ts_code, rule_name
+                FROM score_run_rule_details
+                WHERE run_id = ? AND trade_date = ?
+            ) AS b
+            FULL OUTER JOIN (
+                SELECT ts_code, rule_name
+                FROM score_run_rule_details
+                WHERE run_id = ? AND trade_date = ?
+            ) AS t
+              ON b.ts_code = t.ts_code
+             AND b.rule_name = t.rule_name
+            WHERE b.rule_name IS NULL OR t.rule_name IS NULL
+            ORDER BY ts_code ASC, rule_name ASC
+            "#,
+        )
+        .map_err(|e| format!("预编译版本规则对比失败:{e}"))?;
+    let mut rows = stmt
+        .query(params![base_run_id, trade_date, target_run_id, trade_date])
+        .map_err(|e| format!("查询版本规则对比失败:{e}"))?;
+    let mut out: Vec<ScoreRunRuleDiff> = Vec::new();
+    while let Some(row) = rows
+        .next()
+        .map_err(|e| format!("读取版本规则对比失败:{e}"))?
+    {
+        let ts_code: String = row.get(0).map_err(|e| format!("读取ts_code失败:{e}"))?;
+        let rule_name: String = row.get(1).map_err(|e| format!("读取rule_name失败:{e}"))?;
+        let gained: bool = row.get(2).map_err(|e| format!("读取规则差异失败:{e}"))?;
+        if out.last().is_none_or(|item| item.ts_code != ts_code) {
+            out.push(ScoreRunRuleDiff {
+                ts_code,
+                trade_date: trade_date.to_string(),
+                ..ScoreRunRuleDiff::default()
+            });
+        }
+        let Some(item) = out.last_mut() else {
+            continue;
+        };
+        if gained {
+            item.gained_rules.push(rule_name);
+        } else {
+            item.lost_rules.push(rule_name);
+        }
+    }
+    Ok(out)
+}
+
+// 两个版本在区间内每条规则的命中次数,只在一边出现的规则就是新增或删掉的规则
+pub fn query_score_run_rule_counts(
+    db_path: &Path,
+    base_run_id: &str,
+    target_run_id: &str,
+    start_date: &str,
+    end_date: &str,
+) -> Result<Vec<ScoreRunRuleCount>, String> {
+    let conn = Connection::open(db_path).map_err(|e| format!("结果库连接失败:{e}"))?;
+    let mut stmt = conn
+        .prepare(
+            r#"
+            SELECT
+                rule_name,
+                COUNT(*) FILTER (WHERE run_id = ?) AS base_count,
+                COUNT(*) FILTER (WHERE run_id = ?) AS target_count
+            FROM score_run_rule_details
+            WHERE run_id IN (?, ?)
+              AND trade_date >= ?
+              AND trade_date <= ?
+            GROUP BY rule_name
+            ORDER BY rule_name ASC
+            "#,
+        )
+        .map_err(|e| format!("预编译版本规则命中统计失败:{e}"))?;
+    let mut rows = stmt
+        .query(params![
+            base_run_id,
+            target_run_id,
+            base_run_id,
+            target_run_id,
+            start_date,
+            end_date
+        ])
+        .map_err(|e| format!("查询版本规则命中统计失败:{e}"))?;
+    let mut out = Vec::new();
+    while let Some(row) = rows
+        .next()
+        .map_err(|e| format!("读取版本规则命中统计失败:{e}"))?
+    {
+        out.push(ScoreRunRuleCount {
+            rule_name: row.get(0).map_err(|e| format!("读取rule_name失败:{e}"))?,
+            base_trigger_count: row.get(1).map_err(|e| format!("读取base_count失败:{e}"))?,
+            target_trigger_count: row
+                .get(2)
+                .map_err(|e| format!("读取target_count失败:{e}"))?,
+        });
+    }
+    Ok(out)
+}
+
+// 某个交易日的场景阶段变化,scene_name / to_stage 为空时不过滤
+pub fn query_scene_transitions(
+    conn: &Connection,
+    trade_date: &str,
+    scene_name: Option<&str>,
+    to_stage: Option<&str>,
+) -> Result<Vec<SceneTransition>, String> {
+    let mut filters = vec!["trade_date = ?"];
+    let mut values = vec![trade_date];
+    if let Some(scene_name) = scene_name {
+        filters.push("scene_name = ?");
+        values.push(scene_name);
+    }
+    if let Some(to_stage) = to_stage {
+        filters.push("to_stage = ?");
+        values.push(to_stage);
+    }
+    let sql = format!(
+        r#"
+        SELECT
+            ts_code,
+            trade_date,
+            scene_name,
+            direction,
+            from_stage,
+            to_stage,
+            prev_stage_days,
+            rule_names
+        FROM scene_transitions
+        WHERE {}
+        ORDER BY scene_name ASC, to_stage ASC, prev_stage_days DESC, ts_code ASC
+        "#,
+        filters.join(" AND ")
+    );
+    let mut stmt = conn
+        .prepare(&sql)
+        .map_err(|e| format!("预编译scene_transitions查询失败:{e}"))?;
+    let mut rows = stmt
+        .query(params_from_iter(values))
+        .map_err(|e| format!("查询scene_transitions失败:{e}"))?;
+    let mut out = Vec::new();
+    while let Some(row) = rows
+        .next()
+        .map_err(|e| format!("读取scene_transitions失败:{e}"))?
+    {
+        out.push(SceneTransition {
+            ts_code: row.get(0).map_err(|e| format!("读取ts_code失败:{e}"))?,
+            trade_date: row.get(1).map_err(|e| format!("读取trade_date失败:{e}"))?,
+            scene_name: row.get(2).map_err(|e| format!("读取scene_name失败:{e}"))?,
+            direction: row.get(3).map_err(|e| format!("读取direction失败:{e}"))?,
+            from_stage: row.get(4).map_err(|e| format!("读取from_stage失败:{e}"))?,
+            to_stage: row.get(5).map_err(|e| format!("读取to_stage失败:{e}"))?,
+            prev_stage_days: row
+                .get(6)
+                .map_err(|e| format!("读取prev_stage_days失败:{e}"))?,
+            rule_names: row.get(7).map_err(|e| format!("读取rule_names失败:{e}"))?,
+        });
+    }
+    Ok(out)
+}
+
+fn query_string_rows(
+    conn: &Connection,
+    sql: &str,
+    column_count: usize,
+) -> Result<Vec<Vec<String>>, String> {
+    let mut stmt = conn
+        .prepare(sql)
+        .map_err(|e| format!("预编译评分状态查询失败:{e}"))?;
+    let mut rows = stmt
+        .query([])
+        .map_err(|e| format!("查询评分状态失败:{e}"))?;
+    let mut out = Vec::new();
+    while let Some(row) = rows.next().map_err(|e| format!("读取评分状态失败:{e}"))? {
+        let values = (0..column_count)
+            .map(|index| {
+                row.get::<_, Option<String>>(index)
+                    .map(Option::unwrap_or_default)
+                    .map_err(|e| format!("读取评分状态字段失败:{e}"))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        out.push(values);
+    }
+    Ok(out)
 }
 
 pub fn row_into_rt(row_data: RowData) -> Result<Runtime, String> {
-    let mut rt = Runtime::default();
+    let mut rt = Runtime {
+        trade_dates: Arc::new(row_data.trade_dates),
+        ..Default::default()
+    };
     for (name, col) in row_data.cols {
         let n_series = Value::NumSeries(col);
         rt.vars.insert(name, n_series);
     }
+    for (name, text) in row_data.text_cols {
+        rt.vars.insert(name, Value::Str(text));
+    }
 
     Ok(rt)
 }
@@ -1333,12 +3453,80 @@ pub fn cache_rule_build(
     source_dir: &str,
     strategy_path: Option<&str>,
 ) -> Result<Vec<CachedRule>, String> {
-    let rules = ScoreRule::load_rules_with_strategy_path(source_dir, strategy_path)?;
+    StrategyRuleSet::load(source_dir, strategy_path).map(|set| set.rules)
+}
+
+// 策略的规则缓存,连同展开表达式用的公共函数和依赖图;
+// 规则草稿、参数验证这类策略外的表达式也按评分时一样展开 define、挂上 RULE 引用
+pub struct StrategyRuleSet {
+    pub functions: Arc<UserFunctions>,
+    pub graph: StrategyGraph,
+    pub rules: Vec<CachedRule>,
+}
+
+impl StrategyRuleSet {
+    pub fn load(source_dir: &str, strategy_path: Option<&str>) -> Result<Self, String> {
+        let cfg = ScoreConfig::load_with_strategy_path(source_dir, strategy_path)?;
+        let functions = load_expression_prelude(source_dir)?;
+        Self::build(cfg, functions)
+    }
+
+    pub fn build(cfg: ScoreConfig, functions: Arc<UserFunctions>) -> Result<Self, String> {
+        let graph = StrategyGraph::build(&cfg, &functions)?;
+        let rules = build_cached_rules(cfg, &functions, &graph)?;
+        Ok(Self {
+            functions,
+            graph,
+            rules,
+        })
+    }
+
+    // 按规则的方式构建策略外的表达式,同时返回它 RULE 引用的规则
+    pub fn build_expression(
+        &self,
+        name: &str,
+        source: String,
+    ) -> Result<(CachedRuleExpression, Vec<CachedRule>), String> {
+        let (expression, ref_names) =
+            build_cached_rule_expression(name, source, &self.functions, &self.graph)?;
+        let rule_refs = ref_names
+            .iter()
+            .filter_map(|name| self.rules.iter().find(|rule| rule.name.trim() == name))
+            .cloned()
+            .collect();
+        Ok((expression, rule_refs))
+    }
+}
+
+fn build_cached_rules(
+    cfg: ScoreConfig,
+    functions: &UserFunctions,
+    graph: &StrategyGraph,
+) -> Result<Vec<CachedRule>, String> {
+    let scene_weights = cfg
+        .scene
+        .iter()
+        .map(|scene| {
+            (
+                scene.name.trim().to_string(),
+                (scene.weight, scene.direction),
+            )
+        })
+        .collect::<HashMap<_, _>>();
     let mut out = Vec::with_capacity(128);
-    for rule in rules {
+    let mut ref_names = Vec::with_capacity(cfg.rule.len());
+    for rule in cfg.rule {
+        let (scene_weight, direction) = scene_weights
+            .get(rule.scene_name.trim())
+            .copied()
+            .unwrap_or((1.0, SceneDirection::Long));
+        let weighting =
+            RuleWeighting::resolve(&cfg.weighting, rule.normalize, scene_weight, direction);
         match rule.kind {
             RuleKind::Single => {
-                let expression = build_cached_rule_expression(&rule.name, rule.when)?;
+                let (expression, rule_refs) =
+                    build_cached_rule_expression(&rule.name, rule.when, functions, graph)?;
+                ref_names.push(rule_refs);
                 out.push(CachedRule {
                     name: rule.name,
                     scope_windows: rule.scope_windows,
@@ -1351,15 +3539,27 @@ pub fn cache_rule_build(
                     when_ast: expression.when_ast,
                     assigned_names: expression.assigned_names,
                     combination: None,
+                    weighting,
+                    rule_refs: Vec::new(),
                 });
             }
             RuleKind::Combination => {
+                let mut rule_refs = Vec::new();
                 let conditions = rule
                     .conditions
                     .into_iter()
                     .map(|condition| {
-                        let expression =
-                            build_cached_rule_expression(&condition.name, condition.when)?;
+                        let (expression, condition_refs) = build_cached_rule_expression(
+                            &condition.name,
+                            condition.when,
+                            functions,
+                            graph,
+                        )?;
+                        for name in condition_refs {
+                            if !rule_refs.contains(&name) {
+                                rule_refs.push(name);
+                            }
+                        }
                         Ok(CachedCombinationCondition {
                             expression,
                             bonus_points: condition.bonus_points,
@@ -1374,6 +3574,7 @@ pub fn cache_rule_build(
                 let points_by_hits = rule
                     .points_by_hits
                     .ok_or_else(|| format!("组合规则({})缺少 points_by_hits", rule.name))?;
+                ref_names.push(rule_refs);
                 out.push(CachedRule {
                     name: rule.name,
                     scope_windows: rule.scope_windows,
@@ -1391,27 +3592,119 @@ pub fn cache_rule_build(
                         max_points: rule.max_points,
                         max_bonus_points: rule.max_bonus_points,
                     }),
+                    weighting,
+                    rule_refs: Vec::new(),
                 });
             }
         }
     }
+    attach_rule_refs(&mut out, &ref_names);
     Ok(out)
 }
 
+// 按依赖顺序把被引用的规则挂到引用方上,依赖图已保证没有循环
+fn attach_rule_refs(rules: &mut [CachedRule], ref_names: &[Vec<String>]) {
+    fn attach(
+        index: usize,
+        rules: &mut [CachedRule],
+        ref_names: &[Vec<String>],
+        positions: &HashMap<String, usize>,
+        done: &mut [bool],
+    ) {
+        if done[index] {
+            return;
+        }
+        done[index] = true;
+        let mut rule_refs = Vec::with_capacity(ref_names[index].len());
+        for name in &ref_names[index] {
+            let ref_index = positions[name];
+            attach(ref_index, rules, ref_names, positions, done);
+            rule_refs.push(rules[ref_index].clone());
+        }
+        rules[index].rule_refs = rule_refs;
+    }
+
+    let positions = rules
+        .iter()
+        .enumerate()
+        .map(|(index, rule)| (rule.name.trim().to_string(), index))
+        .collect::<HashMap<_, _>>();
+    let mut done = vec![false; rules.len()];
+    for index in 0..rules.len() {
+        attach(index, rules, ref_names, &positions, &mut done);
+    }
+}
+
+pub fn rank_order_build(
+    source_dir: &str,
+    strategy_path: Option<&str>,
+) -> Result<RankOrder, String> {
+    let cfg = ScoreConfig::load_with_strategy_path(source_dir, strategy_path)?;
+    let functions = load_expression_prelude(source_dir)?;
+    let graph = StrategyGraph::build(&cfg, &functions)?;
+    let keys = parse_rank_order(&cfg.rank_order)?;
+    let mut expressions = Vec::new();
+    let mut ref_names = Vec::new();
+    for (index, key) in keys.iter().enumerate() {
+        if key.is_total_score() {
+            continue;
+        }
+        let (expression, key_refs) = build_cached_rule_expression(
+            &format!("rank_order:{}", key.source),
+            key.source.clone(),
+            &functions,
+            &graph,
+        )?;
+        for name in key_refs {
+            if !ref_names.contains(&name) {
+                ref_names.push(name);
+            }
+        }
+        expressions.push((index, expression));
+    }
+    let rule_refs = if ref_names.is_empty() {
+        Vec::new()
+    } else {
+        let rules = cache_rule_build(source_dir, strategy_path)?;
+        ref_names
+            .iter()
+            .filter_map(|name| rules.iter().find(|rule| rule.name.trim() == name))
+            .cloned()
+            .collect()
+    };
+    Ok(RankOrder {
+        keys,
+        expressions,
+        rule_refs,
+    })
+}
+
+// 共享定义的语句按依赖顺序拼到表达式前面,同时返回 RULE("规则名") 引用的规则名
 fn build_cached_rule_expression(
     name: &str,
     when_src: String,
-) -> Result<CachedRuleExpression, String> {
-    let when_ast = parse_expression_program(&when_src)
-        .map_err(|error| format!("表达式({name})解析错误在{}:{}", error.idx, error.msg))?;
-    validate_expression_functions(&when_ast)?;
+    functions: &UserFunctions,
+    graph: &StrategyGraph,
+) -> Result<(CachedRuleExpression, Vec<String>), String> {
+    let checked = graph
+        .check_expression(&when_src, functions)
+        .map_err(|error| format!("表达式({name}){error}"))?;
+    let rule_refs = graph
+        .rule_refs(&checked)
+        .map_err(|error| format!("表达式({name}){error}"))?;
+    let when_ast = graph
+        .expand(&checked)
+        .map_err(|error| format!("表达式({name}){error}"))?;
     let assigned_names = collect_assigned_names_from_expr_program(&when_ast);
-    Ok(CachedRuleExpression {
-        name: name.to_string(),
-        when_src,
-        when_ast,
-        assigned_names,
-    })
+    Ok((
+        CachedRuleExpression {
+            name: name.to_string(),
+            when_src,
+            when_ast,
+            assigned_names,
+        },
+        rule_refs,
+    ))
 }
 
 #[cfg(test)]
@@ -1421,7 +3714,14 @@ mod combination_cache_tests {
         time::{SystemTime, UNIX_EPOCH},
     };
 
-    use super::cache_rule_build;
+    use duckdb::Connection;
+
+    use super::{cache_rule_build, init_result_db, load_rule_weighting, save_rule_weighting};
+    use crate::{
+        data::NormalizeMode,
+        expr::eval::{Runtime, Value},
+        scoring::{CachedRulesPlan, evaluate_cached_rule_scores, scoring_rules_details_cache},
+    };
 
     #[test]
     fn cache_builder_compiles_all_combination_expressions() {
@@ -1478,4 +3778,203 @@ bonus_points = 1.0
         assert_eq!(combination.conditions[1].bonus_points, 1.0);
         assert_eq!(combination.points_by_hits, vec![0.0, 1.0, 3.0]);
     }
+
+    #[test]
+    fn rule_refs_read_the_triggers_scored_for_the_referenced_rules() {
+        let unique = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("clock")
+            .as_nanos();
+        let source_dir = std::env::temp_dir().join(format!("lianghua-rule-refs-{unique}"));
+        create_dir_all(&source_dir).expect("create temp source");
+        write(
+            source_dir.join("score_rule.toml"),
+            r#"
+version = 1
+
+[[scene]]
+name = "趋势启动"
+direction = "long"
+observe_threshold = 1.0
+trigger_threshold = 2.0
+confirm_threshold = 3.0
+fail_threshold = 1.0
+
+[[rule]]
+name = "放量后收红"
+scene = "趋势启动"
+stage = "confirm"
+scope_windows = 1
+scope_way = "LAST"
+points = 1.0
+explain = "引用排在后面的规则"
+when = "RULE('近期放量') AND RULE('量价组合') AND C > O"
+
+[[rule]]
+name = "近期放量"
+scene = "趋势启动"
+stage = "trigger"
+scope_windows = 3
+scope_way = "RECENT"
+points = 1.0
+explain = "近3日放过量"
+when = "V > REF(V, 1)"
+
+[[rule]]
+name = "量价组合"
+scene = "趋势启动"
+kind = "combination"
+stage = "trigger"
+scope_windows = 2
+scope_way = "ANY"
+points_by_hits = [0.0, 0.0, 2.0]
+explain = "收红放量同时出现"
+
+[[rule.condition]]
+name = "收红"
+when = "C > O"
+
+[[rule.condition]]
+name = "放量"
+when = "V > REF(V, 1)"
+"#,
+        )
+        .expect("write strategy");
+        let rules =
+            cache_rule_build(source_dir.to_str().expect("utf8"), None).expect("build rule cache");
+        remove_dir_all(&source_dir).expect("remove temp source");
+
+        let rule_refs = rules[0]
+            .rule_refs
+            .iter()
+            .map(|rule| rule.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(rule_refs, vec!["近期放量", "量价组合"]);
+
+        let runtime = || {
+            let mut rt = Runtime::default();
+            let series = |values: [f64; 8]| Value::NumSeries(values.map(Some).to_vec());
+            rt.vars.insert(
+                "O".to_string(),
+                series([1.0, 1.0, 2.0, 1.0, 1.0, 1.0, 2.0, 1.0]),
+            );
+            rt.vars.insert(
+                "C".to_string(),
+                series([2.0, 2.0, 1.0, 2.0, 2.0, 2.0, 1.0, 2.0]),
+            );
+            rt.vars.insert(
+                "V".to_string(),
+                series([1.0, 2.0, 1.0, 1.0, 3.0, 1.0, 1.0, 1.0]),
+            );
+            rt
+        };
+        let rules_plan = CachedRulesPlan::build(&rules);
+        let mut rt = runtime();
+        let (_, details) =
+            scoring_rules_details_cache(&mut rt, &rules, &rules_plan).expect("score rules");
+
+        // RULE 读到的就是被引用规则打分时的触发序列, RECENT 和组合规则也一样
+        let close_above_open = [true, true, false, true, true, true, false, true];
+        let expected = (0..8)
+            .map(|day| {
+                details[1].triggered[day] && details[2].triggered[day] && close_above_open[day]
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(details[0].triggered, expected);
+        assert_eq!(
+            details[0].triggered,
+            vec![false, true, false, false, true, true, false, false]
+        );
+        assert!(rt.vars.keys().all(|name| !name.starts_with("RULE:")));
+
+        // 单独求值引用方时先按它带的引用算出触发序列
+        let mut standalone = runtime();
+        let (scores, triggered) =
+            evaluate_cached_rule_scores(&rules[0], &mut standalone).expect("score rule alone");
+        assert_eq!(scores, details[0].raw_series);
+        assert_eq!(triggered, details[0].triggered);
+        assert!(
+            standalone
+                .vars
+                .keys()
+                .all(|name| !name.starts_with("RULE:"))
+        );
+    }
+
+    #[test]
+    fn cache_builder_resolves_and_records_rule_weighting() {
+        let unique = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("clock")
+            .as_nanos();
+        let source_dir = std::env::temp_dir().join(format!("lianghua-rule-weighting-{unique}"));
+        create_dir_all(&source_dir).expect("create temp source");
+        write(
+            source_dir.join("score_rule.toml"),
+            r#"
+version = 1
+
+[weighting]
+normalize = "zscore"
+scale = 5.0
+cap = 8.0
+decay = 0.5
+
+[[scene]]
+name = "趋势启动"
+direction = "long"
+weight = 2.0
+observe_threshold = 1.0
+trigger_threshold = 2.0
+confirm_threshold = 3.0
+fail_threshold = 1.0
+
+[[rule]]
+name = "收红"
+scene = "趋势启动"
+stage = "trigger"
+scope_windows = 1
+scope_way = "LAST"
+points = 3.0
+explain = "收红"
+when = "C > O"
+
+[[rule]]
+name = "放量"
+scene = "趋势启动"
+stage = "trigger"
+scope_windows = 1
+scope_way = "LAST"
+points = 1.0
+normalize = "raw"
+explain = "放量"
+when = "V > REF(V, 1)"
+"#,
+        )
+        .expect("write strategy");
+
+        let rules = cache_rule_build(source_dir.to_str().expect("utf8"), None)
+            .expect("build weighted cache");
+        assert_eq!(rules[0].weighting.normalize, NormalizeMode::Zscore);
+        assert_eq!(rules[0].weighting.weight, 2.0);
+        assert_eq!(rules[0].weighting.scale, 5.0);
+        assert_eq!(rules[0].weighting.cap, Some(8.0));
+        assert_eq!(rules[1].weighting.normalize, NormalizeMode::Raw);
+
+        let db_path = source_dir.join("scoring_result.db");
+        init_result_db(&db_path).expect("init db");
+        save_rule_weighting(&db_path, &rules).expect("save rule weighting");
+        let conn = Connection::open(&db_path).expect("open result db");
+        let stored = load_rule_weighting(&conn).expect("load rule weighting");
+        drop(conn);
+        remove_dir_all(&source_dir).expect("remove temp source");
+
+        assert_eq!(
+            stored,
+            vec![
+                ("放量".to_string(), rules[1].weighting.clone()),
+                ("收红".to_string(), rules[0].weighting.clone()),
+            ]
+        );
+    }
 }
@@ -1,6 +1,6 @@
 use crate::{
     data::{RowData, scoring_data::row_into_rt},
-    download::ind_calc::{cache_ind_build, calc_inds_with_cache},
+    download::ind_calc::{IndsCache, cache_ind_build, calc_inds_with_cache},
     expr::eval::Runtime,
 };
 
@@ -170,20 +170,30 @@ pub fn build_simulated_row_data(
     Ok(row_data)
 }
 
-pub fn build_simulated_runtime(
-    source_dir: &str,
+// 追加模拟K线后按指标配置重算指标列,否则指标尾部只是沿用上一根的值
+pub fn build_simulated_row_data_with_inds(
+    indicator_cache: &[IndsCache],
     row_data: RowData,
     simulate: &SimulateBarInput,
-) -> Result<Runtime, String> {
+) -> Result<RowData, String> {
     let mut row_data = build_simulated_row_data(row_data, simulate)?;
-    let indicator_cache = cache_ind_build(source_dir)?;
 
     if !indicator_cache.is_empty() {
-        for (name, series) in calc_inds_with_cache(&indicator_cache, row_data.clone())? {
+        for (name, series) in calc_inds_with_cache(indicator_cache, row_data.clone())? {
             row_data.cols.insert(name, series);
         }
     }
 
+    Ok(row_data)
+}
+
+pub fn build_simulated_runtime(
+    source_dir: &str,
+    row_data: RowData,
+    simulate: &SimulateBarInput,
+) -> Result<Runtime, String> {
+    let indicator_cache = cache_ind_build(source_dir)?;
+    let row_data = build_simulated_row_data_with_inds(&indicator_cache, row_data, simulate)?;
     row_into_rt(row_data)
 }
 
@@ -218,6 +228,7 @@ mod tests {
         crate::data::RowData {
             trade_dates: vec!["20260403".to_string(), "20260407".to_string()],
             cols,
+            text_cols: HashMap::new(),
         }
     }
 
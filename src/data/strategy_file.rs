@@ -0,0 +1,428 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde::Serialize;
+
+const EXTENDS_KEY: &str = "extends";
+const INCLUDE_KEY: &str = "include";
+const DISABLED_KEY: &str = "disabled";
+
+// 规则来自哪个策略文件,以及之后按顺序覆盖过它的文件
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct RuleSource {
+    pub file: String,
+    pub overrides: Vec<String>,
+}
+
+// extends/include 全部展开后的策略
+#[derive(Debug, Clone, Default)]
+pub struct ResolvedStrategy {
+    pub table: toml::Table,
+    pub rule_sources: HashMap<String, RuleSource>,
+}
+
+impl ResolvedStrategy {
+    pub fn to_toml_string(&self) -> Result<String, String> {
+        toml::to_string_pretty(&self.table).map_err(|e| format!("序列化策略规则文件失败: {e}"))
+    }
+}
+
+pub fn is_composed_strategy(table: &toml::Table) -> bool {
+    table.contains_key(EXTENDS_KEY) || table.contains_key(INCLUDE_KEY)
+}
+
+// 展开策略文件:
+// extends = "base.toml" 先整份继承,include = [...] 再追加这些文件的 define/scene/rule(不许重名),
+// 最后本文件的内容: 顶层配置直接覆盖,和继承来的同名 define/scene/rule 只改写写出的字段,
+// rule 写 disabled = true 则去掉继承来的这条规则;路径相对当前文件所在目录
+pub fn resolve_strategy_file(path: &Path) -> Result<ResolvedStrategy, String> {
+    let root_dir = path
+        .parent()
+        .and_then(|dir| dir.canonicalize().ok())
+        .unwrap_or_default();
+    resolve_strategy_file_inner(path, &root_dir, &mut Vec::new())
+}
+
+fn display_name(path: &Path, root_dir: &Path) -> String {
+    path.strip_prefix(root_dir)
+        .unwrap_or(path)
+        .display()
+        .to_string()
+}
+
+fn read_strategy_table(path: &Path) -> Result<toml::Table, String> {
+    let text = fs::read_to_string(path)
+        .map_err(|e| format!("规则文件不存在或不可读: path={}, err={e}", path.display()))?;
+    toml::from_str(&text).map_err(|e| format!("规则文件格式错误: path={}, err={e}", path.display()))
+}
+
+fn entry_name(entry: &toml::Value) -> Option<&str> {
+    entry
+        .get("name")
+        .and_then(toml::Value::as_str)
+        .map(str::trim)
+}
+
+fn take_entries(table: &mut toml::Table, key: &str) -> Result<Vec<toml::Value>, String> {
+    match table.remove(key) {
+        None => Ok(Vec::new()),
+        Some(toml::Value::Array(items)) => Ok(items),
+        Some(_) => Err(format!("{key} 必须是数组")),
+    }
+}
+
+fn resolve_strategy_file_inner(
+    path: &Path,
+    root_dir: &Path,
+    visiting: &mut Vec<PathBuf>,
+) -> Result<ResolvedStrategy, String> {
+    let canonical = path
+        .canonicalize()
+        .map_err(|e| format!("规则文件不存在或不可读: path={}, err={e}", path.display()))?;
+    if visiting.contains(&canonical) {
+        let chain = visiting
+            .iter()
+            .chain(std::iter::once(&canonical))
+            .map(|item| display_name(item, root_dir))
+            .collect::<Vec<_>>()
+            .join(" -> ");
+        return Err(format!("策略文件循环引用: {chain}"));
+    }
+    visiting.push(canonical.clone());
+
+    let mut own = read_strategy_table(&canonical)?;
+    let file_name = display_name(&canonical, root_dir);
+    let base_dir = canonical
+        .parent()
+        .map(Path::to_path_buf)
+        .unwrap_or_default();
+
+    let mut resolved = match own.remove(EXTENDS_KEY) {
+        None => ResolvedStrategy::default(),
+        Some(toml::Value::String(base)) => {
+            resolve_strategy_file_inner(&base_dir.join(base.trim()), root_dir, visiting)?
+        }
+        Some(_) => return Err(format!("{file_name} 的 extends 必须是文件名字符串")),
+    };
+
+    let includes = match own.remove(INCLUDE_KEY) {
+        None => Vec::new(),
+        Some(toml::Value::Array(items)) => items
+            .iter()
+            .map(|item| {
+                item.as_str()
+                    .map(|value| base_dir.join(value.trim()))
+                    .ok_or_else(|| format!("{file_name} 的 include 必须是文件名数组"))
+            })
+            .collect::<Result<Vec<_>, _>>()?,
+        Some(_) => return Err(format!("{file_name} 的 include 必须是文件名数组")),
+    };
+    for include_path in includes {
+        let include_name = display_name(&include_path, root_dir);
+        let included = resolve_strategy_file_inner(&include_path, root_dir, visiting)?;
+        append_included(&mut resolved, included, &include_name)?;
+    }
+
+    apply_own(&mut resolved, own, &file_name)?;
+    visiting.pop();
+    Ok(resolved)
+}
+
+// include 的文件只贡献 define、scene 和 rule
+fn append_included(
+    resolved: &mut ResolvedStrategy,
+    mut included: ResolvedStrategy,
+    include_name: &str,
+) -> Result<(), String> {
+    for key in ["define", "scene", "rule"] {
+        let existing = take_entries(&mut resolved.table, key)?;
+        let names = existing
+            .iter()
+            .filter_map(entry_name)
+            .map(str::to_string)
+            .collect::<HashSet<_>>();
+        let mut merged = existing;
+        for entry in take_entries(&mut included.table, key)? {
+            if let Some(name) = entry_name(&entry).filter(|name| names.contains(*name)) {
+                return Err(format!(
+                    "include文件{include_name}的{key}与已有{key}重名: {name}"
+                ));
+            }
+            merged.push(entry);
+        }
+        if merged.is_empty() && key == "define" {
+            continue;
+        }
+        resolved
+            .table
+            .insert(key.to_string(), toml::Value::Array(merged));
+    }
+    resolved.rule_sources.extend(included.rule_sources);
+    Ok(())
+}
+
+fn apply_own(
+    resolved: &mut ResolvedStrategy,
+    mut own: toml::Table,
+    file_name: &str,
+) -> Result<(), String> {
+    let own_defines = take_entries(&mut own, "define")?;
+    let own_scenes = take_entries(&mut own, "scene")?;
+    let own_rules = take_entries(&mut own, "rule")?;
+    for (key, value) in own {
+        resolved.table.insert(key, value);
+    }
+
+    let defines = merge_named_entries(&mut resolved.table, "define", own_defines)?;
+    if !defines.is_empty() {
+        resolved
+            .table
+            .insert("define".to_string(), toml::Value::Array(defines));
+    }
+    let scenes = merge_named_entries(&mut resolved.table, "scene", own_scenes)?;
+    resolved
+        .table
+        .insert("scene".to_string(), toml::Value::Array(scenes));
+
+    let mut rules = take_entries(&mut resolved.table, "rule")?;
+    let inherited_rules = rules.len();
+    let mut removed = vec![false; inherited_rules];
+    for mut rule in own_rules {
+        let disabled = match rule
+            .as_table_mut()
+            .and_then(|item| item.remove(DISABLED_KEY))
+        {
+            None => false,
+            Some(toml::Value::Boolean(value)) => value,
+            Some(_) => return Err(format!("{file_name} 的 disabled 必须是 true/false")),
+        };
+        let name = entry_name(&rule).unwrap_or_default().to_string();
+        let target = rules[..inherited_rules]
+            .iter()
+            .position(|item| entry_name(item) == Some(name.as_str()));
+        match (target, disabled) {
+            (Some(index), true) => {
+                removed[index] = true;
+                resolved.rule_sources.remove(&name);
+            }
+            (None, true) => {
+                return Err(format!("{file_name} 禁用的规则不是继承来的: {name}"));
+            }
+            (Some(index), false) => {
+                merge_entry(&mut rules[index], rule);
+                resolved
+                    .rule_sources
+                    .entry(name)
+                    .or_default()
+                    .overrides
+                    .push(file_name.to_string());
+            }
+            (None, false) => {
+                rules.push(rule);
+                resolved.rule_sources.insert(
+                    name,
+                    RuleSource {
+                        file: file_name.to_string(),
+                        overrides: Vec::new(),
+                    },
+                );
+            }
+        }
+    }
+    let rules = rules
+        .into_iter()
+        .enumerate()
+        .filter(|(index, _)| !removed.get(*index).copied().unwrap_or(false))
+        .map(|(_, rule)| rule)
+        .collect();
+    resolved
+        .table
+        .insert("rule".to_string(), toml::Value::Array(rules));
+    Ok(())
+}
+
+// 同名的改写继承来的条目,其余追加在后面
+fn merge_named_entries(
+    table: &mut toml::Table,
+    key: &str,
+    own_entries: Vec<toml::Value>,
+) -> Result<Vec<toml::Value>, String> {
+    let mut entries = take_entries(table, key)?;
+    let inherited = entries.len();
+    for entry in own_entries {
+        let target = entry_name(&entry).and_then(|name| {
+            entries[..inherited]
+                .iter()
+                .position(|item| entry_name(item) == Some(name))
+        });
+        match target {
+            Some(index) => merge_entry(&mut entries[index], entry),
+            None => entries.push(entry),
+        }
+    }
+    Ok(entries)
+}
+
+fn merge_entry(target: &mut toml::Value, patch: toml::Value) {
+    let (Some(target), toml::Value::Table(patch)) = (target.as_table_mut(), patch) else {
+        return;
+    };
+    for (key, value) in patch {
+        target.insert(key, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    use super::*;
+    use crate::data::ScoreConfig;
+
+    fn temp_strategy_dir(files: &[(&str, &str)]) -> PathBuf {
+        let unique = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("clock")
+            .as_nanos();
+        let dir = std::env::temp_dir().join(format!("lianghua-strategy-file-{unique}"));
+        fs::create_dir_all(&dir).expect("create temp dir");
+        for (name, text) in files {
+            fs::write(dir.join(name), text).expect("write strategy");
+        }
+        dir
+    }
+
+    const BASE: &str = r#"
+version = 1
+rank_order = ["total_score desc"]
+
+[[scene]]
+name = "s1"
+direction = "long"
+observe_threshold = 1.0
+trigger_threshold = 2.0
+confirm_threshold = 3.0
+fail_threshold = 1.0
+
+[[rule]]
+name = "r1"
+scene = "s1"
+stage = "trigger"
+scope_windows = 1
+scope_way = "LAST"
+points = 1.0
+explain = "r1"
+when = "C > O"
+
+[[rule]]
+name = "r2"
+scene = "s1"
+stage = "trigger"
+scope_windows = 1
+scope_way = "LAST"
+points = 2.0
+explain = "r2"
+when = "V > REF(V, 1)"
+"#;
+
+    const RISK: &str = r#"
+[[rule]]
+name = "risk"
+scene = "s1"
+stage = "fail"
+scope_windows = 1
+scope_way = "LAST"
+points = -3.0
+explain = "risk"
+when = "C < O"
+"#;
+
+    #[test]
+    fn extends_and_include_resolve_overrides_and_sources() {
+        let dir = temp_strategy_dir(&[
+            ("base.toml", BASE),
+            ("risk.toml", RISK),
+            (
+                "exp.toml",
+                r#"
+extends = "base.toml"
+include = ["risk.toml"]
+
+[[scene]]
+name = "s1"
+trigger_threshold = 5.0
+
+[[rule]]
+name = "r1"
+points = 4.0
+
+[[rule]]
+name = "r2"
+disabled = true
+"#,
+            ),
+        ]);
+
+        let resolved = resolve_strategy_file(&dir.join("exp.toml")).expect("resolve strategy");
+        let config = ScoreConfig::load_resolved(&dir.join("exp.toml")).expect("load config");
+        fs::remove_dir_all(&dir).expect("remove temp dir");
+
+        assert_eq!(config.rule.len(), 2);
+        assert_eq!(config.rule[0].points, 4.0);
+        assert_eq!(config.rule[1].source.file, "risk.toml");
+        assert_eq!(config.scene[0].trigger_threshold, 5.0);
+        assert_eq!(config.rank_order, vec!["total_score desc".to_string()]);
+
+        let table = &resolved.table;
+        assert!(!is_composed_strategy(table));
+        assert_eq!(table["version"].as_integer(), Some(1));
+        assert_eq!(table["scene"][0]["trigger_threshold"].as_float(), Some(5.0));
+        assert_eq!(table["scene"][0]["confirm_threshold"].as_float(), Some(3.0));
+        let rules = table["rule"].as_array().expect("rules");
+        assert_eq!(
+            rules.iter().filter_map(entry_name).collect::<Vec<_>>(),
+            vec!["r1", "risk"]
+        );
+        assert_eq!(rules[0]["points"].as_float(), Some(4.0));
+        assert_eq!(rules[0]["when"].as_str(), Some("C > O"));
+        assert_eq!(
+            resolved.rule_sources["r1"],
+            RuleSource {
+                file: "base.toml".to_string(),
+                overrides: vec!["exp.toml".to_string()],
+            }
+        );
+        assert_eq!(resolved.rule_sources["risk"].file, "risk.toml");
+        assert!(!resolved.rule_sources.contains_key("r2"));
+    }
+
+    #[test]
+    fn resolve_rejects_cycles_and_duplicate_includes() {
+        let dir = temp_strategy_dir(&[
+            ("a.toml", "extends = \"b.toml\"\n"),
+            ("b.toml", "extends = \"a.toml\"\n"),
+            ("base.toml", BASE),
+            (
+                "dup.toml",
+                "extends = \"base.toml\"\ninclude = [\"base.toml\"]\n",
+            ),
+            (
+                "bad_disable.toml",
+                "extends = \"base.toml\"\n[[rule]]\nname = \"nope\"\ndisabled = true\n",
+            ),
+        ]);
+
+        let cycle = resolve_strategy_file(&dir.join("a.toml")).expect_err("cycle");
+        let duplicate = resolve_strategy_file(&dir.join("dup.toml")).expect_err("duplicate");
+        let bad_disable =
+            resolve_strategy_file(&dir.join("bad_disable.toml")).expect_err("bad disable");
+        fs::remove_dir_all(&dir).expect("remove temp dir");
+
+        assert!(cycle.contains("a.toml -> b.toml -> a.toml"));
+        assert!(duplicate.contains("重名"));
+        assert!(bad_disable.contains("nope"));
+    }
+}
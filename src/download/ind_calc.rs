@@ -10,13 +10,18 @@ use duckdb::params_from_iter;
 
 use crate::{
     data::scoring_data::row_into_rt,
-    data::{DataReader, IndsData, RowData, STOCK_DATA_RUNTIME_FIELDS, ind_toml_path},
+    data::{
+        DataReader, IndsData, RowData, STOCK_DATA_RUNTIME_FIELDS, ind_toml_path,
+        load_expression_prelude,
+    },
     download::ProBarRow,
     expr::eval::Value,
     expr::{
+        func::UserFunctions,
         parser::Stmts,
         validation::{
-            estimate_expression_warmup, parse_expression_program, validate_expression_functions,
+            estimate_expression_warmup, parse_expression_program_with_functions,
+            validate_expression_functions,
         },
     },
 };
@@ -37,6 +42,7 @@ struct IndicatorFileStamp {
 #[derive(Clone)]
 struct IndicatorCacheEntry {
     stamp: IndicatorFileStamp,
+    functions: Arc<UserFunctions>,
     caches: Vec<IndsCache>,
 }
 
@@ -83,10 +89,13 @@ fn build_indicator_file_stamp(path: &Path) -> Result<IndicatorFileStamp, String>
     })
 }
 
-fn compile_indicator_defs(inds: Vec<crate::data::IndData>) -> Result<Vec<IndsCache>, String> {
+fn compile_indicator_defs(
+    inds: Vec<crate::data::IndData>,
+    functions: &UserFunctions,
+) -> Result<Vec<IndsCache>, String> {
     let mut out = Vec::with_capacity(128);
     for ind in inds {
-        let stmt = parse_expression_program(&ind.expr)
+        let stmt = parse_expression_program_with_functions(&ind.expr, functions)
             .map_err(|e| format!("表达式解析错误在{}:{}", e.idx, e.msg))?;
         validate_expression_functions(&stmt)?;
         out.push(IndsCache {
@@ -122,11 +131,18 @@ pub fn cache_ind_build(source_dir: &str) -> Result<Vec<IndsCache>, String> {
     let Some(inds) = load_optional_inds(source_dir)? else {
         return Ok(Vec::new());
     };
-    compile_indicator_defs(inds)
+    let functions = load_expression_prelude(source_dir)?;
+    compile_indicator_defs(inds, &functions)
 }
 
 pub fn cache_ind_build_from_path(indicator_path: &Path) -> Result<Vec<IndsCache>, String> {
     let stamp = build_indicator_file_stamp(indicator_path)?;
+    // 公共函数文件和指标文件放在同一目录下
+    let prelude_dir = indicator_path
+        .parent()
+        .map(|dir| dir.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let functions = load_expression_prelude(&prelude_dir)?;
     let cache_key = indicator_path.to_string_lossy().to_string();
     let cache_store = indicator_cache_store();
 
@@ -135,7 +151,7 @@ pub fn cache_ind_build_from_path(indicator_path: &Path) -> Result<Vec<IndsCache>
             .lock()
             .map_err(|_| "指标缓存锁已中毒".to_string())?;
         if let Some(entry) = cache_map.get(&cache_key) {
-            if entry.stamp == stamp {
+            if entry.stamp == stamp && Arc::ptr_eq(&entry.functions, &functions) {
                 return Ok(entry.caches.clone());
             }
         }
@@ -152,7 +168,7 @@ pub fn cache_ind_build_from_path(indicator_path: &Path) -> Result<Vec<IndsCache>
     }
 
     let inds = IndsData::parse_from_text(&text)?;
-    let caches = compile_indicator_defs(inds)?;
+    let caches = compile_indicator_defs(inds, &functions)?;
 
     let mut cache_map = cache_store
         .lock()
@@ -161,6 +177,7 @@ pub fn cache_ind_build_from_path(indicator_path: &Path) -> Result<Vec<IndsCache>
         cache_key,
         IndicatorCacheEntry {
             stamp,
+            functions,
             caches: caches.clone(),
         },
     );
@@ -172,10 +189,11 @@ pub fn warmup_ind_estimate(source_dir: &str) -> Result<usize, String> {
     let Some(inds) = load_optional_inds(source_dir)? else {
         return Ok(0);
     };
+    let functions = load_expression_prelude(source_dir)?;
     let mut all_ind_max_need = 0;
 
     for ind in inds {
-        let stmts = parse_expression_program(&ind.expr)
+        let stmts = parse_expression_program_with_functions(&ind.expr, &functions)
             .map_err(|e| format!("表达式解析错误在{}:{}", e.idx, e.msg))?;
         validate_expression_functions(&stmts)?;
         all_ind_max_need = all_ind_max_need.max(estimate_expression_warmup(&stmts)?);
@@ -334,7 +352,7 @@ pub fn load_many_tail_rows_with_warmup_need(
         let cols = cols_by_stock
             .remove(&ts_code)
             .ok_or_else(|| format!("批量warmup缺少列数据: {ts_code}"))?;
-        let row_data = RowData { trade_dates, cols };
+        let row_data = RowData { trade_dates, cols, text_cols: HashMap::new() };
         row_data.validate()?;
         out.insert(ts_code, row_data);
     }
@@ -363,7 +381,7 @@ fn pro_bar_rows_to_row_data(rows: &[ProBarRow]) -> Result<RowData, String> {
         }
     }
 
-    let row_data = RowData { trade_dates, cols };
+    let row_data = RowData { trade_dates, cols, text_cols: HashMap::new() };
     row_data.validate()?;
     Ok(row_data)
 }
@@ -384,6 +402,7 @@ fn normalize_row_data_for_indicators(row_data: RowData) -> Result<RowData, Strin
     let out = RowData {
         trade_dates: row_data.trade_dates,
         cols,
+        text_cols: row_data.text_cols,
     };
     out.validate()?;
     Ok(out)
@@ -556,6 +575,7 @@ mod tests {
     use super::{calc_inds_for_rows_with_cache, calc_inds_with_cache, compile_indicator_defs};
     use crate::data::{IndsData, RowData};
     use crate::download::{MoneyflowRow, ProBarRow};
+    use crate::expr::func::UserFunctions;
 
     #[test]
     fn calculated_indicator_remains_available_to_later_indicators() {
@@ -580,10 +600,12 @@ mod tests {
             "#,
         )
         .expect("parse indicators");
-        let cache = compile_indicator_defs(definitions).expect("compile indicators");
+        let cache = compile_indicator_defs(definitions, &UserFunctions::default())
+            .expect("compile indicators");
         let row_data = RowData {
             trade_dates: vec!["20260101".to_string(), "20260102".to_string()],
             cols: HashMap::from([("C".to_string(), vec![Some(1.23), Some(2.34)])]),
+            text_cols: HashMap::new(),
         };
 
         let result = calc_inds_with_cache(&cache, row_data).expect("calculate indicators");
@@ -606,7 +628,8 @@ mod tests {
             "#,
         )
         .expect("parse indicators");
-        let cache = compile_indicator_defs(definitions).expect("compile indicators");
+        let cache = compile_indicator_defs(definitions, &UserFunctions::default())
+            .expect("compile indicators");
         let rows = vec![ProBarRow {
             ts_code: "000001.SZ".to_string(),
             trade_date: "20240102".to_string(),
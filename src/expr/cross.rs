@@ -0,0 +1,311 @@
+//! Cross-sectional (market-wide) expression functions.
+//!
+//! `XRANK(expr)`, `XPCT(expr)`, `XZSCORE(expr)` and `XMEDIAN(expr)` compare a
+//! value with every other stock on the same trade date. An optional second
+//! argument names a text field to group by, e.g. `XRANK(PCT_CHG, INDUSTRY)`.
+//! A group value listing several members (the comma-joined `CONCEPT` field)
+//! places the stock in each of those groups; its result is the mean of the
+//! per-group results.
+//!
+//! A single stock's runtime cannot see the market, so the batch scorer pulls
+//! these calls out of the rule ASTs before evaluation: [`extract_cross_calls`]
+//! replaces each call with an identifier whose series is computed market-wide
+//! from the per-stock inner values. Evaluated directly on one stock the
+//! functions degrade to a one-stock cross-section.
+
+use std::collections::HashMap;
+
+use super::parser::{Expr, Stmt, Stmts};
+
+const EPS: f64 = 1e-12;
+
+/// Prefix of the runtime keys that replace cross-sectional calls.
+pub const CROSS_KEY_PREFIX: &str = "__XS";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CrossKind {
+    /// 降序排名, 并列取最小名次
+    Rank,
+    /// 百分位: 不大于当前值的股票占比 * 100
+    Pct,
+    /// 标准分, 总体标准差
+    Zscore,
+    /// 截面中位数
+    Median,
+}
+
+impl CrossKind {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.trim().to_ascii_uppercase().as_str() {
+            "XRANK" => Some(Self::Rank),
+            "XPCT" => Some(Self::Pct),
+            "XZSCORE" => Some(Self::Zscore),
+            "XMEDIAN" => Some(Self::Median),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Rank => "XRANK",
+            Self::Pct => "XPCT",
+            Self::Zscore => "XZSCORE",
+            Self::Median => "XMEDIAN",
+        }
+    }
+}
+
+/// 对同一交易日(同一分组)内的取值做截面计算, 缺失值保持缺失且不参与计算
+pub fn cross_section_values(kind: CrossKind, values: &[Option<f64>]) -> Vec<Option<f64>> {
+    let mut present: Vec<f64> = values.iter().flatten().copied().collect();
+    if present.is_empty() {
+        return vec![None; values.len()];
+    }
+    let n = present.len() as f64;
+
+    match kind {
+        CrossKind::Rank => values
+            .iter()
+            .map(|v| {
+                let v = (*v)?;
+                let above = present.iter().filter(|other| **other > v + EPS).count();
+                Some((above + 1) as f64)
+            })
+            .collect(),
+        CrossKind::Pct => values
+            .iter()
+            .map(|v| {
+                let v = (*v)?;
+                let not_above = present.iter().filter(|other| **other <= v + EPS).count();
+                Some(not_above as f64 / n * 100.0)
+            })
+            .collect(),
+        CrossKind::Zscore => {
+            let mean = present.iter().sum::<f64>() / n;
+            let var = present.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / n;
+            let std = var.sqrt();
+            values
+                .iter()
+                .map(|v| {
+                    let v = (*v)?;
+                    if std < EPS {
+                        Some(0.0)
+                    } else {
+                        Some((v - mean) / std)
+                    }
+                })
+                .collect()
+        }
+        CrossKind::Median => {
+            present.sort_by(|a, b| a.total_cmp(b));
+            let mid = present.len() / 2;
+            let median = if present.len().is_multiple_of(2) {
+                (present[mid - 1] + present[mid]) / 2.0
+            } else {
+                present[mid]
+            };
+            values.iter().map(|v| v.map(|_| median)).collect()
+        }
+    }
+}
+
+/// 一个被抽出的截面调用: 逐股计算 `value` (和分组 `group`), 再按日期做截面
+#[derive(Debug, Clone)]
+pub struct CrossCall {
+    pub key: String,
+    pub kind: CrossKind,
+    pub value: Stmts,
+    pub group: Option<Stmts>,
+}
+
+/// 把 `stmts` 里的截面函数调用替换为运行时变量, 新出现的调用追加到 `calls`,
+/// 参数完全相同的调用共用同一个变量。
+///
+/// 参数里引用的前置赋值会被展开, 使参数可以脱离原表达式单独逐股计算。
+pub fn extract_cross_calls(stmts: &mut Stmts, calls: &mut Vec<CrossCall>) -> Result<(), String> {
+    let mut defs: HashMap<String, Expr> = HashMap::new();
+    for stmt in &mut stmts.item {
+        match stmt {
+            Stmt::Expr(expr) => rewrite_expr(expr, &defs, calls)?,
+            Stmt::Assign { name, value } => {
+                rewrite_expr(value, &defs, calls)?;
+                let expanded = substitute_defs(value, &defs);
+                defs.insert(name.clone(), expanded);
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn contains_cross_call(expr: &Expr) -> bool {
+    match expr {
+        Expr::Call { name, args } => {
+            CrossKind::parse(name).is_some() || args.iter().any(contains_cross_call)
+        }
+        Expr::Unary { rhs, .. } => contains_cross_call(rhs),
+        Expr::Binary { lhs, rhs, .. } => contains_cross_call(lhs) || contains_cross_call(rhs),
+        Expr::Number(_) | Expr::Str(_) | Expr::Ident(_) => false,
+    }
+}
+
+fn references_cross_key(expr: &Expr) -> bool {
+    match expr {
+        Expr::Ident(name) => name.starts_with(CROSS_KEY_PREFIX),
+        Expr::Call { args, .. } => args.iter().any(references_cross_key),
+        Expr::Unary { rhs, .. } => references_cross_key(rhs),
+        Expr::Binary { lhs, rhs, .. } => references_cross_key(lhs) || references_cross_key(rhs),
+        Expr::Number(_) | Expr::Str(_) => false,
+    }
+}
+
+fn rewrite_expr(
+    expr: &mut Expr,
+    defs: &HashMap<String, Expr>,
+    calls: &mut Vec<CrossCall>,
+) -> Result<(), String> {
+    match expr {
+        Expr::Call { name, args } => {
+            let Some(kind) = CrossKind::parse(name) else {
+                for arg in args.iter_mut() {
+                    rewrite_expr(arg, defs, calls)?;
+                }
+                return Ok(());
+            };
+            if args.is_empty() || args.len() > 2 {
+                return Err(format!("{}需要1到2个参数", kind.name()));
+            }
+
+            let value = substitute_defs(&args[0], defs);
+            let group = args.get(1).map(|arg| substitute_defs(arg, defs));
+            for arg in std::iter::once(&value).chain(group.iter()) {
+                if contains_cross_call(arg) || references_cross_key(arg) {
+                    return Err(format!("{}的参数不能依赖其他截面函数", kind.name()));
+                }
+            }
+
+            let key = match calls.iter().find(|call| {
+                call.kind == kind
+                    && single_expr(&call.value) == Some(&value)
+                    && call.group.as_ref().and_then(single_expr) == group.as_ref()
+            }) {
+                Some(call) => call.key.clone(),
+                None => {
+                    let key = format!("{CROSS_KEY_PREFIX}{}", calls.len());
+                    calls.push(CrossCall {
+                        key: key.clone(),
+                        kind,
+                        value: Stmts {
+                            item: vec![Stmt::Expr(value)],
+                        },
+                        group: group.map(|group| Stmts {
+                            item: vec![Stmt::Expr(group)],
+                        }),
+                    });
+                    key
+                }
+            };
+            *expr = Expr::Ident(key);
+            Ok(())
+        }
+        Expr::Unary { rhs, .. } => rewrite_expr(rhs, defs, calls),
+        Expr::Binary { lhs, rhs, .. } => {
+            rewrite_expr(lhs, defs, calls)?;
+            rewrite_expr(rhs, defs, calls)
+        }
+        Expr::Number(_) | Expr::Str(_) | Expr::Ident(_) => Ok(()),
+    }
+}
+
+fn single_expr(stmts: &Stmts) -> Option<&Expr> {
+    match stmts.item.as_slice() {
+        [Stmt::Expr(expr)] => Some(expr),
+        _ => None,
+    }
+}
+
+fn substitute_defs(expr: &Expr, defs: &HashMap<String, Expr>) -> Expr {
+    match expr {
+        Expr::Ident(name) => defs.get(name).cloned().unwrap_or_else(|| expr.clone()),
+        Expr::Call { name, args } => Expr::Call {
+            name: name.clone(),
+            args: args.iter().map(|arg| substitute_defs(arg, defs)).collect(),
+        },
+        Expr::Unary { op, rhs } => Expr::Unary {
+            op: op.clone(),
+            rhs: Box::new(substitute_defs(rhs, defs)),
+        },
+        Expr::Binary { op, lhs, rhs } => Expr::Binary {
+            op: op.clone(),
+            lhs: Box::new(substitute_defs(lhs, defs)),
+            rhs: Box::new(substitute_defs(rhs, defs)),
+        },
+        Expr::Number(_) | Expr::Str(_) => expr.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expr::parser::{Parser, lex_all};
+
+    fn parse(text: &str) -> Stmts {
+        Parser::new(lex_all(text))
+            .parse_main()
+            .expect("parse should succeed")
+    }
+
+    #[test]
+    fn cross_section_values_rank_pct_zscore_median() {
+        let values = [Some(3.0), None, Some(1.0), Some(3.0), Some(2.0)];
+
+        let rank = cross_section_values(CrossKind::Rank, &values);
+        assert_eq!(rank, vec![Some(1.0), None, Some(4.0), Some(1.0), Some(3.0)]);
+
+        let pct = cross_section_values(CrossKind::Pct, &values);
+        assert_eq!(
+            pct,
+            vec![Some(100.0), None, Some(25.0), Some(100.0), Some(50.0)]
+        );
+
+        let median = cross_section_values(CrossKind::Median, &values);
+        assert_eq!(
+            median,
+            vec![Some(2.5), None, Some(2.5), Some(2.5), Some(2.5)]
+        );
+
+        let zscore = cross_section_values(CrossKind::Zscore, &[Some(1.0), Some(3.0)]);
+        assert_eq!(zscore, vec![Some(-1.0), Some(1.0)]);
+        let flat = cross_section_values(CrossKind::Zscore, &[Some(2.0), Some(2.0)]);
+        assert_eq!(flat, vec![Some(0.0), Some(0.0)]);
+    }
+
+    #[test]
+    fn extract_cross_calls_rewrites_and_dedupes_calls() {
+        let mut first = parse("X := C / REF(C, 1); XRANK(X) <= 10 AND XPCT(V, INDUSTRY) > 90");
+        let mut second = parse("XRANK(C / REF(C, 1)) <= 3");
+        let mut calls = Vec::new();
+
+        extract_cross_calls(&mut first, &mut calls).expect("first should extract");
+        extract_cross_calls(&mut second, &mut calls).expect("second should extract");
+
+        assert_eq!(calls.len(), 2);
+        assert_eq!(calls[0].kind, CrossKind::Rank);
+        assert!(calls[0].group.is_none());
+        assert_eq!(calls[1].kind, CrossKind::Pct);
+        assert!(calls[1].group.is_some());
+        assert!(!first.item.iter().any(|stmt| match stmt {
+            Stmt::Expr(expr) | Stmt::Assign { value: expr, .. } => contains_cross_call(expr),
+        }));
+        let Stmt::Expr(Expr::Binary { lhs, .. }) = &second.item[0] else {
+            panic!("second should stay a comparison");
+        };
+        assert_eq!(**lhs, Expr::Ident("__XS0".to_string()));
+    }
+
+    #[test]
+    fn extract_cross_calls_rejects_nested_cross_section() {
+        let mut stmts = parse("A := XRANK(C); XPCT(A) > 50");
+        let err = extract_cross_calls(&mut stmts, &mut Vec::new()).unwrap_err();
+        assert!(err.contains("截面函数"));
+    }
+}
@@ -1,4 +1,8 @@
+use crate::expr::cross::{CrossKind, cross_section_values};
 use crate::expr::parser::{BinaryOp, Expr, Stmt, Stmts, UnaryOp};
+use crate::expr::period::{
+    PeriodFreq, Resample, align_to_daily, period_ids, resample_last, resample_series,
+};
 use std::collections::HashMap;
 use std::sync::Arc;
 
@@ -12,6 +16,8 @@ pub struct EvalErr {
 #[derive(Debug, Default, Clone)]
 pub struct Runtime {
     pub vars: HashMap<String, Value>,
+    /// 日线交易日期(yyyymmdd), PERIOD按它切分周/月; 为空时不支持多周期
+    pub trade_dates: Arc<Vec<String>>,
 }
 
 fn to_bool(v: f64) -> bool {
@@ -662,6 +668,13 @@ impl Runtime {
                 }),
             },
             Value::BoolSeries(bs) => Ok(Value::Bool(bs[idx])),
+            Value::Str(text) => Ok(Value::Str(text)),
+            Value::StrSeries(mut series) => match series[idx].take() {
+                Some(text) => Ok(Value::Str(text)),
+                None => Err(EvalErr {
+                    msg: "LAST命中的值为空".to_string(),
+                }),
+            },
         }
     }
 
@@ -851,17 +864,43 @@ impl Runtime {
             });
         }
 
+        // 短路: 没有任何一根K线选中的分支不求值, CASE 展开的嵌套 IF 逐层跳过
         let cond = self.eval_expr(&args[0])?;
-        let l = self.eval_expr(&args[1])?;
-        let r = self.eval_expr(&args[2])?;
         let len_cond = Value::len_of(&cond);
-        let len_l = Value::len_of(&l);
-        let len_r = Value::len_of(&r);
+        let cond_series = Value::as_bool_series(&cond, len_cond)?;
+        let l = if cond_series.iter().any(|b| *b) {
+            Some(self.eval_expr(&args[1])?)
+        } else {
+            None
+        };
+        let r = if cond_series.iter().any(|b| !*b) {
+            Some(self.eval_expr(&args[2])?)
+        } else {
+            None
+        };
+        // 标量条件选中标量分支时, 结果长度要由另一分支决定, 仍需求值它以保持原来的序列形状
+        let (l, r) = match (l, r) {
+            (Some(l), None) if len_cond == 1 && Value::len_of(&l) == 1 => {
+                (Some(l), Some(self.eval_expr(&args[2])?))
+            }
+            (None, Some(r)) if len_cond == 1 && Value::len_of(&r) == 1 => {
+                (Some(self.eval_expr(&args[1])?), Some(r))
+            }
+            pair => pair,
+        };
+        let len_l = l.as_ref().map_or(0, Value::len_of);
+        let len_r = r.as_ref().map_or(0, Value::len_of);
         let len = len_cond.max(len_l).max(len_r);
 
         let b_series = Value::as_bool_series(&cond, len)?;
-        let l_series = Value::as_num_series(&l, len)?;
-        let r_series = Value::as_num_series(&r, len)?;
+        let l_series = match &l {
+            Some(l) => Value::as_num_series(l, len)?,
+            None => vec![None; len],
+        };
+        let r_series = match &r {
+            Some(r) => Value::as_num_series(r, len)?,
+            None => vec![None; len],
+        };
         let mut out = Vec::with_capacity(len);
 
         for i in 0..len {
@@ -1510,6 +1549,759 @@ impl Runtime {
     }
 }
 
+impl Runtime {
+    fn impl_str_match(
+        &mut self,
+        args: &[Expr],
+        fn_name: &str,
+        matcher: fn(&str, &str) -> bool,
+    ) -> Result<Value, EvalErr> {
+        if args.len() != 2 {
+            return Err(EvalErr {
+                msg: format!("{fn_name}需要两个参数"),
+            });
+        }
+
+        let text = self.eval_expr(&args[0])?;
+        let pattern = self.eval_expr(&args[1])?;
+        if !Value::is_str(&text) || !Value::is_str(&pattern) {
+            return Err(EvalErr {
+                msg: format!("{fn_name}的两个参数都必须是字符串"),
+            });
+        }
+
+        if let (Value::Str(text), Value::Str(pattern)) = (&text, &pattern) {
+            return Ok(Value::Bool(matcher(text, pattern)));
+        }
+
+        let len = Value::len_of(&text).max(Value::len_of(&pattern));
+        let mut out = Vec::with_capacity(len);
+        for i in 0..len {
+            match (Value::str_at(&text, i), Value::str_at(&pattern, i)) {
+                (Some(text), Some(pattern)) => out.push(matcher(text, pattern)),
+                _ => out.push(false),
+            }
+        }
+        Ok(Value::BoolSeries(out))
+    }
+
+    // 单只股票上没有全市场截面, 退化为只含自身的截面; 批量评分会预先替换掉这些调用
+    fn impl_cross_section(&mut self, args: &[Expr], kind: CrossKind) -> Result<Value, EvalErr> {
+        if args.is_empty() || args.len() > 2 {
+            return Err(EvalErr {
+                msg: format!("{}需要1到2个参数", kind.name()),
+            });
+        }
+
+        let v = self.eval_expr(&args[0])?;
+        let group = match args.get(1) {
+            Some(arg) => Some(self.eval_expr(arg)?),
+            None => None,
+        };
+        if let Some(group) = &group
+            && !Value::is_str(group)
+        {
+            return Err(EvalErr {
+                msg: format!("{}的分组参数必须是字符串", kind.name()),
+            });
+        }
+
+        let len = group
+            .as_ref()
+            .map_or(1, Value::len_of)
+            .max(Value::len_of(&v));
+        let n_series = Value::as_num_series(&v, len)?;
+        let mut out = Vec::with_capacity(len);
+        for (i, value) in n_series.into_iter().enumerate() {
+            let in_group = group
+                .as_ref()
+                .is_none_or(|group| Value::str_at(group, i).is_some());
+            let value = if in_group { value } else { None };
+            out.push(cross_section_values(kind, &[value])[0]);
+        }
+        Ok(Value::NumSeries(out))
+    }
+}
+
+// 通达信/同花顺常用指标函数, 口径按通达信公式说明实现
+impl Runtime {
+    fn tdx_window(&mut self, arg: &Expr) -> Result<usize, EvalErr> {
+        let ori_n = Value::as_num(&self.eval_expr(arg)?)?;
+        Ok(if ori_n as i64 <= 0 { 1 } else { ori_n as usize })
+    }
+
+    // 满窗口逐根计算, 窗口内有空值时结果为空
+    fn impl_tdx_rolling(
+        &mut self,
+        args: &[Expr],
+        fn_name: &str,
+        calc: fn(&[f64]) -> Option<f64>,
+    ) -> Result<Value, EvalErr> {
+        if args.len() != 2 {
+            return Err(EvalErr {
+                msg: format!("{fn_name}需要两个参数"),
+            });
+        }
+
+        let v = self.eval_expr(&args[0])?;
+        let len = Value::len_of(&v);
+        let num_series = Value::as_num_series(&v, len)?;
+        let std_n = self.tdx_window(&args[1])?;
+        let mut out = Vec::with_capacity(len);
+        for i in 0..len {
+            if i + 1 < std_n {
+                out.push(None);
+                continue;
+            }
+            let window = num_series[i + 1 - std_n..=i]
+                .iter()
+                .copied()
+                .collect::<Option<Vec<f64>>>();
+            out.push(window.and_then(|window| calc(&window)));
+        }
+        Ok(Value::NumSeries(out))
+    }
+
+    // CORR/COVAR: 两个序列同一窗口
+    fn impl_tdx_pair_rolling(
+        &mut self,
+        args: &[Expr],
+        fn_name: &str,
+        calc: fn(&[f64], &[f64]) -> Option<f64>,
+    ) -> Result<Value, EvalErr> {
+        if args.len() != 3 {
+            return Err(EvalErr {
+                msg: format!("{fn_name}需要三个参数"),
+            });
+        }
+
+        let x = self.eval_expr(&args[0])?;
+        let y = self.eval_expr(&args[1])?;
+        let len = Value::len_of(&x).max(Value::len_of(&y));
+        let x_series = Value::as_num_series(&x, len)?;
+        let y_series = Value::as_num_series(&y, len)?;
+        let std_n = self.tdx_window(&args[2])?;
+        let mut out = Vec::with_capacity(len);
+        for i in 0..len {
+            if i + 1 < std_n {
+                out.push(None);
+                continue;
+            }
+            let start = i + 1 - std_n;
+            let xs = x_series[start..=i]
+                .iter()
+                .copied()
+                .collect::<Option<Vec<f64>>>();
+            let ys = y_series[start..=i]
+                .iter()
+                .copied()
+                .collect::<Option<Vec<f64>>>();
+            out.push(match (xs, ys) {
+                (Some(xs), Some(ys)) => calc(&xs, &ys),
+                _ => None,
+            });
+        }
+        Ok(Value::NumSeries(out))
+    }
+
+    // DMA(X,A): Y = A*X + (1-A)*Y', A可以是序列; 遇到空值重新起算
+    fn impl_dma(&mut self, args: &[Expr]) -> Result<Value, EvalErr> {
+        if args.len() != 2 {
+            return Err(EvalErr {
+                msg: "DMA需要两个参数".to_string(),
+            });
+        }
+
+        let x = self.eval_expr(&args[0])?;
+        let a = self.eval_expr(&args[1])?;
+        let len = Value::len_of(&x).max(Value::len_of(&a));
+        let x_series = Value::as_num_series(&x, len)?;
+        let a_series = Value::as_num_series(&a, len)?;
+        let mut out = Vec::with_capacity(len);
+        let mut prev: Option<f64> = None;
+        for i in 0..len {
+            let (Some(x), Some(a)) = (x_series[i], a_series[i]) else {
+                out.push(None);
+                prev = None;
+                continue;
+            };
+            let dma = match prev {
+                None => x,
+                Some(p) => a * x + (1.0 - a) * p,
+            };
+            out.push(Some(dma));
+            prev = Some(dma);
+        }
+        Ok(Value::NumSeries(out))
+    }
+
+    // FILTER(X,N): X成立后其后N根内的信号被过滤
+    fn impl_filter(&mut self, args: &[Expr]) -> Result<Value, EvalErr> {
+        if args.len() != 2 {
+            return Err(EvalErr {
+                msg: "FILTER需要两个参数".to_string(),
+            });
+        }
+
+        let v = self.eval_expr(&args[0])?;
+        let len = Value::len_of(&v);
+        let b_series = Value::as_bool_series(&v, len)?;
+        let ori_n = Value::as_num(&self.eval_expr(&args[1])?)?;
+        let skip = if ori_n as i64 <= 0 { 0 } else { ori_n as usize };
+        let mut out = vec![false; len];
+        let mut i = 0;
+        while i < len {
+            if b_series[i] {
+                out[i] = true;
+                i += skip + 1;
+            } else {
+                i += 1;
+            }
+        }
+        Ok(Value::BoolSeries(out))
+    }
+
+    // BARSCOUNT(X): 从第一个有效值开始的周期数, 第一根为1
+    fn impl_barscount(&mut self, args: &[Expr]) -> Result<Value, EvalErr> {
+        if args.len() != 1 {
+            return Err(EvalErr {
+                msg: "BARSCOUNT需要一个参数".to_string(),
+            });
+        }
+
+        let v = self.eval_expr(&args[0])?;
+        let len = Value::len_of(&v);
+        let num_series = Value::as_num_series(&v, len)?;
+        let first = num_series.iter().position(Option::is_some).unwrap_or(len);
+        Ok(Value::NumSeries(
+            (0..len)
+                .map(|i| (i >= first).then(|| (i - first + 1) as f64))
+                .collect(),
+        ))
+    }
+
+    // BARSSINCE(X): 距第一次X成立的周期数, 之前为空
+    fn impl_barssince(&mut self, args: &[Expr]) -> Result<Value, EvalErr> {
+        if args.len() != 1 {
+            return Err(EvalErr {
+                msg: "BARSSINCE需要一个参数".to_string(),
+            });
+        }
+
+        let v = self.eval_expr(&args[0])?;
+        let len = Value::len_of(&v);
+        let b_series = Value::as_bool_series(&v, len)?;
+        let first = b_series.iter().position(|b| *b).unwrap_or(len);
+        Ok(Value::NumSeries(
+            (0..len)
+                .map(|i| (i >= first).then(|| (i - first) as f64))
+                .collect(),
+        ))
+    }
+
+    // HHVBARS/LLVBARS(X,N): 到N周期内极值的距离, N为0表示全部历史, 并列取最近一根
+    fn impl_extreme_bars(
+        &mut self,
+        args: &[Expr],
+        fn_name: &str,
+        highest: bool,
+    ) -> Result<Value, EvalErr> {
+        if args.len() != 2 {
+            return Err(EvalErr {
+                msg: format!("{fn_name}需要两个参数"),
+            });
+        }
+
+        let v = self.eval_expr(&args[0])?;
+        let len = Value::len_of(&v);
+        let num_series = Value::as_num_series(&v, len)?;
+        let ori_n = Value::as_num(&self.eval_expr(&args[1])?)?;
+        let std_n = if ori_n as i64 <= 0 { 0 } else { ori_n as usize };
+        let mut out = Vec::with_capacity(len);
+        for i in 0..len {
+            if i + 1 < std_n {
+                out.push(None);
+                continue;
+            }
+            let start = if std_n == 0 { 0 } else { i + 1 - std_n };
+            let mut best: Option<(usize, f64)> = None;
+            for j in (start..=i).rev() {
+                let Some(a) = num_series[j] else {
+                    continue;
+                };
+                let better = match best {
+                    None => true,
+                    Some((_, b)) => {
+                        if highest {
+                            a > b
+                        } else {
+                            a < b
+                        }
+                    }
+                };
+                if better {
+                    best = Some((j, a));
+                }
+            }
+            out.push(best.map(|(j, _)| (i - j) as f64));
+        }
+        Ok(Value::NumSeries(out))
+    }
+
+    // ZIG/PEAK/TROUGH的第一个参数: 0到3依次为开高低收, 否则按序列计算
+    fn zig_source(&mut self, arg: &Expr, fn_name: &str) -> Result<Vec<Option<f64>>, EvalErr> {
+        let v = self.eval_expr(arg)?;
+        let v = match v {
+            Value::Num(k) => {
+                let key = match k as i64 {
+                    0 => "O",
+                    1 => "H",
+                    2 => "L",
+                    3 => "C",
+                    _ => {
+                        return Err(EvalErr {
+                            msg: format!("{fn_name}的第一个参数为数字时只能是0到3"),
+                        });
+                    }
+                };
+                self.vars.get(key).cloned().ok_or_else(|| EvalErr {
+                    msg: format!("变量不存在:{}", key),
+                })?
+            }
+            other => other,
+        };
+        let len = Value::len_of(&v);
+        Value::as_num_series(&v, len)
+    }
+
+    // ZIG(K,N): 转向幅度N%的之字线, 转折点之间线性连接; 与通达信一样使用了未来数据
+    fn impl_zig(&mut self, args: &[Expr]) -> Result<Value, EvalErr> {
+        if args.len() != 2 {
+            return Err(EvalErr {
+                msg: "ZIG需要两个参数".to_string(),
+            });
+        }
+
+        let num_series = self.zig_source(&args[0], "ZIG")?;
+        let pct = Value::as_num(&self.eval_expr(&args[1])?)?;
+        let turns = ZigTurns::build(&num_series, pct);
+        let mut out = vec![None; num_series.len()];
+        for pair in turns.points.windows(2) {
+            let (a, b) = (pair[0], pair[1]);
+            let (Some(va), Some(vb)) = (num_series[a], num_series[b]) else {
+                continue;
+            };
+            for (i, slot) in out.iter_mut().enumerate().take(b + 1).skip(a) {
+                *slot = Some(va + (vb - va) * (i - a) as f64 / (b - a) as f64);
+            }
+        }
+        if let [only] = turns.points.as_slice() {
+            out[*only] = num_series[*only];
+        }
+        Ok(Value::NumSeries(out))
+    }
+
+    // PEAK/TROUGH(K,N,M): 截至当前已确认的前M个波峰/波谷的值
+    // 转折点要等反向走出N%才确认, 确认之前的K线看不到它, 避免和通达信一样引入未来数据
+    fn impl_zig_turn(
+        &mut self,
+        args: &[Expr],
+        fn_name: &str,
+        peak: bool,
+    ) -> Result<Value, EvalErr> {
+        if args.len() != 3 {
+            return Err(EvalErr {
+                msg: format!("{fn_name}需要三个参数"),
+            });
+        }
+
+        let num_series = self.zig_source(&args[0], fn_name)?;
+        let pct = Value::as_num(&self.eval_expr(&args[1])?)?;
+        let nth = self.tdx_window(&args[2])?;
+        let turns = ZigTurns::build(&num_series, pct)
+            .turns
+            .into_iter()
+            .filter(|(_, is_peak, _)| *is_peak == peak)
+            .map(|(idx, _, confirmed_at)| (idx, confirmed_at))
+            .collect::<Vec<_>>();
+
+        let mut out = Vec::with_capacity(num_series.len());
+        let mut seen = 0;
+        for i in 0..num_series.len() {
+            while seen < turns.len() && turns[seen].1 <= i {
+                seen += 1;
+            }
+            out.push(if seen >= nth {
+                num_series[turns[seen - nth].0]
+            } else {
+                None
+            });
+        }
+        Ok(Value::NumSeries(out))
+    }
+
+    // BACKSET(X,N): X成立时把当前及之前共N根置为成立
+    fn impl_backset(&mut self, args: &[Expr]) -> Result<Value, EvalErr> {
+        if args.len() != 2 {
+            return Err(EvalErr {
+                msg: "BACKSET需要两个参数".to_string(),
+            });
+        }
+
+        let v = self.eval_expr(&args[0])?;
+        let len = Value::len_of(&v);
+        let b_series = Value::as_bool_series(&v, len)?;
+        let ori_n = Value::as_num(&self.eval_expr(&args[1])?)?;
+        let std_n = if ori_n as i64 <= 0 { 0 } else { ori_n as usize };
+        let mut out = vec![false; len];
+        for i in 0..len {
+            if b_series[i] && std_n > 0 {
+                let start = (i + 1).saturating_sub(std_n);
+                out[start..=i].iter_mut().for_each(|b| *b = true);
+            }
+        }
+        Ok(Value::BoolSeries(out))
+    }
+
+    // VALUEWHEN(COND,X): COND成立时取X当前值, 否则沿用上一次的值
+    fn impl_valuewhen(&mut self, args: &[Expr]) -> Result<Value, EvalErr> {
+        if args.len() != 2 {
+            return Err(EvalErr {
+                msg: "VALUEWHEN需要两个参数".to_string(),
+            });
+        }
+
+        let cond = self.eval_expr(&args[0])?;
+        let x = self.eval_expr(&args[1])?;
+        let len = Value::len_of(&cond).max(Value::len_of(&x));
+        let b_series = Value::as_bool_series(&cond, len)?;
+        let num_series = Value::as_num_series(&x, len)?;
+        let mut out = Vec::with_capacity(len);
+        let mut last = None;
+        for i in 0..len {
+            if b_series[i] {
+                last = num_series[i];
+            }
+            out.push(last);
+        }
+        Ok(Value::NumSeries(out))
+    }
+
+    // SAR(N,S,M): 抛物线转向, 前N根定初始方向和SAR, 步长S%, 极限M%
+    fn impl_sar(&mut self, args: &[Expr]) -> Result<Value, EvalErr> {
+        if args.len() != 3 {
+            return Err(EvalErr {
+                msg: "SAR需要三个参数".to_string(),
+            });
+        }
+
+        let std_n = self.tdx_window(&args[0])?;
+        let step = Value::as_num(&self.eval_expr(&args[1])?)? / 100.0;
+        let limit = Value::as_num(&self.eval_expr(&args[2])?)? / 100.0;
+        let high = self.zig_source(&Expr::Number(1.0), "SAR")?;
+        let low = self.zig_source(&Expr::Number(2.0), "SAR")?;
+        let len = high.len().min(low.len());
+
+        // (是否上涨, sar, 极值点, 加速因子)
+        let mut state: Option<(bool, f64, f64, f64)> = None;
+        let mut out = Vec::with_capacity(len);
+        for i in 0..len {
+            let (Some(hi), Some(lo)) = (high[i], low[i]) else {
+                out.push(None);
+                continue;
+            };
+            let Some((up, sar, ep, af)) = &mut state else {
+                if i + 1 < std_n {
+                    out.push(None);
+                    continue;
+                }
+                let start = i + 1 - std_n;
+                let window = (start..=i)
+                    .map(|j| Some((high[j]?, low[j]?)))
+                    .collect::<Option<Vec<(f64, f64)>>>();
+                let Some(window) = window else {
+                    out.push(None);
+                    continue;
+                };
+                let hh = window.iter().map(|(h, _)| *h).fold(f64::MIN, f64::max);
+                let ll = window.iter().map(|(_, l)| *l).fold(f64::MAX, f64::min);
+                let up = hi + lo >= window[0].0 + window[0].1;
+                let (sar, ep) = if up { (ll, hh) } else { (hh, ll) };
+                state = Some((up, sar, ep, step));
+                out.push(Some(sar));
+                continue;
+            };
+
+            let mut next = *sar + *af * (*ep - *sar);
+            if *up {
+                if lo < next {
+                    *up = false;
+                    next = *ep;
+                    *ep = lo;
+                    *af = step;
+                } else if hi > *ep {
+                    *ep = hi;
+                    *af = (*af + step).min(limit);
+                }
+            } else if hi > next {
+                *up = true;
+                next = *ep;
+                *ep = hi;
+                *af = step;
+            } else if lo < *ep {
+                *ep = lo;
+                *af = (*af + step).min(limit);
+            }
+            *sar = next;
+            out.push(Some(next));
+        }
+        Ok(Value::NumSeries(out))
+    }
+}
+
+impl Runtime {
+    // PERIOD(FREQ,X): 在周线("W")/月线("M")上计算X, 每根日线取上一个已走完周期的值
+    // 评分策略按依赖顺序把被引用规则的触发序列先算进变量,这里只负责读出
+    fn impl_rule(&mut self, args: &[Expr]) -> Result<Value, EvalErr> {
+        let [Expr::Str(rule_name)] = args else {
+            return Err(EvalErr {
+                msg: "RULE需要一个规则名字符串参数".to_string(),
+            });
+        };
+        self.vars
+            .get(&rule_trigger_var(rule_name))
+            .cloned()
+            .ok_or_else(|| EvalErr {
+                msg: format!("规则({rule_name})的触发序列不存在,RULE只能在评分策略里引用其他规则"),
+            })
+    }
+
+    fn impl_period(&mut self, args: &[Expr]) -> Result<Value, EvalErr> {
+        if args.len() != 2 {
+            return Err(EvalErr {
+                msg: "PERIOD需要两个参数".to_string(),
+            });
+        }
+
+        let freq = match self.eval_expr(&args[0])? {
+            Value::Str(text) => PeriodFreq::parse(&text).ok_or_else(|| EvalErr {
+                msg: format!("PERIOD的周期只支持W或M, 拿到{text:?}"),
+            })?,
+            _ => {
+                return Err(EvalErr {
+                    msg: "PERIOD的第1个参数必须是字符串\"W\"或\"M\"".to_string(),
+                });
+            }
+        };
+        if self.trade_dates.is_empty() {
+            return Err(EvalErr {
+                msg: "PERIOD需要日线交易日期, 不能嵌套使用".to_string(),
+            });
+        }
+
+        let (ids, count) = period_ids(freq, &self.trade_dates).map_err(|msg| EvalErr { msg })?;
+        let len = ids.len();
+        let mut inner = Runtime::default();
+        for (name, value) in &self.vars {
+            let resampled = match value {
+                Value::NumSeries(series) if series.len() == len => Value::NumSeries(
+                    resample_series(series, &ids, count, Resample::for_field(name)),
+                ),
+                Value::SharedNumSeries(series) if series.len() == len => Value::NumSeries(
+                    resample_series(series, &ids, count, Resample::for_field(name)),
+                ),
+                Value::BoolSeries(series) if series.len() == len => {
+                    Value::BoolSeries(resample_last(series, &ids, count, false))
+                }
+                Value::StrSeries(series) if series.len() == len => {
+                    Value::StrSeries(resample_last(series, &ids, count, None))
+                }
+                Value::Num(_) | Value::Bool(_) | Value::Str(_) => value.clone(),
+                // 长度对不上日线的序列无法切分周期, 留给内部表达式报变量不存在
+                _ => continue,
+            };
+            inner.vars.insert(name.clone(), resampled);
+        }
+
+        match inner.eval_expr(&args[1])? {
+            Value::NumSeries(series) if series.len() == count => {
+                Ok(Value::NumSeries(align_to_daily(&series, &ids, None)))
+            }
+            Value::SharedNumSeries(series) if series.len() == count => {
+                Ok(Value::NumSeries(align_to_daily(&series, &ids, None)))
+            }
+            Value::BoolSeries(series) if series.len() == count => {
+                Ok(Value::BoolSeries(align_to_daily(&series, &ids, false)))
+            }
+            value @ (Value::Num(_) | Value::Bool(_)) => Ok(value),
+            Value::Str(_) | Value::StrSeries(_) => Err(EvalErr {
+                msg: "PERIOD的结果不能是字符串".to_string(),
+            }),
+            _ => Err(EvalErr {
+                msg: "PERIOD的结果序列长度不对".to_string(),
+            }),
+        }
+    }
+}
+
+// ZIG的转折点: points是连线端点(含首根和最后一根), turns是已确认的波峰(true)/波谷(false)
+struct ZigTurns {
+    points: Vec<usize>,
+    // (转折点, 是否波峰, 确认该转折点的K线)
+    turns: Vec<(usize, bool, usize)>,
+}
+
+impl ZigTurns {
+    fn build(series: &[Option<f64>], pct: f64) -> Self {
+        let valid = series
+            .iter()
+            .enumerate()
+            .filter_map(|(i, v)| v.map(|v| (i, v)))
+            .collect::<Vec<_>>();
+        let Some(&(first, first_v)) = valid.first() else {
+            return Self {
+                points: Vec::new(),
+                turns: Vec::new(),
+            };
+        };
+
+        let rate = pct / 100.0;
+        let mut points = vec![first];
+        let mut turns = Vec::new();
+        // 0: 方向未定, 1: 上涨段, -1: 下跌段
+        let mut trend = 0;
+        let (mut ext, mut ext_v) = (first, first_v);
+        for &(i, v) in &valid[1..] {
+            match trend {
+                0 => {
+                    if v >= first_v * (1.0 + rate) {
+                        trend = 1;
+                        (ext, ext_v) = (i, v);
+                    } else if v <= first_v * (1.0 - rate) {
+                        trend = -1;
+                        (ext, ext_v) = (i, v);
+                    }
+                }
+                1 => {
+                    if v > ext_v {
+                        (ext, ext_v) = (i, v);
+                    } else if v <= ext_v * (1.0 - rate) {
+                        points.push(ext);
+                        turns.push((ext, true, i));
+                        trend = -1;
+                        (ext, ext_v) = (i, v);
+                    }
+                }
+                _ => {
+                    if v < ext_v {
+                        (ext, ext_v) = (i, v);
+                    } else if v >= ext_v * (1.0 + rate) {
+                        points.push(ext);
+                        turns.push((ext, false, i));
+                        trend = 1;
+                        (ext, ext_v) = (i, v);
+                    }
+                }
+            }
+        }
+
+        let last = valid[valid.len() - 1].0;
+        for idx in [ext, last] {
+            if points.last() != Some(&idx) {
+                points.push(idx);
+            }
+        }
+        Self { points, turns }
+    }
+}
+
+fn linear_regression(ys: &[f64]) -> (f64, f64) {
+    let n = ys.len() as f64;
+    let sum_x = (0..ys.len()).map(|x| x as f64).sum::<f64>();
+    let sum_xx = (0..ys.len()).map(|x| (x * x) as f64).sum::<f64>();
+    let sum_y = ys.iter().sum::<f64>();
+    let sum_xy = ys
+        .iter()
+        .enumerate()
+        .map(|(x, y)| x as f64 * y)
+        .sum::<f64>();
+    let denom = n * sum_xx - sum_x * sum_x;
+    let slope = if denom.abs() < 1e-12 {
+        0.0
+    } else {
+        (n * sum_xy - sum_x * sum_y) / denom
+    };
+    (slope, (sum_y - slope * sum_x) / n)
+}
+
+fn tdx_slope(window: &[f64]) -> Option<f64> {
+    Some(linear_regression(window).0)
+}
+
+// 回归直线在窗口最后一根上的取值
+fn tdx_forcast(window: &[f64]) -> Option<f64> {
+    let (slope, intercept) = linear_regression(window);
+    Some(intercept + slope * (window.len() - 1) as f64)
+}
+
+// 权重从远到近依次为1..N
+fn tdx_wma(window: &[f64]) -> Option<f64> {
+    let n = window.len() as f64;
+    let weighted = window
+        .iter()
+        .enumerate()
+        .map(|(k, v)| (k + 1) as f64 * v)
+        .sum::<f64>();
+    Some(weighted / (n * (n + 1.0) / 2.0))
+}
+
+fn tdx_mean(window: &[f64]) -> f64 {
+    window.iter().sum::<f64>() / window.len() as f64
+}
+
+fn tdx_avedev(window: &[f64]) -> Option<f64> {
+    let mean = tdx_mean(window);
+    Some(window.iter().map(|v| (v - mean).abs()).sum::<f64>() / window.len() as f64)
+}
+
+// VAR按通达信口径是样本方差(除以N-1), 注意与本项目STD的总体口径不同
+fn tdx_var(window: &[f64]) -> Option<f64> {
+    if window.len() < 2 {
+        return None;
+    }
+    let mean = tdx_mean(window);
+    Some(window.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (window.len() - 1) as f64)
+}
+
+// 总体协方差
+fn tdx_covar(xs: &[f64], ys: &[f64]) -> Option<f64> {
+    let (mx, my) = (tdx_mean(xs), tdx_mean(ys));
+    Some(
+        xs.iter()
+            .zip(ys)
+            .map(|(x, y)| (x - mx) * (y - my))
+            .sum::<f64>()
+            / xs.len() as f64,
+    )
+}
+
+// 皮尔逊相关系数, 任一序列在窗口内不变时为空
+fn tdx_corr(xs: &[f64], ys: &[f64]) -> Option<f64> {
+    let (mx, my) = (tdx_mean(xs), tdx_mean(ys));
+    let sxx = xs.iter().map(|x| (x - mx).powi(2)).sum::<f64>();
+    let syy = ys.iter().map(|y| (y - my).powi(2)).sum::<f64>();
+    let denom = (sxx * syy).sqrt();
+    if denom < 1e-12 {
+        return None;
+    }
+    let sxy = xs
+        .iter()
+        .zip(ys)
+        .map(|(x, y)| (x - mx) * (y - my))
+        .sum::<f64>();
+    Some(sxy / denom)
+}
+
 macro_rules! define_expression_functions {
     ($($variant:ident => $name:literal),+ $(,)?) => {
         #[derive(Debug, Clone, Copy)]
@@ -1527,7 +2319,7 @@ macro_rules! define_expression_functions {
                 }
             }
 
-            const fn name(self) -> &'static str {
+            pub(crate) const fn name(self) -> &'static str {
                 match self {
                     $(Self::$variant => $name),+
                 }
@@ -1575,6 +2367,34 @@ define_expression_functions! {
     Lrankd => "LRANKD",
     Get => "GET",
     Getd => "GETD",
+    Contains => "CONTAINS",
+    Startswith => "STARTSWITH",
+    Endswith => "ENDSWITH",
+    Xrank => "XRANK",
+    Xpct => "XPCT",
+    Xzscore => "XZSCORE",
+    Xmedian => "XMEDIAN",
+    Slope => "SLOPE",
+    Forcast => "FORCAST",
+    Wma => "WMA",
+    Dma => "DMA",
+    Avedev => "AVEDEV",
+    Var => "VAR",
+    Corr => "CORR",
+    Covar => "COVAR",
+    Filter => "FILTER",
+    Barscount => "BARSCOUNT",
+    Barssince => "BARSSINCE",
+    Hhvbars => "HHVBARS",
+    Llvbars => "LLVBARS",
+    Zig => "ZIG",
+    Peak => "PEAK",
+    Trough => "TROUGH",
+    Backset => "BACKSET",
+    Valuewhen => "VALUEWHEN",
+    Sar => "SAR",
+    Period => "PERIOD",
+    Rule => "RULE",
 }
 
 pub fn supported_expression_functions() -> impl ExactSizeIterator<Item = &'static str> {
@@ -1584,14 +2404,40 @@ pub fn supported_expression_functions() -> impl ExactSizeIterator<Item = &'stati
         .map(ExpressionFunction::name)
 }
 
+// RULE("规则名") 引用的规则触发序列在运行时里的变量名,表达式里写不出这种名字
+pub fn rule_trigger_var(rule_name: &str) -> String {
+    format!("RULE:{}", rule_name.trim())
+}
+
 pub fn is_supported_expression_function(name: &str) -> bool {
     ExpressionFunction::parse(name).is_some()
 }
 
+/// 不出现在参数里、由函数自己读取的行情字段, 收集运行时字段时需要补上
+pub fn implicit_runtime_keys(name: &str, args: &[Expr]) -> &'static [&'static str] {
+    match ExpressionFunction::parse(name) {
+        Some(ExpressionFunction::Sar) => &["H", "L"],
+        Some(ExpressionFunction::Zig | ExpressionFunction::Peak | ExpressionFunction::Trough) => {
+            match args.first() {
+                Some(Expr::Number(k)) => match *k as i64 {
+                    0 => &["O"],
+                    1 => &["H"],
+                    2 => &["L"],
+                    3 => &["C"],
+                    _ => &[],
+                },
+                _ => &[],
+            }
+        }
+        _ => &[],
+    }
+}
+
 impl Runtime {
     fn eval_expr(&mut self, expr: &Expr) -> Result<Value, EvalErr> {
         match expr {
             Expr::Number(n) => Ok(Value::Num(*n)),
+            Expr::Str(text) => Ok(Value::Str(text.clone())),
             Expr::Ident(name) => self.vars.get(name).cloned().ok_or_else(|| EvalErr {
                 msg: format!("变量不存在:{}", name),
             }),
@@ -1607,7 +2453,7 @@ impl Runtime {
         Ok(v)
     }
 
-    fn eval_call(&mut self, name: &str, args: &[Expr]) -> Result<Value, EvalErr> {
+    pub(crate) fn eval_call(&mut self, name: &str, args: &[Expr]) -> Result<Value, EvalErr> {
         let fn_name = name.to_ascii_uppercase();
         let Some(function) = ExpressionFunction::parse(&fn_name) else {
             return Err(EvalErr {
@@ -1653,11 +2499,49 @@ impl Runtime {
             ExpressionFunction::Lrankd => self.impl_rankd(args, "LRANKD", false),
             ExpressionFunction::Get => self.impl_get(args),
             ExpressionFunction::Getd => self.impl_getd(args),
+            ExpressionFunction::Contains => {
+                self.impl_str_match(args, "CONTAINS", |text, pat| text.contains(pat))
+            }
+            ExpressionFunction::Startswith => {
+                self.impl_str_match(args, "STARTSWITH", |text, pat| text.starts_with(pat))
+            }
+            ExpressionFunction::Endswith => {
+                self.impl_str_match(args, "ENDSWITH", |text, pat| text.ends_with(pat))
+            }
+            ExpressionFunction::Xrank => self.impl_cross_section(args, CrossKind::Rank),
+            ExpressionFunction::Xpct => self.impl_cross_section(args, CrossKind::Pct),
+            ExpressionFunction::Xzscore => self.impl_cross_section(args, CrossKind::Zscore),
+            ExpressionFunction::Xmedian => self.impl_cross_section(args, CrossKind::Median),
+            ExpressionFunction::Slope => self.impl_tdx_rolling(args, "SLOPE", tdx_slope),
+            ExpressionFunction::Forcast => self.impl_tdx_rolling(args, "FORCAST", tdx_forcast),
+            ExpressionFunction::Wma => self.impl_tdx_rolling(args, "WMA", tdx_wma),
+            ExpressionFunction::Dma => self.impl_dma(args),
+            ExpressionFunction::Avedev => self.impl_tdx_rolling(args, "AVEDEV", tdx_avedev),
+            ExpressionFunction::Var => self.impl_tdx_rolling(args, "VAR", tdx_var),
+            ExpressionFunction::Corr => self.impl_tdx_pair_rolling(args, "CORR", tdx_corr),
+            ExpressionFunction::Covar => self.impl_tdx_pair_rolling(args, "COVAR", tdx_covar),
+            ExpressionFunction::Filter => self.impl_filter(args),
+            ExpressionFunction::Barscount => self.impl_barscount(args),
+            ExpressionFunction::Barssince => self.impl_barssince(args),
+            ExpressionFunction::Hhvbars => self.impl_extreme_bars(args, "HHVBARS", true),
+            ExpressionFunction::Llvbars => self.impl_extreme_bars(args, "LLVBARS", false),
+            ExpressionFunction::Zig => self.impl_zig(args),
+            ExpressionFunction::Peak => self.impl_zig_turn(args, "PEAK", true),
+            ExpressionFunction::Trough => self.impl_zig_turn(args, "TROUGH", false),
+            ExpressionFunction::Backset => self.impl_backset(args),
+            ExpressionFunction::Valuewhen => self.impl_valuewhen(args),
+            ExpressionFunction::Sar => self.impl_sar(args),
+            ExpressionFunction::Period => self.impl_period(args),
+            ExpressionFunction::Rule => self.impl_rule(args),
         }
     }
 
     fn eval_unary(&mut self, op: &UnaryOp, rhs: &Expr) -> Result<Value, EvalErr> {
         let v = self.eval_expr(rhs)?;
+        Self::unary_value(op, v)
+    }
+
+    pub(crate) fn unary_value(op: &UnaryOp, v: Value) -> Result<Value, EvalErr> {
         match (op, v) {
             (UnaryOp::Neg, Value::Num(n)) => Ok(Value::Num(-n)),
             (UnaryOp::Neg, Value::Bool(b)) => Ok(Value::Num(-to_num(b))),
@@ -1681,18 +2565,28 @@ impl Runtime {
             (UnaryOp::Not, Value::BoolSeries(bs)) => {
                 Ok(Value::BoolSeries(bs.into_iter().map(|b| !b).collect()))
             }
+            (_, Value::Str(_) | Value::StrSeries(_)) => Err(EvalErr {
+                msg: "字符串不支持负号和NOT运算".to_string(),
+            }),
         }
     }
 
     fn eval_binary(&mut self, op: &BinaryOp, lhs: &Expr, rhs: &Expr) -> Result<Value, EvalErr> {
         let lv = self.eval_expr(lhs)?;
         let rv = self.eval_expr(rhs)?;
+        Self::binary_values(op, &lv, &rv)
+    }
+
+    pub(crate) fn binary_values(op: &BinaryOp, lv: &Value, rv: &Value) -> Result<Value, EvalErr> {
+        if Value::is_str(lv) || Value::is_str(rv) {
+            return Self::eval_str_binary(op, lv, rv);
+        }
 
         if matches!(lv, Value::Num(_) | Value::Bool(_))
             && matches!(rv, Value::Num(_) | Value::Bool(_))
         {
-            let l = Value::as_num(&lv)?;
-            let r = Value::as_num(&rv)?;
+            let l = Value::as_num(lv)?;
+            let r = Value::as_num(rv)?;
 
             return match op {
                 BinaryOp::Add => Ok(Value::Num(l + r)),
@@ -1716,9 +2610,9 @@ impl Runtime {
             };
         }
 
-        let len = usize::max(Value::len_of(&lv), Value::len_of(&rv));
-        let ls = Value::as_num_series(&lv, len)?;
-        let rs = Value::as_num_series(&rv, len)?;
+        let len = usize::max(Value::len_of(lv), Value::len_of(rv));
+        let ls = Value::as_num_series(lv, len)?;
+        let rs = Value::as_num_series(rv, len)?;
 
         match op {
             BinaryOp::Add => {
@@ -1876,6 +2770,38 @@ impl Runtime {
         }
     }
 
+    // 字符串只参与相等比较,缺失值与任何字符串都不相等
+    fn eval_str_binary(op: &BinaryOp, lv: &Value, rv: &Value) -> Result<Value, EvalErr> {
+        if !Value::is_str(lv) || !Value::is_str(rv) {
+            return Err(EvalErr {
+                msg: "字符串不能和数字或布尔值比较".to_string(),
+            });
+        }
+        let equal = match op {
+            BinaryOp::Eq => true,
+            BinaryOp::Ne => false,
+            _ => {
+                return Err(EvalErr {
+                    msg: "字符串只支持 == 和 != 比较".to_string(),
+                });
+            }
+        };
+
+        if let (Value::Str(l), Value::Str(r)) = (lv, rv) {
+            return Ok(Value::Bool((l == r) == equal));
+        }
+
+        let len = usize::max(Value::len_of(lv), Value::len_of(rv));
+        let mut out = Vec::with_capacity(len);
+        for i in 0..len {
+            match (Value::str_at(lv, i), Value::str_at(rv, i)) {
+                (Some(l), Some(r)) => out.push((l == r) == equal),
+                _ => out.push(false),
+            }
+        }
+        Ok(Value::BoolSeries(out))
+    }
+
     fn eval_stmt(&mut self, stmt: &Stmt) -> Result<Value, EvalErr> {
         // 赋值分支和语句分支的选择处理
         match stmt {
@@ -1884,21 +2810,25 @@ impl Runtime {
         }
     }
 
-    fn day_value(&self, name: &str, day_index: usize) -> Result<DayValue, EvalErr> {
+    // 字符串变量没有单日快速路径,返回None交给完整求值
+    fn day_value(&self, name: &str, day_index: usize) -> Result<Option<DayValue>, EvalErr> {
         let value = self.vars.get(name).ok_or_else(|| EvalErr {
             msg: format!("变量不存在:{name}"),
         })?;
-        match value {
-            Value::Num(value) => Ok(DayValue::Num(Some(*value))),
-            Value::Bool(value) => Ok(DayValue::Bool(*value)),
-            Value::NumSeries(series) => Ok(DayValue::Num(series.get(day_index).copied().flatten())),
+        Ok(match value {
+            Value::Num(value) => Some(DayValue::Num(Some(*value))),
+            Value::Bool(value) => Some(DayValue::Bool(*value)),
+            Value::NumSeries(series) => {
+                Some(DayValue::Num(series.get(day_index).copied().flatten()))
+            }
             Value::SharedNumSeries(series) => {
-                Ok(DayValue::Num(series.get(day_index).copied().flatten()))
+                Some(DayValue::Num(series.get(day_index).copied().flatten()))
             }
-            Value::BoolSeries(series) => Ok(DayValue::Bool(
+            Value::BoolSeries(series) => Some(DayValue::Bool(
                 series.get(day_index).copied().unwrap_or(false),
             )),
-        }
+            Value::Str(_) | Value::StrSeries(_) => None,
+        })
     }
 
     fn eval_expr_bool_at(
@@ -1913,10 +2843,27 @@ impl Runtime {
                 if let Some(value) = locals.get(name).copied() {
                     Ok(Some(value))
                 } else {
-                    self.day_value(name, day_index).map(Some)
+                    self.day_value(name, day_index)
                 }
             }
-            Expr::Call { .. } => Ok(None),
+            Expr::Call { name, args }
+                if args.len() == 3 && name.eq_ignore_ascii_case(ExpressionFunction::If.name()) =>
+            {
+                // IF 只算选中的分支, 结果和完整求值一样按数值返回
+                let Some(cond) = self.eval_expr_bool_at(&args[0], locals, day_index)? else {
+                    return Ok(None);
+                };
+                let branch = if day_value_as_bool(cond) {
+                    &args[1]
+                } else {
+                    &args[2]
+                };
+                let Some(value) = self.eval_expr_bool_at(branch, locals, day_index)? else {
+                    return Ok(None);
+                };
+                Ok(Some(DayValue::Num(day_value_as_num(value))))
+            }
+            Expr::Str(_) | Expr::Call { .. } => Ok(None),
             Expr::Unary { op, rhs } => {
                 let Some(rhs) = self.eval_expr_bool_at(rhs, locals, day_index)? else {
                     return Ok(None);
@@ -2036,6 +2983,127 @@ impl Runtime {
         }
         Ok(last)
     }
+
+    /// 逐语句求值并记录每个赋值和子表达式的结果,供表达式调试逐K线查看。
+    /// 子表达式先于父表达式记录;某个节点出错时记下错误,后续语句不再执行。
+    pub fn trace_program(&mut self, stmts: &Stmts) -> ExprTrace {
+        let mut trace = ExprTrace::default();
+        for (stmt_index, stmt) in stmts.item.iter().enumerate() {
+            let (expr, assign_name) = match stmt {
+                Stmt::Expr(expr) => (expr, None),
+                Stmt::Assign { name, value } => (value, Some(name.clone())),
+            };
+            let result = self.trace_expr(expr, stmt_index, 0, &mut trace.nodes);
+            if let Some(node) = trace.nodes.last_mut() {
+                node.assign_name = assign_name.clone();
+            }
+            match result {
+                Ok(value) => {
+                    if let Some(name) = assign_name {
+                        self.vars.insert(name, value);
+                    }
+                }
+                Err(err) => {
+                    trace.error = Some(err.msg);
+                    break;
+                }
+            }
+        }
+        trace
+    }
+
+    fn trace_expr(
+        &mut self,
+        expr: &Expr,
+        stmt_index: usize,
+        depth: usize,
+        nodes: &mut Vec<TraceNode>,
+    ) -> Result<Value, EvalErr> {
+        let result = match expr {
+            // 常量不单独成行
+            Expr::Number(_) | Expr::Str(_) => return self.eval_expr(expr),
+            Expr::Ident(_) => self.eval_expr(expr),
+            Expr::Unary { op, rhs } => self
+                .trace_expr(rhs, stmt_index, depth + 1, nodes)
+                .and_then(|v| Self::unary_value(op, v)),
+            Expr::Binary { op, lhs, rhs } => {
+                let lv = self.trace_expr(lhs, stmt_index, depth + 1, nodes);
+                let rv = self.trace_expr(rhs, stmt_index, depth + 1, nodes);
+                lv.and_then(|lv| rv.and_then(|rv| Self::binary_values(op, &lv, &rv)))
+            }
+            Expr::Call { name, args } => {
+                // PERIOD 的参数在周/月K线上求值, 按日线展开没有意义, 只记录整体结果
+                let is_period = matches!(
+                    ExpressionFunction::parse(name),
+                    Some(ExpressionFunction::Period)
+                );
+                if is_period {
+                    self.eval_call(name, args)
+                } else {
+                    self.trace_call(name, args, stmt_index, depth, nodes)
+                }
+            }
+        };
+        nodes.push(TraceNode {
+            stmt_index,
+            depth,
+            text: expr.to_string(),
+            assign_name: None,
+            value: result.clone().map_err(|err| err.msg),
+        });
+        result
+    }
+
+    // 参数逐个展开记录, 算好的值临时绑定成变量再调用函数, 子表达式不会重复求值;
+    // 出错的参数(比如 IF 没选中的分支)原样交给函数, 按原语义短路或报错
+    fn trace_call(
+        &mut self,
+        name: &str,
+        args: &[Expr],
+        stmt_index: usize,
+        depth: usize,
+        nodes: &mut Vec<TraceNode>,
+    ) -> Result<Value, EvalErr> {
+        // 先展开全部参数再绑定, 内层调用的临时变量不会覆盖外层的
+        let traced = args
+            .iter()
+            .map(|arg| self.trace_expr(arg, stmt_index, depth + 1, nodes))
+            .collect::<Vec<_>>();
+        let mut call_args = Vec::with_capacity(args.len());
+        let mut bound = Vec::new();
+        for (index, (arg, value)) in args.iter().zip(traced).enumerate() {
+            match (arg, value) {
+                (Expr::Call { .. } | Expr::Unary { .. } | Expr::Binary { .. }, Ok(value)) => {
+                    let key = format!("#trace{index}");
+                    self.vars.insert(key.clone(), value);
+                    call_args.push(Expr::Ident(key.clone()));
+                    bound.push(key);
+                }
+                _ => call_args.push(arg.clone()),
+            }
+        }
+        let result = self.eval_call(name, &call_args);
+        for key in bound {
+            self.vars.remove(&key);
+        }
+        result
+    }
+}
+
+/// 表达式调试中的一行: 一个赋值或子表达式在每根K线上的取值
+#[derive(Debug, Clone)]
+pub struct TraceNode {
+    pub stmt_index: usize,
+    pub depth: usize,
+    pub text: String,
+    pub assign_name: Option<String>,
+    pub value: Result<Value, String>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ExprTrace {
+    pub nodes: Vec<TraceNode>,
+    pub error: Option<String>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -2045,6 +3113,8 @@ pub enum Value {
     SharedNumSeries(Arc<Vec<Option<f64>>>),
     Bool(bool),
     BoolSeries(Vec<bool>),
+    Str(String),
+    StrSeries(Vec<Option<String>>),
 }
 
 impl Value {
@@ -2052,9 +3122,24 @@ impl Value {
         match v {
             Value::Num(_) => 1,
             Value::Bool(_) => 1,
+            Value::Str(_) => 1,
             Value::NumSeries(n) => n.len(),
             Value::SharedNumSeries(n) => n.len(),
             Value::BoolSeries(b) => b.len(),
+            Value::StrSeries(s) => s.len(),
+        }
+    }
+
+    pub fn is_str(v: &Value) -> bool {
+        matches!(v, Value::Str(_) | Value::StrSeries(_))
+    }
+
+    // 取第i根K线的字符串,标量字符串每根都相同
+    pub fn str_at(v: &Value, index: usize) -> Option<&str> {
+        match v {
+            Value::Str(text) => Some(text.as_str()),
+            Value::StrSeries(series) => series.get(index)?.as_deref(),
+            _ => None,
         }
     }
 
@@ -2068,6 +3153,9 @@ impl Value {
             Value::BoolSeries(_) => Err(EvalErr {
                 msg: "需要标量数字，但拿到布尔序列".to_string(),
             }),
+            Value::Str(_) | Value::StrSeries(_) => Err(EvalErr {
+                msg: "需要标量数字，但拿到字符串".to_string(),
+            }),
         }
     }
 
@@ -2081,6 +3169,9 @@ impl Value {
             Value::BoolSeries(_) => Err(EvalErr {
                 msg: "需要布尔，但拿到布尔序列，可用LAST函数转换".to_string(),
             }),
+            Value::Str(_) | Value::StrSeries(_) => Err(EvalErr {
+                msg: "需要布尔，但拿到字符串".to_string(),
+            }),
         }
     }
 
@@ -2118,6 +3209,9 @@ impl Value {
                     })
                 }
             }
+            Value::Str(_) | Value::StrSeries(_) => Err(EvalErr {
+                msg: "需要数值序列，但拿到字符串".to_string(),
+            }),
         }
     }
 
@@ -2155,6 +3249,9 @@ impl Value {
                     })
                 }
             }
+            Value::Str(_) | Value::StrSeries(_) => Err(EvalErr {
+                msg: "需要数值序列，但拿到字符串".to_string(),
+            }),
         }
     }
 
@@ -2201,6 +3298,9 @@ impl Value {
                     })
                 }
             }
+            Value::Str(_) | Value::StrSeries(_) => Err(EvalErr {
+                msg: "需要布尔序列，但拿到字符串".to_string(),
+            }),
         }
     }
 }
@@ -2293,6 +3393,160 @@ fn eval_program_bool_at_matches_full_series_for_call_free_program() {
     }
 }
 
+#[test]
+fn case_short_circuits_unselected_branches() {
+    use crate::expr::parser::{Parser, lex_all};
+
+    let expr = "CASE WHEN C > 2 THEN 1 WHEN C > 1 THEN 2 ELSE MISSING END";
+    let stmts = Parser::new(lex_all(expr))
+        .parse_main()
+        .expect("parse failed");
+
+    let mut rt = Runtime::default();
+    rt.vars.insert(
+        "C".to_string(),
+        Value::NumSeries(vec![Some(3.0), Some(2.0), Some(4.0)]),
+    );
+
+    let out = rt.eval_program(&stmts).expect("eval failed");
+    assert_eq!(out, Value::NumSeries(vec![Some(1.0), Some(2.0), Some(1.0)]));
+
+    for day_index in 0..3 {
+        let fast = rt
+            .eval_program_bool_at(&stmts, day_index)
+            .expect("fast eval")
+            .expect("call-free CASE should use fast path");
+        assert!(fast, "day_index={day_index}");
+    }
+
+    rt.vars.insert(
+        "C".to_string(),
+        Value::NumSeries(vec![Some(3.0), Some(0.0), Some(4.0)]),
+    );
+    assert!(rt.eval_program(&stmts).is_err());
+    assert!(rt.eval_program_bool_at(&stmts, 1).is_err());
+}
+
+#[test]
+fn if_with_scalar_condition_keeps_series_shape() {
+    use crate::expr::parser::{Parser, lex_all};
+
+    let mut rt = Runtime::default();
+    rt.vars.insert(
+        "C".to_string(),
+        Value::NumSeries(vec![Some(1.0), Some(2.0)]),
+    );
+    let mut eval = |text: &str| {
+        let stmts = Parser::new(lex_all(text))
+            .parse_main()
+            .expect("parse failed");
+        rt.eval_program(&stmts).expect("eval failed")
+    };
+
+    assert_eq!(eval("IF(1, 5, 6)"), Value::NumSeries(vec![Some(5.0)]));
+    assert_eq!(
+        eval("IF(1, 5, C)"),
+        Value::NumSeries(vec![Some(5.0), Some(5.0)])
+    );
+    assert_eq!(
+        eval("IF(0, C, 5)"),
+        Value::NumSeries(vec![Some(5.0), Some(5.0)])
+    );
+    assert_eq!(
+        eval("IF(1, 5, C) + C"),
+        Value::NumSeries(vec![Some(6.0), Some(7.0)])
+    );
+}
+
+#[test]
+fn trace_program_records_assignments_and_sub_expressions() {
+    use crate::expr::parser::{Parser, lex_all};
+
+    let stmts = Parser::new(lex_all("UP := C > O; UP AND V > 100 AND MISSING > 0"))
+        .parse_main()
+        .expect("parse failed");
+    let mut rt = Runtime::default();
+    rt.vars.insert(
+        "C".to_string(),
+        Value::NumSeries(vec![Some(2.0), Some(1.0)]),
+    );
+    rt.vars.insert(
+        "O".to_string(),
+        Value::NumSeries(vec![Some(1.0), Some(2.0)]),
+    );
+    rt.vars.insert(
+        "V".to_string(),
+        Value::NumSeries(vec![Some(50.0), Some(200.0)]),
+    );
+
+    let trace = rt.trace_program(&stmts);
+    let texts = trace
+        .nodes
+        .iter()
+        .map(|node| (node.stmt_index, node.depth, node.text.as_str()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        texts,
+        vec![
+            (0, 1, "C"),
+            (0, 1, "O"),
+            (0, 0, "C > O"),
+            (1, 2, "UP"),
+            (1, 3, "V"),
+            (1, 2, "V > 100"),
+            (1, 1, "UP AND V > 100"),
+            (1, 2, "MISSING"),
+            (1, 1, "MISSING > 0"),
+            (1, 0, "UP AND V > 100 AND MISSING > 0"),
+        ]
+    );
+    assert_eq!(trace.nodes[2].assign_name.as_deref(), Some("UP"));
+    assert_eq!(
+        trace.nodes[2].value,
+        Ok(Value::BoolSeries(vec![true, false]))
+    );
+    assert_eq!(
+        trace.nodes[5].value,
+        Ok(Value::BoolSeries(vec![false, true]))
+    );
+    assert!(trace.nodes[7].value.is_err());
+    assert_eq!(trace.error.as_deref(), Some("变量不存在:MISSING"));
+}
+
+#[test]
+fn trace_program_calls_reuse_traced_arguments() {
+    use crate::expr::parser::{Parser, lex_all};
+
+    let stmts = Parser::new(lex_all("MA(ABS(C - O), 2) + IF(C > O, C, MISSING)"))
+        .parse_main()
+        .expect("parse failed");
+    let mut rt = Runtime::default();
+    rt.vars.insert(
+        "C".to_string(),
+        Value::NumSeries(vec![Some(2.0), Some(3.0), Some(5.0)]),
+    );
+    rt.vars.insert(
+        "O".to_string(),
+        Value::NumSeries(vec![Some(1.0), Some(1.0), Some(2.0)]),
+    );
+
+    let expected = rt.clone().eval_program(&stmts).expect("eval failed");
+    let trace = rt.trace_program(&stmts);
+    assert_eq!(trace.error, None);
+    assert_eq!(
+        trace.nodes.last().map(|node| &node.value),
+        Some(&Ok(expected))
+    );
+    // 没选中的分支照样记录报错, 函数本身仍按短路语义求值
+    let missing = trace
+        .nodes
+        .iter()
+        .find(|node| node.text == "MISSING")
+        .expect("missing branch traced");
+    assert!(missing.value.is_err());
+    assert!(!rt.vars.keys().any(|key| key.starts_with('#')));
+}
+
 #[test]
 fn eval_program_bool_at_defers_programs_with_calls() {
     use crate::expr::parser::{Parser, lex_all};
@@ -2708,3 +3962,319 @@ fn dynamic_limit_rejects_fractional_upper_bound() {
         .expect_err("fractional cap should fail");
     assert_eq!(err.msg, "HHVD动态周期上限必须是正整数");
 }
+
+#[test]
+fn string_values_support_equality_contains_and_in_list() {
+    use crate::expr::parser::{Parser, lex_all};
+
+    let expr = "A := INDUSTRY IN ['半导体', '元器件']; \
+                B := CONTAINS(CONCEPT, '机器人') AND STARTSWITH(BOARD, '创业'); \
+                A AND B AND C > 1";
+    let toks = lex_all(expr);
+    let mut p = Parser::new(toks);
+    let stmts = p.parse_main().expect("parse failed");
+    let mut rt = Runtime::default();
+
+    rt.vars
+        .insert("INDUSTRY".to_string(), Value::Str("半导体".to_string()));
+    rt.vars.insert(
+        "CONCEPT".to_string(),
+        Value::Str("人形机器人,芯片".to_string()),
+    );
+    rt.vars
+        .insert("BOARD".to_string(), Value::Str("创业/科创".to_string()));
+    rt.vars.insert(
+        "C".to_string(),
+        Value::NumSeries(vec![Some(1.0), Some(2.0), None]),
+    );
+
+    let out = rt.eval_program(&stmts).expect("eval failed");
+    assert_eq!(out, Value::BoolSeries(vec![false, true, false]));
+    assert_eq!(rt.eval_program_bool_at(&stmts, 1).expect("bool at"), None);
+}
+
+#[test]
+fn string_series_compare_per_bar_and_reject_arithmetic() {
+    use crate::expr::parser::{Parser, lex_all};
+
+    let mut rt = Runtime::default();
+    rt.vars.insert(
+        "TAG".to_string(),
+        Value::StrSeries(vec![
+            Some("涨停".to_string()),
+            None,
+            Some("炸板".to_string()),
+        ]),
+    );
+
+    let stmts = Parser::new(lex_all("TAG != '炸板'"))
+        .parse_main()
+        .expect("parse failed");
+    let out = rt.eval_program(&stmts).expect("eval failed");
+    assert_eq!(out, Value::BoolSeries(vec![true, false, false]));
+
+    let stmts = Parser::new(lex_all("TAG > 'A'"))
+        .parse_main()
+        .expect("parse failed");
+    let err = rt.eval_program(&stmts).expect_err("ordering should fail");
+    assert_eq!(err.msg, "字符串只支持 == 和 != 比较");
+
+    let stmts = Parser::new(lex_all("TAG == 1"))
+        .parse_main()
+        .expect("parse failed");
+    let err = rt
+        .eval_program(&stmts)
+        .expect_err("mixed compare should fail");
+    assert_eq!(err.msg, "字符串不能和数字或布尔值比较");
+}
+
+#[cfg(test)]
+fn eval_tdx_series(expr: &str, vars: &[(&str, Vec<Option<f64>>)]) -> Value {
+    use crate::expr::parser::{Parser, lex_all};
+
+    let stmts = Parser::new(lex_all(expr))
+        .parse_main()
+        .expect("parse failed");
+    let mut rt = Runtime::default();
+    for (name, series) in vars {
+        rt.vars
+            .insert(name.to_string(), Value::NumSeries(series.clone()));
+    }
+    rt.eval_program(&stmts).expect("eval failed")
+}
+
+#[cfg(test)]
+fn assert_series_close(out: Value, expected: &[Option<f64>]) {
+    let Value::NumSeries(out) = out else {
+        panic!("expected num series, got {out:?}");
+    };
+    assert_eq!(out.len(), expected.len(), "{out:?}");
+    for (got, want) in out.iter().zip(expected) {
+        match (got, want) {
+            (Some(got), Some(want)) => assert!((got - want).abs() < 1e-9, "{out:?}"),
+            (None, None) => {}
+            _ => panic!("{out:?} != {expected:?}"),
+        }
+    }
+}
+
+// 期望值按通达信公式说明手算, 不是通达信软件导出的数值
+#[test]
+fn tdx_window_functions_follow_documented_formulas() {
+    let c = vec![Some(1.0), Some(2.0), Some(4.0), Some(7.0), Some(11.0)];
+    let vars = [("C", c)];
+
+    assert_series_close(
+        eval_tdx_series("SLOPE(C, 3)", &vars),
+        &[None, None, Some(1.5), Some(2.5), Some(3.5)],
+    );
+    assert_series_close(
+        eval_tdx_series("FORCAST(C, 3)", &vars),
+        &[
+            None,
+            None,
+            Some(23.0 / 6.0),
+            Some(41.0 / 6.0),
+            Some(65.0 / 6.0),
+        ],
+    );
+    assert_series_close(
+        eval_tdx_series("WMA(C, 3)", &vars),
+        &[None, None, Some(17.0 / 6.0), Some(31.0 / 6.0), Some(8.5)],
+    );
+    assert_series_close(
+        eval_tdx_series("AVEDEV(C, 3)", &vars),
+        &[
+            None,
+            None,
+            Some(10.0 / 9.0),
+            Some(16.0 / 9.0),
+            Some(22.0 / 9.0),
+        ],
+    );
+    assert_series_close(
+        eval_tdx_series("VAR(C, 3)", &vars),
+        &[
+            None,
+            None,
+            Some(7.0 / 3.0),
+            Some(19.0 / 3.0),
+            Some(37.0 / 3.0),
+        ],
+    );
+    assert_series_close(
+        eval_tdx_series("DMA(C, 0.5)", &vars),
+        &[Some(1.0), Some(1.5), Some(2.75), Some(4.875), Some(7.9375)],
+    );
+    assert_series_close(
+        eval_tdx_series("VALUEWHEN(C > 3, C)", &vars),
+        &[None, None, Some(4.0), Some(7.0), Some(11.0)],
+    );
+    assert_series_close(
+        eval_tdx_series("BARSSINCE(C > 3)", &vars),
+        &[None, None, Some(0.0), Some(1.0), Some(2.0)],
+    );
+
+    let x = vec![Some(1.0), Some(2.0), Some(3.0), Some(4.0), Some(5.0)];
+    let up = vec![Some(2.0), Some(4.0), Some(6.0), Some(8.0), Some(10.0)];
+    let down = vec![Some(5.0), Some(4.0), Some(3.0), Some(2.0), Some(1.0)];
+    let vars = [("X", x), ("UP", up), ("DOWN", down)];
+    assert_series_close(
+        eval_tdx_series("COVAR(X, UP, 5)", &vars),
+        &[None, None, None, None, Some(4.0)],
+    );
+    assert_series_close(
+        eval_tdx_series("CORR(X, UP, 5)", &vars),
+        &[None, None, None, None, Some(1.0)],
+    );
+    assert_series_close(
+        eval_tdx_series("CORR(X, DOWN, 3)", &vars),
+        &[None, None, Some(-1.0), Some(-1.0), Some(-1.0)],
+    );
+}
+
+#[test]
+fn tdx_bar_counting_functions_follow_documented_formulas() {
+    let x = vec![Some(3.0), Some(5.0), Some(2.0), Some(5.0), Some(1.0)];
+    let vars = [("X", x)];
+
+    assert_series_close(
+        eval_tdx_series("HHVBARS(X, 3)", &vars),
+        &[None, None, Some(1.0), Some(0.0), Some(1.0)],
+    );
+    assert_series_close(
+        eval_tdx_series("HHVBARS(X, 0)", &vars),
+        &[Some(0.0), Some(0.0), Some(1.0), Some(0.0), Some(1.0)],
+    );
+    assert_series_close(
+        eval_tdx_series("LLVBARS(X, 0)", &vars),
+        &[Some(0.0), Some(1.0), Some(0.0), Some(1.0), Some(0.0)],
+    );
+    assert_eq!(
+        eval_tdx_series("FILTER(X > 1, 1)", &vars),
+        Value::BoolSeries(vec![true, false, true, false, false])
+    );
+    assert_eq!(
+        eval_tdx_series("BACKSET(X == 5, 2)", &vars),
+        Value::BoolSeries(vec![true, true, true, true, false])
+    );
+
+    let listed = vec![None, None, Some(3.0), Some(4.0)];
+    assert_series_close(
+        eval_tdx_series("BARSCOUNT(C)", &[("C", listed)]),
+        &[None, None, Some(1.0), Some(2.0)],
+    );
+}
+
+#[test]
+fn tdx_zig_and_sar_follow_documented_formulas() {
+    let c = vec![
+        Some(10.0),
+        Some(11.5),
+        Some(12.0),
+        Some(9.0),
+        Some(10.0),
+        Some(13.5),
+    ];
+    let vars = [("C", c)];
+
+    let zig = [
+        Some(10.0),
+        Some(11.0),
+        Some(12.0),
+        Some(9.0),
+        Some(11.25),
+        Some(13.5),
+    ];
+    assert_series_close(eval_tdx_series("ZIG(3, 10)", &vars), &zig);
+    assert_series_close(eval_tdx_series("ZIG(C, 10)", &vars), &zig);
+    assert_series_close(
+        eval_tdx_series("PEAK(3, 10, 1)", &vars),
+        &[None, None, None, Some(12.0), Some(12.0), Some(12.0)],
+    );
+    // 9.0 的波谷要到 10.0 反弹超过 10% 才确认
+    assert_series_close(
+        eval_tdx_series("TROUGH(3, 10, 1)", &vars),
+        &[None, None, None, None, Some(9.0), Some(9.0)],
+    );
+    assert_series_close(eval_tdx_series("PEAK(3, 10, 2)", &vars), &[None; 6]);
+
+    let h = vec![
+        Some(10.0),
+        Some(11.0),
+        Some(12.0),
+        Some(13.0),
+        Some(12.0),
+        Some(10.0),
+    ];
+    let l = vec![
+        Some(9.0),
+        Some(10.0),
+        Some(11.0),
+        Some(12.0),
+        Some(10.0),
+        Some(8.0),
+    ];
+    assert_series_close(
+        eval_tdx_series("SAR(3, 10, 20)", &[("H", h), ("L", l)]),
+        &[None, None, Some(9.0), Some(9.3), Some(13.0), Some(12.7)],
+    );
+}
+
+#[test]
+fn period_resamples_weeks_and_months_without_look_ahead() {
+    use crate::expr::parser::{Parser, lex_all};
+
+    let dates = [
+        "20240102", "20240103", "20240104", "20240105", "20240108", "20240109", "20240110",
+        "20240115", "20240201",
+    ];
+    let close = (1..=9).map(|v| Some(v as f64)).collect::<Vec<_>>();
+    let mut rt = Runtime {
+        trade_dates: Arc::new(dates.iter().map(|date| date.to_string()).collect()),
+        ..Default::default()
+    };
+    rt.vars
+        .insert("C".to_string(), Value::NumSeries(close.clone()));
+    rt.vars.insert(
+        "H".to_string(),
+        Value::NumSeries(close.iter().map(|v| v.map(|v| v + 1.0)).collect()),
+    );
+    rt.vars
+        .insert("V".to_string(), Value::NumSeries(vec![Some(1.0); 9]));
+    let mut eval = |expr: &str| {
+        let stmts = Parser::new(lex_all(expr))
+            .parse_main()
+            .expect("parse failed");
+        rt.eval_program(&stmts)
+    };
+
+    let none4 = [None; 4];
+    let expect = |tail: [Option<f64>; 5]| [none4.as_slice(), tail.as_slice()].concat();
+    assert_series_close(
+        eval("W.C").expect("eval failed"),
+        &expect([Some(4.0), Some(4.0), Some(4.0), Some(7.0), Some(8.0)]),
+    );
+    assert_series_close(
+        eval("W.H").expect("eval failed"),
+        &expect([Some(5.0), Some(5.0), Some(5.0), Some(8.0), Some(9.0)]),
+    );
+    assert_series_close(
+        eval("PERIOD('W', V)").expect("eval failed"),
+        &expect([Some(4.0), Some(4.0), Some(4.0), Some(3.0), Some(1.0)]),
+    );
+    let mut month = vec![None; 8];
+    month.push(Some(8.0));
+    assert_series_close(eval("M.C").expect("eval failed"), &month);
+    assert_eq!(
+        eval("W.(C > REF(C, 1))").expect("eval failed"),
+        Value::BoolSeries(vec![
+            false, false, false, false, false, false, false, true, true
+        ])
+    );
+
+    let err = eval("W.M.C").expect_err("nested period should fail");
+    assert_eq!(err.msg, "PERIOD需要日线交易日期, 不能嵌套使用");
+    let err = eval("PERIOD('D', C)").expect_err("unknown freq should fail");
+    assert_eq!(err.msg, "PERIOD的周期只支持W或M, 拿到\"D\"");
+}
@@ -0,0 +1,225 @@
+//! User-defined expression functions.
+//!
+//! `FUNC NAME(A, B) := expr;` declares a function whose body is expanded into
+//! the caller's AST while parsing. Parameters are replaced by the call
+//! arguments; every other identifier in the body resolves in the caller's
+//! scope. Because expansion happens before validation, the evaluator, the
+//! unknown-function check and warmup estimation only ever see builtin calls.
+
+use std::collections::{HashMap, HashSet};
+
+use super::{
+    eval::is_supported_expression_function,
+    parser::{Expr, FuncDef, ParseErr, Parser, lex_all},
+};
+
+/// A set of user functions keyed by upper-cased name.
+#[derive(Debug, Clone, Default)]
+pub struct UserFunctions {
+    defs: HashMap<String, FuncDef>,
+}
+
+impl UserFunctions {
+    /// Parse a prelude text that contains only `FUNC` definitions.
+    pub fn parse_prelude(text: &str) -> Result<Self, ParseErr> {
+        let mut parser = Parser::new(lex_all(text));
+        let stmts = parser.parse_main()?;
+        if !stmts.item.is_empty() {
+            return Err(ParseErr {
+                msg: "公共函数文件只能包含 FUNC 定义".to_string(),
+                idx: 0,
+            });
+        }
+        Ok(parser.into_functions())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.defs.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.defs.len()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&FuncDef> {
+        self.defs.get(&name.trim().to_ascii_uppercase())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// Function definitions sorted by name, for listing in the UI.
+    pub fn definitions(&self) -> Vec<&FuncDef> {
+        let mut defs: Vec<&FuncDef> = self.defs.values().collect();
+        defs.sort_by(|left, right| left.name.cmp(&right.name));
+        defs
+    }
+
+    pub fn insert(&mut self, def: FuncDef) -> Result<(), String> {
+        let key = def.name.trim().to_ascii_uppercase();
+        if is_supported_expression_function(&key) {
+            return Err(format!("自定义函数 {} 与内置函数同名", def.name));
+        }
+        if self.defs.contains_key(&key) {
+            return Err(format!("自定义函数重复定义: {}", def.name));
+        }
+
+        let mut seen = HashSet::new();
+        for param in &def.params {
+            if !seen.insert(param.as_str()) {
+                return Err(format!("自定义函数 {} 的参数重复: {param}", def.name));
+            }
+        }
+
+        self.defs.insert(key, def);
+        Ok(())
+    }
+
+    /// Inline every user function call inside `expr`.
+    pub fn expand_expr(&self, expr: Expr) -> Result<Expr, String> {
+        if self.defs.is_empty() {
+            return Ok(expr);
+        }
+        let mut stack = Vec::new();
+        self.expand_with_stack(expr, &mut stack)
+    }
+
+    fn expand_with_stack(&self, expr: Expr, stack: &mut Vec<String>) -> Result<Expr, String> {
+        match expr {
+            Expr::Number(_) | Expr::Str(_) | Expr::Ident(_) => Ok(expr),
+            Expr::Unary { op, rhs } => Ok(Expr::Unary {
+                op,
+                rhs: Box::new(self.expand_with_stack(*rhs, stack)?),
+            }),
+            Expr::Binary { op, lhs, rhs } => Ok(Expr::Binary {
+                op,
+                lhs: Box::new(self.expand_with_stack(*lhs, stack)?),
+                rhs: Box::new(self.expand_with_stack(*rhs, stack)?),
+            }),
+            Expr::Call { name, args } => {
+                let args = args
+                    .into_iter()
+                    .map(|arg| self.expand_with_stack(arg, stack))
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                let key = name.trim().to_ascii_uppercase();
+                let Some(def) = self.defs.get(&key) else {
+                    return Ok(Expr::Call { name, args });
+                };
+                if args.len() != def.params.len() {
+                    return Err(format!(
+                        "自定义函数 {} 需要{}个参数，实际传入{}个",
+                        def.name,
+                        def.params.len(),
+                        args.len()
+                    ));
+                }
+                if stack.contains(&key) {
+                    stack.push(key);
+                    return Err(format!("自定义函数存在递归调用: {}", stack.join(" -> ")));
+                }
+
+                let bindings: HashMap<&str, &Expr> = def
+                    .params
+                    .iter()
+                    .map(String::as_str)
+                    .zip(args.iter())
+                    .collect();
+                let body = substitute_params(&def.body, &bindings);
+
+                stack.push(key);
+                let expanded = self.expand_with_stack(body, stack);
+                stack.pop();
+                expanded
+            }
+        }
+    }
+}
+
+// 参数替换成调用处的实参，其余标识符保持原样，留给调用方作用域解析
+fn substitute_params(expr: &Expr, bindings: &HashMap<&str, &Expr>) -> Expr {
+    match expr {
+        Expr::Number(value) => Expr::Number(*value),
+        Expr::Str(text) => Expr::Str(text.clone()),
+        Expr::Ident(name) => match bindings.get(name.as_str()) {
+            Some(arg) => (*arg).clone(),
+            None => Expr::Ident(name.clone()),
+        },
+        Expr::Call { name, args } => Expr::Call {
+            name: name.clone(),
+            args: args
+                .iter()
+                .map(|arg| substitute_params(arg, bindings))
+                .collect(),
+        },
+        Expr::Unary { op, rhs } => Expr::Unary {
+            op: op.clone(),
+            rhs: Box::new(substitute_params(rhs, bindings)),
+        },
+        Expr::Binary { op, lhs, rhs } => Expr::Binary {
+            op: op.clone(),
+            lhs: Box::new(substitute_params(lhs, bindings)),
+            rhs: Box::new(substitute_params(rhs, bindings)),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::UserFunctions;
+    use crate::expr::{
+        eval::{Runtime, Value},
+        validation::{
+            estimate_expression_warmup, parse_expression_program,
+            parse_expression_program_with_functions, validate_expression_functions,
+        },
+    };
+
+    #[test]
+    fn local_function_is_inlined_before_evaluation() {
+        let program = parse_expression_program("FUNC VBREAK(N) := V > MA(V, N) * 2; VBREAK(3)")
+            .expect("program should parse");
+        validate_expression_functions(&program).expect("inlined calls are builtin");
+
+        let mut rt = Runtime::default();
+        rt.vars.insert(
+            "V".to_string(),
+            Value::NumSeries(vec![Some(1.0), Some(1.0), Some(1.0), Some(9.0)]),
+        );
+        let out = rt.eval_program(&program).expect("eval should succeed");
+        assert_eq!(out, Value::BoolSeries(vec![false, false, false, true]));
+    }
+
+    #[test]
+    fn prelude_functions_keep_warmup_exact_through_the_call() {
+        let prelude = UserFunctions::parse_prelude(
+            "# shared helpers\nFUNC SMOOTH(X, N) := MA(X, N);\nFUNC LAGGED(X, N) := REF(SMOOTH(X, N), 1);",
+        )
+        .expect("prelude should parse");
+        assert_eq!(prelude.len(), 2);
+
+        let program = parse_expression_program_with_functions("C > LAGGED(C, 5)", &prelude)
+            .expect("program should parse");
+        assert_eq!(estimate_expression_warmup(&program), Ok(5));
+    }
+
+    #[test]
+    fn rejects_recursion_arity_and_builtin_names() {
+        let err = parse_expression_program("FUNC A(X) := B(X); FUNC B(X) := A(X); A(C)")
+            .expect_err("recursion should fail");
+        assert!(err.msg.contains("递归调用"));
+
+        let err = parse_expression_program("FUNC A(X, Y) := X + Y; A(C)")
+            .expect_err("arity mismatch should fail");
+        assert!(err.msg.contains("需要2个参数"));
+
+        let err = parse_expression_program("FUNC MA(X) := X; MA(C)")
+            .expect_err("builtin shadowing should fail");
+        assert!(err.msg.contains("与内置函数同名"));
+
+        let err = UserFunctions::parse_prelude("FUNC A(X) := X; C > 1")
+            .expect_err("prelude must only define functions");
+        assert_eq!(err.msg, "公共函数文件只能包含 FUNC 定义");
+    }
+}
@@ -11,12 +11,20 @@ pub enum TokenKind {
     RBracket,
     Comma,
     Semi,
+    Dot,
     And,
     Or,
     Not,
     In,
+    Func,
+    Case,
+    When,
+    Then,
+    Else,
+    End,
     Ident(String),
     Number(f64),
+    Str(String),
     Gt,
     Ge,
     Lt,
@@ -103,6 +111,17 @@ impl<'a> Lexer<'a> {
 
         self.input[start..self.pos].parse().unwrap()
     }
+    // 读引号字符串,没有闭合时返回None
+    fn read_str(&mut self, quote: char) -> Option<String> {
+        let mut out = String::new();
+        while let Some(ch) = self.pop_char() {
+            if ch == quote {
+                return Some(out);
+            }
+            out.push(ch);
+        }
+        None
+    }
 
     // 读多字符符号
     fn seek_next_char(&self) -> Option<char> {
@@ -148,6 +167,12 @@ impl<'a> Lexer<'a> {
                     "OR" => TokenKind::Or,
                     "NOT" => TokenKind::Not,
                     "IN" => TokenKind::In,
+                    "FUNC" => TokenKind::Func,
+                    "CASE" => TokenKind::Case,
+                    "WHEN" => TokenKind::When,
+                    "THEN" => TokenKind::Then,
+                    "ELSE" => TokenKind::Else,
+                    "END" => TokenKind::End,
                     _ => TokenKind::Ident(ident),
                 };
                 Token {
@@ -165,6 +190,19 @@ impl<'a> Lexer<'a> {
                 }
             }
 
+            Some(quote @ ('"' | '\'')) => {
+                self.pop_char();
+                let kind = match self.read_str(quote) {
+                    Some(text) => TokenKind::Str(text),
+                    None => TokenKind::Unknown(quote),
+                };
+                Token {
+                    kind,
+                    start,
+                    end: self.pos,
+                }
+            }
+
             Some('+') => {
                 self.pop_char();
                 Token {
@@ -247,6 +285,14 @@ impl<'a> Lexer<'a> {
                     end: self.pos,
                 }
             }
+            Some('.') => {
+                self.pop_char();
+                Token {
+                    kind: TokenKind::Dot,
+                    start,
+                    end: self.pos,
+                }
+            }
 
             Some(':') => {
                 if self.seek_next_char() == Some('=') {
@@ -365,6 +411,75 @@ mod tests {
         assert_eq!(kinds, vec![TokenKind::Ident("C".into()), TokenKind::Gt, TokenKind::Number(5.0), TokenKind::Eof]);
     }
 
+    #[test]
+    fn string_literals_keep_chinese_text() {
+        let tokens = lex_all("INDUSTRY == '半导体' OR BOARD == \"创业/科创\"");
+        let kinds: Vec<_> = tokens.into_iter().map(|t| t.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                TokenKind::Ident("INDUSTRY".into()),
+                TokenKind::Eq,
+                TokenKind::Str("半导体".into()),
+                TokenKind::Or,
+                TokenKind::Ident("BOARD".into()),
+                TokenKind::Eq,
+                TokenKind::Str("创业/科创".into()),
+                TokenKind::Eof
+            ]
+        );
+    }
+
+    #[test]
+    fn unterminated_string_is_unknown() {
+        let tokens = lex_all("C == 'abc");
+        assert_eq!(tokens[2].kind, TokenKind::Unknown('\''));
+    }
+
+    #[test]
+    fn case_keywords_are_case_insensitive() {
+        let tokens = lex_all("case when C > 1 Then 2 else 3 END");
+        let kinds: Vec<_> = tokens.into_iter().map(|t| t.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                TokenKind::Case,
+                TokenKind::When,
+                TokenKind::Ident("C".into()),
+                TokenKind::Gt,
+                TokenKind::Number(1.0),
+                TokenKind::Then,
+                TokenKind::Number(2.0),
+                TokenKind::Else,
+                TokenKind::Number(3.0),
+                TokenKind::End,
+                TokenKind::Eof
+            ]
+        );
+    }
+
+    #[test]
+    fn dot_after_ident_is_a_token() {
+        let tokens = lex_all("W.MA(C,5) > 1.5");
+        let kinds: Vec<_> = tokens.into_iter().map(|t| t.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                TokenKind::Ident("W".into()),
+                TokenKind::Dot,
+                TokenKind::Ident("MA".into()),
+                TokenKind::LParen,
+                TokenKind::Ident("C".into()),
+                TokenKind::Comma,
+                TokenKind::Number(5.0),
+                TokenKind::RParen,
+                TokenKind::Gt,
+                TokenKind::Number(1.5),
+                TokenKind::Eof
+            ]
+        );
+    }
+
     #[test]
     fn comment_only() {
         let tokens = lex_all("# only a comment");
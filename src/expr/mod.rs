@@ -1,4 +1,8 @@
+pub mod cross;
 pub mod eval;
+pub mod func;
 pub mod lexer;
 pub mod parser;
+pub mod period;
+pub mod plan;
 pub mod validation;
@@ -1,3 +1,6 @@
+use std::fmt;
+
+use crate::expr::func::UserFunctions;
 use crate::expr::lexer::{Lexer, Token, TokenKind};
 
 //循环解析表达式字符到数组
@@ -21,10 +24,14 @@ pub struct ParseErr {
     pub idx: usize,
 }
 
+// 多周期简写 W.X / M.X 的前缀
+const PERIOD_PREFIXES: [&str; 2] = ["W", "M"];
+
 // 类型枚举
 #[derive(Debug, Clone, PartialEq)]
 pub enum Expr {
     Number(f64),
+    Str(String),
     Ident(String),
     Call {
         name: String,
@@ -40,12 +47,12 @@ pub enum Expr {
         rhs: Box<Expr>,
     }, // 二元运算符, 表达式不定长度,用指针装
 }
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum UnaryOp {
     Neg, // 负号
     Not, // 逻辑非
 }
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum BinaryOp {
     Add,
     Sub,
@@ -83,6 +90,114 @@ fn infix_bp(kind: &TokenKind) -> Option<(u8, u8, BinaryOp)> {
     }
 }
 
+impl BinaryOp {
+    fn symbol(&self) -> &'static str {
+        match self {
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::Gt => ">",
+            BinaryOp::Ge => ">=",
+            BinaryOp::Lt => "<",
+            BinaryOp::Le => "<=",
+            BinaryOp::Eq => "==",
+            BinaryOp::Ne => "!=",
+            BinaryOp::And => "AND",
+            BinaryOp::Or => "OR",
+        }
+    }
+
+    // 和 infix_bp 的左优先级一致
+    fn precedence(&self) -> u8 {
+        match self {
+            BinaryOp::Or => 10,
+            BinaryOp::And => 20,
+            BinaryOp::Gt
+            | BinaryOp::Ge
+            | BinaryOp::Lt
+            | BinaryOp::Le
+            | BinaryOp::Eq
+            | BinaryOp::Ne => 30,
+            BinaryOp::Add | BinaryOp::Sub => 40,
+            BinaryOp::Mul | BinaryOp::Div => 50,
+        }
+    }
+}
+
+// 表达式还原成文本,只在优先级需要时加括号,重新解析得到同一棵树
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expr::Number(value) => write!(f, "{value}"),
+            Expr::Str(text) => {
+                let quote = if text.contains('\'') { '"' } else { '\'' };
+                write!(f, "{quote}{text}{quote}")
+            }
+            Expr::Ident(name) => write!(f, "{name}"),
+            Expr::Call { name, args } => {
+                write!(f, "{name}(")?;
+                for (index, arg) in args.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{arg}")?;
+                }
+                write!(f, ")")
+            }
+            Expr::Unary { op, rhs } => {
+                let prefix = match op {
+                    UnaryOp::Neg => "-",
+                    UnaryOp::Not => "NOT ",
+                };
+                if matches!(**rhs, Expr::Binary { .. }) {
+                    write!(f, "{prefix}({rhs})")
+                } else {
+                    write!(f, "{prefix}{rhs}")
+                }
+            }
+            Expr::Binary { op, lhs, rhs } => {
+                let prec = op.precedence();
+                match &**lhs {
+                    Expr::Binary { op: lhs_op, .. } if lhs_op.precedence() < prec => {
+                        write!(f, "({lhs})")?
+                    }
+                    _ => write!(f, "{lhs}")?,
+                }
+                write!(f, " {} ", op.symbol())?;
+                match &**rhs {
+                    Expr::Binary { op: rhs_op, .. } if rhs_op.precedence() <= prec => {
+                        write!(f, "({rhs})")
+                    }
+                    _ => write!(f, "{rhs}"),
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for Stmt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Stmt::Expr(expr) => write!(f, "{expr}"),
+            Stmt::Assign { name, value } => write!(f, "{name} := {value}"),
+        }
+    }
+}
+
+// 程序按语句用分号连接,规则指纹靠它识别表达式变化
+impl fmt::Display for Stmts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, stmt) in self.item.iter().enumerate() {
+            if index > 0 {
+                write!(f, "; ")?;
+            }
+            write!(f, "{stmt}")?;
+        }
+        Ok(())
+    }
+}
+
 fn in_bp(kind: &TokenKind) -> Option<(u8, u8)> {
     match kind {
         TokenKind::In => Some((30, 31)),
@@ -95,8 +210,50 @@ fn in_bp(kind: &TokenKind) -> Option<(u8, u8)> {
 pub struct Parser {
     token: Vec<Token>,
     idx: usize,
+    functions: UserFunctions,
+    spanned: Vec<SpannedStmt>,
+}
+
+// 表达式节点在源码中的区间, children 顺序和 Expr 的子表达式一致:
+// Call 对应参数, Unary 对应 rhs, Binary 对应 lhs 和 rhs
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ExprSpan {
+    pub start: usize,
+    pub end: usize,
+    pub children: Vec<ExprSpan>,
+}
+
+impl ExprSpan {
+    fn leaf(token: &Token) -> Self {
+        Self {
+            start: token.start,
+            end: token.end,
+            children: Vec::new(),
+        }
+    }
+
+    fn node(start: usize, end: usize, children: Vec<ExprSpan>) -> Self {
+        Self {
+            start,
+            end,
+            children,
+        }
+    }
+
+    pub fn child(&self, index: usize) -> Option<&ExprSpan> {
+        self.children.get(index)
+    }
+}
+
+// 展开自定义函数之前的语句和位置, 供静态检查定位错误
+#[derive(Debug, Clone)]
+pub struct SpannedStmt {
+    pub stmt: Stmt,
+    pub span: ExprSpan,
 }
 
+type Parsed = (Expr, ExprSpan);
+
 // 语句和赋值的枚举
 #[derive(Debug, Clone, PartialEq)]
 pub enum Stmt {
@@ -110,14 +267,38 @@ pub struct Stmts {
     pub item: Vec<Stmt>,
 }
 
+// 自定义函数定义: FUNC NAME(A, B) := 表达式
+#[derive(Debug, Clone, PartialEq)]
+pub struct FuncDef {
+    pub name: String,
+    pub params: Vec<String>,
+    pub body: Expr,
+}
+
 impl Parser {
     pub fn new(input: Vec<Token>) -> Self {
+        Self::with_functions(input, &UserFunctions::default())
+    }
+
+    // 带公共函数的解析器,程序内的FUNC定义会追加到这份函数表
+    pub fn with_functions(input: Vec<Token>, functions: &UserFunctions) -> Self {
         Self {
             token: input,
             idx: 0,
+            functions: functions.clone(),
+            spanned: Vec::new(),
         }
     }
 
+    pub fn into_functions(self) -> UserFunctions {
+        self.functions
+    }
+
+    // parse_main 之后取出未展开的语句, 和 Stmts 的语句一一对应
+    pub fn take_spanned_stmts(&mut self) -> Vec<SpannedStmt> {
+        std::mem::take(&mut self.spanned)
+    }
+
     fn peek_kind(&self) -> &TokenKind {
         &self.token[self.idx].kind
     }
@@ -130,6 +311,10 @@ impl Parser {
         self.current_token().start
     }
 
+    fn prev_end(&self) -> usize {
+        self.idx.checked_sub(1).map_or(0, |idx| self.token[idx].end)
+    }
+
     fn err_here(&self, msg: impl Into<String>) -> ParseErr {
         ParseErr {
             msg: msg.into(),
@@ -150,12 +335,20 @@ impl Parser {
             TokenKind::RBracket => "`]`".to_string(),
             TokenKind::Comma => "`,`".to_string(),
             TokenKind::Semi => "`;`".to_string(),
+            TokenKind::Dot => "`.`".to_string(),
             TokenKind::And => "`AND`".to_string(),
             TokenKind::Or => "`OR`".to_string(),
             TokenKind::Not => "`NOT`".to_string(),
             TokenKind::In => "`IN`".to_string(),
+            TokenKind::Func => "`FUNC`".to_string(),
+            TokenKind::Case => "`CASE`".to_string(),
+            TokenKind::When => "`WHEN`".to_string(),
+            TokenKind::Then => "`THEN`".to_string(),
+            TokenKind::Else => "`ELSE`".to_string(),
+            TokenKind::End => "`END`".to_string(),
             TokenKind::Ident(name) => format!("标识符 `{name}`"),
             TokenKind::Number(num) => format!("数字 `{num}`"),
+            TokenKind::Str(text) => format!("字符串 \"{text}\""),
             TokenKind::Gt => "`>`".to_string(),
             TokenKind::Ge => "`>=`".to_string(),
             TokenKind::Lt => "`<`".to_string(),
@@ -180,9 +373,9 @@ impl Parser {
             .unwrap_or(&TokenKind::Eof)
     }
 
-    fn parse_expr(&mut self, min_bp: u8) -> Result<Expr, ParseErr> {
+    fn parse_expr(&mut self, min_bp: u8) -> Result<Parsed, ParseErr> {
         // 吃掉左操作数
-        let mut lhs = self.parse_primary()?;
+        let (mut lhs, mut lhs_span) = self.parse_primary()?;
 
         // 匹配优先级
         loop {
@@ -192,7 +385,7 @@ impl Parser {
                 }
 
                 self.pop_token();
-                lhs = self.parse_in_range_expr(lhs)?;
+                (lhs, lhs_span) = self.parse_in_range_expr(lhs, lhs_span)?;
                 continue;
             }
 
@@ -208,21 +401,26 @@ impl Parser {
             // 通过优先级检查,拼装二元表达式
             self.pop_token();
             // 右边的再次解析,用较大的r_bp,避免同级之间争抢中间操作数,应归属于前者所有
-            let rhs = self.parse_expr(r_bp)?;
+            let (rhs, rhs_span) = self.parse_expr(r_bp)?;
             lhs = Expr::Binary {
                 op,
                 lhs: Box::new(lhs),
                 rhs: Box::new(rhs),
             };
+            lhs_span = ExprSpan::node(lhs_span.start, rhs_span.end, vec![lhs_span, rhs_span]);
         }
 
-        Ok(lhs)
+        Ok((lhs, lhs_span))
     }
 
-    fn parse_in_range_expr(&mut self, lhs: Expr) -> Result<Expr, ParseErr> {
+    fn parse_in_range_expr(&mut self, lhs: Expr, lhs_span: ExprSpan) -> Result<Parsed, ParseErr> {
         let include_lower = match self.peek_kind() {
             TokenKind::LBracket => {
                 self.pop_token();
+                // 方括号里第一个是字符串时按枚举列表处理
+                if matches!(self.peek_kind(), TokenKind::Str(_)) {
+                    return self.parse_in_list_expr(lhs, lhs_span);
+                }
                 true
             }
             TokenKind::LParen => {
@@ -237,7 +435,7 @@ impl Parser {
             }
         };
 
-        let lower = self.parse_expr(0)?;
+        let (lower, lower_span) = self.parse_expr(0)?;
         match self.peek_kind() {
             TokenKind::Comma => {
                 self.pop_token();
@@ -250,7 +448,7 @@ impl Parser {
             }
         }
 
-        let upper = self.parse_expr(0)?;
+        let (upper, upper_span) = self.parse_expr(0)?;
         let include_upper = match self.peek_kind() {
             TokenKind::RBracket => {
                 self.pop_token();
@@ -287,14 +485,78 @@ impl Parser {
             rhs: Box::new(upper),
         };
 
-        Ok(Expr::Binary {
-            op: BinaryOp::And,
-            lhs: Box::new(lower_cmp),
-            rhs: Box::new(upper_cmp),
-        })
+        let start = lhs_span.start;
+        let lower_span = ExprSpan::node(start, lower_span.end, vec![lhs_span.clone(), lower_span]);
+        let upper_span = ExprSpan::node(start, upper_span.end, vec![lhs_span, upper_span]);
+        Ok((
+            Expr::Binary {
+                op: BinaryOp::And,
+                lhs: Box::new(lower_cmp),
+                rhs: Box::new(upper_cmp),
+            },
+            ExprSpan::node(start, self.prev_end(), vec![lower_span, upper_span]),
+        ))
+    }
+
+    // X IN ["A", "B"] 展开成 X == "A" OR X == "B"
+    fn parse_in_list_expr(&mut self, lhs: Expr, lhs_span: ExprSpan) -> Result<Parsed, ParseErr> {
+        let mut out: Option<Parsed> = None;
+        loop {
+            let item_span = ExprSpan::leaf(self.current_token());
+            let item = match self.peek_kind() {
+                TokenKind::Str(text) => Expr::Str(text.clone()),
+                other => {
+                    return Err(self.err_here(format!(
+                        "`IN` 列表只能包含字符串，当前位置是 {}",
+                        Self::token_brief(other)
+                    )));
+                }
+            };
+            self.pop_token();
+            let eq = Expr::Binary {
+                op: BinaryOp::Eq,
+                lhs: Box::new(lhs.clone()),
+                rhs: Box::new(item),
+            };
+            let eq_span = ExprSpan::node(
+                lhs_span.start,
+                item_span.end,
+                vec![lhs_span.clone(), item_span],
+            );
+            out = Some(match out {
+                Some((prev, prev_span)) => (
+                    Expr::Binary {
+                        op: BinaryOp::Or,
+                        lhs: Box::new(prev),
+                        rhs: Box::new(eq),
+                    },
+                    ExprSpan::node(lhs_span.start, eq_span.end, vec![prev_span, eq_span]),
+                ),
+                None => (eq, eq_span),
+            });
+
+            match self.peek_kind() {
+                TokenKind::Comma => {
+                    self.pop_token();
+                }
+                TokenKind::RBracket => {
+                    self.pop_token();
+                    break;
+                }
+                other => {
+                    return Err(self.err_here(format!(
+                        "`IN` 列表没有正确闭合，期望 `,` 或 `]`，当前位置是 {}",
+                        Self::token_brief(other)
+                    )));
+                }
+            }
+        }
+        Ok(out.expect("IN 列表至少有一项"))
     }
 
-    fn parse_primary(&mut self) -> Result<Expr, ParseErr> {
+    fn parse_primary(&mut self) -> Result<Parsed, ParseErr> {
+        let start_token = self.current_token().clone();
+        let start = start_token.start;
         match self.peek_kind() {
             // 字符串分支 先判断是不是内置函数
             TokenKind::Ident(_) => {
@@ -302,9 +564,27 @@ impl Parser {
                     TokenKind::Ident(name) => name,
                     other => return Err(self.err_here(format!("变量名解析失败，当前位置是 {}", Self::token_brief(&other)))),
                 };
+                // W.X / M.X 是 PERIOD("W", X) / PERIOD("M", X) 的简写
+                if matches!(self.peek_kind(), TokenKind::Dot)
+                    && PERIOD_PREFIXES.iter().any(|p| name.eq_ignore_ascii_case(p))
+                {
+                    self.pop_token();
+                    let (inner, inner_span) = self.parse_primary()?;
+                    return Ok((
+                        Expr::Call {
+                            name: "PERIOD".to_string(),
+                            args: vec![Expr::Str(name.to_ascii_uppercase()), inner],
+                        },
+                        ExprSpan::node(
+                            start,
+                            self.prev_end(),
+                            vec![ExprSpan::leaf(&start_token), inner_span],
+                        ),
+                    ));
+                }
                 // 用左括号检查是否是函数
                 if !matches!(self.peek_kind(), TokenKind::LParen) {
-                    return Ok(Expr::Ident(name));
+                    return Ok((Expr::Ident(name), ExprSpan::leaf(&start_token)));
                 }
                 match self.pop_token() {
                     TokenKind::LParen => {}
@@ -318,14 +598,20 @@ impl Parser {
 
                 // 函数参数解析
                 let mut args = Vec::new();
+                let mut arg_spans = Vec::new();
 
                 if matches!(self.peek_kind(), TokenKind::RParen) {
                     self.pop_token();
-                    return Ok(Expr::Call { name, args });
+                    return Ok((
+                        Expr::Call { name, args },
+                        ExprSpan::node(start, self.prev_end(), arg_spans),
+                    ));
                 }
 
                 loop {
-                    args.push(self.parse_expr(0)?);
+                    let (arg, arg_span) = self.parse_expr(0)?;
+                    args.push(arg);
+                    arg_spans.push(arg_span);
 
                     match self.peek_kind() {
                         TokenKind::Comma => {
@@ -343,25 +629,40 @@ impl Parser {
                         }
                     }
                 }
-                Ok(Expr::Call { name, args })
+                Ok((
+                    Expr::Call { name, args },
+                    ExprSpan::node(start, self.prev_end(), arg_spans),
+                ))
             }
 
             // 数字分支
             TokenKind::Number(_) => match self.pop_token() {
-                TokenKind::Number(num) => Ok(Expr::Number(num)),
+                TokenKind::Number(num) => Ok((Expr::Number(num), ExprSpan::leaf(&start_token))),
                 other => Err(self.err_here(format!(
                     "数字解析失败，当前位置是 {}",
                     Self::token_brief(&other)
                 ))),
             },
+            // 字符串分支
+            TokenKind::Str(_) => match self.pop_token() {
+                TokenKind::Str(text) => Ok((Expr::Str(text), ExprSpan::leaf(&start_token))),
+                other => Err(self.err_here(format!(
+                    "字符串解析失败，当前位置是 {}",
+                    Self::token_brief(&other)
+                ))),
+            },
             // 左括号分支
             TokenKind::LParen => {
                 self.pop_token();
-                let inner = self.parse_expr(0)?;
+                let (inner, inner_span) = self.parse_expr(0)?;
                 match self.peek_kind() {
                     TokenKind::RParen => {
                         self.pop_token();
-                        Ok(inner)
+                        // 括号不产生节点, 区间连括号一起标出
+                        Ok((
+                            inner,
+                            ExprSpan::node(start, self.prev_end(), inner_span.children),
+                        ))
                     }
                     other => Err(self.err_here(format!(
                         "括号表达式没有闭合，期望 `)`，当前位置是 {}",
@@ -369,34 +670,129 @@ impl Parser {
                     ))),
                 }
             }
+            // CASE 分支
+            TokenKind::Case => self.parse_case_expr(),
             // 负号分支
             TokenKind::Minus => {
                 self.pop_token();
-                let rhs = self.parse_primary()?;
-                Ok(Expr::Unary {
-                    op: UnaryOp::Neg,
-                    rhs: Box::new(rhs),
-                })
+                let (rhs, rhs_span) = self.parse_primary()?;
+                Ok((
+                    Expr::Unary {
+                        op: UnaryOp::Neg,
+                        rhs: Box::new(rhs),
+                    },
+                    ExprSpan::node(start, rhs_span.end, vec![rhs_span]),
+                ))
             }
             // 感叹号分支
             TokenKind::Not => {
                 self.pop_token();
-                let rhs = self.parse_primary()?;
-                Ok(Expr::Unary {
-                    op: UnaryOp::Not,
-                    rhs: Box::new(rhs),
-                })
+                let (rhs, rhs_span) = self.parse_primary()?;
+                Ok((
+                    Expr::Unary {
+                        op: UnaryOp::Not,
+                        rhs: Box::new(rhs),
+                    },
+                    ExprSpan::node(start, rhs_span.end, vec![rhs_span]),
+                ))
             }
 
             other => Err(self.err_here(format!(
-                "这里不能直接开始一个表达式，当前位置是 {}；期望数字、变量、函数调用、括号表达式、负号 `-` 或 `NOT`",
+                "这里不能直接开始一个表达式，当前位置是 {}；期望数字、字符串、变量、函数调用、括号表达式、`CASE`、负号 `-` 或 `NOT`",
                 Self::token_brief(other)
             ))),
         }
     }
 
+    // CASE [X] WHEN a THEN x ... ELSE y END 展开成嵌套 IF,
+    // 带 X 时每个 WHEN 比较 X == a; IF 求值时按条件短路
+    fn parse_case_expr(&mut self) -> Result<Parsed, ParseErr> {
+        let start = self.current_offset();
+        self.pop_token();
+        let subject = if matches!(self.peek_kind(), TokenKind::When) {
+            None
+        } else {
+            Some(self.parse_expr(0)?)
+        };
+
+        let mut branches = Vec::new();
+        while matches!(self.peek_kind(), TokenKind::When) {
+            self.pop_token();
+            let (when, when_span) = self.parse_expr(0)?;
+            match self.peek_kind() {
+                TokenKind::Then => {
+                    self.pop_token();
+                }
+                other => {
+                    return Err(self.err_here(format!(
+                        "`WHEN` 条件后需要 `THEN`，当前位置是 {}",
+                        Self::token_brief(other)
+                    )));
+                }
+            }
+            let then = self.parse_expr(0)?;
+            let cond = match &subject {
+                Some((subject, subject_span)) => (
+                    Expr::Binary {
+                        op: BinaryOp::Eq,
+                        lhs: Box::new(subject.clone()),
+                        rhs: Box::new(when),
+                    },
+                    ExprSpan::node(
+                        when_span.start,
+                        when_span.end,
+                        vec![subject_span.clone(), when_span],
+                    ),
+                ),
+                None => (when, when_span),
+            };
+            branches.push((cond, then));
+        }
+        if branches.is_empty() {
+            return Err(self.err_here(format!(
+                "`CASE` 至少需要一个 `WHEN` 分支，当前位置是 {}",
+                Self::token_brief(self.peek_kind())
+            )));
+        }
+
+        match self.peek_kind() {
+            TokenKind::Else => {
+                self.pop_token();
+            }
+            other => {
+                return Err(self.err_here(format!(
+                    "`CASE` 需要 `ELSE` 分支，当前位置是 {}",
+                    Self::token_brief(other)
+                )));
+            }
+        }
+        let (mut out, mut out_span) = self.parse_expr(0)?;
+        match self.peek_kind() {
+            TokenKind::End => {
+                self.pop_token();
+            }
+            other => {
+                return Err(self.err_here(format!(
+                    "`CASE` 没有正确结束，期望 `END`，当前位置是 {}",
+                    Self::token_brief(other)
+                )));
+            }
+        }
+
+        // 展开出来的 IF 都标在整个 CASE 上
+        let end = self.prev_end();
+        for ((cond, cond_span), (then, then_span)) in branches.into_iter().rev() {
+            out = Expr::Call {
+                name: "IF".to_string(),
+                args: vec![cond, then, out],
+            };
+            out_span = ExprSpan::node(start, end, vec![cond_span, then_span, out_span]);
+        }
+        Ok((out, out_span))
+    }
+
     // 等号右边表达式判断
-    fn parse_stmt(&mut self) -> Result<Stmt, ParseErr> {
+    fn parse_stmt(&mut self) -> Result<(Stmt, ExprSpan), ParseErr> {
         if matches!(self.peek_kind(), TokenKind::Ident(_)) {
             // 检查是否为赋值分支
             if matches!(self.peek_next_token(), TokenKind::ColonEq) {
@@ -415,13 +811,105 @@ impl Parser {
                         return Err(self.err_here("赋值语句需要使用 `:=`".to_string()));
                     }
                 }
-                let value = self.parse_expr(0)?;
-                return Ok(Stmt::Assign { name, value });
+                let (value, span) = self.parse_expr(0)?;
+                return Ok((Stmt::Assign { name, value }, span));
             }
         }
         // 否则走正常表达式分支
-        let expr = self.parse_expr(0)?;
-        Ok(Stmt::Expr(expr))
+        let (expr, span) = self.parse_expr(0)?;
+        Ok((Stmt::Expr(expr), span))
+    }
+
+    // FUNC 定义分支
+    fn parse_func_def(&mut self) -> Result<FuncDef, ParseErr> {
+        self.pop_token();
+        let name = match self.peek_kind() {
+            TokenKind::Ident(name) => name.clone(),
+            other => {
+                return Err(self.err_here(format!(
+                    "`FUNC` 后需要函数名，当前位置是 {}",
+                    Self::token_brief(other)
+                )));
+            }
+        };
+        self.pop_token();
+
+        match self.peek_kind() {
+            TokenKind::LParen => {
+                self.pop_token();
+            }
+            other => {
+                return Err(self.err_here(format!(
+                    "自定义函数 `{name}` 后需要左括号 `(`，当前位置是 {}",
+                    Self::token_brief(other)
+                )));
+            }
+        }
+
+        let mut params = Vec::new();
+        if matches!(self.peek_kind(), TokenKind::RParen) {
+            self.pop_token();
+        } else {
+            loop {
+                match self.peek_kind() {
+                    TokenKind::Ident(param) => {
+                        params.push(param.clone());
+                        self.pop_token();
+                    }
+                    other => {
+                        return Err(self.err_here(format!(
+                            "自定义函数 `{name}` 的参数必须是变量名，当前位置是 {}",
+                            Self::token_brief(other)
+                        )));
+                    }
+                }
+
+                match self.peek_kind() {
+                    TokenKind::Comma => {
+                        self.pop_token();
+                    }
+                    TokenKind::RParen => {
+                        self.pop_token();
+                        break;
+                    }
+                    other => {
+                        return Err(self.err_here(format!(
+                            "自定义函数 `{name}` 的参数列表未正确结束，期望 `,` 或 `)`，当前位置是 {}",
+                            Self::token_brief(other)
+                        )));
+                    }
+                }
+            }
+        }
+
+        match self.peek_kind() {
+            TokenKind::ColonEq => {
+                self.pop_token();
+            }
+            other => {
+                return Err(self.err_here(format!(
+                    "自定义函数 `{name}` 定义需要使用 `:=`，当前位置是 {}",
+                    Self::token_brief(other)
+                )));
+            }
+        }
+
+        let (body, _) = self.parse_expr(0)?;
+        Ok(FuncDef { name, params, body })
+    }
+
+    // 把语句里的自定义函数调用展开成内置函数
+    fn expand_stmt(&self, stmt: Stmt) -> Result<Stmt, String> {
+        if self.functions.is_empty() {
+            return Ok(stmt);
+        }
+        match stmt {
+            Stmt::Expr(expr) => Ok(Stmt::Expr(self.functions.expand_expr(expr)?)),
+            Stmt::Assign { name, value } => Ok(Stmt::Assign {
+                name,
+                value: self.functions.expand_expr(value)?,
+            }),
+        }
     }
 
     pub fn parse_main(&mut self) -> Result<Stmts, ParseErr> {
@@ -438,7 +926,26 @@ impl Parser {
                 _ => {}
             }
 
-            stmts.push(self.parse_stmt()?);
+            // 函数定义只登记不产出语句,调用处按出现顺序展开
+            let stmt_start = self.current_offset();
+            if matches!(self.peek_kind(), TokenKind::Func) {
+                let def = self.parse_func_def()?;
+                self.functions.insert(def).map_err(|msg| ParseErr {
+                    msg,
+                    idx: stmt_start,
+                })?;
+            } else {
+                let (stmt, span) = self.parse_stmt()?;
+                self.spanned.push(SpannedStmt {
+                    stmt: stmt.clone(),
+                    span,
+                });
+                let stmt = self.expand_stmt(stmt).map_err(|msg| ParseErr {
+                    msg,
+                    idx: stmt_start,
+                })?;
+                stmts.push(stmt);
+            }
 
             match self.peek_kind() {
                 TokenKind::Semi => {
@@ -536,6 +1043,62 @@ mod tests {
         }
     }
 
+    #[test]
+    fn func_definition_is_expanded_at_call_site() {
+        use super::{BinaryOp, Expr, Stmt};
+
+        let mut parser = Parser::new(lex_all("FUNC UP(X, N) := X > REF(X, N); UP(C, 1)"));
+        let stmts = parser.parse_main().expect("parse should succeed");
+
+        assert_eq!(stmts.item.len(), 1);
+        match &stmts.item[0] {
+            Stmt::Expr(Expr::Binary { op, lhs, rhs }) => {
+                assert_eq!(*op, BinaryOp::Gt);
+                assert_eq!(**lhs, Expr::Ident("C".to_string()));
+                assert!(
+                    matches!(&**rhs, Expr::Call { name, args } if name == "REF" && args.len() == 2)
+                );
+            }
+            other => panic!("unexpected stmt: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn reports_func_definition_without_assign_clearly() {
+        let (idx, msg) = parse_err("FUNC UP(X) X > 1");
+        assert_eq!(idx, 11);
+        assert!(msg.contains("自定义函数 `UP` 定义需要使用 `:=`"));
+    }
+
+    #[test]
+    fn parses_in_string_list_into_eq_chain() {
+        use super::{BinaryOp, Expr, Stmt};
+
+        let mut parser = Parser::new(lex_all("INDUSTRY IN ['半导体', '元器件']"));
+        let stmts = parser.parse_main().expect("parse should succeed");
+
+        let eq = |text: &str| Expr::Binary {
+            op: BinaryOp::Eq,
+            lhs: Box::new(Expr::Ident("INDUSTRY".to_string())),
+            rhs: Box::new(Expr::Str(text.to_string())),
+        };
+        assert_eq!(
+            stmts.item,
+            vec![Stmt::Expr(Expr::Binary {
+                op: BinaryOp::Or,
+                lhs: Box::new(eq("半导体")),
+                rhs: Box::new(eq("元器件")),
+            })]
+        );
+    }
+
+    #[test]
+    fn reports_non_string_in_list_item_clearly() {
+        let (idx, msg) = parse_err("BOARD IN ['主板', 1]");
+        assert_eq!(idx, 20);
+        assert!(msg.contains("`IN` 列表只能包含字符串"));
+    }
+
     #[test]
     fn reports_missing_in_range_delimiter_clearly() {
         let (idx, msg) = parse_err("C IN [1 2]");
@@ -543,4 +1106,107 @@ mod tests {
         assert!(msg.contains("`IN` 范围缺少分隔符"));
         assert!(msg.contains("标识符") || msg.contains("数字 `2`"));
     }
+
+    #[test]
+    fn display_round_trips_through_the_parser() {
+        for text in [
+            "(C - O) / O * 100 > 3 AND NOT (V < MA(V, 5) OR C == 'ST')",
+            "C - (O - L) >= -H",
+            "IF(C > O, 1, 0) + REF(C, 1)",
+        ] {
+            let stmts = Parser::new(lex_all(text))
+                .parse_main()
+                .expect("parse should succeed");
+            let super::Stmt::Expr(expr) = &stmts.item[0] else {
+                panic!("expected expression");
+            };
+            let printed = expr.to_string();
+            let reparsed = Parser::new(lex_all(&printed))
+                .parse_main()
+                .expect("printed text should parse");
+            assert_eq!(reparsed.item, stmts.item, "printed={printed}");
+        }
+    }
+
+    #[test]
+    fn program_display_round_trips_through_the_parser() {
+        let text = "UP := C > O; N := REF(V, 1); UP AND V > N * 2";
+        let stmts = Parser::new(lex_all(text))
+            .parse_main()
+            .expect("parse should succeed");
+        let printed = stmts.to_string();
+        assert_eq!(printed, text);
+        let reparsed = Parser::new(lex_all(&printed))
+            .parse_main()
+            .expect("printed text should parse");
+        assert_eq!(reparsed.item, stmts.item);
+    }
+
+    #[test]
+    fn parses_case_into_nested_if_calls() {
+        use super::{BinaryOp, Expr, Stmt};
+
+        let mut parser = Parser::new(lex_all(
+            "CASE BOARD WHEN 'ST' THEN 0 WHEN '主板' THEN 1 ELSE 2 END",
+        ));
+        let stmts = parser.parse_main().expect("parse should succeed");
+
+        let eq = |text: &str| Expr::Binary {
+            op: BinaryOp::Eq,
+            lhs: Box::new(Expr::Ident("BOARD".to_string())),
+            rhs: Box::new(Expr::Str(text.to_string())),
+        };
+        let if_call = |cond, then, other| Expr::Call {
+            name: "IF".to_string(),
+            args: vec![cond, then, other],
+        };
+        assert_eq!(
+            stmts.item,
+            vec![Stmt::Expr(if_call(
+                eq("ST"),
+                Expr::Number(0.0),
+                if_call(eq("主板"), Expr::Number(1.0), Expr::Number(2.0)),
+            ))]
+        );
+    }
+
+    #[test]
+    fn period_prefix_lowers_to_period_call() {
+        use super::{BinaryOp, Expr, Stmt};
+
+        let stmts = Parser::new(lex_all("w.MA(C, 5) > M.C"))
+            .parse_main()
+            .expect("parse should succeed");
+        let period = |freq: &str, inner| Expr::Call {
+            name: "PERIOD".to_string(),
+            args: vec![Expr::Str(freq.to_string()), inner],
+        };
+        assert_eq!(
+            stmts.item,
+            vec![Stmt::Expr(Expr::Binary {
+                op: BinaryOp::Gt,
+                lhs: Box::new(period(
+                    "W",
+                    Expr::Call {
+                        name: "MA".to_string(),
+                        args: vec![Expr::Ident("C".to_string()), Expr::Number(5.0)],
+                    },
+                )),
+                rhs: Box::new(period("M", Expr::Ident("C".to_string()))),
+            })]
+        );
+
+        let (_, msg) = parse_err("X.C > 1");
+        assert!(msg.contains("`.`"));
+    }
+
+    #[test]
+    fn reports_case_without_else_clearly() {
+        let (idx, msg) = parse_err("CASE WHEN C > 1 THEN 1 END");
+        assert_eq!(idx, 23);
+        assert!(msg.contains("`CASE` 需要 `ELSE` 分支"));
+
+        let (_, msg) = parse_err("CASE WHEN C > 1 1 ELSE 0 END");
+        assert!(msg.contains("`WHEN` 条件后需要 `THEN`"));
+    }
 }
@@ -0,0 +1,175 @@
+//! Multi-timeframe (weekly / monthly) expression access.
+//!
+//! `PERIOD("W", expr)` and `PERIOD("M", expr)`, or the shorthand `W.expr` and
+//! `M.expr`, resample the daily runtime into weekly or monthly bars, evaluate
+//! `expr` on those bars and map the result back onto the daily bars.
+//!
+//! A daily bar only sees periods that closed before it: every day of week `k`
+//! gets the value of week `k - 1`, so a weekly signal shows up on the first
+//! trading day of the following week and never leaks the rest of the current
+//! week into earlier days.
+
+use chrono::{Datelike, NaiveDate};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PeriodFreq {
+    Week,
+    Month,
+}
+
+impl PeriodFreq {
+    pub fn parse(text: &str) -> Option<Self> {
+        match text.trim().to_ascii_uppercase().as_str() {
+            "W" | "WEEK" | "WEEKLY" => Some(Self::Week),
+            "M" | "MONTH" | "MONTHLY" => Some(Self::Month),
+            _ => None,
+        }
+    }
+
+    /// 一个周期大约包含的交易日数, 用于估算warmup
+    pub fn bars_per_period(self) -> usize {
+        match self {
+            Self::Week => 5,
+            Self::Month => 23,
+        }
+    }
+}
+
+/// 日线聚合成周期K线的方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resample {
+    First,
+    Max,
+    Min,
+    Sum,
+    Last,
+}
+
+impl Resample {
+    /// 开盘/昨收取周期首日, 高低取极值, 量额换手累加, 其他字段取周期末值
+    pub fn for_field(name: &str) -> Self {
+        match name.to_ascii_uppercase().as_str() {
+            "O" | "OPEN" | "PRE_CLOSE" => Self::First,
+            "H" | "HIGH" => Self::Max,
+            "L" | "LOW" => Self::Min,
+            "V" | "VOL" | "AMOUNT" | "TOR" => Self::Sum,
+            _ => Self::Last,
+        }
+    }
+}
+
+/// 每根日线所在周期的序号(从0递增)和周期总数
+pub fn period_ids(freq: PeriodFreq, trade_dates: &[String]) -> Result<(Vec<usize>, usize), String> {
+    let mut ids = Vec::with_capacity(trade_dates.len());
+    let mut prev_key = None;
+    let mut count = 0;
+    for date in trade_dates {
+        let day = NaiveDate::parse_from_str(date.trim(), "%Y%m%d")
+            .map_err(|_| format!("交易日期格式不对:{date}"))?;
+        let key = match freq {
+            PeriodFreq::Week => {
+                let week = day.iso_week();
+                (week.year(), week.week())
+            }
+            PeriodFreq::Month => (day.year(), day.month()),
+        };
+        if prev_key != Some(key) {
+            prev_key = Some(key);
+            count += 1;
+        }
+        ids.push(count - 1);
+    }
+    Ok((ids, count))
+}
+
+/// 按周期聚合日线序列, 缺失值不参与聚合, 整个周期都缺失时结果也缺失
+pub fn resample_series(
+    series: &[Option<f64>],
+    ids: &[usize],
+    count: usize,
+    rule: Resample,
+) -> Vec<Option<f64>> {
+    let mut out: Vec<Option<f64>> = vec![None; count];
+    for (value, &id) in series.iter().zip(ids) {
+        let Some(value) = *value else {
+            continue;
+        };
+        let slot = &mut out[id];
+        *slot = Some(match (*slot, rule) {
+            (None, _) => value,
+            (Some(acc), Resample::First) => acc,
+            (Some(acc), Resample::Max) => acc.max(value),
+            (Some(acc), Resample::Min) => acc.min(value),
+            (Some(acc), Resample::Sum) => acc + value,
+            (Some(_), Resample::Last) => value,
+        });
+    }
+    out
+}
+
+/// 非数值序列按周期取末值
+pub fn resample_last<T: Clone>(series: &[T], ids: &[usize], count: usize, missing: T) -> Vec<T> {
+    let mut out = vec![missing; count];
+    for (value, &id) in series.iter().zip(ids) {
+        out[id] = value.clone();
+    }
+    out
+}
+
+/// 对齐回日线: 第k个周期内的日线取第k-1个已走完周期的值, 第一个周期内为missing
+pub fn align_to_daily<T: Clone>(values: &[T], ids: &[usize], missing: T) -> Vec<T> {
+    ids.iter()
+        .map(|&id| match id.checked_sub(1) {
+            Some(prev) => values[prev].clone(),
+            None => missing.clone(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dates(items: &[&str]) -> Vec<String> {
+        items.iter().map(|item| item.to_string()).collect()
+    }
+
+    #[test]
+    fn weeks_and_months_follow_the_calendar() {
+        // 2024-12-30是周一, 和2025-01-03同属ISO第1周
+        let trade_dates = dates(&["20241227", "20241230", "20241231", "20250102", "20250106"]);
+        let (ids, count) = period_ids(PeriodFreq::Week, &trade_dates).unwrap();
+        assert_eq!(ids, vec![0, 1, 1, 1, 2]);
+        assert_eq!(count, 3);
+
+        let (ids, count) = period_ids(PeriodFreq::Month, &trade_dates).unwrap();
+        assert_eq!(ids, vec![0, 0, 0, 1, 1]);
+        assert_eq!(count, 2);
+
+        assert!(period_ids(PeriodFreq::Week, &dates(&["2024-12-27"])).is_err());
+    }
+
+    #[test]
+    fn resample_and_align_do_not_look_ahead() {
+        let ids = vec![0, 0, 1, 1, 1, 2];
+        let high = vec![Some(3.0), Some(5.0), Some(4.0), None, Some(6.0), Some(1.0)];
+        assert_eq!(
+            resample_series(&high, &ids, 3, Resample::Max),
+            vec![Some(5.0), Some(6.0), Some(1.0)]
+        );
+        assert_eq!(
+            resample_series(&high, &ids, 3, Resample::First),
+            vec![Some(3.0), Some(4.0), Some(1.0)]
+        );
+        assert_eq!(
+            resample_series(&high, &ids, 3, Resample::Sum),
+            vec![Some(8.0), Some(10.0), Some(1.0)]
+        );
+
+        let weekly = vec![Some(5.0), Some(6.0), Some(1.0)];
+        assert_eq!(
+            align_to_daily(&weekly, &ids, None),
+            vec![None, None, Some(5.0), Some(5.0), Some(5.0), Some(6.0)]
+        );
+    }
+}
@@ -0,0 +1,667 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::expr::eval::{EvalErr, ExpressionFunction, Runtime, Value};
+use crate::expr::parser::{BinaryOp, Expr, Stmt, Stmts, UnaryOp};
+
+// 操作数: 常量内联,基础变量按名字借用,中间结果放在槽位里
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Operand {
+    Num(u64),
+    Str(String),
+    Var(String),
+    Slot(usize),
+}
+
+// 指令本身就是公共子表达式的去重键
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Op {
+    Unary(UnaryOp, Operand),
+    Binary(BinaryOp, Operand, Operand),
+    Call(String, Vec<Operand>),
+}
+
+#[derive(Debug, Clone)]
+struct Instr {
+    op: Op,
+    // 函数实现按表达式取参数,槽位参数在执行时临时绑定成变量
+    call_args: Vec<Expr>,
+    // 原样保留的参数里引用的局部变量名, 依次对应操作数末尾的同样数量的部分,
+    // 执行时按原名绑定
+    locals: Vec<String>,
+}
+
+/// 把一组程序编译成扁平的指令计划。
+/// 每条指令写一个槽位,相同的子表达式(包括跨程序的)只算一次,
+/// 槽位在最后一次使用后立即移交或释放。程序之间互相隔离,
+/// 执行时不会把赋值写回 Runtime,效果等同逐条求值后恢复变量。
+#[derive(Debug, Clone)]
+pub struct ExprPlan {
+    instrs: Vec<Instr>,
+    outputs: Vec<Operand>,
+    last_use: Vec<usize>,
+    programs: Vec<Stmts>,
+}
+
+const OUTPUT_USE: usize = usize::MAX;
+
+fn slot_var_name(slot: usize) -> String {
+    format!("#{slot}")
+}
+
+struct PlanBuilder {
+    instrs: Vec<Instr>,
+    memo: HashMap<Op, usize>,
+}
+
+impl PlanBuilder {
+    fn push(&mut self, op: Op) -> Operand {
+        let call_args = match &op {
+            Op::Call(_, args) => args.iter().map(operand_expr).collect(),
+            _ => Vec::new(),
+        };
+        self.push_with_args(op, call_args, Vec::new())
+    }
+
+    fn push_with_args(&mut self, op: Op, call_args: Vec<Expr>, locals: Vec<String>) -> Operand {
+        if let Some(slot) = self.memo.get(&op) {
+            return Operand::Slot(*slot);
+        }
+        let slot = self.instrs.len();
+        self.instrs.push(Instr {
+            op: op.clone(),
+            call_args,
+            locals,
+        });
+        self.memo.insert(op, slot);
+        Operand::Slot(slot)
+    }
+
+    fn compile_expr(&mut self, expr: &Expr, scope: &HashMap<String, Operand>) -> Operand {
+        match expr {
+            Expr::Number(value) => Operand::Num(value.to_bits()),
+            Expr::Str(text) => Operand::Str(text.clone()),
+            Expr::Ident(name) => scope
+                .get(name)
+                .cloned()
+                .unwrap_or_else(|| Operand::Var(name.clone())),
+            Expr::Call { name, args }
+                if matches!(
+                    ExpressionFunction::parse(name),
+                    Some(ExpressionFunction::Period)
+                ) =>
+            {
+                self.compile_period(name, args, scope)
+            }
+            Expr::Call { name, args }
+                if args.len() == 3
+                    && matches!(
+                        ExpressionFunction::parse(name),
+                        Some(ExpressionFunction::If)
+                    ) =>
+            {
+                self.compile_if(name, args, scope)
+            }
+            Expr::Call { name, args } => {
+                let args = args
+                    .iter()
+                    .map(|arg| self.compile_expr(arg, scope))
+                    .collect();
+                self.push(Op::Call(name.to_ascii_uppercase(), args))
+            }
+            Expr::Unary { op, rhs } => {
+                let rhs = self.compile_expr(rhs, scope);
+                self.push(Op::Unary(op.clone(), rhs))
+            }
+            Expr::Binary { op, lhs, rhs } => {
+                let lhs = self.compile_expr(lhs, scope);
+                let rhs = self.compile_expr(rhs, scope);
+                self.push(Op::Binary(op.clone(), lhs, rhs))
+            }
+        }
+    }
+
+    // PERIOD的参数要在周/月K线上求值, 不能预先算成日线槽位:
+    // 参数原样保留, 引用到的局部变量作为操作数, 执行时按原名绑定
+    fn compile_period(
+        &mut self,
+        name: &str,
+        args: &[Expr],
+        scope: &HashMap<String, Operand>,
+    ) -> Operand {
+        let mut refs = Vec::new();
+        for arg in args {
+            collect_scope_refs(arg, scope, &mut refs);
+        }
+        let locals = refs.into_iter().map(str::to_string).collect::<Vec<_>>();
+        let mut operands = vec![Operand::Str(format!("{args:?} {locals:?}"))];
+        operands.extend(locals.iter().map(|local| scope[local].clone()));
+        self.push_with_args(
+            Op::Call(name.to_ascii_uppercase(), operands),
+            args.to_vec(),
+            locals,
+        )
+    }
+
+    // IF(CASE 展开后也是嵌套 IF)只把条件编成槽位, 两个分支原样保留:
+    // 执行时和逐条求值一样短路, 没有K线选中的分支不求值
+    fn compile_if(
+        &mut self,
+        name: &str,
+        args: &[Expr],
+        scope: &HashMap<String, Operand>,
+    ) -> Operand {
+        let cond = self.compile_expr(&args[0], scope);
+        let mut refs = Vec::new();
+        for arg in &args[1..] {
+            collect_scope_refs(arg, scope, &mut refs);
+        }
+        let locals = refs.into_iter().map(str::to_string).collect::<Vec<_>>();
+        let mut operands = vec![
+            cond.clone(),
+            Operand::Str(format!("{:?} {locals:?}", &args[1..])),
+        ];
+        operands.extend(locals.iter().map(|local| scope[local].clone()));
+        let mut call_args = vec![operand_expr(&cond)];
+        call_args.extend(args[1..].iter().cloned());
+        self.push_with_args(
+            Op::Call(name.to_ascii_uppercase(), operands),
+            call_args,
+            locals,
+        )
+    }
+
+    fn compile_program(&mut self, stmts: &Stmts) -> Operand {
+        // 赋值只在本程序内可见,和规则求值后恢复变量的语义一致
+        let mut scope = HashMap::new();
+        let mut last = Operand::Num(0.0_f64.to_bits());
+        for stmt in &stmts.item {
+            last = match stmt {
+                Stmt::Expr(expr) => self.compile_expr(expr, &scope),
+                Stmt::Assign { name, value } => {
+                    let value = self.compile_expr(value, &scope);
+                    scope.insert(name.clone(), value.clone());
+                    value
+                }
+            };
+        }
+        last
+    }
+}
+
+fn operand_expr(operand: &Operand) -> Expr {
+    match operand {
+        Operand::Num(bits) => Expr::Number(f64::from_bits(*bits)),
+        Operand::Str(text) => Expr::Str(text.clone()),
+        Operand::Var(name) => Expr::Ident(name.clone()),
+        Operand::Slot(slot) => Expr::Ident(slot_var_name(*slot)),
+    }
+}
+
+// 按首次出现的顺序收集表达式里引用到的局部变量
+fn collect_scope_refs<'a>(
+    expr: &'a Expr,
+    scope: &HashMap<String, Operand>,
+    refs: &mut Vec<&'a str>,
+) {
+    match expr {
+        Expr::Ident(name) => {
+            if scope.contains_key(name) && !refs.contains(&name.as_str()) {
+                refs.push(name);
+            }
+        }
+        Expr::Number(_) | Expr::Str(_) => {}
+        Expr::Call { args, .. } => {
+            for arg in args {
+                collect_scope_refs(arg, scope, refs);
+            }
+        }
+        Expr::Unary { rhs, .. } => collect_scope_refs(rhs, scope, refs),
+        Expr::Binary { lhs, rhs, .. } => {
+            collect_scope_refs(lhs, scope, refs);
+            collect_scope_refs(rhs, scope, refs);
+        }
+    }
+}
+
+fn op_operands(op: &Op) -> Vec<&Operand> {
+    match op {
+        Op::Unary(_, src) => vec![src],
+        Op::Binary(_, lhs, rhs) => vec![lhs, rhs],
+        Op::Call(_, args) => args.iter().collect(),
+    }
+}
+
+impl ExprPlan {
+    pub fn compile(programs: &[&Stmts]) -> Self {
+        let mut builder = PlanBuilder {
+            instrs: Vec::new(),
+            memo: HashMap::new(),
+        };
+        let outputs = programs
+            .iter()
+            .map(|stmts| builder.compile_program(stmts))
+            .collect::<Vec<_>>();
+
+        let mut last_use = vec![0; builder.instrs.len()];
+        for (index, instr) in builder.instrs.iter().enumerate() {
+            for operand in op_operands(&instr.op) {
+                if let Operand::Slot(slot) = operand {
+                    last_use[*slot] = index;
+                }
+            }
+        }
+        for output in &outputs {
+            if let Operand::Slot(slot) = output {
+                last_use[*slot] = OUTPUT_USE;
+            }
+        }
+
+        Self {
+            instrs: builder.instrs,
+            outputs,
+            last_use,
+            programs: programs.iter().map(|stmts| (*stmts).clone()).collect(),
+        }
+    }
+
+    pub fn program_count(&self) -> usize {
+        self.outputs.len()
+    }
+
+    pub fn instr_count(&self) -> usize {
+        self.instrs.len()
+    }
+}
+
+fn operand_value<'a>(
+    vars: &'a HashMap<String, Value>,
+    slots: &'a [Option<Value>],
+    operand: &Operand,
+) -> Result<Cow<'a, Value>, EvalErr> {
+    match operand {
+        Operand::Num(bits) => Ok(Cow::Owned(Value::Num(f64::from_bits(*bits)))),
+        Operand::Str(text) => Ok(Cow::Owned(Value::Str(text.clone()))),
+        Operand::Var(name) => vars.get(name).map(Cow::Borrowed).ok_or_else(|| EvalErr {
+            msg: format!("变量不存在:{}", name),
+        }),
+        Operand::Slot(slot) => slots[*slot]
+            .as_ref()
+            .map(Cow::Borrowed)
+            .ok_or_else(|| EvalErr {
+                msg: format!("计划槽位{slot}已释放"),
+            }),
+    }
+}
+
+impl Runtime {
+    // 最后一次使用的槽位直接移交,否则克隆
+    fn take_operand(
+        &self,
+        plan: &ExprPlan,
+        slots: &mut [Option<Value>],
+        operand: &Operand,
+        index: usize,
+    ) -> Result<Value, EvalErr> {
+        if let Operand::Slot(slot) = operand
+            && plan.last_use[*slot] == index
+            && let Some(value) = slots[*slot].take()
+        {
+            return Ok(value);
+        }
+        operand_value(&self.vars, slots, operand).map(Cow::into_owned)
+    }
+
+    // 还要被后续指令读取的中间序列改成共享序列,之后的克隆只增加引用计数
+    fn share_slot(plan: &ExprPlan, slots: &mut [Option<Value>], slot: usize) {
+        if plan.last_use[slot] == OUTPUT_USE {
+            return;
+        }
+        if let Some(Value::NumSeries(series)) = &mut slots[slot] {
+            let series = std::mem::take(series);
+            slots[slot] = Some(Value::SharedNumSeries(Arc::new(series)));
+        }
+    }
+
+    fn exec_call(
+        &mut self,
+        plan: &ExprPlan,
+        slots: &mut [Option<Value>],
+        instr: &Instr,
+        index: usize,
+    ) -> Result<Value, EvalErr> {
+        let Op::Call(name, args) = &instr.op else {
+            unreachable!("exec_call只处理函数指令");
+        };
+        let (args, local_operands) = args.split_at(args.len() - instr.locals.len());
+        // 局部变量按原名读取(PERIOD 重采样规则看名字), 先取齐所有值再临时覆盖
+        let local_values = local_operands
+            .iter()
+            .map(|operand| operand_value(&self.vars, slots, operand).map(Cow::into_owned))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut bound = Vec::new();
+        for arg in args {
+            let Operand::Slot(slot) = arg else {
+                continue;
+            };
+            let key = slot_var_name(*slot);
+            if self.vars.contains_key(&key) {
+                continue;
+            }
+            // 序列转成共享序列,函数实现取参数时只增加引用计数
+            Self::share_slot(plan, slots, *slot);
+            let value = match self.take_operand(plan, slots, arg, index)? {
+                Value::NumSeries(series) => Value::SharedNumSeries(Arc::new(series)),
+                value => value,
+            };
+            self.vars.insert(key.clone(), value);
+            bound.push(key);
+        }
+        let saved = instr
+            .locals
+            .iter()
+            .zip(local_values)
+            .map(|(local, value)| (local.clone(), self.vars.insert(local.clone(), value)))
+            .collect::<Vec<_>>();
+        let result = self.eval_call(name, &instr.call_args);
+        for (local, value) in saved.into_iter().rev() {
+            match value {
+                Some(value) => self.vars.insert(local, value),
+                None => self.vars.remove(&local),
+            };
+        }
+        for key in bound {
+            self.vars.remove(&key);
+        }
+        result
+    }
+
+    fn run_plan(&mut self, plan: &ExprPlan) -> Result<Vec<Value>, EvalErr> {
+        let mut slots: Vec<Option<Value>> = vec![None; plan.instrs.len()];
+
+        for (index, instr) in plan.instrs.iter().enumerate() {
+            let value = match &instr.op {
+                Op::Unary(op, src) => {
+                    let value = self.take_operand(plan, &mut slots, src, index)?;
+                    Self::unary_value(op, value)?
+                }
+                Op::Binary(op, lhs, rhs) => {
+                    let lv = operand_value(&self.vars, &slots, lhs)?;
+                    let rv = operand_value(&self.vars, &slots, rhs)?;
+                    Self::binary_values(op, &lv, &rv)?
+                }
+                Op::Call(..) => self.exec_call(plan, &mut slots, instr, index)?,
+            };
+            slots[index] = Some(value);
+
+            for operand in op_operands(&instr.op) {
+                if let Operand::Slot(slot) = operand
+                    && plan.last_use[*slot] == index
+                {
+                    slots[*slot] = None;
+                }
+            }
+        }
+
+        let mut out = Vec::with_capacity(plan.outputs.len());
+        for (pos, output) in plan.outputs.iter().enumerate() {
+            // 多个程序返回同一个槽位时,只有最后一个直接拿走
+            let shared_later = plan.outputs[pos + 1..].contains(output);
+            let value = match output {
+                Operand::Slot(slot) if !shared_later => {
+                    slots[*slot].take().ok_or_else(|| EvalErr {
+                        msg: format!("计划槽位{slot}已释放"),
+                    })?
+                }
+                _ => operand_value(&self.vars, &slots, output)?.into_owned(),
+            };
+            out.push(value);
+        }
+        Ok(out)
+    }
+
+    // 逐条解释求值,每个程序结束后恢复被赋值覆盖的变量
+    fn eval_programs_isolated(&mut self, programs: &[Stmts]) -> Result<Vec<Value>, EvalErr> {
+        let mut out = Vec::with_capacity(programs.len());
+        for stmts in programs {
+            let snapshots = stmts
+                .item
+                .iter()
+                .filter_map(|stmt| match stmt {
+                    Stmt::Assign { name, .. } => Some((name.clone(), self.vars.get(name).cloned())),
+                    Stmt::Expr(_) => None,
+                })
+                .collect::<Vec<_>>();
+            let result = self.eval_program(stmts);
+            for (name, value) in snapshots.into_iter().rev() {
+                match value {
+                    Some(value) => self.vars.insert(name, value),
+                    None => self.vars.remove(&name),
+                };
+            }
+            out.push(result?);
+        }
+        Ok(out)
+    }
+
+    /// 按计划求值,返回每个程序最后一条语句的值。
+    /// 计划会提前算好参数,出错时报的可能不是逐条解释遇到的第一个错误;
+    /// 需要一致报错的调用方自己回退到逐条求值。
+    pub fn eval_plan(&mut self, plan: &ExprPlan) -> Result<Vec<Value>, EvalErr> {
+        self.run_plan(plan)
+    }
+
+    /// 单个程序的计划求值,返回值和报错都和 eval_program 一致。
+    pub fn eval_plan_single(&mut self, plan: &ExprPlan) -> Result<Value, EvalErr> {
+        let mut values = match self.run_plan(plan) {
+            Ok(values) => values,
+            Err(_) => self.eval_programs_isolated(&plan.programs)?,
+        };
+        Ok(values.pop().unwrap_or(Value::Num(0.0)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use super::ExprPlan;
+    use crate::expr::{
+        eval::{Runtime, Value},
+        parser::Stmts,
+        validation::parse_expression_program,
+    };
+
+    fn sample_runtime() -> Runtime {
+        let close = [
+            Some(10.0),
+            Some(10.4),
+            None,
+            Some(10.2),
+            Some(10.9),
+            Some(11.3),
+            Some(11.0),
+            Some(11.8),
+            Some(12.1),
+            Some(11.7),
+            Some(12.6),
+            Some(12.9),
+        ];
+        let mut vars = HashMap::new();
+        vars.insert("C".to_string(), Value::NumSeries(close.to_vec()));
+        vars.insert(
+            "O".to_string(),
+            Value::NumSeries(close.iter().map(|v| v.map(|v| v - 0.2)).collect()),
+        );
+        vars.insert(
+            "H".to_string(),
+            Value::NumSeries(close.iter().map(|v| v.map(|v| v + 0.5)).collect()),
+        );
+        vars.insert(
+            "L".to_string(),
+            Value::NumSeries(close.iter().map(|v| v.map(|v| v - 0.6)).collect()),
+        );
+        vars.insert(
+            "V".to_string(),
+            Value::NumSeries(
+                (0..close.len())
+                    .map(|i| Some(1000.0 + i as f64 * 37.0))
+                    .collect(),
+            ),
+        );
+        vars.insert("ZHANG".to_string(), Value::Num(0.1));
+        vars.insert("INDUSTRY".to_string(), Value::Str("半导体".to_string()));
+        let trade_dates = [
+            "20240102", "20240103", "20240104", "20240105", "20240108", "20240109", "20240110",
+            "20240111", "20240112", "20240115", "20240116", "20240117",
+        ];
+        Runtime {
+            vars,
+            trade_dates: Arc::new(trade_dates.iter().map(|date| date.to_string()).collect()),
+        }
+    }
+
+    fn parse(expr: &str) -> Stmts {
+        parse_expression_program(expr).expect("parse should succeed")
+    }
+
+    // 差分校验: 计划求值和逐条解释的结果必须完全一致,且不改动 Runtime
+    fn assert_same_as_tree_walker(exprs: &[&str]) {
+        let programs = exprs.iter().map(|expr| parse(expr)).collect::<Vec<_>>();
+        let refs = programs.iter().collect::<Vec<_>>();
+        let plan = ExprPlan::compile(&refs);
+
+        let base = sample_runtime();
+        let mut rt = base.clone();
+        let planned = rt.eval_plan(&plan).expect("plan eval should succeed");
+        assert_eq!(rt.vars, base.vars, "计划求值不应改动变量");
+
+        for (index, program) in programs.iter().enumerate() {
+            let mut tree_rt = base.clone();
+            let expected = tree_rt
+                .eval_program(program)
+                .expect("tree eval should succeed");
+            assert_eq!(
+                planned[index], expected,
+                "表达式 {} 结果不一致",
+                exprs[index]
+            );
+        }
+    }
+
+    #[test]
+    fn plan_matches_tree_walker_for_each_expression() {
+        let cases = [
+            "C > MA(C, 3)",
+            "MA(C, 3) > MA(C, 5) AND V > REF(V, 1)",
+            "CROSS(MA(C, 2), MA(C, 4))",
+            "-C + ABS(O - C) * 2 / (H - L)",
+            "NOT (C > O) OR C == O",
+            "X := (C - L) / (H - L); Y := X * 100; Y > 50",
+            "C := C * 2; MA(C, 3)",
+            "IF(C > O, H, L)",
+            "X := MA(C, 2); IF(C > X, X - C, REF(X, 1))",
+            "IF(C > 0, IF(C > O, H, L), O)",
+            "CASE WHEN C > 11 THEN H WHEN C > 10 THEN L ELSE O END",
+            "COUNT(C > REF(C, 1), 5) >= 2",
+            "HHV(H, 4) - LLV(L, 4)",
+            "EMA(C, 3) - SMA(C, 5, 1)",
+            "BARSLAST(C > O)",
+            "LAST(C, 1)",
+            "LAST(MA(C, 2), 0) > 11",
+            "MAX(C, O) - MIN(H, L)",
+            "SUM(V, 3) / MA(V, 3)",
+            "STD(C, 4)",
+            "RSV(C, H, L, 5)",
+            "COUNTD(C > O, 3, 10)",
+            "HHVD(H, 3, 10)",
+            "REFD(C, 2, 10)",
+            "GET(C > O, C, 5)",
+            "C IN [O, H)",
+            "ZHANG * 100 + 1",
+            "INDUSTRY == '半导体' AND C > 0",
+            "CONTAINS(INDUSTRY, '导') AND STARTSWITH(INDUSTRY, '半')",
+            "1 + 2",
+            "C",
+            "W.MA(C, 2) > W.C AND C > O",
+            "X := H; N := 2; W.X - W.H + W.REF(L, N)",
+            "H := L; W.H",
+            "UP := C > O; W.COUNT(UP, 2) + M.C",
+        ];
+        for case in cases {
+            assert_same_as_tree_walker(&[case]);
+        }
+    }
+
+    #[test]
+    fn plan_matches_tree_walker_across_programs() {
+        assert_same_as_tree_walker(&[
+            "MA(C, 5) > MA(C, 10)",
+            "C := MA(C, 5); C > O",
+            "C > MA(C, 5) AND V > MA(V, 5)",
+            "X := MA(C, 5); X > REF(X, 1)",
+            "C",
+            "C",
+        ]);
+    }
+
+    #[test]
+    fn shared_subexpressions_compile_once() {
+        let programs = [
+            parse("MA(C, 5) > MA(C, 10)"),
+            parse("C > MA(C, 5)"),
+            parse("MA(C, 5) > REF(MA(C, 5), 1)"),
+        ];
+        let refs = programs.iter().collect::<Vec<_>>();
+        let plan = ExprPlan::compile(&refs);
+
+        // MA(C,5) MA(C,10) 比较 / 比较 / REF 比较
+        assert_eq!(plan.program_count(), 3);
+        assert_eq!(plan.instr_count(), 6);
+    }
+
+    #[test]
+    fn assignment_shadowing_keeps_programs_isolated() {
+        let programs = [parse("C := O; MA(C, 3)"), parse("MA(C, 3)")];
+        let refs = programs.iter().collect::<Vec<_>>();
+        let plan = ExprPlan::compile(&refs);
+        assert_eq!(plan.instr_count(), 2);
+
+        let mut rt = sample_runtime();
+        let values = rt.eval_plan(&plan).expect("plan eval");
+        assert_ne!(values[0], values[1]);
+    }
+
+    #[test]
+    fn if_branches_short_circuit_like_tree_walker() {
+        // 没有K线选中的分支不求值, 引用不存在的变量也不报错
+        assert_same_as_tree_walker(&[
+            "IF(1 > 2, MISSING, C)",
+            "CASE WHEN 1 > 2 THEN MISSING WHEN 2 > 1 THEN H ELSE UNKNOWN END",
+            "X := C > 0; IF(X OR 1 > 0, C, MISSING)",
+        ]);
+    }
+
+    #[test]
+    fn plan_errors_match_tree_walker() {
+        let cases = [
+            "MISSING + MA(C, 3)",
+            "MA(C)",
+            "C + '文本'",
+            "MA(NOPE, 2) > UNKNOWN",
+        ];
+        for case in cases {
+            let program = parse(case);
+            let plan = ExprPlan::compile(&[&program]);
+            let plan_err = sample_runtime()
+                .eval_plan_single(&plan)
+                .expect_err("plan eval should fail");
+            let tree_err = sample_runtime()
+                .eval_program(&program)
+                .expect_err("tree eval should fail");
+            assert_eq!(plan_err.msg, tree_err.msg, "表达式 {case} 报错不一致");
+        }
+    }
+}
@@ -4,19 +4,33 @@
 //! "买点方程" are known), but keep AST traversal and warmup semantics here so
 //! every expression entry point accepts the same functions and assignments.
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use super::{
-    eval::is_supported_expression_function,
-    parser::{Expr, ParseErr, Parser, Stmt, Stmts, lex_all},
+    eval::{ExpressionFunction, is_supported_expression_function},
+    func::UserFunctions,
+    parser::{
+        BinaryOp, Expr, ExprSpan, ParseErr, Parser, SpannedStmt, Stmt, Stmts, UnaryOp, lex_all,
+    },
 };
 use crate::utils::utils::{eval_binary_for_warmup, impl_expr_warmup};
 
 /// Parse a complete, possibly multi-statement, expression program.
+///
+/// `FUNC` definitions inside the program are expanded in place; use
+/// [`parse_expression_program_with_functions`] to also resolve a shared prelude.
 pub fn parse_expression_program(expression: &str) -> Result<Stmts, ParseErr> {
     Parser::new(lex_all(expression)).parse_main()
 }
 
+/// Parse an expression program with user functions from a shared prelude.
+pub fn parse_expression_program_with_functions(
+    expression: &str,
+    functions: &UserFunctions,
+) -> Result<Stmts, ParseErr> {
+    Parser::with_functions(lex_all(expression), functions).parse_main()
+}
+
 /// Reject function calls that the evaluator cannot execute.
 ///
 /// The parser deliberately accepts arbitrary call names, so this check must be
@@ -44,7 +58,7 @@ pub fn first_unsupported_expression_function(stmts: &Stmts) -> Option<&str> {
 
 fn first_unsupported_expr_function(expr: &Expr) -> Option<&str> {
     match expr {
-        Expr::Number(_) | Expr::Ident(_) => None,
+        Expr::Number(_) | Expr::Str(_) | Expr::Ident(_) => None,
         Expr::Call { name, args } => {
             if !is_supported_expression_function(name) {
                 return Some(name);
@@ -63,9 +77,498 @@ fn first_unsupported_expr_function(expr: &Expr) -> Option<&str> {
     }
 }
 
+/// Return the functions in a program whose value on a bar depends on later
+/// bars, in call order without duplicates.
+///
+/// `PEAK`/`TROUGH` only report turns after the bar that confirms them, so they
+/// are not listed even though their TDX counterparts look ahead.
+pub fn future_data_functions(stmts: &Stmts) -> Vec<&'static str> {
+    fn walk(expr: &Expr, out: &mut Vec<&'static str>) {
+        match expr {
+            Expr::Call { name, args } => {
+                if let Some(function @ (ExpressionFunction::Zig | ExpressionFunction::Backset)) =
+                    ExpressionFunction::parse(name)
+                    && !out.contains(&function.name())
+                {
+                    out.push(function.name());
+                }
+                args.iter().for_each(|arg| walk(arg, out));
+            }
+            Expr::Unary { rhs, .. } => walk(rhs, out),
+            Expr::Binary { lhs, rhs, .. } => {
+                walk(lhs, out);
+                walk(rhs, out);
+            }
+            Expr::Number(_) | Expr::Str(_) | Expr::Ident(_) => {}
+        }
+    }
+
+    let mut out = Vec::new();
+    for stmt in &stmts.item {
+        match stmt {
+            Stmt::Assign { value: expr, .. } | Stmt::Expr(expr) => walk(expr, &mut out),
+        }
+    }
+    out
+}
+
+/// Warning text for a program that uses [`future_data_functions`], prefixed
+/// with the caller's label (e.g. a rule name).
+pub fn future_data_warning(label: &str, stmts: &Stmts) -> Option<String> {
+    let names = future_data_functions(stmts);
+    if names.is_empty() {
+        return None;
+    }
+    Some(format!(
+        "{label}使用了未来函数{}: 历史K线的值会被之后的行情改写, 回测结果会偏乐观",
+        names.join("、")
+    ))
+}
+
+/// Element type of an expression value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueKind {
+    Num,
+    Bool,
+    Str,
+}
+
+/// Whether a value holds one entry per bar or a single entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueShape {
+    Scalar,
+    Series,
+}
+
+/// Static type inferred for an expression.
+///
+/// `constant` marks scalars known at compile time, which is what window
+/// lengths need for the evaluator and for warmup estimation to agree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExprType {
+    pub kind: ValueKind,
+    pub shape: ValueShape,
+    pub constant: bool,
+}
+
+impl ExprType {
+    pub const NUM_SERIES: Self = Self::series(ValueKind::Num);
+    pub const BOOL_SERIES: Self = Self::series(ValueKind::Bool);
+    pub const STR_SCALAR: Self = Self {
+        kind: ValueKind::Str,
+        shape: ValueShape::Scalar,
+        constant: false,
+    };
+
+    const fn series(kind: ValueKind) -> Self {
+        Self {
+            kind,
+            shape: ValueShape::Series,
+            constant: false,
+        }
+    }
+
+    const fn constant(kind: ValueKind) -> Self {
+        Self {
+            kind,
+            shape: ValueShape::Scalar,
+            constant: true,
+        }
+    }
+
+    // 逐元素运算: 有序列参与就是序列, 全是常量才是常量
+    fn derived(kind: ValueKind, operands: &[ExprType]) -> Self {
+        Self {
+            kind,
+            shape: if operands.iter().any(|ty| ty.shape == ValueShape::Series) {
+                ValueShape::Series
+            } else {
+                ValueShape::Scalar
+            },
+            constant: operands.iter().all(|ty| ty.constant),
+        }
+    }
+
+    fn kind_name(self) -> &'static str {
+        match (self.kind, self.shape) {
+            (ValueKind::Num, ValueShape::Scalar) => "数值",
+            (ValueKind::Num, ValueShape::Series) => "数值序列",
+            (ValueKind::Bool, ValueShape::Scalar) => "布尔值",
+            (ValueKind::Bool, ValueShape::Series) => "布尔序列",
+            (ValueKind::Str, _) => "字符串",
+        }
+    }
+}
+
+/// Variables visible to a program before its first statement.
+///
+/// Identifiers that are neither assigned nor listed in `vars` are data fields
+/// and typed as numeric series. When the caller knows the field list, pass it
+/// with [`ExprTypeEnv::with_fields`] so unknown identifiers are rejected.
+#[derive(Debug, Clone, Default)]
+pub struct ExprTypeEnv {
+    vars: HashMap<String, ExprType>,
+    fields: Option<HashSet<String>>,
+}
+
+impl ExprTypeEnv {
+    pub fn with_var(mut self, name: impl Into<String>, ty: ExprType) -> Self {
+        self.vars.insert(name.into(), ty);
+        self
+    }
+
+    /// Restrict free identifiers to these fields, compared case-insensitively.
+    pub fn with_fields<I, S>(mut self, fields: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.fields.get_or_insert_with(HashSet::new).extend(
+            fields
+                .into_iter()
+                .map(|field| field.as_ref().to_ascii_uppercase()),
+        );
+        self
+    }
+}
+
+/// A static type error and the source range it points at.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExprTypeErr {
+    pub msg: String,
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Infer value types for every statement and reject programs that would fail,
+/// or silently coerce, at runtime.
+///
+/// The program is parsed again so errors point at the original source text,
+/// including arguments of user function calls. Errors inside a function body
+/// are reported on the call.
+pub fn check_expression_types(
+    expression: &str,
+    functions: &UserFunctions,
+    env: &ExprTypeEnv,
+) -> Result<(), ExprTypeErr> {
+    infer_expression_type(expression, functions, env).map(|_| ())
+}
+
+/// Type check a program like [`check_expression_types`] and return the type of
+/// its last statement, or `None` for an empty program.
+pub fn infer_expression_type(
+    expression: &str,
+    functions: &UserFunctions,
+    env: &ExprTypeEnv,
+) -> Result<Option<ExprType>, ExprTypeErr> {
+    let mut parser = Parser::with_functions(lex_all(expression), functions);
+    parser.parse_main().map_err(|error| ExprTypeErr {
+        msg: error.msg,
+        start: error.idx,
+        end: error.idx,
+    })?;
+    let stmts = parser.take_spanned_stmts();
+    let functions = parser.into_functions();
+    TypeChecker {
+        env,
+        functions: &functions,
+        locals: HashMap::new(),
+    }
+    .check(&stmts)
+}
+
+struct TypeChecker<'a> {
+    env: &'a ExprTypeEnv,
+    functions: &'a UserFunctions,
+    locals: HashMap<String, ExprType>,
+}
+
+impl TypeChecker<'_> {
+    fn check(mut self, stmts: &[SpannedStmt]) -> Result<Option<ExprType>, ExprTypeErr> {
+        let mut last = None;
+        for SpannedStmt { stmt, span } in stmts {
+            let range = (span.start, span.end);
+            let ty = match stmt {
+                Stmt::Assign { name, value } => {
+                    let ty = self.infer(value, Some(span), range)?;
+                    self.locals.insert(name.clone(), ty);
+                    ty
+                }
+                Stmt::Expr(expr) => self.infer(expr, Some(span), range)?,
+            };
+            last = Some(ty);
+        }
+        Ok(last)
+    }
+
+    // span 为空时(自定义函数展开后的节点)错误落在 outer 上
+    fn infer(
+        &self,
+        expr: &Expr,
+        span: Option<&ExprSpan>,
+        outer: (usize, usize),
+    ) -> Result<ExprType, ExprTypeErr> {
+        let here = span.map_or(outer, |span| (span.start, span.end));
+        let child = |index: usize| span.and_then(|span| span.child(index));
+        let err = |msg: String| ExprTypeErr {
+            msg,
+            start: here.0,
+            end: here.1,
+        };
+
+        match expr {
+            Expr::Number(_) => Ok(ExprType::constant(ValueKind::Num)),
+            Expr::Str(_) => Ok(ExprType::constant(ValueKind::Str)),
+            Expr::Ident(name) => {
+                if let Some(ty) = self.locals.get(name).or_else(|| self.env.vars.get(name)) {
+                    return Ok(*ty);
+                }
+                match &self.env.fields {
+                    Some(fields) if !fields.contains(&name.to_ascii_uppercase()) => {
+                        Err(err(format!("未定义的变量: {name}")))
+                    }
+                    _ => Ok(ExprType::NUM_SERIES),
+                }
+            }
+            Expr::Unary { op, rhs } => {
+                let rhs = self.infer(rhs, child(0), here)?;
+                if rhs.kind == ValueKind::Str {
+                    return Err(err("字符串不能取负或取反".to_string()));
+                }
+                let kind = match op {
+                    UnaryOp::Neg => ValueKind::Num,
+                    UnaryOp::Not => ValueKind::Bool,
+                };
+                Ok(ExprType::derived(kind, &[rhs]))
+            }
+            Expr::Binary { op, lhs, rhs } => {
+                let lhs = self.infer(lhs, child(0), here)?;
+                let rhs = self.infer(rhs, child(1), here)?;
+                self.binary_type(op, lhs, rhs).map_err(err)
+            }
+            Expr::Call { name, args } => {
+                let arg_types = args
+                    .iter()
+                    .enumerate()
+                    .map(|(index, arg)| self.infer(arg, child(index), here))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let upper = name.trim().to_ascii_uppercase();
+                match ExpressionFunction::parse(&upper) {
+                    Some(function) => {
+                        check_call_args(function, &upper, &arg_types).map_err(|(index, msg)| {
+                            match child(index) {
+                                Some(span) => ExprTypeErr {
+                                    msg,
+                                    start: span.start,
+                                    end: span.end,
+                                },
+                                None => err(msg),
+                            }
+                        })?;
+                        Ok(call_result_type(function, &arg_types))
+                    }
+                    None if self.functions.contains(&upper) => {
+                        let expanded = self.functions.expand_expr(expr.clone()).map_err(err)?;
+                        self.infer(&expanded, None, here)
+                    }
+                    // 未知函数由 validate_expression_functions 报错
+                    None => Ok(ExprType::NUM_SERIES),
+                }
+            }
+        }
+    }
+
+    fn binary_type(&self, op: &BinaryOp, lhs: ExprType, rhs: ExprType) -> Result<ExprType, String> {
+        let has_str = lhs.kind == ValueKind::Str || rhs.kind == ValueKind::Str;
+        match op {
+            BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Div => {
+                if has_str {
+                    return Err("字符串不能参与算术运算".to_string());
+                }
+                Ok(ExprType::derived(ValueKind::Num, &[lhs, rhs]))
+            }
+            BinaryOp::And | BinaryOp::Or => {
+                if has_str {
+                    return Err("字符串不能参与逻辑运算".to_string());
+                }
+                Ok(ExprType::derived(ValueKind::Bool, &[lhs, rhs]))
+            }
+            BinaryOp::Gt
+            | BinaryOp::Ge
+            | BinaryOp::Lt
+            | BinaryOp::Le
+            | BinaryOp::Eq
+            | BinaryOp::Ne => {
+                if has_str {
+                    if lhs.kind != rhs.kind {
+                        return Err("字符串不能和数字或布尔值比较".to_string());
+                    }
+                    if !matches!(op, BinaryOp::Eq | BinaryOp::Ne) {
+                        return Err("字符串只支持 == 和 != 比较".to_string());
+                    }
+                }
+                // 布尔和数值比较时运行期按 0/1 处理, 例如 CROSS(A, B) > 0
+                Ok(ExprType::derived(ValueKind::Bool, &[lhs, rhs]))
+            }
+        }
+    }
+}
+
+// 必须是编译期常量的参数下标: 固定周期、动态周期上限、SAR/ZIG 的比例参数
+fn constant_arg_indexes(function: ExpressionFunction) -> &'static [usize] {
+    use ExpressionFunction as F;
+    match function {
+        F::Hhv
+        | F::Llv
+        | F::Ma
+        | F::Sum
+        | F::Std
+        | F::Count
+        | F::Exist
+        | F::Ref
+        | F::Last
+        | F::Ema
+        | F::Grank
+        | F::Lrank
+        | F::Slope
+        | F::Forcast
+        | F::Wma
+        | F::Avedev
+        | F::Var
+        | F::Filter
+        | F::Hhvbars
+        | F::Llvbars
+        | F::Backset
+        | F::Zig => &[1],
+        F::Sma | F::Peak | F::Trough => &[1, 2],
+        F::Rsv | F::Getd => &[3],
+        F::Gtopcount | F::Ltopcount => &[2, 3],
+        F::Gtopcountd | F::Ltopcountd => &[3, 4],
+        F::Get | F::Corr | F::Covar => &[2],
+        F::Sar => &[0, 1, 2],
+        F::Hhvd
+        | F::Llvd
+        | F::Mad
+        | F::Sumd
+        | F::Stdd
+        | F::Countd
+        | F::Existd
+        | F::Refd
+        | F::Lrankd
+        | F::Grankd => &[2],
+        F::Rsvd => &[4],
+        _ => &[],
+    }
+}
+
+// 参数错误返回出错参数的下标, 便于标出参数位置
+fn check_call_args(
+    function: ExpressionFunction,
+    name: &str,
+    args: &[ExprType],
+) -> Result<(), (usize, String)> {
+    for &index in constant_arg_indexes(function) {
+        let Some(arg) = args.get(index) else {
+            continue;
+        };
+        let position = index + 1;
+        if arg.kind == ValueKind::Str {
+            return Err((
+                index,
+                format!("{name}的第{position}个参数需要数字，不能是字符串"),
+            ));
+        }
+        if arg.shape == ValueShape::Series {
+            let dynamic = format!("{name}D");
+            let hint = if is_supported_expression_function(&dynamic) {
+                format!("，逐K线变化的周期请改用{dynamic}")
+            } else {
+                String::new()
+            };
+            return Err((
+                index,
+                format!(
+                    "{name}的第{position}个参数需要常量，但拿到{}{hint}",
+                    arg.kind_name()
+                ),
+            ));
+        }
+        if !arg.constant {
+            return Err((index, format!("{name}的第{position}个参数需要常量")));
+        }
+    }
+
+    use ExpressionFunction as F;
+    match function {
+        F::Contains | F::Startswith | F::Endswith => {
+            if let Some(index) = args.iter().position(|arg| arg.kind != ValueKind::Str) {
+                return Err((index, format!("{name}的两个参数都必须是字符串")));
+            }
+        }
+        F::Xrank | F::Xpct | F::Xzscore | F::Xmedian => {
+            if let Some(group) = args.get(1)
+                && group.kind != ValueKind::Str
+            {
+                return Err((1, format!("{name}的分组参数必须是字符串")));
+            }
+        }
+        F::Rule => {
+            if let Some(rule) = args.first()
+                && (rule.kind != ValueKind::Str || !rule.constant)
+            {
+                return Err((0, format!("{name}的参数必须是规则名字符串")));
+            }
+        }
+        F::Period => {
+            if let Some(freq) = args.first()
+                && (freq.kind != ValueKind::Str || !freq.constant)
+            {
+                return Err((0, format!("{name}的第1个参数必须是字符串\"W\"或\"M\"")));
+            }
+            if let Some(inner) = args.get(1)
+                && inner.kind == ValueKind::Str
+            {
+                return Err((1, format!("{name}的结果不能是字符串")));
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn call_result_type(function: ExpressionFunction, args: &[ExprType]) -> ExprType {
+    use ExpressionFunction as F;
+    match function {
+        F::Exist | F::Existd | F::Cross | F::Filter | F::Backset | F::Rule => ExprType::BOOL_SERIES,
+        F::Contains | F::Startswith | F::Endswith => ExprType::derived(ValueKind::Bool, args),
+        // LAST 取最后一根, 结果是标量但不是编译期常量
+        F::Last => ExprType {
+            kind: args.first().map_or(ValueKind::Num, |arg| arg.kind),
+            shape: ValueShape::Scalar,
+            constant: false,
+        },
+        F::If | F::Abs | F::Max | F::Min | F::Div => ExprType::derived(ValueKind::Num, args),
+        // 周期结果对齐回日线, 类型跟内部表达式一致
+        F::Period => {
+            let inner = args.get(1..).unwrap_or_default();
+            let kind = inner.first().map_or(ValueKind::Num, |arg| arg.kind);
+            ExprType::derived(kind, inner)
+        }
+        _ => ExprType::NUM_SERIES,
+    }
+}
+
 /// Estimate how many rows before the output range an expression needs.
 pub fn estimate_expression_warmup(stmts: &Stmts) -> Result<usize, String> {
-    let mut locals = HashMap::new();
+    estimate_expression_warmup_with_locals(stmts, HashMap::new())
+}
+
+/// Same as `estimate_expression_warmup`, with variables assigned before the program
+/// (for example referenced rule triggers) already carrying their own warmup.
+pub fn estimate_expression_warmup_with_locals(
+    stmts: &Stmts,
+    mut locals: HashMap<String, usize>,
+) -> Result<usize, String> {
     let mut consts: HashMap<String, usize> = HashMap::new();
     let mut expression_need = 0usize;
 
@@ -109,9 +612,21 @@ pub fn estimate_expression_warmup(stmts: &Stmts) -> Result<usize, String> {
 #[cfg(test)]
 mod tests {
     use super::{
-        estimate_expression_warmup, parse_expression_program, validate_expression_functions,
+        ExprType, ExprTypeEnv, ExprTypeErr, check_expression_types, estimate_expression_warmup,
+        future_data_functions, future_data_warning, parse_expression_program,
+        validate_expression_functions,
     };
-    use crate::expr::parser::{Expr, Stmt, Stmts};
+    use crate::expr::{
+        func::UserFunctions,
+        parser::{Expr, Stmt, Stmts},
+    };
+
+    fn type_err<'a>(expression: &'a str, env: &ExprTypeEnv) -> (String, &'a str) {
+        let ExprTypeErr { msg, start, end } =
+            check_expression_types(expression, &UserFunctions::default(), env)
+                .expect_err("expression should not type check");
+        (msg, &expression[start..end])
+    }
 
     #[test]
     fn estimates_assignments_and_reassignments_consistently() {
@@ -123,6 +638,57 @@ mod tests {
         assert_eq!(estimate_expression_warmup(&program), Ok(5));
     }
 
+    #[test]
+    fn estimates_case_branches_with_the_longest_window() {
+        let program = parse_expression_program(
+            "CASE WHEN C > MA(C, 5) THEN REF(C, 10) WHEN C > 0 THEN HHV(H, 20) ELSE 0 END",
+        )
+        .expect("expression should parse");
+
+        assert_eq!(validate_expression_functions(&program), Ok(()));
+        assert_eq!(estimate_expression_warmup(&program), Ok(19));
+    }
+
+    #[test]
+    fn estimates_tdx_indicator_windows() {
+        let program = parse_expression_program(
+            "A := SLOPE(MA(C, 5), 10); B := CORR(C, REF(V, 3), 20); FILTER(A > 0 AND B > 0.5, 5)",
+        )
+        .expect("expression should parse");
+
+        assert_eq!(validate_expression_functions(&program), Ok(()));
+        assert_eq!(estimate_expression_warmup(&program), Ok(27));
+    }
+
+    #[test]
+    fn estimates_period_windows_in_daily_bars() {
+        let program =
+            parse_expression_program("W.MA(C, 5) > M.REF(C, 1)").expect("expression should parse");
+
+        assert_eq!(validate_expression_functions(&program), Ok(()));
+        // 周线MA5: (4 + 2) * 5, 月线REF1: (1 + 2) * 23
+        assert_eq!(estimate_expression_warmup(&program), Ok(69));
+    }
+
+    #[test]
+    fn warns_about_future_data_functions() {
+        let program = parse_expression_program(
+            "A := ZIG(3, 10); BACKSET(C > A, 2) AND zig(C, 5) > PEAK(3, 10, 1)",
+        )
+        .expect("expression should parse");
+
+        assert_eq!(future_data_functions(&program), vec!["ZIG", "BACKSET"]);
+        assert_eq!(
+            future_data_warning("规则A", &program).as_deref(),
+            Some(
+                "规则A使用了未来函数ZIG、BACKSET: 历史K线的值会被之后的行情改写, 回测结果会偏乐观"
+            )
+        );
+        let program =
+            parse_expression_program("PEAK(3, 10, 1) > C").expect("expression should parse");
+        assert_eq!(future_data_warning("规则B", &program), None);
+    }
+
     #[test]
     fn rejects_unknown_functions_before_runtime() {
         let program = parse_expression_program("UNKNOWN(C, 5) > 0")
@@ -151,4 +717,109 @@ mod tests {
             Err("表达式常量赋值结果不能为负数或非有限值".to_string())
         );
     }
+
+    #[test]
+    fn type_check_accepts_well_typed_programs() {
+        let env = ExprTypeEnv::default().with_var("INDUSTRY", ExprType::STR_SCALAR);
+        let program = "N := 5; UP := C > REF(C, 1); \
+            CASE WHEN INDUSTRY == '银行' THEN COUNT(UP, N) ELSE MAD(C, BARSLAST(UP) + 1, 60) END >= 3 \
+            AND XRANK(C, INDUSTRY) > 0.5 AND IF(UP, 1, 0) == 1";
+        assert_eq!(
+            check_expression_types(program, &UserFunctions::default(), &env),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn type_check_reports_mismatches_with_source_spans() {
+        let env = ExprTypeEnv::default();
+
+        let (msg, text) = type_err("UP := C > O; (UP) > '0.5'", &env);
+        assert_eq!(msg, "字符串不能和数字或布尔值比较");
+        assert_eq!(text, "(UP) > '0.5'");
+
+        let (msg, text) = type_err("C > MA(C, BARSLAST(C > O) + 1)", &env);
+        assert_eq!(
+            msg,
+            "MA的第2个参数需要常量，但拿到数值序列，逐K线变化的周期请改用MAD"
+        );
+        assert_eq!(text, "BARSLAST(C > O) + 1");
+
+        let (msg, text) = type_err("N := LAST(C, 0); HHV(H, N) > 0", &env);
+        assert_eq!(msg, "HHV的第2个参数需要常量");
+        assert_eq!(text, "N");
+    }
+
+    #[test]
+    fn type_check_compares_bools_with_numbers_as_zero_or_one() {
+        let env = ExprTypeEnv::default();
+        for program in [
+            "CROSS(C, MA(C, 5)) > 0",
+            "(C > O) == 1",
+            "W.(C > O) + (C > O) >= 1",
+        ] {
+            assert_eq!(
+                check_expression_types(program, &UserFunctions::default(), &env),
+                Ok(()),
+                "{program}"
+            );
+        }
+    }
+
+    #[test]
+    fn type_check_rejects_undefined_variables_when_fields_are_known() {
+        let env = ExprTypeEnv::default().with_fields(["c", "O"]);
+        let (msg, text) = type_err("MID := (C + O) / 2; C > MIDD", &env);
+        assert_eq!(msg, "未定义的变量: MIDD");
+        assert_eq!(text, "MIDD");
+
+        // 不知道字段列表时, 未赋值的标识符都按行情字段处理
+        assert_eq!(
+            check_expression_types(
+                "C > MIDD",
+                &UserFunctions::default(),
+                &ExprTypeEnv::default()
+            ),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn type_check_follows_period_inner_types() {
+        let env = ExprTypeEnv::default();
+        assert_eq!(
+            check_expression_types(
+                "W.CROSS(MA(C, 5), MA(C, 10)) AND C > REF(M.HHV(H, 3), 1)",
+                &UserFunctions::default(),
+                &env
+            ),
+            Ok(())
+        );
+
+        let (msg, text) = type_err("W.(C > O) == 'W'", &env);
+        assert_eq!(msg, "字符串不能和数字或布尔值比较");
+        assert_eq!(text, "W.(C > O) == 'W'");
+
+        let (msg, text) = type_err("PERIOD(C, MA(C, 5)) > 0", &env);
+        assert_eq!(msg, "PERIOD的第1个参数必须是字符串\"W\"或\"M\"");
+        assert_eq!(text, "C");
+    }
+
+    #[test]
+    fn type_check_points_into_user_function_calls() {
+        let functions =
+            UserFunctions::parse_prelude("FUNC FLAG(X) := X > 0;").expect("prelude should parse");
+        let expression = "A := 1; FLAG(C - O) + FLAG(MA(C, C))";
+        let error = check_expression_types(expression, &functions, &ExprTypeEnv::default())
+            .expect_err("series window should fail");
+        assert_eq!(&expression[error.start..error.end], "C");
+        assert!(error.msg.starts_with("MA的第2个参数需要常量"));
+
+        let error = check_expression_types("FLAG(C) == 'Y'", &functions, &ExprTypeEnv::default())
+            .expect_err("inlined bool should not compare with a string");
+        assert_eq!(
+            (error.start, error.end, error.msg.as_str()),
+            (0, 14, "字符串不能和数字或布尔值比较")
+        );
+    }
 }
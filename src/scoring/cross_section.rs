@@ -0,0 +1,465 @@
+use std::collections::HashMap;
+
+use crate::{
+    data::{RowData, scoring_data::row_into_rt},
+    expr::{
+        cross::{CrossCall, contains_cross_call, cross_section_values, extract_cross_calls},
+        eval::{Runtime, Value},
+        parser::{Stmt, Stmts},
+    },
+    scoring::CachedRule,
+};
+
+// 规则里抽出的截面函数调用, 第一遍逐股算参数, 按日截面后第二遍注入给规则使用
+#[derive(Debug, Clone, Default)]
+pub struct CrossSectionPlan {
+    calls: Vec<CrossCall>,
+}
+
+// 单只股票第一遍的结果: 每个截面调用的参数序列和分组序列,
+// 分组序列每天是该股所属的全部分组(如多个概念), 为空表示当天不参与分组截面
+#[derive(Debug, Clone)]
+pub struct StockCrossInputs {
+    pub ts_code: String,
+    pub trade_dates: Vec<String>,
+    values: Vec<Vec<Option<f64>>>,
+    groups: Vec<Option<Vec<Vec<String>>>>,
+}
+
+// 单只股票的交易日和每个截面变量的序列
+pub type StockCrossSeries = (Vec<String>, Vec<Vec<Option<f64>>>);
+
+type CrossBucketKey<'a> = (&'a str, Option<&'a str>);
+
+// 截面结果, 按股票保存每个截面变量的序列
+#[derive(Debug, Clone, Default)]
+pub struct CrossSectionValues {
+    keys: Vec<String>,
+    by_stock: HashMap<String, StockCrossSeries>,
+}
+
+impl CrossSectionPlan {
+    // 改写规则表达式, 截面调用换成运行时变量; 必须在收集运行时字段之后调用
+    pub fn extract(rules_cache: &mut [CachedRule]) -> Result<Self, String> {
+        let mut calls = Vec::new();
+        for rule in rules_cache.iter_mut() {
+            extract_cross_calls(&mut rule.when_ast, &mut calls)
+                .map_err(|e| format!("规则{}截面函数解析失败:{e}", rule.name))?;
+            if let Some(combination) = &mut rule.combination {
+                for condition in &mut combination.conditions {
+                    extract_cross_calls(&mut condition.expression.when_ast, &mut calls).map_err(
+                        |e| {
+                            format!(
+                                "规则{}条件{}截面函数解析失败:{e}",
+                                rule.name, condition.expression.name
+                            )
+                        },
+                    )?;
+                }
+            }
+        }
+        Ok(Self { calls })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.calls.is_empty()
+    }
+
+    pub fn keys(&self) -> Vec<&str> {
+        self.calls.iter().map(|call| call.key.as_str()).collect()
+    }
+
+    // 第一遍: 在已注入字段的行数据上计算每个截面调用的参数
+    pub fn collect_stock(&self, row: RowData, ts_code: &str) -> Result<StockCrossInputs, String> {
+        let trade_dates = row.trade_dates.clone();
+        let len = trade_dates.len();
+        let mut rt = row_into_rt(row)?;
+        let mut values = Vec::with_capacity(self.calls.len());
+        let mut groups = Vec::with_capacity(self.calls.len());
+
+        for call in &self.calls {
+            let value = eval_stock_program(&mut rt, &call.value, ts_code, call.kind.name())?;
+            values.push(value.into_num_series(len).map_err(|e| e.msg)?);
+
+            let group = match &call.group {
+                Some(group) => {
+                    let group = eval_stock_program(&mut rt, group, ts_code, call.kind.name())?;
+                    if !Value::is_str(&group) {
+                        return Err(format!("{}的分组参数必须是字符串", call.kind.name()));
+                    }
+                    // 空字符串视为没有分组, 不参与分组截面
+                    Some(
+                        (0..len)
+                            .map(|i| {
+                                Value::str_at(&group, i)
+                                    .map(split_group_members)
+                                    .unwrap_or_default()
+                            })
+                            .collect(),
+                    )
+                }
+                None => None,
+            };
+            groups.push(group);
+        }
+
+        Ok(StockCrossInputs {
+            ts_code: ts_code.to_string(),
+            trade_dates,
+            values,
+            groups,
+        })
+    }
+}
+
+// 分组字符串按概念列表的分隔符拆开, 一只股票可同时属于多个组
+// 用到截面函数的规则名, 要在 extract 改写表达式之前取
+pub fn cross_rule_names(rules_cache: &[CachedRule]) -> Vec<String> {
+    rules_cache
+        .iter()
+        .filter(|rule| {
+            stmts_contain_cross_call(&rule.when_ast)
+                || rule.combination.as_ref().is_some_and(|combination| {
+                    combination
+                        .conditions
+                        .iter()
+                        .any(|condition| stmts_contain_cross_call(&condition.expression.when_ast))
+                })
+        })
+        .map(|rule| rule.name.clone())
+        .collect()
+}
+
+fn stmts_contain_cross_call(stmts: &Stmts) -> bool {
+    stmts.item.iter().any(|stmt| match stmt {
+        Stmt::Expr(expr) | Stmt::Assign { value: expr, .. } => contains_cross_call(expr),
+    })
+}
+
+fn split_group_members(text: &str) -> Vec<String> {
+    let mut members = text
+        .split([',', ';', '，', '；', '|', '、', '/'])
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(str::to_string)
+        .collect::<Vec<_>>();
+    members.sort_unstable();
+    members.dedup();
+    members
+}
+
+fn eval_stock_program(
+    rt: &mut Runtime,
+    program: &Stmts,
+    ts_code: &str,
+    fn_name: &str,
+) -> Result<Value, String> {
+    rt.eval_program(program)
+        .map_err(|e| format!("{ts_code}计算{fn_name}参数失败:{}", e.msg))
+}
+
+impl CrossSectionValues {
+    // 按(交易日, 分组)把所有股票的参数放在一起做截面;
+    // 一只股票同时属于多个组时, 在每个组内各算一次, 结果取均值
+    pub fn compute(plan: &CrossSectionPlan, inputs: Vec<StockCrossInputs>) -> Self {
+        let mut outputs: Vec<Vec<Vec<Option<f64>>>> = inputs
+            .iter()
+            .map(|stock| vec![vec![None; stock.trade_dates.len()]; plan.calls.len()])
+            .collect();
+
+        for (call_idx, call) in plan.calls.iter().enumerate() {
+            let mut buckets: HashMap<CrossBucketKey<'_>, Vec<(usize, usize)>> = HashMap::new();
+            for (stock_idx, stock) in inputs.iter().enumerate() {
+                let groups = stock.groups[call_idx].as_ref();
+                for (pos, trade_date) in stock.trade_dates.iter().enumerate() {
+                    match groups {
+                        Some(groups) => {
+                            for group in &groups[pos] {
+                                buckets
+                                    .entry((trade_date.as_str(), Some(group.as_str())))
+                                    .or_default()
+                                    .push((stock_idx, pos));
+                            }
+                        }
+                        None => buckets
+                            .entry((trade_date.as_str(), None))
+                            .or_default()
+                            .push((stock_idx, pos)),
+                    }
+                }
+            }
+
+            let mut sums: Vec<Vec<(f64, usize)>> = inputs
+                .iter()
+                .map(|stock| vec![(0.0, 0); stock.trade_dates.len()])
+                .collect();
+            for members in buckets.values() {
+                let values = members
+                    .iter()
+                    .map(|(stock_idx, pos)| inputs[*stock_idx].values[call_idx][*pos])
+                    .collect::<Vec<_>>();
+                let crossed = cross_section_values(call.kind, &values);
+                for ((stock_idx, pos), value) in members.iter().zip(crossed) {
+                    if let Some(value) = value {
+                        let slot = &mut sums[*stock_idx][*pos];
+                        slot.0 += value;
+                        slot.1 += 1;
+                    }
+                }
+            }
+            for (stock_outputs, stock_sums) in outputs.iter_mut().zip(sums) {
+                for (output, (sum, count)) in stock_outputs[call_idx].iter_mut().zip(stock_sums) {
+                    if count > 0 {
+                        *output = Some(sum / count as f64);
+                    }
+                }
+            }
+        }
+
+        let by_stock = inputs
+            .into_iter()
+            .zip(outputs)
+            .map(|(stock, series)| (stock.ts_code, (stock.trade_dates, series)))
+            .collect();
+        Self {
+            keys: plan.keys().into_iter().map(str::to_string).collect(),
+            by_stock,
+        }
+    }
+
+    // 已按股票算好的序列直接组装, 规则得分标准化复用同一套注入
+    pub fn from_stock_series(
+        keys: Vec<String>,
+        by_stock: HashMap<String, StockCrossSeries>,
+    ) -> Self {
+        Self { keys, by_stock }
+    }
+
+    // 合并另一组截面变量, 对方的序列按本方交易日对齐
+    pub fn merge(&mut self, other: Self) {
+        if other.keys.is_empty() {
+            return;
+        }
+        if self.keys.is_empty() {
+            *self = other;
+            return;
+        }
+
+        let own_key_count = self.keys.len();
+        for (ts_code, (trade_dates, series)) in &mut self.by_stock {
+            match other.by_stock.get(ts_code) {
+                Some((other_dates, other_series)) => {
+                    for values in other_series {
+                        series.push(align_series(other_dates, values, trade_dates));
+                    }
+                }
+                None => {
+                    for _ in &other.keys {
+                        series.push(vec![None; trade_dates.len()]);
+                    }
+                }
+            }
+        }
+        for (ts_code, (trade_dates, other_series)) in other.by_stock {
+            if self.by_stock.contains_key(&ts_code) {
+                continue;
+            }
+            let mut series = vec![vec![None; trade_dates.len()]; own_key_count];
+            series.extend(other_series);
+            self.by_stock.insert(ts_code, (trade_dates, series));
+        }
+        self.keys.extend(other.keys);
+    }
+
+    // 第二遍: 把截面结果按交易日对齐注入行数据, 没有结果的日期为空值
+    pub fn inject(&self, row: &mut RowData, ts_code: &str) {
+        if self.keys.is_empty() {
+            return;
+        }
+        let len = row.trade_dates.len();
+        let Some((trade_dates, series)) = self.by_stock.get(ts_code) else {
+            for key in &self.keys {
+                row.cols.insert(key.clone(), vec![None; len]);
+            }
+            return;
+        };
+
+        if *trade_dates == row.trade_dates {
+            for (key, values) in self.keys.iter().zip(series) {
+                row.cols.insert(key.clone(), values.clone());
+            }
+            return;
+        }
+
+        for (key, values) in self.keys.iter().zip(series) {
+            row.cols.insert(
+                key.clone(),
+                align_series(trade_dates, values, &row.trade_dates),
+            );
+        }
+    }
+}
+
+fn align_series(
+    from_dates: &[String],
+    values: &[Option<f64>],
+    to_dates: &[String],
+) -> Vec<Option<f64>> {
+    if from_dates == to_dates {
+        return values.to_vec();
+    }
+    let date_pos = from_dates
+        .iter()
+        .enumerate()
+        .map(|(pos, date)| (date.as_str(), pos))
+        .collect::<HashMap<_, _>>();
+    to_dates
+        .iter()
+        .map(|date| date_pos.get(date.as_str()).and_then(|pos| values[*pos]))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        data::{RuleTag, ScopeWay, collect_assigned_names_from_expr_program},
+        expr::parser::{Parser, lex_all},
+        scoring::{CachedRulesPlan, scoring_rules_total_cache, weighting::RuleWeighting},
+    };
+
+    fn cached_rule(name: &str, expression: &str) -> CachedRule {
+        let when_ast = Parser::new(lex_all(expression))
+            .parse_main()
+            .expect("expression should parse");
+        let assigned_names = collect_assigned_names_from_expr_program(&when_ast);
+
+        CachedRule {
+            name: name.to_string(),
+            scope_windows: 1,
+            scope_way: ScopeWay::Last,
+            points: 1.0,
+            dist_points: None,
+            max_points: None,
+            tag: RuleTag::Normal,
+            when_src: expression.to_string(),
+            when_ast,
+            assigned_names,
+            combination: None,
+            weighting: RuleWeighting::default(),
+            rule_refs: Vec::new(),
+        }
+    }
+
+    fn stock_row(trade_dates: &[&str], close: &[f64], industry: &str) -> RowData {
+        RowData {
+            trade_dates: trade_dates.iter().map(|date| date.to_string()).collect(),
+            cols: HashMap::from([(
+                "C".to_string(),
+                close.iter().copied().map(Some).collect::<Vec<_>>(),
+            )]),
+            text_cols: HashMap::from([("INDUSTRY".to_string(), industry.to_string())]),
+        }
+    }
+
+    #[test]
+    fn cross_section_values_rank_stocks_per_date_and_group() {
+        let mut rules = vec![
+            cached_rule("top", "XRANK(C) <= 1"),
+            cached_rule("industry_top", "XRANK(C, INDUSTRY) <= 1"),
+        ];
+        let plan = CrossSectionPlan::extract(&mut rules).expect("extract should succeed");
+        assert_eq!(plan.keys(), vec!["__XS0", "__XS1"]);
+
+        let dates = ["20240102", "20240103"];
+        let stocks = [
+            ("000001.SZ", stock_row(&dates, &[10.0, 1.0], "银行")),
+            ("000002.SZ", stock_row(&dates, &[5.0, 8.0], "地产")),
+            ("000003.SZ", stock_row(&dates[1..], &[3.0], "银行")),
+        ];
+        let inputs = stocks
+            .iter()
+            .map(|(ts_code, row)| plan.collect_stock(row.clone(), ts_code))
+            .collect::<Result<Vec<_>, _>>()
+            .expect("collect should succeed");
+        let values = CrossSectionValues::compute(&plan, inputs);
+
+        let rules_plan = CachedRulesPlan::build(&rules);
+        let mut totals = Vec::new();
+        for (ts_code, row) in &stocks {
+            let mut row = row.clone();
+            values.inject(&mut row, ts_code);
+            let mut rt = row_into_rt(row).expect("row should convert");
+            totals.push(
+                scoring_rules_total_cache(&mut rt, &rules, &rules_plan)
+                    .expect("scoring should succeed")
+                    .total,
+            );
+        }
+
+        assert_eq!(totals[0], vec![52.0, 50.0]);
+        assert_eq!(totals[1], vec![51.0, 52.0]);
+        assert_eq!(totals[2], vec![51.0]);
+    }
+
+    #[test]
+    fn cross_rule_names_lists_rules_before_extract_rewrites_them() {
+        let mut rules = vec![
+            cached_rule("plain", "C > 1"),
+            cached_rule("top", "X := XRANK(C); X <= 3"),
+            cached_rule("pct", "XPCT(C, INDUSTRY) > 0.5"),
+        ];
+        assert_eq!(cross_rule_names(&rules), vec!["top", "pct"]);
+
+        CrossSectionPlan::extract(&mut rules).expect("extract should succeed");
+        assert!(cross_rule_names(&rules).is_empty());
+    }
+
+    #[test]
+    fn cross_section_group_ranks_within_each_concept_and_averages() {
+        let mut rules = vec![cached_rule("concept_top", "XRANK(C, CONCEPT) <= 1")];
+        let plan = CrossSectionPlan::extract(&mut rules).expect("extract should succeed");
+
+        let stocks = [
+            ("000001.SZ", 6.0, "芯片，算力"),
+            ("000002.SZ", 5.0, "芯片"),
+            ("000003.SZ", 8.0, "算力"),
+            ("000004.SZ", 9.0, ""),
+        ];
+        let inputs = stocks
+            .iter()
+            .map(|(ts_code, close, concept)| {
+                let row = RowData {
+                    trade_dates: vec!["20240102".to_string()],
+                    cols: HashMap::from([("C".to_string(), vec![Some(*close)])]),
+                    text_cols: HashMap::from([("CONCEPT".to_string(), concept.to_string())]),
+                };
+                plan.collect_stock(row, ts_code)
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .expect("collect should succeed");
+        let values = CrossSectionValues::compute(&plan, inputs);
+
+        let ranks = stocks
+            .iter()
+            .map(|(ts_code, _, _)| {
+                let mut row = stock_row(&["20240102"], &[0.0], "");
+                values.inject(&mut row, ts_code);
+                row.cols.get("__XS0").expect("cross value")[0]
+            })
+            .collect::<Vec<_>>();
+        // 芯片组内排第 1, 算力组内排第 2, 取均值; 无概念的股票不参与
+        assert_eq!(ranks, vec![Some(1.5), Some(2.0), Some(1.0), None]);
+    }
+
+    #[test]
+    fn cross_section_inject_fills_missing_stock_with_empty_series() {
+        let mut rules = vec![cached_rule("top", "XPCT(C) > 50")];
+        let plan = CrossSectionPlan::extract(&mut rules).expect("extract should succeed");
+        let values = CrossSectionValues::compute(&plan, Vec::new());
+
+        let mut row = stock_row(&["20240102"], &[1.0], "银行");
+        values.inject(&mut row, "000001.SZ");
+        assert_eq!(row.cols.get("__XS0"), Some(&vec![None]));
+    }
+}
@@ -0,0 +1,566 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    data::{RuleKind, ScopeWay, ScoreConfig, ScoreRule, stock_expression_type_env},
+    expr::{
+        eval::{is_supported_expression_function, rule_trigger_var},
+        func::UserFunctions,
+        parser::{Expr, Stmt, Stmts},
+        validation::{
+            ExprTypeEnv, check_expression_types, estimate_expression_warmup_with_locals,
+            infer_expression_type, parse_expression_program_with_functions,
+            validate_expression_functions,
+        },
+    },
+};
+
+const RULE_REF_FUNCTION: &str = "RULE";
+
+// 图里的节点: [[define]] 共享定义,或者规则本身(可被 RULE("规则名") 引用)
+struct GraphNode {
+    label: String,
+    deps: Vec<usize>,
+    // 定义节点前置到引用方的语句: 局部变量带上节点前缀,最后一句赋值到节点变量;规则节点为空
+    stmts: Vec<Stmt>,
+}
+
+// 规则节点的窗口信息,RULE 引用的 warmup 用
+struct RuleNode {
+    name: String,
+    scope_way: ScopeWay,
+    scope_windows: usize,
+    programs: Vec<Stmts>,
+}
+
+// 共享定义和规则之间的依赖图
+// 定义的语句按拓扑序拼到引用方的程序前面,计划求值时相同的子表达式每只股票只算一次;
+// 规则不展开: 评分时按引用分层先算被引用的规则,RULE("规则名") 直接读它的触发序列
+pub struct StrategyGraph {
+    nodes: Vec<GraphNode>,
+    // 节点在拓扑序里的位置
+    rank: Vec<usize>,
+    define_nodes: HashMap<String, usize>,
+    rule_nodes: HashMap<String, usize>,
+    // 规则节点按 cfg.rule 的顺序排在定义节点之后
+    rules: Vec<RuleNode>,
+    type_env: ExprTypeEnv,
+}
+
+impl StrategyGraph {
+    pub fn build(cfg: &ScoreConfig, functions: &UserFunctions) -> Result<Self, String> {
+        let mut define_nodes = HashMap::with_capacity(cfg.define.len());
+        for (index, define) in cfg.define.iter().enumerate() {
+            let name = define.name.trim();
+            if !is_define_name(name) {
+                return Err(format!(
+                    "第{}个define的name必须是字母或下划线开头的英文标识符: {name}",
+                    index + 1
+                ));
+            }
+            if is_supported_expression_function(name) || functions.contains(name) {
+                return Err(format!("define名称与函数同名: {name}"));
+            }
+            if define_nodes.insert(name.to_string(), index).is_some() {
+                return Err(format!("define名称重复: {name}"));
+            }
+        }
+        let rule_nodes = cfg
+            .rule
+            .iter()
+            .enumerate()
+            .map(|(index, rule)| (rule.name.trim().to_string(), cfg.define.len() + index))
+            .collect::<HashMap<_, _>>();
+
+        let mut programs = Vec::with_capacity(cfg.define.len() + cfg.rule.len());
+        for define in &cfg.define {
+            let label = format!("定义({})", define.name.trim());
+            let stmts = parse_node_program(&label, &define.expr, functions)?;
+            programs.push((label, vec![stmts]));
+        }
+        for rule in &cfg.rule {
+            let label = format!("规则({})", rule.name.trim());
+            let stmts = rule_expression_sources(rule)
+                .into_iter()
+                .map(|source| parse_node_program(&label, source, functions))
+                .collect::<Result<Vec<_>, _>>()?;
+            programs.push((label, stmts));
+        }
+
+        let mut nodes = Vec::with_capacity(programs.len());
+        for (label, stmts) in &programs {
+            let mut deps = Vec::new();
+            for program in stmts {
+                for dep in collect_node_refs(program, &define_nodes, &rule_nodes)
+                    .map_err(|e| format!("{label}{e}"))?
+                {
+                    if !deps.contains(&dep) {
+                        deps.push(dep);
+                    }
+                }
+            }
+            nodes.push(GraphNode {
+                label: label.clone(),
+                deps,
+                stmts: Vec::new(),
+            });
+        }
+
+        let order = topo_order(&nodes)?;
+        let mut rank = vec![0; nodes.len()];
+        for (position, &index) in order.iter().enumerate() {
+            rank[index] = position;
+        }
+
+        // 按拓扑序推断定义的类型,后面的定义和规则才能按类型检查
+        let mut type_env = stock_expression_type_env();
+        for &index in &order {
+            let Some(define) = cfg.define.get(index) else {
+                continue;
+            };
+            let name = define.name.trim();
+            let ty = infer_expression_type(&define.expr, functions, &type_env)
+                .map_err(|error| {
+                    format!(
+                        "定义({name})类型错误在{}..{}:{}",
+                        error.start, error.end, error.msg
+                    )
+                })?
+                .ok_or_else(|| format!("定义({name})的expr为空"))?;
+            type_env = type_env.with_var(name, ty);
+        }
+
+        let mut rules = Vec::with_capacity(cfg.rule.len());
+        for (index, (_, stmts)) in programs.into_iter().enumerate() {
+            match cfg.define.get(index) {
+                Some(define) => {
+                    let name = define.name.trim();
+                    nodes[index].stmts = node_statements(&stmts[0], name, name);
+                }
+                None => {
+                    let rule = &cfg.rule[index - cfg.define.len()];
+                    rules.push(RuleNode {
+                        name: rule.name.trim().to_string(),
+                        scope_way: rule.scope_way,
+                        scope_windows: rule.scope_windows,
+                        programs: stmts,
+                    });
+                }
+            }
+        }
+
+        Ok(Self {
+            nodes,
+            rank,
+            define_nodes,
+            rule_nodes,
+            rules,
+            type_env,
+        })
+    }
+
+    // 行情字段加上全部共享定义的类型,规则表达式按它做类型检查
+    pub fn type_env(&self) -> &ExprTypeEnv {
+        &self.type_env
+    }
+
+    // 规则和排序键表达式的统一检查入口: 解析、未知函数和类型检查,
+    // 配置校验和规则缓存都走这里,返回的错误由调用方加上表达式名称前缀
+    pub fn check_expression(
+        &self,
+        source: &str,
+        functions: &UserFunctions,
+    ) -> Result<Stmts, String> {
+        let stmts = parse_expression_program_with_functions(source, functions)
+            .map_err(|error| format!("解析错误在{}:{}", error.idx, error.msg))?;
+        validate_expression_functions(&stmts)?;
+        check_expression_types(source, functions, &self.type_env)
+            .map_err(|error| format!("类型错误在{}..{}:{}", error.start, error.end, error.msg))?;
+        Ok(stmts)
+    }
+
+    // 程序直接和经由定义间接依赖的节点,分成按拓扑序排好的定义节点和引用到的规则名
+    fn resolve_refs(&self, stmts: &Stmts) -> Result<(Vec<usize>, Vec<String>), String> {
+        let mut pending = collect_node_refs(stmts, &self.define_nodes, &self.rule_nodes)?;
+        let mut needed = HashSet::new();
+        let mut rule_refs = Vec::new();
+        while let Some(index) = pending.pop() {
+            if let Some(rule) = index
+                .checked_sub(self.define_nodes.len())
+                .map(|rule_index| &self.rules[rule_index])
+            {
+                if !rule_refs.contains(&rule.name) {
+                    rule_refs.push(rule.name.clone());
+                }
+                continue;
+            }
+            if needed.insert(index) {
+                pending.extend(self.nodes[index].deps.iter().copied());
+            }
+        }
+        let mut needed = needed.into_iter().collect::<Vec<_>>();
+        needed.sort_by_key(|index| self.rank[*index]);
+        rule_refs.sort_by_key(|name| self.rank[self.rule_nodes[name]]);
+        Ok((needed, rule_refs))
+    }
+
+    // 在程序前面按拓扑序拼上它直接和间接依赖的定义,RULE("规则名") 保持原样在评分时读取
+    pub fn expand(&self, stmts: &Stmts) -> Result<Stmts, String> {
+        let (needed, _) = self.resolve_refs(stmts)?;
+        let mut item = needed
+            .into_iter()
+            .flat_map(|index| self.nodes[index].stmts.iter().cloned())
+            .collect::<Vec<_>>();
+        item.extend(stmts.item.iter().cloned());
+        Ok(Stmts { item })
+    }
+
+    // 程序通过 RULE("规则名") 引用的规则,包括经由定义引用的,按依赖顺序排列
+    pub fn rule_refs(&self, stmts: &Stmts) -> Result<Vec<String>, String> {
+        Ok(self.resolve_refs(stmts)?.1)
+    }
+
+    // 程序的 warmup: 展开定义后估算,RULE 引用沿用被引用规则自己的 warmup
+    pub fn expression_warmup(&self, stmts: &Stmts) -> Result<usize, String> {
+        let mut locals = HashMap::new();
+        for name in self.rule_refs(stmts)? {
+            let need = self.rule_warmup(&name)?;
+            locals.insert(rule_trigger_var(&name), need);
+        }
+        estimate_expression_warmup_with_locals(&self.expand(stmts)?, locals)
+    }
+
+    fn rule_warmup(&self, name: &str) -> Result<usize, String> {
+        let rule = &self.rules[self.rule_nodes[name] - self.define_nodes.len()];
+        let mut need = 0;
+        for program in &rule.programs {
+            need = need.max(self.expression_warmup(program)?);
+        }
+        Ok(need + scope_warmup(rule.scope_way, rule.scope_windows))
+    }
+}
+
+// scope 回看窗口额外需要的K线数
+pub fn scope_warmup(scope_way: ScopeWay, scope_windows: usize) -> usize {
+    match scope_way {
+        ScopeWay::Last => 0,
+        ScopeWay::Any | ScopeWay::Consec(_) | ScopeWay::Each | ScopeWay::Recent => {
+            scope_windows.saturating_sub(1)
+        }
+    }
+}
+
+fn is_define_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|ch| ch == '_' || ch.is_ascii_alphabetic())
+        && chars.all(|ch| ch == '_' || ch.is_ascii_alphanumeric())
+}
+
+pub(crate) fn rule_expression_sources(rule: &ScoreRule) -> Vec<&str> {
+    match rule.kind {
+        RuleKind::Single => vec![rule.when.as_str()],
+        RuleKind::Combination => rule
+            .conditions
+            .iter()
+            .map(|condition| condition.when.as_str())
+            .collect(),
+    }
+}
+
+fn parse_node_program(
+    label: &str,
+    expression: &str,
+    functions: &UserFunctions,
+) -> Result<Stmts, String> {
+    let stmts = parse_expression_program_with_functions(expression, functions)
+        .map_err(|error| format!("{label}表达式解析错误在{}:{}", error.idx, error.msg))?;
+    validate_expression_functions(&stmts).map_err(|error| format!("{label}{error}"))?;
+    Ok(stmts)
+}
+
+fn rule_ref_name(name: &str, args: &[Expr]) -> Option<Result<String, String>> {
+    if !name.trim().eq_ignore_ascii_case(RULE_REF_FUNCTION) {
+        return None;
+    }
+    Some(match args {
+        [Expr::Str(rule_name)] => Ok(rule_name.trim().to_string()),
+        _ => Err("的RULE参数必须是规则名字符串".to_string()),
+    })
+}
+
+// 程序直接引用的节点: 没被本程序赋值覆盖的定义名,以及 RULE("规则名")
+fn collect_node_refs(
+    stmts: &Stmts,
+    define_nodes: &HashMap<String, usize>,
+    rule_nodes: &HashMap<String, usize>,
+) -> Result<Vec<usize>, String> {
+    fn walk(
+        expr: &Expr,
+        locals: &HashSet<&str>,
+        define_nodes: &HashMap<String, usize>,
+        rule_nodes: &HashMap<String, usize>,
+        out: &mut Vec<usize>,
+    ) -> Result<(), String> {
+        match expr {
+            Expr::Number(_) | Expr::Str(_) => {}
+            Expr::Ident(name) => {
+                if !locals.contains(name.as_str())
+                    && let Some(&index) = define_nodes.get(name)
+                    && !out.contains(&index)
+                {
+                    out.push(index);
+                }
+            }
+            Expr::Call { name, args } => {
+                if let Some(rule_name) = rule_ref_name(name, args) {
+                    let rule_name = rule_name?;
+                    let &index = rule_nodes
+                        .get(&rule_name)
+                        .ok_or_else(|| format!("引用的规则不存在: {rule_name}"))?;
+                    if !out.contains(&index) {
+                        out.push(index);
+                    }
+                    return Ok(());
+                }
+                for arg in args {
+                    walk(arg, locals, define_nodes, rule_nodes, out)?;
+                }
+            }
+            Expr::Unary { rhs, .. } => walk(rhs, locals, define_nodes, rule_nodes, out)?,
+            Expr::Binary { lhs, rhs, .. } => {
+                walk(lhs, locals, define_nodes, rule_nodes, out)?;
+                walk(rhs, locals, define_nodes, rule_nodes, out)?;
+            }
+        }
+        Ok(())
+    }
+
+    let mut locals = HashSet::new();
+    let mut out = Vec::new();
+    for stmt in &stmts.item {
+        match stmt {
+            Stmt::Expr(expr) => walk(expr, &locals, define_nodes, rule_nodes, &mut out)?,
+            Stmt::Assign { name, value } => {
+                walk(value, &locals, define_nodes, rule_nodes, &mut out)?;
+                locals.insert(name.as_str());
+            }
+        }
+    }
+    Ok(out)
+}
+
+// 深度优先求拓扑序,依赖排在前面;回到正在访问的节点就是循环
+fn topo_order(nodes: &[GraphNode]) -> Result<Vec<usize>, String> {
+    fn visit(
+        index: usize,
+        nodes: &[GraphNode],
+        state: &mut [u8],
+        path: &mut Vec<usize>,
+        order: &mut Vec<usize>,
+    ) -> Result<(), String> {
+        match state[index] {
+            2 => return Ok(()),
+            1 => {
+                let start = path.iter().position(|item| *item == index).unwrap_or(0);
+                let chain = path[start..]
+                    .iter()
+                    .chain(std::iter::once(&index))
+                    .map(|item| nodes[*item].label.as_str())
+                    .collect::<Vec<_>>()
+                    .join(" -> ");
+                return Err(format!("规则依赖存在循环: {chain}"));
+            }
+            _ => {}
+        }
+        state[index] = 1;
+        path.push(index);
+        for &dep in &nodes[index].deps {
+            visit(dep, nodes, state, path, order)?;
+        }
+        path.pop();
+        state[index] = 2;
+        order.push(index);
+        Ok(())
+    }
+
+    let mut state = vec![0u8; nodes.len()];
+    let mut order = Vec::with_capacity(nodes.len());
+    for index in 0..nodes.len() {
+        visit(index, nodes, &mut state, &mut Vec::new(), &mut order)?;
+    }
+    Ok(order)
+}
+
+// 局部变量改名成 {prefix}.{name},避免和引用方的变量冲突;最后的值赋给 output
+fn node_statements(stmts: &Stmts, prefix: &str, output: &str) -> Vec<Stmt> {
+    let mut renamed = HashMap::new();
+    let mut out = Vec::with_capacity(stmts.item.len() + 1);
+    let mut last = None;
+    for stmt in &stmts.item {
+        match stmt {
+            Stmt::Expr(expr) => last = Some(rewrite_node_refs(expr, &renamed)),
+            Stmt::Assign { name, value } => {
+                let value = rewrite_node_refs(value, &renamed);
+                let local = format!("{prefix}.{name}");
+                renamed.insert(name.clone(), local.clone());
+                last = Some(Expr::Ident(local.clone()));
+                out.push(Stmt::Assign { name: local, value });
+            }
+        }
+    }
+    out.push(Stmt::Assign {
+        name: output.to_string(),
+        value: last.unwrap_or(Expr::Number(0.0)),
+    });
+    out
+}
+
+fn rewrite_node_refs(expr: &Expr, renamed: &HashMap<String, String>) -> Expr {
+    match expr {
+        Expr::Number(value) => Expr::Number(*value),
+        Expr::Str(text) => Expr::Str(text.clone()),
+        Expr::Ident(name) => Expr::Ident(renamed.get(name).unwrap_or(name).clone()),
+        Expr::Call { name, args } => Expr::Call {
+            name: name.clone(),
+            args: args
+                .iter()
+                .map(|arg| rewrite_node_refs(arg, renamed))
+                .collect(),
+        },
+        Expr::Unary { op, rhs } => Expr::Unary {
+            op: op.clone(),
+            rhs: Box::new(rewrite_node_refs(rhs, renamed)),
+        },
+        Expr::Binary { op, lhs, rhs } => Expr::Binary {
+            op: op.clone(),
+            lhs: Box::new(rewrite_node_refs(lhs, renamed)),
+            rhs: Box::new(rewrite_node_refs(rhs, renamed)),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::StrategyGraph;
+    use crate::{
+        data::ScoreConfig,
+        expr::{
+            eval::{Runtime, Value, rule_trigger_var},
+            func::UserFunctions,
+            validation::parse_expression_program,
+        },
+    };
+
+    fn config(text: &str) -> ScoreConfig {
+        toml::from_str(text).expect("parse config")
+    }
+
+    const SCENE: &str = r#"
+version = 1
+
+[[scene]]
+name = "趋势"
+direction = "long"
+observe_threshold = 1.0
+trigger_threshold = 2.0
+confirm_threshold = 3.0
+fail_threshold = 1.0
+"#;
+
+    #[test]
+    fn expand_prepends_defines_and_leaves_rule_refs_to_the_scorer() {
+        let cfg = config(&format!(
+            r#"{SCENE}
+[[define]]
+name = "TREND_UP"
+expr = "X := MA(C, 3); C > X"
+
+[[define]]
+name = "AFTER_UP"
+expr = "RULE('站上均线') AND V > 0"
+
+[[rule]]
+name = "站上均线"
+scene = "趋势"
+stage = "trigger"
+scope_windows = 2
+scope_way = "ANY"
+points = 2.0
+explain = "站上均线"
+when = "TREND_UP"
+
+[[rule]]
+name = "均线后放量"
+scene = "趋势"
+stage = "confirm"
+scope_windows = 1
+scope_way = "LAST"
+points = 1.0
+explain = "站上均线后放量"
+when = "X := V > REF(V, 1); AFTER_UP AND X"
+"#
+        ));
+        let graph = StrategyGraph::build(&cfg, &UserFunctions::default()).expect("build graph");
+        let stmts = parse_expression_program(&cfg.rule[1].when).expect("parse rule");
+
+        // 经由定义引用的规则也算在内
+        assert_eq!(graph.rule_refs(&stmts), Ok(vec!["站上均线".to_string()]));
+        // 均线3根 + ANY窗口2根 与 REF(V, 1) 取大
+        assert_eq!(graph.expression_warmup(&stmts), Ok(3));
+
+        let program = graph.expand(&stmts).expect("expand rule");
+        let mut rt = Runtime::default();
+        rt.vars.insert(
+            "V".to_string(),
+            Value::NumSeries([1.0, 1.0, 1.0, 1.0, 2.0, 1.0].map(Some).to_vec()),
+        );
+        // 被引用规则的触发序列由评分按依赖顺序先写进运行时
+        rt.vars.insert(
+            rule_trigger_var("站上均线"),
+            Value::BoolSeries(vec![false, false, false, true, true, false]),
+        );
+        let value = rt.eval_program(&program).expect("eval expanded rule");
+        assert_eq!(
+            value,
+            Value::BoolSeries(vec![false, false, false, false, true, false])
+        );
+        // 被引用规则自己的定义不再拼进来
+        assert!(rt.vars.contains_key("AFTER_UP"));
+        assert!(!rt.vars.contains_key("TREND_UP.X"));
+    }
+
+    #[test]
+    fn build_rejects_cycles_and_unknown_rule_refs() {
+        let rule = |name: &str, when: &str| {
+            format!(
+                r#"
+[[rule]]
+name = "{name}"
+scene = "趋势"
+stage = "trigger"
+scope_windows = 1
+scope_way = "LAST"
+points = 1.0
+explain = "{name}"
+when = "{when}"
+"#
+            )
+        };
+
+        let cfg = config(&format!(
+            "{SCENE}\n[[define]]\nname = \"A\"\nexpr = \"RULE('r1') AND C > O\"\n{}",
+            rule("r1", "A AND V > 0")
+        ));
+        let error = StrategyGraph::build(&cfg, &UserFunctions::default())
+            .err()
+            .expect("cycle should fail");
+        assert_eq!(error, "规则依赖存在循环: 定义(A) -> 规则(r1) -> 定义(A)");
+
+        let cfg = config(&format!("{SCENE}{}", rule("r1", "RULE('r2')")));
+        let error = StrategyGraph::build(&cfg, &UserFunctions::default())
+            .err()
+            .expect("unknown rule should fail");
+        assert_eq!(error, "规则(r1)引用的规则不存在: r2");
+    }
+}
@@ -0,0 +1,334 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    data::ScoreScene,
+    scoring::{CachedRule, RuleSceneMeta},
+};
+
+// 单条规则编译后的指纹,规则改名、改场景、改表达式或改分值都会变
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuleFingerprint {
+    pub rule_name: String,
+    pub scene_name: String,
+    pub fingerprint: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SceneFingerprint {
+    pub scene_name: String,
+    pub fingerprint: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct StrategyFingerprint {
+    pub rules: Vec<RuleFingerprint>,
+    pub scenes: Vec<SceneFingerprint>,
+}
+
+// 结果库里记录的上次评分状态
+#[derive(Debug, Clone, Default)]
+pub struct ScoringState {
+    pub strategy: StrategyFingerprint,
+    pub stock_last_dates: HashMap<String, String>,
+    pub scored_range: Option<(String, String)>,
+}
+
+// 策略变化后需要整段重写的规则和场景
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RuleRefreshPlan {
+    // 明细要删掉重写的规则: 定义变了、新增的和已删除的
+    pub rule_names: Vec<String>,
+    // 场景明细要删掉重写的场景
+    pub scene_names: Vec<String>,
+    // 需要重新求值的规则下标: 变化的规则和受影响场景里的全部规则
+    pub eval_rule_indices: Vec<usize>,
+}
+
+impl RuleRefreshPlan {
+    pub fn is_empty(&self) -> bool {
+        self.rule_names.is_empty() && self.scene_names.is_empty()
+    }
+}
+
+// 指纹要存库跨版本比较, 用固定参数的 FNV-1a 64, 不用随 Rust 版本可能变化的 DefaultHasher
+fn hash_to_hex(text: &str) -> String {
+    const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+    let hash = text.bytes().fold(FNV_OFFSET_BASIS, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(FNV_PRIME)
+    });
+    format!("{hash:016x}")
+}
+
+// 策略文件原文的哈希,用来标识评分版本
+pub fn strategy_text_hash(text: &str) -> String {
+    hash_to_hex(text)
+}
+
+fn rule_fingerprint_source(rule: &CachedRule, meta: &RuleSceneMeta) -> String {
+    let mut parts = vec![
+        format!("scene={}", meta.scene_name),
+        format!("stage={:?}", meta.stage),
+        format!("scope={}:{:?}", rule.scope_windows, rule.scope_way),
+        format!("points={:?}", rule.points),
+        format!("dist_points={:?}", rule.dist_points),
+        format!("max_points={:?}", rule.max_points),
+        format!("tag={:?}", rule.tag),
+        format!("weighting={:?}", rule.weighting),
+    ];
+    // 用展开自定义函数后的程序文本,改了公共 FUNC 也能识别出来;
+    // 文本由 Display 还原,不受 Debug 输出格式变化影响
+    for program in rule.expression_programs() {
+        parts.push(format!("when={program}"));
+    }
+    if let Some(combination) = &rule.combination {
+        parts.push(format!(
+            "combination={:?}:{:?}:{:?}",
+            combination.points_by_hits, combination.max_points, combination.max_bonus_points
+        ));
+        for condition in &combination.conditions {
+            parts.push(format!("bonus={:?}", condition.bonus_points));
+        }
+    }
+    // RULE 引用读的是被引用规则的触发序列,它的表达式和窗口变了引用方也要重算
+    for rule_ref in &rule.rule_refs {
+        parts.push(format!(
+            "rule_ref={}:{}",
+            rule_ref.name,
+            rule_trigger_source(rule_ref)
+        ));
+    }
+    parts.join("\n")
+}
+
+fn rule_trigger_source(rule: &CachedRule) -> String {
+    let mut parts = vec![format!("scope={}:{:?}", rule.scope_windows, rule.scope_way)];
+    for program in rule.expression_programs() {
+        parts.push(format!("when={program}"));
+    }
+    if let Some(combination) = &rule.combination {
+        parts.push(format!("points_by_hits={:?}", combination.points_by_hits));
+    }
+    for rule_ref in &rule.rule_refs {
+        parts.push(format!(
+            "rule_ref={}:{}",
+            rule_ref.name,
+            rule_trigger_source(rule_ref)
+        ));
+    }
+    parts.join("\n")
+}
+
+pub fn strategy_fingerprint(
+    rules_cache: &[CachedRule],
+    rule_scene_meta: &[RuleSceneMeta],
+    scenes: &[ScoreScene],
+) -> StrategyFingerprint {
+    let rules = rules_cache
+        .iter()
+        .zip(rule_scene_meta)
+        .map(|(rule, meta)| RuleFingerprint {
+            rule_name: rule.name.clone(),
+            scene_name: meta.scene_name.clone(),
+            fingerprint: hash_to_hex(&rule_fingerprint_source(rule, meta)),
+        })
+        .collect();
+    let scenes = scenes
+        .iter()
+        .map(|scene| SceneFingerprint {
+            scene_name: scene.name.clone(),
+            fingerprint: hash_to_hex(&format!(
+                "{:?}:{:?}:{:?}:{:?}:{:?}",
+                scene.direction,
+                scene.observe_threshold,
+                scene.trigger_threshold,
+                scene.confirm_threshold,
+                scene.fail_threshold
+            )),
+        })
+        .collect();
+    StrategyFingerprint { rules, scenes }
+}
+
+// 对比上次记录的指纹,找出要重写的规则和场景;旧指纹为空视为全部变化
+pub fn plan_rule_refresh(
+    current: &StrategyFingerprint,
+    stored: &StrategyFingerprint,
+) -> RuleRefreshPlan {
+    let stored_rules = stored
+        .rules
+        .iter()
+        .map(|rule| (rule.rule_name.as_str(), rule))
+        .collect::<HashMap<_, _>>();
+    let stored_scenes = stored
+        .scenes
+        .iter()
+        .map(|scene| (scene.scene_name.as_str(), scene.fingerprint.as_str()))
+        .collect::<HashMap<_, _>>();
+    let current_rule_names = current
+        .rules
+        .iter()
+        .map(|rule| rule.rule_name.as_str())
+        .collect::<HashSet<_>>();
+    let current_scene_names = current
+        .scenes
+        .iter()
+        .map(|scene| scene.scene_name.as_str())
+        .collect::<HashSet<_>>();
+
+    let mut rule_names = Vec::new();
+    let mut scene_names = HashSet::new();
+    for rule in &current.rules {
+        let unchanged = stored_rules
+            .get(rule.rule_name.as_str())
+            .is_some_and(|old| old.fingerprint == rule.fingerprint);
+        if unchanged {
+            continue;
+        }
+        rule_names.push(rule.rule_name.clone());
+        scene_names.insert(rule.scene_name.clone());
+        if let Some(old) = stored_rules.get(rule.rule_name.as_str()) {
+            scene_names.insert(old.scene_name.clone());
+        }
+    }
+    for old in &stored.rules {
+        if !current_rule_names.contains(old.rule_name.as_str()) {
+            rule_names.push(old.rule_name.clone());
+            scene_names.insert(old.scene_name.clone());
+        }
+    }
+    for scene in &current.scenes {
+        if stored_scenes.get(scene.scene_name.as_str()) != Some(&scene.fingerprint.as_str()) {
+            scene_names.insert(scene.scene_name.clone());
+        }
+    }
+    for old in &stored.scenes {
+        if !current_scene_names.contains(old.scene_name.as_str()) {
+            scene_names.insert(old.scene_name.clone());
+        }
+    }
+    scene_names.remove("");
+
+    let changed_rules = rule_names.iter().cloned().collect::<HashSet<_>>();
+    let eval_rule_indices = current
+        .rules
+        .iter()
+        .enumerate()
+        .filter(|(_, rule)| {
+            changed_rules.contains(&rule.rule_name) || scene_names.contains(&rule.scene_name)
+        })
+        .map(|(index, _)| index)
+        .collect();
+    let mut scene_names = scene_names.into_iter().collect::<Vec<_>>();
+    scene_names.sort();
+
+    RuleRefreshPlan {
+        rule_names,
+        scene_names,
+        eval_rule_indices,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(rule_name: &str, scene_name: &str, fingerprint: &str) -> RuleFingerprint {
+        RuleFingerprint {
+            rule_name: rule_name.to_string(),
+            scene_name: scene_name.to_string(),
+            fingerprint: fingerprint.to_string(),
+        }
+    }
+
+    fn scene(scene_name: &str, fingerprint: &str) -> SceneFingerprint {
+        SceneFingerprint {
+            scene_name: scene_name.to_string(),
+            fingerprint: fingerprint.to_string(),
+        }
+    }
+
+    #[test]
+    fn strategy_hash_is_stable_fnv1a() {
+        // FNV-1a 64 的公开测试向量, 换编译器版本也不能变
+        assert_eq!(strategy_text_hash(""), "cbf29ce484222325");
+        assert_eq!(strategy_text_hash("a"), "af63dc4c8601ec8c");
+        assert_eq!(strategy_text_hash("foobar"), "85944171f73967e8");
+    }
+
+    #[test]
+    fn unchanged_strategy_needs_no_refresh() {
+        let strategy = StrategyFingerprint {
+            rules: vec![rule("r1", "s1", "a"), rule("r2", "s2", "b")],
+            scenes: vec![scene("s1", "x"), scene("s2", "y")],
+        };
+
+        assert!(plan_rule_refresh(&strategy, &strategy.clone()).is_empty());
+    }
+
+    #[test]
+    fn changed_rule_refreshes_its_scene_and_scene_siblings() {
+        let stored = StrategyFingerprint {
+            rules: vec![
+                rule("r1", "s1", "a"),
+                rule("r2", "s1", "b"),
+                rule("r3", "s2", "c"),
+                rule("gone", "s2", "d"),
+            ],
+            scenes: vec![scene("s1", "x"), scene("s2", "y")],
+        };
+        let current = StrategyFingerprint {
+            rules: vec![
+                rule("r1", "s1", "a2"),
+                rule("r2", "s1", "b"),
+                rule("r3", "s2", "c"),
+            ],
+            scenes: vec![scene("s1", "x"), scene("s2", "y")],
+        };
+
+        let plan = plan_rule_refresh(&current, &stored);
+
+        assert_eq!(plan.rule_names, vec!["r1".to_string(), "gone".to_string()]);
+        assert_eq!(plan.scene_names, vec!["s1".to_string(), "s2".to_string()]);
+        assert_eq!(plan.eval_rule_indices, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn scene_threshold_change_only_reevaluates_that_scene() {
+        let stored = StrategyFingerprint {
+            rules: vec![rule("r1", "s1", "a"), rule("r2", "s2", "b")],
+            scenes: vec![scene("s1", "x"), scene("s2", "y")],
+        };
+        let current = StrategyFingerprint {
+            rules: stored.rules.clone(),
+            scenes: vec![scene("s1", "x"), scene("s2", "y2")],
+        };
+
+        let plan = plan_rule_refresh(&current, &stored);
+
+        assert!(plan.rule_names.is_empty());
+        assert_eq!(plan.scene_names, vec!["s2".to_string()]);
+        assert_eq!(plan.eval_rule_indices, vec![1]);
+    }
+
+    #[test]
+    fn missing_stored_fingerprint_refreshes_everything() {
+        let current = StrategyFingerprint {
+            rules: vec![rule("r1", "s1", "a")],
+            scenes: vec![scene("s1", "x")],
+        };
+        let stored = StrategyFingerprint {
+            rules: vec![rule("r1", "", ""), rule("old", "", "")],
+            scenes: vec![scene("old_scene", "")],
+        };
+
+        let plan = plan_rule_refresh(&current, &stored);
+
+        assert_eq!(plan.rule_names, vec!["r1".to_string(), "old".to_string()]);
+        assert_eq!(
+            plan.scene_names,
+            vec!["old_scene".to_string(), "s1".to_string()]
+        );
+        assert_eq!(plan.eval_rule_indices, vec![0]);
+    }
+}
@@ -1,18 +1,35 @@
-use std::{collections::HashMap, time::Instant};
+use std::{
+    collections::{BTreeMap, HashMap},
+    ops::Range,
+    time::Instant,
+};
 
 use duckdb::Connection;
 
 use crate::{
-    data::{DistPoint, RuleStage, RuleTag, SceneDirection, ScopeWay, ScoreScene},
+    data::{
+        DistPoint, RuleStage, RuleTag, SceneDirection, ScopeWay, ScoreScene,
+        scoring_data::load_rank_order,
+    },
     expr::{
-        eval::{Runtime, Value},
+        eval::{Runtime, Value, rule_trigger_var},
         parser::Stmts,
+        plan::ExprPlan,
+    },
+    scoring::{
+        rank_order::{RankKeySpec, net_score_sql, rank_key_joins_sql, rank_order_by_sql},
+        tools::rt_max_len,
+        weighting::RuleWeighting,
     },
-    scoring::tools::rt_max_len,
 };
 
+pub mod cross_section;
+pub mod graph;
+pub mod incremental;
+pub mod rank_order;
 pub mod runner;
 pub mod tools;
+pub mod weighting;
 
 enum ScopeHit {
     Bool(bool),
@@ -24,6 +41,8 @@ enum ScopeHit {
 pub enum TieBreakWay {
     TsCode,
     KdjJ,
+    // 按结果库 score_rank_order 里记录的策略排序键
+    RankOrder,
 }
 
 #[derive(Debug, Default, Clone)]
@@ -53,7 +72,10 @@ fn log_rank_tiebreak_profile(profile: &RankTiebreakProfile) {
 #[derive(Debug, Default)]
 pub struct RuleScoreSeries {
     pub name: String,
+    // 计入总分的贡献,已按 weighting 标准化和加权
     pub series: Vec<f64>,
+    // points 原始分,场景阶段按它和阈值比较
+    pub raw_series: Vec<f64>,
     pub triggered: Vec<bool>,
 }
 
@@ -69,6 +91,30 @@ pub struct SceneScoreSeries {
     pub triggered: Vec<bool>,
 }
 
+impl SceneScoreSeries {
+    // 只留下 at 之后的部分,和落库的交易日对齐
+    pub fn split_off(&mut self, at: usize) {
+        self.stage = self.stage.split_off(at);
+        self.stage_score = self.stage_score.split_off(at);
+        self.risk_score = self.risk_score.split_off(at);
+        self.confirm_strength = self.confirm_strength.split_off(at);
+        self.risk_intensity = self.risk_intensity.split_off(at);
+        self.triggered = self.triggered.split_off(at);
+    }
+}
+
+// 场景阶段发生变化的那根K线;from_stage 为空表示前一根不在任何阶段
+#[derive(Debug, Clone, PartialEq)]
+pub struct SceneTransitionPoint {
+    pub index: usize,
+    pub from_stage: Option<String>,
+    pub to_stage: String,
+    // 前一个阶段连续持续的K线数
+    pub prev_stage_days: usize,
+    // 当根触发且和新阶段同类的规则
+    pub rule_names: Vec<String>,
+}
+
 const SCENE_EPS: f64 = 1e-12;
 
 #[derive(Debug, Clone)]
@@ -90,6 +136,9 @@ pub struct CachedRule {
     pub when_ast: Stmts,
     pub assigned_names: Vec<String>,
     pub combination: Option<CachedCombinationRule>,
+    pub weighting: RuleWeighting,
+    // RULE("规则名") 直接引用的规则(含经由定义引用的),单独求值时先算出它们的触发序列
+    pub rule_refs: Vec<CachedRule>,
 }
 
 #[derive(Clone)]
@@ -126,6 +175,116 @@ impl CachedRule {
             .map(|condition| &condition.expression.when_ast)
             .collect()
     }
+
+    // 连同 RULE 引用到的规则的表达式,统计运行时要准备的字段时用
+    pub fn programs_with_refs(&self) -> Vec<&Stmts> {
+        let mut programs = self.expression_programs();
+        for rule_ref in &self.rule_refs {
+            programs.extend(rule_ref.programs_with_refs());
+        }
+        programs
+    }
+}
+
+// 整套规则按 RULE 引用分层编译成计划: 同一层的规则互不引用,
+// 层内跨规则的公共子表达式每只股票只算一次
+pub struct CachedRulesPlan {
+    layers: Vec<CachedRulesPlanLayer>,
+    // 引用了但不在这批里的规则(增量刷新、截面标准化只算部分规则时),求值前先单独算出触发序列
+    outside_refs: Vec<CachedRule>,
+}
+
+struct CachedRulesPlanLayer {
+    plan: ExprPlan,
+    // 本层规则在 rules_cache 里的下标
+    rules: Vec<usize>,
+    // 每条规则在计划输出里对应的区间,组合规则每个条件占一个
+    spans: Vec<Range<usize>>,
+}
+
+// 规则所在的层: 引用的同批规则都排在更早的层,不在这批里的引用单独求值,依赖图已保证没有循环
+fn rule_layer(
+    index: usize,
+    rules_cache: &[CachedRule],
+    positions: &HashMap<&str, usize>,
+    layers: &mut [Option<usize>],
+) -> usize {
+    if let Some(layer) = layers[index] {
+        return layer;
+    }
+    let mut layer = 0;
+    for rule_ref in &rules_cache[index].rule_refs {
+        if let Some(&ref_index) = positions.get(rule_ref.name.trim()) {
+            layer = layer.max(rule_layer(ref_index, rules_cache, positions, layers) + 1);
+        }
+    }
+    layers[index] = Some(layer);
+    layer
+}
+
+impl CachedRulesPlan {
+    pub fn build(rules_cache: &[CachedRule]) -> Self {
+        let positions = rules_cache
+            .iter()
+            .enumerate()
+            .map(|(index, rule)| (rule.name.trim(), index))
+            .collect::<HashMap<_, _>>();
+        let mut outside_refs: Vec<CachedRule> = Vec::new();
+        for rule_ref in rules_cache.iter().flat_map(|rule| &rule.rule_refs) {
+            if !positions.contains_key(rule_ref.name.trim())
+                && !outside_refs.iter().any(|other| other.name == rule_ref.name)
+            {
+                outside_refs.push(rule_ref.clone());
+            }
+        }
+        let mut layer_of = vec![None; rules_cache.len()];
+        let mut grouped = BTreeMap::<usize, Vec<usize>>::new();
+        for index in 0..rules_cache.len() {
+            let layer = rule_layer(index, rules_cache, &positions, &mut layer_of);
+            grouped.entry(layer).or_default().push(index);
+        }
+
+        let layers = grouped
+            .into_values()
+            .map(|rules| {
+                let mut programs = Vec::new();
+                let mut spans = Vec::with_capacity(rules.len());
+                for &index in &rules {
+                    let start = programs.len();
+                    programs.extend(rules_cache[index].expression_programs());
+                    spans.push(start..programs.len());
+                }
+                CachedRulesPlanLayer {
+                    plan: ExprPlan::compile(&programs),
+                    rules,
+                    spans,
+                }
+            })
+            .collect();
+        Self {
+            layers,
+            outside_refs,
+        }
+    }
+}
+
+impl CachedRulesPlanLayer {
+    // 任何一步出错都返回None,由调用方逐条规则重算拿到原有的报错
+    fn rule_hits(&self, rt: &mut Runtime) -> Option<Vec<Vec<Vec<bool>>>> {
+        let values = rt.eval_plan(&self.plan).ok()?;
+        let len = rt_max_len(rt);
+        let mut hits = values
+            .iter()
+            .map(|value| Value::as_bool_series(value, len).ok())
+            .collect::<Option<Vec<_>>>()?
+            .into_iter();
+        Some(
+            self.spans
+                .iter()
+                .map(|span| hits.by_ref().take(span.len()).collect())
+                .collect(),
+        )
+    }
 }
 
 #[derive(Clone)]
@@ -329,14 +488,9 @@ fn finish_combination_score(
 fn scoring_combination_rule_cache(
     rule: &CachedRule,
     combination: &CachedCombinationRule,
-    rt: &mut Runtime,
+    condition_hits: &[Vec<bool>],
+    len: usize,
 ) -> Result<(Vec<f64>, Vec<bool>), String> {
-    let condition_hits = combination
-        .conditions
-        .iter()
-        .map(|condition| hit_cached_expression(&condition.expression, rt))
-        .collect::<Result<Vec<_>, _>>()?;
-    let len = rt_max_len(rt);
     let mut scores = Vec::with_capacity(len);
     let mut triggered = Vec::with_capacity(len);
 
@@ -401,18 +555,32 @@ fn scoring_combination_rule_cache(
     Ok((scores, triggered))
 }
 
-fn scoring_rule_cache(
-    rule: &CachedRule,
-    rt: &mut Runtime,
-) -> Result<(Vec<f64>, Vec<bool>), String> {
+// 逐条解释求值规则的命中序列,组合规则每个条件一条
+fn rule_hits_cache(rule: &CachedRule, rt: &mut Runtime) -> Result<Vec<Vec<bool>>, String> {
     if let Some(combination) = &rule.combination {
-        return scoring_combination_rule_cache(rule, combination, rt);
+        return combination
+            .conditions
+            .iter()
+            .map(|condition| hit_cached_expression(&condition.expression, rt))
+            .collect();
     }
 
     let snapshots = snapshot_runtime_values(rt, &rule.assigned_names);
     let bs_result = hit_when_cache(rule, rt);
     restore_runtime_values(rt, &snapshots);
-    let bs = bs_result?;
+    Ok(vec![bs_result?])
+}
+
+fn scoring_rule_hits(
+    rule: &CachedRule,
+    mut hits: Vec<Vec<bool>>,
+    len: usize,
+) -> Result<(Vec<f64>, Vec<bool>), String> {
+    if let Some(combination) = &rule.combination {
+        return scoring_combination_rule_cache(rule, combination, &hits, len);
+    }
+
+    let bs = hits.pop().unwrap_or_default();
     let mut out = Vec::with_capacity(bs.len());
     let mut triggered = Vec::with_capacity(bs.len());
 
@@ -426,6 +594,59 @@ fn scoring_rule_cache(
     Ok((out, triggered))
 }
 
+// 把被引用规则的触发序列写进运行时供 RULE("规则名") 读取,已经写过的不重算;
+// 写入的变量名记在 published 里,求值结束后由调用方移除
+fn publish_rule_refs(
+    rule_refs: &[CachedRule],
+    rt: &mut Runtime,
+    published: &mut Vec<String>,
+) -> Result<(), String> {
+    for rule in rule_refs {
+        let var = rule_trigger_var(&rule.name);
+        if rt.vars.contains_key(&var) {
+            continue;
+        }
+        publish_rule_refs(&rule.rule_refs, rt, published)?;
+        let hits = rule_hits_cache(rule, rt)?;
+        let (_, triggered) = scoring_rule_hits(rule, hits, rt_max_len(rt))?;
+        rt.vars.insert(var.clone(), Value::BoolSeries(triggered));
+        published.push(var);
+    }
+    Ok(())
+}
+
+fn unpublish_rule_refs(rt: &mut Runtime, published: &[String]) {
+    for var in published {
+        rt.vars.remove(var);
+    }
+}
+
+fn scoring_rule_cache(
+    rule: &CachedRule,
+    rt: &mut Runtime,
+) -> Result<(Vec<f64>, Vec<bool>), String> {
+    let mut published = Vec::new();
+    let result = publish_rule_refs(&rule.rule_refs, rt, &mut published).and_then(|()| {
+        let hits = rule_hits_cache(rule, rt)?;
+        let len = rt_max_len(rt);
+        scoring_rule_hits(rule, hits, len)
+    });
+    unpublish_rule_refs(rt, &published);
+    result
+}
+
+// 计划求值成功时直接用计划的命中序列,否则逐条规则解释求值
+fn scoring_rule_cache_with_plan(
+    rule: &CachedRule,
+    planned_hits: Option<&mut Vec<Vec<bool>>>,
+    rt: &mut Runtime,
+) -> Result<(Vec<f64>, Vec<bool>), String> {
+    match planned_hits {
+        Some(hits) => scoring_rule_hits(rule, std::mem::take(hits), rt_max_len(rt)),
+        None => scoring_rule_cache(rule, rt),
+    }
+}
+
 pub fn evaluate_cached_rule_scores(
     rule: &CachedRule,
     rt: &mut Runtime,
@@ -433,47 +654,117 @@ pub fn evaluate_cached_rule_scores(
     scoring_rule_cache(rule, rt)
 }
 
+// 单条规则逐日的原始分和触发序列
+pub type RuleRawSeries = (Vec<f64>, Vec<bool>);
+
+// 各规则未加权的原始分和触发序列,截面标准化的第一遍也用它
+// 按计划逐层求值: 每层打完分把触发序列写进运行时,后面层的 RULE("规则名") 直接读取,
+// 被引用的规则每只股票只算一次;结果按 rules_cache 的顺序排列,报错也取顺序上的第一条
+pub fn scoring_rules_raw_cache(
+    rt: &mut Runtime,
+    rules_cache: &[CachedRule],
+    rules_plan: &CachedRulesPlan,
+) -> Result<Vec<RuleRawSeries>, String> {
+    let mut results = vec![None; rules_cache.len()];
+    let mut published = Vec::new();
+    if let Err(error) = publish_rule_refs(&rules_plan.outside_refs, rt, &mut published) {
+        unpublish_rule_refs(rt, &published);
+        return Err(error);
+    }
+    for layer in &rules_plan.layers {
+        let mut planned_hits = layer.rule_hits(rt);
+        for (position, &index) in layer.rules.iter().enumerate() {
+            let rule = &rules_cache[index];
+            let hits = planned_hits.as_mut().map(|hits| &mut hits[position]);
+            let result = scoring_rule_cache_with_plan(rule, hits, rt);
+            if let Ok((_, triggered)) = &result {
+                let var = rule_trigger_var(&rule.name);
+                if !rt.vars.contains_key(&var) {
+                    rt.vars
+                        .insert(var.clone(), Value::BoolSeries(triggered.clone()));
+                    published.push(var);
+                }
+            }
+            results[index] = Some(result);
+        }
+    }
+    unpublish_rule_refs(rt, &published);
+    results.into_iter().flatten().collect()
+}
+
+// 多头场景的规则计入总分,空头场景的规则取反后计入风险分
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScoreTotals {
+    pub total: Vec<f64>,
+    pub risk: Vec<f64>,
+}
+
+impl ScoreTotals {
+    fn new(len: usize) -> Self {
+        Self {
+            total: vec![50.0; len],
+            risk: vec![0.0; len],
+        }
+    }
+
+    fn add(&mut self, direction: SceneDirection, score: &[f64]) {
+        let target = match direction {
+            SceneDirection::Long => &mut self.total,
+            SceneDirection::Short => &mut self.risk,
+        };
+        for (sum, value) in target.iter_mut().zip(score) {
+            *sum += value * direction.sign();
+        }
+    }
+
+    pub fn split_off(&mut self, at: usize) {
+        self.total = self.total.split_off(at);
+        self.risk = self.risk.split_off(at);
+    }
+}
+
 pub fn scoring_rules_details_cache(
     rt: &mut Runtime,
     rules_cache: &[CachedRule],
-) -> Result<(Vec<f64>, Vec<RuleScoreSeries>), String> {
-    let len = rt_max_len(rt);
-    let mut total = vec![50.0; len];
+    rules_plan: &CachedRulesPlan,
+) -> Result<(ScoreTotals, Vec<RuleScoreSeries>), String> {
+    let mut totals = ScoreTotals::new(rt_max_len(rt));
     let mut details = Vec::with_capacity(rules_cache.len());
+    let raw_scores = scoring_rules_raw_cache(rt, rules_cache, rules_plan)?;
 
-    for rule in rules_cache {
-        let (score, triggered) = scoring_rule_cache(&rule, rt)?;
-        let min_len = usize::min(total.len(), score.len());
-        for i in 0..min_len {
-            total[i] += score[i];
-        }
+    for (rule, (raw_score, triggered)) in rules_cache.iter().zip(raw_scores) {
+        let score = rule
+            .weighting
+            .apply(&rule.name, rt, &raw_score, &triggered)?;
+        totals.add(rule.weighting.direction, &score);
 
         details.push(RuleScoreSeries {
             name: rule.name.clone(),
             series: score,
+            raw_series: raw_score,
             triggered,
         });
     }
 
-    Ok((total, details))
+    Ok((totals, details))
 }
 
 pub fn scoring_rules_total_cache(
     rt: &mut Runtime,
     rules_cache: &[CachedRule],
-) -> Result<Vec<f64>, String> {
-    let len = rt_max_len(rt);
-    let mut total = vec![50.0; len];
-
-    for rule in rules_cache {
-        let (score, _) = scoring_rule_cache(rule, rt)?;
-        let min_len = usize::min(total.len(), score.len());
-        for i in 0..min_len {
-            total[i] += score[i];
-        }
+    rules_plan: &CachedRulesPlan,
+) -> Result<ScoreTotals, String> {
+    let mut totals = ScoreTotals::new(rt_max_len(rt));
+    let raw_scores = scoring_rules_raw_cache(rt, rules_cache, rules_plan)?;
+
+    for (rule, (raw_score, triggered)) in rules_cache.iter().zip(raw_scores) {
+        let score = rule
+            .weighting
+            .apply(&rule.name, rt, &raw_score, &triggered)?;
+        totals.add(rule.weighting.direction, &score);
     }
 
-    Ok(total)
+    Ok(totals)
 }
 
 fn resolve_scene_stage(
@@ -537,7 +828,7 @@ pub fn build_scene_score_series(
 
     let len = rule_details
         .first()
-        .map(|item| item.series.len())
+        .map(|item| item.raw_series.len())
         .unwrap_or_default();
     let mut scene_index = HashMap::with_capacity(scenes.len());
     let mut out: Vec<SceneScoreSeries> = scenes
@@ -567,7 +858,7 @@ pub fn build_scene_score_series(
             continue;
         };
         let scene_row = &mut out[scene_pos];
-        let min_len = usize::min(detail.series.len(), detail.triggered.len()).min(len);
+        let min_len = usize::min(detail.raw_series.len(), detail.triggered.len()).min(len);
 
         for i in 0..min_len {
             if !detail.triggered[i] {
@@ -578,21 +869,21 @@ pub fn build_scene_score_series(
 
             match rule_meta.stage {
                 RuleStage::Base => {
-                    scene_row.stage_score[i] += detail.series[i];
+                    scene_row.stage_score[i] += detail.raw_series[i];
                 }
                 RuleStage::Trigger => {
-                    scene_row.stage_score[i] += detail.series[i];
+                    scene_row.stage_score[i] += detail.raw_series[i];
                     has_trigger_rule[scene_pos][i] = true;
                 }
                 RuleStage::Confirm => {
-                    scene_row.stage_score[i] += detail.series[i];
+                    scene_row.stage_score[i] += detail.raw_series[i];
                     has_confirm_rule[scene_pos][i] = true;
                 }
                 RuleStage::Risk => {
-                    scene_row.risk_score[i] += detail.series[i];
+                    scene_row.risk_score[i] += detail.raw_series[i];
                 }
                 RuleStage::Fail => {
-                    scene_row.risk_score[i] += detail.series[i];
+                    scene_row.risk_score[i] += detail.raw_series[i];
                     has_fail_rule[scene_pos][i] = true;
                 }
             }
@@ -623,7 +914,67 @@ pub fn build_scene_score_series(
     out
 }
 
-pub(crate) fn build_tirbreak_rank_sql(tie_break: TieBreakWay, adj_type: &str) -> String {
+fn rule_stage_drives(rule_stage: RuleStage, to_stage: &str) -> bool {
+    match to_stage {
+        "fail" => matches!(rule_stage, RuleStage::Fail | RuleStage::Risk),
+        "confirm" => rule_stage == RuleStage::Confirm,
+        "trigger" | "observe" => rule_stage == RuleStage::Trigger,
+        _ => false,
+    }
+}
+
+// 逐根比较场景阶段,只记进入或切换到某个阶段的K线,退出阶段不记;
+// 第一根没有前值,不算变化,所以要从 from 之前留足历史
+pub fn build_scene_transitions(
+    rule_scene_meta: &[RuleSceneMeta],
+    rule_details: &[RuleScoreSeries],
+    scene: &SceneScoreSeries,
+    from: usize,
+) -> Vec<SceneTransitionPoint> {
+    let mut out = Vec::new();
+    let mut prev_stage_days = 0usize;
+    for i in 0..scene.stage.len() {
+        let stage = &scene.stage[i];
+        if i == 0 {
+            prev_stage_days = 1;
+            continue;
+        }
+        let prev_stage = &scene.stage[i - 1];
+        if stage == prev_stage {
+            prev_stage_days += 1;
+            continue;
+        }
+        if let Some(to_stage) = stage.as_deref()
+            && i >= from
+        {
+            let rule_names = rule_scene_meta
+                .iter()
+                .zip(rule_details)
+                .filter(|(meta, detail)| {
+                    meta.scene_name == scene.name
+                        && rule_stage_drives(meta.stage, to_stage)
+                        && detail.triggered.get(i).copied().unwrap_or(false)
+                })
+                .map(|(_, detail)| detail.name.clone())
+                .collect();
+            out.push(SceneTransitionPoint {
+                index: i,
+                from_stage: prev_stage.clone(),
+                to_stage: to_stage.to_string(),
+                prev_stage_days,
+                rule_names,
+            });
+        }
+        prev_stage_days = 1;
+    }
+    out
+}
+
+pub(crate) fn build_tirbreak_rank_sql(
+    tie_break: TieBreakWay,
+    adj_type: &str,
+    rank_keys: &[RankKeySpec],
+) -> String {
     match tie_break {
         TieBreakWay::TsCode => r#"
             UPDATE score_summary AS s
@@ -634,7 +985,7 @@ pub(crate) fn build_tirbreak_rank_sql(tie_break: TieBreakWay, adj_type: &str) ->
                     trade_date,
                     ROW_NUMBER() OVER (
                         PARTITION BY trade_date
-                        ORDER BY total_score DESC, ts_code ASC
+                        ORDER BY total_score - risk_score DESC, ts_code ASC
                     ) AS new_rank
                 FROM score_summary
             ) AS r
@@ -654,7 +1005,7 @@ pub(crate) fn build_tirbreak_rank_sql(tie_break: TieBreakWay, adj_type: &str) ->
                         ROW_NUMBER() OVER (
                             PARTITION BY s.trade_date
                             ORDER BY
-                                s.total_score DESC,
+                                s.total_score - s.risk_score DESC,
                                 src.j ASC NULLS LAST,
                                 s.ts_code ASC
                         ) AS new_rank
@@ -669,6 +1020,28 @@ pub(crate) fn build_tirbreak_rank_sql(tie_break: TieBreakWay, adj_type: &str) ->
                 "#
             )
         }
+        TieBreakWay::RankOrder => {
+            let key_joins = rank_key_joins_sql(rank_keys, "s", "rk");
+            let order_by = rank_order_by_sql(rank_keys, &net_score_sql("s"), "rk");
+            format!(
+                r#"
+                UPDATE score_summary AS s
+                SET rank = r.new_rank
+                FROM (
+                    SELECT
+                        s.ts_code,
+                        s.trade_date,
+                        ROW_NUMBER() OVER (
+                            PARTITION BY s.trade_date
+                            ORDER BY {order_by}, s.ts_code ASC
+                        ) AS new_rank
+                    FROM score_summary AS s{key_joins}
+                ) AS r
+                WHERE s.ts_code = r.ts_code
+                  AND s.trade_date = r.trade_date
+                "#
+            )
+        }
     }
 }
 
@@ -690,7 +1063,12 @@ pub fn build_rank_tiebreak(
         profile.attach_source_db_ms = Some(attach_started_at.elapsed().as_millis() as u64);
     }
 
-    let sql = build_tirbreak_rank_sql(tie_break, adj_type);
+    let rank_keys = if let TieBreakWay::RankOrder = tie_break {
+        load_rank_order(&conn)?
+    } else {
+        Vec::new()
+    };
+    let sql = build_tirbreak_rank_sql(tie_break, adj_type, &rank_keys);
     let update_started_at = Instant::now();
     conn.execute(&sql, [])
         .map_err(|e| format!("补rank失败:{e}"))?;
@@ -711,14 +1089,17 @@ pub fn build_rank_tiebreak(
 mod tests {
     use super::{
         CachedCombinationCondition, CachedCombinationRule, CachedRule, CachedRuleExpression,
-        evaluate_cached_rule_scores,
+        CachedRulesPlan, RuleSceneMeta, RuleScoreSeries, SceneScoreSeries, SceneTransitionPoint,
+        build_scene_transitions, evaluate_cached_rule_scores, scoring_rules_details_cache,
+        scoring_rules_total_cache,
     };
     use crate::{
-        data::{ScopeWay, collect_assigned_names_from_expr_program},
+        data::{RuleStage, SceneDirection, ScopeWay, collect_assigned_names_from_expr_program},
         expr::{
             eval::{Runtime, Value},
             parser::{Parser, lex_all},
         },
+        scoring::weighting::RuleWeighting,
     };
 
     fn cached_rule(expression: &str) -> CachedRule {
@@ -739,6 +1120,8 @@ mod tests {
             when_ast,
             assigned_names,
             combination: None,
+            weighting: RuleWeighting::default(),
+            rule_refs: Vec::new(),
         }
     }
 
@@ -788,6 +1171,8 @@ mod tests {
                 max_points,
                 max_bonus_points,
             }),
+            weighting: RuleWeighting::default(),
+            rule_refs: Vec::new(),
         }
     }
 
@@ -988,4 +1373,123 @@ mod tests {
         assert_eq!(scores, vec![5.5]);
         assert_eq!(triggered, vec![true]);
     }
+
+    #[test]
+    fn rules_plan_scores_match_rule_by_rule_eval() {
+        let rules = vec![
+            cached_rule("C := REF(C, 1); C > 1"),
+            cached_rule("C > 2"),
+            cached_rule("MA(C, 2) > 1.5 AND C > MA(C, 3)"),
+            combination_rule(
+                &["C > 1", "MA(C, 2) > 1.5"],
+                vec![0.0, 1.0, 3.0],
+                &[0.0, 1.0],
+                None,
+                None,
+            ),
+        ];
+        let rules_plan = CachedRulesPlan::build(&rules);
+        let mut runtime = runtime_with_close_series(&[1.0, 2.0, 3.0, 2.5, 4.0]);
+        let (totals, details) = scoring_rules_details_cache(&mut runtime, &rules, &rules_plan)
+            .expect("planned rules evaluate");
+
+        let mut legacy_runtime = runtime_with_close_series(&[1.0, 2.0, 3.0, 2.5, 4.0]);
+        let mut legacy_total = vec![50.0; 5];
+        for (rule, detail) in rules.iter().zip(&details) {
+            let (scores, triggered) =
+                evaluate_cached_rule_scores(rule, &mut legacy_runtime).expect("rule evaluates");
+            assert_eq!(detail.series, scores);
+            assert_eq!(detail.triggered, triggered);
+            for (sum, score) in legacy_total.iter_mut().zip(scores) {
+                *sum += score;
+            }
+        }
+        assert_eq!(totals.total, legacy_total);
+        assert_eq!(totals.risk, vec![0.0; 5]);
+        assert_eq!(runtime.vars, legacy_runtime.vars);
+    }
+
+    #[test]
+    fn short_scene_rules_feed_risk_instead_of_total() {
+        let long_rule = cached_rule("C > 2");
+        let mut short_rule = cached_rule("C < 3");
+        short_rule.points = -2.0;
+        short_rule.weighting.direction = SceneDirection::Short;
+        let rules = vec![long_rule, short_rule];
+        let rules_plan = CachedRulesPlan::build(&rules);
+        let mut runtime = runtime_with_close_series(&[1.0, 2.0, 3.0]);
+
+        let totals =
+            scoring_rules_total_cache(&mut runtime, &rules, &rules_plan).expect("rules evaluate");
+
+        assert_eq!(totals.total, vec![50.0, 50.0, 51.0]);
+        assert_eq!(totals.risk, vec![2.0, 2.0, 0.0]);
+    }
+
+    #[test]
+    fn rules_plan_reports_the_same_error_as_rule_by_rule_eval() {
+        let rules = vec![cached_rule("TMP := 1; TMP > 0"), cached_rule("TMP > 0")];
+        let rules_plan = CachedRulesPlan::build(&rules);
+        let mut runtime = runtime_with_close_series(&[1.0, 2.0, 3.0]);
+
+        let error = scoring_rules_total_cache(&mut runtime, &rules, &rules_plan)
+            .expect_err("TMP should not leak into next rule");
+        assert!(error.contains("变量不存在:TMP"));
+        assert!(!runtime.vars.contains_key("TMP"));
+    }
+
+    #[test]
+    fn scene_transitions_record_entries_with_driving_rules() {
+        let stage = |value: &str| Some(value.to_string());
+        let scene = SceneScoreSeries {
+            name: "s1".to_string(),
+            stage: vec![
+                None,
+                stage("trigger"),
+                stage("trigger"),
+                stage("confirm"),
+                None,
+            ],
+            ..SceneScoreSeries::default()
+        };
+        let meta = |stage: RuleStage| RuleSceneMeta {
+            scene_name: "s1".to_string(),
+            stage,
+        };
+        let detail = |name: &str, triggered: Vec<bool>| RuleScoreSeries {
+            name: name.to_string(),
+            triggered,
+            ..RuleScoreSeries::default()
+        };
+        let rule_scene_meta = vec![meta(RuleStage::Trigger), meta(RuleStage::Confirm)];
+        let rule_details = vec![
+            detail("t1", vec![false, true, true, true, false]),
+            detail("c1", vec![false, false, false, true, false]),
+        ];
+
+        let points = build_scene_transitions(&rule_scene_meta, &rule_details, &scene, 0);
+        assert_eq!(
+            points,
+            vec![
+                SceneTransitionPoint {
+                    index: 1,
+                    from_stage: None,
+                    to_stage: "trigger".to_string(),
+                    prev_stage_days: 1,
+                    rule_names: vec!["t1".to_string()],
+                },
+                SceneTransitionPoint {
+                    index: 3,
+                    from_stage: stage("trigger"),
+                    to_stage: "confirm".to_string(),
+                    prev_stage_days: 2,
+                    rule_names: vec!["c1".to_string()],
+                },
+            ]
+        );
+
+        let kept = build_scene_transitions(&rule_scene_meta, &rule_details, &scene, 2);
+        assert_eq!(kept.len(), 1);
+        assert_eq!(kept[0].index, 3);
+    }
 }
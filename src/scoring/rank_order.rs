@@ -0,0 +1,227 @@
+use crate::{
+    expr::{
+        eval::{Runtime, Value},
+        parser::Stmts,
+    },
+    scoring::{
+        CachedRule, CachedRuleExpression, publish_rule_refs, restore_runtime_values,
+        snapshot_runtime_values, tools::rt_max_len, unpublish_rule_refs,
+    },
+};
+
+pub const TOTAL_SCORE_KEY: &str = "total_score";
+
+// 策略 rank_order 里的一项: 表达式或 stock_data 列名,加排序方向
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RankKeySpec {
+    pub source: String,
+    pub descending: bool,
+}
+
+impl RankKeySpec {
+    pub fn is_total_score(&self) -> bool {
+        self.source.eq_ignore_ascii_case(TOTAL_SCORE_KEY)
+    }
+}
+
+// 解析 rank_order,如 ["total_score desc", "AMOUNT desc", "J asc"];
+// 方向省略时按 SQL 习惯升序,没写 total_score 时自动放在最前面
+pub fn parse_rank_order(items: &[String]) -> Result<Vec<RankKeySpec>, String> {
+    let mut keys = Vec::with_capacity(items.len() + 1);
+    for (index, item) in items.iter().enumerate() {
+        let item = item.trim();
+        if item.is_empty() {
+            return Err(format!("rank_order第{}项为空", index + 1));
+        }
+        let (source, descending) = match item.rsplit_once(char::is_whitespace) {
+            Some((source, direction)) if direction.eq_ignore_ascii_case("desc") => {
+                (source.trim(), true)
+            }
+            Some((source, direction)) if direction.eq_ignore_ascii_case("asc") => {
+                (source.trim(), false)
+            }
+            _ => (item, false),
+        };
+        if source.is_empty() {
+            return Err(format!("rank_order第{}项缺少排序键", index + 1));
+        }
+        let key = RankKeySpec {
+            source: source.to_string(),
+            descending,
+        };
+        if key.is_total_score() && keys.iter().any(RankKeySpec::is_total_score) {
+            return Err("rank_order里total_score重复".to_string());
+        }
+        keys.push(key);
+    }
+    if !keys.is_empty() && !keys.iter().any(RankKeySpec::is_total_score) {
+        keys.insert(
+            0,
+            RankKeySpec {
+                source: TOTAL_SCORE_KEY.to_string(),
+                descending: true,
+            },
+        );
+    }
+    Ok(keys)
+}
+
+// 一个表达式排序键逐日的取值: (键下标, 逐日值)
+pub type RankKeyValues = (usize, Vec<Option<f64>>);
+
+// 编译好的排序键,表达式键逐股逐日求值后写进 score_rank_keys
+#[derive(Clone, Default)]
+pub struct RankOrder {
+    pub keys: Vec<RankKeySpec>,
+    // (键下标, 表达式)
+    pub expressions: Vec<(usize, CachedRuleExpression)>,
+    // 排序键里 RULE("规则名") 引用的规则
+    pub rule_refs: Vec<CachedRule>,
+}
+
+impl RankOrder {
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    pub fn expression_programs(&self) -> Vec<&Stmts> {
+        self.expressions
+            .iter()
+            .map(|(_, expression)| &expression.when_ast)
+            .chain(
+                self.rule_refs
+                    .iter()
+                    .flat_map(CachedRule::programs_with_refs),
+            )
+            .collect()
+    }
+
+    pub fn evaluate(&self, rt: &mut Runtime) -> Result<Vec<RankKeyValues>, String> {
+        let mut published = Vec::new();
+        let result = publish_rule_refs(&self.rule_refs, rt, &mut published)
+            .and_then(|()| self.evaluate_keys(rt));
+        unpublish_rule_refs(rt, &published);
+        result
+    }
+
+    fn evaluate_keys(&self, rt: &mut Runtime) -> Result<Vec<RankKeyValues>, String> {
+        let len = rt_max_len(rt);
+        let mut out = Vec::with_capacity(self.expressions.len());
+        for (key_index, expression) in &self.expressions {
+            let snapshots = snapshot_runtime_values(rt, &expression.assigned_names);
+            let result = rt
+                .eval_program(&expression.when_ast)
+                .map_err(|error| format!("排序键({})计算错误:{}", expression.name, error.msg))
+                .and_then(|value| {
+                    Value::as_num_series(&value, len).map_err(|error| {
+                        format!("排序键({})返回值非数值:{}", expression.name, error.msg)
+                    })
+                });
+            restore_runtime_values(rt, &snapshots);
+            out.push((*key_index, result?));
+        }
+        Ok(out)
+    }
+}
+
+// 排序键对应的 ORDER BY 片段,不含最后兜底的 ts_code;
+// total_score 换成调用方给的分数列,表达式键读 score_rank_keys 连接出来的 {key_alias}{i}.key_value
+pub fn rank_order_by_sql(keys: &[RankKeySpec], score_sql: &str, key_alias: &str) -> String {
+    if keys.is_empty() {
+        return format!("{score_sql} DESC");
+    }
+    keys.iter()
+        .enumerate()
+        .map(|(index, key)| {
+            let direction = if key.descending { "DESC" } else { "ASC" };
+            if key.is_total_score() {
+                format!("{score_sql} {direction}")
+            } else {
+                format!("{key_alias}{index}.key_value {direction} NULLS LAST")
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+// 总榜按净分排名,total_score 里没扣空头风险分
+pub fn net_score_sql(row_alias: &str) -> String {
+    format!("({row_alias}.total_score - {row_alias}.risk_score)")
+}
+
+// 每个表达式键 LEFT JOIN 一次 score_rank_keys
+pub fn rank_key_joins_sql(keys: &[RankKeySpec], row_alias: &str, key_alias: &str) -> String {
+    keys.iter()
+        .enumerate()
+        .filter(|(_, key)| !key.is_total_score())
+        .map(|(index, _)| {
+            format!(
+                r#"
+                LEFT JOIN score_rank_keys AS {key_alias}{index}
+                  ON {key_alias}{index}.ts_code = {row_alias}.ts_code
+                 AND {key_alias}{index}.trade_date = {row_alias}.trade_date
+                 AND {key_alias}{index}.key_index = {index}"#
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn items(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[test]
+    fn parse_rank_order_reads_directions() {
+        let keys = parse_rank_order(&items(&["total_score desc", "AMOUNT DESC", "J asc", "V"]))
+            .expect("rank order should parse");
+
+        assert_eq!(
+            keys.iter()
+                .map(|key| (key.source.as_str(), key.descending))
+                .collect::<Vec<_>>(),
+            vec![
+                ("total_score", true),
+                ("AMOUNT", true),
+                ("J", false),
+                ("V", false)
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_rank_order_puts_missing_total_score_first() {
+        let keys = parse_rank_order(&items(&["MA(C, 5) / C desc"])).expect("rank order");
+
+        assert!(keys[0].is_total_score());
+        assert!(keys[0].descending);
+        assert_eq!(keys[1].source, "MA(C, 5) / C");
+        assert!(parse_rank_order(&[]).expect("empty order").is_empty());
+    }
+
+    #[test]
+    fn parse_rank_order_rejects_bad_items() {
+        assert!(parse_rank_order(&items(&["  "])).is_err());
+        assert!(parse_rank_order(&items(&["total_score desc", "TOTAL_SCORE asc"])).is_err());
+    }
+
+    #[test]
+    fn rank_order_sql_swaps_score_column_and_joins_keys() {
+        let keys = parse_rank_order(&items(&["total_score desc", "AMOUNT desc", "J asc"]))
+            .expect("rank order");
+
+        assert_eq!(
+            rank_order_by_sql(&keys, "st.total_score", "rk"),
+            "st.total_score DESC, rk1.key_value DESC NULLS LAST, rk2.key_value ASC NULLS LAST"
+        );
+        let joins = rank_key_joins_sql(&keys, "st", "rk");
+        assert!(joins.contains("AS rk1"));
+        assert!(joins.contains("rk2.key_index = 2"));
+        assert!(!joins.contains("rk0"));
+        assert_eq!(rank_order_by_sql(&[], "raw_score", "rk"), "raw_score DESC");
+    }
+}
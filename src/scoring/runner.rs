@@ -1,28 +1,46 @@
+use chrono::Local;
 use rayon::prelude::*;
+use serde::Serialize;
 use std::{
     collections::{HashMap, HashSet},
+    path::Path,
     sync::mpsc::sync_channel,
     thread, time,
 };
 
 use crate::data::scoring_data::{
-    SceneDetails, ScoreBatch, ScoreDetails, ScoreSummary, ScoreWriteMessage, ScoreWriteProfile,
-    cache_rule_build, init_result_db, rank_scene_rows, rank_summary_rows_by_score, row_into_rt,
-    write_score_batches_from_channel,
+    SceneDetails, SceneTransition, ScoreBatch, ScoreDetails, ScoreRankKey, ScoreRunMeta,
+    ScoreSummary, ScoreWriteMessage, ScoreWriteProfile, ScoreWriteScope, archive_score_run,
+    cache_rule_build, init_result_db, load_rank_order, load_scoring_state, rank_order_build,
+    rank_scene_rows, rank_summary_rows_by_score, row_into_rt, save_rank_order, save_rule_weighting,
+    save_scoring_state, write_score_batches_from_channel, write_score_batches_with_scope,
 };
 use crate::data::{
     DataReader, RowData, RuntimeKeyCollectOptions, ScoreRule, ScoreScene,
-    collect_runtime_keys_from_expr_programs, result_db_path, source_db_path,
+    collect_runtime_keys_from_expr_programs, load_index_constituents, load_ths_concepts_list,
+    load_trade_date_list, resolve_strategy_path, result_db_path,
+    simulate::{SimulateBarInput, build_simulated_row_data_with_inds},
+    source_db_path,
+    strategy_file::resolve_strategy_file,
 };
+use crate::download::ind_calc::cache_ind_build;
+use crate::expr::validation::future_data_warning;
 use crate::scoring::{
-    CachedRule, RuleSceneMeta, TieBreakWay, build_scene_score_series, scoring_rules_details_cache,
-    scoring_rules_total_cache,
+    CachedRule, CachedRulesPlan, RuleSceneMeta, TieBreakWay, build_scene_score_series,
+    cross_section::{CrossSectionPlan, CrossSectionValues, StockCrossInputs, cross_rule_names},
+    incremental::{
+        RuleRefreshPlan, ScoringState, plan_rule_refresh, strategy_fingerprint, strategy_text_hash,
+    },
+    rank_order::RankOrder,
+    scoring_rules_details_cache, scoring_rules_total_cache,
     tools::{
-        CyqChenFieldInjector, calc_query_need_rows, calc_query_start_date,
+        CyqChenFieldInjector, StockProfile, calc_query_need_rows, calc_query_start_date,
         collect_used_cyq_chen_runtime_keys, cyq_chen_runtime_key_names,
         inject_optional_cyq_chen_fields, inject_stock_extra_fields, load_st_list,
-        load_total_share_map, preview_optional_cyq_chen_injection_warnings, warmup_rows_estimate,
+        load_stock_profile_map, load_total_share_map, preview_optional_cyq_chen_injection_warnings,
+        warmup_rows_estimate,
     },
+    weighting::{StockRuleScores, normalize_rule_scores, normalized_rules},
 };
 
 const SCORING_GROUP_SIZE: usize = 128;
@@ -47,6 +65,11 @@ pub struct ScoringRunProfile {
     pub stock_count: usize,
     pub writer: ScoreWriteProfile,
     pub warnings: Vec<String>,
+    // 增量评分时重写了明细的规则,以及这一步的写库耗时
+    pub refreshed_rules: Vec<String>,
+    pub refresh_writer: Option<ScoreWriteProfile>,
+    // 本次评分另存的版本号,没要求另存版本时为空
+    pub run_id: Option<String>,
 }
 
 fn format_elapsed_ms(elapsed_ms: u64) -> String {
@@ -88,15 +111,18 @@ fn log_scoring_run_profile(profile: &ScoringRunProfile) {
     );
 }
 
+#[allow(clippy::too_many_arguments)]
 fn scoring_single_core(
     row_data: RowData,
     ts_code: &str,
     score_start_date: &str,
     rules_cache: &[CachedRule],
+    rules_plan: &CachedRulesPlan,
     rule_scene_meta: &[RuleSceneMeta],
     scenes: &[ScoreScene],
+    rank_order: Option<&RankOrder>,
     memory_mode: ScoringMemoryMode,
-) -> Result<(Vec<ScoreSummary>, Vec<ScoreDetails>, Vec<SceneDetails>), String> {
+) -> Result<ScoreBatch, String> {
     let trade_dates = row_data.trade_dates.clone();
     let mut rt = row_into_rt(row_data)?;
 
@@ -105,21 +131,58 @@ fn scoring_single_core(
         .unwrap_or_else(|i| i);
 
     if keep_from >= trade_dates.len() {
-        return Ok((Vec::new(), Vec::new(), Vec::new()));
+        return Ok(ScoreBatch::default());
     }
 
     let kept_trade_dates = &trade_dates[keep_from..];
+    let rank_key_rows = match rank_order {
+        Some(rank_order) => ScoreRankKey::build(
+            ts_code,
+            kept_trade_dates,
+            keep_from,
+            &rank_order.evaluate(&mut rt)?,
+        ),
+        None => Vec::new(),
+    };
 
     if matches!(memory_mode, ScoringMemoryMode::SummaryOnly) {
-        let total_scores = scoring_rules_total_cache(&mut rt, rules_cache)?;
-        let kept_scores = &total_scores[keep_from..];
-        let summary = ScoreSummary::build(ts_code, kept_trade_dates, kept_scores);
-        return Ok((summary, Vec::new(), Vec::new()));
+        let mut totals = scoring_rules_total_cache(&mut rt, rules_cache, rules_plan)?;
+        totals.split_off(keep_from);
+        return Ok(ScoreBatch {
+            summary_rows: ScoreSummary::build(ts_code, kept_trade_dates, &totals),
+            rank_key_rows,
+            ..ScoreBatch::default()
+        });
     }
 
-    let result = scoring_rules_details_cache(&mut rt, rules_cache)?;
-    let (total_scores, mut details_series) = (result.0, result.1);
-    let kept_scores = &total_scores[keep_from..];
+    let (mut totals, mut details_series) =
+        scoring_rules_details_cache(&mut rt, rules_cache, rules_plan)?;
+    totals.split_off(keep_from);
+
+    // 场景阶段按整段算,预热段用来判断落库第一天之前的阶段
+    let (scene_details, transitions) = if matches!(
+        memory_mode,
+        ScoringMemoryMode::All | ScoringMemoryMode::SceneOnly
+    ) {
+        let mut scene_series = build_scene_score_series(rule_scene_meta, &details_series, scenes);
+        let transitions = SceneTransition::build(
+            ts_code,
+            &trade_dates,
+            keep_from,
+            rule_scene_meta,
+            &details_series,
+            &scene_series,
+        );
+        for scene in &mut scene_series {
+            scene.split_off(keep_from);
+        }
+        (
+            SceneDetails::build(ts_code, kept_trade_dates, &totals.total, &scene_series),
+            transitions,
+        )
+    } else {
+        (Vec::new(), Vec::new())
+    };
 
     for rule in &mut details_series {
         rule.series = rule.series.split_off(keep_from);
@@ -130,7 +193,7 @@ fn scoring_single_core(
         memory_mode,
         ScoringMemoryMode::All | ScoringMemoryMode::SummaryAndDetails
     ) {
-        ScoreSummary::build(ts_code, kept_trade_dates, kept_scores)
+        ScoreSummary::build(ts_code, kept_trade_dates, &totals)
     } else {
         Vec::new()
     };
@@ -142,23 +205,28 @@ fn scoring_single_core(
     } else {
         Vec::new()
     };
-    let scene_details = if matches!(
-        memory_mode,
-        ScoringMemoryMode::All | ScoringMemoryMode::SceneOnly
-    ) {
-        let scene_series = build_scene_score_series(rule_scene_meta, &details_series, scenes);
-        SceneDetails::build(ts_code, kept_trade_dates, kept_scores, &scene_series)
-    } else {
-        Vec::new()
-    };
 
-    Ok((summary, details, scene_details))
+    Ok(ScoreBatch {
+        summary_rows: summary,
+        detail_rows: details,
+        scene_rows: scene_details,
+        transition_rows: transitions,
+        rank_key_rows,
+    })
 }
 
-fn collect_scoring_runtime_keys(rules_cache: &[CachedRule]) -> HashSet<String> {
+fn collect_scoring_runtime_keys(
+    rules_cache: &[CachedRule],
+    rank_order: Option<&RankOrder>,
+) -> HashSet<String> {
     let programs = rules_cache
         .iter()
-        .flat_map(CachedRule::expression_programs)
+        .flat_map(CachedRule::programs_with_refs)
+        .chain(
+            rank_order
+                .map(RankOrder::expression_programs)
+                .unwrap_or_default(),
+        )
         .collect::<Vec<_>>();
     let cyq_chen_keys = cyq_chen_runtime_key_names();
     let injected_keys = SCORING_INJECTED_RUNTIME_KEYS
@@ -180,7 +248,7 @@ fn collect_scoring_runtime_keys(rules_cache: &[CachedRule]) -> HashSet<String> {
 fn collect_scoring_used_cyq_chen_runtime_keys(rules_cache: &[CachedRule]) -> HashSet<String> {
     let programs = rules_cache
         .iter()
-        .flat_map(CachedRule::expression_programs)
+        .flat_map(CachedRule::programs_with_refs)
         .collect::<Vec<_>>();
     collect_used_cyq_chen_runtime_keys(&programs)
 }
@@ -194,51 +262,117 @@ pub fn preview_scoring_runtime_warnings(
     let warmup_need = warmup_rows_estimate(source_dir, strategy_path)?;
     let rules_cache = cache_rule_build(source_dir, strategy_path)?;
     let used_cyq_chen_keys = collect_scoring_used_cyq_chen_runtime_keys(&rules_cache);
-    Ok(preview_optional_cyq_chen_injection_warnings(
+    let mut warnings = preview_optional_cyq_chen_injection_warnings(
         source_dir,
         start_date,
         end_date,
         warmup_need,
         &used_cyq_chen_keys,
-    ))
+    );
+    for rule in &rules_cache {
+        warnings.extend(
+            rule.expression_programs()
+                .into_iter()
+                .find_map(|stmts| future_data_warning(&format!("规则{}", rule.name), stmts)),
+        );
+    }
+    Ok(warnings)
+}
+
+fn inject_scoring_stock_fields(
+    row: &mut RowData,
+    cyq_chen_injector: &CyqChenFieldInjector,
+    ts_code: &str,
+    st_list: &HashSet<String>,
+    total_share_map: &HashMap<String, f64>,
+    profile_map: &HashMap<String, StockProfile>,
+) -> Result<(), String> {
+    let _ = cyq_chen_injector.inject(row, ts_code);
+    inject_stock_extra_fields(
+        row,
+        ts_code,
+        st_list.contains(ts_code),
+        total_share_map.get(ts_code).copied(),
+        profile_map.get(ts_code),
+    )
 }
 
+// 批量读取缺行或行数不够预热时,退回按尾部行数单独读取
+fn load_scoring_stock_row(
+    worker_reader: &DataReader,
+    rows_map: &mut HashMap<String, RowData>,
+    ts_code: &str,
+    adj_type: &str,
+    end_date: &str,
+    need_rows: usize,
+) -> Result<Option<RowData>, String> {
+    let mut row = match rows_map.remove(ts_code) {
+        Some(r) => r,
+        None => {
+            let tail = worker_reader.load_one_tail_rows(ts_code, adj_type, end_date, need_rows)?;
+            if tail.trade_dates.is_empty() {
+                RowData {
+                    trade_dates: Vec::new(),
+                    cols: HashMap::new(),
+                    text_cols: HashMap::new(),
+                }
+            } else {
+                tail
+            }
+        }
+    };
+    if row.trade_dates.len() < need_rows {
+        let tail = worker_reader.load_one_tail_rows(ts_code, adj_type, end_date, need_rows)?;
+        if !tail.trade_dates.is_empty() {
+            row = tail;
+        }
+    }
+    if row.trade_dates.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(row))
+}
+
+#[allow(clippy::too_many_arguments)]
 fn scoring_stock_batch(
     mut row: RowData,
     score_start_date: &str,
     rules_cache: &[CachedRule],
+    rules_plan: &CachedRulesPlan,
     rule_scene_meta: &[RuleSceneMeta],
     scenes: &[ScoreScene],
     cyq_chen_injector: &CyqChenFieldInjector,
     ts_code: &str,
     st_list: &HashSet<String>,
     total_share_map: &HashMap<String, f64>,
+    profile_map: &HashMap<String, StockProfile>,
+    cross_values: &CrossSectionValues,
+    rank_order: Option<&RankOrder>,
     memory_mode: ScoringMemoryMode,
 ) -> Result<ScoreBatch, String> {
-    let _ = cyq_chen_injector.inject(&mut row, ts_code);
-    inject_stock_extra_fields(
+    inject_scoring_stock_fields(
         &mut row,
+        cyq_chen_injector,
         ts_code,
-        st_list.contains(ts_code),
-        total_share_map.get(ts_code).copied(),
+        st_list,
+        total_share_map,
+        profile_map,
     )?;
-    let (summary_rows, detail_rows, scene_rows) = scoring_single_core(
+    cross_values.inject(&mut row, ts_code);
+    scoring_single_core(
         row,
         ts_code,
         score_start_date,
         rules_cache,
+        rules_plan,
         rule_scene_meta,
         scenes,
+        rank_order,
         memory_mode,
-    )?;
-
-    Ok(ScoreBatch {
-        summary_rows,
-        detail_rows,
-        scene_rows,
-    })
+    )
 }
 
+#[allow(clippy::too_many_arguments)]
 fn scoring_stock_group_batch(
     worker_reader: &DataReader,
     source_dir: &str,
@@ -248,12 +382,17 @@ fn scoring_stock_group_batch(
     query_start_date: &str,
     need_rows: usize,
     rules_cache: &[CachedRule],
+    rules_plan: &CachedRulesPlan,
     rule_scene_meta: &[RuleSceneMeta],
     scenes: &[ScoreScene],
     st_list: &HashSet<String>,
     total_share_map: &HashMap<String, f64>,
+    profile_map: &HashMap<String, StockProfile>,
     used_cyq_chen_keys: &HashSet<String>,
+    cross_values: &CrossSectionValues,
     ts_group: &[String],
+    stock_starts: Option<&HashMap<String, String>>,
+    rank_order: Option<&RankOrder>,
     memory_mode: ScoringMemoryMode,
 ) -> Result<ScoreBatch, String> {
     let mut rows_map = worker_reader.load_batch(ts_group, adj_type, query_start_date, end_date)?;
@@ -261,41 +400,36 @@ fn scoring_stock_group_batch(
     let mut group_batch = ScoreBatch::default();
 
     for ts_code in ts_group {
-        let mut row = match rows_map.remove(ts_code.as_str()) {
-            Some(r) => r,
-            None => {
-                let tail =
-                    worker_reader.load_one_tail_rows(ts_code, adj_type, end_date, need_rows)?;
-                if tail.trade_dates.is_empty() {
-                    RowData {
-                        trade_dates: Vec::new(),
-                        cols: HashMap::new(),
-                    }
-                } else {
-                    tail
-                }
-            }
-        };
-        if row.trade_dates.len() < need_rows {
-            let tail = worker_reader.load_one_tail_rows(ts_code, adj_type, end_date, need_rows)?;
-            if !tail.trade_dates.is_empty() {
-                row = tail;
-            }
-        }
-        if row.trade_dates.is_empty() {
+        let Some(row) = load_scoring_stock_row(
+            worker_reader,
+            &mut rows_map,
+            ts_code,
+            adj_type,
+            end_date,
+            need_rows,
+        )?
+        else {
             continue;
-        }
+        };
 
+        let stock_start_date = stock_starts
+            .and_then(|starts| starts.get(ts_code))
+            .map(String::as_str)
+            .unwrap_or(score_start_date);
         let batch = scoring_stock_batch(
             row,
-            score_start_date,
+            stock_start_date,
             rules_cache,
+            rules_plan,
             rule_scene_meta,
             scenes,
             &cyq_chen_injector,
             ts_code,
             st_list,
             total_share_map,
+            profile_map,
+            cross_values,
+            rank_order,
             memory_mode,
         )?;
         group_batch.extend(batch);
@@ -303,26 +437,230 @@ fn scoring_stock_group_batch(
     Ok(group_batch)
 }
 
+#[allow(clippy::too_many_arguments)]
+fn cross_section_stock_group_inputs(
+    worker_reader: &DataReader,
+    cross_plan: &CrossSectionPlan,
+    source_dir: &str,
+    adj_type: &str,
+    end_date: &str,
+    query_start_date: &str,
+    need_rows: usize,
+    st_list: &HashSet<String>,
+    total_share_map: &HashMap<String, f64>,
+    profile_map: &HashMap<String, StockProfile>,
+    used_cyq_chen_keys: &HashSet<String>,
+    ts_group: &[String],
+) -> Result<Vec<StockCrossInputs>, String> {
+    let mut rows_map = worker_reader.load_batch(ts_group, adj_type, query_start_date, end_date)?;
+    let cyq_chen_injector = CyqChenFieldInjector::new(source_dir, used_cyq_chen_keys);
+    let mut out = Vec::with_capacity(ts_group.len());
+
+    for ts_code in ts_group {
+        let Some(mut row) = load_scoring_stock_row(
+            worker_reader,
+            &mut rows_map,
+            ts_code,
+            adj_type,
+            end_date,
+            need_rows,
+        )?
+        else {
+            continue;
+        };
+        inject_scoring_stock_fields(
+            &mut row,
+            &cyq_chen_injector,
+            ts_code,
+            st_list,
+            total_share_map,
+            profile_map,
+        )?;
+        out.push(cross_plan.collect_stock(row, ts_code)?);
+    }
+    Ok(out)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn normalize_stock_group_inputs(
+    worker_reader: &DataReader,
+    normalized: &[CachedRule],
+    normalized_plan: &CachedRulesPlan,
+    cross_values: &CrossSectionValues,
+    source_dir: &str,
+    adj_type: &str,
+    end_date: &str,
+    query_start_date: &str,
+    need_rows: usize,
+    st_list: &HashSet<String>,
+    total_share_map: &HashMap<String, f64>,
+    profile_map: &HashMap<String, StockProfile>,
+    used_cyq_chen_keys: &HashSet<String>,
+    ts_group: &[String],
+) -> Result<Vec<StockRuleScores>, String> {
+    let mut rows_map = worker_reader.load_batch(ts_group, adj_type, query_start_date, end_date)?;
+    let cyq_chen_injector = CyqChenFieldInjector::new(source_dir, used_cyq_chen_keys);
+    let mut out = Vec::with_capacity(ts_group.len());
+
+    for ts_code in ts_group {
+        let Some(mut row) = load_scoring_stock_row(
+            worker_reader,
+            &mut rows_map,
+            ts_code,
+            adj_type,
+            end_date,
+            need_rows,
+        )?
+        else {
+            continue;
+        };
+        inject_scoring_stock_fields(
+            &mut row,
+            &cyq_chen_injector,
+            ts_code,
+            st_list,
+            total_share_map,
+            profile_map,
+        )?;
+        cross_values.inject(&mut row, ts_code);
+        let trade_dates = row.trade_dates.clone();
+        let mut rt = row_into_rt(row)?;
+        out.push(StockRuleScores::collect(
+            &mut rt,
+            ts_code,
+            trade_dates,
+            normalized,
+            normalized_plan,
+        )?);
+    }
+    Ok(out)
+}
+
+// 截面函数的第一遍: 全市场逐股算参数序列,再按交易日做截面,结果供第二遍评分注入;
+// 有规则要截面标准化时,再全市场求一遍这些规则的原始分,标准化结果一并注入
+#[allow(clippy::too_many_arguments)]
+fn compute_cross_section_values(
+    cross_plan: &CrossSectionPlan,
+    rules_cache: &[CachedRule],
+    source_dir: &str,
+    adj_type: &str,
+    end_date: &str,
+    query_start_date: &str,
+    need_rows: usize,
+    required_runtime_keys: &HashSet<String>,
+    st_list: &HashSet<String>,
+    total_share_map: &HashMap<String, f64>,
+    profile_map: &HashMap<String, StockProfile>,
+    used_cyq_chen_keys: &HashSet<String>,
+    tc_list: &[String],
+) -> Result<CrossSectionValues, String> {
+    let mut cross_values = if cross_plan.is_empty() {
+        CrossSectionValues::default()
+    } else {
+        let inputs = tc_list
+            .par_chunks(SCORING_GROUP_SIZE)
+            .map(|ts_group| -> Result<Vec<StockCrossInputs>, String> {
+                let worker_reader =
+                    DataReader::new_with_runtime_keys(source_dir, required_runtime_keys)?;
+                cross_section_stock_group_inputs(
+                    &worker_reader,
+                    cross_plan,
+                    source_dir,
+                    adj_type,
+                    end_date,
+                    query_start_date,
+                    need_rows,
+                    st_list,
+                    total_share_map,
+                    profile_map,
+                    used_cyq_chen_keys,
+                    ts_group,
+                )
+            })
+            .try_reduce(Vec::new, |mut left, right| {
+                left.extend(right);
+                Ok(left)
+            })?;
+        CrossSectionValues::compute(cross_plan, inputs)
+    };
+
+    let normalized = normalized_rules(rules_cache);
+    if normalized.is_empty() {
+        return Ok(cross_values);
+    }
+    let normalized_plan = CachedRulesPlan::build(&normalized);
+    let inputs = tc_list
+        .par_chunks(SCORING_GROUP_SIZE)
+        .map(|ts_group| -> Result<Vec<StockRuleScores>, String> {
+            let worker_reader =
+                DataReader::new_with_runtime_keys(source_dir, required_runtime_keys)?;
+            normalize_stock_group_inputs(
+                &worker_reader,
+                &normalized,
+                &normalized_plan,
+                &cross_values,
+                source_dir,
+                adj_type,
+                end_date,
+                query_start_date,
+                need_rows,
+                st_list,
+                total_share_map,
+                profile_map,
+                used_cyq_chen_keys,
+                ts_group,
+            )
+        })
+        .try_reduce(Vec::new, |mut left, right| {
+            left.extend(right);
+            Ok(left)
+        })?;
+    cross_values.merge(normalize_rule_scores(&normalized, inputs));
+    Ok(cross_values)
+}
+
+fn load_rule_scene_meta(
+    source_dir: &str,
+    strategy_path: Option<&str>,
+) -> Result<Vec<RuleSceneMeta>, String> {
+    Ok(
+        ScoreRule::load_rules_with_strategy_path(source_dir, strategy_path)?
+            .into_iter()
+            .map(|rule| RuleSceneMeta {
+                scene_name: rule.scene_name,
+                stage: rule.stage,
+            })
+            .collect(),
+    )
+}
+
+// archive_keep_last 为 Some(n) 时把本次结果另存为版本,并只保留最近 n 个版本
 pub fn scoring_all_to_db(
     source_dir: &str,
     strategy_path: Option<&str>,
     adj_type: &str,
     start_date: &str,
     end_date: &str,
+    archive_keep_last: Option<usize>,
 ) -> Result<ScoringRunProfile, String> {
     let total_started_at = time::Instant::now();
     let out_db = result_db_path(source_dir);
     let init_result_db_started_at = time::Instant::now();
     init_result_db(&out_db)?;
+    // 写库前先清掉策略指纹,中途失败时下次增量评分会按全部规则变化处理
+    let prior_state = load_scoring_state(&out_db)?;
+    save_scoring_state(&out_db, None)?;
     let init_result_db_ms = init_result_db_started_at.elapsed().as_millis() as u64;
 
     let prepare_started_at = time::Instant::now();
     let st_list = load_st_list(source_dir)?;
     let total_share_map = load_total_share_map(source_dir).unwrap_or_default();
+    let profile_map = load_stock_profile_map(source_dir).unwrap_or_default();
     let warmup_need = warmup_rows_estimate(source_dir, strategy_path)?;
     let query_start_date = calc_query_start_date(source_dir, warmup_need, start_date)?;
     let need_rows = calc_query_need_rows(source_dir, warmup_need, start_date, end_date)?;
-    let rules_cache = cache_rule_build(source_dir, strategy_path)?;
+    let mut rules_cache = cache_rule_build(source_dir, strategy_path)?;
+    let rank_order = rank_order_build(source_dir, strategy_path)?;
     let used_cyq_chen_keys = collect_scoring_used_cyq_chen_runtime_keys(&rules_cache);
     let warnings = preview_optional_cyq_chen_injection_warnings(
         source_dir,
@@ -331,18 +669,29 @@ pub fn scoring_all_to_db(
         warmup_need,
         &used_cyq_chen_keys,
     );
-    let required_runtime_keys = collect_scoring_runtime_keys(&rules_cache);
+    let required_runtime_keys = collect_scoring_runtime_keys(&rules_cache, Some(&rank_order));
+    let rule_scene_meta = load_rule_scene_meta(source_dir, strategy_path)?;
+    let scenes = ScoreScene::load_scenes_with_strategy_path(source_dir, strategy_path)?;
+    let fingerprint = strategy_fingerprint(&rules_cache, &rule_scene_meta, &scenes);
+    let cross_plan = CrossSectionPlan::extract(&mut rules_cache)?;
+    let rules_plan = CachedRulesPlan::build(&rules_cache);
     let dr = DataReader::new_with_runtime_keys(source_dir, &required_runtime_keys)?;
     let tc_list = DataReader::list_ts_code(&dr, adj_type, start_date, end_date)?;
-    let rule_scene_meta: Vec<RuleSceneMeta> =
-        ScoreRule::load_rules_with_strategy_path(source_dir, strategy_path)?
-            .into_iter()
-            .map(|rule| RuleSceneMeta {
-                scene_name: rule.scene_name,
-                stage: rule.stage,
-            })
-            .collect();
-    let scenes = ScoreScene::load_scenes_with_strategy_path(source_dir, strategy_path)?;
+    let cross_values = compute_cross_section_values(
+        &cross_plan,
+        &rules_cache,
+        source_dir,
+        adj_type,
+        end_date,
+        &query_start_date,
+        need_rows,
+        &required_runtime_keys,
+        &st_list,
+        &total_share_map,
+        &profile_map,
+        &used_cyq_chen_keys,
+        &tc_list,
+    )?;
     let prepare_ms = prepare_started_at.elapsed().as_millis() as u64;
 
     let out_db_path = out_db
@@ -353,6 +702,10 @@ pub fn scoring_all_to_db(
         .to_str()
         .ok_or_else(|| "原始数据库路径不是有效UTF-8".to_string())?;
 
+    // 排序键先落库,写排名时按它连 score_rank_keys
+    save_rank_order(&out_db, &rank_order.keys)?;
+    save_rule_weighting(&out_db, &rules_cache)?;
+    let tie_break = rank_tie_break(&rank_order);
     let (tx, rx) = sync_channel(SCORING_QUEUE_BOUND);
     let abort_tx = tx.clone();
     let db_path = out_db_path.to_string();
@@ -365,7 +718,7 @@ pub fn scoring_all_to_db(
             &db_path,
             Some(&source_db_path),
             &adj_type_owned,
-            TieBreakWay::KdjJ,
+            tie_break,
             &start_date_owned,
             &end_date_owned,
             rx,
@@ -387,12 +740,17 @@ pub fn scoring_all_to_db(
                 &query_start_date,
                 need_rows,
                 &rules_cache,
+                &rules_plan,
                 &rule_scene_meta,
                 &scenes,
                 &st_list,
                 &total_share_map,
+                &profile_map,
                 &used_cyq_chen_keys,
+                &cross_values,
                 ts_group,
+                None,
+                Some(&rank_order),
                 ScoringMemoryMode::All,
             )?;
             sender
@@ -415,6 +773,28 @@ pub fn scoring_all_to_db(
 
     compute_result?;
     let writer = writer_result?;
+    // 本次区间没盖住已有结果时,库里混着新旧策略的分数,不记指纹
+    let covers_scored_range =
+        prior_state
+            .scored_range
+            .as_ref()
+            .is_none_or(|(min_date, max_date)| {
+                min_date.as_str() >= start_date && max_date.as_str() <= end_date
+            });
+    save_scoring_state(&out_db, covers_scored_range.then_some(&fingerprint))?;
+    let run_id = archive_keep_last
+        .map(|keep_last_n| {
+            archive_scoring_run(
+                &out_db,
+                source_dir,
+                strategy_path,
+                adj_type,
+                start_date,
+                end_date,
+                keep_last_n,
+            )
+        })
+        .transpose()?;
 
     let profile = ScoringRunProfile {
         total_ms: total_started_at.elapsed().as_millis() as u64,
@@ -424,7 +804,463 @@ pub fn scoring_all_to_db(
         stock_count: tc_list.len(),
         writer,
         warnings,
+        run_id,
+        ..ScoringRunProfile::default()
+    };
+    log_scoring_run_profile(&profile);
+    Ok(profile)
+}
+
+// 把结果库当前区间的结果按策略文件哈希和时间另存成一个版本,只保留最近 keep_last_n 个
+fn archive_scoring_run(
+    out_db: &Path,
+    source_dir: &str,
+    strategy_path: Option<&str>,
+    adj_type: &str,
+    start_date: &str,
+    end_date: &str,
+    keep_last_n: usize,
+) -> Result<String, String> {
+    let strategy_file = resolve_strategy_path(source_dir, strategy_path);
+    // 按展开 extends/include 后的内容算哈希,改了被引用的文件也算新版本
+    let strategy_text = resolve_strategy_file(&strategy_file)?.to_toml_string()?;
+    let strategy_hash = strategy_text_hash(&strategy_text);
+    let now = Local::now();
+    let meta = ScoreRunMeta {
+        run_id: format!("{}-{}", now.format("%Y%m%dT%H%M%S%3f"), &strategy_hash[..8]),
+        strategy_hash,
+        strategy_path: strategy_file.to_string_lossy().to_string(),
+        adj_type: adj_type.to_string(),
+        start_date: start_date.to_string(),
+        end_date: end_date.to_string(),
+        created_at: now.format("%Y-%m-%d %H:%M:%S").to_string(),
+    };
+    archive_score_run(out_db, &meta, keep_last_n)?;
+    Ok(meta.run_id)
+}
+
+// 策略没配排序键时沿用J值同分规则
+fn rank_tie_break(rank_order: &RankOrder) -> TieBreakWay {
+    if rank_order.is_empty() {
+        TieBreakWay::KdjJ
+    } else {
+        TieBreakWay::RankOrder
+    }
+}
+
+// 评分批次交给写库线程,计算出错时通知写线程回滚
+fn write_scored_groups_to_db<F>(
+    out_db_path: &str,
+    source_db_path: &str,
+    adj_type: &str,
+    tie_break: TieBreakWay,
+    scope: ScoreWriteScope,
+    ts_codes: &[String],
+    score_group: F,
+) -> Result<(ScoreWriteProfile, u64), String>
+where
+    F: Fn(&[String]) -> Result<ScoreBatch, String> + Sync,
+{
+    let (tx, rx) = sync_channel(SCORING_QUEUE_BOUND);
+    let abort_tx = tx.clone();
+    let db_path = out_db_path.to_string();
+    let source_db_path = source_db_path.to_string();
+    let adj_type_owned = adj_type.to_string();
+    let writer_handle = thread::spawn(move || {
+        write_score_batches_with_scope(
+            &db_path,
+            Some(&source_db_path),
+            &adj_type_owned,
+            tie_break,
+            &scope,
+            rx,
+        )
+    });
+
+    let compute_started_at = time::Instant::now();
+    let compute_result = ts_codes.par_chunks(SCORING_GROUP_SIZE).try_for_each_with(
+        tx,
+        |sender, ts_group| -> Result<(), String> {
+            let batch = score_group(ts_group)?;
+            sender
+                .send(ScoreWriteMessage::Batch(batch))
+                .map_err(|e| format!("发送评分批次失败:{e}"))?;
+            Ok(())
+        },
+    );
+    let compute_ms = compute_started_at.elapsed().as_millis() as u64;
+
+    if let Err(err) = &compute_result {
+        let _ = abort_tx.send(ScoreWriteMessage::Abort(err.clone()));
+    }
+    drop(abort_tx);
+
+    let writer_result = match writer_handle.join() {
+        Ok(result) => result,
+        Err(_) => Err("结果库写线程异常退出".to_string()),
+    };
+
+    compute_result?;
+    Ok((writer_result?, compute_ms))
+}
+
+fn next_trade_date_after(trade_dates: &[String], trade_date: &str) -> Option<String> {
+    let index = trade_dates.partition_point(|date| date.as_str() <= trade_date);
+    trade_dates.get(index).cloned()
+}
+
+// 每只股票从上次评分日的下一个交易日开始补,没评过的从start_date开始
+fn incremental_stock_starts(
+    tc_list: &[String],
+    state: &ScoringState,
+    trade_dates: &[String],
+    start_date: &str,
+    end_date: &str,
+) -> Vec<(String, String)> {
+    tc_list
+        .iter()
+        .filter_map(|ts_code| {
+            let from = match state.stock_last_dates.get(ts_code) {
+                Some(last_date) => next_trade_date_after(trade_dates, last_date)?,
+                None => start_date.to_string(),
+            };
+            let from = from.max(start_date.to_string());
+            (from.as_str() <= end_date).then(|| (ts_code.clone(), from))
+        })
+        .collect()
+}
+
+// 规则或场景定义变了: 只对受影响的规则在已评分区间重算,改写明细、总分和排名
+#[allow(clippy::too_many_arguments)]
+fn refresh_changed_rules_to_db(
+    source_dir: &str,
+    out_db_path: &str,
+    source_db_path: &str,
+    adj_type: &str,
+    tie_break: TieBreakWay,
+    warmup_need: usize,
+    rules_cache: &[CachedRule],
+    rule_scene_meta: &[RuleSceneMeta],
+    scenes: &[ScoreScene],
+    refresh_plan: &RuleRefreshPlan,
+    state: &ScoringState,
+    st_list: &HashSet<String>,
+    total_share_map: &HashMap<String, f64>,
+    profile_map: &HashMap<String, StockProfile>,
+) -> Result<(ScoreWriteProfile, u64), String> {
+    let Some((range_start, range_end)) = state.scored_range.as_ref() else {
+        return Ok((ScoreWriteProfile::default(), 0));
+    };
+    let mut refresh_rules = refresh_plan
+        .eval_rule_indices
+        .iter()
+        .map(|&index| rules_cache[index].clone())
+        .collect::<Vec<_>>();
+    let refresh_meta = refresh_plan
+        .eval_rule_indices
+        .iter()
+        .map(|&index| rule_scene_meta[index].clone())
+        .collect::<Vec<_>>();
+    let used_cyq_chen_keys = collect_scoring_used_cyq_chen_runtime_keys(&refresh_rules);
+    let required_runtime_keys = collect_scoring_runtime_keys(&refresh_rules, None);
+    let cross_plan = CrossSectionPlan::extract(&mut refresh_rules)?;
+    let rules_plan = CachedRulesPlan::build(&refresh_rules);
+    let query_start_date = calc_query_start_date(source_dir, warmup_need, range_start)?;
+    let need_rows = calc_query_need_rows(source_dir, warmup_need, range_start, range_end)?;
+
+    let mut ts_codes = if refresh_rules.is_empty() {
+        Vec::new()
+    } else {
+        state.stock_last_dates.keys().cloned().collect::<Vec<_>>()
+    };
+    ts_codes.sort();
+    let cross_values = compute_cross_section_values(
+        &cross_plan,
+        &refresh_rules,
+        source_dir,
+        adj_type,
+        range_end,
+        &query_start_date,
+        need_rows,
+        &required_runtime_keys,
+        st_list,
+        total_share_map,
+        profile_map,
+        &used_cyq_chen_keys,
+        &ts_codes,
+    )?;
+    let refresh_rule_names = refresh_plan
+        .rule_names
+        .iter()
+        .map(String::as_str)
+        .collect::<HashSet<_>>();
+    let refresh_scene_names = refresh_plan
+        .scene_names
+        .iter()
+        .map(String::as_str)
+        .collect::<HashSet<_>>();
+    write_scored_groups_to_db(
+        out_db_path,
+        source_db_path,
+        adj_type,
+        tie_break,
+        ScoreWriteScope::Rules {
+            rule_names: refresh_plan.rule_names.clone(),
+            scene_names: refresh_plan.scene_names.clone(),
+            start_date: range_start.clone(),
+            end_date: range_end.clone(),
+        },
+        &ts_codes,
+        |ts_group| {
+            let worker_reader =
+                DataReader::new_with_runtime_keys(source_dir, &required_runtime_keys)?;
+            let mut batch = scoring_stock_group_batch(
+                &worker_reader,
+                source_dir,
+                adj_type,
+                range_start,
+                range_end,
+                &query_start_date,
+                need_rows,
+                &refresh_rules,
+                &rules_plan,
+                &refresh_meta,
+                scenes,
+                st_list,
+                total_share_map,
+                profile_map,
+                &used_cyq_chen_keys,
+                &cross_values,
+                ts_group,
+                None,
+                None,
+                ScoringMemoryMode::All,
+            )?;
+            retain_refreshed_rows(
+                &mut batch,
+                &refresh_rule_names,
+                &refresh_scene_names,
+                &state.stock_last_dates,
+            );
+            Ok(batch)
+        },
+    )
+}
+
+// 规则重算只写回受影响规则和场景的行,且只保留各股票已评分过的日期,新日期交给后面的补算
+fn retain_refreshed_rows(
+    batch: &mut ScoreBatch,
+    rule_names: &HashSet<&str>,
+    scene_names: &HashSet<&str>,
+    stock_last_dates: &HashMap<String, String>,
+) {
+    let scored_before = |ts_code: &str, trade_date: &str| {
+        stock_last_dates
+            .get(ts_code)
+            .is_some_and(|last_date| trade_date <= last_date.as_str())
+    };
+    batch.summary_rows.clear();
+    batch.detail_rows.retain(|row| {
+        rule_names.contains(row.rule_name.as_str()) && scored_before(&row.ts_code, &row.trade_date)
+    });
+    batch.scene_rows.retain(|row| {
+        scene_names.contains(row.scene_name.as_str())
+            && scored_before(&row.ts_code, &row.trade_date)
+    });
+    batch.transition_rows.retain(|row| {
+        scene_names.contains(row.scene_name.as_str())
+            && scored_before(&row.ts_code, &row.trade_date)
+    });
+}
+
+// 增量评分: 策略没变只补新交易日,规则或场景变了只重写受影响的明细和排名
+pub fn scoring_incremental_to_db(
+    source_dir: &str,
+    strategy_path: Option<&str>,
+    adj_type: &str,
+    start_date: &str,
+    end_date: &str,
+    archive_keep_last: Option<usize>,
+) -> Result<ScoringRunProfile, String> {
+    let total_started_at = time::Instant::now();
+    let out_db = result_db_path(source_dir);
+    let init_result_db_started_at = time::Instant::now();
+    init_result_db(&out_db)?;
+    let state = load_scoring_state(&out_db)?;
+    let init_result_db_ms = init_result_db_started_at.elapsed().as_millis() as u64;
+
+    let prepare_started_at = time::Instant::now();
+    let st_list = load_st_list(source_dir)?;
+    let total_share_map = load_total_share_map(source_dir).unwrap_or_default();
+    let profile_map = load_stock_profile_map(source_dir).unwrap_or_default();
+    let warmup_need = warmup_rows_estimate(source_dir, strategy_path)?;
+    let mut rules_cache = cache_rule_build(source_dir, strategy_path)?;
+    let rank_order = rank_order_build(source_dir, strategy_path)?;
+    // 已有结果的排序键取值不会重算,排序键变了只能全量评分
+    let stored_rank_keys = {
+        let conn = duckdb::Connection::open(&out_db).map_err(|e| format!("结果库连接失败:{e}"))?;
+        load_rank_order(&conn)?
+    };
+    if state.scored_range.is_some() && stored_rank_keys != rank_order.keys {
+        return Err("策略rank_order排序键已变化,请全量重新评分".to_string());
+    }
+    save_rank_order(&out_db, &rank_order.keys)?;
+    save_rule_weighting(&out_db, &rules_cache)?;
+    let tie_break = rank_tie_break(&rank_order);
+    let rule_scene_meta = load_rule_scene_meta(source_dir, strategy_path)?;
+    let scenes = ScoreScene::load_scenes_with_strategy_path(source_dir, strategy_path)?;
+    let fingerprint = strategy_fingerprint(&rules_cache, &rule_scene_meta, &scenes);
+    let refresh_plan = plan_rule_refresh(&fingerprint, &state.strategy);
+    let used_cyq_chen_keys = collect_scoring_used_cyq_chen_runtime_keys(&rules_cache);
+    let warnings = preview_optional_cyq_chen_injection_warnings(
+        source_dir,
+        start_date,
+        end_date,
+        warmup_need,
+        &used_cyq_chen_keys,
+    );
+    let required_runtime_keys = collect_scoring_runtime_keys(&rules_cache, Some(&rank_order));
+    let dr = DataReader::new_with_runtime_keys(source_dir, &required_runtime_keys)?;
+    let tc_list = DataReader::list_ts_code(&dr, adj_type, start_date, end_date)?;
+    let trade_dates = load_trade_date_list(source_dir)?;
+    let stock_starts =
+        incremental_stock_starts(&tc_list, &state, &trade_dates, start_date, end_date);
+    let prepare_ms = prepare_started_at.elapsed().as_millis() as u64;
+
+    let out_db_path = out_db
+        .to_str()
+        .ok_or_else(|| "结果数据库路径不是有效UTF-8".to_string())?;
+    let source_db = source_db_path(source_dir);
+    let source_db_path = source_db
+        .to_str()
+        .ok_or_else(|| "原始数据库路径不是有效UTF-8".to_string())?;
+
+    let mut compute_and_send_batches_ms = 0;
+    let mut refresh_writer = None;
+    if !refresh_plan.is_empty() && state.scored_range.is_some() {
+        let (writer, compute_ms) = refresh_changed_rules_to_db(
+            source_dir,
+            out_db_path,
+            source_db_path,
+            adj_type,
+            tie_break,
+            warmup_need,
+            &rules_cache,
+            &rule_scene_meta,
+            &scenes,
+            &refresh_plan,
+            &state,
+            &st_list,
+            &total_share_map,
+            &profile_map,
+        )?;
+        compute_and_send_batches_ms += compute_ms;
+        refresh_writer = Some(writer);
+    }
+
+    let mut writer = ScoreWriteProfile::default();
+    if let Some(min_start) = stock_starts.iter().map(|(_, from)| from.clone()).min() {
+        let query_start_date = calc_query_start_date(source_dir, warmup_need, &min_start)?;
+        let need_rows = calc_query_need_rows(source_dir, warmup_need, &min_start, end_date)?;
+        let cross_plan = CrossSectionPlan::extract(&mut rules_cache)?;
+        let rules_plan = CachedRulesPlan::build(&rules_cache);
+        let cross_values = compute_cross_section_values(
+            &cross_plan,
+            &rules_cache,
+            source_dir,
+            adj_type,
+            end_date,
+            &query_start_date,
+            need_rows,
+            &required_runtime_keys,
+            &st_list,
+            &total_share_map,
+            &profile_map,
+            &used_cyq_chen_keys,
+            &tc_list,
+        )?;
+        let ts_codes = stock_starts
+            .iter()
+            .map(|(ts_code, _)| ts_code.clone())
+            .collect::<Vec<_>>();
+        let starts_map = stock_starts.iter().cloned().collect::<HashMap<_, _>>();
+        let (new_dates_writer, compute_ms) = write_scored_groups_to_db(
+            out_db_path,
+            source_db_path,
+            adj_type,
+            tie_break,
+            ScoreWriteScope::Stocks {
+                stock_starts: stock_starts.clone(),
+                end_date: end_date.to_string(),
+            },
+            &ts_codes,
+            |ts_group| {
+                let worker_reader =
+                    DataReader::new_with_runtime_keys(source_dir, &required_runtime_keys)?;
+                scoring_stock_group_batch(
+                    &worker_reader,
+                    source_dir,
+                    adj_type,
+                    &min_start,
+                    end_date,
+                    &query_start_date,
+                    need_rows,
+                    &rules_cache,
+                    &rules_plan,
+                    &rule_scene_meta,
+                    &scenes,
+                    &st_list,
+                    &total_share_map,
+                    &profile_map,
+                    &used_cyq_chen_keys,
+                    &cross_values,
+                    ts_group,
+                    Some(&starts_map),
+                    Some(&rank_order),
+                    ScoringMemoryMode::All,
+                )
+            },
+        )?;
+        compute_and_send_batches_ms += compute_ms;
+        writer = new_dates_writer;
+    }
+    save_scoring_state(&out_db, Some(&fingerprint))?;
+    // 增量后整段结果都对应当前策略,版本覆盖已评分的全部区间
+    let scored_range = load_scoring_state(&out_db)?
+        .scored_range
+        .unwrap_or_else(|| (start_date.to_string(), end_date.to_string()));
+    let run_id = archive_keep_last
+        .map(|keep_last_n| {
+            archive_scoring_run(
+                &out_db,
+                source_dir,
+                strategy_path,
+                adj_type,
+                &scored_range.0,
+                &scored_range.1,
+                keep_last_n,
+            )
+        })
+        .transpose()?;
+
+    let profile = ScoringRunProfile {
+        total_ms: total_started_at.elapsed().as_millis() as u64,
+        init_result_db_ms,
+        prepare_ms,
+        compute_and_send_batches_ms,
+        stock_count: stock_starts.len(),
+        writer,
+        warnings,
+        refreshed_rules: refresh_plan.rule_names,
+        refresh_writer,
+        run_id,
     };
+    println!(
+        "增量评分: 补算股票={}；重算规则={}；重写场景={}",
+        profile.stock_count,
+        profile.refreshed_rules.len(),
+        refresh_plan.scene_names.len(),
+    );
     log_scoring_run_profile(&profile);
     Ok(profile)
 }
@@ -458,10 +1294,11 @@ pub fn scoring_all_to_memory_with_mode(
     let prepare_started_at = time::Instant::now();
     let st_list = load_st_list(source_dir)?;
     let total_share_map = load_total_share_map(source_dir).unwrap_or_default();
+    let profile_map = load_stock_profile_map(source_dir).unwrap_or_default();
     let warmup_need = warmup_rows_estimate(source_dir, strategy_path)?;
     let query_start_date = calc_query_start_date(source_dir, warmup_need, start_date)?;
     let need_rows = calc_query_need_rows(source_dir, warmup_need, start_date, end_date)?;
-    let rules_cache = cache_rule_build(source_dir, strategy_path)?;
+    let mut rules_cache = cache_rule_build(source_dir, strategy_path)?;
     let used_cyq_chen_keys = collect_scoring_used_cyq_chen_runtime_keys(&rules_cache);
     let warnings = preview_optional_cyq_chen_injection_warnings(
         source_dir,
@@ -470,9 +1307,26 @@ pub fn scoring_all_to_memory_with_mode(
         warmup_need,
         &used_cyq_chen_keys,
     );
-    let required_runtime_keys = collect_scoring_runtime_keys(&rules_cache);
+    let required_runtime_keys = collect_scoring_runtime_keys(&rules_cache, None);
+    let cross_plan = CrossSectionPlan::extract(&mut rules_cache)?;
+    let rules_plan = CachedRulesPlan::build(&rules_cache);
     let dr = DataReader::new_with_runtime_keys(source_dir, &required_runtime_keys)?;
     let tc_list = DataReader::list_ts_code(&dr, adj_type, start_date, end_date)?;
+    let cross_values = compute_cross_section_values(
+        &cross_plan,
+        &rules_cache,
+        source_dir,
+        adj_type,
+        end_date,
+        &query_start_date,
+        need_rows,
+        &required_runtime_keys,
+        &st_list,
+        &total_share_map,
+        &profile_map,
+        &used_cyq_chen_keys,
+        &tc_list,
+    )?;
     let rule_scene_meta: Vec<RuleSceneMeta> =
         ScoreRule::load_rules_with_strategy_path(source_dir, strategy_path)?
             .into_iter()
@@ -499,12 +1353,17 @@ pub fn scoring_all_to_memory_with_mode(
                 &query_start_date,
                 need_rows,
                 &rules_cache,
+                &rules_plan,
                 &rule_scene_meta,
                 &scenes,
                 &st_list,
                 &total_share_map,
+                &profile_map,
                 &used_cyq_chen_keys,
+                &cross_values,
                 ts_group,
+                None,
+                None,
                 memory_mode,
             )
         })
@@ -535,6 +1394,7 @@ pub fn scoring_all_to_memory_with_mode(
             ..ScoreWriteProfile::default()
         },
         warnings,
+        ..ScoringRunProfile::default()
     };
     Ok((batch, profile))
 }
@@ -549,13 +1409,37 @@ pub fn scoring_single_period(
 ) -> Result<(Vec<ScoreSummary>, Vec<ScoreDetails>, Vec<SceneDetails>), String> {
     let st_list = load_st_list(source_dir)?;
     let total_share_map = load_total_share_map(source_dir).unwrap_or_default();
+    let profile_map = load_stock_profile_map(source_dir).unwrap_or_default();
     let warmup_need = warmup_rows_estimate(source_dir, strategy_path)?;
     let query_start_date = calc_query_start_date(source_dir, warmup_need, start_date)?;
     let need_rows = calc_query_need_rows(source_dir, warmup_need, start_date, end_date)?;
-    let rules_cache = cache_rule_build(source_dir, strategy_path)?;
+    let mut rules_cache = cache_rule_build(source_dir, strategy_path)?;
     let used_cyq_chen_keys = collect_scoring_used_cyq_chen_runtime_keys(&rules_cache);
-    let required_runtime_keys = collect_scoring_runtime_keys(&rules_cache);
+    let required_runtime_keys = collect_scoring_runtime_keys(&rules_cache, None);
+    let cross_plan = CrossSectionPlan::extract(&mut rules_cache)?;
+    let rules_plan = CachedRulesPlan::build(&rules_cache);
     let dr = DataReader::new_with_runtime_keys(source_dir, &required_runtime_keys)?;
+    // 截面函数和截面标准化要看全市场,单股评分也得先跑一遍全市场
+    let cross_values = if cross_plan.is_empty() && normalized_rules(&rules_cache).is_empty() {
+        CrossSectionValues::default()
+    } else {
+        let tc_list = DataReader::list_ts_code(&dr, adj_type, start_date, end_date)?;
+        compute_cross_section_values(
+            &cross_plan,
+            &rules_cache,
+            source_dir,
+            adj_type,
+            end_date,
+            &query_start_date,
+            need_rows,
+            &required_runtime_keys,
+            &st_list,
+            &total_share_map,
+            &profile_map,
+            &used_cyq_chen_keys,
+            &tc_list,
+        )?
+    };
 
     let mut row_data = DataReader::load_one(&dr, ts_code, adj_type, &query_start_date, end_date)?;
     if row_data.trade_dates.len() < need_rows {
@@ -571,7 +1455,9 @@ pub fn scoring_single_period(
         ts_code,
         st_list.contains(ts_code),
         total_share_map.get(ts_code).copied(),
+        profile_map.get(ts_code),
     )?;
+    cross_values.inject(&mut row_data, ts_code);
     let rule_scene_meta: Vec<RuleSceneMeta> =
         ScoreRule::load_rules_with_strategy_path(source_dir, strategy_path)?
             .into_iter()
@@ -581,15 +1467,360 @@ pub fn scoring_single_period(
             })
             .collect();
     let scenes = ScoreScene::load_scenes_with_strategy_path(source_dir, strategy_path)?;
-    Ok(scoring_single_core(
+    let batch = scoring_single_core(
         row_data,
         ts_code,
         start_date,
         &rules_cache,
+        &rules_plan,
         &rule_scene_meta,
         &scenes,
+        None,
         ScoringMemoryMode::All,
-    )?)
+    )?;
+    Ok((batch.summary_rows, batch.detail_rows, batch.scene_rows))
+}
+
+// 自定义股票池:直接给代码、按同花顺概念、按指数成分股(index_constituents.csv)
+#[derive(Debug, Clone)]
+pub enum ScoringUniverse {
+    Codes(Vec<String>),
+    Concept(String),
+    Index(String),
+}
+
+impl ScoringUniverse {
+    pub fn resolve(&self, source_dir: &str) -> Result<Vec<String>, String> {
+        let raw_codes = match self {
+            Self::Codes(codes) => codes.clone(),
+            Self::Concept(concept) => {
+                let concept = concept.trim();
+                if concept.is_empty() {
+                    return Err("概念名称不能为空".to_string());
+                }
+                load_ths_concepts_list(source_dir)?
+                    .into_iter()
+                    .filter(|cols| {
+                        cols.get(2).is_some_and(|value| {
+                            value
+                                .split(|ch| {
+                                    matches!(ch, ',' | ';' | '，' | '；' | '|' | '、' | '/')
+                                })
+                                .any(|item| item.trim() == concept)
+                        })
+                    })
+                    .filter_map(|cols| cols.into_iter().next())
+                    .collect()
+            }
+            Self::Index(index_code) => {
+                if index_code.trim().is_empty() {
+                    return Err("指数代码不能为空".to_string());
+                }
+                load_index_constituents(source_dir, index_code)?
+            }
+        };
+
+        let mut seen = HashSet::with_capacity(raw_codes.len());
+        Ok(raw_codes
+            .into_iter()
+            .map(|code| code.trim().to_ascii_uppercase())
+            .filter(|code| !code.is_empty() && seen.insert(code.clone()))
+            .collect())
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WhatIfRuleHit {
+    pub rule_name: String,
+    pub rule_score: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WhatIfSceneStage {
+    pub scene_name: String,
+    pub direction: String,
+    pub stage: Option<String>,
+    pub stage_score: f64,
+}
+
+// trade_date 是模拟K线的日期或股票最后一个真实交易日,rank 只在本次股票池内排
+#[derive(Debug, Clone, Serialize)]
+pub struct WhatIfScoreRow {
+    pub ts_code: String,
+    pub trade_date: String,
+    pub simulated: bool,
+    pub total_score: f64,
+    pub risk_score: f64,
+    pub net_score: f64,
+    pub rank: Option<i64>,
+    pub risk_rank: Option<i64>,
+    pub rules: Vec<WhatIfRuleHit>,
+    pub scenes: Vec<WhatIfSceneStage>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct WhatIfScoreResult {
+    pub rows: Vec<WhatIfScoreRow>,
+    pub warnings: Vec<String>,
+}
+
+// 模拟K线没给日期时落在交易日历里最后一根真实K线的下一个交易日
+fn resolve_what_if_bar(
+    trade_calendar: &[String],
+    last_trade_date: &str,
+    bar: &SimulateBarInput,
+) -> Result<SimulateBarInput, String> {
+    let trade_date = match bar
+        .trade_date
+        .as_deref()
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+    {
+        Some(trade_date) => trade_date.to_string(),
+        None => next_trade_date_after(trade_calendar, last_trade_date).ok_or_else(|| {
+            format!("交易日历里找不到{last_trade_date}之后的交易日,请给模拟K线指定日期")
+        })?,
+    };
+    if trade_date.as_str() <= last_trade_date {
+        return Err(format!(
+            "模拟K线日期{trade_date}必须晚于最后一个真实交易日{last_trade_date}"
+        ));
+    }
+    Ok(SimulateBarInput {
+        trade_date: Some(trade_date),
+        ..bar.clone()
+    })
+}
+
+fn what_if_row_from_batch(
+    ts_code: &str,
+    simulated: bool,
+    batch: ScoreBatch,
+) -> Option<WhatIfScoreRow> {
+    let summary = batch.summary_rows.into_iter().last()?;
+    let trade_date = summary.trade_date.clone();
+    Some(WhatIfScoreRow {
+        ts_code: ts_code.to_string(),
+        simulated,
+        total_score: summary.total_score,
+        risk_score: summary.risk_score,
+        net_score: summary.net_score(),
+        rank: None,
+        risk_rank: None,
+        rules: batch
+            .detail_rows
+            .into_iter()
+            .filter(|row| row.trade_date == trade_date)
+            .map(|row| WhatIfRuleHit {
+                rule_name: row.rule_name,
+                rule_score: row.rule_score,
+            })
+            .collect(),
+        scenes: batch
+            .scene_rows
+            .into_iter()
+            .filter(|row| row.trade_date == trade_date)
+            .map(|row| WhatIfSceneStage {
+                scene_name: row.scene_name,
+                direction: row.direction,
+                stage: row.stage,
+                stage_score: row.stage_score,
+            })
+            .collect(),
+        trade_date,
+    })
+}
+
+// 模拟K线那天截面取不到值的规则逐条点名,免得把0分当成没触发
+fn what_if_cross_warnings(cross_names: &[String], normalized_names: &[String]) -> Vec<String> {
+    let mut warnings = Vec::new();
+    if !cross_names.is_empty() {
+        warnings.push(format!(
+            "模拟K线当天没有全市场数据,以下截面函数规则取不到值: {}",
+            cross_names.join(", ")
+        ));
+    }
+    if !normalized_names.is_empty() {
+        warnings.push(format!(
+            "模拟K线当天没有全市场数据,以下截面标准化规则按0分计: {}",
+            normalized_names.join(", ")
+        ));
+    }
+    warnings
+}
+
+// 对任意股票池评分,可以给部分股票追加一根假设的K线,只算结果不写 scoring_result.db。
+// 模拟K线那天没有全市场数据,截面函数和截面标准化取不到值。
+pub fn scoring_what_if(
+    source_dir: &str,
+    strategy_path: Option<&str>,
+    universe: &ScoringUniverse,
+    adj_type: &str,
+    trade_date: &str,
+    what_if_bars: &HashMap<String, SimulateBarInput>,
+) -> Result<WhatIfScoreResult, String> {
+    let ts_codes = universe.resolve(source_dir)?;
+    if ts_codes.is_empty() {
+        return Err("股票池为空".to_string());
+    }
+    let what_if_bars = what_if_bars
+        .iter()
+        .map(|(ts_code, bar)| (ts_code.trim().to_ascii_uppercase(), bar))
+        .collect::<HashMap<_, _>>();
+
+    let trade_calendar = load_trade_date_list(source_dir)?;
+    let st_list = load_st_list(source_dir)?;
+    let total_share_map = load_total_share_map(source_dir).unwrap_or_default();
+    let profile_map = load_stock_profile_map(source_dir).unwrap_or_default();
+    let warmup_need = warmup_rows_estimate(source_dir, strategy_path)?;
+    let query_start_date = calc_query_start_date(source_dir, warmup_need, trade_date)?;
+    let need_rows = calc_query_need_rows(source_dir, warmup_need, trade_date, trade_date)?;
+    let mut rules_cache = cache_rule_build(source_dir, strategy_path)?;
+    let used_cyq_chen_keys = collect_scoring_used_cyq_chen_runtime_keys(&rules_cache);
+    let required_runtime_keys = collect_scoring_runtime_keys(&rules_cache, None);
+    let cross_names = cross_rule_names(&rules_cache);
+    let normalized_names = normalized_rules(&rules_cache)
+        .into_iter()
+        .map(|rule| rule.name)
+        .collect::<Vec<_>>();
+    let cross_plan = CrossSectionPlan::extract(&mut rules_cache)?;
+    let rules_plan = CachedRulesPlan::build(&rules_cache);
+    let dr = DataReader::new_with_runtime_keys(source_dir, &required_runtime_keys)?;
+    let cross_values = if cross_plan.is_empty() && normalized_names.is_empty() {
+        CrossSectionValues::default()
+    } else {
+        let tc_list = DataReader::list_ts_code(&dr, adj_type, trade_date, trade_date)?;
+        compute_cross_section_values(
+            &cross_plan,
+            &rules_cache,
+            source_dir,
+            adj_type,
+            trade_date,
+            &query_start_date,
+            need_rows,
+            &required_runtime_keys,
+            &st_list,
+            &total_share_map,
+            &profile_map,
+            &used_cyq_chen_keys,
+            &tc_list,
+        )?
+    };
+    let indicator_cache = if what_if_bars.is_empty() {
+        Vec::new()
+    } else {
+        cache_ind_build(source_dir)?
+    };
+    let rule_scene_meta = load_rule_scene_meta(source_dir, strategy_path)?;
+    let scenes = ScoreScene::load_scenes_with_strategy_path(source_dir, strategy_path)?;
+
+    let outcomes = ts_codes
+        .par_chunks(SCORING_GROUP_SIZE)
+        .map(
+            |ts_group| -> Result<Vec<Result<Option<WhatIfScoreRow>, String>>, String> {
+                let worker_reader =
+                    DataReader::new_with_runtime_keys(source_dir, &required_runtime_keys)?;
+                let cyq_chen_injector = CyqChenFieldInjector::new(source_dir, &used_cyq_chen_keys);
+                Ok(ts_group
+                    .iter()
+                    .map(|ts_code| -> Result<Option<WhatIfScoreRow>, String> {
+                        let mut rows_map = HashMap::new();
+                        let Some(mut row) = load_scoring_stock_row(
+                            &worker_reader,
+                            &mut rows_map,
+                            ts_code,
+                            adj_type,
+                            trade_date,
+                            need_rows,
+                        )?
+                        else {
+                            return Ok(None);
+                        };
+                        let _ = cyq_chen_injector.inject(&mut row, ts_code);
+                        let last_trade_date = row.trade_dates.last().cloned().unwrap_or_default();
+                        let simulated = match what_if_bars.get(ts_code) {
+                            Some(bar) => {
+                                let bar =
+                                    resolve_what_if_bar(&trade_calendar, &last_trade_date, bar)?;
+                                row = build_simulated_row_data_with_inds(
+                                    &indicator_cache,
+                                    row,
+                                    &bar,
+                                )?;
+                                true
+                            }
+                            None => false,
+                        };
+                        inject_stock_extra_fields(
+                            &mut row,
+                            ts_code,
+                            st_list.contains(ts_code),
+                            total_share_map.get(ts_code).copied(),
+                            profile_map.get(ts_code),
+                        )?;
+                        cross_values.inject(&mut row, ts_code);
+                        let score_date = row.trade_dates.last().cloned().unwrap_or_default();
+                        let batch = scoring_single_core(
+                            row,
+                            ts_code,
+                            &score_date,
+                            &rules_cache,
+                            &rules_plan,
+                            &rule_scene_meta,
+                            &scenes,
+                            None,
+                            ScoringMemoryMode::All,
+                        )?;
+                        Ok(what_if_row_from_batch(ts_code, simulated, batch))
+                    })
+                    .collect())
+            },
+        )
+        .collect::<Result<Vec<_>, String>>()?;
+
+    let mut result = WhatIfScoreResult::default();
+    for (ts_code, outcome) in ts_codes.iter().zip(outcomes.into_iter().flatten()) {
+        match outcome {
+            Ok(Some(row)) => result.rows.push(row),
+            Ok(None) => result
+                .warnings
+                .push(format!("{ts_code}: 没有{trade_date}及以前的行情")),
+            Err(e) => result.warnings.push(format!("{ts_code}: {e}")),
+        }
+    }
+    if result.rows.iter().any(|row| row.simulated) {
+        result
+            .warnings
+            .extend(what_if_cross_warnings(&cross_names, &normalized_names));
+    }
+
+    // 复用总榜的排名口径,同分按代码排
+    let mut summaries = result
+        .rows
+        .iter()
+        .map(|row| ScoreSummary {
+            ts_code: row.ts_code.clone(),
+            trade_date: String::new(),
+            total_score: row.total_score,
+            risk_score: row.risk_score,
+            ..ScoreSummary::default()
+        })
+        .collect::<Vec<_>>();
+    rank_summary_rows_by_score(&mut summaries);
+    let ranks = summaries
+        .into_iter()
+        .map(|row| (row.ts_code, (row.rank, row.risk_rank)))
+        .collect::<HashMap<_, _>>();
+    for row in &mut result.rows {
+        if let Some((rank, risk_rank)) = ranks.get(&row.ts_code) {
+            row.rank = *rank;
+            row.risk_rank = *risk_rank;
+        }
+    }
+    result
+        .rows
+        .sort_by_key(|row| (row.rank.unwrap_or(i64::MAX), row.ts_code.clone()));
+    Ok(result)
 }
 
 #[cfg(test)]
@@ -598,6 +1829,7 @@ mod tests {
     use crate::{
         data::{RuleTag, ScopeWay, collect_assigned_names_from_expr_program},
         expr::parser::{Parser, lex_all},
+        scoring::weighting::RuleWeighting,
     };
 
     fn cached_rule(name: &str, expression: &str) -> CachedRule {
@@ -618,6 +1850,8 @@ mod tests {
             when_ast,
             assigned_names,
             combination: None,
+            weighting: RuleWeighting::default(),
+            rule_refs: Vec::new(),
         }
     }
 
@@ -628,7 +1862,7 @@ mod tests {
             "M := MA(C, 5); M > MY_SCORE_IND AND ZHANG > 0 AND TOTAL_MV_YI <= 300 AND CYQ_TPR > 0.6",
         )];
 
-        let keys = collect_scoring_runtime_keys(&rules);
+        let keys = collect_scoring_runtime_keys(&rules, None);
 
         for required_key in ["C", "MY_SCORE_IND"] {
             assert!(keys.contains(required_key), "missing {required_key}");
@@ -639,4 +1873,182 @@ mod tests {
         }
         assert!(!keys.contains("O"));
     }
+
+    #[test]
+    fn scoring_runtime_keys_keep_cross_section_arguments() {
+        let mut rules = vec![cached_rule(
+            "rule_x",
+            "R := V / MA(V, 5); XPCT(R, INDUSTRY) > 95",
+        )];
+
+        let keys = collect_scoring_runtime_keys(&rules, None);
+        let cross_plan = CrossSectionPlan::extract(&mut rules).expect("extract should succeed");
+
+        assert!(keys.contains("V"));
+        assert!(!keys.contains("INDUSTRY"));
+        assert_eq!(cross_plan.keys(), vec!["__XS0"]);
+        assert!(!collect_scoring_runtime_keys(&rules, None).contains("V"));
+    }
+
+    #[test]
+    fn scoring_universe_resolves_concepts_and_index_constituents() {
+        let source_dir = std::env::temp_dir().join(format!(
+            "lianghua_universe_{}",
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .expect("system time")
+                .as_nanos()
+        ));
+        std::fs::create_dir_all(&source_dir).expect("create source dir");
+        std::fs::write(
+            source_dir.join("stock_concepts.csv"),
+            "ts_code,name,concept\n000001.SZ,平安银行,银行;金融科技\n000002.SZ,万科A,房地产\n300001.SZ,特锐德,\"充电桩,金融科技\"\n",
+        )
+        .expect("write concepts");
+        std::fs::write(
+            source_dir.join("index_constituents.csv"),
+            "index_code,ts_code\n000300.SH,000001.SZ\n000300.SH,000002.SZ\n399006.SZ,300001.SZ\n",
+        )
+        .expect("write constituents");
+        let source_dir_str = source_dir.to_str().expect("source dir utf8");
+
+        assert_eq!(
+            ScoringUniverse::Concept("金融科技".to_string())
+                .resolve(source_dir_str)
+                .expect("concept universe"),
+            vec!["000001.SZ".to_string(), "300001.SZ".to_string()]
+        );
+        assert_eq!(
+            ScoringUniverse::Index("000300.sh".to_string())
+                .resolve(source_dir_str)
+                .expect("index universe"),
+            vec!["000001.SZ".to_string(), "000002.SZ".to_string()]
+        );
+        assert_eq!(
+            ScoringUniverse::Codes(vec![
+                " 600000.sh".to_string(),
+                "600000.SH".to_string(),
+                String::new(),
+            ])
+            .resolve(source_dir_str)
+            .expect("code universe"),
+            vec!["600000.SH".to_string()]
+        );
+
+        std::fs::remove_dir_all(source_dir).expect("remove source dir");
+    }
+
+    #[test]
+    fn rule_refresh_keeps_only_refreshed_rows_on_scored_dates() {
+        let detail = |rule_name: &str, trade_date: &str| ScoreDetails {
+            ts_code: "000001.SZ".to_string(),
+            trade_date: trade_date.to_string(),
+            rule_name: rule_name.to_string(),
+            ..ScoreDetails::default()
+        };
+        let scene = |scene_name: &str, trade_date: &str| SceneDetails {
+            ts_code: "000001.SZ".to_string(),
+            trade_date: trade_date.to_string(),
+            scene_name: scene_name.to_string(),
+            ..SceneDetails::default()
+        };
+        let transition = |scene_name: &str, trade_date: &str| SceneTransition {
+            ts_code: "000001.SZ".to_string(),
+            trade_date: trade_date.to_string(),
+            scene_name: scene_name.to_string(),
+            ..SceneTransition::default()
+        };
+        let mut batch = ScoreBatch {
+            summary_rows: vec![ScoreSummary::default()],
+            detail_rows: vec![
+                detail("r1", "20240102"),
+                detail("r1", "20240103"),
+                detail("r2", "20240102"),
+            ],
+            scene_rows: vec![
+                scene("s1", "20240102"),
+                scene("s1", "20240103"),
+                scene("s2", "20240102"),
+            ],
+            transition_rows: vec![
+                transition("s1", "20240102"),
+                transition("s1", "20240103"),
+                transition("s2", "20240102"),
+            ],
+            rank_key_rows: Vec::new(),
+        };
+        let stock_last_dates = HashMap::from([("000001.SZ".to_string(), "20240102".to_string())]);
+
+        retain_refreshed_rows(
+            &mut batch,
+            &HashSet::from(["r1"]),
+            &HashSet::from(["s1"]),
+            &stock_last_dates,
+        );
+
+        assert!(batch.summary_rows.is_empty());
+        let keys = |rows: Vec<(&str, &str)>| {
+            rows.into_iter()
+                .map(|(name, date)| format!("{name}@{date}"))
+                .collect::<Vec<_>>()
+        };
+        let expected = |name: &str| vec![format!("{name}@20240102")];
+        assert_eq!(
+            keys(
+                batch
+                    .detail_rows
+                    .iter()
+                    .map(|row| (row.rule_name.as_str(), row.trade_date.as_str()))
+                    .collect()
+            ),
+            expected("r1")
+        );
+        assert_eq!(
+            keys(
+                batch
+                    .scene_rows
+                    .iter()
+                    .map(|row| (row.scene_name.as_str(), row.trade_date.as_str()))
+                    .collect()
+            ),
+            expected("s1")
+        );
+        // 场景事件和场景明细一样过滤,未重算的场景和新日期不会重复写入
+        assert_eq!(
+            keys(
+                batch
+                    .transition_rows
+                    .iter()
+                    .map(|row| (row.scene_name.as_str(), row.trade_date.as_str()))
+                    .collect()
+            ),
+            expected("s1")
+        );
+    }
+
+    #[test]
+    fn what_if_bar_defaults_to_next_trade_date() {
+        let calendar = ["20260406", "20260407", "20260408"]
+            .into_iter()
+            .map(str::to_string)
+            .collect::<Vec<_>>();
+        let bar = SimulateBarInput {
+            trade_date: None,
+            open_gap_pct: 0.0,
+            pct_chg: 5.0,
+            pct_chg_relative_to_open: false,
+            volume_ratio: 2.0,
+            upper_shadow_pct: 0.0,
+            lower_shadow_pct: 0.0,
+        };
+
+        let resolved = resolve_what_if_bar(&calendar, "20260407", &bar).expect("resolve bar");
+        assert_eq!(resolved.trade_date.as_deref(), Some("20260408"));
+        assert!(resolve_what_if_bar(&calendar, "20260408", &bar).is_err());
+        let stale = SimulateBarInput {
+            trade_date: Some("20260407".to_string()),
+            ..bar
+        };
+        assert!(resolve_what_if_bar(&calendar, "20260407", &stale).is_err());
+    }
 }
@@ -3,16 +3,20 @@ use std::collections::{HashMap, HashSet};
 use duckdb::{Connection, params};
 
 use crate::data::{
-    RowData, RuleKind, ScopeWay, ScoreRule, cyq_chen_data::init_cyq_chen_db, cyq_chen_db_path,
-    load_stock_list, load_trade_date_list,
+    RowData, RuleKind, ScoreConfig, cyq_chen_data::init_cyq_chen_db, cyq_chen_db_path,
+    load_expression_prelude, load_stock_list, load_ths_concepts_list, load_trade_date_list,
+    ths_concepts_path,
 };
 use crate::expr::eval::{Runtime, Value};
 use crate::expr::{
     parser::Stmts,
-    validation::{
-        estimate_expression_warmup, parse_expression_program, validate_expression_functions,
-    },
+    validation::{parse_expression_program_with_functions, validate_expression_functions},
 };
+use crate::scoring::{
+    graph::{StrategyGraph, scope_warmup},
+    rank_order::parse_rank_order,
+};
+use crate::utils::utils::board_category;
 
 pub const CYQ_CHEN_RUNTIME_FIELDS: [(&str, &str); 16] = [
     ("CYQ_MIN", "min_price"),
@@ -59,17 +63,14 @@ pub fn warmup_rows_estimate(
     source_dir: &str,
     strategy_path: Option<&str>,
 ) -> Result<usize, String> {
-    // 从拿rule原数据开始计算warmup
-    let rules = ScoreRule::load_rules_with_strategy_path(source_dir, strategy_path)?;
+    // 从拿rule原数据开始计算warmup,共享定义和被引用规则的窗口跟着依赖图展开
+    let cfg = ScoreConfig::load_with_strategy_path(source_dir, strategy_path)?;
+    let functions = load_expression_prelude(source_dir)?;
+    let graph = StrategyGraph::build(&cfg, &functions)?;
     let mut all_expr_max_need = 0;
 
-    for rule in rules {
-        let extra_need = match rule.scope_way {
-            ScopeWay::Last => 0,
-            ScopeWay::Any | ScopeWay::Consec(_) | ScopeWay::Each | ScopeWay::Recent => {
-                rule.scope_windows.saturating_sub(1)
-            }
-        };
+    for rule in &cfg.rule {
+        let extra_need = scope_warmup(rule.scope_way, rule.scope_windows);
         let expressions = match rule.kind {
             RuleKind::Single => vec![rule.when.as_str()],
             RuleKind::Combination => rule
@@ -79,14 +80,24 @@ pub fn warmup_rows_estimate(
                 .collect(),
         };
         for expression in expressions {
-            let stmts = parse_expression_program(expression)
+            let stmts = parse_expression_program_with_functions(expression, &functions)
                 .map_err(|e| format!("表达式解析错误在{}:{}", e.idx, e.msg))?;
             validate_expression_functions(&stmts)?;
-            let expression_need = estimate_expression_warmup(&stmts)?;
+            let expression_need = graph.expression_warmup(&stmts)?;
             all_expr_max_need = all_expr_max_need.max(extra_need + expression_need);
         }
     }
 
+    // 排序键表达式也要在评分首日就有值
+    for key in parse_rank_order(&cfg.rank_order)? {
+        if key.is_total_score() {
+            continue;
+        }
+        let stmts = parse_expression_program_with_functions(&key.source, &functions)
+            .map_err(|e| format!("排序键表达式解析错误在{}:{}", e.idx, e.msg))?;
+        all_expr_max_need = all_expr_max_need.max(graph.expression_warmup(&stmts)?);
+    }
+
     Ok(all_expr_max_need)
 }
 
@@ -142,6 +153,8 @@ pub fn rt_max_len(rt: &Runtime) -> usize {
             Value::NumSeries(ns) => ns.len(),
             Value::SharedNumSeries(ns) => ns.len(),
             Value::BoolSeries(bs) => bs.len(),
+            Value::Str(_) => 1,
+            Value::StrSeries(ss) => ss.len(),
         };
         if len > max_len {
             max_len = len;
@@ -638,13 +651,75 @@ pub fn inject_latest_num_fields(
     row_data.validate()
 }
 
+// 股票的静态文本信息,来自stock_list.csv和stock_concepts.csv
+#[derive(Debug, Clone, Default)]
+pub struct StockProfile {
+    pub name: String,
+    pub area: String,
+    pub industry: String,
+    pub concepts: String,
+}
+
+pub fn load_stock_profile_map(source_dir: &str) -> Result<HashMap<String, StockProfile>, String> {
+    let rows = load_stock_list(source_dir)?;
+    let mut out = HashMap::with_capacity(rows.len());
+    for cols in rows {
+        let Some(ts_code) = cols
+            .first()
+            .map(|value| value.trim())
+            .filter(|value| !value.is_empty())
+        else {
+            continue;
+        };
+        let text_at = |idx: usize| {
+            cols.get(idx)
+                .map(|value| value.trim().to_string())
+                .unwrap_or_default()
+        };
+        out.insert(
+            ts_code.to_string(),
+            StockProfile {
+                name: text_at(2),
+                area: text_at(3),
+                industry: text_at(4),
+                concepts: String::new(),
+            },
+        );
+    }
+
+    // 概念文件是可选下载项,没有时概念为空
+    if ths_concepts_path(source_dir).exists() {
+        for cols in load_ths_concepts_list(source_dir)? {
+            let (Some(ts_code), Some(concepts)) = (cols.first(), cols.get(2)) else {
+                continue;
+            };
+            if let Some(profile) = out.get_mut(ts_code.trim()) {
+                profile.concepts = concepts.trim().to_string();
+            }
+        }
+    }
+
+    Ok(out)
+}
+
 pub fn inject_stock_extra_fields(
     row_data: &mut RowData,
     ts_code: &str,
     is_st: bool,
     fallback_total_share: Option<f64>,
+    profile: Option<&StockProfile>,
 ) -> Result<(), String> {
-    inject_constant_num_fields(row_data, &[("ZHANG", Some(calc_zhang_pct(ts_code, is_st)))])?;
+    // 名称带 ST 或处于退市整理的股票都记为 IS_ST,BOARD 仍然只按交易所板块划分
+    let st_flag =
+        is_st || board_category(ts_code, profile.map(|profile| profile.name.as_str())) == "ST";
+    inject_constant_num_fields(
+        row_data,
+        &[
+            ("ZHANG", Some(calc_zhang_pct(ts_code, is_st))),
+            ("IS_ST", Some(if st_flag { 1.0 } else { 0.0 })),
+        ],
+    )?;
+    inject_stock_text_fields(row_data, ts_code, profile);
 
     let len = row_data.trade_dates.len();
     let close_series = row_data.cols.get("C");
@@ -668,6 +743,22 @@ pub fn inject_stock_extra_fields(
     row_data.validate()
 }
 
+// 没有股票信息时文本字段为空串,表达式里引用这些字段不会因变量不存在而报错
+fn inject_stock_text_fields(row_data: &mut RowData, ts_code: &str, profile: Option<&StockProfile>) {
+    let board = board_category(ts_code, None);
+    let fields = [
+        ("INDUSTRY", profile.map(|profile| profile.industry.as_str())),
+        ("AREA", profile.map(|profile| profile.area.as_str())),
+        ("BOARD", Some(board)),
+        ("CONCEPT", profile.map(|profile| profile.concepts.as_str())),
+    ];
+    for (key, value) in fields {
+        row_data
+            .text_cols
+            .insert(key.to_string(), value.unwrap_or_default().to_string());
+    }
+}
+
 pub fn load_total_share_map(source_dir: &str) -> Result<HashMap<String, f64>, String> {
     let rows = load_stock_list(source_dir)?;
     let mut out = HashMap::with_capacity(rows.len());
@@ -700,17 +791,19 @@ pub fn load_total_share_map(source_dir: &str) -> Result<HashMap<String, f64>, St
 mod tests {
     use std::collections::{HashMap, HashSet};
 
-    use super::{inject_optional_cyq_chen_fields, inject_stock_extra_fields};
-    use crate::data::RowData;
+    use super::{StockProfile, inject_optional_cyq_chen_fields, inject_stock_extra_fields};
+    use crate::data::{RowData, scoring_data::row_into_rt};
+    use crate::expr::{eval::Value, validation::parse_expression_program};
 
     #[test]
     fn stock_extra_fields_compute_total_mv_yi_from_total_share_and_close() {
         let mut row_data = RowData {
             trade_dates: vec!["20240102".to_string(), "20240103".to_string()],
             cols: HashMap::from([("C".to_string(), vec![Some(10.0), Some(12.0)])]),
+            text_cols: HashMap::new(),
         };
 
-        inject_stock_extra_fields(&mut row_data, "000001.SZ", false, Some(20_000.0))
+        inject_stock_extra_fields(&mut row_data, "000001.SZ", false, Some(20_000.0), None)
             .expect("inject stock extra fields");
 
         assert_eq!(
@@ -719,6 +812,63 @@ mod tests {
         );
     }
 
+    #[test]
+    fn stock_extra_fields_expose_profile_as_string_runtime_keys() {
+        let mut row_data = RowData {
+            trade_dates: vec!["20240102".to_string(), "20240103".to_string()],
+            cols: HashMap::from([("C".to_string(), vec![Some(10.0), Some(12.0)])]),
+            text_cols: HashMap::new(),
+        };
+        let profile = StockProfile {
+            name: "中科曙光".to_string(),
+            area: "天津".to_string(),
+            industry: "IT设备".to_string(),
+            concepts: "算力,人形机器人".to_string(),
+        };
+
+        inject_stock_extra_fields(&mut row_data, "300001.SZ", false, None, Some(&profile))
+            .expect("inject stock extra fields");
+        assert_eq!(
+            row_data.text_cols.get("BOARD").map(String::as_str),
+            Some("创业/科创")
+        );
+        assert_eq!(
+            row_data.cols.get("IS_ST").map(Vec::as_slice),
+            Some([Some(0.0), Some(0.0)].as_slice())
+        );
+
+        let program = parse_expression_program(
+            "INDUSTRY IN ['IT设备', '半导体'] AND CONTAINS(CONCEPT, '机器人') AND AREA != '北京'",
+        )
+        .expect("parse");
+        let mut rt = row_into_rt(row_data).expect("runtime");
+        assert_eq!(rt.eval_program(&program).expect("eval"), Value::Bool(true));
+    }
+
+    #[test]
+    fn stock_extra_fields_keep_exchange_board_for_st_stocks() {
+        let mut row_data = RowData {
+            trade_dates: vec!["20240102".to_string()],
+            cols: HashMap::from([("C".to_string(), vec![Some(10.0)])]),
+            text_cols: HashMap::new(),
+        };
+        let profile = StockProfile {
+            name: "*ST海创".to_string(),
+            ..StockProfile::default()
+        };
+
+        inject_stock_extra_fields(&mut row_data, "600001.SH", true, None, Some(&profile))
+            .expect("inject stock extra fields");
+        assert_eq!(
+            row_data.text_cols.get("BOARD").map(String::as_str),
+            Some("主板")
+        );
+        assert_eq!(
+            row_data.cols.get("IS_ST").map(Vec::as_slice),
+            Some([Some(1.0)].as_slice())
+        );
+    }
+
     #[test]
     fn stock_extra_fields_prefers_row_total_share_when_present() {
         let mut row_data = RowData {
@@ -727,9 +877,10 @@ mod tests {
                 ("C".to_string(), vec![Some(10.0), Some(12.0)]),
                 ("TOTAL_SHARE".to_string(), vec![Some(30_000.0), None]),
             ]),
+            text_cols: HashMap::new(),
         };
 
-        inject_stock_extra_fields(&mut row_data, "000001.SZ", false, Some(20_000.0))
+        inject_stock_extra_fields(&mut row_data, "000001.SZ", false, Some(20_000.0), None)
             .expect("inject stock extra fields");
 
         assert_eq!(
@@ -743,6 +894,7 @@ mod tests {
         let mut row_data = RowData {
             trade_dates: vec!["20240102".to_string(), "20240103".to_string()],
             cols: HashMap::from([("C".to_string(), vec![Some(10.0), Some(12.0)])]),
+            text_cols: HashMap::new(),
         };
         let used_keys = HashSet::from(["CYQ_TPR".to_string()]);
         let missing_source_dir =
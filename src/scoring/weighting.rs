@@ -0,0 +1,293 @@
+use std::collections::HashMap;
+
+use crate::{
+    data::{NormalizeMode, SceneDirection, ScoreWeighting},
+    expr::eval::{Runtime, Value},
+    scoring::{
+        CachedRule, CachedRulesPlan, cross_section::CrossSectionValues, scoring_rules_raw_cache,
+    },
+};
+
+const NORMALIZE_EPS: f64 = 1e-12;
+
+// 单条规则计入总分的方式,由 [weighting]、规则的 normalize 和场景 weight 合成
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuleWeighting {
+    pub normalize: NormalizeMode,
+    pub weight: f64,
+    // 只作用于标准化后的分数,原始分本身就是分值
+    pub scale: f64,
+    pub cap: Option<f64>,
+    pub decay: f64,
+    // 空头场景的规则不进总分,取反后计入风险分
+    pub direction: SceneDirection,
+}
+
+impl Default for RuleWeighting {
+    fn default() -> Self {
+        Self {
+            normalize: NormalizeMode::Raw,
+            weight: 1.0,
+            scale: 1.0,
+            cap: None,
+            decay: 0.0,
+            direction: SceneDirection::Long,
+        }
+    }
+}
+
+impl RuleWeighting {
+    pub fn resolve(
+        policy: &ScoreWeighting,
+        rule_normalize: Option<NormalizeMode>,
+        scene_weight: f64,
+        direction: SceneDirection,
+    ) -> Self {
+        Self {
+            normalize: rule_normalize.unwrap_or(policy.normalize),
+            weight: scene_weight,
+            scale: policy.scale,
+            cap: policy.cap,
+            decay: policy.decay,
+            direction,
+        }
+    }
+
+    pub fn is_identity(&self) -> bool {
+        self.normalize == NormalizeMode::Raw && self.weight == 1.0 && self.cap.is_none()
+    }
+
+    // 原始分 -> 截面标准化 -> 乘场景权重 -> 超过 cap 的部分按 decay 衰减;未触发的日期贡献为0
+    pub fn apply(
+        &self,
+        rule_name: &str,
+        rt: &Runtime,
+        raw: &[f64],
+        triggered: &[bool],
+    ) -> Result<Vec<f64>, String> {
+        if self.is_identity() {
+            return Ok(raw.to_vec());
+        }
+
+        let normalized = match self.normalize {
+            NormalizeMode::Raw => None,
+            NormalizeMode::Zscore | NormalizeMode::Percentile => {
+                let key = normalized_score_key(rule_name);
+                let value = rt
+                    .vars
+                    .get(&key)
+                    .ok_or_else(|| format!("规则({rule_name})缺少截面标准化结果"))?;
+                Some(
+                    Value::as_num_series(value, raw.len())
+                        .map_err(|e| format!("规则({rule_name})截面标准化结果非数值:{}", e.msg))?,
+                )
+            }
+        };
+
+        Ok(raw
+            .iter()
+            .enumerate()
+            .map(|(i, score)| {
+                if !triggered.get(i).copied().unwrap_or(false) {
+                    return 0.0;
+                }
+                let base = match &normalized {
+                    Some(values) => values[i].unwrap_or(0.0) * self.scale,
+                    None => *score,
+                };
+                soft_cap(base * self.weight, self.cap, self.decay)
+            })
+            .collect())
+    }
+}
+
+fn soft_cap(value: f64, cap: Option<f64>, decay: f64) -> f64 {
+    match cap {
+        Some(cap) if value.abs() > cap => value.signum() * (cap + (value.abs() - cap) * decay),
+        _ => value,
+    }
+}
+
+pub fn normalized_score_key(rule_name: &str) -> String {
+    format!("__NORM:{rule_name}")
+}
+
+// 需要截面标准化的规则,交给额外一遍全市场求原始分
+pub fn normalized_rules(rules_cache: &[CachedRule]) -> Vec<CachedRule> {
+    rules_cache
+        .iter()
+        .filter(|rule| rule.weighting.normalize != NormalizeMode::Raw)
+        .cloned()
+        .collect()
+}
+
+// 单只股票各标准化规则的原始分序列
+#[derive(Debug, Clone)]
+pub struct StockRuleScores {
+    pub ts_code: String,
+    pub trade_dates: Vec<String>,
+    scores: Vec<Vec<f64>>,
+}
+
+impl StockRuleScores {
+    pub fn collect(
+        rt: &mut Runtime,
+        ts_code: &str,
+        trade_dates: Vec<String>,
+        rules: &[CachedRule],
+        rules_plan: &CachedRulesPlan,
+    ) -> Result<Self, String> {
+        let scores = scoring_rules_raw_cache(rt, rules, rules_plan)?
+            .into_iter()
+            .map(|(score, _)| score)
+            .collect();
+        Ok(Self {
+            ts_code: ts_code.to_string(),
+            trade_dates,
+            scores,
+        })
+    }
+}
+
+// 按交易日把全市场的原始分放在一起标准化,未触发的股票按0分参与
+pub fn normalize_rule_scores(
+    rules: &[CachedRule],
+    inputs: Vec<StockRuleScores>,
+) -> CrossSectionValues {
+    let mut outputs: Vec<Vec<Vec<Option<f64>>>> = inputs
+        .iter()
+        .map(|stock| vec![vec![None; stock.trade_dates.len()]; rules.len()])
+        .collect();
+
+    // 按交易日分桶只和股票日期有关, 所有规则共用一份
+    let mut buckets: HashMap<&str, Vec<(usize, usize)>> = HashMap::new();
+    for (stock_idx, stock) in inputs.iter().enumerate() {
+        for (pos, trade_date) in stock.trade_dates.iter().enumerate() {
+            buckets
+                .entry(trade_date.as_str())
+                .or_default()
+                .push((stock_idx, pos));
+        }
+    }
+
+    for (rule_idx, rule) in rules.iter().enumerate() {
+        for members in buckets.values() {
+            let values = members
+                .iter()
+                .map(|(stock_idx, pos)| inputs[*stock_idx].scores[rule_idx][*pos])
+                .collect::<Vec<_>>();
+            let normalized = normalize_values(rule.weighting.normalize, &values);
+            for ((stock_idx, pos), value) in members.iter().zip(normalized) {
+                outputs[*stock_idx][rule_idx][*pos] = Some(value);
+            }
+        }
+    }
+
+    let keys = rules
+        .iter()
+        .map(|rule| normalized_score_key(&rule.name))
+        .collect();
+    let by_stock = inputs
+        .into_iter()
+        .zip(outputs)
+        .map(|(stock, series)| (stock.ts_code, (stock.trade_dates, series)))
+        .collect();
+    CrossSectionValues::from_stock_series(keys, by_stock)
+}
+
+fn normalize_values(mode: NormalizeMode, values: &[f64]) -> Vec<f64> {
+    let n = values.len() as f64;
+    match mode {
+        NormalizeMode::Raw => values.to_vec(),
+        NormalizeMode::Zscore => {
+            let mean = values.iter().sum::<f64>() / n;
+            let std = (values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / n).sqrt();
+            values
+                .iter()
+                .map(|v| {
+                    if std < NORMALIZE_EPS {
+                        0.0
+                    } else {
+                        (v - mean) / std
+                    }
+                })
+                .collect()
+        }
+        NormalizeMode::Percentile => {
+            // 不大于当前值的股票占比,和 XPCT 口径一致但取 0~1
+            let mut sorted = values.to_vec();
+            sorted.sort_by(|a, b| a.total_cmp(b));
+            values
+                .iter()
+                .map(|v| sorted.partition_point(|other| *other <= v + NORMALIZE_EPS) as f64 / n)
+                .collect()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_values_zscore_and_percentile() {
+        let zscores = normalize_values(NormalizeMode::Zscore, &[0.0, 0.0, 0.0, 10.0]);
+        assert!((zscores[3] - 3.0_f64.sqrt()).abs() < 1e-9);
+        assert!((zscores[0] + 1.0 / 3.0_f64.sqrt()).abs() < 1e-9);
+        assert_eq!(
+            normalize_values(NormalizeMode::Zscore, &[2.0, 2.0]),
+            vec![0.0, 0.0]
+        );
+        assert_eq!(
+            normalize_values(NormalizeMode::Percentile, &[0.0, 0.0, 5.0, 10.0]),
+            vec![0.5, 0.5, 0.75, 1.0]
+        );
+    }
+
+    #[test]
+    fn weighting_applies_scene_weight_and_soft_cap() {
+        let rt = Runtime::default();
+        let weighting = RuleWeighting {
+            weight: 2.0,
+            cap: Some(6.0),
+            decay: 0.5,
+            ..RuleWeighting::default()
+        };
+
+        let scores = weighting
+            .apply(
+                "r1",
+                &rt,
+                &[2.0, 5.0, -5.0, 5.0],
+                &[true, true, true, false],
+            )
+            .expect("apply weighting");
+
+        assert_eq!(scores, vec![4.0, 8.0, -8.0, 0.0]);
+    }
+
+    #[test]
+    fn normalized_rule_reads_cross_section_result() {
+        let mut weighting = RuleWeighting {
+            normalize: NormalizeMode::Percentile,
+            scale: 10.0,
+            ..RuleWeighting::default()
+        };
+        let mut rt = Runtime::default();
+        rt.vars.insert(
+            normalized_score_key("r1"),
+            Value::NumSeries(vec![Some(0.75), Some(0.5)]),
+        );
+
+        let scores = weighting
+            .apply("r1", &rt, &[3.0, 0.0], &[true, false])
+            .expect("apply percentile");
+        assert_eq!(scores, vec![7.5, 0.0]);
+
+        weighting.normalize = NormalizeMode::Zscore;
+        let error = weighting
+            .apply("r2", &rt, &[3.0, 0.0], &[true, false])
+            .expect_err("missing normalized series");
+        assert!(error.contains("r2"));
+    }
+}
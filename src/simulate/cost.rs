@@ -0,0 +1,263 @@
+use std::fs;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{data::trade_cost_path, simulate::fp_utils::EPS};
+
+use super::portfolio::BOARD_LOT_SHARES;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FillPriceModel {
+    // 按买点基准(开盘/收盘)或卖出收盘价成交
+    Basis,
+    // 用 (O+H+L+C)/4 近似当日成交均价
+    Vwap,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TradeSide {
+    Buy,
+    Sell,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct TradeCostBreakdown {
+    pub amount: f64,
+    pub commission: f64,
+    pub stamp_duty: f64,
+    pub transfer_fee: f64,
+    pub total: f64,
+}
+
+/// A股交易成本和成交模型,对应数据目录下的 `trade_cost.toml`。
+///
+/// 文件里缺省的字段取 A 股常见费率:佣金万 2.5 最低 5 元、卖出印花税万 5、
+/// 双向过户费十万分之一。成交量约束只作用在买入,卖出视为可全部成交。
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TradeCostConfig {
+    pub commission_rate: f64,
+    pub min_commission: f64,
+    pub stamp_duty_rate: f64,
+    pub transfer_fee_rate: f64,
+    // 单笔买入不超过当日成交额 AMOUNT 的百分比,不填不限制
+    pub max_amount_participation_pct: Option<f64>,
+    pub fill_price: FillPriceModel,
+    // 没有资金账户的模拟验证按这个金额折算每笔股数,用于计算最低佣金
+    pub reference_trade_amount: f64,
+}
+
+impl Default for TradeCostConfig {
+    fn default() -> Self {
+        Self {
+            commission_rate: 0.000_25,
+            min_commission: 5.0,
+            stamp_duty_rate: 0.000_5,
+            transfer_fee_rate: 0.000_01,
+            max_amount_participation_pct: None,
+            fill_price: FillPriceModel::Basis,
+            reference_trade_amount: 100_000.0,
+        }
+    }
+}
+
+impl TradeCostConfig {
+    /// 不计任何费用、按基准价成交,用于对照和测试。
+    pub fn zero() -> Self {
+        Self {
+            commission_rate: 0.0,
+            min_commission: 0.0,
+            stamp_duty_rate: 0.0,
+            transfer_fee_rate: 0.0,
+            ..Self::default()
+        }
+    }
+
+    pub fn parse_from_text(text: &str) -> Result<Self, String> {
+        let config =
+            toml::from_str::<Self>(text).map_err(|e| format!("解析交易成本配置失败:{e}"))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        for (label, value) in [
+            ("commission_rate", self.commission_rate),
+            ("min_commission", self.min_commission),
+            ("stamp_duty_rate", self.stamp_duty_rate),
+            ("transfer_fee_rate", self.transfer_fee_rate),
+        ] {
+            if !value.is_finite() || value < 0.0 {
+                return Err(format!("交易成本配置 {label} 必须是非负有限数字"));
+            }
+        }
+        if let Some(pct) = self.max_amount_participation_pct
+            && (!pct.is_finite() || pct <= 0.0 || pct > 100.0)
+        {
+            return Err(
+                "交易成本配置 max_amount_participation_pct 必须在 (0, 100] 之间".to_string(),
+            );
+        }
+        if !self.reference_trade_amount.is_finite() || self.reference_trade_amount <= 0.0 {
+            return Err("交易成本配置 reference_trade_amount 必须大于 0".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn calc_fee(&self, side: TradeSide, amount: f64) -> TradeCostBreakdown {
+        if !amount.is_finite() || amount <= EPS {
+            return TradeCostBreakdown::default();
+        }
+        let commission = if self.commission_rate > 0.0 || self.min_commission > 0.0 {
+            (amount * self.commission_rate).max(self.min_commission)
+        } else {
+            0.0
+        };
+        let stamp_duty = match side {
+            TradeSide::Buy => 0.0,
+            TradeSide::Sell => amount * self.stamp_duty_rate,
+        };
+        let transfer_fee = amount * self.transfer_fee_rate;
+        TradeCostBreakdown {
+            amount,
+            commission,
+            stamp_duty,
+            transfer_fee,
+            total: commission + stamp_duty + transfer_fee,
+        }
+    }
+
+    /// 当日允许的最大买入金额(元),`day_amount` 为 AMOUNT 字段(千元)。
+    pub fn max_fill_amount(&self, day_amount: Option<f64>) -> Option<f64> {
+        let pct = self.max_amount_participation_pct?;
+        let day_amount = day_amount.filter(|value| value.is_finite() && *value > 0.0)?;
+        Some(day_amount * 1000.0 * pct / 100.0)
+    }
+
+    pub fn resolve_fill_price(
+        &self,
+        basis_price: f64,
+        open: Option<f64>,
+        high: Option<f64>,
+        low: Option<f64>,
+        close: Option<f64>,
+    ) -> f64 {
+        match (self.fill_price, open, high, low, close) {
+            (FillPriceModel::Vwap, Some(open), Some(high), Some(low), Some(close)) => {
+                let vwap = (open + high + low + close) / 4.0;
+                if vwap.is_finite() && vwap > EPS {
+                    vwap
+                } else {
+                    basis_price
+                }
+            }
+            _ => basis_price,
+        }
+    }
+
+    /// 预算内(含买入费用)按整手能买的最大股数。
+    pub fn max_affordable_shares(&self, budget: f64, price: f64) -> u64 {
+        if !budget.is_finite() || !price.is_finite() || price <= EPS {
+            return 0;
+        }
+        let rate = 1.0 + self.commission_rate + self.transfer_fee_rate;
+        let mut lots = (budget / (price * rate) / BOARD_LOT_SHARES as f64).floor() as u64;
+        // 最低佣金可能让按费率估的手数超预算,逐手回退
+        while lots > 0 {
+            let amount = (lots * BOARD_LOT_SHARES) as f64 * price;
+            if amount + self.calc_fee(TradeSide::Buy, amount).total <= budget + EPS {
+                break;
+            }
+            lots -= 1;
+        }
+        lots * BOARD_LOT_SHARES
+    }
+}
+
+/// 读取数据目录下的 `trade_cost.toml`,文件不存在时按 A股默认费率计费。
+pub fn load_trade_cost_config(source_dir: &str) -> Result<TradeCostConfig, String> {
+    let path = trade_cost_path(source_dir);
+    if !path.exists() {
+        return Ok(TradeCostConfig::default());
+    }
+    let text = fs::read_to_string(&path)
+        .map_err(|e| format!("读取交易成本配置失败: path={}, err={e}", path.display()))?;
+    TradeCostConfig::parse_from_text(&text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_fields_use_a_share_defaults() {
+        let config = TradeCostConfig::parse_from_text(
+            "fill_price = \"vwap\"\nmax_amount_participation_pct = 5\n",
+        )
+        .expect("config should parse");
+
+        assert_eq!(config.fill_price, FillPriceModel::Vwap);
+        assert_eq!(config.max_amount_participation_pct, Some(5.0));
+        assert!((config.min_commission - 5.0).abs() < 1e-12);
+        assert!(TradeCostConfig::parse_from_text("unknown = 1").is_err());
+        assert!(TradeCostConfig::parse_from_text("commission_rate = -0.1").is_err());
+    }
+
+    #[test]
+    fn missing_config_file_uses_default_fees() {
+        let source_dir = std::env::temp_dir().join("lianghua_trade_cost_missing");
+        let config = load_trade_cost_config(&source_dir.to_string_lossy())
+            .expect("missing config should fall back");
+
+        assert!((config.commission_rate - 0.000_25).abs() < 1e-12);
+        assert!((config.min_commission - 5.0).abs() < 1e-12);
+        assert!((config.stamp_duty_rate - 0.000_5).abs() < 1e-12);
+        assert!((config.transfer_fee_rate - 0.000_01).abs() < 1e-12);
+    }
+
+    #[test]
+    fn fee_applies_min_commission_and_sell_stamp_duty() {
+        let config = TradeCostConfig::default();
+
+        let buy = config.calc_fee(TradeSide::Buy, 10_000.0);
+        assert!((buy.commission - 5.0).abs() < 1e-9);
+        assert_eq!(buy.stamp_duty, 0.0);
+        assert!((buy.transfer_fee - 0.1).abs() < 1e-9);
+
+        let sell = config.calc_fee(TradeSide::Sell, 100_000.0);
+        assert!((sell.commission - 25.0).abs() < 1e-9);
+        assert!((sell.stamp_duty - 50.0).abs() < 1e-9);
+        assert!((sell.total - 76.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn affordable_shares_leave_room_for_fees() {
+        let config = TradeCostConfig::default();
+
+        // 10000 元买 10 元股票: 1000 股需 10000+5.1 元,只能买 900 股
+        assert_eq!(config.max_affordable_shares(10_000.0, 10.0), 900);
+        assert_eq!(
+            TradeCostConfig::zero().max_affordable_shares(10_000.0, 10.0),
+            1000
+        );
+    }
+
+    #[test]
+    fn participation_cap_and_vwap_fill() {
+        let config = TradeCostConfig {
+            max_amount_participation_pct: Some(10.0),
+            fill_price: FillPriceModel::Vwap,
+            ..TradeCostConfig::default()
+        };
+
+        assert_eq!(config.max_fill_amount(Some(500.0)), Some(50_000.0));
+        assert_eq!(config.max_fill_amount(None), None);
+        let price = config.resolve_fill_price(10.0, Some(10.0), Some(11.0), Some(9.8), Some(10.6));
+        assert!((price - 10.35).abs() < 1e-9);
+        assert_eq!(
+            config.resolve_fill_price(10.0, Some(10.0), None, Some(9.8), Some(10.6)),
+            10.0
+        );
+    }
+}
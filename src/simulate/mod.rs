@@ -1,7 +1,12 @@
+pub mod cost;
 pub mod fp_utils;
+pub mod portfolio;
 pub mod rank;
+pub mod robustness;
 pub mod rule;
 pub mod scene;
+pub mod walk_forward;
+pub mod weight_fit;
 
 use std::collections::HashMap;
 
@@ -0,0 +1,640 @@
+use serde::Serialize;
+
+use crate::simulate::{
+    cost::{TradeCostBreakdown, TradeCostConfig, TradeSide},
+    fp_utils::{EPS, mean, sample_std},
+};
+
+// A股最小买入单位: 一手 100 股
+pub const BOARD_LOT_SHARES: u64 = 100;
+pub const PORTFOLIO_VOLATILITY_WINDOW: usize = 20;
+const TRADING_DAYS_PER_YEAR: f64 = 252.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PortfolioSizingMode {
+    EqualWeight,
+    // 按当日候选的 SCORE 占比分配,分数缺失或全部非正时退回等权
+    ScoreWeighted,
+    // 按近期日收益波动率倒数分配,波动越大仓位越小
+    VolatilityScaled,
+}
+
+impl PortfolioSizingMode {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        match text.trim().to_ascii_lowercase().as_str() {
+            "equal" | "equal_weight" | "等权" => Ok(Self::EqualWeight),
+            "score" | "score_weighted" | "按分数" => Ok(Self::ScoreWeighted),
+            "volatility" | "volatility_scaled" | "按波动率" => Ok(Self::VolatilityScaled),
+            other => Err(format!(
+                "不支持的仓位分配方式: {other}，可选值为 equal_weight/score_weighted/volatility_scaled"
+            )),
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::EqualWeight => "equal_weight",
+            Self::ScoreWeighted => "score_weighted",
+            Self::VolatilityScaled => "volatility_scaled",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PortfolioConfig {
+    pub initial_capital: f64,
+    // 0 表示不限持仓数,此时按当日候选数平分可用资金
+    pub max_position_count: usize,
+    pub sizing_mode: PortfolioSizingMode,
+    pub cost: TradeCostConfig,
+}
+
+#[derive(Debug, Clone)]
+pub struct PortfolioBuyOrder {
+    pub ts_code: String,
+    pub price: f64,
+    pub score: Option<f64>,
+    pub volatility: Option<f64>,
+    // 成交量约束下的最大买入金额(元)
+    pub max_amount: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PortfolioFill {
+    pub trade_date: String,
+    pub ts_code: String,
+    pub side: String,
+    pub shares: u64,
+    pub price: f64,
+    pub amount: f64,
+    pub fee: TradeCostBreakdown,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PortfolioNavPoint {
+    pub trade_date: String,
+    pub cash: f64,
+    pub market_value: f64,
+    pub nav: f64,
+    pub nav_ratio: f64,
+    pub benchmark_nav_ratio: Option<f64>,
+    pub drawdown_pct: f64,
+    pub exposure: f64,
+    pub turnover: f64,
+    pub position_count: usize,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PortfolioMetrics {
+    pub trade_days: usize,
+    pub final_nav: f64,
+    pub total_return_pct: Option<f64>,
+    pub annualized_return_pct: Option<f64>,
+    pub max_drawdown_pct: Option<f64>,
+    pub sharpe: Option<f64>,
+    pub sortino: Option<f64>,
+    pub calmar: Option<f64>,
+    pub avg_daily_turnover: Option<f64>,
+    pub total_turnover: Option<f64>,
+    pub avg_exposure: Option<f64>,
+    pub benchmark_return_pct: Option<f64>,
+    pub excess_return_pct: Option<f64>,
+    pub total_fee: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PortfolioBacktestResult {
+    pub initial_capital: f64,
+    pub sizing_mode: String,
+    pub metrics: PortfolioMetrics,
+    pub nav_points: Vec<PortfolioNavPoint>,
+    pub fills: Vec<PortfolioFill>,
+}
+
+impl PortfolioBacktestResult {
+    /// 逐日净值收益率(%),首日相对初始资金。
+    pub fn daily_returns_pct(&self) -> Vec<(String, f64)> {
+        let mut prev_nav = self.initial_capital;
+        let mut out = Vec::with_capacity(self.nav_points.len());
+        for point in &self.nav_points {
+            if prev_nav > EPS {
+                out.push((
+                    point.trade_date.clone(),
+                    (point.nav / prev_nav - 1.0) * 100.0,
+                ));
+            }
+            prev_nav = point.nav;
+        }
+        out
+    }
+}
+
+#[derive(Debug, Clone)]
+struct PortfolioHolding {
+    ts_code: String,
+    shares: u64,
+    buy_date: String,
+    last_price: f64,
+}
+
+/// 组合账户: 现金、持仓、按手取整的下单和逐日净值。
+///
+/// 引擎本身不关心买卖信号,调用方每个交易日按"先卖后买、收盘估值"的顺序驱动:
+/// `sell` -> `buy` -> `mark_to_market`,最后用 `finish` 汇总指标。
+/// 当日买入的持仓在 `can_sell` 里返回 false,以满足 T+1。
+#[derive(Debug, Clone)]
+pub struct PortfolioEngine {
+    config: PortfolioConfig,
+    cash: f64,
+    holdings: Vec<PortfolioHolding>,
+    fills: Vec<PortfolioFill>,
+    nav_points: Vec<PortfolioNavPoint>,
+    // 历史最高净值(含初始资金),逐日累计算回撤
+    peak_nav: f64,
+    day_traded_amount: f64,
+}
+
+impl PortfolioEngine {
+    pub fn new(config: PortfolioConfig) -> Result<Self, String> {
+        if !config.initial_capital.is_finite() || config.initial_capital <= 0.0 {
+            return Err("初始资金必须是大于 0 的有限数字".to_string());
+        }
+        Ok(Self {
+            cash: config.initial_capital,
+            peak_nav: config.initial_capital,
+            config,
+            holdings: Vec::new(),
+            fills: Vec::new(),
+            nav_points: Vec::new(),
+            day_traded_amount: 0.0,
+        })
+    }
+
+    pub fn cash(&self) -> f64 {
+        self.cash
+    }
+
+    pub fn holds(&self, ts_code: &str) -> bool {
+        self.holdings.iter().any(|item| item.ts_code == ts_code)
+    }
+
+    pub fn can_sell(&self, ts_code: &str, trade_date: &str) -> bool {
+        self.holdings
+            .iter()
+            .any(|item| item.ts_code == ts_code && item.buy_date.as_str() < trade_date)
+    }
+
+    fn market_value(&self) -> f64 {
+        self.holdings
+            .iter()
+            .map(|item| item.shares as f64 * item.last_price)
+            .sum()
+    }
+
+    /// 全部卖出某只持仓,不可卖(未持有或 T+1 未满足)时返回 None。
+    pub fn sell(&mut self, trade_date: &str, ts_code: &str, price: f64) -> Option<PortfolioFill> {
+        if !price.is_finite() || price <= EPS || !self.can_sell(ts_code, trade_date) {
+            return None;
+        }
+        let position = self
+            .holdings
+            .iter()
+            .position(|item| item.ts_code == ts_code)?;
+        let holding = self.holdings.remove(position);
+        let amount = holding.shares as f64 * price;
+        let fee = self.config.cost.calc_fee(TradeSide::Sell, amount);
+        self.cash += amount - fee.total;
+        self.day_traded_amount += amount;
+
+        let fill = PortfolioFill {
+            trade_date: trade_date.to_string(),
+            ts_code: holding.ts_code,
+            side: "sell".to_string(),
+            shares: holding.shares,
+            price,
+            amount,
+            fee,
+        };
+        self.fills.push(fill.clone());
+        Some(fill)
+    }
+
+    /// 按仓位分配方式给当日一批买单下单,返回值和 `orders` 一一对应,
+    /// 资金不足一手或价格无效的单子为 None。
+    ///
+    /// 每个空仓位的目标市值为 `当前净值 / max_position_count`,当日总预算
+    /// 不超过现金;分配方式只决定预算在这批买单间的比例。买入费用从预算里扣,
+    /// 单笔金额同时受 `max_amount` 成交量约束。
+    pub fn buy(&mut self, trade_date: &str, orders: &[PortfolioBuyOrder]) -> Vec<Option<u64>> {
+        if orders.is_empty() {
+            return Vec::new();
+        }
+
+        let nav = self.cash + self.market_value();
+        let slot_value = if self.config.max_position_count == 0 {
+            nav / orders.len() as f64
+        } else {
+            nav / self.config.max_position_count as f64
+        };
+        let budget = (slot_value * orders.len() as f64).min(self.cash);
+        let weights = resolve_order_weights(orders, self.config.sizing_mode);
+
+        let mut out = Vec::with_capacity(orders.len());
+        for (order, weight) in orders.iter().zip(weights) {
+            if self.holds(&order.ts_code) || !order.price.is_finite() || order.price <= EPS {
+                out.push(None);
+                continue;
+            }
+
+            let mut target_amount = (budget * weight).min(self.cash);
+            if let Some(max_amount) = order.max_amount {
+                target_amount = target_amount.min(max_amount);
+            }
+            let shares = self
+                .config
+                .cost
+                .max_affordable_shares(target_amount, order.price);
+            if shares == 0 {
+                out.push(None);
+                continue;
+            }
+
+            let amount = shares as f64 * order.price;
+            let fee = self.config.cost.calc_fee(TradeSide::Buy, amount);
+            self.cash -= amount + fee.total;
+            self.day_traded_amount += amount;
+            self.holdings.push(PortfolioHolding {
+                ts_code: order.ts_code.clone(),
+                shares,
+                buy_date: trade_date.to_string(),
+                last_price: order.price,
+            });
+            self.fills.push(PortfolioFill {
+                trade_date: trade_date.to_string(),
+                ts_code: order.ts_code.clone(),
+                side: "buy".to_string(),
+                shares,
+                price: order.price,
+                amount,
+                fee,
+            });
+            out.push(Some(shares));
+        }
+        out
+    }
+
+    /// 收盘估值并记录当日净值,取不到收盘价(停牌)的持仓沿用上一次价格。
+    pub fn mark_to_market<F>(&mut self, trade_date: &str, close_price: F)
+    where
+        F: Fn(&str) -> Option<f64>,
+    {
+        for holding in &mut self.holdings {
+            if let Some(price) = close_price(&holding.ts_code)
+                && price.is_finite()
+                && price > EPS
+            {
+                holding.last_price = price;
+            }
+        }
+
+        let market_value = self.market_value();
+        let nav = self.cash + market_value;
+        self.peak_nav = self.peak_nav.max(nav);
+        let drawdown_pct = if self.peak_nav > EPS {
+            (self.peak_nav - nav) / self.peak_nav * 100.0
+        } else {
+            0.0
+        };
+        let (exposure, turnover) = if nav > EPS {
+            (market_value / nav, self.day_traded_amount / 2.0 / nav)
+        } else {
+            (0.0, 0.0)
+        };
+
+        self.nav_points.push(PortfolioNavPoint {
+            trade_date: trade_date.to_string(),
+            cash: self.cash,
+            market_value,
+            nav,
+            nav_ratio: nav / self.config.initial_capital,
+            benchmark_nav_ratio: None,
+            drawdown_pct,
+            exposure,
+            turnover,
+            position_count: self.holdings.len(),
+        });
+        self.day_traded_amount = 0.0;
+    }
+
+    /// 汇总净值曲线指标,`benchmark_daily_returns` 为 (交易日, 涨跌幅%) 列表。
+    pub fn finish(mut self, benchmark_daily_returns: &[(String, f64)]) -> PortfolioBacktestResult {
+        let mut benchmark_nav_ratio = 1.0;
+        let mut benchmark_index = 0usize;
+        let mut has_benchmark = false;
+        for point in &mut self.nav_points {
+            while benchmark_index < benchmark_daily_returns.len()
+                && benchmark_daily_returns[benchmark_index].0 <= point.trade_date
+            {
+                if benchmark_daily_returns[benchmark_index].0 == point.trade_date
+                    && benchmark_daily_returns[benchmark_index].1.is_finite()
+                {
+                    benchmark_nav_ratio *= 1.0 + benchmark_daily_returns[benchmark_index].1 / 100.0;
+                    has_benchmark = true;
+                }
+                benchmark_index += 1;
+            }
+            if has_benchmark {
+                point.benchmark_nav_ratio = Some(benchmark_nav_ratio);
+            }
+        }
+
+        let metrics =
+            build_portfolio_metrics(self.config.initial_capital, &self.nav_points, &self.fills);
+        PortfolioBacktestResult {
+            initial_capital: self.config.initial_capital,
+            sizing_mode: self.config.sizing_mode.as_str().to_string(),
+            metrics,
+            nav_points: self.nav_points,
+            fills: self.fills,
+        }
+    }
+}
+
+// 返回归一化到和为 1 的权重,取不到有效权重的单子按其余单子的平均权重补齐
+fn resolve_order_weights(
+    orders: &[PortfolioBuyOrder],
+    sizing_mode: PortfolioSizingMode,
+) -> Vec<f64> {
+    let raw = orders
+        .iter()
+        .map(|order| match sizing_mode {
+            PortfolioSizingMode::EqualWeight => Some(1.0),
+            PortfolioSizingMode::ScoreWeighted => order
+                .score
+                .filter(|score| score.is_finite() && *score > EPS),
+            PortfolioSizingMode::VolatilityScaled => order
+                .volatility
+                .filter(|vol| vol.is_finite() && *vol > EPS)
+                .map(|vol| 1.0 / vol),
+        })
+        .collect::<Vec<_>>();
+
+    let valid = raw.iter().flatten().copied().collect::<Vec<_>>();
+    let fallback = mean(&valid).unwrap_or(1.0);
+    let filled = raw
+        .into_iter()
+        .map(|value| value.unwrap_or(fallback))
+        .collect::<Vec<_>>();
+    let total = filled.iter().sum::<f64>();
+    if total <= EPS {
+        return vec![1.0 / orders.len() as f64; orders.len()];
+    }
+    filled.into_iter().map(|value| value / total).collect()
+}
+
+/// `end_index` 之前(不含)最近 `window` 个日收益的样本标准差,用于波动率定仓。
+pub fn trailing_return_volatility(
+    close_series: &[Option<f64>],
+    end_index: usize,
+    window: usize,
+) -> Option<f64> {
+    let end = end_index.min(close_series.len());
+    let start = end.saturating_sub(window + 1);
+    let returns = close_series[start..end]
+        .windows(2)
+        .filter_map(|pair| match (pair[0], pair[1]) {
+            (Some(prev), Some(curr)) if prev > EPS => Some(curr / prev - 1.0),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    sample_std(&returns)
+}
+
+fn build_portfolio_metrics(
+    initial_capital: f64,
+    nav_points: &[PortfolioNavPoint],
+    fills: &[PortfolioFill],
+) -> PortfolioMetrics {
+    let total_fee = fills.iter().map(|fill| fill.fee.total).sum();
+    let Some(last_point) = nav_points.last() else {
+        return PortfolioMetrics {
+            final_nav: initial_capital,
+            total_fee,
+            ..PortfolioMetrics::default()
+        };
+    };
+
+    let mut daily_returns = Vec::with_capacity(nav_points.len());
+    let mut prev_nav = initial_capital;
+    for point in nav_points {
+        if prev_nav > EPS {
+            daily_returns.push(point.nav / prev_nav - 1.0);
+        }
+        prev_nav = point.nav;
+    }
+
+    let trade_days = nav_points.len();
+    let total_ratio = last_point.nav / initial_capital;
+    let annualized_return = if total_ratio > 0.0 {
+        Some(total_ratio.powf(TRADING_DAYS_PER_YEAR / trade_days as f64) - 1.0)
+    } else {
+        None
+    };
+    let max_drawdown_pct = nav_points
+        .iter()
+        .map(|point| point.drawdown_pct)
+        .fold(0.0, f64::max);
+
+    let annual_scale = TRADING_DAYS_PER_YEAR.sqrt();
+    let avg_return = mean(&daily_returns);
+    let sharpe = match (avg_return, sample_std(&daily_returns)) {
+        (Some(avg), Some(std)) if std > EPS => Some(avg / std * annual_scale),
+        _ => None,
+    };
+    // 下行偏差以 0 为目标收益,分母用全部样本数
+    let downside_deviation = if daily_returns.is_empty() {
+        None
+    } else {
+        Some(
+            (daily_returns
+                .iter()
+                .map(|value| value.min(0.0).powi(2))
+                .sum::<f64>()
+                / daily_returns.len() as f64)
+                .sqrt(),
+        )
+    };
+    let sortino = match (avg_return, downside_deviation) {
+        (Some(avg), Some(downside)) if downside > EPS => Some(avg / downside * annual_scale),
+        _ => None,
+    };
+    let calmar = match annualized_return {
+        Some(annualized) if max_drawdown_pct > EPS => Some(annualized * 100.0 / max_drawdown_pct),
+        _ => None,
+    };
+
+    let turnovers = nav_points
+        .iter()
+        .map(|point| point.turnover)
+        .collect::<Vec<_>>();
+    let exposures = nav_points
+        .iter()
+        .map(|point| point.exposure)
+        .collect::<Vec<_>>();
+    let total_return_pct = (total_ratio - 1.0) * 100.0;
+    let benchmark_return_pct = last_point
+        .benchmark_nav_ratio
+        .map(|ratio| (ratio - 1.0) * 100.0);
+
+    PortfolioMetrics {
+        trade_days,
+        final_nav: last_point.nav,
+        total_return_pct: Some(total_return_pct),
+        annualized_return_pct: annualized_return.map(|value| value * 100.0),
+        max_drawdown_pct: Some(max_drawdown_pct),
+        sharpe,
+        sortino,
+        calmar,
+        avg_daily_turnover: mean(&turnovers),
+        total_turnover: Some(turnovers.iter().sum()),
+        avg_exposure: mean(&exposures),
+        benchmark_return_pct,
+        excess_return_pct: benchmark_return_pct.map(|value| total_return_pct - value),
+        total_fee,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn order(ts_code: &str, price: f64) -> PortfolioBuyOrder {
+        PortfolioBuyOrder {
+            ts_code: ts_code.to_string(),
+            price,
+            score: None,
+            volatility: None,
+            max_amount: None,
+        }
+    }
+
+    fn engine(
+        initial_capital: f64,
+        max_position_count: usize,
+        sizing_mode: PortfolioSizingMode,
+    ) -> PortfolioEngine {
+        PortfolioEngine::new(PortfolioConfig {
+            initial_capital,
+            max_position_count,
+            sizing_mode,
+            cost: TradeCostConfig::zero(),
+        })
+        .expect("engine should build")
+    }
+
+    #[test]
+    fn equal_weight_buy_rounds_to_board_lot() {
+        let mut engine = engine(100_000.0, 4, PortfolioSizingMode::EqualWeight);
+        let shares = engine.buy(
+            "20240102",
+            &[order("000001.SZ", 10.3), order("000002.SZ", 333.0)],
+        );
+
+        // 每仓目标 25000: 25000/10.3=2427 股 -> 2400 股; 25000/333=75 股不足一手
+        assert_eq!(shares, vec![Some(2400), None]);
+        assert!((engine.cash() - (100_000.0 - 2400.0 * 10.3)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn score_weighted_buy_splits_budget_by_score() {
+        let mut engine = engine(100_000.0, 2, PortfolioSizingMode::ScoreWeighted);
+        let mut high = order("000001.SZ", 10.0);
+        high.score = Some(3.0);
+        let mut low = order("000002.SZ", 10.0);
+        low.score = Some(1.0);
+
+        let shares = engine.buy("20240102", &[high, low]);
+
+        assert_eq!(shares, vec![Some(7500), Some(2500)]);
+    }
+
+    #[test]
+    fn same_day_buy_cannot_be_sold() {
+        let mut engine = engine(100_000.0, 1, PortfolioSizingMode::EqualWeight);
+        engine.buy("20240102", &[order("000001.SZ", 10.0)]);
+
+        assert!(engine.sell("20240102", "000001.SZ", 11.0).is_none());
+        engine.mark_to_market("20240102", |_| Some(10.5));
+
+        let fill = engine
+            .sell("20240103", "000001.SZ", 11.0)
+            .expect("next day sell should fill");
+        assert_eq!(fill.shares, 10_000);
+        assert!((engine.cash() - 110_000.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn trade_costs_reduce_cash_and_respect_participation_cap() {
+        let mut engine = PortfolioEngine::new(PortfolioConfig {
+            initial_capital: 100_000.0,
+            max_position_count: 1,
+            sizing_mode: PortfolioSizingMode::EqualWeight,
+            cost: TradeCostConfig::default(),
+        })
+        .expect("engine should build");
+        let mut capped = order("000001.SZ", 10.0);
+        capped.max_amount = Some(20_050.0);
+
+        assert_eq!(engine.buy("20240102", &[capped]), vec![Some(2000)]);
+        assert!((engine.cash() - (100_000.0 - 20_000.0 - 5.2)).abs() < 1e-9);
+
+        let fill = engine
+            .sell("20240103", "000001.SZ", 10.0)
+            .expect("next day sell should fill");
+        assert!((fill.fee.total - (5.0 + 10.0 + 0.2)).abs() < 1e-9);
+        engine.mark_to_market("20240103", |_| None);
+        let result = engine.finish(&[]);
+        assert!((result.metrics.total_fee - 20.4).abs() < 1e-9);
+    }
+
+    #[test]
+    fn finish_reports_drawdown_and_benchmark() {
+        let mut engine = engine(100_000.0, 1, PortfolioSizingMode::EqualWeight);
+        engine.buy("20240102", &[order("000001.SZ", 10.0)]);
+        engine.mark_to_market("20240102", |_| Some(10.0));
+        engine.mark_to_market("20240103", |_| Some(12.0));
+        engine.mark_to_market("20240104", |_| Some(9.0));
+        engine.sell("20240105", "000001.SZ", 10.8);
+        engine.mark_to_market("20240105", |_| None);
+
+        let result = engine.finish(&[
+            ("20240102".to_string(), 1.0),
+            ("20240103".to_string(), -1.0),
+            ("20240105".to_string(), 2.0),
+        ]);
+        let metrics = &result.metrics;
+
+        assert_eq!(metrics.trade_days, 4);
+        assert!((metrics.final_nav - 108_000.0).abs() < 1e-6);
+        assert!((metrics.max_drawdown_pct.unwrap() - 25.0).abs() < 1e-9);
+        assert!((metrics.total_return_pct.unwrap() - 8.0).abs() < 1e-9);
+        assert!((result.nav_points[0].exposure - 1.0).abs() < 1e-9);
+        assert_eq!(result.nav_points[3].position_count, 0);
+        let benchmark = 1.01 * 0.99 * 1.02;
+        assert!((result.nav_points[2].benchmark_nav_ratio.unwrap() - 1.01 * 0.99).abs() < 1e-12);
+        assert!((metrics.benchmark_return_pct.unwrap() - (benchmark - 1.0) * 100.0).abs() < 1e-9);
+        assert!(metrics.sharpe.is_some());
+        assert!(metrics.calmar.is_some());
+    }
+
+    #[test]
+    fn trailing_volatility_uses_only_prior_closes() {
+        let closes = vec![Some(10.0), Some(11.0), Some(10.0), Some(50.0)];
+
+        let vol = trailing_return_volatility(&closes, 3, 20).expect("two returns available");
+        let returns = [0.1, 10.0 / 11.0 - 1.0];
+
+        assert!((vol - sample_std(&returns).unwrap()).abs() < 1e-12);
+        assert!(trailing_return_volatility(&closes, 1, 20).is_none());
+    }
+}
@@ -345,6 +345,8 @@ pub struct RankTopKSummary {
     pub daily_std: Option<f64>,
     pub hac_t_value: Option<f64>,
     pub hac_lag: usize,
+    // 按交易日排列的逐日残差收益,供稳健性重抽样使用
+    pub daily_returns: Vec<f64>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -778,6 +780,7 @@ fn build_rank_top_k_summary(
         daily_std: sample_std(&daily_returns),
         hac_t_value: calc_newey_west_t_value(&daily_returns, hac_lag),
         hac_lag,
+        daily_returns,
     }
 }
 
@@ -1272,6 +1275,7 @@ mod tests {
                     trade_date: trade_date.to_string(),
                     total_score: scores[index],
                     rank: (index == 2).then_some(database_rank),
+                    ..ScoreSummary::default()
                 });
             }
         }
@@ -1282,6 +1286,7 @@ mod tests {
                 trade_date: trade_date.to_string(),
                 total_score: score,
                 rank: None,
+                ..ScoreSummary::default()
             });
         }
 
@@ -1310,6 +1315,7 @@ mod tests {
                 trade_date: trade_date.to_string(),
                 total_score: 10.0,
                 rank: Some(1),
+                ..ScoreSummary::default()
             })
             .collect::<Vec<_>>();
 
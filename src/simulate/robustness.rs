@@ -0,0 +1,349 @@
+use rand::{Rng, SeedableRng, rngs::StdRng};
+use serde::{Deserialize, Serialize};
+
+use crate::simulate::fp_utils::{EPS, mean};
+
+const DEFAULT_ROBUSTNESS_ITERATIONS: usize = 1000;
+const MAX_ROBUSTNESS_ITERATIONS: usize = 10_000;
+const DEFAULT_ROBUSTNESS_CONFIDENCE: f64 = 0.95;
+const DEFAULT_ROBUSTNESS_SEED: u64 = 0x524f_4255_5354_2026;
+
+/// 稳健性分析参数,作为各回测命令的可选项传入。
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RobustnessConfig {
+    pub iterations: usize,
+    pub confidence: f64,
+    // 块自助法的块长,不填时取 max(backtest_period, T^(1/3))
+    pub block_len: Option<usize>,
+    pub seed: u64,
+}
+
+impl Default for RobustnessConfig {
+    fn default() -> Self {
+        Self {
+            iterations: DEFAULT_ROBUSTNESS_ITERATIONS,
+            confidence: DEFAULT_ROBUSTNESS_CONFIDENCE,
+            block_len: None,
+            seed: DEFAULT_ROBUSTNESS_SEED,
+        }
+    }
+}
+
+impl RobustnessConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.iterations == 0 || self.iterations > MAX_ROBUSTNESS_ITERATIONS {
+            return Err(format!(
+                "稳健性分析重抽样次数必须在 1 到 {MAX_ROBUSTNESS_ITERATIONS} 之间"
+            ));
+        }
+        if !self.confidence.is_finite() || self.confidence <= 0.0 || self.confidence >= 1.0 {
+            return Err("稳健性分析置信度必须在 (0, 1) 之间".to_string());
+        }
+        if self.block_len == Some(0) {
+            return Err("稳健性分析块长必须>=1".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct RobustnessInterval {
+    pub estimate: Option<f64>,
+    pub lower: Option<f64>,
+    pub upper: Option<f64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RobustnessSummary {
+    pub method: String,
+    pub iterations: usize,
+    pub confidence: f64,
+    pub block_len: Option<usize>,
+    pub sample_count: usize,
+    // 胜率为 0~1 比例,收益和回撤单位同输入(%)
+    pub win_rate: RobustnessInterval,
+    pub avg_excess_return: RobustnessInterval,
+    // 多空价差序列的均值区间,只有按价差重抽样时才有,此时 avg_excess_return 为空
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub avg_spread: Option<RobustnessInterval>,
+    pub max_drawdown_pct: RobustnessInterval,
+    pub warnings: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct RobustnessSample {
+    pub win_rate: Option<f64>,
+    pub avg_return: Option<f64>,
+    pub max_drawdown_pct: Option<f64>,
+}
+
+impl RobustnessSample {
+    /// `returns` 按时间顺序排列,每个值是一笔持仓收益(%)。资金均分成
+    /// `capital_slots` 份轮流投入,据此估算净值回撤。
+    pub fn from_returns(returns: &[f64], capital_slots: usize) -> Self {
+        if returns.is_empty() {
+            return Self::default();
+        }
+        let slots = capital_slots.max(1) as f64;
+        let mut nav = 1.0;
+        let mut peak = 1.0;
+        let mut max_drawdown: f64 = 0.0;
+        for value in returns {
+            nav *= (1.0 + value / 100.0 / slots).max(0.0);
+            peak = f64::max(peak, nav);
+            if peak > EPS {
+                max_drawdown = max_drawdown.max((1.0 - nav / peak) * 100.0);
+            }
+        }
+
+        Self {
+            win_rate: Some(
+                returns.iter().filter(|value| **value > 0.0).count() as f64 / returns.len() as f64,
+            ),
+            avg_return: mean(returns),
+            max_drawdown_pct: Some(max_drawdown),
+        }
+    }
+}
+
+/// 对逐日收益做循环移动块自助法。每天的值是持有 `backtest_period` 天的收益,
+/// 相邻样本持仓重叠,块长不小于 `backtest_period` 才能保留这段自相关。
+pub fn block_bootstrap_returns(
+    daily_returns: &[f64],
+    backtest_period: usize,
+    config: &RobustnessConfig,
+) -> Option<RobustnessSummary> {
+    let values = daily_returns
+        .iter()
+        .copied()
+        .filter(|value| value.is_finite())
+        .collect::<Vec<_>>();
+    let count = values.len();
+    if count < 2 {
+        return None;
+    }
+    let backtest_period = backtest_period.max(1);
+    let default_block_len = (count as f64).cbrt().ceil() as usize;
+    let block_len = config
+        .block_len
+        .unwrap_or(default_block_len)
+        .max(backtest_period)
+        .min(count);
+
+    let mut rng = StdRng::seed_from_u64(config.seed);
+    let mut resampled = Vec::with_capacity(count);
+    let runs = (0..config.iterations)
+        .map(|_| {
+            resampled.clear();
+            while resampled.len() < count {
+                let start = rng.random_range(0..count);
+                for offset in 0..block_len.min(count - resampled.len()) {
+                    resampled.push(values[(start + offset) % count]);
+                }
+            }
+            RobustnessSample::from_returns(&resampled, backtest_period)
+        })
+        .collect::<Vec<_>>();
+
+    Some(summarize_robustness_runs(
+        "block_bootstrap",
+        RobustnessSample::from_returns(&values, backtest_period),
+        &runs,
+        config,
+        Some(block_len),
+        count,
+    ))
+}
+
+/// 逐日多空价差(%)的块自助法,均值区间写进 `avg_spread` 而不是超额收益。
+pub fn block_bootstrap_spreads(
+    daily_spreads: &[f64],
+    backtest_period: usize,
+    config: &RobustnessConfig,
+) -> Option<RobustnessSummary> {
+    let mut summary = block_bootstrap_returns(daily_spreads, backtest_period, config)?;
+    summary.avg_spread = Some(std::mem::take(&mut summary.avg_excess_return));
+    Some(summary)
+}
+
+/// 按平仓顺序排列的逐笔收益做有放回重抽样,适用于各笔交易互不重叠的场景。
+pub fn bootstrap_trade_returns(
+    trade_returns: &[f64],
+    capital_slots: usize,
+    config: &RobustnessConfig,
+) -> Option<RobustnessSummary> {
+    let values = trade_returns
+        .iter()
+        .copied()
+        .filter(|value| value.is_finite())
+        .collect::<Vec<_>>();
+    if values.len() < 2 {
+        return None;
+    }
+
+    let mut rng = StdRng::seed_from_u64(config.seed);
+    let mut resampled = vec![0.0; values.len()];
+    let runs = (0..config.iterations)
+        .map(|_| {
+            for slot in resampled.iter_mut() {
+                *slot = values[rng.random_range(0..values.len())];
+            }
+            RobustnessSample::from_returns(&resampled, capital_slots)
+        })
+        .collect::<Vec<_>>();
+
+    Some(summarize_robustness_runs(
+        "trade_bootstrap",
+        RobustnessSample::from_returns(&values, capital_slots),
+        &runs,
+        config,
+        None,
+        values.len(),
+    ))
+}
+
+/// 把多次重抽样(或多次随机重跑)的结果汇总成分位数置信区间。
+pub fn summarize_robustness_runs(
+    method: &str,
+    estimate: RobustnessSample,
+    runs: &[RobustnessSample],
+    config: &RobustnessConfig,
+    block_len: Option<usize>,
+    sample_count: usize,
+) -> RobustnessSummary {
+    let interval = |estimate: Option<f64>, pick: fn(&RobustnessSample) -> Option<f64>| {
+        let mut values = runs
+            .iter()
+            .filter_map(pick)
+            .filter(|value| value.is_finite())
+            .collect::<Vec<_>>();
+        values.sort_by(f64::total_cmp);
+        let tail = (1.0 - config.confidence) / 2.0;
+        RobustnessInterval {
+            estimate,
+            lower: percentile(&values, tail),
+            upper: percentile(&values, 1.0 - tail),
+        }
+    };
+
+    RobustnessSummary {
+        method: method.to_string(),
+        iterations: runs.len(),
+        confidence: config.confidence,
+        block_len,
+        sample_count,
+        win_rate: interval(estimate.win_rate, |run| run.win_rate),
+        avg_excess_return: interval(estimate.avg_return, |run| run.avg_return),
+        avg_spread: None,
+        max_drawdown_pct: interval(estimate.max_drawdown_pct, |run| run.max_drawdown_pct),
+        warnings: Vec::new(),
+    }
+}
+
+// 已排序序列的线性插值分位数
+fn percentile(sorted: &[f64], quantile: f64) -> Option<f64> {
+    let last = sorted.len().checked_sub(1)?;
+    let position = quantile.clamp(0.0, 1.0) * last as f64;
+    let lower = position.floor() as usize;
+    let upper = position.ceil() as usize;
+    let weight = position - lower as f64;
+    Some(sorted[lower] * (1.0 - weight) + sorted[upper] * weight)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sample_metrics_use_capital_slots_for_drawdown() {
+        let sample = RobustnessSample::from_returns(&[10.0, -20.0, 5.0, 0.0], 2);
+
+        assert_eq!(sample.win_rate, Some(0.5));
+        assert!((sample.avg_return.unwrap() + 1.25).abs() < 1e-12);
+        // 1.05 -> 0.945,回撤 10%
+        assert!((sample.max_drawdown_pct.unwrap() - 10.0).abs() < 1e-9);
+        assert_eq!(
+            RobustnessSample::from_returns(&[], 1),
+            RobustnessSample::default()
+        );
+    }
+
+    #[test]
+    fn block_bootstrap_interval_brackets_estimate_and_respects_period() {
+        let returns = (0..120)
+            .map(|index| ((index * 37) % 11) as f64 - 4.0)
+            .collect::<Vec<_>>();
+        let config = RobustnessConfig {
+            iterations: 400,
+            ..RobustnessConfig::default()
+        };
+
+        let summary = block_bootstrap_returns(&returns, 10, &config).expect("summary");
+        assert_eq!(summary.method, "block_bootstrap");
+        assert_eq!(summary.block_len, Some(10));
+        assert_eq!(summary.iterations, 400);
+        let avg = summary.avg_excess_return;
+        assert!(avg.lower.unwrap() <= avg.estimate.unwrap());
+        assert!(avg.estimate.unwrap() <= avg.upper.unwrap());
+        assert!(summary.win_rate.upper.unwrap() <= 1.0);
+        assert!(summary.max_drawdown_pct.lower.unwrap() >= 0.0);
+
+        // 同一种子结果可复现
+        assert_eq!(
+            block_bootstrap_returns(&returns, 10, &config),
+            Some(summary)
+        );
+        assert!(block_bootstrap_returns(&[1.0], 1, &config).is_none());
+    }
+
+    #[test]
+    fn spread_bootstrap_reports_spread_instead_of_excess_return() {
+        let spreads = (0..60)
+            .map(|index| ((index * 13) % 7) as f64 - 2.0)
+            .collect::<Vec<_>>();
+        let config = RobustnessConfig {
+            iterations: 200,
+            ..RobustnessConfig::default()
+        };
+
+        let returns = block_bootstrap_returns(&spreads, 5, &config).expect("summary");
+        let summary = block_bootstrap_spreads(&spreads, 5, &config).expect("summary");
+        assert_eq!(summary.avg_spread, Some(returns.avg_excess_return));
+        assert_eq!(summary.avg_excess_return, RobustnessInterval::default());
+        assert_eq!(summary.win_rate, returns.win_rate);
+        assert!(returns.avg_spread.is_none());
+    }
+
+    #[test]
+    fn trade_bootstrap_and_config_validation() {
+        let config = RobustnessConfig {
+            iterations: 200,
+            confidence: 0.9,
+            ..RobustnessConfig::default()
+        };
+        let summary = bootstrap_trade_returns(&[3.0, 3.0, 3.0, 3.0], 1, &config).expect("summary");
+        assert_eq!(summary.win_rate.lower, Some(1.0));
+        assert_eq!(summary.avg_excess_return.upper, Some(3.0));
+        assert_eq!(summary.max_drawdown_pct.upper, Some(0.0));
+
+        assert!(config.validate().is_ok());
+        assert!(
+            RobustnessConfig {
+                confidence: 1.0,
+                ..config
+            }
+            .validate()
+            .is_err()
+        );
+        assert!(
+            RobustnessConfig {
+                block_len: Some(0),
+                ..config
+            }
+            .validate()
+            .is_err()
+        );
+        assert_eq!(percentile(&[1.0, 2.0, 3.0, 4.0, 5.0], 0.25), Some(2.0));
+    }
+}
@@ -1403,6 +1403,92 @@ pub fn collect_all_rule_samples_from_cache(
     .all_samples)
 }
 
+// 单个交易日的规则得分矩阵: 每只股票一行,只存触发规则的(规则下标, 得分),未触发视为0
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuleScoreMatrixDay {
+    pub trade_date: String,
+    pub residual_returns: Vec<f64>,
+    pub hits: Vec<Vec<(usize, f64)>>,
+}
+
+// 规则权重拟合用的样本: 和规则分层回测同一套未来残差收益,按规则名顺序排列列
+#[allow(clippy::too_many_arguments)]
+pub fn load_rule_score_matrix_from_db(
+    source_conn: &Connection,
+    source_dir: &str,
+    rule_names: &[String],
+    stock_adj_type: &str,
+    index_ts_code: &str,
+    index_beta: f64,
+    concept_beta: f64,
+    industry_beta: f64,
+    start_date: &str,
+    end_date: &str,
+    layer_config: &RuleLayerConfig,
+) -> Result<Vec<RuleScoreMatrixDay>, String> {
+    let runtime_cache = build_rule_layer_runtime_cache(
+        source_conn,
+        source_dir,
+        stock_adj_type,
+        index_ts_code,
+        index_beta,
+        concept_beta,
+        industry_beta,
+        start_date,
+        end_date,
+        layer_config,
+    )?;
+    let triggered_score_map_by_rule = load_triggered_score_maps_for_names_filtered(
+        source_dir, rule_names, start_date, end_date, None,
+    )?;
+    let empty_triggered_score_map = TriggeredScoreMap::new();
+    let triggered_score_maps = rule_names
+        .iter()
+        .map(|rule_name| {
+            triggered_score_map_by_rule
+                .get(rule_name)
+                .unwrap_or(&empty_triggered_score_map)
+        })
+        .collect::<Vec<_>>();
+
+    let mut out = Vec::with_capacity(runtime_cache.day_groups.len());
+    for day_group in &runtime_cache.day_groups {
+        let samples = day_group
+            .samples
+            .iter()
+            .filter(|sample| sample.residual_return.is_finite())
+            .collect::<Vec<_>>();
+        if samples.len() < layer_config.min_samples_per_day {
+            continue;
+        }
+        let hits = samples
+            .iter()
+            .map(|sample| {
+                triggered_score_maps
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(rule_index, score_map)| {
+                        score_map
+                            .get(sample.ts_code.as_ref())
+                            .and_then(|date_score| date_score.get(day_group.trade_date.as_ref()))
+                            .filter(|score| score.is_finite())
+                            .map(|score| (rule_index, *score))
+                    })
+                    .collect()
+            })
+            .collect();
+        out.push(RuleScoreMatrixDay {
+            trade_date: day_group.trade_date.to_string(),
+            residual_returns: samples
+                .iter()
+                .map(|sample| sample.residual_return)
+                .collect(),
+            hits,
+        });
+    }
+    Ok(out)
+}
+
 pub fn calc_rule_layer_metrics(
     samples: &[RuleSample],
     config: &RuleLayerConfig,
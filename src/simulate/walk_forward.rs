@@ -0,0 +1,838 @@
+use std::collections::{HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::simulate::fp_utils::{EPS, mean, sample_std};
+
+pub const MAX_SWEEP_COMBINATIONS: usize = 64;
+const MAX_SWEEP_VALUES_PER_PARAM: usize = 256;
+const TRADING_DAYS_PER_YEAR: f64 = 252.0;
+const DEFAULT_TRAIN_DAYS: usize = 250;
+const DEFAULT_TEST_DAYS: usize = 60;
+// CSCV 把样本切成偶数块,8 块对应 70 种训练/测试组合
+const PBO_PARTITION_COUNT: usize = 8;
+const EULER_GAMMA: f64 = 0.577_215_664_901_532_9;
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct SweepParamRange {
+    pub name: String,
+    pub start: f64,
+    pub end: f64,
+    pub step: f64,
+}
+
+impl SweepParamRange {
+    /// 解析 `N in 5..30 step 5`,两端都包含,省略 `step` 时步长为 1。
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let text = text.trim();
+        let (name, rest) = text
+            .split_once(" in ")
+            .ok_or_else(|| format!("参数定义格式错误:{text},应为 `N in 5..30 step 5`"))?;
+        let name = name.trim();
+        let (range, step) = match rest.split_once(" step ") {
+            Some((range, step)) => (range.trim(), Some(step.trim())),
+            None => (rest.trim(), None),
+        };
+        let (start, end) = range
+            .split_once("..")
+            .ok_or_else(|| format!("参数 {name} 的区间格式错误:{range}"))?;
+        let parse_number = |value: &str, label: &str| {
+            value
+                .trim()
+                .trim_start_matches('=')
+                .parse::<f64>()
+                .map_err(|e| format!("解析参数 {name} 的{label}失败:{value}, err={e}"))
+        };
+
+        let range = Self {
+            name: name.to_string(),
+            start: parse_number(start, "起点")?,
+            end: parse_number(end, "终点")?,
+            step: step.map_or(Ok(1.0), |value| parse_number(value, "步长"))?,
+        };
+        range.values()?;
+        Ok(range)
+    }
+
+    fn values(&self) -> Result<Vec<f64>, String> {
+        let name = self.name.as_str();
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|ch| ch.is_ascii_alphanumeric() || ch == '_')
+        {
+            return Err(format!("参数名只能包含字母、数字和下划线:{name}"));
+        }
+        if !self.start.is_finite() || !self.end.is_finite() || !self.step.is_finite() {
+            return Err(format!("参数 {name} 存在非法数值"));
+        }
+        if self.step <= 0.0 {
+            return Err(format!("参数 {name} 的 step 必须 > 0"));
+        }
+        if self.end < self.start {
+            return Err(format!("参数 {name} 的终点不能小于起点"));
+        }
+
+        let mut values = Vec::new();
+        let mut index = 0usize;
+        loop {
+            // 用起点加倍数累加,避免浮点步长误差越滚越大
+            let value = self.start + self.step * index as f64;
+            if value > self.end + self.step * 1e-9 {
+                break;
+            }
+            values.push(value.min(self.end));
+            index += 1;
+            if values.len() > MAX_SWEEP_VALUES_PER_PARAM {
+                return Err(format!("参数 {name} 的取值数量过多,请增大 step 或缩小范围"));
+            }
+        }
+        Ok(values)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SweepParamValue {
+    pub name: String,
+    pub value: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SweepCombo {
+    pub combo_key: String,
+    pub label: String,
+    pub values: Vec<SweepParamValue>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct WalkForwardConfig {
+    pub train_days: usize,
+    pub test_days: usize,
+    // 窗口每次向后滚动的交易日数,不填等于 test_days
+    pub step_days: Option<usize>,
+}
+
+impl Default for WalkForwardConfig {
+    fn default() -> Self {
+        Self {
+            train_days: DEFAULT_TRAIN_DAYS,
+            test_days: DEFAULT_TEST_DAYS,
+            step_days: None,
+        }
+    }
+}
+
+impl WalkForwardConfig {
+    fn validate(&self) -> Result<usize, String> {
+        if self.train_days < 2 || self.test_days < 2 {
+            return Err("训练窗口和测试窗口都至少需要 2 个交易日".to_string());
+        }
+        let step_days = self.step_days.unwrap_or(self.test_days);
+        // 步长小于测试窗口会让样本外区间重叠,拼接后的样本外收益会重复计算
+        if step_days < self.test_days {
+            return Err("滚动步长不能小于测试窗口长度".to_string());
+        }
+        Ok(step_days)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct WalkForwardWindow {
+    pub train_start: String,
+    pub train_end: String,
+    pub test_start: String,
+    pub test_end: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct SweepPeriodMetrics {
+    pub day_count: usize,
+    pub total_return_pct: Option<f64>,
+    pub avg_daily_return_pct: Option<f64>,
+    pub sharpe: Option<f64>,
+    pub max_drawdown_pct: Option<f64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SweepCandidateReturns {
+    pub combo: SweepCombo,
+    // (trade_date, 当日收益率%),没有出现的交易日按 0 收益处理
+    pub daily_returns: Vec<(String, f64)>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WalkForwardWindowResult {
+    pub window: WalkForwardWindow,
+    pub selected_combo_key: String,
+    pub in_sample: SweepPeriodMetrics,
+    pub out_of_sample: SweepPeriodMetrics,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SweepComboSummary {
+    pub combo: SweepCombo,
+    pub full_period: SweepPeriodMetrics,
+    pub selected_window_count: usize,
+    pub avg_in_sample_sharpe: Option<f64>,
+    pub avg_out_of_sample_sharpe: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WalkForwardReport {
+    pub windows: Vec<WalkForwardWindowResult>,
+    pub combos: Vec<SweepComboSummary>,
+    // 每个窗口训练期选中参数的样本内夏普均值
+    pub in_sample_avg_sharpe: Option<f64>,
+    // 各窗口选中参数在测试期的收益首尾拼接
+    pub out_of_sample: SweepPeriodMetrics,
+    pub sharpe_efficiency: Option<f64>,
+    pub best_combo_key: Option<String>,
+    // 全区间最优参数的 Deflated Sharpe Ratio,即扣除多重试验后夏普 > 0 的概率
+    pub deflated_sharpe: Option<f64>,
+    // CSCV 估计的回测过拟合概率:样本内最优参数在样本外落到中位数以下的比例
+    pub pbo: Option<f64>,
+    pub pbo_split_count: usize,
+}
+
+/// 按参数区间展开笛卡尔积,组合数超过 [`MAX_SWEEP_COMBINATIONS`] 时报错。
+pub fn expand_sweep_combos(ranges: &[SweepParamRange]) -> Result<Vec<SweepCombo>, String> {
+    let mut seen = HashSet::new();
+    let mut groups = Vec::with_capacity(ranges.len());
+    let mut total = 1usize;
+    for range in ranges {
+        let name = range.name.trim();
+        if !seen.insert(name.to_string()) {
+            return Err(format!("参数名称重复: {name}"));
+        }
+        let values = range.values()?;
+        total = total.saturating_mul(values.len());
+        if total > MAX_SWEEP_COMBINATIONS {
+            return Err(format!(
+                "参数组合过多({total}),当前上限为 {MAX_SWEEP_COMBINATIONS}"
+            ));
+        }
+        groups.push((name.to_string(), values));
+    }
+
+    let mut assignments = vec![Vec::<SweepParamValue>::new()];
+    for (name, values) in &groups {
+        assignments = assignments
+            .into_iter()
+            .flat_map(|prefix| {
+                values.iter().map(move |value| {
+                    let mut next = prefix.clone();
+                    next.push(SweepParamValue {
+                        name: name.clone(),
+                        value: *value,
+                    });
+                    next
+                })
+            })
+            .collect();
+    }
+
+    Ok(assignments
+        .into_iter()
+        .enumerate()
+        .map(|(index, values)| {
+            let label = if values.is_empty() {
+                "默认参数".to_string()
+            } else {
+                values
+                    .iter()
+                    .map(|item| format!("{}={}", item.name, format_sweep_value(item.value)))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            };
+            SweepCombo {
+                combo_key: format!("sweep_combo_{:03}", index + 1),
+                label,
+                values,
+            }
+        })
+        .collect())
+}
+
+/// 列出表达式里出现的 `{NAME}` 占位符,按首次出现顺序去重。
+pub fn collect_sweep_placeholders(template: &str) -> Vec<String> {
+    let mut out = Vec::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let after = &rest[start + 1..];
+        let Some(end) = after.find('}') else {
+            break;
+        };
+        let name = after[..end].trim();
+        if !name.is_empty() && !out.iter().any(|item| item == name) {
+            out.push(name.to_string());
+        }
+        rest = &after[end + 1..];
+    }
+    out
+}
+
+/// 把 `{NAME}` 替换成组合里的取值,出现未定义的占位符时报错。
+pub fn fill_sweep_placeholders(template: &str, combo: &SweepCombo) -> Result<String, String> {
+    let value_map = combo
+        .values
+        .iter()
+        .map(|item| (item.name.as_str(), format_sweep_value(item.value)))
+        .collect::<HashMap<_, _>>();
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let end = after
+            .find('}')
+            .ok_or_else(|| format!("表达式占位符缺少右花括号:{template}"))?;
+        let name = after[..end].trim();
+        let value = value_map
+            .get(name)
+            .ok_or_else(|| format!("表达式占位符 {{{name}}} 没有对应的参数定义"))?;
+        out.push_str(value);
+        rest = &after[end + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+fn format_sweep_value(value: f64) -> String {
+    let rounded = value.round();
+    if (value - rounded).abs() < 1e-9 {
+        return format!("{rounded:.0}");
+    }
+    let text = format!("{value:.6}");
+    text.trim_end_matches('0').trim_end_matches('.').to_string()
+}
+
+/// 在交易日序列上切出滚动的 训练期 + 测试期 窗口,不足一个完整测试期的尾部丢弃。
+fn build_walk_forward_windows(
+    trade_dates: &[String],
+    config: &WalkForwardConfig,
+) -> Result<Vec<(usize, usize, usize)>, String> {
+    let step_days = config.validate()?;
+    let mut windows = Vec::new();
+    let mut train_start = 0usize;
+    while train_start + config.train_days + config.test_days <= trade_dates.len() {
+        let test_start = train_start + config.train_days;
+        windows.push((train_start, test_start, test_start + config.test_days));
+        train_start += step_days;
+    }
+    if windows.is_empty() {
+        return Err(format!(
+            "区间内只有 {} 个交易日,不足一个训练期({})加测试期({})",
+            trade_dates.len(),
+            config.train_days,
+            config.test_days
+        ));
+    }
+    Ok(windows)
+}
+
+/// 日收益率(%)序列的区间指标,夏普按 252 个交易日年化。
+pub fn calc_sweep_period_metrics(daily_returns_pct: &[f64]) -> SweepPeriodMetrics {
+    if daily_returns_pct.is_empty() {
+        return SweepPeriodMetrics::default();
+    }
+    let mut nav = 1.0;
+    let mut peak = 1.0;
+    let mut max_drawdown: f64 = 0.0;
+    for value in daily_returns_pct {
+        nav *= 1.0 + value / 100.0;
+        peak = f64::max(peak, nav);
+        if peak > EPS {
+            max_drawdown = max_drawdown.max((1.0 - nav / peak) * 100.0);
+        }
+    }
+
+    SweepPeriodMetrics {
+        day_count: daily_returns_pct.len(),
+        total_return_pct: Some((nav - 1.0) * 100.0),
+        avg_daily_return_pct: mean(daily_returns_pct),
+        sharpe: per_period_sharpe(daily_returns_pct)
+            .map(|value| value * TRADING_DAYS_PER_YEAR.sqrt()),
+        max_drawdown_pct: Some(max_drawdown),
+    }
+}
+
+/// 滚动窗口样本内选参、样本外检验,并在全区间上计算 Deflated Sharpe 和 PBO。
+pub fn run_walk_forward_analysis(
+    trade_dates: &[String],
+    candidates: &[SweepCandidateReturns],
+    config: &WalkForwardConfig,
+) -> Result<WalkForwardReport, String> {
+    if candidates.is_empty() {
+        return Err("参数扫描没有可用的组合".to_string());
+    }
+    let windows = build_walk_forward_windows(trade_dates, config)?;
+    let date_index = trade_dates
+        .iter()
+        .enumerate()
+        .map(|(index, trade_date)| (trade_date.as_str(), index))
+        .collect::<HashMap<_, _>>();
+    let matrix = candidates
+        .iter()
+        .map(|candidate| {
+            let mut series = vec![0.0; trade_dates.len()];
+            for (trade_date, value) in &candidate.daily_returns {
+                if let Some(index) = date_index.get(trade_date.as_str())
+                    && value.is_finite()
+                {
+                    series[*index] = *value;
+                }
+            }
+            series
+        })
+        .collect::<Vec<_>>();
+
+    let mut window_results = Vec::with_capacity(windows.len());
+    let mut selected_counts = vec![0usize; candidates.len()];
+    let mut in_sample_sharpes = vec![Vec::new(); candidates.len()];
+    let mut out_of_sample_sharpes = vec![Vec::new(); candidates.len()];
+    let mut stitched_out_of_sample = Vec::new();
+    let mut selected_in_sample_sharpes = Vec::new();
+    for (train_start, test_start, test_end) in windows {
+        let in_sample = matrix
+            .iter()
+            .map(|series| calc_sweep_period_metrics(&series[train_start..test_start]))
+            .collect::<Vec<_>>();
+        let selected = select_best_metrics(&in_sample);
+        for (index, series) in matrix.iter().enumerate() {
+            if let Some(sharpe) = in_sample[index].sharpe {
+                in_sample_sharpes[index].push(sharpe);
+            }
+            if let Some(sharpe) = calc_sweep_period_metrics(&series[test_start..test_end]).sharpe {
+                out_of_sample_sharpes[index].push(sharpe);
+            }
+        }
+        selected_counts[selected] += 1;
+        if let Some(sharpe) = in_sample[selected].sharpe {
+            selected_in_sample_sharpes.push(sharpe);
+        }
+        let test_slice = &matrix[selected][test_start..test_end];
+        stitched_out_of_sample.extend_from_slice(test_slice);
+        window_results.push(WalkForwardWindowResult {
+            window: WalkForwardWindow {
+                train_start: trade_dates[train_start].clone(),
+                train_end: trade_dates[test_start - 1].clone(),
+                test_start: trade_dates[test_start].clone(),
+                test_end: trade_dates[test_end - 1].clone(),
+            },
+            selected_combo_key: candidates[selected].combo.combo_key.clone(),
+            in_sample: in_sample[selected].clone(),
+            out_of_sample: calc_sweep_period_metrics(test_slice),
+        });
+    }
+
+    let full_metrics = matrix
+        .iter()
+        .map(|series| calc_sweep_period_metrics(series))
+        .collect::<Vec<_>>();
+    let best_index = select_best_metrics(&full_metrics);
+    let combos = candidates
+        .iter()
+        .enumerate()
+        .map(|(index, candidate)| SweepComboSummary {
+            combo: candidate.combo.clone(),
+            full_period: full_metrics[index].clone(),
+            selected_window_count: selected_counts[index],
+            avg_in_sample_sharpe: mean(&in_sample_sharpes[index]),
+            avg_out_of_sample_sharpe: mean(&out_of_sample_sharpes[index]),
+        })
+        .collect::<Vec<_>>();
+
+    let in_sample_avg_sharpe = mean(&selected_in_sample_sharpes);
+    let out_of_sample = calc_sweep_period_metrics(&stitched_out_of_sample);
+    let sharpe_efficiency = match (out_of_sample.sharpe, in_sample_avg_sharpe) {
+        (Some(out), Some(inside)) if inside.abs() > EPS => Some(out / inside),
+        _ => None,
+    };
+    let (pbo, pbo_split_count) = match calc_probability_of_backtest_overfitting(&matrix) {
+        Some((pbo, split_count)) => (Some(pbo), split_count),
+        None => (None, 0),
+    };
+
+    Ok(WalkForwardReport {
+        windows: window_results,
+        combos,
+        in_sample_avg_sharpe,
+        out_of_sample,
+        sharpe_efficiency,
+        best_combo_key: Some(candidates[best_index].combo.combo_key.clone()),
+        deflated_sharpe: calc_deflated_sharpe_ratio(&matrix, best_index),
+        pbo,
+        pbo_split_count,
+    })
+}
+
+fn per_period_sharpe(values: &[f64]) -> Option<f64> {
+    match (mean(values), sample_std(values)) {
+        (Some(avg), Some(std)) if std > EPS => Some(avg / std),
+        _ => None,
+    }
+}
+
+// 夏普优先,算不出夏普的组合排在最后;同分取总收益高的,再按组合顺序
+fn select_best_metrics(metrics: &[SweepPeriodMetrics]) -> usize {
+    let key = |item: &SweepPeriodMetrics| {
+        (
+            item.sharpe.unwrap_or(f64::NEG_INFINITY),
+            item.total_return_pct.unwrap_or(f64::NEG_INFINITY),
+        )
+    };
+    let mut best = 0usize;
+    for index in 1..metrics.len() {
+        let (sharpe, total) = key(&metrics[index]);
+        let (best_sharpe, best_total) = key(&metrics[best]);
+        if sharpe > best_sharpe || (sharpe == best_sharpe && total > best_total) {
+            best = index;
+        }
+    }
+    best
+}
+
+// Bailey & López de Prado (2014):用各组合夏普的离散度估计 N 次试验下的期望最大夏普,
+// 再按偏度峰度修正后的标准误检验最优组合是否显著超过它
+fn calc_deflated_sharpe_ratio(matrix: &[Vec<f64>], best_index: usize) -> Option<f64> {
+    let best_series = matrix.get(best_index)?;
+    let observations = best_series.len();
+    if observations < 3 {
+        return None;
+    }
+    let best_sharpe = per_period_sharpe(best_series)?;
+    let trial_sharpes = matrix
+        .iter()
+        .filter_map(|series| per_period_sharpe(series))
+        .collect::<Vec<_>>();
+    let expected_max_sharpe = if trial_sharpes.len() >= 2 {
+        let trial_std = sample_std(&trial_sharpes)?;
+        let trials = trial_sharpes.len() as f64;
+        trial_std
+            * ((1.0 - EULER_GAMMA) * inverse_normal_cdf(1.0 - 1.0 / trials)
+                + EULER_GAMMA * inverse_normal_cdf(1.0 - 1.0 / (trials * std::f64::consts::E)))
+    } else {
+        0.0
+    };
+
+    let avg = mean(best_series)?;
+    let std = sample_std(best_series)?;
+    let count = observations as f64;
+    let skew = best_series
+        .iter()
+        .map(|value| ((value - avg) / std).powi(3))
+        .sum::<f64>()
+        / count;
+    let kurtosis = best_series
+        .iter()
+        .map(|value| ((value - avg) / std).powi(4))
+        .sum::<f64>()
+        / count;
+    let variance = 1.0 - skew * best_sharpe + (kurtosis - 1.0) / 4.0 * best_sharpe * best_sharpe;
+    if variance <= EPS {
+        return None;
+    }
+    Some(normal_cdf(
+        (best_sharpe - expected_max_sharpe) * (count - 1.0).sqrt() / variance.sqrt(),
+    ))
+}
+
+// CSCV:样本按时间切成 S 块,任取一半做样本内、其余做样本外,
+// 统计样本内最优组合在样本外排名落到中位数及以下的比例
+fn calc_probability_of_backtest_overfitting(matrix: &[Vec<f64>]) -> Option<(f64, usize)> {
+    let combo_count = matrix.len();
+    let observations = matrix.first()?.len();
+    let block_size = observations / PBO_PARTITION_COUNT;
+    if combo_count < 2 || block_size < 2 {
+        return None;
+    }
+
+    let half = PBO_PARTITION_COUNT / 2;
+    let mut overfit_count = 0usize;
+    let mut split_count = 0usize;
+    for mask in 0u32..(1 << PBO_PARTITION_COUNT) {
+        if mask.count_ones() as usize != half {
+            continue;
+        }
+        let mut in_sample = Vec::with_capacity(combo_count);
+        let mut out_of_sample = Vec::with_capacity(combo_count);
+        for series in matrix {
+            let mut inside = Vec::with_capacity(block_size * half);
+            let mut outside = Vec::with_capacity(block_size * half);
+            for block in 0..PBO_PARTITION_COUNT {
+                let slice = &series[block * block_size..(block + 1) * block_size];
+                if mask & (1 << block) != 0 {
+                    inside.extend_from_slice(slice);
+                } else {
+                    outside.extend_from_slice(slice);
+                }
+            }
+            in_sample.push(per_period_sharpe(&inside).unwrap_or(f64::NEG_INFINITY));
+            out_of_sample.push(per_period_sharpe(&outside).unwrap_or(f64::NEG_INFINITY));
+        }
+
+        let selected = in_sample
+            .iter()
+            .enumerate()
+            .fold(0usize, |best, (index, value)| {
+                if *value > in_sample[best] {
+                    index
+                } else {
+                    best
+                }
+            });
+        let selected_value = out_of_sample[selected];
+        let below = out_of_sample
+            .iter()
+            .filter(|value| **value < selected_value)
+            .count() as f64;
+        let ties = out_of_sample
+            .iter()
+            .filter(|value| **value == selected_value)
+            .count() as f64;
+        // 并列取平均名次,相对名次 ω = rank / (N + 1),logit(ω) <= 0 视为过拟合
+        let rank = below + (ties + 1.0) / 2.0;
+        let relative_rank = rank / (combo_count as f64 + 1.0);
+        if relative_rank <= 0.5 {
+            overfit_count += 1;
+        }
+        split_count += 1;
+    }
+
+    Some((overfit_count as f64 / split_count as f64, split_count))
+}
+
+fn normal_cdf(value: f64) -> f64 {
+    0.5 * (1.0 + erf(value / std::f64::consts::SQRT_2))
+}
+
+// Abramowitz & Stegun 7.1.26,绝对误差 < 1.5e-7
+fn erf(value: f64) -> f64 {
+    let sign = value.signum();
+    let x = value.abs();
+    let t = 1.0 / (1.0 + 0.327_591_1 * x);
+    let poly = t
+        * (0.254_829_592
+            + t * (-0.284_496_736
+                + t * (1.421_413_741 + t * (-1.453_152_027 + t * 1.061_405_429))));
+    sign * (1.0 - poly * (-x * x).exp())
+}
+
+// Acklam 的有理逼近,相对误差约 1.15e-9
+fn inverse_normal_cdf(p: f64) -> f64 {
+    const A: [f64; 6] = [
+        -3.969_683_028_665_376e1,
+        2.209_460_984_245_205e2,
+        -2.759_285_104_469_687e2,
+        1.383_577_518_672_69e2,
+        -3.066_479_806_614_716e1,
+        2.506_628_277_459_239,
+    ];
+    const B: [f64; 5] = [
+        -5.447_609_879_822_406e1,
+        1.615_858_368_580_409e2,
+        -1.556_989_798_598_866e2,
+        6.680_131_188_771_972e1,
+        -1.328_068_155_288_572e1,
+    ];
+    const C: [f64; 6] = [
+        -7.784_894_002_430_293e-3,
+        -3.223_964_580_411_365e-1,
+        -2.400_758_277_161_838,
+        -2.549_732_539_343_734,
+        4.374_664_141_464_968,
+        2.938_163_982_698_783,
+    ];
+    const D: [f64; 4] = [
+        7.784_695_709_041_462e-3,
+        3.224_671_290_700_398e-1,
+        2.445_134_137_142_996,
+        3.754_408_661_907_416,
+    ];
+    const P_LOW: f64 = 0.024_25;
+
+    if p <= 0.0 {
+        return f64::NEG_INFINITY;
+    }
+    if p >= 1.0 {
+        return f64::INFINITY;
+    }
+    if p < P_LOW {
+        let q = (-2.0 * p.ln()).sqrt();
+        return (((((C[0] * q + C[1]) * q + C[2]) * q + C[3]) * q + C[4]) * q + C[5])
+            / ((((D[0] * q + D[1]) * q + D[2]) * q + D[3]) * q + 1.0);
+    }
+    if p > 1.0 - P_LOW {
+        let q = (-2.0 * (1.0 - p).ln()).sqrt();
+        return -(((((C[0] * q + C[1]) * q + C[2]) * q + C[3]) * q + C[4]) * q + C[5])
+            / ((((D[0] * q + D[1]) * q + D[2]) * q + D[3]) * q + 1.0);
+    }
+    let q = p - 0.5;
+    let r = q * q;
+    (((((A[0] * r + A[1]) * r + A[2]) * r + A[3]) * r + A[4]) * r + A[5]) * q
+        / (((((B[0] * r + B[1]) * r + B[2]) * r + B[3]) * r + B[4]) * r + 1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dates(count: usize) -> Vec<String> {
+        (0..count).map(|index| format!("2024{index:04}")).collect()
+    }
+
+    fn candidate(
+        combo: &SweepCombo,
+        trade_dates: &[String],
+        value: impl Fn(usize) -> f64,
+    ) -> SweepCandidateReturns {
+        SweepCandidateReturns {
+            combo: combo.clone(),
+            daily_returns: trade_dates
+                .iter()
+                .enumerate()
+                .map(|(index, trade_date)| (trade_date.clone(), value(index)))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn param_range_parses_inclusive_steps_and_fills_placeholders() {
+        let range = SweepParamRange::parse("N in 5..30 step 5").expect("range should parse");
+        assert_eq!(
+            range.values().unwrap(),
+            vec![5.0, 10.0, 15.0, 20.0, 25.0, 30.0]
+        );
+        let ratio = SweepParamRange::parse("K in 0.5..1").expect("default step");
+        assert_eq!(ratio.values().unwrap(), vec![0.5]);
+        assert!(SweepParamRange::parse("N 5..30").is_err());
+        assert!(SweepParamRange::parse("N in 30..5").is_err());
+
+        let combos = expand_sweep_combos(&[range, SweepParamRange::parse("M in 1..2").unwrap()])
+            .expect("combos should expand");
+        assert_eq!(combos.len(), 12);
+        assert_eq!(combos[1].label, "N=5, M=2");
+        assert_eq!(
+            fill_sweep_placeholders("C > MA(C, {N}) AND C > MA(C, { M })", &combos[1]).unwrap(),
+            "C > MA(C, 5) AND C > MA(C, 2)"
+        );
+        assert!(fill_sweep_placeholders("MA(C, {X})", &combos[1]).is_err());
+        assert_eq!(
+            collect_sweep_placeholders("MA(C, {N}) > MA(C, {M}) + {N}"),
+            vec!["N".to_string(), "M".to_string()]
+        );
+
+        let too_many = SweepParamRange::parse("N in 1..100").unwrap();
+        assert!(expand_sweep_combos(&[too_many]).is_err());
+    }
+
+    #[test]
+    fn walk_forward_selects_in_sample_best_and_stitches_out_of_sample() {
+        let trade_dates = dates(40);
+        let combos = expand_sweep_combos(&[SweepParamRange::parse("N in 1..2").unwrap()]).unwrap();
+        // 组合 1 前 20 天好、后 20 天差,组合 2 反过来
+        let first = candidate(&combos[0], &trade_dates, |index| {
+            let base = if index < 20 { 1.0 } else { -1.0 };
+            base + (index % 3) as f64 * 0.1
+        });
+        let second = candidate(&combos[1], &trade_dates, |index| {
+            let base = if index < 20 { -0.5 } else { 0.5 };
+            base + (index % 2) as f64 * 0.1
+        });
+        let report = run_walk_forward_analysis(
+            &trade_dates,
+            &[first, second],
+            &WalkForwardConfig {
+                train_days: 10,
+                test_days: 10,
+                step_days: None,
+            },
+        )
+        .expect("walk forward should run");
+
+        assert_eq!(report.windows.len(), 3);
+        assert_eq!(report.windows[0].selected_combo_key, "sweep_combo_001");
+        assert_eq!(report.windows[0].window.test_start, "20240010");
+        assert_eq!(report.windows[2].selected_combo_key, "sweep_combo_002");
+        assert_eq!(report.out_of_sample.day_count, 30);
+        // 第二个窗口在训练期选了组合 1,测试期恰好反转,样本外必然亏损
+        assert!(report.windows[1].out_of_sample.total_return_pct.unwrap() < 0.0);
+        assert!(report.in_sample_avg_sharpe.unwrap() > 0.0);
+        assert_eq!(
+            report
+                .combos
+                .iter()
+                .map(|item| item.selected_window_count)
+                .sum::<usize>(),
+            3
+        );
+        assert!(
+            build_walk_forward_windows(
+                &dates(15),
+                &WalkForwardConfig {
+                    train_days: 10,
+                    test_days: 10,
+                    step_days: None,
+                }
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn overfitting_diagnostics_flag_noise_and_keep_real_edge() {
+        let trade_dates = dates(160);
+        let combos = expand_sweep_combos(&[SweepParamRange::parse("N in 1..8").unwrap()]).unwrap();
+        // 8 组纯噪声:相位不同的确定性伪随机序列
+        let noise = combos
+            .iter()
+            .enumerate()
+            .map(|(offset, combo)| {
+                candidate(combo, &trade_dates, move |index| {
+                    (((index * 7919 + offset * 104_729) % 1000) as f64 / 1000.0 - 0.5) * 2.0
+                })
+            })
+            .collect::<Vec<_>>();
+        let report = run_walk_forward_analysis(
+            &trade_dates,
+            &noise,
+            &WalkForwardConfig {
+                train_days: 60,
+                test_days: 20,
+                step_days: None,
+            },
+        )
+        .expect("walk forward should run");
+        assert_eq!(report.pbo_split_count, 70);
+        assert!(report.pbo.unwrap() > 0.2);
+        assert!(report.deflated_sharpe.unwrap() < 0.95);
+
+        // 其中一组换成稳定正收益,最优组合的 DSR 应接近 1、PBO 应很低
+        let mut edged = noise.clone();
+        edged[3] = candidate(&combos[3], &trade_dates, |index| {
+            0.8 + (index % 5) as f64 * 0.1
+        });
+        let report = run_walk_forward_analysis(
+            &trade_dates,
+            &edged,
+            &WalkForwardConfig {
+                train_days: 60,
+                test_days: 20,
+                step_days: None,
+            },
+        )
+        .expect("walk forward should run");
+        assert_eq!(report.best_combo_key.as_deref(), Some("sweep_combo_004"));
+        assert!(report.deflated_sharpe.unwrap() > 0.99);
+        assert!(report.pbo.unwrap() < 0.05);
+    }
+
+    #[test]
+    fn normal_helpers_round_trip() {
+        for p in [0.01, 0.2, 0.5, 0.8, 0.99] {
+            assert!((normal_cdf(inverse_normal_cdf(p)) - p).abs() < 1e-6);
+        }
+        assert!((inverse_normal_cdf(0.975) - 1.959_964).abs() < 1e-5);
+    }
+}
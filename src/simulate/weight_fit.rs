@@ -0,0 +1,727 @@
+use std::{fs, ops::Range, path::Path};
+
+use crate::data::strategy_file::resolve_strategy_file;
+use crate::simulate::{
+    fp_utils::{EPS, mean, sample_std, spearman_corr},
+    rule::RuleScoreMatrixDay,
+};
+
+const COORDINATE_DESCENT_MAX_ITER: usize = 500;
+const COORDINATE_DESCENT_TOL: f64 = 1e-9;
+const PROPOSED_POINTS_DECIMALS: f64 = 10_000.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WeightFitMethod {
+    Ridge,
+    Lasso,
+    // 按各规则训练期日度 Rank IC 均值分配权重
+    RankIc,
+}
+
+impl WeightFitMethod {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        match text.trim().to_ascii_lowercase().as_str() {
+            "ridge" => Ok(Self::Ridge),
+            "lasso" => Ok(Self::Lasso),
+            "ic" | "rank_ic" => Ok(Self::RankIc),
+            other => Err(format!("拟合方法不支持: {other},仅支持 ridge/lasso/ic")),
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Ridge => "ridge",
+            Self::Lasso => "lasso",
+            Self::RankIc => "ic",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct WeightFitConfig {
+    pub method: WeightFitMethod,
+    // 标准化后的惩罚系数,rank_ic 不用
+    pub lambda: f64,
+    pub folds: usize,
+    // 训练段末尾留空的交易日数,避免持有期收益和验证段重叠,一般取回测周期
+    pub embargo_days: usize,
+}
+
+impl Default for WeightFitConfig {
+    fn default() -> Self {
+        Self {
+            method: WeightFitMethod::Ridge,
+            lambda: 0.1,
+            folds: 4,
+            embargo_days: 1,
+        }
+    }
+}
+
+impl WeightFitConfig {
+    fn validate(&self) -> Result<(), String> {
+        if !self.lambda.is_finite() || self.lambda < 0.0 {
+            return Err("lambda必须是>=0的有限数字".to_string());
+        }
+        if self.folds == 0 {
+            return Err("走步折数必须>=1".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WalkForwardSplit {
+    pub train: Range<usize>,
+    pub validation: Range<usize>,
+}
+
+// 按时间切成 folds+1 段,第k折用前k段训练、第k+1段验证,训练段末尾去掉 embargo 天
+pub fn walk_forward_splits(
+    day_count: usize,
+    folds: usize,
+    embargo_days: usize,
+) -> Vec<WalkForwardSplit> {
+    let block = day_count / (folds + 1);
+    if block == 0 {
+        return Vec::new();
+    }
+    (1..=folds)
+        .filter_map(|fold| {
+            let validation_start = fold * block;
+            let validation_end = if fold == folds {
+                day_count
+            } else {
+                (fold + 1) * block
+            };
+            let train_end = validation_start.saturating_sub(embargo_days);
+            (train_end > 0).then_some(WalkForwardSplit {
+                train: 0..train_end,
+                validation: validation_start..validation_end,
+            })
+        })
+        .collect()
+}
+
+// 逐日截面去均值后的协方差,规则多时也只有 p*p 大小
+struct DesignStats {
+    rule_count: usize,
+    sample_count: usize,
+    gram: Vec<f64>,
+    xy: Vec<f64>,
+}
+
+impl DesignStats {
+    fn collect(days: &[RuleScoreMatrixDay], rule_count: usize) -> Self {
+        let mut stats = Self {
+            rule_count,
+            sample_count: 0,
+            gram: vec![0.0; rule_count * rule_count],
+            xy: vec![0.0; rule_count],
+        };
+        for day in days {
+            let n = day.residual_returns.len();
+            if n == 0 {
+                continue;
+            }
+            let return_mean = day.residual_returns.iter().sum::<f64>() / n as f64;
+            let mut score_sums = vec![0.0; rule_count];
+            for (row, residual_return) in day.hits.iter().zip(&day.residual_returns) {
+                for (left_pos, (left, left_score)) in row.iter().enumerate() {
+                    score_sums[*left] += left_score;
+                    stats.xy[*left] += left_score * (residual_return - return_mean);
+                    for (right, right_score) in &row[left_pos..] {
+                        stats.gram[left * rule_count + right] += left_score * right_score;
+                    }
+                }
+            }
+            let active = score_sums
+                .iter()
+                .enumerate()
+                .filter(|(_, sum)| sum.abs() > EPS)
+                .map(|(index, sum)| (index, sum / n as f64))
+                .collect::<Vec<_>>();
+            for (left_pos, (left, left_mean)) in active.iter().enumerate() {
+                for (right, right_mean) in &active[left_pos..] {
+                    stats.gram[left * rule_count + right] -= n as f64 * left_mean * right_mean;
+                }
+            }
+            stats.sample_count += n;
+        }
+        // 上面只累加了 left<=right 的一半,补成对称矩阵
+        for left in 0..rule_count {
+            for right in 0..left {
+                stats.gram[left * rule_count + right] = stats.gram[right * rule_count + left];
+            }
+        }
+        stats
+    }
+
+    fn score_std(&self) -> Vec<f64> {
+        (0..self.rule_count)
+            .map(|index| {
+                let variance =
+                    self.gram[index * self.rule_count + index] / self.sample_count.max(1) as f64;
+                if variance > EPS { variance.sqrt() } else { 0.0 }
+            })
+            .collect()
+    }
+
+    // 标准化后的坐标下降,l1=lasso、l2=ridge;返回原始得分尺度上的系数
+    fn fit_penalized(&self, l1: f64, l2: f64) -> Vec<f64> {
+        let p = self.rule_count;
+        let n = self.sample_count.max(1) as f64;
+        let std = self.score_std();
+        let corr =
+            |left: usize, right: usize| self.gram[left * p + right] / (n * std[left] * std[right]);
+        let target = (0..p)
+            .map(|index| {
+                if std[index] > 0.0 {
+                    self.xy[index] / (n * std[index])
+                } else {
+                    0.0
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let mut coef = vec![0.0; p];
+        for _ in 0..COORDINATE_DESCENT_MAX_ITER {
+            let mut max_change = 0.0_f64;
+            for index in 0..p {
+                if std[index] == 0.0 {
+                    continue;
+                }
+                let partial = target[index]
+                    - (0..p)
+                        .filter(|other| *other != index && std[*other] > 0.0 && coef[*other] != 0.0)
+                        .map(|other| corr(index, other) * coef[other])
+                        .sum::<f64>();
+                let next = soft_threshold(partial, l1) / (1.0 + l2);
+                max_change = max_change.max((next - coef[index]).abs());
+                coef[index] = next;
+            }
+            if max_change < COORDINATE_DESCENT_TOL {
+                break;
+            }
+        }
+        coef.iter()
+            .zip(&std)
+            .map(|(value, std)| if *std > 0.0 { value / std } else { 0.0 })
+            .collect()
+    }
+}
+
+fn soft_threshold(value: f64, threshold: f64) -> f64 {
+    if value > threshold {
+        value - threshold
+    } else if value < -threshold {
+        value + threshold
+    } else {
+        0.0
+    }
+}
+
+fn day_rule_scores(day: &RuleScoreMatrixDay, rule_index: usize) -> Vec<f64> {
+    day.hits
+        .iter()
+        .map(|row| {
+            row.iter()
+                .find(|(index, _)| *index == rule_index)
+                .map(|(_, score)| *score)
+                .unwrap_or(0.0)
+        })
+        .collect()
+}
+
+fn fit_rank_ic(days: &[RuleScoreMatrixDay], rule_count: usize, std: &[f64]) -> Vec<f64> {
+    let mut daily_ics = vec![Vec::new(); rule_count];
+    for day in days {
+        let mut active = vec![false; rule_count];
+        for row in &day.hits {
+            for (index, _) in row {
+                active[*index] = true;
+            }
+        }
+        for (rule_index, ics) in daily_ics.iter_mut().enumerate() {
+            if !active[rule_index] {
+                continue;
+            }
+            if let Some(ic) =
+                spearman_corr(&day_rule_scores(day, rule_index), &day.residual_returns)
+            {
+                ics.push(ic);
+            }
+        }
+    }
+    daily_ics
+        .iter()
+        .zip(std)
+        .map(|(ics, std)| match mean(ics) {
+            Some(ic) if *std > 0.0 => ic / std,
+            _ => 0.0,
+        })
+        .collect()
+}
+
+// 在训练日上拟合每条规则得分的系数,系数作用在当前 points 算出的得分上
+pub fn fit_rule_coefficients(
+    days: &[RuleScoreMatrixDay],
+    rule_count: usize,
+    config: &WeightFitConfig,
+) -> Vec<f64> {
+    let stats = DesignStats::collect(days, rule_count);
+    match config.method {
+        WeightFitMethod::Ridge => stats.fit_penalized(0.0, config.lambda),
+        WeightFitMethod::Lasso => stats.fit_penalized(config.lambda, 0.0),
+        WeightFitMethod::RankIc => fit_rank_ic(days, rule_count, &stats.score_std()),
+    }
+}
+
+// 系数换成 points 倍数: 整体缩放到和当前得分同样的标准差总量,方向和相对大小来自拟合
+pub fn coefficients_to_multipliers(
+    days: &[RuleScoreMatrixDay],
+    rule_count: usize,
+    coefficients: &[f64],
+) -> Result<Vec<f64>, String> {
+    let std = DesignStats::collect(days, rule_count).score_std();
+    let current = std.iter().sum::<f64>();
+    let fitted = coefficients
+        .iter()
+        .zip(&std)
+        .map(|(coef, std)| coef.abs() * std)
+        .sum::<f64>();
+    if fitted <= EPS {
+        return Err("拟合出的规则权重全部为0,请调小lambda或换拟合方法".to_string());
+    }
+    let scale = current / fitted;
+    Ok(coefficients.iter().map(|coef| coef * scale).collect())
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CompositeIc {
+    pub day_count: usize,
+    pub ic_mean: Option<f64>,
+    pub icir: Option<f64>,
+}
+
+// 按权重合成总分后的日度 Rank IC;权重全为1时就是当前策略的总分
+pub fn composite_rank_ic(days: &[RuleScoreMatrixDay], weights: &[f64]) -> CompositeIc {
+    let ics = days
+        .iter()
+        .filter_map(|day| {
+            let scores = day
+                .hits
+                .iter()
+                .map(|row| {
+                    row.iter()
+                        .map(|(index, score)| weights[*index] * score)
+                        .sum::<f64>()
+                })
+                .collect::<Vec<_>>();
+            spearman_corr(&scores, &day.residual_returns)
+        })
+        .collect::<Vec<_>>();
+    let ic_mean = mean(&ics);
+    let icir = match (ic_mean, sample_std(&ics)) {
+        (Some(ic_mean), Some(std)) if std > EPS => Some(ic_mean / std),
+        _ => None,
+    };
+    CompositeIc {
+        day_count: ics.len(),
+        ic_mean,
+        icir,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WeightFitFold {
+    pub train_start: String,
+    pub train_end: String,
+    pub validation_start: String,
+    pub validation_end: String,
+    pub train: CompositeIc,
+    pub validation: CompositeIc,
+    pub baseline_validation: CompositeIc,
+    // 训练段拟合失败时记下原因,这一折的训练/验证 IC 留空
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuleWeightProposal {
+    pub rule_name: String,
+    pub multiplier: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WeightFitReport {
+    pub method: WeightFitMethod,
+    pub lambda: f64,
+    pub folds: Vec<WeightFitFold>,
+    pub rules: Vec<RuleWeightProposal>,
+}
+
+// 走步验证给出样本外表现,最终倍数用全部交易日重新拟合
+pub fn run_weight_fit(
+    rule_names: &[String],
+    days: &[RuleScoreMatrixDay],
+    config: &WeightFitConfig,
+) -> Result<WeightFitReport, String> {
+    config.validate()?;
+    if rule_names.is_empty() {
+        return Err("没有可拟合的规则".to_string());
+    }
+    let splits = walk_forward_splits(days.len(), config.folds, config.embargo_days);
+    if splits.is_empty() {
+        return Err(format!(
+            "交易日不足以做{}折走步验证,实际只有{}日",
+            config.folds,
+            days.len()
+        ));
+    }
+
+    let rule_count = rule_names.len();
+    let baseline = vec![1.0; rule_count];
+    let mut folds = Vec::with_capacity(splits.len());
+    for split in splits {
+        let train_days = &days[split.train.clone()];
+        let validation_days = &days[split.validation.clone()];
+        let fitted = coefficients_to_multipliers(
+            train_days,
+            rule_count,
+            &fit_rule_coefficients(train_days, rule_count, config),
+        );
+        let (train, validation, error) = match fitted {
+            Ok(weights) => (
+                composite_rank_ic(train_days, &weights),
+                composite_rank_ic(validation_days, &weights),
+                None,
+            ),
+            Err(error) => (CompositeIc::default(), CompositeIc::default(), Some(error)),
+        };
+        folds.push(WeightFitFold {
+            train_start: train_days[0].trade_date.clone(),
+            train_end: train_days[train_days.len() - 1].trade_date.clone(),
+            validation_start: validation_days[0].trade_date.clone(),
+            validation_end: validation_days[validation_days.len() - 1]
+                .trade_date
+                .clone(),
+            train,
+            validation,
+            baseline_validation: composite_rank_ic(validation_days, &baseline),
+            error,
+        });
+    }
+
+    let multipliers = coefficients_to_multipliers(
+        days,
+        rule_count,
+        &fit_rule_coefficients(days, rule_count, config),
+    )?;
+    Ok(WeightFitReport {
+        method: config.method,
+        lambda: config.lambda,
+        folds,
+        rules: rule_names
+            .iter()
+            .zip(multipliers)
+            .map(|(rule_name, multiplier)| RuleWeightProposal {
+                rule_name: rule_name.clone(),
+                multiplier,
+            })
+            .collect(),
+    })
+}
+
+fn format_optional(value: Option<f64>) -> String {
+    value
+        .map(|value| format!("{value:.4}"))
+        .unwrap_or_else(|| "-".to_string())
+}
+
+pub fn format_weight_fit_report(report: &WeightFitReport) -> String {
+    let mut lines = vec![
+        format!(
+            "拟合方法: {}, lambda={}",
+            report.method.as_str(),
+            report.lambda
+        ),
+        String::new(),
+        "走步样本外(合成总分日度 Rank IC):".to_string(),
+        "训练区间\t验证区间\t训练IC\t验证IC\t验证ICIR\t当前IC\t当前ICIR\t备注".to_string(),
+    ];
+    for fold in &report.folds {
+        lines.push(format!(
+            "{}..{}\t{}..{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            fold.train_start,
+            fold.train_end,
+            fold.validation_start,
+            fold.validation_end,
+            format_optional(fold.train.ic_mean),
+            format_optional(fold.validation.ic_mean),
+            format_optional(fold.validation.icir),
+            format_optional(fold.baseline_validation.ic_mean),
+            format_optional(fold.baseline_validation.icir),
+            fold.error
+                .as_deref()
+                .map(|error| format!("拟合失败: {error}"))
+                .unwrap_or_default(),
+        ));
+    }
+    lines.push(String::new());
+    lines.push("规则\tpoints倍数".to_string());
+    for rule in &report.rules {
+        lines.push(format!("{}\t{:.4}", rule.rule_name, rule.multiplier));
+    }
+    lines.join("\n")
+}
+
+fn round_points(value: f64) -> f64 {
+    (value * PROPOSED_POINTS_DECIMALS).round() / PROPOSED_POINTS_DECIMALS
+}
+
+fn scale_toml_number(value: &mut toml::Value, multiplier: f64) {
+    let scaled = match value {
+        toml::Value::Float(number) => *number * multiplier,
+        toml::Value::Integer(number) => *number as f64 * multiplier,
+        _ => return,
+    };
+    *value = toml::Value::Float(round_points(scaled));
+}
+
+fn scale_rule_points(rule: &mut toml::Table, multiplier: f64) {
+    for key in ["points", "max_points", "max_bonus_points"] {
+        if let Some(value) = rule.get_mut(key) {
+            scale_toml_number(value, multiplier);
+        }
+    }
+    if let Some(toml::Value::Array(items)) = rule.get_mut("points_by_hits") {
+        items
+            .iter_mut()
+            .for_each(|value| scale_toml_number(value, multiplier));
+    }
+    for (list_key, value_key) in [("dist_points", "points"), ("condition", "bonus_points")] {
+        if let Some(toml::Value::Array(items)) = rule.get_mut(list_key) {
+            for item in items {
+                if let Some(value) = item.as_table_mut().and_then(|item| item.get_mut(value_key)) {
+                    scale_toml_number(value, multiplier);
+                }
+            }
+        }
+    }
+}
+
+// 在原策略文件基础上按倍数改写各规则分值,其它配置原样保留;
+// 截面标准化的规则不看 points,跳过并返回它们的名字
+pub fn proposed_strategy_text(
+    strategy_text: &str,
+    proposals: &[RuleWeightProposal],
+) -> Result<(String, Vec<String>), String> {
+    let mut file: toml::Table =
+        toml::from_str(strategy_text).map_err(|e| format!("解析策略规则文件失败: {e}"))?;
+    let default_normalize = file
+        .get("weighting")
+        .and_then(|weighting| weighting.get("normalize"))
+        .and_then(toml::Value::as_str)
+        .unwrap_or("raw")
+        .to_string();
+    let mut skipped = Vec::new();
+    if let Some(toml::Value::Array(rules)) = file.get_mut("rule") {
+        for rule in rules.iter_mut().filter_map(toml::Value::as_table_mut) {
+            let Some(name) = rule.get("name").and_then(toml::Value::as_str) else {
+                continue;
+            };
+            let Some(proposal) = proposals.iter().find(|proposal| proposal.rule_name == name)
+            else {
+                continue;
+            };
+            let normalize = rule
+                .get("normalize")
+                .and_then(toml::Value::as_str)
+                .unwrap_or(&default_normalize);
+            if !normalize.eq_ignore_ascii_case("raw") {
+                skipped.push(name.to_string());
+                continue;
+            }
+            scale_rule_points(rule, proposal.multiplier);
+        }
+    }
+    let text = toml::to_string_pretty(&file).map_err(|e| format!("序列化策略规则文件失败: {e}"))?;
+    Ok((text, skipped))
+}
+
+pub fn write_proposed_strategy(
+    strategy_path: &Path,
+    output_path: &Path,
+    proposals: &[RuleWeightProposal],
+) -> Result<Vec<String>, String> {
+    // 用 extends/include 展开后的完整策略,建议文件可以单独使用
+    let strategy_text = resolve_strategy_file(strategy_path)?.to_toml_string()?;
+    let (text, skipped) = proposed_strategy_text(&strategy_text, proposals)?;
+    fs::write(output_path, text).map_err(|e| {
+        format!(
+            "写入建议策略文件失败: path={}, err={e}",
+            output_path.display()
+        )
+    })?;
+    Ok(skipped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 规则0和收益同向,规则1是噪声
+    fn synthetic_days(day_count: usize) -> Vec<RuleScoreMatrixDay> {
+        (0..day_count)
+            .map(|day| {
+                let mut residual_returns = Vec::new();
+                let mut hits = Vec::new();
+                for stock in 0..20 {
+                    let signal = ((stock + day) % 5) as f64;
+                    let noise = ((stock * 7 + day * 3) % 4) as f64;
+                    residual_returns.push(signal * 0.5 + ((stock * 13 + day) % 3) as f64 * 0.01);
+                    hits.push(vec![(0, signal), (1, noise)]);
+                }
+                RuleScoreMatrixDay {
+                    trade_date: format!("2024{:04}", day + 101),
+                    residual_returns,
+                    hits,
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn walk_forward_splits_keep_embargo_before_validation() {
+        let splits = walk_forward_splits(10, 4, 1);
+
+        assert_eq!(
+            splits,
+            vec![
+                WalkForwardSplit {
+                    train: 0..1,
+                    validation: 2..4
+                },
+                WalkForwardSplit {
+                    train: 0..3,
+                    validation: 4..6
+                },
+                WalkForwardSplit {
+                    train: 0..5,
+                    validation: 6..8
+                },
+                WalkForwardSplit {
+                    train: 0..7,
+                    validation: 8..10
+                },
+            ]
+        );
+        assert!(walk_forward_splits(3, 4, 1).is_empty());
+    }
+
+    #[test]
+    fn fitted_weights_favor_predictive_rule() {
+        let days = synthetic_days(40);
+        for method in [
+            WeightFitMethod::Ridge,
+            WeightFitMethod::Lasso,
+            WeightFitMethod::RankIc,
+        ] {
+            let config = WeightFitConfig {
+                method,
+                lambda: 0.05,
+                ..WeightFitConfig::default()
+            };
+            let report = run_weight_fit(&["信号".to_string(), "噪声".to_string()], &days, &config)
+                .expect("fit weights");
+
+            assert_eq!(report.folds.len(), 4);
+            assert!(report.rules[0].multiplier > report.rules[1].multiplier.abs());
+            assert!(
+                report.folds[3].validation.ic_mean.expect("validation ic")
+                    >= report.folds[3]
+                        .baseline_validation
+                        .ic_mean
+                        .expect("baseline ic")
+            );
+        }
+    }
+
+    #[test]
+    fn failed_fold_fit_is_recorded_instead_of_zero_weights() {
+        // 前10日没有任何规则命中,第一折训练段拟合不出权重
+        let mut days = synthetic_days(40);
+        for day in &mut days[..10] {
+            day.hits = vec![Vec::new(); day.residual_returns.len()];
+        }
+        let report = run_weight_fit(
+            &["信号".to_string(), "噪声".to_string()],
+            &days,
+            &WeightFitConfig::default(),
+        )
+        .expect("fit weights");
+
+        assert!(report.folds[0].error.is_some());
+        assert_eq!(report.folds[0].validation, CompositeIc::default());
+        assert!(report.folds[0].baseline_validation.day_count > 0);
+        assert!(report.folds[1..].iter().all(|fold| fold.error.is_none()));
+        assert!(format_weight_fit_report(&report).contains("拟合失败"));
+    }
+
+    #[test]
+    fn proposed_strategy_scales_points_and_skips_normalized_rules() {
+        let text = r#"
+version = 1
+
+[[scene]]
+name = "s1"
+direction = "long"
+observe_threshold = 1.0
+trigger_threshold = 2.0
+confirm_threshold = 3.0
+fail_threshold = 1.0
+
+[[rule]]
+name = "r1"
+scene = "s1"
+stage = "trigger"
+scope_windows = 1
+scope_way = "EACH"
+points = 2
+max_points = 4.0
+explain = "r1"
+when = "C > O"
+
+[[rule]]
+name = "r2"
+scene = "s1"
+stage = "trigger"
+scope_windows = 1
+scope_way = "LAST"
+points = 3.0
+normalize = "zscore"
+explain = "r2"
+when = "C > O"
+"#;
+        let proposals = vec![
+            RuleWeightProposal {
+                rule_name: "r1".to_string(),
+                multiplier: 1.5,
+            },
+            RuleWeightProposal {
+                rule_name: "r2".to_string(),
+                multiplier: 0.5,
+            },
+        ];
+
+        let (proposed, skipped) = proposed_strategy_text(text, &proposals).expect("propose");
+        let file: toml::Table = toml::from_str(&proposed).expect("proposed toml");
+        let rules = file["rule"].as_array().expect("rules");
+
+        assert_eq!(skipped, vec!["r2".to_string()]);
+        assert_eq!(rules[0]["points"].as_float(), Some(3.0));
+        assert_eq!(rules[0]["max_points"].as_float(), Some(6.0));
+        assert_eq!(rules[1]["points"].as_float(), Some(3.0));
+        assert_eq!(file["scene"].as_array().map(Vec::len), Some(1));
+    }
+}
@@ -20,10 +20,12 @@ use crate::{
     expr::{
         eval::{Runtime, Value},
         parser::{Expr, Stmt, Stmts},
+        plan::ExprPlan,
     },
     scoring::tools::{
-        CyqChenFieldInjector, inject_empty_optional_cyq_chen_fields, inject_latest_num_fields,
-        inject_stock_extra_fields, load_total_share_map, rt_max_len,
+        CyqChenFieldInjector, StockProfile, inject_empty_optional_cyq_chen_fields,
+        inject_latest_num_fields, inject_stock_extra_fields, load_stock_profile_map,
+        load_total_share_map, rt_max_len,
     },
     ui_tools::{
         build_concepts_map,
@@ -176,6 +178,7 @@ struct AllMarketTemplateRuntimeCacheEntry {
     base_rows: HashMap<String, RowData>,
     indicator_cache: Vec<IndsCache>,
     total_share_map: HashMap<String, f64>,
+    profile_map: HashMap<String, StockProfile>,
     cyq_chen_runtime_keys: HashSet<String>,
     warning_messages: Vec<String>,
 }
@@ -738,7 +741,7 @@ fn expr_uses_ident(expr: &Expr, targets: &HashSet<&str>) -> bool {
         Expr::Binary { lhs, rhs, .. } => {
             expr_uses_ident(lhs, targets) || expr_uses_ident(rhs, targets)
         }
-        Expr::Number(_) => false,
+        Expr::Number(_) | Expr::Str(_) => false,
     }
 }
 
@@ -917,7 +920,7 @@ fn cached_template_runtime(
     templates: &[IntradayMonitorTemplate],
     data_mode: TemplateRuntimeDataMode,
 ) -> Result<Arc<AllMarketTemplateRuntimeCacheEntry>, String> {
-    let compiled_templates = compile_intraday_templates(templates);
+    let compiled_templates = compile_intraday_templates(source_path, templates);
     let template_order = collect_template_order(templates);
     let ready_programs = compiled_templates
         .values()
@@ -986,6 +989,7 @@ fn cached_template_runtime(
     }
 
     let total_share_map = load_total_share_map(source_path).unwrap_or_default();
+    let profile_map = load_stock_profile_map(source_path).unwrap_or_default();
     let warning_messages = template_order
         .iter()
         .filter_map(|template_id| match compiled_templates.get(template_id) {
@@ -1006,6 +1010,7 @@ fn cached_template_runtime(
             base_rows: HashMap::new(),
             indicator_cache,
             total_share_map,
+            profile_map,
             cyq_chen_runtime_keys,
             warning_messages,
         });
@@ -1023,6 +1028,7 @@ fn cached_template_runtime(
             base_rows: HashMap::new(),
             indicator_cache,
             total_share_map,
+            profile_map,
             cyq_chen_runtime_keys,
             warning_messages,
         });
@@ -1061,6 +1067,7 @@ fn cached_template_runtime(
             &ts_code,
             is_st,
             total_share_map.get(&ts_code).copied(),
+            profile_map.get(&ts_code),
         )?;
         inject_template_rank_score_series(&mut row_data, rank_score_map.get(&ts_code))?;
         let _ = cyq_injector.inject(&mut row_data, &ts_code);
@@ -1075,6 +1082,7 @@ fn cached_template_runtime(
         base_rows,
         indicator_cache,
         total_share_map,
+        profile_map,
         cyq_chen_runtime_keys,
         warning_messages,
     });
@@ -1119,6 +1127,7 @@ fn build_template_runtime_row_data(
         &row.ts_code,
         row.board.trim() == "ST",
         entry.total_share_map.get(&row.ts_code).copied(),
+        entry.profile_map.get(&row.ts_code),
     )?;
     inject_latest_num_fields(
         &mut row_data,
@@ -1158,16 +1167,16 @@ fn value_latest_as_sort_number(value: &Value, len: usize) -> Result<Option<f64>,
                 .map_err(|error| format!("表达式返回值非布尔:{}", error.msg))?;
             Ok(series.last().map(|value| if *value { 1.0 } else { 0.0 }))
         }
+        Value::Str(_) | Value::StrSeries(_) => {
+            Err("表达式返回值是字符串，不能用于排序".to_string())
+        }
     }
 }
 
-fn eval_other_sort_value(
-    runtime: &Runtime,
-    ast: &crate::expr::parser::Stmts,
-) -> Result<Option<f64>, String> {
+fn eval_other_sort_value(runtime: &Runtime, plan: &ExprPlan) -> Result<Option<f64>, String> {
     let mut rt = runtime.clone();
     let value = rt
-        .eval_program(ast)
+        .eval_plan_single(plan)
         .map_err(|error| format!("表达式计算错误:{}", error.msg))?;
     let len = rt_max_len(&rt);
     value_latest_as_sort_number(&value, len)
@@ -1267,7 +1276,7 @@ fn cached_daily_other_sort_values(
             };
             freeze_runtime_series(&mut runtime);
             Some(
-                eval_other_sort_value(&runtime, &compiled.ast)
+                eval_other_sort_value(&runtime, &compiled.plan)
                     .map(|value| (stock.ts_code.clone(), value))
                     .map_err(|error| format!("{}: {}", stock.ts_code, error)),
             )
@@ -1351,7 +1360,7 @@ fn apply_other_sort_values(
             };
             freeze_runtime_series(&mut runtime);
             Some(
-                eval_other_sort_value(&runtime, &compiled.ast)
+                eval_other_sort_value(&runtime, &compiled.plan)
                     .map(|value| (row_index, value))
                     .map_err(|error| format!("{}: {}", row.ts_code, error)),
             )
@@ -1461,7 +1470,7 @@ fn apply_all_market_template_hits(
                 let mut rt = ctx.runtime.clone();
                 let hit = match (|| -> Result<bool, String> {
                     let value = rt
-                        .eval_program(&tpl.ast)
+                        .eval_plan_single(&tpl.plan)
                         .map_err(|e| format!("表达式计算错误:{}", e.msg))?;
                     let len = rt_max_len(&rt);
                     let series = Value::as_bool_series(&value, len)
@@ -1812,7 +1821,7 @@ mod tests {
             name: "其他排序".to_string(),
             expression: "REF(C, 1)".to_string(),
         }];
-        let compiled = compile_intraday_templates(&templates);
+        let compiled = compile_intraday_templates("", &templates);
         let Some(CompiledIntradayMonitorTemplate::Ready(template)) = compiled.get("sort") else {
             panic!("template should compile");
         };
@@ -1827,6 +1836,7 @@ mod tests {
                 ("RANK".to_string(), vec![Some(12.0), Some(8.0)]),
                 ("SCORE".to_string(), vec![Some(88.0), Some(91.0)]),
             ]),
+            text_cols: HashMap::new(),
         };
         clear_latest_template_rank_score(&mut row_data);
         assert_eq!(row_data.cols["RANK"], vec![Some(12.0), None]);
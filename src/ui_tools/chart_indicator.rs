@@ -2,18 +2,25 @@ use std::{
     collections::{HashMap, HashSet},
     fs,
     path::{Path, PathBuf},
-    sync::{Mutex, OnceLock},
+    sync::{Arc, Mutex, OnceLock},
     time::UNIX_EPOCH,
 };
 
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    data::RowData,
+    data::{
+        RowData, STOCK_FLAG_RUNTIME_KEYS, STOCK_TEXT_RUNTIME_KEYS, load_expression_prelude,
+        stock_expression_type_env,
+    },
     expr::{
         eval::{Runtime, Value},
+        func::UserFunctions,
         parser::{Expr, Stmt, Stmts},
-        validation::{first_unsupported_expression_function, parse_expression_program},
+        validation::{
+            check_expression_types, first_unsupported_expression_function,
+            parse_expression_program_with_functions,
+        },
     },
 };
 
@@ -21,9 +28,11 @@ pub const CHART_INDICATORS_FILE_NAME: &str = "chart_indicators.toml";
 const CHART_INDICATOR_INJECTED_RUNTIME_KEYS: [&str; 4] = ["RANK", "SCORE", "ZHANG", "TOTAL_MV_YI"];
 const CHART_INDICATOR_RUNTIME_ALIASES: [(&str, &str); 0] = [];
 
-static CHART_INDICATOR_COMPILE_CACHE: OnceLock<
-    Mutex<HashMap<ChartIndicatorCacheKey, CompiledChartIndicatorConfig>>,
-> = OnceLock::new();
+// 编译结果连同编译时用的函数库一起缓存,函数库换了就重新编译
+type ChartIndicatorCompileCache =
+    HashMap<ChartIndicatorCacheKey, (Arc<UserFunctions>, CompiledChartIndicatorConfig)>;
+
+static CHART_INDICATOR_COMPILE_CACHE: OnceLock<Mutex<ChartIndicatorCompileCache>> = OnceLock::new();
 
 #[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
 pub struct ChartIndicatorConfig {
@@ -393,22 +402,25 @@ pub fn load_compiled_chart_indicator_config(
         db_columns: available_db_columns.map(sorted_db_columns_cache_key),
     };
 
+    let functions = load_expression_prelude(&source_path.to_string_lossy())?;
+
     let cache = CHART_INDICATOR_COMPILE_CACHE.get_or_init(|| Mutex::new(HashMap::new()));
-    if let Some(compiled) = cache
+    if let Some((cached_functions, compiled)) = cache
         .lock()
         .map_err(|_| "chart indicator compile cache poisoned".to_string())?
         .get(&cache_key)
-        .cloned()
+        && Arc::ptr_eq(cached_functions, &functions)
     {
-        return Ok(compiled);
+        return Ok(compiled.clone());
     }
 
     let config = load_chart_indicator_config(source_path)?;
-    let compiled = compile_chart_indicator_config(&config, available_db_columns)?;
+    let compiled =
+        compile_chart_indicator_config_with_functions(&config, available_db_columns, &functions)?;
     cache
         .lock()
         .map_err(|_| "chart indicator compile cache poisoned".to_string())?
-        .insert(cache_key, compiled.clone());
+        .insert(cache_key, (functions, compiled.clone()));
 
     Ok(compiled)
 }
@@ -524,6 +536,19 @@ pub fn validate_chart_indicator_config(config: &ChartIndicatorConfig) -> Result<
 pub fn compile_chart_indicator_config(
     config: &ChartIndicatorConfig,
     available_db_columns: Option<&HashSet<String>>,
+) -> Result<CompiledChartIndicatorConfig, String> {
+    compile_chart_indicator_config_with_functions(
+        config,
+        available_db_columns,
+        &UserFunctions::default(),
+    )
+}
+
+/// Same as [`compile_chart_indicator_config`], resolving calls to shared `FUNC` definitions.
+pub fn compile_chart_indicator_config_with_functions(
+    config: &ChartIndicatorConfig,
+    available_db_columns: Option<&HashSet<String>>,
+    functions: &UserFunctions,
 ) -> Result<CompiledChartIndicatorConfig, String> {
     let config = normalize_chart_indicator_config(config);
     validate_chart_indicator_config(&config)?;
@@ -540,6 +565,7 @@ pub fn compile_chart_indicator_config(
             let expr = compile_expression(
                 &series.expr,
                 &format!("panel.{}.series.{}.expr", panel.key, series.key),
+                functions,
             )?;
             collect_database_dependencies(
                 &expr,
@@ -561,6 +587,7 @@ pub fn compile_chart_indicator_config(
                         "panel.{}.series.{}.color_when[{}].when",
                         panel.key, series.key, index
                     ),
+                    functions,
                 )?;
                 collect_database_dependencies(
                     &when_expr,
@@ -600,6 +627,7 @@ pub fn compile_chart_indicator_config(
             let when_expr = compile_expression(
                 &marker.when,
                 &format!("panel.{}.marker.{}.when", panel.key, marker.key),
+                functions,
             )?;
             collect_database_dependencies(
                 &when_expr,
@@ -648,6 +676,7 @@ pub fn compile_chart_indicator_config(
             let expr = compile_expression(
                 &tooltip.expr,
                 &format!("panel.{}.tooltip.{}.expr", panel.key, tooltip.key),
+                functions,
             )?;
             collect_database_dependencies(
                 &expr,
@@ -778,7 +807,10 @@ pub fn execute_chart_indicator_config(
 
 fn row_data_into_chart_runtime(row_data: RowData) -> Result<Runtime, String> {
     row_data.validate()?;
-    let mut runtime = Runtime::default();
+    let mut runtime = Runtime {
+        trade_dates: Arc::new(row_data.trade_dates),
+        ..Default::default()
+    };
 
     for (name, series) in row_data.cols {
         insert_runtime_series_aliases(&mut runtime, &name, series);
@@ -828,12 +860,18 @@ fn bool_series_to_json_values(series: &[bool]) -> Vec<serde_json::Value> {
         .collect()
 }
 
-fn compile_expression(expr: &str, path: &str) -> Result<Stmts, String> {
-    let stmts = parse_expression_program(expr)
+fn compile_expression(expr: &str, path: &str, functions: &UserFunctions) -> Result<Stmts, String> {
+    let stmts = parse_expression_program_with_functions(expr, functions)
         .map_err(|error| format!("{path} parse failed at {}: {}", error.idx, error.msg))?;
     if let Some(name) = first_unsupported_expression_function(&stmts) {
         return Err(format!("{path} references unknown function `{name}`"));
     }
+    check_expression_types(expr, functions, &stock_expression_type_env()).map_err(|error| {
+        format!(
+            "{path} type error at {}..{}: {}",
+            error.start, error.end, error.msg
+        )
+    })?;
     Ok(stmts)
 }
 
@@ -1034,7 +1072,7 @@ fn collect_expr_identifiers(
     identifiers: &mut HashSet<String>,
 ) {
     match expr {
-        Expr::Number(_) => {}
+        Expr::Number(_) | Expr::Str(_) => {}
         Expr::Ident(name) => {
             if !locals.contains(name) {
                 identifiers.insert(name.clone());
@@ -1059,6 +1097,8 @@ fn normalize_identifier(identifier: &str) -> String {
 
 fn is_injected_runtime_key(key: &str) -> bool {
     CHART_INDICATOR_INJECTED_RUNTIME_KEYS.contains(&key)
+        || STOCK_TEXT_RUNTIME_KEYS.contains(&key)
+        || STOCK_FLAG_RUNTIME_KEYS.contains(&key)
 }
 
 fn injected_runtime_db_dependency(key: &str) -> Option<&'static str> {
@@ -1305,6 +1345,35 @@ kind = "line"
         assert!(error.contains("before it is declared"));
     }
 
+    #[test]
+    fn marker_type_errors_report_the_expression_span() {
+        let config = parse_chart_indicator_config(
+            r##"
+version = 1
+
+[[panel]]
+key = "price"
+label = "Price"
+role = "main"
+kind = "candles"
+
+[[panel.marker]]
+key = "up"
+when = "CROSS(C, MA(C, 5)) > '0'"
+position = "below"
+"##,
+        )
+        .expect("config should parse");
+
+        let error = compile_chart_indicator_config(&config, None)
+            .expect_err("bool series compared with a string should fail");
+
+        assert_eq!(
+            error,
+            "panel.price.marker.up.when type error at 0..24: 字符串不能和数字或布尔值比较"
+        );
+    }
+
     #[test]
     fn unknown_database_dependency_is_rejected_with_path() {
         let config = parse_chart_indicator_config(
@@ -1365,6 +1434,7 @@ shape = "flag"
                     ("C".to_string(), vec![Some(9.0), Some(11.0)]),
                     ("J".to_string(), vec![Some(10.0), Some(10.0)]),
                 ]),
+                text_cols: HashMap::new(),
             },
         )
         .expect("marker should execute");
@@ -1486,6 +1556,7 @@ format = "ratio"
             RowData {
                 trade_dates: vec!["20240101".to_string(), "20240102".to_string()],
                 cols: HashMap::from([("V".to_string(), vec![Some(100.0), Some(150.0)])]),
+                text_cols: HashMap::new(),
             },
         )
         .expect("tooltip should execute");
@@ -198,7 +198,9 @@ fn summarize_chart_indicator_config_with_columns(
     })
 }
 
-fn serialize_chart_indicator_config(config: &ChartIndicatorConfig) -> Result<String, String> {
+pub(crate) fn serialize_chart_indicator_config(
+    config: &ChartIndicatorConfig,
+) -> Result<String, String> {
     let config = normalize_chart_indicator_config(config);
     let mut lines = vec!["version = 1".to_string(), String::new()];
 
@@ -4,7 +4,11 @@ use duckdb::{Connection, params, params_from_iter};
 use serde::Serialize;
 
 use crate::{
-    data::{result_db_path, scoring_data::ScoreSummary},
+    data::{
+        result_db_path,
+        scoring_data::{ScoreSummary, load_rank_order},
+    },
+    scoring::rank_order::{rank_key_joins_sql, rank_order_by_sql},
     simulate::rank::{
         DEFAULT_CONVOLUTION_KERNEL_NAME, calc_convolution_ranking, default_convolution_kernel,
     },
@@ -125,6 +129,7 @@ fn load_recent_score_rows(
                 trade_date: row.get(1)?,
                 total_score: row.get::<_, Option<f64>>(2)?.unwrap_or(f64::NAN),
                 rank: row.get(3)?,
+                ..ScoreSummary::default()
             })
         })
         .map_err(|e| format!("查询卷积评分失败: {e}"))?;
@@ -313,6 +318,11 @@ fn compute_convolution_rank_range(
         .join(",\n                ");
     let score_sql = convolution_score_sql(kernel);
     let oldest_lag = kernel.len() - 1;
+    // 同分时沿用策略排序键,总分换成原始分或卷积分
+    let rank_keys = load_rank_order(conn)?;
+    let key_joins = rank_key_joins_sql(&rank_keys, "c", "rk");
+    let raw_order_by = rank_order_by_sql(&rank_keys, "c.raw_score", "rk");
+    let convolution_order_by = rank_order_by_sql(&rank_keys, "c.convolution_score", "rk");
     let sql = format!(
         r#"
         INSERT INTO convolution_rank
@@ -352,16 +362,16 @@ fn compute_convolution_rank_range(
         ),
         ranked AS (
             SELECT
-                *,
+                c.*,
                 ROW_NUMBER() OVER (
-                    PARTITION BY trade_date
-                    ORDER BY raw_score DESC, ts_code ASC
+                    PARTITION BY c.trade_date
+                    ORDER BY {raw_order_by}, c.ts_code ASC
                 ) AS raw_rank,
                 ROW_NUMBER() OVER (
-                    PARTITION BY trade_date
-                    ORDER BY convolution_score DESC, ts_code ASC
+                    PARTITION BY c.trade_date
+                    ORDER BY {convolution_order_by}, c.ts_code ASC
                 ) AS convolution_rank
-            FROM candidates
+            FROM candidates AS c{key_joins}
         )
         SELECT
             ts_code,
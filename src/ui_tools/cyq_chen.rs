@@ -16,9 +16,12 @@ use crate::{
             collect_chen_chip_runtime_keys, compute_chen_chip_snapshots_with_compiled_config,
             round_chen_chip_snapshot,
         },
-        load_trade_date_list, source_db_path,
+        load_expression_prelude, load_trade_date_list, source_db_path,
+    },
+    expr::func::UserFunctions,
+    scoring::tools::{
+        inject_stock_extra_fields, load_st_list, load_stock_profile_map, load_total_share_map,
     },
-    scoring::tools::{inject_stock_extra_fields, load_st_list, load_total_share_map},
     ui_tools::{details::DetailKlinePayload, watch_observe::normalize_ts_code},
 };
 
@@ -169,12 +172,15 @@ fn chip_change_backup_dir(source_path: &str) -> PathBuf {
     Path::new(source_path).join(CHIP_CHANGE_BACKUP_DIR_NAME)
 }
 
-fn validate_chip_change_draft(draft: CyqChenStrategyFileDraft) -> Result<ChipChangeConfig, String> {
+fn validate_chip_change_draft(
+    draft: CyqChenStrategyFileDraft,
+    functions: &UserFunctions,
+) -> Result<ChipChangeConfig, String> {
     let config = ChipChangeConfig {
         version: 1,
         strategy: draft.strategies,
     };
-    config.compile()?;
+    config.compile_with_functions(functions)?;
     Ok(config)
 }
 
@@ -637,7 +643,8 @@ pub fn save_cyq_chen_strategy_file(
     if source_path.is_empty() {
         return Err("数据目录为空，请先确认当前数据源".to_string());
     }
-    let config = validate_chip_change_draft(draft)?;
+    let functions = load_expression_prelude(source_path)?;
+    let config = validate_chip_change_draft(draft, &functions)?;
     let path = chip_change_rule_path(source_path);
     if let Some(parent) = path.parent() {
         fs::create_dir_all(parent).map_err(|e| format!("创建筹码策略目录失败: {e}"))?;
@@ -652,7 +659,7 @@ pub fn save_cyq_chen_strategy_file(
 pub fn check_cyq_chen_strategy_file_draft(
     draft: CyqChenStrategyFileDraft,
 ) -> Result<String, String> {
-    validate_chip_change_draft(draft)?;
+    validate_chip_change_draft(draft, &UserFunctions::default())?;
     Ok("筹码策略草稿检查通过".to_string())
 }
 
@@ -783,7 +790,8 @@ pub fn import_cyq_chen_strategy_backup(
             source_file_path.display()
         )
     })?;
-    ChipChangeConfig::from_toml_str(&text)?;
+    let functions = load_expression_prelude(source_path)?;
+    ChipChangeConfig::from_toml_str_with_functions(&text, &functions)?;
     let backup_dir = chip_change_backup_dir(source_path);
     fs::create_dir_all(&backup_dir).map_err(|e| {
         format!(
@@ -933,7 +941,8 @@ pub fn activate_cyq_chen_strategy_backup(
             backup_path.display()
         )
     })?;
-    ChipChangeConfig::from_toml_str(&text)?;
+    let functions = load_expression_prelude(source_path)?;
+    ChipChangeConfig::from_toml_str_with_functions(&text, &functions)?;
     let active_path = chip_change_rule_path(source_path);
     fs::write(&active_path, text).map_err(|e| {
         format!(
@@ -962,7 +971,8 @@ pub fn run_cyq_chen_single_stock_test(
         version: 1,
         strategy: request.strategies,
     };
-    let compiled_chip_config = chip_config.compile()?;
+    let functions = load_expression_prelude(source_path)?;
+    let compiled_chip_config = chip_config.compile_with_functions(&functions)?;
 
     let (start_date, end_date) =
         resolve_requested_range(source_path, request.start_date, request.end_date)?;
@@ -1015,11 +1025,13 @@ pub fn run_cyq_chen_single_stock_test(
 
     let st_list = load_st_list(source_path).unwrap_or_default();
     let total_share_map = load_total_share_map(source_path).unwrap_or_default();
+    let profile_map = load_stock_profile_map(source_path).unwrap_or_default();
     inject_stock_extra_fields(
         &mut row_data,
         &ts_code,
         st_list.contains(&ts_code),
         total_share_map.get(&ts_code).copied(),
+        profile_map.get(&ts_code),
     )?;
 
     let kline = build_kline_rows(&row_data, &start_date, &end_date)?;
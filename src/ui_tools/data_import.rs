@@ -48,6 +48,8 @@ pub fn managed_source_file_name(file_id: &str) -> Option<&'static str> {
         "indicator-config" => Some("ind.toml"),
         "chart-indicator-config" => Some("chart_indicators.toml"),
         "ths-concepts" => Some("stock_concepts.csv"),
+        "index-constituents" => Some("index_constituents.csv"),
+        "trade-cost" => Some("trade_cost.toml"),
         _ => None,
     }
 }
@@ -1,6 +1,5 @@
 use std::{
     collections::{HashMap, HashSet},
-    fs,
     path::Path,
     time::Duration,
 };
@@ -19,7 +18,9 @@ use crate::{
         stock_list_path, ths_concepts_path,
     },
     download::ind_calc::{cache_ind_build, calc_inds_with_cache},
-    scoring::tools::{inject_stock_extra_fields, load_st_list, load_total_share_map},
+    scoring::tools::{
+        inject_stock_extra_fields, load_st_list, load_stock_profile_map, load_total_share_map,
+    },
     ui_tools::{
         chart_indicator::{
             ChartMarkerKind, ChartMarkerLineStyle, ChartMarkerPosition, ChartMarkerShape,
@@ -1326,11 +1327,15 @@ fn inject_chart_indicator_extra_runtime_fields(
     let fallback_total_share = load_total_share_map(source_path)
         .ok()
         .and_then(|map| map.get(ts_code).copied());
+    let profile = load_stock_profile_map(source_path)
+        .ok()
+        .and_then(|mut map| map.remove(ts_code));
     inject_stock_extra_fields(
         row_data,
         ts_code,
         st_list.contains(ts_code),
         fallback_total_share,
+        profile.as_ref(),
     )?;
     inject_chart_indicator_rank_series(row_data, source_path, ts_code)
 }
@@ -1570,6 +1575,7 @@ fn query_kline_rows(
     let mut row_data = RowData {
         trade_dates: Vec::new(),
         cols: HashMap::new(),
+        text_cols: HashMap::new(),
     };
     for (_, runtime_key) in &base_columns {
         row_data.cols.insert(runtime_key.clone(), Vec::new());
@@ -1730,7 +1736,11 @@ fn build_chart_indicator_row_data_from_items(
         prev_close = item.close;
     }
 
-    let mut row_data = RowData { trade_dates, cols };
+    let mut row_data = RowData {
+        trade_dates,
+        cols,
+        text_cols: HashMap::new(),
+    };
     inject_chart_indicator_extra_runtime_fields(&mut row_data, source_path, ts_code)?;
     Ok(row_data)
 }
@@ -1855,10 +1865,7 @@ fn load_rule_meta_list(source_path: &str) -> Result<Vec<RuleMeta>, String> {
     } else {
         project_rule_path
     };
-    let text = fs::read_to_string(&rule_path)
-        .map_err(|e| format!("读取规则文件失败: path={}, err={e}", rule_path.display()))?;
-    let config: ScoreConfig =
-        toml::from_str(&text).map_err(|e| format!("解析规则文件失败: {e}"))?;
+    let config = ScoreConfig::load_resolved(&rule_path)?;
 
     Ok(config
         .rule
@@ -1882,10 +1889,7 @@ fn load_scene_meta_list(source_path: &str) -> Result<Vec<SceneMeta>, String> {
     } else {
         project_rule_path
     };
-    let text = fs::read_to_string(&rule_path)
-        .map_err(|e| format!("读取规则文件失败: path={}, err={e}", rule_path.display()))?;
-    let config: ScoreConfig =
-        toml::from_str(&text).map_err(|e| format!("解析规则文件失败: {e}"))?;
+    let config = ScoreConfig::load_resolved(&rule_path)?;
 
     Ok(config
         .scene
@@ -1,6 +1,17 @@
+use std::sync::Arc;
+
 use serde::Serialize;
 
-use crate::expr::eval::supported_expression_functions;
+use crate::{
+    data::{load_expression_prelude, scoring_data::row_into_rt},
+    expr::{
+        eval::{Value, supported_expression_functions},
+        parser::Stmts,
+        validation::{parse_expression_program_with_functions, validate_expression_functions},
+    },
+};
+
+use super::stock_pick::load_expression_stock_row;
 
 pub const RT_OPEN_CHANGE_PCT: &str = "RT_OP";
 pub const RT_FALL_FROM_HIGH_PCT: &str = "RT_FH";
@@ -68,12 +79,170 @@ pub fn get_expression_capabilities() -> ExpressionCapabilitiesData {
     }
 }
 
+// 调试表里的单元格,序列化后是数字、布尔、字符串或 null
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum ExpressionTraceValue {
+    Num(Option<f64>),
+    Bool(bool),
+    Str(Option<String>),
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExpressionTraceRow {
+    pub statement_index: usize,
+    pub depth: usize,
+    pub expression: String,
+    pub assign_name: Option<String>,
+    pub values: Vec<ExpressionTraceValue>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExpressionTraceData {
+    pub ts_code: String,
+    pub trade_dates: Vec<String>,
+    pub rows: Vec<ExpressionTraceRow>,
+    pub error: Option<String>,
+}
+
+// 取值按K线展开,标量每根K线相同,只保留 keep_from 之后的部分
+fn trace_values(value: &Value, len: usize, keep_from: usize) -> Vec<ExpressionTraceValue> {
+    (keep_from..len)
+        .map(|index| match value {
+            Value::Num(value) => ExpressionTraceValue::Num(Some(*value)),
+            Value::NumSeries(series) => {
+                ExpressionTraceValue::Num(series.get(index).copied().flatten())
+            }
+            Value::SharedNumSeries(series) => {
+                ExpressionTraceValue::Num(series.get(index).copied().flatten())
+            }
+            Value::Bool(value) => ExpressionTraceValue::Bool(*value),
+            Value::BoolSeries(series) => {
+                ExpressionTraceValue::Bool(series.get(index).copied().unwrap_or(false))
+            }
+            Value::Str(_) | Value::StrSeries(_) => {
+                ExpressionTraceValue::Str(Value::str_at(value, index).map(str::to_string))
+            }
+        })
+        .collect()
+}
+
+/// 按数据目录里的公共函数解析表达式,并拒绝执行器不支持的函数。
+/// 数据目录为空时不加载公共函数,只认表达式内部的 FUNC。
+pub(crate) fn parse_source_expression(
+    source_path: &str,
+    expression: &str,
+) -> Result<Stmts, String> {
+    let functions = if source_path.trim().is_empty() {
+        Arc::default()
+    } else {
+        load_expression_prelude(source_path)?
+    };
+    let stmts = parse_expression_program_with_functions(expression, &functions)
+        .map_err(|e| format!("表达式解析错误在{}:{}", e.idx, e.msg))?;
+    validate_expression_functions(&stmts)?;
+    Ok(stmts)
+}
+
+/// 对单只股票逐K线展开表达式: 每个赋值和子表达式一行,每个交易日一列。
+/// 字段注入和表达式选股一致,区间外的预热K线参与计算但不返回。
+pub fn trace_stock_expression(
+    source_path: &str,
+    ts_code: String,
+    start_date: String,
+    end_date: String,
+    expression: String,
+) -> Result<ExpressionTraceData, String> {
+    let ts_code = ts_code.trim().to_ascii_uppercase();
+    let start_date = start_date.trim().to_string();
+    let end_date = end_date.trim().to_string();
+    if ts_code.is_empty() {
+        return Err("股票代码不能为空".to_string());
+    }
+    if start_date.is_empty() || end_date.is_empty() || start_date > end_date {
+        return Err(format!("日期区间无效: {start_date} ~ {end_date}"));
+    }
+    let expression = expression.trim();
+    if expression.is_empty() {
+        return Err("表达式不能为空".to_string());
+    }
+
+    let stmts = parse_source_expression(source_path, expression)?;
+
+    let row_data =
+        load_expression_stock_row(source_path, &stmts, &ts_code, &start_date, &end_date)?;
+    let trade_dates = row_data.trade_dates.clone();
+    let len = trade_dates.len();
+    let keep_from = trade_dates
+        .binary_search_by(|d| d.as_str().cmp(&start_date))
+        .unwrap_or_else(|index| index);
+
+    let mut runtime = row_into_rt(row_data)?;
+    let trace = runtime.trace_program(&stmts);
+    let rows = trace
+        .nodes
+        .into_iter()
+        .map(|node| {
+            let (values, error) = match &node.value {
+                Ok(value) => (trace_values(value, len, keep_from), None),
+                Err(err) => (Vec::new(), Some(err.clone())),
+            };
+            ExpressionTraceRow {
+                statement_index: node.stmt_index,
+                depth: node.depth,
+                expression: node.text,
+                assign_name: node.assign_name,
+                values,
+                error,
+            }
+        })
+        .collect();
+
+    Ok(ExpressionTraceData {
+        ts_code,
+        trade_dates: trade_dates[keep_from.min(len)..].to_vec(),
+        rows,
+        error: trace.error,
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::{
-        INTRADAY_REALTIME_FIELDS, RT_AVERAGE_PRICE, RT_FALL_FROM_HIGH_PCT, RT_OPEN_CHANGE_PCT,
-        RT_VOLUME_RATIO, get_expression_capabilities,
+        ExpressionTraceValue, INTRADAY_REALTIME_FIELDS, RT_AVERAGE_PRICE, RT_FALL_FROM_HIGH_PCT,
+        RT_OPEN_CHANGE_PCT, RT_VOLUME_RATIO, get_expression_capabilities, trace_values,
     };
+    use crate::expr::eval::Value;
+
+    #[test]
+    fn trace_values_expand_scalars_and_drop_warmup_bars() {
+        let series = Value::NumSeries(vec![Some(1.0), None, Some(3.0)]);
+        assert_eq!(
+            trace_values(&series, 3, 1),
+            vec![
+                ExpressionTraceValue::Num(None),
+                ExpressionTraceValue::Num(Some(3.0))
+            ]
+        );
+        assert_eq!(
+            trace_values(&Value::Bool(true), 3, 2),
+            vec![ExpressionTraceValue::Bool(true)]
+        );
+        assert_eq!(
+            trace_values(&Value::Str("银行".to_string()), 2, 0),
+            vec![
+                ExpressionTraceValue::Str(Some("银行".to_string())),
+                ExpressionTraceValue::Str(Some("银行".to_string()))
+            ]
+        );
+        assert_eq!(
+            serde_json::to_string(&trace_values(&series, 3, 0)).expect("serialize"),
+            "[1.0,null,3.0]"
+        );
+    }
 
     #[test]
     fn capabilities_are_derived_from_runtime_field_definitions() {
@@ -13,24 +13,18 @@ use crate::{
     download::ind_calc::{
         IndsCache, cache_ind_build, calc_inds_with_cache_lossy, warmup_ind_estimate,
     },
-    expr::{
-        eval::Value,
-        parser::Stmts,
-        validation::{
-            estimate_expression_warmup, parse_expression_program, validate_expression_functions,
-        },
-    },
+    expr::{eval::Value, parser::Stmts, plan::ExprPlan, validation::estimate_expression_warmup},
     scoring::tools::{
-        collect_used_cyq_chen_runtime_keys, cyq_chen_runtime_key_names,
+        StockProfile, collect_used_cyq_chen_runtime_keys, cyq_chen_runtime_key_names,
         inject_empty_optional_cyq_chen_fields, inject_latest_num_fields,
         inject_optional_cyq_chen_fields, inject_stock_extra_fields, load_st_list,
-        load_total_share_map, rt_max_len,
+        load_stock_profile_map, load_total_share_map, rt_max_len,
     },
     ui_tools::{
         build_concepts_map, build_name_map, build_total_mv_map,
         expression::{
             INTRADAY_REALTIME_FIELDS, RT_AVERAGE_PRICE, RT_FALL_FROM_HIGH_PCT, RT_OPEN_CHANGE_PCT,
-            RT_VOLUME_RATIO,
+            RT_VOLUME_RATIO, parse_source_expression,
         },
         filter_mv,
         realtime::{
@@ -146,6 +140,7 @@ pub enum RealtimeQuoteProvider {
 pub(crate) struct ReadyIntradayMonitorTemplate {
     pub(crate) name: String,
     pub(crate) ast: Stmts,
+    pub(crate) plan: ExprPlan,
     pub(crate) warmup_need: usize,
 }
 
@@ -605,6 +600,7 @@ pub(crate) fn add_indicator_input_runtime_keys(keys: &mut HashSet<String>) {
 }
 
 pub(crate) fn compile_intraday_templates(
+    source_path: &str,
     templates: &[IntradayMonitorTemplate],
 ) -> HashMap<String, CompiledIntradayMonitorTemplate> {
     let mut out = HashMap::with_capacity(templates.len());
@@ -629,13 +625,13 @@ pub(crate) fn compile_intraday_templates(
         }
 
         let compiled = (|| -> Result<ReadyIntradayMonitorTemplate, String> {
-            let ast = parse_expression_program(expression)
-                .map_err(|e| format!("表达式解析错误在{}:{}", e.idx, e.msg))?;
-            validate_expression_functions(&ast)?;
+            let ast = parse_source_expression(source_path, expression)?;
             let warmup_need = estimate_expression_warmup(&ast)?;
+            let plan = ExprPlan::compile(&[&ast]);
             Ok(ReadyIntradayMonitorTemplate {
                 name: name.clone(),
                 ast,
+                plan,
                 warmup_need,
             })
         })();
@@ -668,9 +664,7 @@ pub fn validate_intraday_monitor_template_expression(
         return Err("表达式不能为空".to_string());
     }
 
-    let ast = parse_expression_program(&normalized)
-        .map_err(|e| format!("表达式解析错误在{}:{}", e.idx, e.msg))?;
-    validate_expression_functions(&ast)?;
+    let ast = parse_source_expression(source_path.unwrap_or_default(), &normalized)?;
     let warmup_need = estimate_expression_warmup(&ast)?;
     let required_runtime_keys = collect_intraday_template_runtime_keys(&[&ast]);
     let cyq_chen_runtime_keys = collect_intraday_template_cyq_chen_keys(&[&ast]);
@@ -737,6 +731,7 @@ pub(crate) fn normalize_runtime_row_data(row_data: RowData) -> Result<RowData, S
     let out = RowData {
         trade_dates: row_data.trade_dates,
         cols,
+        text_cols: row_data.text_cols,
     };
     out.validate()?;
     Ok(out)
@@ -818,11 +813,15 @@ fn build_real_intraday_template_validation_row_data(
     let total_share = load_total_share_map(source_path)
         .ok()
         .and_then(|items| items.get(&sample_ts_code).copied());
+    let profile = load_stock_profile_map(source_path)
+        .ok()
+        .and_then(|mut items| items.remove(&sample_ts_code));
     inject_stock_extra_fields(
         &mut row_data,
         &sample_ts_code,
         st_list.contains(&sample_ts_code),
         total_share,
+        profile.as_ref(),
     )?;
     inject_template_validation_extra_series(&mut row_data)?;
     inject_optional_cyq_chen_fields(
@@ -879,9 +878,14 @@ fn build_intraday_template_validation_row_data(
     }
 
     cols.insert("ZHANG".to_string(), vec![Some(0.095); len]);
+    cols.insert("IS_ST".to_string(), vec![Some(0.0); len]);
     cols.insert("TOTAL_MV_YI".to_string(), vec![Some(100.0); len]);
 
-    let mut out = RowData { trade_dates, cols };
+    let mut out = RowData {
+        trade_dates,
+        cols,
+        text_cols: HashMap::new(),
+    };
     inject_template_validation_extra_series(&mut out)?;
     inject_empty_optional_cyq_chen_fields(&mut out, cyq_chen_runtime_keys)?;
     out.validate()?;
@@ -917,6 +921,7 @@ pub(crate) fn build_quote_only_runtime_row_data(
     let out = RowData {
         trade_dates: vec![trade_date.to_string()],
         cols,
+        text_cols: HashMap::new(),
     };
     out.validate()?;
     Ok(out)
@@ -981,9 +986,10 @@ fn attach_runtime_extra_series(
     row_data: &mut RowData,
     row: &IntradayMonitorRow,
     total_share: Option<f64>,
+    profile: Option<&StockProfile>,
 ) -> Result<(), String> {
     let is_st = row.board.trim() == BOARD_ST;
-    inject_stock_extra_fields(row_data, &row.ts_code, is_st, total_share)?;
+    inject_stock_extra_fields(row_data, &row.ts_code, is_st, total_share, profile)?;
     inject_latest_num_fields(
         row_data,
         &[
@@ -1111,6 +1117,7 @@ fn build_intraday_runtime_row_data(
     need_rows: usize,
     indicator_cache: &[IndsCache],
     total_share: Option<f64>,
+    profile: Option<&StockProfile>,
     cyq_chen_runtime_keys: &HashSet<String>,
 ) -> Result<RowData, String> {
     let end_date = resolve_runtime_trade_date(row, quote)?;
@@ -1132,7 +1139,7 @@ fn build_intraday_runtime_row_data(
         Err(err) => return Err(format!("读取 runtime 历史K线失败: {err}")),
     };
 
-    attach_runtime_extra_series(&mut row_data, row, total_share)?;
+    attach_runtime_extra_series(&mut row_data, row, total_share, profile)?;
     inject_optional_cyq_chen_fields(
         &mut row_data,
         source_path,
@@ -1205,6 +1212,7 @@ struct IntradayTemplateRuntimeContext<'a> {
     need_rows: usize,
     indicator_cache: &'a [IndsCache],
     total_share_map: &'a HashMap<String, f64>,
+    profile_map: &'a HashMap<String, StockProfile>,
     cyq_chen_runtime_keys: &'a HashSet<String>,
 }
 
@@ -1253,11 +1261,12 @@ fn apply_intraday_template_tag_for_row(
                     runtime_context.need_rows,
                     runtime_context.indicator_cache,
                     runtime_context.total_share_map.get(&row.ts_code).copied(),
+                    runtime_context.profile_map.get(&row.ts_code),
                     runtime_context.cyq_chen_runtime_keys,
                 )?;
                 let mut rt = row_into_rt(row_data)?;
                 let value = rt
-                    .eval_program(&template.ast)
+                    .eval_plan_single(&template.plan)
                     .map_err(|e| format!("表达式计算错误:{}", e.msg))?;
                 let len = rt_max_len(&rt);
                 let series = Value::as_bool_series(&value, len)
@@ -1292,7 +1301,7 @@ fn apply_intraday_template_tags(
         return None;
     }
 
-    let compiled_templates = compile_intraday_templates(templates);
+    let compiled_templates = compile_intraday_templates(source_path, templates);
     let ready_programs = compiled_templates
         .values()
         .filter_map(|item| match item {
@@ -1312,6 +1321,7 @@ fn apply_intraday_template_tags(
         .unwrap_or(0);
     let indicator_cache = cache_ind_build(source_path).unwrap_or_default();
     let total_share_map = load_total_share_map(source_path).unwrap_or_default();
+    let profile_map = load_stock_profile_map(source_path).unwrap_or_default();
     let indicator_warmup_need = if indicator_cache.is_empty() {
         0
     } else {
@@ -1350,6 +1360,7 @@ fn apply_intraday_template_tags(
         need_rows,
         indicator_cache: &indicator_cache,
         total_share_map: &total_share_map,
+        profile_map: &profile_map,
         cyq_chen_runtime_keys: &cyq_chen_runtime_keys,
     };
 
@@ -1938,6 +1949,7 @@ pub fn get_intraday_monitor_page(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::expr::validation::parse_expression_program;
 
     fn sample_quote() -> SinaQuote {
         SinaQuote {
@@ -1964,6 +1976,7 @@ mod tests {
         RowData {
             trade_dates: vec!["20240401".to_string()],
             cols,
+            text_cols: HashMap::new(),
         }
     }
 
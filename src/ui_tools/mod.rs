@@ -25,6 +25,7 @@ pub mod expression_stock_pick;
 pub mod intraday_monitor;
 pub mod overview;
 pub mod overview_classic;
+pub mod param_sweep;
 pub mod ranking_compute;
 pub mod realtime;
 pub mod statistics;
@@ -33,6 +34,7 @@ pub mod stock_similarity;
 pub mod strategy_manage;
 pub mod strategy_paper_validation;
 pub mod strategy_trigger_similarity;
+pub mod tdx_import;
 pub mod watch_observe;
 
 const DEFAULT_ADJ_TYPE: &str = "qfq";
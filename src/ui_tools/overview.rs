@@ -2,7 +2,7 @@ use duckdb::{Connection, params};
 use serde::Serialize;
 
 use crate::{
-    data::result_db_path,
+    data::{result_db_path, scoring_data::query_scene_transitions},
     ui_tools::{build_concepts_map, build_name_map, build_total_mv_map, filter_mv},
     utils::utils::board_category,
 };
@@ -35,6 +35,26 @@ pub struct SceneOverviewPageData {
     pub resolved_rank_date: Option<String>,
 }
 
+#[derive(Debug, Serialize, Clone)]
+pub struct SceneTransitionRow {
+    pub ts_code: String,
+    pub trade_date: String,
+    pub scene_name: String,
+    pub direction: String,
+    pub from_stage: Option<String>,
+    pub to_stage: String,
+    pub prev_stage_days: i32,
+    pub rule_names: Vec<String>,
+    pub name: String,
+    pub board: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SceneTransitionPageData {
+    pub rows: Vec<SceneTransitionRow>,
+    pub resolved_trade_date: Option<String>,
+}
+
 fn open_result_conn(source_path: &str) -> Result<Connection, String> {
     let result_db = result_db_path(source_path);
     let result_db_str = result_db
@@ -239,3 +259,55 @@ pub fn get_scene_rank_overview_page(
         resolved_rank_date: Some(effective_rank_date),
     })
 }
+
+// 某天刚进入或切换阶段的股票,默认取场景明细的最新交易日
+pub fn get_scene_transition_page(
+    source_path: &str,
+    trade_date: Option<String>,
+    scene_name: Option<String>,
+    to_stage: Option<String>,
+) -> Result<SceneTransitionPageData, String> {
+    let conn = open_result_conn(source_path)?;
+    let effective_trade_date = resolve_trade_date(&conn, trade_date)?;
+    let name_map = build_name_map(source_path)?;
+    let scene_name = scene_name
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty() && value != "全部");
+    let to_stage = to_stage
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty() && value != "全部");
+
+    let rows = query_scene_transitions(
+        &conn,
+        &effective_trade_date,
+        scene_name.as_deref(),
+        to_stage.as_deref(),
+    )?
+    .into_iter()
+    .map(|row| {
+        let name = name_map.get(&row.ts_code).cloned().unwrap_or_default();
+        SceneTransitionRow {
+            board: board_category(&row.ts_code, Some(name.as_str())).to_string(),
+            name,
+            rule_names: row
+                .rule_names
+                .split(',')
+                .filter(|value| !value.is_empty())
+                .map(str::to_string)
+                .collect(),
+            ts_code: row.ts_code,
+            trade_date: row.trade_date,
+            scene_name: row.scene_name,
+            direction: row.direction,
+            from_stage: row.from_stage,
+            to_stage: row.to_stage,
+            prev_stage_days: row.prev_stage_days,
+        }
+    })
+    .collect();
+
+    Ok(SceneTransitionPageData {
+        rows,
+        resolved_trade_date: Some(effective_trade_date),
+    })
+}
@@ -4,7 +4,8 @@ use duckdb::{Connection, params};
 use serde::Serialize;
 
 use crate::{
-    data::{result_db_path, source_db_path},
+    data::{result_db_path, scoring_data::load_rank_order, source_db_path},
+    scoring::rank_order::{net_score_sql, rank_key_joins_sql, rank_order_by_sql},
     ui_tools::{
         build_concepts_map, build_name_map, build_total_mv_map, filter_mv, resolve_trade_date,
     },
@@ -13,6 +14,7 @@ use crate::{
 
 const DEFAULT_ADJ_TYPE: &str = "qfq";
 const BOARD_ST: &str = "ST";
+const RANK_BY_RISK: &str = "risk";
 
 #[derive(Debug, Serialize, Clone)]
 pub struct OverviewRow {
@@ -22,6 +24,9 @@ pub struct OverviewRow {
     pub total_score: Option<f64>,
     pub tiebreak_j: Option<f64>,
     pub rank: Option<i64>,
+    pub risk_score: Option<f64>,
+    pub net_score: Option<f64>,
+    pub risk_rank: Option<i64>,
     pub ref_rank: Option<i64>,
     pub post_rank_return_pct: Option<f64>,
     pub name: String,
@@ -65,6 +70,17 @@ fn table_exists(conn: &Connection, table_name: &str) -> Result<bool, String> {
     Ok(count > 0)
 }
 
+fn summary_has_risk_columns(conn: &Connection) -> Result<bool, String> {
+    let count = conn
+        .query_row(
+            "SELECT COUNT(*) FROM information_schema.columns WHERE table_name = 'score_summary' AND column_name = 'risk_rank'",
+            [],
+            |row| row.get::<_, i64>(0),
+        )
+        .map_err(|e| format!("检查风险排名字段失败: {e}"))?;
+    Ok(count > 0)
+}
+
 fn query_rank_trade_date_options_from_conn(conn: &Connection) -> Result<Vec<String>, String> {
     if !table_exists(conn, "score_summary")? {
         return Ok(Vec::new());
@@ -271,6 +287,7 @@ pub fn get_rank_trade_date_options(source_path: String) -> Result<Vec<String>, S
     query_rank_trade_date_options_from_conn(&conn)
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn get_rank_overview(
     source_path: String,
     trade_date: Option<String>,
@@ -279,6 +296,7 @@ pub fn get_rank_overview(
     exclude_st_board: Option<bool>,
     total_mv_min: Option<f64>,
     total_mv_max: Option<f64>,
+    rank_by: Option<String>,
 ) -> Result<Vec<OverviewRow>, String> {
     if let (Some(min_v), Some(max_v)) = (total_mv_min, total_mv_max) {
         if min_v > max_v {
@@ -293,18 +311,58 @@ pub fn get_rank_overview(
     let total_mv_map = build_total_mv_map(&source_path)?;
     let concepts_map = build_concepts_map(&source_path)?;
 
-    let sql = r#"
+    // 旧结果库没有风险字段,风险榜直接为空,总分榜照常
+    let has_risk = summary_has_risk_columns(&conn)?;
+    let by_risk = rank_by.as_deref().map(str::trim) == Some(RANK_BY_RISK);
+    if by_risk && !has_risk {
+        return Ok(Vec::new());
+    }
+    let risk_columns = if has_risk {
+        "s.risk_score, s.net_score, s.risk_rank"
+    } else {
+        "NULL, NULL, NULL"
+    };
+
+    let sql = if by_risk {
+        format!(
+            r#"
+    SELECT
+        s.ts_code,
+        s.trade_date,
+        s.total_score,
+        s.rank,
+        {risk_columns}
+    FROM score_summary AS s
+    WHERE s.trade_date = ? AND s.risk_rank IS NOT NULL
+    ORDER BY s.risk_rank ASC, s.ts_code ASC
+    "#
+        )
+    } else {
+        // 排名缺失时按策略排序键兜底,和写排名时一致
+        let rank_keys = load_rank_order(&conn)?;
+        let key_joins = rank_key_joins_sql(&rank_keys, "s", "rk");
+        let score_sql = if has_risk {
+            net_score_sql("s")
+        } else {
+            "s.total_score".to_string()
+        };
+        let order_by = rank_order_by_sql(&rank_keys, &score_sql, "rk");
+        format!(
+            r#"
     SELECT
-        ts_code,
-        trade_date,
-        total_score,
-        rank
-    FROM score_summary
-    WHERE trade_date = ?
-    ORDER BY COALESCE(rank, 999999) ASC, total_score DESC, ts_code ASC
-    "#;
-
-    let mut stmt = conn.prepare(sql).map_err(|e| format!("预编译失败: {e}"))?;
+        s.ts_code,
+        s.trade_date,
+        s.total_score,
+        s.rank,
+        {risk_columns}
+    FROM score_summary AS s{key_joins}
+    WHERE s.trade_date = ?
+    ORDER BY COALESCE(s.rank, 999999) ASC, {order_by}, s.ts_code ASC
+    "#
+        )
+    };
+
+    let mut stmt = conn.prepare(&sql).map_err(|e| format!("预编译失败: {e}"))?;
     let mut rows = stmt
         .query(params![effective_trade_date])
         .map_err(|e| format!("查询失败: {e}"))?;
@@ -349,6 +407,15 @@ pub fn get_rank_overview(
             ),
             tiebreak_j: None,
             rank: row.get(3).map_err(|e| format!("读取 rank 失败: {e}"))?,
+            risk_score: row
+                .get(4)
+                .map_err(|e| format!("读取 risk_score 失败: {e}"))?,
+            net_score: row
+                .get(5)
+                .map_err(|e| format!("读取 net_score 失败: {e}"))?,
+            risk_rank: row
+                .get(6)
+                .map_err(|e| format!("读取 risk_rank 失败: {e}"))?,
             ref_rank: None,
             post_rank_return_pct: None,
             name: name_map.get(&ts_code).cloned().unwrap_or_default(),
@@ -367,6 +434,7 @@ pub fn get_rank_overview(
     Ok(out)
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn get_rank_overview_page(
     source_path: String,
     rank_date: Option<String>,
@@ -376,6 +444,7 @@ pub fn get_rank_overview_page(
     exclude_st_board: Option<bool>,
     total_mv_min: Option<f64>,
     total_mv_max: Option<f64>,
+    rank_by: Option<String>,
 ) -> Result<OverviewPageData, String> {
     let result_conn = open_result_conn(&source_path)?;
     let effective_rank_date = resolve_trade_date(&result_conn, rank_date)?;
@@ -390,6 +459,7 @@ pub fn get_rank_overview_page(
         exclude_st_board,
         total_mv_min,
         total_mv_max,
+        rank_by,
     )?;
 
     let source_conn = open_source_conn(&source_path)?;
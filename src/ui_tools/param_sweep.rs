@@ -0,0 +1,410 @@
+use std::collections::HashSet;
+
+use serde::Serialize;
+
+use crate::{
+    data::load_trade_date_list,
+    simulate::{
+        portfolio::PortfolioMetrics,
+        walk_forward::{
+            SweepCandidateReturns, SweepCombo, SweepParamRange, SweepParamValue, WalkForwardConfig,
+            WalkForwardReport, collect_sweep_placeholders, expand_sweep_combos,
+            fill_sweep_placeholders, run_walk_forward_analysis,
+        },
+    },
+    ui_tools::{
+        statistics::{RuleValidationUnknownConfig, run_rule_expression_validation},
+        strategy_paper_validation::{
+            StrategyPaperValidationExitRules, StrategyPaperValidationSummaryData,
+            run_strategy_portfolio_backtest_batch,
+        },
+    },
+};
+
+#[derive(Debug, Serialize)]
+pub struct StrategyParamSweepComboResult {
+    pub combo: SweepCombo,
+    pub buy_expression: String,
+    pub sell_expression: String,
+    pub summary: StrategyPaperValidationSummaryData,
+    pub metrics: PortfolioMetrics,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StrategyParamSweepData {
+    pub start_date: String,
+    pub end_date: String,
+    pub params: Vec<SweepParamRange>,
+    pub walk_forward: WalkForwardConfig,
+    pub combo_results: Vec<StrategyParamSweepComboResult>,
+    pub report: WalkForwardReport,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RuleParamSweepComboResult {
+    pub combo: SweepCombo,
+    pub formula: String,
+    pub trigger_samples: usize,
+    pub avg_residual_mean: Option<f64>,
+    pub spread_mean: Option<f64>,
+    pub icir: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RuleParamSweepData {
+    pub import_rule_name: String,
+    pub start_date: String,
+    pub end_date: String,
+    pub backtest_period: usize,
+    pub params: Vec<SweepParamRange>,
+    pub walk_forward: WalkForwardConfig,
+    pub combo_results: Vec<RuleParamSweepComboResult>,
+    pub report: WalkForwardReport,
+}
+
+/// 买卖点方程里的 `{N}` 按 `params`(如 `N in 5..30 step 5`)展开后并行跑组合回测,
+/// 再用各组合的逐日净值收益做滚动窗口样本内选参、样本外检验。
+#[allow(clippy::too_many_arguments)]
+pub fn run_strategy_param_sweep(
+    source_path: &str,
+    start_date: Option<String>,
+    end_date: Option<String>,
+    min_listed_trade_days: Option<usize>,
+    index_ts_code: Option<String>,
+    board: Option<String>,
+    buy_price_basis: String,
+    slippage_pct: Option<f64>,
+    max_position_count: Option<usize>,
+    buy_selection_mode: Option<String>,
+    buy_expression: String,
+    sell_expression: String,
+    exit_rules: Option<StrategyPaperValidationExitRules>,
+    initial_capital: Option<f64>,
+    sizing_mode: Option<String>,
+    params: Vec<String>,
+    walk_forward: Option<WalkForwardConfig>,
+) -> Result<StrategyParamSweepData, String> {
+    let ranges = parse_sweep_params(&params)?;
+    ensure_params_used(&ranges, &[&buy_expression, &sell_expression])?;
+    let combos = expand_sweep_combos(&ranges)?;
+    let walk_forward = walk_forward.unwrap_or_default();
+
+    // 行情按全部组合的方程只加载一次,各组合共用
+    let expressions = combos
+        .iter()
+        .map(|combo| -> Result<_, String> {
+            Ok((
+                fill_sweep_placeholders(&buy_expression, combo)?,
+                fill_sweep_placeholders(&sell_expression, combo)?,
+            ))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    let runs = run_strategy_portfolio_backtest_batch(
+        source_path,
+        start_date,
+        end_date,
+        min_listed_trade_days,
+        index_ts_code,
+        board,
+        buy_price_basis,
+        slippage_pct,
+        max_position_count,
+        buy_selection_mode,
+        expressions,
+        exit_rules,
+        initial_capital,
+        sizing_mode,
+    )?
+    .into_iter()
+    .zip(&combos)
+    .map(|(data, combo)| {
+        data.map(|data| (combo.clone(), data))
+            .map_err(|e| format!("参数组合 {} 回测失败:{e}", combo.label))
+    })
+    .collect::<Result<Vec<_>, String>>()?;
+
+    let (resolved_start_date, resolved_end_date) = runs
+        .first()
+        .map(|(_, data)| {
+            (
+                data.validation.start_date.clone(),
+                data.validation.end_date.clone(),
+            )
+        })
+        .ok_or_else(|| "参数扫描没有可用的组合".to_string())?;
+    let trade_dates =
+        load_sweep_trade_dates(source_path, &resolved_start_date, &resolved_end_date)?;
+    let candidates = runs
+        .iter()
+        .map(|(combo, data)| SweepCandidateReturns {
+            combo: combo.clone(),
+            daily_returns: data.portfolio.daily_returns_pct(),
+        })
+        .collect::<Vec<_>>();
+    let report = run_walk_forward_analysis(&trade_dates, &candidates, &walk_forward)?;
+    let combo_results = runs
+        .into_iter()
+        .map(|(combo, data)| StrategyParamSweepComboResult {
+            combo,
+            buy_expression: data.validation.buy_expression,
+            sell_expression: data.validation.sell_expression,
+            summary: data.validation.summary,
+            metrics: data.portfolio.metrics,
+        })
+        .collect();
+
+    Ok(StrategyParamSweepData {
+        start_date: resolved_start_date,
+        end_date: resolved_end_date,
+        params: ranges,
+        walk_forward,
+        combo_results,
+        report,
+    })
+}
+
+/// 规则 `when` 里的 `{N}` 交给规则验证的未知数展开,共用一份残差缓存,
+/// 每个组合的触发样本平均残差收益作为收益序列做滚动窗口检验。
+///
+/// 每个交易日的样本都持有 `backtest_period` 天,相邻日期的收益大部分重叠,
+/// 直接当日收益算夏普会把同一段行情重复计数、高估夏普和 DSR/PBO 的显著性。
+/// 这里只取从区间首日起每隔 `backtest_period` 个交易日的样本,整段收益记在入场日,
+/// 其余日期记 0,相当于每期满仓换一次的不重叠持有。按 252 日年化时,
+/// 稀疏序列的夏普近似等于周期收益夏普乘 `sqrt(252 / backtest_period)`,
+/// 均值相对波动很小时成立;结果依赖起始相位,不同相位之间的差异没有计入。
+#[allow(clippy::too_many_arguments)]
+pub fn run_rule_param_sweep(
+    source_path: String,
+    import_rule_name: String,
+    when: String,
+    scope_way: Option<String>,
+    scope_windows: Option<usize>,
+    stock_adj_type: Option<String>,
+    index_ts_code: String,
+    start_date: String,
+    end_date: String,
+    min_samples_per_rule_day: Option<usize>,
+    backtest_period: Option<usize>,
+    board: Option<String>,
+    params: Vec<String>,
+    walk_forward: Option<WalkForwardConfig>,
+) -> Result<RuleParamSweepData, String> {
+    let ranges = parse_sweep_params(&params)?;
+    ensure_params_used(&ranges, &[&when])?;
+    // 先按扫描的上限展开一次,超限时在跑验证之前报错
+    expand_sweep_combos(&ranges)?;
+    let walk_forward = walk_forward.unwrap_or_default();
+    let unknown_configs = ranges
+        .iter()
+        .map(|range| RuleValidationUnknownConfig {
+            name: range.name.clone(),
+            start: range.start,
+            end: range.end,
+            step: range.step,
+        })
+        .collect::<Vec<_>>();
+
+    let validation = run_rule_expression_validation(
+        source_path.clone(),
+        import_rule_name,
+        Some(strip_placeholder_braces(&when)),
+        scope_way,
+        scope_windows,
+        stock_adj_type,
+        index_ts_code,
+        None,
+        None,
+        None,
+        start_date.clone(),
+        end_date.clone(),
+        min_samples_per_rule_day,
+        None,
+        backtest_period,
+        None,
+        Some(unknown_configs),
+        Some(1),
+        board,
+        None,
+        None,
+        None,
+    )?;
+
+    let trade_dates = load_sweep_trade_dates(&source_path, start_date.trim(), end_date.trim())?;
+    let resolved_backtest_period = validation
+        .combo_results
+        .first()
+        .map(|item| item.backtest.backtest_period)
+        .unwrap_or(1)
+        .max(1);
+    let mut combo_results = validation
+        .combo_results
+        .into_iter()
+        .map(|item| {
+            let combo = SweepCombo {
+                combo_key: item.combo_key,
+                label: item.combo_label,
+                values: item
+                    .unknown_values
+                    .into_iter()
+                    .map(|value| SweepParamValue {
+                        name: value.name,
+                        value: value.value,
+                    })
+                    .collect(),
+            };
+            let period_returns = item
+                .backtest
+                .points
+                .iter()
+                .filter_map(|point| {
+                    point
+                        .avg_residual_return
+                        .map(|value| (point.trade_date.clone(), value))
+                })
+                .collect::<Vec<_>>();
+            let daily_returns = non_overlapping_period_returns(
+                &trade_dates,
+                &period_returns,
+                resolved_backtest_period,
+            );
+            (
+                RuleParamSweepComboResult {
+                    combo,
+                    formula: item.formula,
+                    trigger_samples: item.trigger_samples,
+                    avg_residual_mean: item.backtest.avg_residual_mean,
+                    spread_mean: item.backtest.spread_mean,
+                    icir: item.backtest.icir,
+                },
+                daily_returns,
+            )
+        })
+        .collect::<Vec<_>>();
+    // 规则验证按效果排过序,这里恢复成参数展开顺序
+    combo_results.sort_by(|left, right| left.0.combo.combo_key.cmp(&right.0.combo.combo_key));
+
+    let candidates = combo_results
+        .iter()
+        .map(|(result, daily_returns)| SweepCandidateReturns {
+            combo: result.combo.clone(),
+            daily_returns: daily_returns.clone(),
+        })
+        .collect::<Vec<_>>();
+    let report = run_walk_forward_analysis(&trade_dates, &candidates, &walk_forward)?;
+
+    Ok(RuleParamSweepData {
+        import_rule_name: validation.import_rule_name,
+        start_date,
+        end_date,
+        backtest_period: resolved_backtest_period,
+        params: ranges,
+        walk_forward,
+        combo_results: combo_results
+            .into_iter()
+            .map(|(result, _)| result)
+            .collect(),
+        report,
+    })
+}
+
+fn parse_sweep_params(params: &[String]) -> Result<Vec<SweepParamRange>, String> {
+    let ranges = params
+        .iter()
+        .map(|text| text.trim())
+        .filter(|text| !text.is_empty())
+        .map(SweepParamRange::parse)
+        .collect::<Result<Vec<_>, _>>()?;
+    if ranges.is_empty() {
+        return Err("参数扫描至少需要一个参数定义".to_string());
+    }
+    Ok(ranges)
+}
+
+fn ensure_params_used(ranges: &[SweepParamRange], templates: &[&str]) -> Result<(), String> {
+    let used = templates
+        .iter()
+        .flat_map(|template| collect_sweep_placeholders(template))
+        .collect::<HashSet<_>>();
+    for name in &used {
+        if !ranges.iter().any(|range| &range.name == name) {
+            return Err(format!("表达式占位符 {{{name}}} 没有对应的参数定义"));
+        }
+    }
+    if let Some(range) = ranges.iter().find(|range| !used.contains(&range.name)) {
+        return Err(format!("参数 {} 没有出现在表达式的占位符里", range.name));
+    }
+    Ok(())
+}
+
+// 规则验证的未知数是裸标识符,去掉花括号即可
+fn strip_placeholder_braces(template: &str) -> String {
+    template.replace(['{', '}'], "")
+}
+
+fn load_sweep_trade_dates(
+    source_path: &str,
+    start_date: &str,
+    end_date: &str,
+) -> Result<Vec<String>, String> {
+    Ok(load_trade_date_list(source_path)?
+        .into_iter()
+        .filter(|trade_date| trade_date.as_str() >= start_date && trade_date.as_str() <= end_date)
+        .collect())
+}
+
+// 从区间首日起每隔 period 个交易日取一个样本,其余日期不出现(按 0 收益处理)
+fn non_overlapping_period_returns(
+    trade_dates: &[String],
+    period_returns: &[(String, f64)],
+    period: usize,
+) -> Vec<(String, f64)> {
+    let sampled_dates = trade_dates
+        .iter()
+        .step_by(period.max(1))
+        .map(String::as_str)
+        .collect::<HashSet<_>>();
+    period_returns
+        .iter()
+        .filter(|(trade_date, _)| sampled_dates.contains(trade_date.as_str()))
+        .cloned()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sweep_params_must_match_placeholders() {
+        let ranges = parse_sweep_params(&["N in 5..30 step 5".to_string(), " ".to_string()])
+            .expect("params should parse");
+        assert_eq!(ranges.len(), 1);
+        assert!(ensure_params_used(&ranges, &["C > MA(C, {N})", "C < MA(C, {N})"]).is_ok());
+        assert!(ensure_params_used(&ranges, &["C > MA(C, 5)"]).is_err());
+        assert!(ensure_params_used(&ranges, &["C > MA(C, {N}) AND V > {M}"]).is_err());
+        assert!(parse_sweep_params(&[]).is_err());
+        assert_eq!(strip_placeholder_braces("C > MA(C, {N})"), "C > MA(C, N)");
+    }
+
+    #[test]
+    fn rule_sweep_keeps_one_sample_per_holding_period() {
+        let trade_dates = (1..=7)
+            .map(|day| format!("2024010{day}"))
+            .collect::<Vec<_>>();
+        let period_returns = trade_dates
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| *index != 3)
+            .map(|(index, trade_date)| (trade_date.clone(), index as f64))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            non_overlapping_period_returns(&trade_dates, &period_returns, 3),
+            vec![("20240101".to_string(), 0.0), ("20240107".to_string(), 6.0)]
+        );
+        assert_eq!(
+            non_overlapping_period_returns(&trade_dates, &period_returns, 1),
+            period_returns
+        );
+    }
+}
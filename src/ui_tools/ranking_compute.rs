@@ -1,19 +1,34 @@
-use std::{collections::HashSet, path::Path, time::Instant};
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+    time::Instant,
+};
 
 use duckdb::Connection;
 use serde::Serialize;
 
 use crate::{
     data::{
-        concept_performance_data::rebuild_concept_performance_all, cyq::CyqConfig,
-        cyq_chen::ChenChipConfig, cyq_chen_data::rebuild_cyq_chen_all_with_progress,
-        cyq_chen_db_path, cyq_data::rebuild_cyq_all_with_progress, cyq_db_path,
-        load_trade_date_list, result_db_path, source_db_path,
+        concept_performance_data::rebuild_concept_performance_all,
+        cyq::CyqConfig,
+        cyq_chen::ChenChipConfig,
+        cyq_chen_data::rebuild_cyq_chen_all_with_progress,
+        cyq_chen_db_path,
+        cyq_data::rebuild_cyq_all_with_progress,
+        cyq_db_path, load_trade_date_list, result_db_path,
+        scoring_data::{
+            ScoreRunMeta, delete_score_run, list_score_runs, load_rank_order, load_score_run,
+            query_score_run_rank_diffs, query_score_run_rule_counts, query_score_run_rule_diffs,
+        },
+        source_db_path,
     },
     download::runner::DownloadProgressCallback,
     scoring::{
         RankTiebreakProfile, TieBreakWay, build_rank_tiebreak,
-        runner::{ScoringRunProfile, preview_scoring_runtime_warnings, scoring_all_to_db},
+        runner::{
+            ScoringRunProfile, preview_scoring_runtime_warnings, scoring_all_to_db,
+            scoring_incremental_to_db,
+        },
     },
 };
 
@@ -110,6 +125,56 @@ pub struct RankComputeRunResult {
     pub timings: Vec<RankComputeTimingItem>,
     pub warnings: Vec<String>,
     pub status: RankComputeStatus,
+    pub run_id: Option<String>,
+}
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RankScoreRunItem {
+    pub run_id: String,
+    pub strategy_hash: String,
+    pub strategy_path: String,
+    pub adj_type: String,
+    pub start_date: String,
+    pub end_date: String,
+    pub created_at: String,
+}
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RankScoreRunStockDiff {
+    pub ts_code: String,
+    pub base_score: Option<f64>,
+    pub target_score: Option<f64>,
+    pub base_rank: Option<i64>,
+    pub target_rank: Option<i64>,
+    // 正数表示新版本排名更靠前
+    pub rank_change: Option<i64>,
+    pub gained_rules: Vec<String>,
+    pub lost_rules: Vec<String>,
+}
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RankScoreRunRuleCountDiff {
+    pub rule_name: String,
+    pub base_trigger_count: i64,
+    pub target_trigger_count: i64,
+}
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RankScoreRunCompareResult {
+    pub base: RankScoreRunItem,
+    pub target: RankScoreRunItem,
+    pub trade_date: String,
+    pub range_start: String,
+    pub range_end: String,
+    pub stocks: Vec<RankScoreRunStockDiff>,
+    pub rules: Vec<RankScoreRunRuleCountDiff>,
+    // 共同区间内只在一个版本里命中过的规则
+    pub added_rules: Vec<String>,
+    pub removed_rules: Vec<String>,
 }
 
 #[derive(Clone, Serialize)]
@@ -136,7 +201,16 @@ fn timing_item(
 }
 
 fn scoring_run_timings(profile: &ScoringRunProfile) -> Vec<RankComputeTimingItem> {
-    vec![
+    let mut items = Vec::new();
+    if let Some(refresh_writer) = &profile.refresh_writer {
+        items.push(timing_item(
+            "refresh-writer-total",
+            "重写变更规则",
+            refresh_writer.total_ms,
+            Some(format!("规则 {}", profile.refreshed_rules.len())),
+        ));
+    }
+    items.extend([
         timing_item("init-result-db", "初始化", profile.init_result_db_ms, None),
         timing_item("prepare", "准备", profile.prepare_ms, None),
         timing_item(
@@ -194,7 +268,8 @@ fn scoring_run_timings(profile: &ScoringRunProfile) -> Vec<RankComputeTimingItem
             profile.writer.recreate_indexes_ms,
             None,
         ),
-    ]
+    ]);
+    items
 }
 
 fn tiebreak_timings(profile: &RankTiebreakProfile) -> Vec<RankComputeTimingItem> {
@@ -623,6 +698,7 @@ pub fn run_ranking_score_calculation(
     strategy_path: Option<&str>,
     start_date: &str,
     end_date: &str,
+    archive_keep_last: Option<usize>,
 ) -> Result<RankComputeRunResult, String> {
     let source_path = source_path.trim().to_string();
     if source_path.is_empty() {
@@ -636,7 +712,14 @@ pub fn run_ranking_score_calculation(
     }
 
     let started_at = Instant::now();
-    let profile = scoring_all_to_db(&source_path, strategy_path, "qfq", &start_date, &end_date)?;
+    let profile = scoring_all_to_db(
+        &source_path,
+        strategy_path,
+        "qfq",
+        &start_date,
+        &end_date,
+        archive_keep_last,
+    )?;
     let status = get_rank_compute_status_inner(&source_path, strategy_path)?;
     Ok(RankComputeRunResult {
         action: "score".to_string(),
@@ -646,6 +729,189 @@ pub fn run_ranking_score_calculation(
         timings: scoring_run_timings(&profile),
         warnings: profile.warnings,
         status,
+        run_id: profile.run_id,
+    })
+}
+
+// 增量评分: 只补新交易日,策略改过的规则和场景单独重写
+pub fn run_ranking_incremental_score_calculation(
+    source_path: &str,
+    strategy_path: Option<&str>,
+    start_date: &str,
+    end_date: &str,
+    archive_keep_last: Option<usize>,
+) -> Result<RankComputeRunResult, String> {
+    let source_path = source_path.trim().to_string();
+    if source_path.is_empty() {
+        return Err("数据目录为空，请先到数据管理页确认当前目录".to_string());
+    }
+
+    let start_date = normalize_rank_compute_date(start_date, "开始日期")?;
+    let end_date = normalize_rank_compute_date(end_date, "结束日期")?;
+    if start_date > end_date {
+        return Err("开始日期不能晚于结束日期".to_string());
+    }
+
+    let started_at = Instant::now();
+    let profile = scoring_incremental_to_db(
+        &source_path,
+        strategy_path,
+        "qfq",
+        &start_date,
+        &end_date,
+        archive_keep_last,
+    )?;
+    let status = get_rank_compute_status_inner(&source_path, strategy_path)?;
+    Ok(RankComputeRunResult {
+        action: "score-incremental".to_string(),
+        start_date: Some(start_date),
+        end_date: Some(end_date),
+        elapsed_ms: started_at.elapsed().as_millis() as u64,
+        timings: scoring_run_timings(&profile),
+        warnings: profile.warnings,
+        status,
+        run_id: profile.run_id,
+    })
+}
+
+fn rank_score_run_item(meta: ScoreRunMeta) -> RankScoreRunItem {
+    RankScoreRunItem {
+        run_id: meta.run_id,
+        strategy_hash: meta.strategy_hash,
+        strategy_path: meta.strategy_path,
+        adj_type: meta.adj_type,
+        start_date: meta.start_date,
+        end_date: meta.end_date,
+        created_at: meta.created_at,
+    }
+}
+
+fn normalize_rank_source_path(source_path: &str) -> Result<&str, String> {
+    let source_path = source_path.trim();
+    if source_path.is_empty() {
+        return Err("数据目录为空，请先到数据管理页确认当前目录".to_string());
+    }
+    Ok(source_path)
+}
+
+pub fn list_ranking_score_runs(source_path: &str) -> Result<Vec<RankScoreRunItem>, String> {
+    let source_path = normalize_rank_source_path(source_path)?;
+    let result_db = result_db_path(source_path);
+    if !result_db.exists() {
+        return Ok(Vec::new());
+    }
+    Ok(list_score_runs(&result_db)?
+        .into_iter()
+        .map(rank_score_run_item)
+        .collect())
+}
+
+pub fn delete_ranking_score_run(source_path: &str, run_id: &str) -> Result<(), String> {
+    let source_path = normalize_rank_source_path(source_path)?;
+    delete_score_run(&result_db_path(source_path), run_id.trim())
+}
+
+// 对比两个评分版本: 指定交易日(默认两版共同区间的最后一天)每只股票的排名变化和多/少命中的规则,
+// 以及共同区间内每条规则的命中次数
+pub fn compare_ranking_score_runs(
+    source_path: &str,
+    base_run_id: &str,
+    target_run_id: &str,
+    trade_date: Option<&str>,
+    limit: Option<usize>,
+) -> Result<RankScoreRunCompareResult, String> {
+    let source_path = normalize_rank_source_path(source_path)?;
+    let result_db = result_db_path(source_path);
+    let base = load_score_run(&result_db, base_run_id.trim())?;
+    let target = load_score_run(&result_db, target_run_id.trim())?;
+    let range_start = base.start_date.clone().max(target.start_date.clone());
+    let range_end = base.end_date.clone().min(target.end_date.clone());
+    if range_start > range_end {
+        return Err("两个评分版本的日期区间没有重叠".to_string());
+    }
+    let trade_date = match trade_date.map(str::trim).filter(|value| !value.is_empty()) {
+        Some(value) => normalize_rank_compute_date(value, "对比日期")?,
+        None => range_end.clone(),
+    };
+
+    let rule_diffs =
+        query_score_run_rule_diffs(&result_db, &base.run_id, &target.run_id, &trade_date)?
+            .into_iter()
+            .map(|item| (item.ts_code.clone(), item))
+            .collect::<HashMap<_, _>>();
+    let mut stocks =
+        query_score_run_rank_diffs(&result_db, &base.run_id, &target.run_id, &trade_date)?
+            .into_iter()
+            .map(|item| {
+                let (gained_rules, lost_rules) = rule_diffs
+                    .get(&item.ts_code)
+                    .map(|diff| (diff.gained_rules.clone(), diff.lost_rules.clone()))
+                    .unwrap_or_default();
+                RankScoreRunStockDiff {
+                    rank_change: item
+                        .base_rank
+                        .zip(item.target_rank)
+                        .map(|(base_rank, target_rank)| base_rank - target_rank),
+                    ts_code: item.ts_code,
+                    base_score: item.base_score,
+                    target_score: item.target_score,
+                    base_rank: item.base_rank,
+                    target_rank: item.target_rank,
+                    gained_rules,
+                    lost_rules,
+                }
+            })
+            .collect::<Vec<_>>();
+    stocks.sort_by(|left, right| {
+        left.target_rank
+            .unwrap_or(i64::MAX)
+            .cmp(&right.target_rank.unwrap_or(i64::MAX))
+            .then_with(|| {
+                left.base_rank
+                    .unwrap_or(i64::MAX)
+                    .cmp(&right.base_rank.unwrap_or(i64::MAX))
+            })
+            .then_with(|| left.ts_code.cmp(&right.ts_code))
+    });
+    if let Some(limit) = limit.filter(|value| *value > 0) {
+        stocks.truncate(limit);
+    }
+
+    let rules = query_score_run_rule_counts(
+        &result_db,
+        &base.run_id,
+        &target.run_id,
+        &range_start,
+        &range_end,
+    )?
+    .into_iter()
+    .map(|item| RankScoreRunRuleCountDiff {
+        rule_name: item.rule_name,
+        base_trigger_count: item.base_trigger_count,
+        target_trigger_count: item.target_trigger_count,
+    })
+    .collect::<Vec<_>>();
+    let added_rules = rules
+        .iter()
+        .filter(|item| item.base_trigger_count == 0)
+        .map(|item| item.rule_name.clone())
+        .collect();
+    let removed_rules = rules
+        .iter()
+        .filter(|item| item.target_trigger_count == 0)
+        .map(|item| item.rule_name.clone())
+        .collect();
+
+    Ok(RankScoreRunCompareResult {
+        base: rank_score_run_item(base),
+        target: rank_score_run_item(target),
+        trade_date,
+        range_start,
+        range_end,
+        stocks,
+        rules,
+        added_rules,
+        removed_rules,
     })
 }
 
@@ -845,7 +1111,17 @@ pub fn run_ranking_tiebreak_fill(
         .to_str()
         .ok_or_else(|| "原始库路径不是有效 UTF-8".to_string())?;
 
-    let profile = build_rank_tiebreak(result_db_str, source_db_str, "qfq", TieBreakWay::KdjJ)?;
+    // 结果库记了策略排序键就按排序键重排,否则按J值
+    let has_rank_order = {
+        let conn = Connection::open(result_db_str).map_err(|e| format!("打开结果库失败: {e}"))?;
+        !load_rank_order(&conn)?.is_empty()
+    };
+    let tie_break = if has_rank_order {
+        TieBreakWay::RankOrder
+    } else {
+        TieBreakWay::KdjJ
+    };
+    let profile = build_rank_tiebreak(result_db_str, source_db_str, "qfq", tie_break)?;
     let status = get_rank_compute_status_inner(&source_path, strategy_path)?;
     Ok(RankComputeRunResult {
         action: "tiebreak".to_string(),
@@ -855,5 +1131,6 @@ pub fn run_ranking_tiebreak_fill(
         timings: tiebreak_timings(&profile),
         warnings: Vec::new(),
         status,
+        run_id: None,
     })
 }
@@ -14,37 +14,37 @@ use serde::{Deserialize, Serialize};
 
 use crate::{
     data::scoring_data::{
-        SceneDetails, ScoreDetails, ScoreSummary, cache_rule_build as build_scoring_rule_cache,
-        row_into_rt,
+        SceneDetails, ScoreDetails, ScoreSummary, StrategyRuleSet,
+        cache_rule_build as build_scoring_rule_cache, load_score_run_summary_rows, row_into_rt,
     },
     data::{
         DataReader, RuleKind, RuleStage, RuleTag, RuntimeKeyCollectOptions, ScopeWay, ScoreRule,
-        ScoreScene, collect_assigned_names_from_expr_program,
-        collect_runtime_keys_from_expr_programs, concept_performance_db_path,
+        ScoreScene, collect_runtime_keys_from_expr_programs, concept_performance_db_path,
         expr_program_uses_runtime_key, load_stock_list, load_ths_concepts_list, result_db_path,
         source_db_path,
     },
     expr::{
-        eval::{Runtime, Value},
+        eval::{Runtime, Value, rule_trigger_var},
         lexer::TokenKind,
         parser::{Stmt, Stmts, lex_all},
-        validation::{
-            estimate_expression_warmup, parse_expression_program, validate_expression_functions,
-        },
+        validation::estimate_expression_warmup_with_locals,
     },
     scoring::runner::{ScoringMemoryMode, scoring_all_to_memory_with_mode},
     scoring::tools::{
-        CyqChenFieldInjector, calc_query_need_rows, calc_query_start_date,
+        CyqChenFieldInjector, StockProfile, calc_query_need_rows, calc_query_start_date,
         collect_used_cyq_chen_runtime_keys, cyq_chen_runtime_key_names, inject_stock_extra_fields,
-        load_st_list, load_total_share_map,
+        load_st_list, load_stock_profile_map, load_total_share_map,
     },
-    scoring::{CachedRule, evaluate_cached_rule_scores},
+    scoring::{CachedRule, evaluate_cached_rule_scores, weighting::RuleWeighting},
     simulate::{
         DEFAULT_BACKTEST_MIN_LISTED_TRADE_DAYS, build_backtest_sample_eligibility,
         rank::{
             RankLayerConfig, RankLayerFromDbInput, RankLayerMethod,
             calc_rank_layer_metrics_from_rank_samples, calc_rank_layer_metrics_from_score_rows,
         },
+        robustness::{
+            RobustnessConfig, RobustnessSummary, block_bootstrap_returns, block_bootstrap_spreads,
+        },
         rule::{
             DEFAULT_RULE_WITH_SAMPLES_PARALLEL_BATCH_SIZE, RuleLayerConfig,
             RuleLayerDailyScoreLayers, RuleLayerFromDbInput, RuleLayerMetricsWithValidation,
@@ -218,6 +218,9 @@ pub struct SceneLayerSceneSummary {
     pub ic_std: Option<f64>,
     pub icir: Option<f64>,
     pub ic_t_value: Option<f64>,
+    pub robustness: Option<RobustnessSummary>,
+    #[serde(skip)]
+    pub spread_daily_values: Vec<f64>,
 }
 
 #[derive(Debug, Serialize)]
@@ -299,6 +302,7 @@ pub struct RuleLayerRuleSummary {
     pub icir: Option<f64>,
     pub ic_t_value: Option<f64>,
     pub decay_validations: Vec<RuleDecayValidation>,
+    pub robustness: Option<RobustnessSummary>,
     #[serde(skip)]
     pub decay_daily_values: Vec<(String, f64)>,
 }
@@ -372,6 +376,9 @@ pub struct RankTopKSummaryData {
     pub daily_std: Option<f64>,
     pub hac_t_value: Option<f64>,
     pub hac_lag: usize,
+    pub robustness: Option<RobustnessSummary>,
+    #[serde(skip)]
+    pub daily_returns: Vec<f64>,
 }
 
 #[derive(Debug, Serialize)]
@@ -436,6 +443,14 @@ pub struct RankLayerBacktestData {
     pub market_value_summaries: Vec<RankLayerMarketValueSummary>,
 }
 
+#[derive(Debug, Serialize)]
+pub struct ScoreRunRankLayerCompareData {
+    pub base_run_id: String,
+    pub target_run_id: String,
+    pub base: RankLayerBacktestData,
+    pub target: RankLayerBacktestData,
+}
+
 #[derive(Debug, Serialize)]
 pub struct RankLayerMarketValueSummary {
     pub group_label: String,
@@ -2121,14 +2136,25 @@ fn build_validation_variants(
     Ok(out)
 }
 
-fn estimate_rule_warmup(
-    stmts: &Stmts,
-    scope_way: ScopeWay,
-    scope_windows: usize,
-) -> Result<usize, String> {
-    let expression_need = estimate_expression_warmup(stmts)?;
+// RULE("规则名") 读被引用规则的触发序列,它的 warmup 按被引用规则自己的算
+pub(crate) fn estimate_rule_warmup(rule: &CachedRule) -> Result<usize, String> {
+    let mut locals = HashMap::with_capacity(rule.rule_refs.len());
+    for rule_ref in &rule.rule_refs {
+        locals.insert(
+            rule_trigger_var(&rule_ref.name),
+            estimate_rule_warmup(rule_ref)?,
+        );
+    }
+    let mut expression_need = 0;
+    for program in rule.expression_programs() {
+        expression_need = expression_need.max(estimate_expression_warmup_with_locals(
+            program,
+            locals.clone(),
+        )?);
+    }
 
-    let scope_extra = match scope_way {
+    let scope_windows = rule.scope_windows;
+    let scope_extra = match rule.scope_way {
         ScopeWay::Last => 0,
         ScopeWay::Any | ScopeWay::Each | ScopeWay::Recent => scope_windows.saturating_sub(1),
         ScopeWay::Consec(threshold) => scope_windows
@@ -2139,7 +2165,9 @@ fn estimate_rule_warmup(
     Ok(expression_need + scope_extra)
 }
 
+#[allow(clippy::too_many_arguments)]
 fn build_validation_cached_rule(
+    strategy: &StrategyRuleSet,
     rule_name: String,
     scope_way: ScopeWay,
     scope_windows: usize,
@@ -2148,10 +2176,8 @@ fn build_validation_cached_rule(
     tag: crate::data::RuleTag,
     formula: &str,
 ) -> Result<CachedRule, String> {
-    let stmts = parse_expression_program(formula)
-        .map_err(|e| format!("表达式解析错误在{}:{}", e.idx, e.msg))?;
-    validate_expression_functions(&stmts)?;
-    let assigned_names = collect_assigned_names_from_expr_program(&stmts);
+    // 和评分一样展开 [[define]],RULE("规则名") 挂上策略里被引用的规则
+    let (expression, rule_refs) = strategy.build_expression(&rule_name, formula.to_string())?;
 
     Ok(CachedRule {
         name: rule_name,
@@ -2161,10 +2187,12 @@ fn build_validation_cached_rule(
         dist_points,
         max_points: None,
         tag,
-        when_src: formula.to_string(),
-        when_ast: stmts,
-        assigned_names,
+        when_src: expression.when_src,
+        when_ast: expression.when_ast,
+        assigned_names: expression.assigned_names,
         combination: None,
+        weighting: RuleWeighting::default(),
+        rule_refs,
     })
 }
 
@@ -2181,11 +2209,16 @@ fn collect_validation_assigned_names(stmts: &Stmts) -> Vec<String> {
     out
 }
 
-fn collect_rule_validation_runtime_keys(combos: &[PreparedValidationCombo]) -> HashSet<String> {
-    let programs = combos
+// 组合自己的表达式加上 RULE 引用的规则的表达式,取数和注入字段都要覆盖到
+fn validation_combo_programs(combos: &[PreparedValidationCombo]) -> Vec<&Stmts> {
+    combos
         .iter()
-        .map(|combo| &combo.cached_rule.when_ast)
-        .collect::<Vec<_>>();
+        .flat_map(|combo| combo.cached_rule.programs_with_refs())
+        .collect()
+}
+
+fn collect_rule_validation_runtime_keys(combos: &[PreparedValidationCombo]) -> HashSet<String> {
+    let programs = validation_combo_programs(combos);
     let cyq_chen_keys = cyq_chen_runtime_key_names();
     let injected_keys = RULE_VALIDATION_INJECTED_RUNTIME_KEYS
         .iter()
@@ -2206,18 +2239,17 @@ fn collect_rule_validation_runtime_keys(combos: &[PreparedValidationCombo]) -> H
 fn collect_rule_validation_cyq_chen_runtime_keys(
     combos: &[PreparedValidationCombo],
 ) -> HashSet<String> {
-    let programs = combos
-        .iter()
-        .map(|combo| &combo.cached_rule.when_ast)
-        .collect::<Vec<_>>();
+    let programs = validation_combo_programs(combos);
     collect_used_cyq_chen_runtime_keys(&programs)
 }
 
 fn prepare_validation_combo(
+    strategy: &StrategyRuleSet,
     seed_rule: &ValidationSeedRule,
     variant: ValidationVariant,
 ) -> Result<PreparedValidationCombo, String> {
     let cached_rule = build_validation_cached_rule(
+        strategy,
         variant.combo_key.clone(),
         seed_rule.scope_way,
         seed_rule.scope_windows,
@@ -2242,16 +2274,13 @@ fn build_validation_execution_plan(
     seed_rule: &ValidationSeedRule,
     variants: Vec<ValidationVariant>,
 ) -> Result<ValidationExecutionPlan, String> {
+    let strategy = StrategyRuleSet::load(source_path, None)?;
     let mut max_warmup_need = 0usize;
     let mut combos = Vec::with_capacity(variants.len());
 
     for variant in variants {
-        let combo = prepare_validation_combo(seed_rule, variant)?;
-        max_warmup_need = max_warmup_need.max(estimate_rule_warmup(
-            &combo.cached_rule.when_ast,
-            combo.cached_rule.scope_way,
-            combo.cached_rule.scope_windows,
-        )?);
+        let combo = prepare_validation_combo(&strategy, seed_rule, variant)?;
+        max_warmup_need = max_warmup_need.max(estimate_rule_warmup(&combo.cached_rule)?);
         combos.push(combo);
     }
 
@@ -2316,10 +2345,12 @@ fn build_validation_date_score_map(
 }
 
 fn validation_combos_use_rank_score(combos: &[PreparedValidationCombo]) -> bool {
-    combos.iter().any(|combo| {
-        expr_program_uses_runtime_key(&combo.cached_rule.when_ast, "RANK")
-            || expr_program_uses_runtime_key(&combo.cached_rule.when_ast, "SCORE")
-    })
+    validation_combo_programs(combos)
+        .into_iter()
+        .any(|program| {
+            expr_program_uses_runtime_key(program, "RANK")
+                || expr_program_uses_runtime_key(program, "SCORE")
+        })
 }
 
 #[derive(Debug, Clone, Copy, Default)]
@@ -2412,6 +2443,7 @@ fn evaluate_validation_combos_for_ts_code(
     need_rows: usize,
     st_list: &HashSet<String>,
     total_share_map: &HashMap<String, f64>,
+    profile_map: &HashMap<String, StockProfile>,
     rank_score_series_map: &HashMap<String, HashMap<String, ValidationRankScoreInfo>>,
     needs_rank_score: bool,
     combos: &[PreparedValidationCombo],
@@ -2423,6 +2455,7 @@ fn evaluate_validation_combos_for_ts_code(
         ts_code,
         st_list.contains(ts_code),
         total_share_map.get(ts_code).copied(),
+        profile_map.get(ts_code),
     )?;
     if needs_rank_score {
         inject_validation_rank_score_series(&mut row_data, ts_code, rank_score_series_map)?;
@@ -2491,6 +2524,7 @@ fn build_validation_triggered_scores_for_combos(
     let required_runtime_keys = collect_rule_validation_runtime_keys(combos);
     let used_cyq_chen_keys = collect_rule_validation_cyq_chen_runtime_keys(combos);
     let total_share_map = load_total_share_map(source_path).unwrap_or_default();
+    let profile_map = load_stock_profile_map(source_path).unwrap_or_default();
     let needs_rank_score = validation_combos_use_rank_score(combos);
     let rank_score_series_map = if needs_rank_score {
         load_validation_rank_score_series_map(source_path, query_start_date, end_date)
@@ -2530,6 +2564,7 @@ fn build_validation_triggered_scores_for_combos(
                     need_rows,
                     st_list,
                     &total_share_map,
+                    &profile_map,
                     &rank_score_series_map,
                     needs_rank_score,
                     combos,
@@ -2580,11 +2615,7 @@ fn build_validation_triggered_scores(
     let reader = DataReader::new_with_runtime_keys(source_path, &required_runtime_keys)?;
     let ts_codes = reader.list_ts_code(stock_adj_type, start_date, end_date)?;
     let st_list = load_st_list(source_path)?;
-    let warmup_need = estimate_rule_warmup(
-        &cached_rule.when_ast,
-        cached_rule.scope_way,
-        cached_rule.scope_windows,
-    )?;
+    let warmup_need = estimate_rule_warmup(cached_rule)?;
     let need_rows = calc_query_need_rows(source_path, warmup_need, start_date, end_date)?;
     let mut triggered_maps = build_validation_triggered_scores_for_combos(
         source_path,
@@ -4404,9 +4435,11 @@ pub fn run_rule_expression_calibration(
         1.0
     };
     let specs = build_validation_calibration_specs(&session.seed_rule);
+    let strategy = StrategyRuleSet::load(&session.source_path, None)?;
     let mut prepared = Vec::with_capacity(specs.len());
     for spec in &specs {
         let cached_rule = build_validation_cached_rule(
+            &strategy,
             format!("calibration__{}", spec.candidate_key),
             spec.scope_way,
             spec.scope_windows,
@@ -4428,12 +4461,7 @@ pub fn run_rule_expression_calibration(
     }
 
     let max_warmup_need = prepared.iter().try_fold(0usize, |current, item| {
-        estimate_rule_warmup(
-            &item.cached_rule.when_ast,
-            item.cached_rule.scope_way,
-            item.cached_rule.scope_windows,
-        )
-        .map(|need| current.max(need))
+        estimate_rule_warmup(&item.cached_rule).map(|need| current.max(need))
     })?;
     let need_rows = calc_query_need_rows(
         &session.source_path,
@@ -6441,6 +6469,7 @@ fn load_score_summary_rows_from_db(
             trade_date: row.get(1).map_err(|e| format!("读取总榜日期失败: {e}"))?,
             total_score: row.get(2).map_err(|e| format!("读取总榜分数失败: {e}"))?,
             rank: row.get(3).map_err(|e| format!("读取总榜排名失败: {e}"))?,
+            ..ScoreSummary::default()
         };
         if ts_code_allowed_by_filter(allowed_ts_codes, &item.ts_code) {
             summaries.push(item);
@@ -6766,6 +6795,12 @@ fn run_scene_layer_backtest_core(
             ic_std: metrics.ic_std,
             icir: metrics.icir,
             ic_t_value: metrics.ic_t_value,
+            robustness: None,
+            spread_daily_values: metrics
+                .points
+                .iter()
+                .filter_map(|point| point.top_bottom_spread)
+                .collect(),
         });
     }
 
@@ -7214,6 +7249,7 @@ fn build_one_rule_backtest_summary_and_detail(
         icir: metrics.icir,
         ic_t_value: metrics.ic_t_value,
         decay_validations,
+        robustness: None,
         decay_daily_values,
     };
     let detail = rule_meta_map.get(one_rule_name).map(|rule_meta| {
@@ -7289,6 +7325,8 @@ fn rank_top_k_summary_data(
             daily_std: item.daily_std,
             hac_t_value: item.hac_t_value,
             hac_lag: item.hac_lag,
+            robustness: None,
+            daily_returns: item.daily_returns,
         })
         .collect()
 }
@@ -7406,6 +7444,21 @@ fn run_rank_layer_backtest_core(
     source_conn: &Connection,
     source_path: &str,
     params: &RankLayerBacktestRunParams,
+) -> Result<RankLayerBacktestData, String> {
+    let summary_rows = load_score_summary_rows_from_db(
+        source_path,
+        &params.start_date,
+        &params.end_date,
+        params.allowed_ts_codes.as_ref(),
+    )?;
+    run_rank_layer_backtest_from_rows(source_conn, source_path, params, &summary_rows)
+}
+
+fn run_rank_layer_backtest_from_rows(
+    source_conn: &Connection,
+    source_path: &str,
+    params: &RankLayerBacktestRunParams,
+    summary_rows: &[ScoreSummary],
 ) -> Result<RankLayerBacktestData, String> {
     let layer_config = RankLayerConfig {
         min_samples_per_day: params.min_samples_per_day,
@@ -7424,18 +7477,12 @@ fn run_rank_layer_backtest_core(
         end_date: params.end_date.clone(),
         layer_config,
     };
-    let summary_rows = load_score_summary_rows_from_db(
-        source_path,
-        &params.start_date,
-        &params.end_date,
-        params.allowed_ts_codes.as_ref(),
-    )?;
     let metrics =
-        calc_rank_layer_metrics_from_score_rows(source_conn, source_path, &input, &summary_rows)?;
+        calc_rank_layer_metrics_from_score_rows(source_conn, source_path, &input, summary_rows)?;
     let market_value_summaries = build_rank_market_value_summaries(
         source_path,
         &input,
-        &summary_rows,
+        summary_rows,
         &metrics.layer_samples,
     )?;
     let stock_meta_map = load_validation_sample_stock_meta_map(source_path)?;
@@ -7639,7 +7686,11 @@ pub fn run_scene_layer_backtest(
     exclude_st_board: Option<bool>,
     total_mv_min: Option<f64>,
     total_mv_max: Option<f64>,
+    robustness: Option<RobustnessConfig>,
 ) -> Result<SceneLayerBacktestData, String> {
+    if let Some(config) = robustness.as_ref() {
+        config.validate()?;
+    }
     validate_backtest_strategy_expressions(&source_path)?;
     let source_db = source_db_path(&source_path);
     let source_db_str = source_db
@@ -7679,7 +7730,14 @@ pub fn run_scene_layer_backtest(
     };
 
     // 当前入口固定全量；后续如需恢复单场景，仅需传入 Some(scene_name)。
-    run_scene_layer_backtest_core(&source_conn, &source_path, None, &params)
+    let mut data = run_scene_layer_backtest_core(&source_conn, &source_path, None, &params)?;
+    if let Some(config) = robustness.as_ref() {
+        for summary in &mut data.all_scene_summaries {
+            summary.robustness =
+                block_bootstrap_spreads(&summary.spread_daily_values, data.backtest_period, config);
+        }
+    }
+    Ok(data)
 }
 
 pub fn run_rule_layer_backtest(
@@ -7699,7 +7757,11 @@ pub fn run_rule_layer_backtest(
     exclude_st_board: Option<bool>,
     total_mv_min: Option<f64>,
     total_mv_max: Option<f64>,
+    robustness: Option<RobustnessConfig>,
 ) -> Result<RuleLayerBacktestData, String> {
+    if let Some(config) = robustness.as_ref() {
+        config.validate()?;
+    }
     validate_backtest_strategy_expressions(&source_path)?;
     let source_db = source_db_path(&source_path);
     let source_db_str = source_db
@@ -7742,7 +7804,19 @@ pub fn run_rule_layer_backtest(
     };
 
     // 当前入口固定全量；后续如需恢复单策略，仅需传入 Some(rule_name)。
-    run_rule_layer_backtest_core(&source_conn, &source_path, None, &params)
+    let mut data = run_rule_layer_backtest_core(&source_conn, &source_path, None, &params)?;
+    if let Some(config) = robustness.as_ref() {
+        for summary in &mut data.all_rule_summaries {
+            let daily_values = summary
+                .decay_daily_values
+                .iter()
+                .map(|(_, value)| *value)
+                .collect::<Vec<_>>();
+            summary.robustness =
+                block_bootstrap_returns(&daily_values, data.backtest_period, config);
+        }
+    }
+    Ok(data)
 }
 
 pub fn run_rank_layer_backtest(
@@ -7761,18 +7835,71 @@ pub fn run_rank_layer_backtest(
     layer_method: Option<String>,
     board: Option<String>,
     exclude_st_board: Option<bool>,
+    robustness: Option<RobustnessConfig>,
 ) -> Result<RankLayerBacktestData, String> {
+    if let Some(config) = robustness.as_ref() {
+        config.validate()?;
+    }
     validate_backtest_strategy_expressions(&source_path)?;
-    let source_db = source_db_path(&source_path);
+    let source_conn = open_source_conn(&source_path)?;
+    let params = build_rank_layer_backtest_params(
+        &source_path,
+        stock_adj_type,
+        index_ts_code,
+        index_beta,
+        concept_beta,
+        industry_beta,
+        start_date,
+        end_date,
+        min_samples_per_rank_day,
+        min_listed_trade_days,
+        backtest_period,
+        layer_count,
+        layer_method,
+        board,
+        exclude_st_board,
+    )?;
+
+    let mut data = run_rank_layer_backtest_core(&source_conn, &source_path, &params)?;
+    if let Some(config) = robustness.as_ref() {
+        for summary in &mut data.top_k_summaries {
+            summary.robustness =
+                block_bootstrap_returns(&summary.daily_returns, data.backtest_period, config);
+        }
+    }
+    Ok(data)
+}
+
+fn open_source_conn(source_path: &str) -> Result<Connection, String> {
+    let source_db = source_db_path(source_path);
     let source_db_str = source_db
         .to_str()
         .ok_or_else(|| "原始库路径不是有效UTF-8".to_string())?;
-    let source_conn =
-        Connection::open(source_db_str).map_err(|e| format!("打开原始库失败: {e}"))?;
+    Connection::open(source_db_str).map_err(|e| format!("打开原始库失败: {e}"))
+}
+
+#[allow(clippy::too_many_arguments)]
+fn build_rank_layer_backtest_params(
+    source_path: &str,
+    stock_adj_type: Option<String>,
+    index_ts_code: String,
+    index_beta: Option<f64>,
+    concept_beta: Option<f64>,
+    industry_beta: Option<f64>,
+    start_date: String,
+    end_date: String,
+    min_samples_per_rank_day: Option<usize>,
+    min_listed_trade_days: Option<usize>,
+    backtest_period: Option<usize>,
+    layer_count: Option<usize>,
+    layer_method: Option<String>,
+    board: Option<String>,
+    exclude_st_board: Option<bool>,
+) -> Result<RankLayerBacktestRunParams, String> {
     let (resolved_board, exclude_st_board, _total_mv_min, _total_mv_max, allowed_ts_codes) =
-        build_backtest_stock_filter(&source_path, board, exclude_st_board, None, None)?;
+        build_backtest_stock_filter(source_path, board, exclude_st_board, None, None)?;
 
-    let params = RankLayerBacktestRunParams {
+    Ok(RankLayerBacktestRunParams {
         stock_adj_type: stock_adj_type
             .unwrap_or_else(|| "qfq".to_string())
             .trim()
@@ -7795,9 +7922,68 @@ pub fn run_rank_layer_backtest(
         resolved_board,
         exclude_st_board,
         allowed_ts_codes,
+    })
+}
+
+// 两个评分版本各自的总榜跑一遍排名分层回测,参数完全相同
+#[allow(clippy::too_many_arguments)]
+pub fn run_score_run_rank_layer_compare(
+    source_path: String,
+    base_run_id: String,
+    target_run_id: String,
+    stock_adj_type: Option<String>,
+    index_ts_code: String,
+    index_beta: Option<f64>,
+    concept_beta: Option<f64>,
+    industry_beta: Option<f64>,
+    start_date: String,
+    end_date: String,
+    min_samples_per_rank_day: Option<usize>,
+    min_listed_trade_days: Option<usize>,
+    backtest_period: Option<usize>,
+    layer_count: Option<usize>,
+    layer_method: Option<String>,
+    board: Option<String>,
+    exclude_st_board: Option<bool>,
+) -> Result<ScoreRunRankLayerCompareData, String> {
+    let source_conn = open_source_conn(&source_path)?;
+    let params = build_rank_layer_backtest_params(
+        &source_path,
+        stock_adj_type,
+        index_ts_code,
+        index_beta,
+        concept_beta,
+        industry_beta,
+        start_date,
+        end_date,
+        min_samples_per_rank_day,
+        min_listed_trade_days,
+        backtest_period,
+        layer_count,
+        layer_method,
+        board,
+        exclude_st_board,
+    )?;
+    let result_db = result_db_path(&source_path);
+    let run_backtest = |run_id: &str| -> Result<RankLayerBacktestData, String> {
+        let summary_rows = load_score_run_summary_rows(
+            &result_db,
+            run_id.trim(),
+            &params.start_date,
+            &params.end_date,
+        )?
+        .into_iter()
+        .filter(|row| ts_code_allowed_by_filter(params.allowed_ts_codes.as_ref(), &row.ts_code))
+        .collect::<Vec<_>>();
+        run_rank_layer_backtest_from_rows(&source_conn, &source_path, &params, &summary_rows)
     };
 
-    run_rank_layer_backtest_core(&source_conn, &source_path, &params)
+    Ok(ScoreRunRankLayerCompareData {
+        base: run_backtest(&base_run_id)?,
+        target: run_backtest(&target_run_id)?,
+        base_run_id,
+        target_run_id,
+    })
 }
 
 pub fn run_transient_scene_layer_backtest(
@@ -7896,6 +8082,12 @@ pub fn run_transient_scene_layer_backtest(
             ic_std: metrics.ic_std,
             icir: metrics.icir,
             ic_t_value: metrics.ic_t_value,
+            robustness: None,
+            spread_daily_values: metrics
+                .points
+                .iter()
+                .filter_map(|point| point.top_bottom_spread)
+                .collect(),
         });
     }
     all_scene_summaries.sort_by(|a, b| {
@@ -8272,6 +8464,7 @@ mod tests {
         collections::HashMap,
         fs::{create_dir_all, write},
         path::PathBuf,
+        sync::Arc,
         time::{SystemTime, UNIX_EPOCH},
     };
 
@@ -8279,10 +8472,11 @@ mod tests {
 
     use crate::{
         data::{
-            DataReader, RuleTag, result_db_path,
-            scoring_data::{ScoreDetails, ScoreSummary},
+            DataReader, RuleTag, ScoreConfig, result_db_path,
+            scoring_data::{ScoreDetails, ScoreSummary, StrategyRuleSet},
             source_db_path,
         },
+        expr::func::UserFunctions,
         scoring::tools::load_st_list,
         simulate::rank::RankLayerSamplePoint,
         simulate::rule::{
@@ -8305,12 +8499,86 @@ mod tests {
         build_validation_triggered_scores, build_validation_triggered_scores_for_combos,
         calibration_stability_factor, collect_rule_validation_runtime_keys,
         collect_validation_assigned_names, derive_validation_volatility_group,
-        estimate_net_money_flow_yuan, money_flow_rank_items, money_outflow_rank_items,
-        resolve_validation_sample_board_label, resolve_validation_trigger_count,
-        scope_way_config_label, trailing_period_gain,
+        estimate_net_money_flow_yuan, estimate_rule_warmup, money_flow_rank_items,
+        money_outflow_rank_items, resolve_validation_sample_board_label,
+        resolve_validation_trigger_count, scope_way_config_label, trailing_period_gain,
     };
     use crate::data::ScopeWay;
 
+    fn strategy_from_toml(text: &str) -> StrategyRuleSet {
+        let cfg: ScoreConfig = toml::from_str(text).expect("parse strategy");
+        StrategyRuleSet::build(cfg, Arc::new(UserFunctions::default())).expect("build strategy")
+    }
+
+    fn empty_strategy() -> StrategyRuleSet {
+        strategy_from_toml("version = 1\nscene = []\nrule = []\n")
+    }
+
+    #[test]
+    fn validation_rule_resolves_defines_and_rule_refs_like_scoring() {
+        let strategy = strategy_from_toml(
+            r#"
+version = 1
+
+[[define]]
+name = "UP"
+expr = "C > REF(C, 1)"
+
+[[scene]]
+name = "趋势"
+direction = "long"
+observe_threshold = 1.0
+trigger_threshold = 2.0
+confirm_threshold = 3.0
+fail_threshold = 1.0
+
+[[rule]]
+name = "放量"
+scene = "趋势"
+stage = "base"
+scope_windows = 3
+scope_way = "ANY"
+when = "V > MA(V, 5)"
+points = 1.0
+explain = "test"
+"#,
+        );
+
+        let rule = build_validation_cached_rule(
+            &strategy,
+            "validation_refs".to_string(),
+            ScopeWay::Last,
+            1,
+            1.0,
+            None,
+            RuleTag::Normal,
+            "UP AND RULE('放量')",
+        )
+        .expect("build validation rule");
+
+        assert!(rule.when_ast.item.len() > 1, "define 应该展开到表达式前面");
+        assert_eq!(
+            rule.rule_refs
+                .iter()
+                .map(|rule_ref| rule_ref.name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["放量"]
+        );
+        // 被引用规则的 MA(V,5) 和 ANY 3 窗口也算进 warmup
+        assert_eq!(estimate_rule_warmup(&rule).expect("warmup"), 6);
+        let keys = collect_rule_validation_runtime_keys(&[PreparedValidationCombo {
+            variant: ValidationVariant {
+                combo_key: "validation_refs".to_string(),
+                combo_label: "validation_refs".to_string(),
+                formula: rule.when_src.clone(),
+                unknown_values: Vec::new(),
+            },
+            assigned_names: collect_validation_assigned_names(&rule.when_ast),
+            cached_rule: rule,
+        }]);
+        assert!(keys.contains("V"));
+    }
+
     #[test]
     fn market_analysis_industry_map_uses_industry_instead_of_market_board() {
         let rows = vec![
@@ -8606,24 +8874,28 @@ explain = "test"
                 trade_date: "20240102".to_string(),
                 total_score: 10.0,
                 rank: Some(1),
+                ..ScoreSummary::default()
             },
             ScoreSummary {
                 ts_code: "000002.SZ".to_string(),
                 trade_date: "20240102".to_string(),
                 total_score: 5.0,
                 rank: Some(2),
+                ..ScoreSummary::default()
             },
             ScoreSummary {
                 ts_code: "000001.SZ".to_string(),
                 trade_date: "20240103".to_string(),
                 total_score: 3.0,
                 rank: Some(2),
+                ..ScoreSummary::default()
             },
             ScoreSummary {
                 ts_code: "000002.SZ".to_string(),
                 trade_date: "20240103".to_string(),
                 total_score: 9.0,
                 rank: Some(1),
+                ..ScoreSummary::default()
             },
         ];
         let detail_rows = vec![
@@ -8877,6 +9149,7 @@ explain = "test"
         prepare_validation_source_files(source_dir_str);
 
         let cached_rule = build_validation_cached_rule(
+            &empty_strategy(),
             "validation_test_rule".to_string(),
             ScopeWay::Any,
             1,
@@ -8921,6 +9194,7 @@ explain = "test"
         prepare_validation_result_rank_rows(source_dir_str);
 
         let cached_rule = build_validation_cached_rule(
+            &empty_strategy(),
             "validation_rank_rule".to_string(),
             ScopeWay::Any,
             1,
@@ -9145,6 +9419,7 @@ explain = "test"
         prepare_validation_source_files(source_dir_str);
 
         let first_rule = build_validation_cached_rule(
+            &empty_strategy(),
             "validation_combo_001".to_string(),
             ScopeWay::Any,
             1,
@@ -9155,6 +9430,7 @@ explain = "test"
         )
         .expect("build first cached rule");
         let second_rule = build_validation_cached_rule(
+            &empty_strategy(),
             "validation_combo_002".to_string(),
             ScopeWay::Any,
             1,
@@ -9231,6 +9507,7 @@ explain = "test"
     #[test]
     fn rule_validation_runtime_key_collection_skips_injected_fields() {
         let rule = build_validation_cached_rule(
+            &empty_strategy(),
             "validation_runtime_keys".to_string(),
             ScopeWay::Any,
             1,
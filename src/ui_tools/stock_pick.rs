@@ -7,20 +7,22 @@ use serde::Serialize;
 use crate::{
     data::scoring_data::row_into_rt,
     data::{
-        DataReader, RuntimeKeyCollectOptions, ScoreRule, collect_runtime_keys_from_expr_programs,
-        expr_program_uses_runtime_key, load_ths_concepts_list, result_db_path,
+        DataReader, RowData, RuntimeKeyCollectOptions, ScoreRule,
+        collect_runtime_keys_from_expr_programs, expr_program_uses_runtime_key,
+        load_expression_prelude, load_ths_concepts_list, result_db_path, stock_expression_type_env,
     },
     expr::{
         eval::Value,
         parser::Stmts,
         validation::{
-            estimate_expression_warmup, parse_expression_program, validate_expression_functions,
+            check_expression_types, estimate_expression_warmup,
+            parse_expression_program_with_functions, validate_expression_functions,
         },
     },
     scoring::tools::{
         CyqChenFieldInjector, calc_query_need_rows, calc_query_start_date,
         collect_used_cyq_chen_runtime_keys, cyq_chen_runtime_key_names, inject_stock_extra_fields,
-        load_st_list, load_total_share_map, rt_max_len,
+        load_st_list, load_stock_profile_map, load_total_share_map, rt_max_len,
     },
     utils::utils::board_category,
 };
@@ -392,6 +394,62 @@ fn collect_expression_stock_pick_runtime_keys(stmts: &Stmts) -> HashSet<String>
     )
 }
 
+// 按表达式选股的口径加载单只股票并注入全部运行时字段,行数据包含预热区间
+pub(super) fn load_expression_stock_row(
+    source_path: &str,
+    stmts: &Stmts,
+    ts_code: &str,
+    start_date: &str,
+    end_date: &str,
+) -> Result<RowData, String> {
+    let warmup_need = estimate_custom_warmup(stmts, PickScopeWay::Last)?;
+    let required_runtime_keys = collect_expression_stock_pick_runtime_keys(stmts);
+    let used_cyq_chen_keys = collect_used_cyq_chen_runtime_keys(&[stmts]);
+    let needs_rank_score = expr_program_uses_runtime_key(stmts, "RANK")
+        || expr_program_uses_runtime_key(stmts, "SCORE");
+    let query_start_date = calc_query_start_date(source_path, warmup_need, start_date)?;
+    let need_rows = calc_query_need_rows(source_path, warmup_need, start_date, end_date)?;
+
+    let reader = DataReader::new_with_runtime_keys(source_path, &required_runtime_keys)?;
+    let mut row_data = reader.load_one(ts_code, DEFAULT_ADJ_TYPE, &query_start_date, end_date)?;
+    if row_data.trade_dates.len() < need_rows {
+        let tail = reader.load_one_tail_rows(ts_code, DEFAULT_ADJ_TYPE, end_date, need_rows)?;
+        if !tail.trade_dates.is_empty() {
+            row_data = tail;
+        }
+    }
+    if row_data.trade_dates.is_empty() {
+        return Err(format!(
+            "{ts_code} 在 {start_date} 到 {end_date} 之间没有行情数据"
+        ));
+    }
+
+    let cyq_chen_injector = CyqChenFieldInjector::new(source_path, &used_cyq_chen_keys);
+    let _ = cyq_chen_injector.inject(&mut row_data, ts_code);
+    let st_list = load_st_list(source_path)?;
+    let total_share_map = load_total_share_map(source_path).unwrap_or_default();
+    let profile_map = load_stock_profile_map(source_path).unwrap_or_default();
+    inject_stock_extra_fields(
+        &mut row_data,
+        ts_code,
+        st_list.contains(ts_code),
+        total_share_map.get(ts_code).copied(),
+        profile_map.get(ts_code),
+    )?;
+    if needs_rank_score {
+        let first_trade_date = row_data
+            .trade_dates
+            .first()
+            .cloned()
+            .unwrap_or_else(|| start_date.to_string());
+        let rank_score_series_map =
+            load_rank_score_series_map(source_path, &first_trade_date, end_date);
+        inject_runtime_rank_score_series(&mut row_data, ts_code, &rank_score_series_map)?;
+    }
+
+    Ok(row_data)
+}
+
 pub fn validate_expression_stock_pick_template_expression(
     source_path: &str,
     expression: String,
@@ -401,9 +459,16 @@ pub fn validate_expression_stock_pick_template_expression(
         return Err("表达式不能为空".to_string());
     }
 
-    let stmts = parse_expression_program(&normalized_expression)
+    let functions = load_expression_prelude(source_path)?;
+    let stmts = parse_expression_program_with_functions(&normalized_expression, &functions)
         .map_err(|e| format!("表达式解析错误在{}:{}", e.idx, e.msg))?;
     validate_expression_functions(&stmts)?;
+    check_expression_types(
+        &normalized_expression,
+        &functions,
+        &stock_expression_type_env(),
+    )
+    .map_err(|e| format!("表达式类型错误在{}..{}:{}", e.start, e.end, e.msg))?;
     let warmup_need = estimate_custom_warmup(&stmts, PickScopeWay::Last)?;
     let required_runtime_keys = collect_expression_stock_pick_runtime_keys(&stmts);
     let used_cyq_chen_keys = collect_used_cyq_chen_runtime_keys(&[&stmts]);
@@ -440,11 +505,13 @@ pub fn validate_expression_stock_pick_template_expression(
     let _ = cyq_chen_injector.inject(&mut row_data, &sample_ts_code);
     let st_list = load_st_list(source_path)?;
     let total_share_map = load_total_share_map(source_path).unwrap_or_default();
+    let profile_map = load_stock_profile_map(source_path).unwrap_or_default();
     inject_stock_extra_fields(
         &mut row_data,
         &sample_ts_code,
         st_list.contains(&sample_ts_code),
         total_share_map.get(&sample_ts_code).copied(),
+        profile_map.get(&sample_ts_code),
     )?;
     if needs_rank_score {
         let first_trade_date = row_data
@@ -637,7 +704,7 @@ fn load_rank_score_series_map(
 }
 
 fn inject_runtime_rank_score_series(
-    row_data: &mut crate::data::RowData,
+    row_data: &mut RowData,
     ts_code: &str,
     rank_score_series_map: &HashMap<String, HashMap<String, RankScoreInfo>>,
 ) -> Result<(), String> {
@@ -733,9 +800,12 @@ pub fn run_expression_stock_pick(
         return Err("表达式不能为空".to_string());
     }
 
-    let stmts = parse_expression_program(expression)
+    let functions = load_expression_prelude(source_path)?;
+    let stmts = parse_expression_program_with_functions(expression, &functions)
         .map_err(|e| format!("表达式解析错误在{}:{}", e.idx, e.msg))?;
     validate_expression_functions(&stmts)?;
+    check_expression_types(expression, &functions, &stock_expression_type_env())
+        .map_err(|e| format!("表达式类型错误在{}..{}:{}", e.start, e.end, e.msg))?;
 
     let warmup_need = estimate_custom_warmup(&stmts, parsed_scope_way)?;
     let required_runtime_keys = collect_expression_stock_pick_runtime_keys(&stmts);
@@ -766,6 +836,7 @@ pub fn run_expression_stock_pick(
     let name_map = build_name_map(source_path).unwrap_or_default();
     let concept_map = build_concepts_map(source_path).unwrap_or_default();
     let total_share_map = load_total_share_map(source_path).unwrap_or_default();
+    let profile_map = load_stock_profile_map(source_path).unwrap_or_default();
     let summary_map = load_summary_map(source_path, &resolved_end_date);
     let rank_score_series_map = if needs_rank_score {
         load_rank_score_series_map(source_path, &query_start_date, &resolved_end_date)
@@ -801,6 +872,7 @@ pub fn run_expression_stock_pick(
                     ts_code,
                     st_list.contains(ts_code),
                     total_share_map.get(ts_code).copied(),
+                    profile_map.get(ts_code),
                 )?;
                 if needs_rank_score {
                     inject_runtime_rank_score_series(
@@ -1026,6 +1098,7 @@ pub fn run_concept_stock_pick(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::expr::validation::parse_expression_program;
 
     fn parse_program(expression: &str) -> Stmts {
         parse_expression_program(expression).expect("expression should parse")
@@ -6,20 +6,21 @@ use std::{
 
 use serde::{Deserialize, Serialize};
 
-use crate::data::scoring_data::row_into_rt;
-use crate::expr::eval::Value;
-use crate::expr::{
-    parser::Stmts,
-    validation::{
-        estimate_expression_warmup, parse_expression_program, validate_expression_functions,
-    },
-};
+use crate::data::scoring_data::{StrategyRuleSet, row_into_rt};
 use crate::{
-    data::{DataReader, RuleKind, RuleStage, SceneDirection, ScoreConfig, score_rule_path},
-    scoring::tools::{
-        collect_used_cyq_chen_runtime_keys, inject_optional_cyq_chen_fields,
-        inject_stock_extra_fields, load_st_list, load_total_share_map, rt_max_len,
+    data::{
+        DataReader, RuleKind, RuleStage, SceneDirection, ScoreConfig, load_expression_prelude,
+        score_rule_path,
+        strategy_file::{RuleSource, is_composed_strategy, resolve_strategy_file},
     },
+    scoring::{
+        evaluate_cached_rule_scores,
+        tools::{
+            StockProfile, collect_used_cyq_chen_runtime_keys, inject_optional_cyq_chen_fields,
+            inject_stock_extra_fields, load_st_list, load_stock_profile_map, load_total_share_map,
+        },
+    },
+    ui_tools::statistics::estimate_rule_warmup,
 };
 
 const DEFAULT_ADJ_TYPE: &str = "qfq";
@@ -85,6 +86,9 @@ pub struct StrategyManageRuleItem {
     pub points_by_hits: Option<Vec<f64>>,
     pub max_points: Option<f64>,
     pub max_bonus_points: Option<f64>,
+    // 定义这条规则的策略文件和之后覆盖过它的文件
+    pub source_file: String,
+    pub override_files: Vec<String>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -113,6 +117,8 @@ pub struct StrategyManageRuleDraft {
 pub struct StrategyManagePageData {
     pub scenes: Vec<StrategyManageSceneItem>,
     pub rules: Vec<StrategyManageRuleItem>,
+    // 用了 extends/include 的策略只展示展开结果,不能在这里直接改
+    pub editable: bool,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -126,6 +132,9 @@ struct StrategyRuleFile {
     version: u32,
     scene: Vec<StrategyRuleFileScene>,
     rule: Vec<StrategyRuleFileRule>,
+    // 这里不编辑的配置(rank_order、weighting 等),保存时原样写回
+    #[serde(flatten)]
+    extra: toml::Table,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -136,6 +145,8 @@ struct StrategyRuleFileScene {
     trigger_threshold: f64,
     confirm_threshold: f64,
     fail_threshold: f64,
+    #[serde(flatten)]
+    extra: toml::Table,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -173,6 +184,8 @@ struct StrategyRuleFileRule {
     #[serde(skip_serializing_if = "Option::is_none")]
     max_bonus_points: Option<f64>,
     explain: String,
+    #[serde(flatten)]
+    extra: toml::Table,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -188,6 +201,9 @@ fn load_rule_file(source_path: &str) -> Result<StrategyRuleFile, String> {
     let path = score_rule_path(source_path);
     let text = fs::read_to_string(&path)
         .map_err(|e| format!("读取策略规则文件失败: path={}, err={e}", path.display()))?;
+    if is_composed_rule_file_text(&text) {
+        return Err("策略文件使用了 extends/include,请直接编辑被引用的策略文件".to_string());
+    }
     let file = parse_rule_file_text(&text).map_err(|e| format!("解析策略规则文件失败: {e}"))?;
     if let Some(rule) = file
         .rule
@@ -206,6 +222,21 @@ fn parse_rule_file_text(text: &str) -> Result<StrategyRuleFile, toml::de::Error>
     toml::from_str(text)
 }
 
+fn is_composed_rule_file_text(text: &str) -> bool {
+    toml::from_str::<toml::Table>(text).is_ok_and(|table| is_composed_strategy(&table))
+}
+
+// 展开 extends/include 后的策略,附带每条规则的来源文件
+fn load_resolved_rule_file(
+    source_path: &str,
+) -> Result<(StrategyRuleFile, HashMap<String, RuleSource>), String> {
+    let resolved = resolve_strategy_file(&score_rule_path(source_path))?;
+    let file = toml::Value::Table(resolved.table)
+        .try_into()
+        .map_err(|e| format!("解析策略规则文件失败: {e}"))?;
+    Ok((file, resolved.rule_sources))
+}
+
 fn rule_file_output_path(source_path: &str, file_name: &str) -> Result<PathBuf, String> {
     let trimmed = file_name.trim();
     if trimmed.is_empty() {
@@ -291,26 +322,6 @@ fn format_rule_stage(stage: RuleStage) -> String {
     .to_string()
 }
 
-fn estimate_rule_warmup(
-    stmts: &Stmts,
-    scope_way: StrategyScopeWay,
-    scope_windows: usize,
-) -> Result<usize, String> {
-    let expression_need = estimate_expression_warmup(stmts)?;
-
-    let scope_extra = match scope_way {
-        StrategyScopeWay::Last => 0,
-        StrategyScopeWay::Any | StrategyScopeWay::Each | StrategyScopeWay::Recent => {
-            scope_windows.saturating_sub(1)
-        }
-        StrategyScopeWay::Consec(threshold) => scope_windows
-            .saturating_sub(1)
-            .max(threshold.saturating_sub(1)),
-    };
-
-    Ok(expression_need + scope_extra)
-}
-
 fn validate_scene_values(draft: &StrategyManageSceneDraft) -> Result<(), String> {
     let name = draft.name.trim();
     if name.is_empty() {
@@ -360,12 +371,6 @@ fn validate_scene_draft_basic(
 }
 
 fn validate_rule_definition(
-    source_path: &str,
-    reader: Option<&DataReader>,
-    sample_ts_code: Option<&str>,
-    latest_trade_date: Option<&str>,
-    st_list: Option<&HashSet<String>>,
-    total_share_map: Option<&HashMap<String, f64>>,
     rule: &StrategyRuleFileRule,
     scenes: &[StrategyRuleFileScene],
 ) -> Result<(), String> {
@@ -385,7 +390,7 @@ fn validate_rule_definition(
         return Err(format!("策略 {} 的 scope_windows 必须 >= 1", rule.name));
     }
     let scope_way = parse_scope_way(&rule.scope_way)?;
-    let expression_programs = match rule.kind {
+    match rule.kind {
         RuleKind::Single => {
             if rule.when.trim().is_empty() {
                 return Err(format!("策略 {} 的表达式不能为空", rule.name));
@@ -419,10 +424,6 @@ fn validate_rule_definition(
                 ));
             }
             validate_strategy_dist_points(rule, scope_way)?;
-            vec![(
-                rule.name.clone(),
-                parse_strategy_expression(&rule.name, &rule.when)?,
-            )]
         }
         RuleKind::Combination => {
             if !rule.when.trim().is_empty() || rule.points != 0.0 || rule.dist_points.is_some() {
@@ -477,7 +478,6 @@ fn validate_rule_definition(
             }
 
             let mut names = HashSet::new();
-            let mut programs = Vec::with_capacity(rule.conditions.len());
             for condition in &rule.conditions {
                 let name = condition.name.trim();
                 if name.is_empty() || condition.when.trim().is_empty() {
@@ -489,11 +489,6 @@ fn validate_rule_definition(
                         rule.name
                     ));
                 }
-                let label = format!("{} / 条件 {name}", rule.name);
-                programs.push((
-                    label.clone(),
-                    parse_strategy_expression(&label, &condition.when)?,
-                ));
                 if !condition.bonus_points.is_finite() {
                     return Err(format!(
                         "组合策略 {} 的条件 {name} bonus_points 非法",
@@ -501,32 +496,42 @@ fn validate_rule_definition(
                     ));
                 }
             }
-            programs
         }
+    }
+
+    Ok(())
+}
+
+// 按评分的路径构建草稿所在的整份策略: 展开 [[define]],RULE("规则名") 挂上被引用的规则,
+// 再用样本股票把指定的规则试算一遍
+fn validate_draft_strategy(
+    source_path: &str,
+    sample: &RuleValidationSample,
+    file: &StrategyRuleFile,
+    rule_names: &[&str],
+) -> Result<(), String> {
+    let cfg: ScoreConfig = toml::Value::try_from(file)
+        .map_err(|e| format!("序列化策略规则文件失败: {e}"))?
+        .try_into()
+        .map_err(|e| format!("解析策略规则文件失败: {e}"))?;
+    let strategy = StrategyRuleSet::build(cfg, load_expression_prelude(source_path)?)?;
+    let (Some(sample_ts_code), Some(latest_trade_date)) = (
+        sample.sample_ts_code.as_deref(),
+        sample.latest_trade_date.as_deref(),
+    ) else {
+        return Ok(());
     };
 
-    if let (
-        Some(reader),
-        Some(sample_ts_code),
-        Some(latest_trade_date),
-        Some(st_list),
-        Some(total_share_map),
-    ) = (
-        reader,
-        sample_ts_code,
-        latest_trade_date,
-        st_list,
-        total_share_map,
-    ) {
-        let warmup_need = expression_programs
+    for name in rule_names {
+        let Some(rule) = strategy
+            .rules
             .iter()
-            .map(|(_, stmts)| estimate_rule_warmup(stmts, scope_way, rule.scope_windows))
-            .collect::<Result<Vec<_>, _>>()?
-            .into_iter()
-            .max()
-            .unwrap_or(0);
-        let need_rows = (warmup_need + rule.scope_windows).max(1);
-        let mut row_data = reader.load_one_tail_rows(
+            .find(|rule| rule.name.trim() == name.trim())
+        else {
+            return Err(format!("规则不存在: {}", name.trim()));
+        };
+        let need_rows = (estimate_rule_warmup(rule)? + rule.scope_windows).max(1);
+        let mut row_data = sample.reader.load_one_tail_rows(
             sample_ts_code,
             DEFAULT_ADJ_TYPE,
             latest_trade_date,
@@ -535,41 +540,25 @@ fn validate_rule_definition(
         inject_stock_extra_fields(
             &mut row_data,
             sample_ts_code,
-            st_list.contains(sample_ts_code),
-            total_share_map.get(sample_ts_code).copied(),
+            sample.st_list.contains(sample_ts_code),
+            sample.total_share_map.get(sample_ts_code).copied(),
+            sample.profile_map.get(sample_ts_code),
         )?;
-        let program_refs = expression_programs
-            .iter()
-            .map(|(_, stmts)| stmts)
-            .collect::<Vec<_>>();
-        let used_cyq_chen_keys = collect_used_cyq_chen_runtime_keys(&program_refs);
+        let used_cyq_chen_keys = collect_used_cyq_chen_runtime_keys(&rule.programs_with_refs());
         inject_optional_cyq_chen_fields(
             &mut row_data,
             source_path,
             sample_ts_code,
             &used_cyq_chen_keys,
         );
-        for (label, stmts) in &expression_programs {
-            let mut rt = row_into_rt(row_data.clone())?;
-            let value = rt
-                .eval_program(stmts)
-                .map_err(|e| format!("策略 {label} 表达式运行错误:{}", e.msg))?;
-            let len = rt_max_len(&rt);
-            Value::as_bool_series(&value, len)
-                .map_err(|e| format!("策略 {label} 表达式返回值非布尔:{}", e.msg))?;
-        }
+        let mut rt = row_into_rt(row_data)?;
+        evaluate_cached_rule_scores(rule, &mut rt)
+            .map_err(|e| format!("策略 {} 表达式运行错误:{e}", rule.name))?;
     }
 
     Ok(())
 }
 
-fn parse_strategy_expression(label: &str, expression: &str) -> Result<Stmts, String> {
-    let stmts = parse_expression_program(expression)
-        .map_err(|error| format!("策略 {label} 表达式解析错误在{}:{}", error.idx, error.msg))?;
-    validate_expression_functions(&stmts).map_err(|error| format!("策略 {label} {error}"))?;
-    Ok(stmts)
-}
-
 fn validate_strategy_dist_points(
     rule: &StrategyRuleFileRule,
     scope_way: StrategyScopeWay,
@@ -624,18 +613,17 @@ fn map_dist_points(
     values.filter(|items| !items.is_empty())
 }
 
-fn load_validation_context(
-    source_path: &str,
-) -> Result<
-    (
-        DataReader,
-        Option<String>,
-        Option<String>,
-        HashSet<String>,
-        HashMap<String, f64>,
-    ),
-    String,
-> {
+// 规则草稿试算用的样本股票和按股票注入字段所需的数据
+struct RuleValidationSample {
+    reader: DataReader,
+    sample_ts_code: Option<String>,
+    latest_trade_date: Option<String>,
+    st_list: HashSet<String>,
+    total_share_map: HashMap<String, f64>,
+    profile_map: HashMap<String, StockProfile>,
+}
+
+fn load_validation_context(source_path: &str) -> Result<RuleValidationSample, String> {
     let reader = DataReader::new(source_path)?;
     let latest_trade_date = reader
         .conn
@@ -658,13 +646,15 @@ fn load_validation_context(
     });
     let st_list = load_st_list(source_path)?;
     let total_share_map = load_total_share_map(source_path).unwrap_or_default();
-    Ok((
+    let profile_map = load_stock_profile_map(source_path).unwrap_or_default();
+    Ok(RuleValidationSample {
         reader,
         sample_ts_code,
         latest_trade_date,
         st_list,
         total_share_map,
-    ))
+        profile_map,
+    })
 }
 
 fn draft_to_rule(draft: StrategyManageRuleDraft) -> Result<StrategyRuleFileRule, String> {
@@ -692,6 +682,7 @@ fn draft_to_rule(draft: StrategyManageRuleDraft) -> Result<StrategyRuleFileRule,
         max_points: draft.max_points,
         max_bonus_points: draft.max_bonus_points,
         explain: draft.explain.trim().to_string(),
+        extra: toml::Table::new(),
     })
 }
 
@@ -703,10 +694,15 @@ fn scene_draft_to_file(draft: StrategyManageSceneDraft) -> Result<StrategyRuleFi
         trigger_threshold: draft.trigger_threshold,
         confirm_threshold: draft.confirm_threshold,
         fail_threshold: draft.fail_threshold,
+        extra: toml::Table::new(),
     })
 }
 
-fn build_page_data(config: &StrategyRuleFile) -> StrategyManagePageData {
+fn build_page_data(
+    config: &StrategyRuleFile,
+    rule_sources: &HashMap<String, RuleSource>,
+    editable: bool,
+) -> StrategyManagePageData {
     let mut rule_count_map: HashMap<&str, usize> = HashMap::new();
     for rule in &config.rule {
         *rule_count_map.entry(rule.scene_name.trim()).or_default() += 1;
@@ -732,39 +728,59 @@ fn build_page_data(config: &StrategyRuleFile) -> StrategyManagePageData {
         .rule
         .iter()
         .enumerate()
-        .map(|(index, rule)| StrategyManageRuleItem {
-            index,
-            name: rule.name.clone(),
-            scene_name: rule.scene_name.clone(),
-            kind: rule.kind,
-            stage: format_rule_stage(rule.stage),
-            scope_way: rule.scope_way.clone(),
-            scope_windows: rule.scope_windows,
-            points: rule.points,
-            explain: rule.explain.clone(),
-            when: rule.when.clone(),
-            dist_points: rule.dist_points.clone(),
-            conditions: rule
-                .conditions
-                .iter()
-                .map(|condition| StrategyManageRuleCondition {
-                    name: condition.name.clone(),
-                    when: condition.when.clone(),
-                    bonus_points: condition.bonus_points,
-                })
-                .collect(),
-            points_by_hits: rule.points_by_hits.clone(),
-            max_points: rule.max_points,
-            max_bonus_points: rule.max_bonus_points,
+        .map(|(index, rule)| {
+            let source = rule_sources
+                .get(rule.name.trim())
+                .cloned()
+                .unwrap_or_default();
+            StrategyManageRuleItem {
+                index,
+                name: rule.name.clone(),
+                scene_name: rule.scene_name.clone(),
+                kind: rule.kind,
+                stage: format_rule_stage(rule.stage),
+                scope_way: rule.scope_way.clone(),
+                scope_windows: rule.scope_windows,
+                points: rule.points,
+                explain: rule.explain.clone(),
+                when: rule.when.clone(),
+                dist_points: rule.dist_points.clone(),
+                conditions: rule
+                    .conditions
+                    .iter()
+                    .map(|condition| StrategyManageRuleCondition {
+                        name: condition.name.clone(),
+                        when: condition.when.clone(),
+                        bonus_points: condition.bonus_points,
+                    })
+                    .collect(),
+                points_by_hits: rule.points_by_hits.clone(),
+                max_points: rule.max_points,
+                max_bonus_points: rule.max_bonus_points,
+                source_file: source.file,
+                override_files: source.overrides,
+            }
         })
         .collect();
 
-    StrategyManagePageData { scenes, rules }
+    StrategyManagePageData {
+        scenes,
+        rules,
+        editable,
+    }
 }
 
 pub fn get_strategy_manage_page(source_path: &str) -> Result<StrategyManagePageData, String> {
-    let config = load_rule_file(source_path)?;
-    Ok(build_page_data(&config))
+    let path = score_rule_path(source_path);
+    let text = fs::read_to_string(&path)
+        .map_err(|e| format!("读取策略规则文件失败: path={}, err={e}", path.display()))?;
+    let editable = !is_composed_rule_file_text(&text);
+    if editable {
+        // 单文件策略照旧检查旧版独立 bonus
+        load_rule_file(source_path)?;
+    }
+    let (config, rule_sources) = load_resolved_rule_file(source_path)?;
+    Ok(build_page_data(&config, &rule_sources, editable))
 }
 
 pub fn check_strategy_manage_scene_draft(
@@ -862,18 +878,24 @@ pub fn check_strategy_manage_rule_draft(
     }) {
         return Err(format!("规则名称重复: {}", rule.name));
     }
-    let (reader, sample_ts_code, latest_trade_date, st_list, total_share_map) =
-        load_validation_context(source_path)?;
-    validate_rule_definition(
-        source_path,
-        Some(&reader),
-        sample_ts_code.as_deref(),
-        latest_trade_date.as_deref(),
-        Some(&st_list),
-        Some(&total_share_map),
-        &rule,
-        &config.scene,
-    )?;
+    validate_rule_definition(&rule, &config.scene)?;
+
+    let rule_name = rule.name.clone();
+    let mut file = config;
+    match original_name.and_then(|old| {
+        file.rule
+            .iter_mut()
+            .find(|item| item.name.trim() == old.trim())
+    }) {
+        Some(item) => {
+            let extra = std::mem::take(&mut item.extra);
+            *item = rule;
+            item.extra = extra;
+        }
+        None => file.rule.push(rule),
+    }
+    let sample = load_validation_context(source_path)?;
+    validate_draft_strategy(source_path, &sample, &file, &[rule_name.as_str()])?;
     Ok("rule 草稿检查通过".to_string())
 }
 
@@ -919,11 +941,42 @@ pub fn update_strategy_manage_rule(
     else {
         return Err(format!("规则不存在: {}", original_name.trim()));
     };
+    let extra = std::mem::take(&mut rule.extra);
     *rule = draft_to_rule(draft)?;
+    rule.extra = extra;
     save_rule_file(source_path, &config)?;
     get_strategy_manage_page(source_path)
 }
 
+// 页面只编辑场景和规则的基础字段; weighting、[[define]]、rank_order 和
+// 规则的 normalize/weight、场景的 weight 等从当前策略按名称带到新文件
+fn carry_unmanaged_keys(file: &mut StrategyRuleFile, current: StrategyRuleFile) {
+    let mut scene_extras = current
+        .scene
+        .into_iter()
+        .map(|scene| (scene.name.trim().to_string(), scene.extra))
+        .collect::<HashMap<_, _>>();
+    for scene in &mut file.scene {
+        if let Some(extra) = scene_extras.remove(&scene.name) {
+            scene.extra = extra;
+        }
+    }
+    let mut rule_extras = current
+        .rule
+        .into_iter()
+        .map(|rule| (rule.name.trim().to_string(), rule.extra))
+        .collect::<HashMap<_, _>>();
+    for rule in &mut file.rule {
+        if let Some(extra) = rule_extras.remove(&rule.name) {
+            rule.extra = extra;
+        }
+    }
+    // 新文件是展开后的单文件策略,不再带 extends/include
+    file.extra = current.extra;
+    file.extra.remove("extends");
+    file.extra.remove("include");
+}
+
 pub fn save_strategy_manage_refactor_file(
     source_path: &str,
     file_name: &str,
@@ -956,8 +1009,6 @@ pub fn save_strategy_manage_refactor_file(
         scene_items.push(scene_draft_to_file(checked)?);
     }
 
-    let (reader, sample_ts_code, latest_trade_date, st_list, total_share_map) =
-        load_validation_context(source_path)?;
     let mut rule_name_set: HashSet<String> = HashSet::new();
     let mut rule_items = Vec::with_capacity(draft.rules.len());
     for rule_draft in draft.rules {
@@ -965,24 +1016,25 @@ pub fn save_strategy_manage_refactor_file(
         if !rule_name_set.insert(rule.name.clone()) {
             return Err(format!("规则名称重复: {}", rule.name));
         }
-        validate_rule_definition(
-            source_path,
-            Some(&reader),
-            sample_ts_code.as_deref(),
-            latest_trade_date.as_deref(),
-            Some(&st_list),
-            Some(&total_share_map),
-            &rule,
-            &scene_items,
-        )?;
+        validate_rule_definition(&rule, &scene_items)?;
         rule_items.push(rule);
     }
 
-    let file = StrategyRuleFile {
+    let mut file = StrategyRuleFile {
         version: 1,
         scene: scene_items,
         rule: rule_items,
+        extra: toml::Table::new(),
     };
+    let (current, _) = load_resolved_rule_file(source_path)?;
+    carry_unmanaged_keys(&mut file, current);
+    let sample = load_validation_context(source_path)?;
+    let rule_names = file
+        .rule
+        .iter()
+        .map(|rule| rule.name.as_str())
+        .collect::<Vec<_>>();
+    validate_draft_strategy(source_path, &sample, &file, &rule_names)?;
 
     let text = toml::to_string_pretty(&file).map_err(|e| format!("序列化策略规则文件失败: {e}"))?;
     fs::write(&output_path, text).map_err(|e| {
@@ -1004,7 +1056,10 @@ mod tests {
 
     use duckdb::{Connection, params};
 
-    use super::{StrategyManageRuleDraft, check_strategy_manage_rule_draft, parse_rule_file_text};
+    use super::{
+        StrategyManageRuleDraft, StrategyRuleFile, StrategyRuleFileRule, StrategyRuleFileScene,
+        carry_unmanaged_keys, check_strategy_manage_rule_draft, parse_rule_file_text,
+    };
     use crate::data::source_db_path;
 
     fn temp_source_dir() -> std::path::PathBuf {
@@ -1162,6 +1217,123 @@ bonus_points = 1.0
         assert_eq!(reparsed.rule[0].conditions[0].bonus_points, 1.0);
     }
 
+    #[test]
+    fn rule_file_round_trip_keeps_unmanaged_keys() {
+        let text = r#"
+version = 1
+rank_order = ["total_score desc", "AMOUNT desc"]
+
+[weighting]
+normalize = "zscore"
+
+[[scene]]
+name = "趋势启动"
+direction = "long"
+observe_threshold = 1.0
+trigger_threshold = 2.0
+confirm_threshold = 3.0
+fail_threshold = 1.0
+weight = 2.0
+
+[[rule]]
+name = "启动测试"
+scene = "趋势启动"
+stage = "base"
+scope_windows = 1
+scope_way = "LAST"
+when = "C > O"
+points = 2.0
+normalize = "raw"
+explain = "test"
+"#;
+
+        let file = parse_rule_file_text(text).expect("file should parse");
+        let serialized = toml::to_string_pretty(&file).expect("file should serialize");
+        let reparsed: toml::Table = toml::from_str(&serialized).expect("serialized toml");
+
+        assert_eq!(reparsed["rank_order"].as_array().map(Vec::len), Some(2));
+        assert_eq!(reparsed["weighting"]["normalize"].as_str(), Some("zscore"));
+        assert_eq!(reparsed["scene"][0]["weight"].as_float(), Some(2.0));
+        assert_eq!(reparsed["rule"][0]["normalize"].as_str(), Some("raw"));
+    }
+
+    #[test]
+    fn refactor_save_carries_unmanaged_keys_by_name() {
+        let current = parse_rule_file_text(
+            r#"
+version = 1
+rank_order = ["total_score desc", "AMOUNT desc"]
+extends = "base.toml"
+
+[weighting]
+normalize = "zscore"
+
+[[define]]
+name = "UP"
+expr = "C > O"
+
+[[scene]]
+name = "趋势启动"
+direction = "long"
+observe_threshold = 1.0
+trigger_threshold = 2.0
+confirm_threshold = 3.0
+fail_threshold = 1.0
+weight = 2.0
+
+[[rule]]
+name = "启动测试"
+scene = "趋势启动"
+stage = "base"
+scope_windows = 1
+scope_way = "LAST"
+when = "UP"
+points = 2.0
+normalize = "raw"
+weight = 0.5
+explain = "test"
+"#,
+        )
+        .expect("current file should parse");
+        let mut file = StrategyRuleFile {
+            version: 1,
+            scene: current
+                .scene
+                .iter()
+                .cloned()
+                .map(|scene| StrategyRuleFileScene {
+                    extra: toml::Table::new(),
+                    ..scene
+                })
+                .collect(),
+            rule: vec![
+                StrategyRuleFileRule {
+                    extra: toml::Table::new(),
+                    ..current.rule[0].clone()
+                },
+                StrategyRuleFileRule {
+                    name: "新规则".to_string(),
+                    extra: toml::Table::new(),
+                    ..current.rule[0].clone()
+                },
+            ],
+            extra: toml::Table::new(),
+        };
+
+        carry_unmanaged_keys(&mut file, current);
+        let serialized = toml::to_string_pretty(&file).expect("file should serialize");
+        let reparsed: toml::Table = toml::from_str(&serialized).expect("serialized toml");
+
+        assert_eq!(reparsed["rank_order"].as_array().map(Vec::len), Some(2));
+        assert_eq!(reparsed["weighting"]["normalize"].as_str(), Some("zscore"));
+        assert_eq!(reparsed["define"][0]["name"].as_str(), Some("UP"));
+        assert!(!reparsed.contains_key("extends"));
+        assert_eq!(reparsed["scene"][0]["weight"].as_float(), Some(2.0));
+        assert_eq!(reparsed["rule"][0]["normalize"].as_str(), Some("raw"));
+        assert_eq!(reparsed["rule"][0]["weight"].as_float(), Some(0.5));
+        assert!(reparsed["rule"][1].get("normalize").is_none());
+    }
+
     #[test]
     fn parse_strategy_rule_file_with_legacy_weight() {
         let text = r#"
@@ -1,13 +1,13 @@
 use std::{
     collections::{BTreeMap, HashMap, HashSet},
-    sync::Mutex,
+    sync::{Arc, Mutex},
 };
 
 use chrono::{Days, NaiveDate};
 use duckdb::{Connection, params};
 use rand::{Rng, SeedableRng, rngs::StdRng};
 use rayon::prelude::*;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
 use crate::{
     data::scoring_data::row_into_rt,
@@ -26,11 +26,23 @@ use crate::{
         },
     },
     scoring::tools::{
-        CyqChenFieldInjector, calc_query_need_rows, calc_query_start_date,
+        CyqChenFieldInjector, StockProfile, calc_query_need_rows, calc_query_start_date,
         collect_used_cyq_chen_runtime_keys, cyq_chen_runtime_key_names, inject_stock_extra_fields,
-        load_st_list, load_total_share_map, rt_max_len,
+        load_st_list, load_stock_profile_map, load_total_share_map, rt_max_len,
+    },
+    simulate::{
+        DEFAULT_BACKTEST_MIN_LISTED_TRADE_DAYS,
+        cost::{TradeCostBreakdown, TradeCostConfig, TradeSide, load_trade_cost_config},
+        portfolio::{
+            BOARD_LOT_SHARES, PORTFOLIO_VOLATILITY_WINDOW, PortfolioBacktestResult,
+            PortfolioBuyOrder, PortfolioConfig, PortfolioEngine, PortfolioSizingMode,
+            trailing_return_volatility,
+        },
+        robustness::{
+            RobustnessConfig, RobustnessSample, RobustnessSummary, block_bootstrap_returns,
+            summarize_robustness_runs,
+        },
     },
-    simulate::DEFAULT_BACKTEST_MIN_LISTED_TRADE_DAYS,
     ui_tools::watch_observe::normalize_ts_code,
     utils::utils::board_category,
 };
@@ -42,7 +54,12 @@ const DEFAULT_INDEX_TS_CODE: &str = "000001.SH";
 const DEFAULT_BUY_PRICE_BASIS: &str = "open";
 const DEFAULT_MAX_POSITION_COUNT: usize = 5;
 const DEFAULT_BUY_SELECTION_MODE: &str = "random";
+const DEFAULT_INITIAL_CAPITAL: f64 = 1_000_000.0;
+const DEFAULT_PORTFOLIO_SIZING_MODE: &str = "equal_weight";
+const DEFAULT_EXIT_ATR_PERIOD: usize = 14;
 const PAPER_VALIDATION_RANDOM_SEED: u64 = 0x4c48_5056_2026_0509;
+// 随机选股重跑次数上限,每次都是完整的逐日撮合
+const MAX_RANDOM_ENTRY_RERUNS: usize = 200;
 const PRICE_EPS: f64 = 1e-12;
 const PAPER_VALIDATION_ALWAYS_RUNTIME_KEYS: [&str; 4] = ["O", "H", "C", "PRE_CLOSE"];
 const PAPER_VALIDATION_INJECTED_RUNTIME_KEYS: [&str; 7] = [
@@ -79,6 +96,8 @@ pub struct StrategyPaperValidationDefaultsData {
     pub slippage_pct: f64,
     pub max_position_count: usize,
     pub buy_selection_mode: String,
+    pub initial_capital: f64,
+    pub sizing_mode: String,
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -111,9 +130,53 @@ pub struct StrategyPaperValidationTradeRow {
     pub close_return_pct: Option<f64>,
     pub realized_return_pct: Option<f64>,
     pub daily_holding_close_returns: Vec<StrategyPaperValidationDailyHoldingCloseReturn>,
+    pub cost: StrategyPaperValidationTradeCost,
+    pub exit_reason: Option<String>,
     pub status: String,
 }
 
+/// 卖点方程之外的声明式离场规则,都只在买入后的交易日生效(T+1)。
+///
+/// 止损、ATR 止损、移动止损和止盈按盘中价位触发:开盘跳空越过价位按开盘价成交,
+/// 同一根 K 线上止损和止盈都被触及时按 `intrabar_order` 决定先后。
+/// 最长持有天数和排名跌出前 N 在收盘时判断,按正常卖出价成交。
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct StrategyPaperValidationExitRules {
+    pub stop_loss_pct: Option<f64>,
+    pub take_profit_pct: Option<f64>,
+    // 止损价 = 买入成本 - 倍数 * 买入日 ATR
+    pub atr_stop_multiple: Option<f64>,
+    pub atr_period: Option<usize>,
+    // 自买入以来最高收盘价回撤的百分比
+    pub trailing_stop_pct: Option<f64>,
+    pub max_hold_days: Option<usize>,
+    // 排名数值大于 N(跌出前 N)时卖出
+    pub max_rank: Option<i64>,
+    pub intrabar_order: PaperIntrabarOrder,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PaperIntrabarOrder {
+    // 保守假设:先触发止损
+    #[default]
+    StopFirst,
+    TargetFirst,
+    // 离开盘价更近的价位先触发
+    NearestToOpen,
+}
+
+// 单笔交易的费用明细,未平仓交易没有卖出费用
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct StrategyPaperValidationTradeCost {
+    pub shares: u64,
+    pub buy_fee: TradeCostBreakdown,
+    pub sell_fee: Option<TradeCostBreakdown>,
+    pub total_fee: f64,
+    pub total_fee_pct: Option<f64>,
+}
+
 #[derive(Debug, Clone, Serialize)]
 pub struct StrategyPaperValidationDailyHoldingCloseReturn {
     pub trade_date: String,
@@ -143,11 +206,22 @@ pub struct StrategyPaperValidationData {
     pub buy_selection_mode: String,
     pub buy_expression: String,
     pub sell_expression: String,
+    pub trade_cost: TradeCostConfig,
+    pub exit_rules: StrategyPaperValidationExitRules,
     pub summary: StrategyPaperValidationSummaryData,
+    // random_entry_order 时 avg_excess_return 是单笔实现收益,
+    // block_bootstrap 时是组合日收益,都不扣基准
+    pub robustness: Option<RobustnessSummary>,
     pub trades: Vec<StrategyPaperValidationTradeRow>,
     pub index_daily_returns: Vec<StrategyPaperValidationIndexDailyReturn>,
 }
 
+#[derive(Debug, Clone, Serialize)]
+pub struct StrategyPortfolioBacktestData {
+    pub validation: StrategyPaperValidationData,
+    pub portfolio: PortfolioBacktestResult,
+}
+
 #[derive(Debug, Clone, Serialize)]
 pub struct StrategyPaperValidationTemplateValidationData {
     pub normalized_buy_expression: String,
@@ -171,6 +245,17 @@ enum BuySelectionMode {
     Random,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PaperExitReason {
+    SellExpression,
+    StopLoss,
+    AtrStop,
+    TrailingStop,
+    TakeProfit,
+    MaxHoldDays,
+    RankDrop,
+}
+
 #[derive(Debug, Clone, Copy)]
 enum TradeDateResolveMode {
     Start,
@@ -196,6 +281,55 @@ impl BuyPriceBasis {
     }
 }
 
+impl PaperExitReason {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::SellExpression => "sell_expression",
+            Self::StopLoss => "stop_loss",
+            Self::AtrStop => "atr_stop",
+            Self::TrailingStop => "trailing_stop",
+            Self::TakeProfit => "take_profit",
+            Self::MaxHoldDays => "max_hold_days",
+            Self::RankDrop => "rank_drop",
+        }
+    }
+}
+
+impl StrategyPaperValidationExitRules {
+    fn validate(&self) -> Result<(), String> {
+        for (label, value) in [
+            ("止损比例", self.stop_loss_pct),
+            ("止盈比例", self.take_profit_pct),
+            ("ATR 止损倍数", self.atr_stop_multiple),
+            ("移动止损比例", self.trailing_stop_pct),
+        ] {
+            if let Some(value) = value
+                && (!value.is_finite() || value <= 0.0)
+            {
+                return Err(format!("{label}必须是大于 0 的有限数字"));
+            }
+        }
+        for (label, value) in [
+            ("止损比例", self.stop_loss_pct),
+            ("移动止损比例", self.trailing_stop_pct),
+        ] {
+            if value.is_some_and(|value| value >= 100.0) {
+                return Err(format!("{label}必须小于 100"));
+            }
+        }
+        if self.atr_period == Some(0) {
+            return Err("ATR 周期必须大于 0".to_string());
+        }
+        if self.max_hold_days == Some(0) {
+            return Err("最长持有天数必须大于 0".to_string());
+        }
+        if self.max_rank.is_some_and(|value| value < 1) {
+            return Err("排名阈值必须大于等于 1".to_string());
+        }
+        Ok(())
+    }
+}
+
 impl BuySelectionMode {
     fn parse(raw: &str) -> Result<Self, String> {
         match raw.trim().to_ascii_lowercase().as_str() {
@@ -222,8 +356,12 @@ struct OpenPosition {
     buy_rank: Option<i64>,
     buy_basis_price: f64,
     buy_cost_price: f64,
+    shares: u64,
+    buy_fee: TradeCostBreakdown,
+    entry_atr: Option<f64>,
     last_sell_runtime_index: usize,
-    pending_sell: bool,
+    // 已触发离场但因跌停等原因未成交,之后按正常卖出价尽快卖出
+    pending_exit: Option<PaperExitReason>,
     sell_runtime: Runtime,
 }
 
@@ -242,10 +380,13 @@ struct PreparedPaperStock {
     buy_signal_series: Vec<bool>,
     open_series: Vec<Option<f64>>,
     high_series: Vec<Option<f64>>,
+    low_series: Vec<Option<f64>>,
     close_series: Vec<Option<f64>>,
     pre_close_series: Vec<Option<f64>>,
     zhang_series: Vec<Option<f64>>,
+    amount_series: Vec<Option<f64>>,
     rank_series: Option<Vec<Option<f64>>>,
+    score_series: Option<Vec<Option<f64>>>,
     keep_from: usize,
 }
 
@@ -258,7 +399,18 @@ struct PaperBuyCandidate {
     buy_index: usize,
     buy_rank: Option<i64>,
     buy_basis_price: f64,
-    buy_cost_price: f64,
+    // 含滑点、未含费用的成交价
+    buy_fill_price: f64,
+    paper_shares: u64,
+    max_amount: Option<f64>,
+    buy_score: Option<f64>,
+    buy_volatility: Option<f64>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct PaperPortfolioSettings {
+    initial_capital: f64,
+    sizing_mode: PortfolioSizingMode,
 }
 
 #[derive(Debug, Clone, Default)]
@@ -310,6 +462,8 @@ pub fn get_strategy_paper_validation_defaults(
         slippage_pct: 0.0,
         max_position_count: DEFAULT_MAX_POSITION_COUNT,
         buy_selection_mode: DEFAULT_BUY_SELECTION_MODE.to_string(),
+        initial_capital: DEFAULT_INITIAL_CAPITAL,
+        sizing_mode: DEFAULT_PORTFOLIO_SIZING_MODE.to_string(),
     })
 }
 
@@ -327,7 +481,340 @@ pub fn run_strategy_paper_validation(
     buy_selection_mode: Option<String>,
     buy_expression: String,
     sell_expression: String,
+    exit_rules: Option<StrategyPaperValidationExitRules>,
+    robustness: Option<RobustnessConfig>,
 ) -> Result<StrategyPaperValidationData, String> {
+    run_paper_validation_core(
+        source_path,
+        start_date,
+        end_date,
+        min_listed_trade_days,
+        index_ts_code,
+        test_ts_code,
+        board,
+        buy_price_basis,
+        slippage_pct,
+        max_position_count,
+        buy_selection_mode,
+        buy_expression,
+        sell_expression,
+        exit_rules,
+        None,
+        robustness,
+    )
+    .map(|(validation, _)| validation)
+}
+
+/// 在模拟验证的买卖方程和持仓上限之上加资金账户: 按手取整下单、T+1 卖出,
+/// 逐日记录净值并和 `index_ts_code` 基准对比。
+#[allow(clippy::too_many_arguments)]
+pub fn run_strategy_portfolio_backtest(
+    source_path: &str,
+    start_date: Option<String>,
+    end_date: Option<String>,
+    min_listed_trade_days: Option<usize>,
+    index_ts_code: Option<String>,
+    test_ts_code: Option<String>,
+    board: Option<String>,
+    buy_price_basis: String,
+    slippage_pct: Option<f64>,
+    max_position_count: Option<usize>,
+    buy_selection_mode: Option<String>,
+    buy_expression: String,
+    sell_expression: String,
+    exit_rules: Option<StrategyPaperValidationExitRules>,
+    initial_capital: Option<f64>,
+    sizing_mode: Option<String>,
+    robustness: Option<RobustnessConfig>,
+) -> Result<StrategyPortfolioBacktestData, String> {
+    let sizing_mode = PortfolioSizingMode::parse(
+        sizing_mode
+            .as_deref()
+            .unwrap_or(DEFAULT_PORTFOLIO_SIZING_MODE),
+    )?;
+    let (validation, portfolio) = run_paper_validation_core(
+        source_path,
+        start_date,
+        end_date,
+        min_listed_trade_days,
+        index_ts_code,
+        test_ts_code,
+        board,
+        buy_price_basis,
+        slippage_pct,
+        max_position_count,
+        buy_selection_mode,
+        buy_expression,
+        sell_expression,
+        exit_rules,
+        Some(PaperPortfolioSettings {
+            initial_capital: initial_capital.unwrap_or(DEFAULT_INITIAL_CAPITAL),
+            sizing_mode,
+        }),
+        robustness,
+    )?;
+    let portfolio = portfolio.ok_or_else(|| "组合回测未生成净值结果".to_string())?;
+
+    Ok(StrategyPortfolioBacktestData {
+        validation,
+        portfolio,
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
+fn run_paper_validation_core(
+    source_path: &str,
+    start_date: Option<String>,
+    end_date: Option<String>,
+    min_listed_trade_days: Option<usize>,
+    index_ts_code: Option<String>,
+    test_ts_code: Option<String>,
+    board: Option<String>,
+    buy_price_basis: String,
+    slippage_pct: Option<f64>,
+    max_position_count: Option<usize>,
+    buy_selection_mode: Option<String>,
+    buy_expression: String,
+    sell_expression: String,
+    exit_rules: Option<StrategyPaperValidationExitRules>,
+    portfolio_settings: Option<PaperPortfolioSettings>,
+    robustness: Option<RobustnessConfig>,
+) -> Result<(StrategyPaperValidationData, Option<PortfolioBacktestResult>), String> {
+    let settings = PaperRunSettings::resolve(
+        buy_price_basis,
+        slippage_pct,
+        max_position_count,
+        buy_selection_mode,
+        exit_rules,
+        portfolio_settings,
+        robustness,
+    )?;
+    let expressions = PaperExpressions::parse(buy_expression, sell_expression)?;
+    let market = load_paper_market(
+        source_path,
+        start_date,
+        end_date,
+        min_listed_trade_days,
+        index_ts_code,
+        test_ts_code,
+        board,
+        &settings,
+        std::slice::from_ref(&expressions),
+    )?;
+    run_paper_validation_on_market(&market, &settings, &expressions)
+}
+
+/// 参数扫描用: 按全部买卖方程的预热和字段并集只加载一次行情,各组合共用后并行组合回测。
+/// 单个组合的解析或回测错误放在对应位置返回,不影响其余组合。
+#[allow(clippy::too_many_arguments)]
+pub fn run_strategy_portfolio_backtest_batch(
+    source_path: &str,
+    start_date: Option<String>,
+    end_date: Option<String>,
+    min_listed_trade_days: Option<usize>,
+    index_ts_code: Option<String>,
+    board: Option<String>,
+    buy_price_basis: String,
+    slippage_pct: Option<f64>,
+    max_position_count: Option<usize>,
+    buy_selection_mode: Option<String>,
+    expressions: Vec<(String, String)>,
+    exit_rules: Option<StrategyPaperValidationExitRules>,
+    initial_capital: Option<f64>,
+    sizing_mode: Option<String>,
+) -> Result<Vec<Result<StrategyPortfolioBacktestData, String>>, String> {
+    let sizing_mode = PortfolioSizingMode::parse(
+        sizing_mode
+            .as_deref()
+            .unwrap_or(DEFAULT_PORTFOLIO_SIZING_MODE),
+    )?;
+    let settings = PaperRunSettings::resolve(
+        buy_price_basis,
+        slippage_pct,
+        max_position_count,
+        buy_selection_mode,
+        exit_rules,
+        Some(PaperPortfolioSettings {
+            initial_capital: initial_capital.unwrap_or(DEFAULT_INITIAL_CAPITAL),
+            sizing_mode,
+        }),
+        None,
+    )?;
+    let parsed = expressions
+        .into_iter()
+        .map(|(buy_expression, sell_expression)| {
+            PaperExpressions::parse(buy_expression, sell_expression)
+        })
+        .collect::<Vec<_>>();
+    let loadable = parsed
+        .iter()
+        .filter_map(|item| item.as_ref().ok().cloned())
+        .collect::<Vec<_>>();
+    if loadable.is_empty() {
+        return Err(parsed
+            .into_iter()
+            .find_map(Result::err)
+            .unwrap_or_else(|| "没有要回测的买卖方程".to_string()));
+    }
+    let market = load_paper_market(
+        source_path,
+        start_date,
+        end_date,
+        min_listed_trade_days,
+        index_ts_code,
+        None,
+        board,
+        &settings,
+        &loadable,
+    )?;
+
+    Ok(parsed
+        .into_par_iter()
+        .map(|item| {
+            let (validation, portfolio) =
+                run_paper_validation_on_market(&market, &settings, &item?)?;
+            let portfolio = portfolio.ok_or_else(|| "组合回测未生成净值结果".to_string())?;
+            Ok(StrategyPortfolioBacktestData {
+                validation,
+                portfolio,
+            })
+        })
+        .collect())
+}
+
+#[derive(Debug, Clone)]
+struct PaperExpressions {
+    buy_expression: String,
+    sell_expression: String,
+    buy_program: Stmts,
+    sell_program: Stmts,
+}
+
+impl PaperExpressions {
+    fn parse(buy_expression: String, sell_expression: String) -> Result<Self, String> {
+        let buy_expression = buy_expression.trim().to_string();
+        if buy_expression.is_empty() {
+            return Err("买点方程不能为空".to_string());
+        }
+        let sell_expression = sell_expression.trim().to_string();
+        if sell_expression.is_empty() {
+            return Err("卖点方程不能为空".to_string());
+        }
+        let buy_program = parse_expression_program(&buy_expression, "买点方程")?;
+        let sell_program = parse_expression_program(&sell_expression, "卖点方程")?;
+        Ok(Self {
+            buy_expression,
+            sell_expression,
+            buy_program,
+            sell_program,
+        })
+    }
+
+    fn warmup_need(&self) -> Result<usize, String> {
+        Ok(estimate_expression_warmup(&self.buy_program)?
+            .max(estimate_expression_warmup(&self.sell_program)?))
+    }
+}
+
+// 和买卖方程无关的回测参数,解析校验一次后参数扫描的各组合共用
+#[derive(Debug, Clone)]
+struct PaperRunSettings {
+    buy_price_basis: BuyPriceBasis,
+    slippage_pct: f64,
+    max_position_count: usize,
+    buy_selection_mode: BuySelectionMode,
+    exit_rules: StrategyPaperValidationExitRules,
+    portfolio_settings: Option<PaperPortfolioSettings>,
+    robustness: Option<RobustnessConfig>,
+}
+
+impl PaperRunSettings {
+    fn resolve(
+        buy_price_basis: String,
+        slippage_pct: Option<f64>,
+        max_position_count: Option<usize>,
+        buy_selection_mode: Option<String>,
+        exit_rules: Option<StrategyPaperValidationExitRules>,
+        portfolio_settings: Option<PaperPortfolioSettings>,
+        robustness: Option<RobustnessConfig>,
+    ) -> Result<Self, String> {
+        let buy_price_basis = BuyPriceBasis::parse(&buy_price_basis)?;
+        let slippage_pct = slippage_pct.unwrap_or(0.0);
+        if !slippage_pct.is_finite() {
+            return Err("滑点系数必须是有限数字".to_string());
+        }
+        let buy_selection_mode = BuySelectionMode::parse(
+            buy_selection_mode
+                .as_deref()
+                .unwrap_or(DEFAULT_BUY_SELECTION_MODE),
+        )?;
+        let exit_rules = exit_rules.unwrap_or_default();
+        exit_rules.validate()?;
+        if let Some(config) = robustness.as_ref() {
+            config.validate()?;
+        }
+        Ok(Self {
+            buy_price_basis,
+            slippage_pct,
+            max_position_count: max_position_count.unwrap_or(DEFAULT_MAX_POSITION_COUNT),
+            buy_selection_mode,
+            exit_rules,
+            portfolio_settings,
+            robustness,
+        })
+    }
+
+    fn needs_rank_score(&self, expressions: &PaperExpressions) -> bool {
+        let needs_score_sizing = self
+            .portfolio_settings
+            .is_some_and(|settings| settings.sizing_mode == PortfolioSizingMode::ScoreWeighted);
+        matches!(self.buy_selection_mode, BuySelectionMode::RankTop)
+            || needs_score_sizing
+            || self.exit_rules.max_rank.is_some()
+            || expr_program_uses_runtime_key(&expressions.buy_program, "RANK")
+            || expr_program_uses_runtime_key(&expressions.sell_program, "RANK")
+            || expr_program_uses_runtime_key(&expressions.buy_program, "SCORE")
+            || expr_program_uses_runtime_key(&expressions.sell_program, "SCORE")
+    }
+}
+
+// 按一组买卖方程的预热和字段并集加载好的行情和公共数据
+struct PaperMarket {
+    latest_trade_date: Option<String>,
+    trade_date_options: Vec<String>,
+    start_date: String,
+    end_date: String,
+    index_ts_code: String,
+    board: Option<String>,
+    test_ts_code: Option<String>,
+    test_stock_name: Option<String>,
+    eligibility: PaperTradeEligibility,
+    trade_cost: TradeCostConfig,
+    index_daily_returns: Vec<StrategyPaperValidationIndexDailyReturn>,
+    stocks: Vec<PaperMarketStock>,
+}
+
+// 注入完附加字段、还没算买点的单只股票
+struct PaperMarketStock {
+    ts_code: String,
+    name: Option<String>,
+    trade_dates: Vec<String>,
+    keep_from: usize,
+    runtime: Runtime,
+}
+
+#[allow(clippy::too_many_arguments)]
+fn load_paper_market(
+    source_path: &str,
+    start_date: Option<String>,
+    end_date: Option<String>,
+    min_listed_trade_days: Option<usize>,
+    index_ts_code: Option<String>,
+    test_ts_code: Option<String>,
+    board: Option<String>,
+    settings: &PaperRunSettings,
+    expressions: &[PaperExpressions],
+) -> Result<PaperMarket, String> {
     let source_path = source_path.trim();
     if source_path.is_empty() {
         return Err("source_path 不能为空".to_string());
@@ -357,26 +844,6 @@ pub fn run_strategy_paper_validation(
         return Err("开始日期不能晚于结束日期".to_string());
     }
 
-    let buy_expression = buy_expression.trim().to_string();
-    if buy_expression.is_empty() {
-        return Err("买点方程不能为空".to_string());
-    }
-    let sell_expression = sell_expression.trim().to_string();
-    if sell_expression.is_empty() {
-        return Err("卖点方程不能为空".to_string());
-    }
-
-    let parsed_buy_price_basis = BuyPriceBasis::parse(&buy_price_basis)?;
-    let resolved_slippage_pct = slippage_pct.unwrap_or(0.0);
-    if !resolved_slippage_pct.is_finite() {
-        return Err("滑点系数必须是有限数字".to_string());
-    }
-    let resolved_max_position_count = max_position_count.unwrap_or(defaults.max_position_count);
-    let parsed_buy_selection_mode = BuySelectionMode::parse(
-        buy_selection_mode
-            .as_deref()
-            .unwrap_or(defaults.buy_selection_mode.as_str()),
-    )?;
     let normalized_test_ts_code = test_ts_code
         .as_deref()
         .map(str::trim)
@@ -394,19 +861,25 @@ pub fn run_strategy_paper_validation(
         .filter(|value| !value.is_empty())
         .unwrap_or_else(|| DEFAULT_INDEX_TS_CODE.to_string());
 
-    let buy_program = parse_expression_program(&buy_expression, "买点方程")?;
-    let sell_program = parse_expression_program(&sell_expression, "卖点方程")?;
-    let warmup_need =
-        estimate_expression_warmup(&buy_program)?.max(estimate_expression_warmup(&sell_program)?);
-    let required_runtime_keys = collect_paper_validation_runtime_keys(&buy_program, &sell_program);
-    let used_cyq_chen_keys =
-        collect_paper_validation_cyq_chen_runtime_keys(&buy_program, &sell_program);
-    let sell_runtime_keys = collect_paper_validation_sell_runtime_keys(&sell_program);
-    let needs_rank_score = matches!(parsed_buy_selection_mode, BuySelectionMode::RankTop)
-        || expr_program_uses_runtime_key(&buy_program, "RANK")
-        || expr_program_uses_runtime_key(&sell_program, "RANK")
-        || expr_program_uses_runtime_key(&buy_program, "SCORE")
-        || expr_program_uses_runtime_key(&sell_program, "SCORE");
+    let trade_cost = load_trade_cost_config(source_path)?;
+    let mut warmup_need = 0;
+    let mut required_runtime_keys = HashSet::new();
+    let mut used_cyq_chen_keys = HashSet::new();
+    let mut needs_rank_score = false;
+    for item in expressions {
+        warmup_need = warmup_need.max(item.warmup_need()?);
+        required_runtime_keys.extend(collect_paper_validation_runtime_keys(
+            &item.buy_program,
+            &item.sell_program,
+        ));
+        used_cyq_chen_keys.extend(collect_paper_validation_cyq_chen_runtime_keys(
+            &item.buy_program,
+            &item.sell_program,
+        ));
+        needs_rank_score |= settings.needs_rank_score(item);
+    }
+    // 成交均价和成交额约束要用到
+    required_runtime_keys.extend(["L".to_string(), "AMOUNT".to_string()]);
     let query_start_date = calc_query_start_date(source_path, warmup_need, &resolved_start_date)?;
     let need_rows = calc_query_need_rows(
         source_path,
@@ -419,6 +892,7 @@ pub fn run_strategy_paper_validation(
     let st_list = load_st_list(source_path)?;
     let name_map = build_name_map(source_path).unwrap_or_default();
     let total_share_map = load_total_share_map(source_path).unwrap_or_default();
+    let profile_map = load_stock_profile_map(source_path).unwrap_or_default();
     let ts_codes = if let Some(ts_code) = normalized_test_ts_code.as_ref() {
         vec![ts_code.clone()]
     } else {
@@ -452,25 +926,23 @@ pub fn run_strategy_paper_validation(
         HashMap::new()
     };
 
-    let prepared_stocks = Mutex::new(Vec::new());
+    let stocks = Mutex::new(Vec::new());
     ts_codes
         .par_chunks(128)
         .try_for_each(|ts_group| -> Result<(), String> {
-            let worker_buy_program = buy_program.clone();
             let worker_reader =
                 DataReader::new_with_runtime_keys(source_path, &required_runtime_keys)?;
             let cyq_chen_injector = CyqChenFieldInjector::new(source_path, &used_cyq_chen_keys);
             let mut out = Vec::new();
 
             for ts_code in ts_group {
-                if let Some(stock) = prepare_paper_stock_for_portfolio(
+                if let Some(stock) = load_paper_market_stock(
                     &worker_reader,
                     ts_code,
                     name_map.get(ts_code),
                     st_list.contains(ts_code),
                     total_share_map.get(ts_code).copied(),
-                    &worker_buy_program,
-                    &sell_runtime_keys,
+                    profile_map.get(ts_code),
                     &rank_score_series_map,
                     needs_rank_score,
                     &cyq_chen_injector,
@@ -485,7 +957,7 @@ pub fn run_strategy_paper_validation(
             }
 
             if !out.is_empty() {
-                prepared_stocks
+                stocks
                     .lock()
                     .map_err(|_| "写入策略回测股票缓存失败:锁已损坏".to_string())?
                     .extend(out);
@@ -494,31 +966,11 @@ pub fn run_strategy_paper_validation(
             Ok(())
         })?;
 
-    let mut prepared_stocks = prepared_stocks
+    let mut stocks = stocks
         .into_inner()
         .map_err(|_| "读取策略回测股票缓存失败:锁已损坏".to_string())?;
-    prepared_stocks.sort_by(|left, right| left.ts_code.cmp(&right.ts_code));
-
-    let mut trades = simulate_portfolio_trade_rows(
-        &prepared_stocks,
-        &trade_date_options,
-        &sell_program,
-        &eligibility,
-        &resolved_start_date,
-        &resolved_end_date,
-        parsed_buy_price_basis,
-        resolved_slippage_pct,
-        resolved_max_position_count,
-        parsed_buy_selection_mode,
-    )?;
-    trades.sort_by(|left, right| {
-        left.buy_date
-            .cmp(&right.buy_date)
-            .then_with(|| left.ts_code.cmp(&right.ts_code))
-            .then_with(|| left.sell_date.cmp(&right.sell_date))
-    });
+    stocks.sort_by(|left, right| left.ts_code.cmp(&right.ts_code));
 
-    let summary = build_trade_summary(&trades);
     let index_daily_returns = load_index_daily_returns(
         source_path,
         &resolved_index_ts_code,
@@ -526,27 +978,187 @@ pub fn run_strategy_paper_validation(
         &resolved_end_date,
     )?;
 
-    Ok(StrategyPaperValidationData {
+    Ok(PaperMarket {
         latest_trade_date: defaults.latest_trade_date,
+        trade_date_options,
         start_date: resolved_start_date,
         end_date: resolved_end_date,
-        min_listed_trade_days: eligibility.min_listed_trade_days,
         index_ts_code: resolved_index_ts_code,
-        resolved_board,
+        board: resolved_board,
         test_ts_code: normalized_test_ts_code,
         test_stock_name,
-        buy_price_basis: parsed_buy_price_basis.as_str().to_string(),
-        slippage_pct: resolved_slippage_pct,
-        max_position_count: resolved_max_position_count,
-        buy_selection_mode: parsed_buy_selection_mode.as_str().to_string(),
-        buy_expression,
-        sell_expression,
-        summary,
-        trades,
+        eligibility,
+        trade_cost,
         index_daily_returns,
+        stocks,
     })
 }
 
+fn run_paper_validation_on_market(
+    market: &PaperMarket,
+    settings: &PaperRunSettings,
+    expressions: &PaperExpressions,
+) -> Result<(StrategyPaperValidationData, Option<PortfolioBacktestResult>), String> {
+    let trade_cost = &market.trade_cost;
+    let exit_rules = &settings.exit_rules;
+    let new_portfolio_engine = || {
+        settings
+            .portfolio_settings
+            .map(|settings_item| {
+                PortfolioEngine::new(PortfolioConfig {
+                    initial_capital: settings_item.initial_capital,
+                    max_position_count: settings.max_position_count,
+                    sizing_mode: settings_item.sizing_mode,
+                    cost: trade_cost.clone(),
+                })
+            })
+            .transpose()
+    };
+    let mut portfolio_engine = new_portfolio_engine()?;
+    let sell_runtime_keys = collect_paper_validation_sell_runtime_keys(&expressions.sell_program);
+    let needs_rank_score = settings.needs_rank_score(expressions);
+
+    let prepared_stocks = market
+        .stocks
+        .par_iter()
+        .map(|stock| {
+            prepare_paper_stock_for_portfolio(
+                stock,
+                &expressions.buy_program,
+                &sell_runtime_keys,
+                needs_rank_score,
+            )
+        })
+        .collect::<Result<Vec<_>, String>>()?
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+
+    let mut trades = simulate_portfolio_trade_rows(
+        &prepared_stocks,
+        &market.trade_date_options,
+        &expressions.sell_program,
+        &market.eligibility,
+        &market.start_date,
+        &market.end_date,
+        settings.buy_price_basis,
+        settings.slippage_pct,
+        settings.max_position_count,
+        settings.buy_selection_mode,
+        PAPER_VALIDATION_RANDOM_SEED,
+        trade_cost,
+        exit_rules,
+        portfolio_engine.as_mut(),
+    )?;
+    trades.sort_by(|left, right| {
+        left.buy_date
+            .cmp(&right.buy_date)
+            .then_with(|| left.ts_code.cmp(&right.ts_code))
+            .then_with(|| left.sell_date.cmp(&right.sell_date))
+    });
+
+    let summary = build_trade_summary(&trades);
+    let benchmark_daily_returns = market
+        .index_daily_returns
+        .iter()
+        .map(|item| (item.trade_date.clone(), item.pct_chg))
+        .collect::<Vec<_>>();
+    let portfolio = portfolio_engine.map(|engine| engine.finish(&benchmark_daily_returns));
+
+    let robustness = match settings.robustness.as_ref() {
+        // 随机选股时换种子重跑整段撮合,区间反映入场顺序带来的波动
+        Some(config) if matches!(settings.buy_selection_mode, BuySelectionMode::Random) => {
+            let runs = (0..config.iterations.min(MAX_RANDOM_ENTRY_RERUNS))
+                .into_par_iter()
+                .map(|run_index| -> Result<RobustnessSample, String> {
+                    let mut engine = new_portfolio_engine()?;
+                    let run_trades = simulate_portfolio_trade_rows(
+                        &prepared_stocks,
+                        &market.trade_date_options,
+                        &expressions.sell_program,
+                        &market.eligibility,
+                        &market.start_date,
+                        &market.end_date,
+                        settings.buy_price_basis,
+                        settings.slippage_pct,
+                        settings.max_position_count,
+                        settings.buy_selection_mode,
+                        config.seed.wrapping_add(run_index as u64),
+                        trade_cost,
+                        exit_rules,
+                        engine.as_mut(),
+                    )?;
+                    let run_portfolio =
+                        engine.map(|engine| engine.finish(&benchmark_daily_returns));
+                    Ok(build_paper_robustness_sample(
+                        &run_trades,
+                        run_portfolio.as_ref(),
+                        settings.max_position_count,
+                    ))
+                })
+                .collect::<Result<Vec<_>, String>>()?;
+            let sample_count = summary.closed_trade_count;
+            let mut robustness = summarize_robustness_runs(
+                "random_entry_order",
+                build_paper_robustness_sample(
+                    &trades,
+                    portfolio.as_ref(),
+                    settings.max_position_count,
+                ),
+                &runs,
+                config,
+                None,
+                sample_count,
+            );
+            if config.iterations > MAX_RANDOM_ENTRY_RERUNS {
+                robustness.warnings.push(format!(
+                    "随机选股每次重跑都是完整撮合,重跑次数按上限 {MAX_RANDOM_ENTRY_RERUNS} 执行,未到请求的 {}",
+                    config.iterations
+                ));
+            }
+            Some(robustness)
+        }
+        // 同时持有的交易收益互相重叠,按逐日组合收益做块自助法
+        Some(config) => block_bootstrap_returns(
+            &paper_daily_returns_pct(
+                &market.trade_date_options,
+                &market.start_date,
+                &market.end_date,
+                &trades,
+                portfolio.as_ref(),
+                settings.max_position_count,
+            ),
+            1,
+            config,
+        ),
+        None => None,
+    };
+
+    let validation = StrategyPaperValidationData {
+        latest_trade_date: market.latest_trade_date.clone(),
+        start_date: market.start_date.clone(),
+        end_date: market.end_date.clone(),
+        min_listed_trade_days: market.eligibility.min_listed_trade_days,
+        index_ts_code: market.index_ts_code.clone(),
+        resolved_board: market.board.clone(),
+        test_ts_code: market.test_ts_code.clone(),
+        test_stock_name: market.test_stock_name.clone(),
+        buy_price_basis: settings.buy_price_basis.as_str().to_string(),
+        slippage_pct: settings.slippage_pct,
+        max_position_count: settings.max_position_count,
+        buy_selection_mode: settings.buy_selection_mode.as_str().to_string(),
+        buy_expression: expressions.buy_expression.clone(),
+        sell_expression: expressions.sell_expression.clone(),
+        trade_cost: trade_cost.clone(),
+        exit_rules: exit_rules.clone(),
+        summary,
+        robustness,
+        trades,
+        index_daily_returns: market.index_daily_returns.clone(),
+    };
+    Ok((validation, portfolio))
+}
+
 pub fn validate_strategy_paper_validation_template_expressions(
     buy_expression: String,
     sell_expression: String,
@@ -631,7 +1243,17 @@ fn build_template_validation_runtime(warmup_need: usize, include_sell_fields: bo
         vars.insert("rateh".to_string(), Value::NumSeries(rateh_series));
     }
 
-    Runtime { vars }
+    // 用连续自然日充当交易日期, 让PERIOD在模板校验时也能切分周期
+    let start = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap_or_default();
+    let trade_dates = (0..len)
+        .filter_map(|index| start.checked_add_days(Days::new(index as u64)))
+        .map(|day| day.format("%Y%m%d").to_string())
+        .collect::<Vec<_>>();
+
+    Runtime {
+        vars,
+        trade_dates: Arc::new(trade_dates),
+    }
 }
 
 fn build_trade_summary(
@@ -694,14 +1316,13 @@ fn build_trade_summary(
 }
 
 #[allow(clippy::too_many_arguments)]
-fn prepare_paper_stock_for_portfolio(
+fn load_paper_market_stock(
     reader: &DataReader,
     ts_code: &str,
     stock_name: Option<&String>,
     is_st: bool,
     total_share: Option<f64>,
-    buy_program: &Stmts,
-    sell_runtime_keys: &HashSet<String>,
+    profile: Option<&StockProfile>,
     rank_score_series_map: &HashMap<String, HashMap<String, RankScoreInfo>>,
     needs_rank_score: bool,
     cyq_chen_injector: &CyqChenFieldInjector,
@@ -710,7 +1331,7 @@ fn prepare_paper_stock_for_portfolio(
     query_start_date: &str,
     warmup_need: usize,
     need_rows: usize,
-) -> Result<Option<PreparedPaperStock>, String> {
+) -> Result<Option<PaperMarketStock>, String> {
     let mut row_data = load_paper_validation_row_data(
         reader,
         ts_code,
@@ -724,7 +1345,7 @@ fn prepare_paper_stock_for_portfolio(
         return Ok(None);
     }
 
-    inject_stock_extra_fields(&mut row_data, ts_code, is_st, total_share)?;
+    inject_stock_extra_fields(&mut row_data, ts_code, is_st, total_share, profile)?;
     let _ = cyq_chen_injector.inject(&mut row_data, ts_code);
     if needs_rank_score {
         inject_runtime_rank_score_series(&mut row_data, ts_code, rank_score_series_map)?;
@@ -738,7 +1359,25 @@ fn prepare_paper_stock_for_portfolio(
         return Ok(None);
     }
 
-    let mut base_runtime = row_into_rt(row_data)?;
+    Ok(Some(PaperMarketStock {
+        ts_code: ts_code.to_string(),
+        name: stock_name.cloned(),
+        trade_dates,
+        keep_from,
+        runtime: row_into_rt(row_data)?,
+    }))
+}
+
+// 行情里的排名和总分是按全部组合的需要注入的,本组合用不到时不带进回测
+fn prepare_paper_stock_for_portfolio(
+    stock: &PaperMarketStock,
+    buy_program: &Stmts,
+    sell_runtime_keys: &HashSet<String>,
+    needs_rank_score: bool,
+) -> Result<Option<PreparedPaperStock>, String> {
+    let ts_code = stock.ts_code.as_str();
+    let keep_from = stock.keep_from;
+    let mut base_runtime = stock.runtime.clone();
     let mut buy_runtime = base_runtime.clone();
     let buy_signal_series = evaluate_program_as_bool_series(&mut buy_runtime, buy_program)
         .map_err(|error| format!("{ts_code} 买点方程执行失败: {error}"))?;
@@ -766,28 +1405,101 @@ fn prepare_paper_stock_for_portfolio(
     let zhang_series = runtime_num_series(&base_runtime, "ZHANG")
         .map_err(|error| format!("{ts_code} {error}"))?
         .to_vec();
-    let rank_series =
-        runtime_num_series_optional(&base_runtime, "RANK").map(|series| series.to_vec());
+    let series_len = stock.trade_dates.len();
+    let low_series = runtime_num_series_optional(&base_runtime, "L")
+        .map(|series| series.to_vec())
+        .unwrap_or_else(|| vec![None; series_len]);
+    let amount_series = runtime_num_series_optional(&base_runtime, "AMOUNT")
+        .map(|series| series.to_vec())
+        .unwrap_or_else(|| vec![None; series_len]);
+    let rank_series = runtime_num_series_optional(&base_runtime, "RANK")
+        .filter(|_| needs_rank_score)
+        .map(|series| series.to_vec());
+    let score_series = runtime_num_series_optional(&base_runtime, "SCORE")
+        .filter(|_| needs_rank_score)
+        .map(|series| series.to_vec());
     base_runtime
         .vars
         .retain(|key, _| sell_runtime_keys.contains(key));
 
     Ok(Some(PreparedPaperStock {
         ts_code: ts_code.to_string(),
-        name: stock_name.cloned(),
-        trade_dates,
+        name: stock.name.clone(),
+        trade_dates: stock.trade_dates.clone(),
         open_series,
         high_series,
+        low_series,
         close_series,
         pre_close_series,
         zhang_series,
+        amount_series,
         rank_series,
+        score_series,
         base_runtime,
         buy_signal_series,
         keep_from,
     }))
 }
 
+// 已平仓交易按卖出日排列的实现收益
+fn closed_trade_returns_pct(trades: &[StrategyPaperValidationTradeRow]) -> Vec<f64> {
+    let mut closed = trades
+        .iter()
+        .filter(|trade| trade.status == "closed")
+        .filter_map(|trade| Some((trade.sell_date.as_deref()?, trade.realized_return_pct?)))
+        .collect::<Vec<_>>();
+    closed.sort_by(|left, right| left.0.cmp(right.0));
+    closed
+        .into_iter()
+        .map(|(_, return_pct)| return_pct)
+        .collect()
+}
+
+// 组合回测用逐日净值收益;纯模拟没有资金账户,按持仓上限均分资金,
+// 把每笔持仓的逐日收盘收益加总成组合日收益,空仓日记 0
+fn paper_daily_returns_pct(
+    trade_date_options: &[String],
+    start_date: &str,
+    end_date: &str,
+    trades: &[StrategyPaperValidationTradeRow],
+    portfolio: Option<&PortfolioBacktestResult>,
+    max_position_count: usize,
+) -> Vec<f64> {
+    if let Some(portfolio) = portfolio {
+        return portfolio
+            .daily_returns_pct()
+            .into_iter()
+            .map(|(_, value)| value)
+            .collect();
+    }
+    let slots = max_position_count.max(1) as f64;
+    let mut by_date = HashMap::<&str, f64>::new();
+    for trade in trades {
+        for item in &trade.daily_holding_close_returns {
+            *by_date.entry(item.trade_date.as_str()).or_default() += item.daily_return_pct / slots;
+        }
+    }
+    trade_date_options
+        .iter()
+        .filter(|trade_date| trade_date.as_str() >= start_date && trade_date.as_str() <= end_date)
+        .map(|trade_date| by_date.get(trade_date.as_str()).copied().unwrap_or(0.0))
+        .collect()
+}
+
+// 组合回测用净值回撤,纯模拟按持仓上限均分资金估算
+fn build_paper_robustness_sample(
+    trades: &[StrategyPaperValidationTradeRow],
+    portfolio: Option<&PortfolioBacktestResult>,
+    max_position_count: usize,
+) -> RobustnessSample {
+    let mut sample =
+        RobustnessSample::from_returns(&closed_trade_returns_pct(trades), max_position_count);
+    if let Some(portfolio) = portfolio {
+        sample.max_drawdown_pct = portfolio.metrics.max_drawdown_pct;
+    }
+    sample
+}
+
 #[allow(clippy::too_many_arguments)]
 fn simulate_portfolio_trade_rows(
     stocks: &[PreparedPaperStock],
@@ -800,6 +1512,10 @@ fn simulate_portfolio_trade_rows(
     slippage_pct: f64,
     max_position_count: usize,
     buy_selection_mode: BuySelectionMode,
+    random_seed: u64,
+    trade_cost: &TradeCostConfig,
+    exit_rules: &StrategyPaperValidationExitRules,
+    mut portfolio: Option<&mut PortfolioEngine>,
 ) -> Result<Vec<StrategyPaperValidationTradeRow>, String> {
     let global_trade_dates = trade_date_options
         .iter()
@@ -808,7 +1524,7 @@ fn simulate_portfolio_trade_rows(
     let mut pending_candidates: BTreeMap<String, Vec<PaperBuyCandidate>> = BTreeMap::new();
     let mut open_positions: Vec<PortfolioOpenPosition> = Vec::new();
     let mut trades = Vec::new();
-    let mut rng = StdRng::seed_from_u64(PAPER_VALIDATION_RANDOM_SEED);
+    let mut rng = StdRng::seed_from_u64(random_seed);
 
     for trade_date in global_trade_dates {
         process_portfolio_sells(
@@ -818,6 +1534,9 @@ fn simulate_portfolio_trade_rows(
             &mut open_positions,
             &mut trades,
             buy_price_basis,
+            trade_cost,
+            exit_rules,
+            portfolio.as_deref_mut(),
         )?;
 
         let held_ts_codes = open_positions
@@ -866,16 +1585,30 @@ fn simulate_portfolio_trade_rows(
             let Some(basis_price) = normalize_valid_price(basis_price) else {
                 continue;
             };
+            let fill_price = trade_cost.resolve_fill_price(
+                basis_price,
+                stock.open_series.get(buy_index).copied().flatten(),
+                stock.high_series.get(buy_index).copied().flatten(),
+                stock.low_series.get(buy_index).copied().flatten(),
+                stock.close_series.get(buy_index).copied().flatten(),
+            );
             let pre_close = stock.pre_close_series.get(buy_index).copied().flatten();
             let zhang_pct = stock.zhang_series.get(buy_index).copied().flatten();
-            if !is_buy_basis_executable(basis_price, pre_close, zhang_pct) {
+            if !is_buy_basis_executable(fill_price, pre_close, zhang_pct) {
                 continue;
             }
 
-            let buy_cost_price = basis_price * (1.0 + slippage_pct / 100.0);
-            if !buy_cost_price.is_finite() || buy_cost_price <= PRICE_EPS {
+            let buy_fill_price = fill_price * (1.0 + slippage_pct / 100.0);
+            if !buy_fill_price.is_finite() || buy_fill_price <= PRICE_EPS {
                 continue;
             }
+            let max_amount =
+                trade_cost.max_fill_amount(stock.amount_series.get(buy_index).copied().flatten());
+            let Some(paper_shares) =
+                resolve_paper_trade_shares(trade_cost, buy_fill_price, max_amount)
+            else {
+                continue;
+            };
 
             pending_candidates
                 .entry(buy_date.clone())
@@ -894,60 +1627,49 @@ fn simulate_portfolio_trade_rows(
                         .flatten()
                         .map(|value| value.round() as i64),
                     buy_basis_price: basis_price,
-                    buy_cost_price,
+                    buy_fill_price,
+                    paper_shares,
+                    max_amount,
+                    buy_score: stock
+                        .score_series
+                        .as_ref()
+                        .and_then(|series| series.get(scan_index))
+                        .copied()
+                        .flatten(),
+                    buy_volatility: trailing_return_volatility(
+                        &stock.close_series,
+                        buy_index,
+                        PORTFOLIO_VOLATILITY_WINDOW,
+                    ),
                 });
         }
 
-        let Some(mut due_candidates) = pending_candidates.remove(trade_date.as_str()) else {
-            continue;
-        };
-        order_buy_candidates(&mut due_candidates, buy_selection_mode, &mut rng);
-
-        let mut held_ts_codes = open_positions
-            .iter()
-            .map(|item| stocks[item.stock_index].ts_code.as_str())
-            .collect::<HashSet<_>>();
-        let mut available_slots = if max_position_count == 0 {
-            usize::MAX
-        } else {
-            max_position_count.saturating_sub(open_positions.len())
-        };
-        if available_slots == 0 {
-            continue;
+        if let Some(mut due_candidates) = pending_candidates.remove(trade_date.as_str()) {
+            order_buy_candidates(&mut due_candidates, buy_selection_mode, &mut rng);
+            open_due_candidates(
+                stocks,
+                due_candidates,
+                trade_date,
+                max_position_count,
+                trade_cost,
+                exit_rules,
+                &mut open_positions,
+                portfolio.as_deref_mut(),
+            )?;
         }
 
-        for candidate in due_candidates {
-            if available_slots == 0 {
-                break;
-            }
-            if held_ts_codes.contains(candidate.ts_code.as_str()) {
-                continue;
-            }
-
-            let stock = &stocks[candidate.stock_index];
-            let sell_runtime = init_sell_runtime(
-                &stock.base_runtime,
-                stock.trade_dates.len(),
-                &stock.open_series,
-                &stock.high_series,
-                candidate.buy_index,
-                candidate.buy_cost_price,
-            )?;
-            open_positions.push(PortfolioOpenPosition {
-                stock_index: candidate.stock_index,
-                position: OpenPosition {
-                    buy_date: candidate.buy_date,
-                    buy_index: candidate.buy_index,
-                    buy_rank: candidate.buy_rank,
-                    buy_basis_price: candidate.buy_basis_price,
-                    buy_cost_price: candidate.buy_cost_price,
-                    last_sell_runtime_index: candidate.buy_index,
-                    pending_sell: false,
-                    sell_runtime,
-                },
+        if let Some(engine) = portfolio.as_deref_mut() {
+            engine.mark_to_market(trade_date, |ts_code| {
+                let stock = open_positions
+                    .iter()
+                    .map(|item| &stocks[item.stock_index])
+                    .find(|stock| stock.ts_code == ts_code)?;
+                let index = stock
+                    .trade_dates
+                    .binary_search_by(|value| value.as_str().cmp(trade_date))
+                    .ok()?;
+                stock.close_series.get(index).copied().flatten()
             });
-            held_ts_codes.insert(stock.ts_code.as_str());
-            available_slots = available_slots.saturating_sub(1);
         }
     }
 
@@ -955,6 +1677,135 @@ fn simulate_portfolio_trade_rows(
     Ok(trades)
 }
 
+// 模拟验证没有资金账户,按参考金额折算股数,至少一手;成交额约束连一手都不够时放弃
+fn resolve_paper_trade_shares(
+    trade_cost: &TradeCostConfig,
+    buy_fill_price: f64,
+    max_amount: Option<f64>,
+) -> Option<u64> {
+    let budget = max_amount.map_or(trade_cost.reference_trade_amount, |max_amount| {
+        max_amount.min(trade_cost.reference_trade_amount)
+    });
+    let shares = trade_cost.max_affordable_shares(budget, buy_fill_price);
+    if shares > 0 {
+        return Some(shares);
+    }
+    let one_lot_amount = buy_fill_price * BOARD_LOT_SHARES as f64;
+    match max_amount {
+        Some(max_amount) if max_amount < one_lot_amount => None,
+        _ => Some(BOARD_LOT_SHARES),
+    }
+}
+
+// 卖出按收盘价(缺失时开盘价),成交模型为均价时改用当日 OHLC 均价
+fn resolve_paper_sell_price(
+    stock: &PreparedPaperStock,
+    index: usize,
+    trade_cost: &TradeCostConfig,
+) -> Option<f64> {
+    let open = stock.open_series.get(index).copied().flatten();
+    let close = stock.close_series.get(index).copied().flatten();
+    let basis_price = resolve_normal_sell_price(close, open)?;
+    Some(trade_cost.resolve_fill_price(
+        basis_price,
+        open,
+        stock.high_series.get(index).copied().flatten(),
+        stock.low_series.get(index).copied().flatten(),
+        close,
+    ))
+}
+
+// 按排好序的候选依次占用空仓位;有组合账户时先整批定仓,只有成交的候选才开仓
+#[allow(clippy::too_many_arguments)]
+fn open_due_candidates(
+    stocks: &[PreparedPaperStock],
+    due_candidates: Vec<PaperBuyCandidate>,
+    trade_date: &str,
+    max_position_count: usize,
+    trade_cost: &TradeCostConfig,
+    exit_rules: &StrategyPaperValidationExitRules,
+    open_positions: &mut Vec<PortfolioOpenPosition>,
+    portfolio: Option<&mut PortfolioEngine>,
+) -> Result<(), String> {
+    let mut held_ts_codes = open_positions
+        .iter()
+        .map(|item| stocks[item.stock_index].ts_code.as_str())
+        .collect::<HashSet<_>>();
+    let available_slots = if max_position_count == 0 {
+        usize::MAX
+    } else {
+        max_position_count.saturating_sub(open_positions.len())
+    };
+
+    let mut selected = Vec::new();
+    for candidate in due_candidates {
+        if selected.len() >= available_slots {
+            break;
+        }
+        if held_ts_codes.insert(stocks[candidate.stock_index].ts_code.as_str()) {
+            let shares = candidate.paper_shares;
+            selected.push((candidate, shares));
+        }
+    }
+
+    if let Some(engine) = portfolio {
+        let orders = selected
+            .iter()
+            .map(|(candidate, _)| PortfolioBuyOrder {
+                ts_code: candidate.ts_code.clone(),
+                price: candidate.buy_fill_price,
+                score: candidate.buy_score,
+                volatility: candidate.buy_volatility,
+                max_amount: candidate.max_amount,
+            })
+            .collect::<Vec<_>>();
+        let filled = engine.buy(trade_date, &orders);
+        selected = selected
+            .into_iter()
+            .zip(filled)
+            .filter_map(|((candidate, _), shares)| shares.map(|shares| (candidate, shares)))
+            .collect();
+    }
+
+    for (candidate, shares) in selected {
+        let stock = &stocks[candidate.stock_index];
+        let buy_fee = trade_cost.calc_fee(TradeSide::Buy, shares as f64 * candidate.buy_fill_price);
+        let buy_cost_price = candidate.buy_fill_price + buy_fee.total / shares as f64;
+        let sell_runtime = init_sell_runtime(
+            &stock.base_runtime,
+            stock.trade_dates.len(),
+            &stock.open_series,
+            &stock.high_series,
+            candidate.buy_index,
+            buy_cost_price,
+        )?;
+        open_positions.push(PortfolioOpenPosition {
+            stock_index: candidate.stock_index,
+            position: OpenPosition {
+                buy_date: candidate.buy_date,
+                buy_index: candidate.buy_index,
+                buy_rank: candidate.buy_rank,
+                buy_basis_price: candidate.buy_basis_price,
+                buy_cost_price,
+                shares,
+                buy_fee,
+                entry_atr: exit_rules.atr_stop_multiple.and_then(|_| {
+                    calc_entry_atr(
+                        stock,
+                        candidate.buy_index,
+                        exit_rules.atr_period.unwrap_or(DEFAULT_EXIT_ATR_PERIOD),
+                    )
+                }),
+                last_sell_runtime_index: candidate.buy_index,
+                pending_exit: None,
+                sell_runtime,
+            },
+        });
+    }
+
+    Ok(())
+}
+
 fn order_buy_candidates(
     candidates: &mut [PaperBuyCandidate],
     buy_selection_mode: BuySelectionMode,
@@ -989,6 +1840,7 @@ fn order_buy_candidates(
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn process_portfolio_sells(
     stocks: &[PreparedPaperStock],
     sell_program: &Stmts,
@@ -996,6 +1848,9 @@ fn process_portfolio_sells(
     open_positions: &mut Vec<PortfolioOpenPosition>,
     trades: &mut Vec<StrategyPaperValidationTradeRow>,
     buy_price_basis: BuyPriceBasis,
+    trade_cost: &TradeCostConfig,
+    exit_rules: &StrategyPaperValidationExitRules,
+    mut portfolio: Option<&mut PortfolioEngine>,
 ) -> Result<(), String> {
     let mut remaining_positions = Vec::with_capacity(open_positions.len());
 
@@ -1026,31 +1881,58 @@ fn process_portfolio_sells(
             item.position.last_sell_runtime_index = scan_index;
         }
 
-        let sell_hit = if item.position.pending_sell {
-            true
+        let exit = if let Some(reason) = item.position.pending_exit {
+            Some((
+                reason,
+                resolve_paper_sell_price(stock, scan_index, trade_cost),
+            ))
+        } else if let Some((reason, price)) =
+            evaluate_intraday_exit(stock, &item.position, exit_rules, scan_index)
+        {
+            Some((reason, Some(price)))
+        } else if evaluate_sell_hit(&mut item.position.sell_runtime, sell_program, scan_index)
+            .map_err(|error| format!("{} 卖点方程执行失败: {error}", stock.ts_code))?
+        {
+            Some((
+                PaperExitReason::SellExpression,
+                resolve_paper_sell_price(stock, scan_index, trade_cost),
+            ))
         } else {
-            evaluate_sell_hit(&mut item.position.sell_runtime, sell_program, scan_index)
-                .map_err(|error| format!("{} 卖点方程执行失败: {error}", stock.ts_code))?
+            evaluate_close_exit(stock, &item.position, exit_rules, scan_index).map(|reason| {
+                (
+                    reason,
+                    resolve_paper_sell_price(stock, scan_index, trade_cost),
+                )
+            })
+        };
+        let Some((exit_reason, sell_price)) = exit else {
+            remaining_positions.push(item);
+            continue;
         };
-        let sell_price = resolve_normal_sell_price(
-            stock.close_series.get(scan_index).copied().flatten(),
-            stock.open_series.get(scan_index).copied().flatten(),
-        );
         let pre_close = stock.pre_close_series.get(scan_index).copied().flatten();
         let zhang_pct = stock.zhang_series.get(scan_index).copied().flatten();
-        let sell_executable = is_sell_price_executable(sell_price, pre_close, zhang_pct);
+        // 组合账户下当日买入的持仓要到下一交易日才能卖
+        let t_plus_one_blocked = portfolio
+            .as_deref()
+            .is_some_and(|engine| !engine.can_sell(&stock.ts_code, trade_date));
+        let sell_executable =
+            is_sell_price_executable(sell_price, pre_close, zhang_pct) && !t_plus_one_blocked;
 
-        if sell_hit && sell_executable {
+        if sell_executable {
+            if let (Some(engine), Some(price)) = (portfolio.as_deref_mut(), sell_price) {
+                engine.sell(trade_date, &stock.ts_code, price);
+            }
             trades.push(build_closed_trade_row(
                 stock,
                 item.position,
                 scan_index,
+                sell_price,
+                exit_reason,
                 buy_price_basis,
+                trade_cost,
             ));
         } else {
-            if sell_hit {
-                item.position.pending_sell = true;
-            }
+            item.position.pending_exit = Some(exit_reason);
             remaining_positions.push(item);
         }
     }
@@ -1063,12 +1945,20 @@ fn build_closed_trade_row(
     stock: &PreparedPaperStock,
     position: OpenPosition,
     sell_index: usize,
+    sell_price: Option<f64>,
+    exit_reason: PaperExitReason,
     buy_price_basis: BuyPriceBasis,
+    trade_cost: &TradeCostConfig,
 ) -> StrategyPaperValidationTradeRow {
-    let sell_price = resolve_normal_sell_price(
-        stock.close_series.get(sell_index).copied().flatten(),
-        stock.open_series.get(sell_index).copied().flatten(),
-    );
+    let sell_fee = sell_price
+        .map(|price| trade_cost.calc_fee(TradeSide::Sell, position.shares as f64 * price));
+    let net_sell_price = match (sell_price, sell_fee) {
+        (Some(price), Some(fee)) if position.shares > 0 => {
+            Some(price - fee.total / position.shares as f64)
+        }
+        _ => sell_price,
+    };
+    let cost = build_trade_cost(&position, sell_fee);
     StrategyPaperValidationTradeRow {
         ts_code: stock.ts_code.clone(),
         name: stock.name.clone(),
@@ -1092,7 +1982,7 @@ fn build_closed_trade_row(
             stock.close_series.get(sell_index).copied().flatten(),
             position.buy_cost_price,
         ),
-        realized_return_pct: calc_return_pct(sell_price, position.buy_cost_price),
+        realized_return_pct: calc_return_pct(net_sell_price, position.buy_cost_price),
         daily_holding_close_returns: build_daily_holding_close_returns(
             &stock.trade_dates,
             &stock.close_series,
@@ -1100,10 +1990,160 @@ fn build_closed_trade_row(
             sell_index,
             position.buy_cost_price,
         ),
+        cost,
+        exit_reason: Some(exit_reason.as_str().to_string()),
         status: "closed".to_string(),
     }
 }
 
+// 盘中价位类离场:止损(含 ATR、移动止损)取最高的那条,止盈单独一条
+fn evaluate_intraday_exit(
+    stock: &PreparedPaperStock,
+    position: &OpenPosition,
+    exit_rules: &StrategyPaperValidationExitRules,
+    scan_index: usize,
+) -> Option<(PaperExitReason, f64)> {
+    if scan_index <= position.buy_index {
+        return None;
+    }
+    let open = normalize_valid_price(stock.open_series.get(scan_index).copied().flatten())?;
+    let high = normalize_valid_price(stock.high_series.get(scan_index).copied().flatten())?;
+    let low = normalize_valid_price(stock.low_series.get(scan_index).copied().flatten())?;
+
+    let cost_price = position.buy_cost_price;
+    let mut stop: Option<(PaperExitReason, f64)> = None;
+    let mut push_stop = |reason: PaperExitReason, level: f64| {
+        if level.is_finite()
+            && level > PRICE_EPS
+            && stop.is_none_or(|(_, current)| level > current + PRICE_EPS)
+        {
+            stop = Some((reason, level));
+        }
+    };
+    if let Some(pct) = exit_rules.stop_loss_pct {
+        push_stop(PaperExitReason::StopLoss, cost_price * (1.0 - pct / 100.0));
+    }
+    if let (Some(multiple), Some(atr)) = (exit_rules.atr_stop_multiple, position.entry_atr) {
+        push_stop(PaperExitReason::AtrStop, cost_price - multiple * atr);
+    }
+    if let Some(pct) = exit_rules.trailing_stop_pct {
+        // 只用前一交易日及以前的收盘价,当日价位盘前已知
+        let highest_close = stock
+            .close_series
+            .get(position.buy_index..scan_index)
+            .unwrap_or_default()
+            .iter()
+            .flatten()
+            .copied()
+            .fold(f64::NEG_INFINITY, f64::max);
+        if highest_close.is_finite() {
+            push_stop(
+                PaperExitReason::TrailingStop,
+                highest_close * (1.0 - pct / 100.0),
+            );
+        }
+    }
+    let target = exit_rules
+        .take_profit_pct
+        .map(|pct| cost_price * (1.0 + pct / 100.0));
+
+    if let Some((reason, level)) = stop
+        && open <= level
+    {
+        return Some((reason, open));
+    }
+    if let Some(target) = target
+        && open >= target
+    {
+        return Some((PaperExitReason::TakeProfit, open));
+    }
+
+    let stop_hit = stop.filter(|(_, level)| low <= *level);
+    let target_hit = target.filter(|level| high >= *level);
+    match (stop_hit, target_hit) {
+        (Some(stop), Some(target)) => {
+            let stop_first = match exit_rules.intrabar_order {
+                PaperIntrabarOrder::StopFirst => true,
+                PaperIntrabarOrder::TargetFirst => false,
+                PaperIntrabarOrder::NearestToOpen => open - stop.1 <= target - open,
+            };
+            if stop_first {
+                Some(stop)
+            } else {
+                Some((PaperExitReason::TakeProfit, target))
+            }
+        }
+        (Some(stop), None) => Some(stop),
+        (None, Some(target)) => Some((PaperExitReason::TakeProfit, target)),
+        (None, None) => None,
+    }
+}
+
+// 收盘时判断的离场:持有天数到期、排名跌出前 N
+fn evaluate_close_exit(
+    stock: &PreparedPaperStock,
+    position: &OpenPosition,
+    exit_rules: &StrategyPaperValidationExitRules,
+    scan_index: usize,
+) -> Option<PaperExitReason> {
+    if scan_index <= position.buy_index {
+        return None;
+    }
+    if exit_rules
+        .max_hold_days
+        .is_some_and(|days| scan_index - position.buy_index >= days)
+    {
+        return Some(PaperExitReason::MaxHoldDays);
+    }
+    let rank = stock
+        .rank_series
+        .as_ref()
+        .and_then(|series| series.get(scan_index))
+        .copied()
+        .flatten();
+    match (exit_rules.max_rank, rank) {
+        (Some(max_rank), Some(rank)) if rank > max_rank as f64 => Some(PaperExitReason::RankDrop),
+        _ => None,
+    }
+}
+
+// 买入日及之前 period 根 K 线的平均真实波幅
+fn calc_entry_atr(stock: &PreparedPaperStock, buy_index: usize, period: usize) -> Option<f64> {
+    let start = (buy_index + 1).saturating_sub(period);
+    let true_ranges = (start..=buy_index)
+        .filter_map(|index| {
+            let high = stock.high_series.get(index).copied().flatten()?;
+            let low = stock.low_series.get(index).copied().flatten()?;
+            let range = match stock.pre_close_series.get(index).copied().flatten() {
+                Some(pre_close) => (high - low)
+                    .max((high - pre_close).abs())
+                    .max((low - pre_close).abs()),
+                None => high - low,
+            };
+            range.is_finite().then_some(range)
+        })
+        .collect::<Vec<_>>();
+    if true_ranges.len() < period {
+        return None;
+    }
+    Some(true_ranges.iter().sum::<f64>() / true_ranges.len() as f64)
+}
+
+fn build_trade_cost(
+    position: &OpenPosition,
+    sell_fee: Option<TradeCostBreakdown>,
+) -> StrategyPaperValidationTradeCost {
+    let total_fee = position.buy_fee.total + sell_fee.map_or(0.0, |fee| fee.total);
+    StrategyPaperValidationTradeCost {
+        shares: position.shares,
+        buy_fee: position.buy_fee,
+        sell_fee,
+        total_fee,
+        total_fee_pct: (position.buy_fee.amount > PRICE_EPS)
+            .then(|| total_fee / position.buy_fee.amount * 100.0),
+    }
+}
+
 fn finalize_open_portfolio_positions(
     stocks: &[PreparedPaperStock],
     open_positions: Vec<PortfolioOpenPosition>,
@@ -1114,6 +2154,7 @@ fn finalize_open_portfolio_positions(
         let stock = &stocks[item.stock_index];
         let last_index = stock.trade_dates.len().saturating_sub(1);
         let position = item.position;
+        let cost = build_trade_cost(&position, None);
         trades.push(StrategyPaperValidationTradeRow {
             ts_code: stock.ts_code.clone(),
             name: stock.name.clone(),
@@ -1148,6 +2189,8 @@ fn finalize_open_portfolio_positions(
                 last_index,
                 position.buy_cost_price,
             ),
+            cost,
+            exit_reason: None,
             status: "open".to_string(),
         });
     }
@@ -1270,8 +2313,11 @@ fn simulate_trade_rows_from_runtime(
                                 .map(|value| value.round() as i64),
                             buy_basis_price: basis_price,
                             buy_cost_price,
+                            shares: 0,
+                            buy_fee: TradeCostBreakdown::default(),
+                            entry_atr: None,
                             last_sell_runtime_index: buy_index,
-                            pending_sell: false,
+                            pending_exit: None,
                             sell_runtime,
                         });
                     }
@@ -1303,7 +2349,7 @@ fn simulate_trade_rows_from_runtime(
                 position.last_sell_runtime_index = scan_index;
             }
 
-            let sell_hit = if position.pending_sell {
+            let sell_hit = if position.pending_exit.is_some() {
                 true
             } else {
                 evaluate_sell_hit(&mut position.sell_runtime, sell_program, scan_index)
@@ -1354,11 +2400,13 @@ fn simulate_trade_rows_from_runtime(
                     close_return_pct,
                     realized_return_pct: calc_return_pct(sell_price, position.buy_cost_price),
                     daily_holding_close_returns,
+                    cost: StrategyPaperValidationTradeCost::default(),
+                    exit_reason: Some(PaperExitReason::SellExpression.as_str().to_string()),
                     status: "closed".to_string(),
                 });
             } else {
                 if sell_hit {
-                    position.pending_sell = true;
+                    position.pending_exit = Some(PaperExitReason::SellExpression);
                 }
                 remaining_positions.push(position);
             }
@@ -1403,6 +2451,8 @@ fn simulate_trade_rows_from_runtime(
                 position.buy_cost_price,
             ),
             daily_holding_close_returns,
+            cost: StrategyPaperValidationTradeCost::default(),
+            exit_reason: None,
             status: "open".to_string(),
         });
     }
@@ -2078,7 +3128,11 @@ mod tests {
         cols.insert("TOR".to_string(), vec![Some(1.0); len]);
         cols.insert("ZHANG".to_string(), vec![Some(zhang_pct); len]);
 
-        RowData { trade_dates, cols }
+        RowData {
+            trade_dates,
+            cols,
+            text_cols: HashMap::new(),
+        }
     }
 
     fn run_runtime_trade_simulation(
@@ -2141,13 +3195,18 @@ mod tests {
             trade_dates,
             open_series: runtime_num_series(&base_runtime, "O").unwrap().to_vec(),
             high_series: runtime_num_series(&base_runtime, "H").unwrap().to_vec(),
+            low_series: runtime_num_series(&base_runtime, "L").unwrap().to_vec(),
             close_series: runtime_num_series(&base_runtime, "C").unwrap().to_vec(),
             pre_close_series: runtime_num_series(&base_runtime, "PRE_CLOSE")
                 .unwrap()
                 .to_vec(),
             zhang_series: runtime_num_series(&base_runtime, "ZHANG").unwrap().to_vec(),
+            amount_series: runtime_num_series(&base_runtime, "AMOUNT")
+                .unwrap()
+                .to_vec(),
             rank_series: runtime_num_series_optional(&base_runtime, "RANK")
                 .map(|series| series.to_vec()),
+            score_series: None,
             base_runtime,
             buy_signal_series,
             keep_from: 0,
@@ -2388,6 +3447,10 @@ mod tests {
             0.0,
             2,
             BuySelectionMode::RankTop,
+            PAPER_VALIDATION_RANDOM_SEED,
+            &TradeCostConfig::zero(),
+            &StrategyPaperValidationExitRules::default(),
+            None,
         )
         .expect("portfolio simulation should succeed");
 
@@ -2402,6 +3465,313 @@ mod tests {
         assert!(trades.iter().all(|trade| trade.status == "open"));
     }
 
+    #[test]
+    fn random_selection_entry_order_follows_seed() {
+        let bars = [SampleBar {
+            trade_date: "20240102",
+            open: 10.0,
+            high: 10.2,
+            low: 9.9,
+            close: 10.0,
+            pre_close: 9.8,
+        }];
+        let stocks = vec![
+            build_prepared_portfolio_stock("000001.SZ", &bars, "C > 0", 3),
+            build_prepared_portfolio_stock("000002.SZ", &bars, "C > 0", 1),
+            build_prepared_portfolio_stock("000003.SZ", &bars, "C > 0", 2),
+        ];
+        let sell_program = parse_expression_program("TIME >= 10", "卖点方程")
+            .expect("sell expression should parse");
+        let picked = |seed: u64| {
+            let trades = simulate_portfolio_trade_rows(
+                &stocks,
+                &["20240102".to_string()],
+                &sell_program,
+                &PaperTradeEligibility::default(),
+                "20240102",
+                "20240102",
+                BuyPriceBasis::Open,
+                0.0,
+                1,
+                BuySelectionMode::Random,
+                seed,
+                &TradeCostConfig::zero(),
+                &StrategyPaperValidationExitRules::default(),
+                None,
+            )
+            .expect("portfolio simulation should succeed");
+            assert_eq!(trades.len(), 1);
+            trades[0].ts_code.clone()
+        };
+
+        // 同一种子可复现,不同种子的重跑会覆盖不同的入场顺序
+        assert_eq!(picked(7), picked(7));
+        let distinct = (0..32).map(picked).collect::<HashSet<_>>();
+        assert!(distinct.len() > 1);
+    }
+
+    #[test]
+    fn paper_daily_returns_split_holdings_across_position_slots() {
+        let bars = [
+            SampleBar {
+                trade_date: "20240102",
+                open: 10.0,
+                high: 10.2,
+                low: 9.9,
+                close: 10.1,
+                pre_close: 9.8,
+            },
+            SampleBar {
+                trade_date: "20240103",
+                open: 10.5,
+                high: 11.2,
+                low: 10.4,
+                close: 11.0,
+                pre_close: 10.1,
+            },
+        ];
+        let mut stock = build_prepared_portfolio_stock("000001.SZ", &bars, "C > 0", 1);
+        stock.buy_signal_series = vec![true, false];
+        let sell_program = parse_expression_program("TIME >= 10", "卖点方程")
+            .expect("sell expression should parse");
+        let trade_dates = ["20240101", "20240102", "20240103"]
+            .map(str::to_string)
+            .to_vec();
+        let trades = simulate_portfolio_trade_rows(
+            &[stock],
+            &trade_dates[1..],
+            &sell_program,
+            &PaperTradeEligibility::default(),
+            "20240102",
+            "20240103",
+            BuyPriceBasis::Open,
+            0.0,
+            2,
+            BuySelectionMode::RankTop,
+            PAPER_VALIDATION_RANDOM_SEED,
+            &TradeCostConfig::zero(),
+            &StrategyPaperValidationExitRules::default(),
+            None,
+        )
+        .expect("portfolio simulation should succeed");
+
+        // 两个仓位只用了一个,每天的组合收益是持仓收益的一半,空仓日记 0
+        let returns =
+            paper_daily_returns_pct(&trade_dates, "20240101", "20240103", &trades, None, 2);
+        assert_eq!(returns.len(), 3);
+        assert_eq!(returns[0], 0.0);
+        assert!((returns[1] - 0.5).abs() < 1e-9);
+        assert!((returns[2] - (11.0 / 10.1 - 1.0) * 50.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn portfolio_engine_defers_same_day_sell_and_tracks_nav() {
+        let bars = [
+            SampleBar {
+                trade_date: "20240102",
+                open: 10.0,
+                high: 10.2,
+                low: 9.9,
+                close: 10.1,
+                pre_close: 9.8,
+            },
+            SampleBar {
+                trade_date: "20240103",
+                open: 10.5,
+                high: 11.2,
+                low: 10.4,
+                close: 11.0,
+                pre_close: 10.1,
+            },
+        ];
+        let mut stock = build_prepared_portfolio_stock("000001.SZ", &bars, "C > 0", 1);
+        stock.buy_signal_series = vec![true, false];
+        let sell_program =
+            parse_expression_program("C > 0", "卖点方程").expect("sell expression should parse");
+        let mut engine = PortfolioEngine::new(PortfolioConfig {
+            initial_capital: 100_000.0,
+            max_position_count: 2,
+            sizing_mode: PortfolioSizingMode::EqualWeight,
+            cost: TradeCostConfig::zero(),
+        })
+        .expect("engine should build");
+
+        let trades = simulate_portfolio_trade_rows(
+            &[stock],
+            &["20240102".to_string(), "20240103".to_string()],
+            &sell_program,
+            &PaperTradeEligibility::default(),
+            "20240102",
+            "20240103",
+            BuyPriceBasis::Open,
+            0.0,
+            2,
+            BuySelectionMode::RankTop,
+            PAPER_VALIDATION_RANDOM_SEED,
+            &TradeCostConfig::zero(),
+            &StrategyPaperValidationExitRules::default(),
+            Some(&mut engine),
+        )
+        .expect("portfolio simulation should succeed");
+
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].sell_date.as_deref(), Some("20240103"));
+        let result = engine.finish(&[]);
+        assert_eq!(result.fills.len(), 2);
+        assert_eq!(result.fills[0].shares, 5000);
+        assert!((result.nav_points[0].nav - 100_500.0).abs() < 1e-9);
+        assert!((result.nav_points[1].nav - 105_000.0).abs() < 1e-9);
+        assert_eq!(result.nav_points[1].position_count, 0);
+    }
+
+    #[test]
+    fn trade_cost_model_reports_fee_breakdown_and_net_return() {
+        let bars = [
+            SampleBar {
+                trade_date: "20240102",
+                open: 10.0,
+                high: 10.2,
+                low: 9.9,
+                close: 10.1,
+                pre_close: 9.8,
+            },
+            SampleBar {
+                trade_date: "20240103",
+                open: 10.5,
+                high: 11.2,
+                low: 10.4,
+                close: 11.0,
+                pre_close: 10.1,
+            },
+        ];
+        let mut stock = build_prepared_portfolio_stock("000001.SZ", &bars, "C > 0", 1);
+        stock.buy_signal_series = vec![true, false];
+        let sell_program = parse_expression_program("TIME >= 1", "卖点方程")
+            .expect("sell expression should parse");
+        let run = |trade_cost: &TradeCostConfig| {
+            simulate_portfolio_trade_rows(
+                std::slice::from_ref(&stock),
+                &["20240102".to_string(), "20240103".to_string()],
+                &sell_program,
+                &PaperTradeEligibility::default(),
+                "20240102",
+                "20240103",
+                BuyPriceBasis::Open,
+                0.0,
+                1,
+                BuySelectionMode::RankTop,
+                PAPER_VALIDATION_RANDOM_SEED,
+                trade_cost,
+                &StrategyPaperValidationExitRules::default(),
+                None,
+            )
+            .expect("portfolio simulation should succeed")
+        };
+
+        let trades = run(&TradeCostConfig::default());
+        assert_eq!(trades.len(), 1);
+        let cost = &trades[0].cost;
+        // 参考金额 10 万,按 10 元含费用只能买 99 手
+        assert_eq!(cost.shares, 9900);
+        assert!((cost.buy_fee.commission - 24.75).abs() < 1e-9);
+        let sell_fee = cost.sell_fee.expect("closed trade should have sell fee");
+        assert!((sell_fee.stamp_duty - 9900.0 * 11.0 * 0.0005).abs() < 1e-9);
+        let net_return = trades[0].realized_return_pct.unwrap();
+        assert!(net_return < 10.0 && net_return > 9.0);
+
+        // AMOUNT=1000 千元,0.05% 只够 500 元,不足一手不成交
+        let capped = TradeCostConfig {
+            max_amount_participation_pct: Some(0.05),
+            ..TradeCostConfig::default()
+        };
+        assert!(run(&capped).is_empty());
+    }
+
+    #[test]
+    fn exit_rules_resolve_intrabar_stop_and_target_order() {
+        let buy_bar = SampleBar {
+            trade_date: "20240102",
+            open: 10.0,
+            high: 10.2,
+            low: 9.9,
+            close: 10.0,
+            pre_close: 9.8,
+        };
+        let run = |second_bar: SampleBar, exit_rules: StrategyPaperValidationExitRules| {
+            let mut stock =
+                build_prepared_portfolio_stock("000001.SZ", &[buy_bar, second_bar], "C > 0", 1);
+            stock.buy_signal_series = vec![true, false];
+            let sell_program = parse_expression_program("C < 0", "卖点方程")
+                .expect("sell expression should parse");
+            simulate_portfolio_trade_rows(
+                std::slice::from_ref(&stock),
+                &["20240102".to_string(), "20240103".to_string()],
+                &sell_program,
+                &PaperTradeEligibility::default(),
+                "20240102",
+                "20240103",
+                BuyPriceBasis::Open,
+                0.0,
+                1,
+                BuySelectionMode::RankTop,
+                PAPER_VALIDATION_RANDOM_SEED,
+                &TradeCostConfig::zero(),
+                &exit_rules,
+                None,
+            )
+            .expect("portfolio simulation should succeed")
+        };
+        let wide_bar = SampleBar {
+            trade_date: "20240103",
+            open: 10.0,
+            high: 10.6,
+            low: 9.4,
+            close: 10.0,
+            pre_close: 10.0,
+        };
+        let rules = |intrabar_order| StrategyPaperValidationExitRules {
+            stop_loss_pct: Some(5.0),
+            take_profit_pct: Some(5.0),
+            intrabar_order,
+            ..StrategyPaperValidationExitRules::default()
+        };
+
+        let trades = run(wide_bar, rules(PaperIntrabarOrder::StopFirst));
+        assert_eq!(trades[0].exit_reason.as_deref(), Some("stop_loss"));
+        assert!((trades[0].sell_price.unwrap() - 9.5).abs() < 1e-9);
+
+        let trades = run(wide_bar, rules(PaperIntrabarOrder::TargetFirst));
+        assert_eq!(trades[0].exit_reason.as_deref(), Some("take_profit"));
+        assert!((trades[0].sell_price.unwrap() - 10.5).abs() < 1e-9);
+
+        // 跳空低开直接按开盘价止损
+        let gap_bar = SampleBar {
+            open: 9.2,
+            high: 9.6,
+            low: 9.1,
+            ..wide_bar
+        };
+        let trades = run(gap_bar, rules(PaperIntrabarOrder::TargetFirst));
+        assert_eq!(trades[0].exit_reason.as_deref(), Some("stop_loss"));
+        assert!((trades[0].sell_price.unwrap() - 9.2).abs() < 1e-9);
+
+        // 没有盘中触发时按持有天数到期收盘卖出
+        let calm_bar = SampleBar {
+            high: 10.3,
+            low: 9.8,
+            ..wide_bar
+        };
+        let trades = run(
+            calm_bar,
+            StrategyPaperValidationExitRules {
+                max_hold_days: Some(1),
+                ..rules(PaperIntrabarOrder::StopFirst)
+            },
+        );
+        assert_eq!(trades[0].exit_reason.as_deref(), Some("max_hold_days"));
+        assert_eq!(trades[0].status, "closed");
+    }
+
     #[test]
     fn pending_limit_down_sell_stays_open_when_window_ends() {
         let trades = run_runtime_trade_simulation(
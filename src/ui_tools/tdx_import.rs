@@ -0,0 +1,890 @@
+//! 通达信/同花顺公式导入。
+//!
+//! 把公式源码(`:=` 赋值、`:` 输出、`COLORRED`/`LINETHICK2` 等绘图属性、`收盘`
+//! 这类中文字段)翻译成本项目的表达式, 再生成图表指标配置和 `ind.toml` 指标。
+//! 每个输出都生成一段只含自身依赖的独立表达式, 可以单独放进图表或指标文件。
+
+use std::collections::{HashMap, HashSet};
+
+use serde::Serialize;
+
+use crate::{
+    data::IndData,
+    expr::{
+        parser::{BinaryOp, Expr, Stmt, Stmts},
+        validation::{
+            first_unsupported_expression_function, future_data_warning, parse_expression_program,
+        },
+    },
+    ui_tools::{
+        chart_indicator::{
+            ChartIndicatorConfig, ChartMarkerConfig, ChartMarkerKind, ChartMarkerPosition,
+            ChartMarkerShape, ChartPanelConfig, ChartPanelKind, ChartPanelRole, ChartSeriesConfig,
+            ChartSeriesKind, ChartTooltipConfig, ChartTooltipFormat,
+            default_chart_indicator_config, normalize_chart_indicator_config,
+            validate_chart_indicator_config,
+        },
+        chart_indicator_settings::serialize_chart_indicator_config,
+    },
+};
+
+const DEFAULT_IND_PREC: usize = 2;
+
+const TDX_FIELD_ALIASES: [(&str, &str); 20] = [
+    ("CLOSE", "C"),
+    ("OPEN", "O"),
+    ("HIGH", "H"),
+    ("LOW", "L"),
+    ("VOL", "V"),
+    ("VOLUME", "V"),
+    ("AMO", "AMOUNT"),
+    ("收盘价", "C"),
+    ("收盘", "C"),
+    ("开盘价", "O"),
+    ("开盘", "O"),
+    ("最高价", "H"),
+    ("最高", "H"),
+    ("最低价", "L"),
+    ("最低", "L"),
+    ("成交量", "V"),
+    ("成交额", "AMOUNT"),
+    ("换手率", "TOR"),
+    ("涨跌幅", "PCT_CHG"),
+    ("昨收", "PRE_CLOSE"),
+];
+
+const TDX_FUNCTION_ALIASES: [(&str, &str); 2] = [("IFF", "IF"), ("EXPMA", "EMA")];
+
+// 能转换成标记的绘图函数之外, 其余绘图函数只提示后跳过
+const TDX_SKIPPED_DRAW_FUNCTIONS: [&str; 13] = [
+    "STICKLINE",
+    "DRAWKLINE",
+    "DRAWLINE",
+    "POLYLINE",
+    "PLOYLINE",
+    "DRAWNUMBER",
+    "DRAWBAND",
+    "DRAWTEXT_FIX",
+    "DRAWGBK",
+    "DRAWSL",
+    "PARTLINE",
+    "FILLRGN",
+    "VERTLINE",
+];
+
+const TDX_NAMED_COLORS: [(&str, &str); 10] = [
+    ("COLORBLACK", "#000000"),
+    ("COLORBLUE", "#0000FF"),
+    ("COLORGREEN", "#00FF00"),
+    ("COLORCYAN", "#00FFFF"),
+    ("COLORRED", "#FF0000"),
+    ("COLORMAGENTA", "#FF00FF"),
+    ("COLORYELLOW", "#FFFF00"),
+    ("COLORWHITE", "#FFFFFF"),
+    ("COLORGRAY", "#808080"),
+    ("COLORLIGRAY", "#C0C0C0"),
+];
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TdxImportOutput {
+    pub key: String,
+    pub label: Option<String>,
+    pub expression: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TdxFormulaImport {
+    // 整段公式的翻译结果, 输出行改成赋值, 最后一句是最后一个输出
+    pub expression: String,
+    pub outputs: Vec<TdxImportOutput>,
+    pub chart: ChartIndicatorConfig,
+    pub chart_text: String,
+    pub ind: Vec<IndData>,
+    pub warnings: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum TdxToken {
+    Ident(String),
+    Number(String),
+    Str(String),
+    Op(&'static str),
+    Comma,
+    Semi,
+    LParen,
+    RParen,
+    Assign,
+    Colon,
+}
+
+#[derive(Debug, Default)]
+struct TdxDecoration {
+    color: Option<String>,
+    line_width: Option<f64>,
+    stick: bool,
+    no_draw: bool,
+}
+
+// 翻译后的一句: 赋值和输出都按变量保存, 供后面的句子引用
+struct TdxStatement {
+    var: String,
+    expr: Expr,
+}
+
+struct TdxNames {
+    vars: HashMap<String, String>,
+    next: usize,
+}
+
+impl TdxNames {
+    // 中文变量名换成 T_1, T_2..., ASCII 变量名统一大写
+    fn define(&mut self, raw: &str) -> String {
+        let upper = raw.to_ascii_uppercase();
+        if is_ascii_ident(&upper) {
+            self.vars.insert(upper.clone(), upper.clone());
+            return upper;
+        }
+        if let Some(name) = self.vars.get(&upper) {
+            return name.clone();
+        }
+        self.next += 1;
+        let name = format!("T_{}", self.next);
+        self.vars.insert(upper, name.clone());
+        name
+    }
+
+    fn resolve(&self, raw: &str) -> Result<String, String> {
+        let upper = raw.to_ascii_uppercase();
+        if let Some(name) = self.vars.get(&upper) {
+            return Ok(name.clone());
+        }
+        if let Some((_, field)) = TDX_FIELD_ALIASES.iter().find(|(from, _)| *from == upper) {
+            return Ok((*field).to_string());
+        }
+        if is_ascii_ident(&upper) {
+            return Ok(upper);
+        }
+        Err(format!("无法识别的字段或变量: {raw}"))
+    }
+}
+
+/// 把一段通达信/同花顺公式导入为表达式、图表面板和 `ind.toml` 指标。
+///
+/// 公式里出现本项目不支持的函数时直接报错, 错误里带上函数名。
+pub fn import_tdx_formula(
+    panel_key: &str,
+    panel_label: &str,
+    role: ChartPanelRole,
+    source: &str,
+) -> Result<TdxFormulaImport, String> {
+    let tokens = tokenize_tdx(&strip_tdx_comments(source))?;
+    let mut names = TdxNames {
+        vars: HashMap::new(),
+        next: 0,
+    };
+    let mut statements: Vec<TdxStatement> = Vec::new();
+    let mut series = Vec::new();
+    let mut tooltips = Vec::new();
+    let mut markers = Vec::new();
+    let mut outputs = Vec::new();
+    let mut warnings = Vec::new();
+    let mut used_keys = HashSet::new();
+    let mut last_output_var = None;
+
+    for (index, stmt_tokens) in tokens.split(|token| *token == TdxToken::Semi).enumerate() {
+        if stmt_tokens.is_empty() {
+            continue;
+        }
+        let n = index + 1;
+        let (head, body) = match stmt_tokens {
+            [TdxToken::Ident(name), TdxToken::Assign, rest @ ..] => (Some((name, true)), rest),
+            [TdxToken::Ident(name), TdxToken::Colon, rest @ ..] => (Some((name, false)), rest),
+            _ => (None, stmt_tokens),
+        };
+
+        let mut parts = split_top_level_commas(body);
+        let expr_tokens = parts.remove(0);
+        let expr = translate_tdx_expr(expr_tokens, &names, n)?;
+        let decoration = parse_decorations(&parts, n, &mut warnings);
+
+        if let Some((name, true)) = head {
+            if !parts.is_empty() {
+                warnings.push(format!("第{n}句是中间变量, 绘图属性已忽略"));
+            }
+            let var = names.define(name);
+            statements.push(TdxStatement { var, expr });
+            continue;
+        }
+
+        if head.is_none()
+            && let Expr::Call { name, args } = &expr
+        {
+            let upper = name.to_ascii_uppercase();
+            if upper == "DRAWTEXT" || upper == "DRAWICON" {
+                markers.push(build_marker(
+                    &upper,
+                    args,
+                    &statements,
+                    &used_keys,
+                    &decoration,
+                    n,
+                    markers.len() + 1,
+                    &mut warnings,
+                )?);
+                continue;
+            }
+            if TDX_SKIPPED_DRAW_FUNCTIONS.contains(&upper.as_str()) {
+                warnings.push(format!("第{n}句的绘图函数{upper}暂不支持, 已跳过"));
+                continue;
+            }
+        }
+
+        let output_no = outputs.len() + 1;
+        let label = head.map(|(name, _)| name.clone());
+        let key = match label.as_deref() {
+            Some(name) if is_ascii_ident(&name.to_ascii_uppercase()) => name.to_ascii_uppercase(),
+            _ => format!("OUT{output_no}"),
+        };
+        if !used_keys.insert(key.clone()) {
+            return Err(format!("第{n}句的输出名称重复: {key}"));
+        }
+        let var = match label.as_deref() {
+            Some(name) => names.define(name),
+            None => names.define(&key),
+        };
+        let expression = standalone_program(&statements, &expr);
+        last_output_var = Some(var.clone());
+        statements.push(TdxStatement { var, expr });
+
+        if decoration.no_draw {
+            tooltips.push(ChartTooltipConfig {
+                key: key.clone(),
+                label: label.clone(),
+                expr: expression.clone(),
+                format: Some(ChartTooltipFormat::Number),
+            });
+        } else {
+            let kind = if decoration.stick && role == ChartPanelRole::Sub {
+                ChartSeriesKind::Bar
+            } else {
+                if decoration.stick {
+                    warnings.push(format!("主图只支持线条, 第{n}句的柱线改为线条"));
+                }
+                ChartSeriesKind::Line
+            };
+            series.push(ChartSeriesConfig {
+                key: key.clone(),
+                label: label.clone(),
+                expr: expression.clone(),
+                kind,
+                color: decoration.color.clone(),
+                color_when: Vec::new(),
+                line_width: decoration.line_width,
+                opacity: None,
+                base_value: None,
+            });
+        }
+        outputs.push(TdxImportOutput {
+            key,
+            label,
+            expression,
+        });
+    }
+
+    if outputs.is_empty() && markers.is_empty() {
+        return Err("公式没有任何输出".to_string());
+    }
+
+    let program = Stmts {
+        item: statements
+            .iter()
+            .map(|stmt| Stmt::Assign {
+                name: stmt.var.clone(),
+                value: stmt.expr.clone(),
+            })
+            .chain(
+                markers
+                    .iter()
+                    .flat_map(|(_, args)| args.iter().cloned().map(Stmt::Expr).collect::<Vec<_>>()),
+            )
+            .collect(),
+    };
+    if let Some(name) = first_unsupported_expression_function(&program) {
+        return Err(format!("公式使用了暂不支持的函数: {name}"));
+    }
+    if uses_function(&program, "STD") {
+        warnings.push("STD按总体标准差计算, 与通达信的样本标准差略有差异".to_string());
+    }
+    warnings.extend(future_data_warning("公式", &program));
+    if uses_function(&program, "PEAK") || uses_function(&program, "TROUGH") {
+        warnings.push("PEAK/TROUGH只取已确认的转折点, 与通达信的取值时间不同".to_string());
+    }
+
+    let chart = build_chart(
+        panel_key,
+        panel_label,
+        role,
+        series,
+        markers.into_iter().map(|(marker, _)| marker).collect(),
+        tooltips,
+    )?;
+    let chart_text = serialize_chart_indicator_config(&chart)?;
+    let ind = outputs
+        .iter()
+        .map(|output| IndData {
+            name: output.key.clone(),
+            expr: output.expression.clone(),
+            prec: DEFAULT_IND_PREC,
+        })
+        .collect();
+
+    Ok(TdxFormulaImport {
+        expression: full_program(&statements, last_output_var),
+        outputs,
+        chart,
+        chart_text,
+        ind,
+        warnings,
+    })
+}
+
+fn build_chart(
+    panel_key: &str,
+    panel_label: &str,
+    role: ChartPanelRole,
+    series: Vec<ChartSeriesConfig>,
+    markers: Vec<ChartMarkerConfig>,
+    tooltips: Vec<ChartTooltipConfig>,
+) -> Result<ChartIndicatorConfig, String> {
+    let mut chart = default_chart_indicator_config();
+    match role {
+        ChartPanelRole::Main => {
+            let main = &mut chart.panels[0];
+            main.series = series;
+            main.markers = markers;
+            main.tooltips.extend(tooltips);
+        }
+        ChartPanelRole::Sub => chart.panels.push(ChartPanelConfig {
+            key: panel_key.trim().to_string(),
+            label: panel_label.trim().to_string(),
+            role,
+            kind: ChartPanelKind::Line,
+            series,
+            markers,
+            tooltips,
+        }),
+    }
+    let chart = normalize_chart_indicator_config(&chart);
+    validate_chart_indicator_config(&chart)?;
+    Ok(chart)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn build_marker(
+    fn_name: &str,
+    args: &[Expr],
+    statements: &[TdxStatement],
+    used_keys: &HashSet<String>,
+    decoration: &TdxDecoration,
+    n: usize,
+    marker_no: usize,
+    warnings: &mut Vec<String>,
+) -> Result<(ChartMarkerConfig, Vec<Expr>), String> {
+    let [cond, price, third] = args else {
+        return Err(format!("第{n}句的{fn_name}需要三个参数"));
+    };
+
+    let (text, shape) = if fn_name == "DRAWTEXT" {
+        let Expr::Str(text) = third else {
+            return Err(format!("第{n}句的DRAWTEXT第三个参数必须是文字"));
+        };
+        (Some(text.clone()), None)
+    } else {
+        // 通达信图标1、2分别是买卖箭头
+        let shape = match third {
+            Expr::Number(icon) if *icon as i64 == 1 => ChartMarkerShape::TriangleUp,
+            Expr::Number(icon) if *icon as i64 == 2 => ChartMarkerShape::TriangleDown,
+            _ => ChartMarkerShape::Dot,
+        };
+        (None, Some(shape))
+    };
+
+    // 标记的y只能引用字段或输出, 其他价格表达式改为画在K线上方
+    let (y, position) = match price {
+        Expr::Ident(name)
+            if matches!(name.as_str(), "O" | "H" | "L" | "C") || used_keys.contains(name) =>
+        {
+            (Some(name.clone()), ChartMarkerPosition::Value)
+        }
+        _ => {
+            warnings.push(format!(
+                "第{n}句的{fn_name}价格位置无法直接对应, 改为画在上方"
+            ));
+            (None, ChartMarkerPosition::Above)
+        }
+    };
+
+    let marker = ChartMarkerConfig {
+        key: format!("MARK{marker_no}"),
+        label: text.clone(),
+        when: standalone_program(statements, cond),
+        y,
+        kind: Some(ChartMarkerKind::Symbol),
+        position: Some(position),
+        shape,
+        color: decoration.color.clone(),
+        text,
+        line_style: None,
+        line_width: None,
+        opacity: None,
+    };
+    Ok((marker, vec![cond.clone(), price.clone()]))
+}
+
+// 只保留目标表达式实际依赖的前置语句, 拼成一段可以单独运行的程序
+fn standalone_program(statements: &[TdxStatement], target: &Expr) -> String {
+    let mut needed = HashSet::new();
+    collect_idents(target, &mut needed);
+    let mut keep = vec![false; statements.len()];
+    for (index, stmt) in statements.iter().enumerate().rev() {
+        if needed.contains(&stmt.var) {
+            keep[index] = true;
+            needed.remove(&stmt.var);
+            collect_idents(&stmt.expr, &mut needed);
+        }
+    }
+
+    let mut lines = statements
+        .iter()
+        .zip(keep)
+        .filter(|(_, keep)| *keep)
+        .map(|(stmt, _)| format!("{} := {};", stmt.var, stmt.expr))
+        .collect::<Vec<_>>();
+    lines.push(target.to_string());
+    lines.join("\n")
+}
+
+fn full_program(statements: &[TdxStatement], last_output_var: Option<String>) -> String {
+    let mut lines = statements
+        .iter()
+        .map(|stmt| format!("{} := {};", stmt.var, stmt.expr))
+        .collect::<Vec<_>>();
+    lines.extend(last_output_var);
+    lines.join("\n")
+}
+
+fn collect_idents(expr: &Expr, out: &mut HashSet<String>) {
+    match expr {
+        Expr::Ident(name) => {
+            out.insert(name.clone());
+        }
+        Expr::Call { args, .. } => args.iter().for_each(|arg| collect_idents(arg, out)),
+        Expr::Unary { rhs, .. } => collect_idents(rhs, out),
+        Expr::Binary { lhs, rhs, .. } => {
+            collect_idents(lhs, out);
+            collect_idents(rhs, out);
+        }
+        Expr::Number(_) | Expr::Str(_) => {}
+    }
+}
+
+fn uses_function(stmts: &Stmts, target: &str) -> bool {
+    fn walk(expr: &Expr, target: &str) -> bool {
+        match expr {
+            Expr::Call { name, args } => {
+                name.eq_ignore_ascii_case(target) || args.iter().any(|arg| walk(arg, target))
+            }
+            Expr::Unary { rhs, .. } => walk(rhs, target),
+            Expr::Binary { lhs, rhs, .. } => walk(lhs, target) || walk(rhs, target),
+            Expr::Number(_) | Expr::Str(_) | Expr::Ident(_) => false,
+        }
+    }
+    stmts.item.iter().any(|stmt| match stmt {
+        Stmt::Expr(expr) | Stmt::Assign { value: expr, .. } => walk(expr, target),
+    })
+}
+
+fn parse_decorations(parts: &[&[TdxToken]], n: usize, warnings: &mut Vec<String>) -> TdxDecoration {
+    let mut decoration = TdxDecoration::default();
+    for part in parts {
+        let [TdxToken::Ident(raw)] = part else {
+            warnings.push(format!("第{n}句有无法识别的绘图属性, 已忽略"));
+            continue;
+        };
+        let upper = raw.to_ascii_uppercase();
+        if let Some((_, color)) = TDX_NAMED_COLORS.iter().find(|(name, _)| *name == upper) {
+            decoration.color = Some((*color).to_string());
+        } else if let Some(color) = tdx_hex_color(&upper) {
+            decoration.color = Some(color);
+        } else if let Some(width) = upper.strip_prefix("LINETHICK") {
+            match width.parse::<u8>() {
+                Ok(width) if width > 0 => decoration.line_width = Some(width as f64),
+                _ => warnings.push(format!("第{n}句的{raw}线宽无法识别, 已忽略")),
+            }
+        } else {
+            match upper.as_str() {
+                "STICK" | "COLORSTICK" | "VOLSTICK" => decoration.stick = true,
+                "NODRAW" => decoration.no_draw = true,
+                _ => warnings.push(format!("第{n}句的绘图属性{raw}暂不支持, 已忽略")),
+            }
+        }
+    }
+    decoration
+}
+
+// 通达信的 COLORBBGGRR 按蓝绿红顺序书写
+fn tdx_hex_color(upper: &str) -> Option<String> {
+    let hex = upper.strip_prefix("COLOR")?;
+    if hex.len() != 6 || !hex.chars().all(|ch| ch.is_ascii_hexdigit()) {
+        return None;
+    }
+    Some(format!("#{}{}{}", &hex[4..6], &hex[2..4], &hex[0..2]))
+}
+
+fn translate_tdx_expr(tokens: &[TdxToken], names: &TdxNames, n: usize) -> Result<Expr, String> {
+    if tokens.is_empty() {
+        return Err(format!("第{n}句缺少表达式"));
+    }
+
+    let mut parts = Vec::with_capacity(tokens.len());
+    for (index, token) in tokens.iter().enumerate() {
+        let part = match token {
+            TdxToken::Ident(raw) => {
+                if tokens.get(index + 1) == Some(&TdxToken::LParen) {
+                    let upper = raw.to_ascii_uppercase();
+                    TDX_FUNCTION_ALIASES
+                        .iter()
+                        .find(|(from, _)| *from == upper)
+                        .map_or(upper, |(_, to)| (*to).to_string())
+                } else {
+                    names.resolve(raw).map_err(|e| format!("第{n}句{e}"))?
+                }
+            }
+            TdxToken::Number(text) => text.clone(),
+            TdxToken::Str(text) => format!("'{text}'"),
+            TdxToken::Op(op) => match *op {
+                "=" => "==",
+                "<>" => "!=",
+                "&&" => "AND",
+                "||" => "OR",
+                other => other,
+            }
+            .to_string(),
+            TdxToken::Comma => ",".to_string(),
+            TdxToken::LParen => "(".to_string(),
+            TdxToken::RParen => ")".to_string(),
+            TdxToken::Semi | TdxToken::Assign | TdxToken::Colon => {
+                return Err(format!("第{n}句的赋值符号位置不对"));
+            }
+        };
+        parts.push(part);
+    }
+
+    let text = parts.join(" ");
+    let stmts = parse_expression_program(&text)
+        .map_err(|e| format!("第{n}句翻译后无法解析({text}): {}", e.msg))?;
+    match stmts.item.as_slice() {
+        [Stmt::Expr(expr)] => Ok(rewrite_tdx_calls(expr.clone())),
+        _ => Err(format!("第{n}句不是单个表达式")),
+    }
+}
+
+// 通达信里有、本项目用组合写法表达的函数
+fn rewrite_tdx_calls(expr: Expr) -> Expr {
+    match expr {
+        Expr::Call { name, args } => {
+            let mut args = args.into_iter().map(rewrite_tdx_calls).collect::<Vec<_>>();
+            match (name.to_ascii_uppercase().as_str(), args.len()) {
+                // EVERY(X,N): N周期内一直满足
+                ("EVERY", 2) => {
+                    let n = args[1].clone();
+                    binary(BinaryOp::Eq, call("COUNT", args), n)
+                }
+                // BETWEEN(A,B,C): A处于B和C之间, 含边界
+                ("BETWEEN", 3) => {
+                    let c = args.pop().expect("three args");
+                    let b = args.pop().expect("three args");
+                    let a = args.pop().expect("three args");
+                    binary(
+                        BinaryOp::And,
+                        binary(
+                            BinaryOp::Ge,
+                            a.clone(),
+                            call("MIN", vec![b.clone(), c.clone()]),
+                        ),
+                        binary(BinaryOp::Le, a, call("MAX", vec![b, c])),
+                    )
+                }
+                // RANGE(A,B,C): B < A < C
+                ("RANGE", 3) => {
+                    let c = args.pop().expect("three args");
+                    let b = args.pop().expect("three args");
+                    let a = args.pop().expect("three args");
+                    binary(
+                        BinaryOp::And,
+                        binary(BinaryOp::Gt, a.clone(), b),
+                        binary(BinaryOp::Lt, a, c),
+                    )
+                }
+                _ => Expr::Call { name, args },
+            }
+        }
+        Expr::Unary { op, rhs } => Expr::Unary {
+            op,
+            rhs: Box::new(rewrite_tdx_calls(*rhs)),
+        },
+        Expr::Binary { op, lhs, rhs } => {
+            binary(op, rewrite_tdx_calls(*lhs), rewrite_tdx_calls(*rhs))
+        }
+        other => other,
+    }
+}
+
+fn call(name: &str, args: Vec<Expr>) -> Expr {
+    Expr::Call {
+        name: name.to_string(),
+        args,
+    }
+}
+
+fn binary(op: BinaryOp, lhs: Expr, rhs: Expr) -> Expr {
+    Expr::Binary {
+        op,
+        lhs: Box::new(lhs),
+        rhs: Box::new(rhs),
+    }
+}
+
+fn split_top_level_commas(tokens: &[TdxToken]) -> Vec<&[TdxToken]> {
+    let mut parts = Vec::new();
+    let mut depth = 0usize;
+    let mut start = 0;
+    for (index, token) in tokens.iter().enumerate() {
+        match token {
+            TdxToken::LParen => depth += 1,
+            TdxToken::RParen => depth = depth.saturating_sub(1),
+            TdxToken::Comma if depth == 0 => {
+                parts.push(&tokens[start..index]);
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&tokens[start..]);
+    parts
+}
+
+// 去掉 {..} 块注释和 // 行注释, 引号里的内容保持不变
+fn strip_tdx_comments(source: &str) -> String {
+    let mut out = String::with_capacity(source.len());
+    let mut chars = source.chars().peekable();
+    let mut quote = None;
+    while let Some(ch) = chars.next() {
+        if let Some(q) = quote {
+            out.push(ch);
+            if ch == q {
+                quote = None;
+            }
+            continue;
+        }
+        match ch {
+            '\'' | '"' => {
+                quote = Some(ch);
+                out.push(ch);
+            }
+            '{' => {
+                for inner in chars.by_ref() {
+                    if inner == '}' {
+                        break;
+                    }
+                }
+                out.push(' ');
+            }
+            '/' if chars.peek() == Some(&'/') => {
+                for inner in chars.by_ref() {
+                    if inner == '\n' {
+                        break;
+                    }
+                }
+                out.push('\n');
+            }
+            _ => out.push(ch),
+        }
+    }
+    out
+}
+
+fn tokenize_tdx(source: &str) -> Result<Vec<TdxToken>, String> {
+    // 全角标点按半角处理
+    let normalized = source
+        .chars()
+        .map(|ch| match ch {
+            '；' => ';',
+            '，' => ',',
+            '（' => '(',
+            '）' => ')',
+            '：' => ':',
+            '＝' => '=',
+            _ => ch,
+        })
+        .collect::<Vec<_>>();
+
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < normalized.len() {
+        let ch = normalized[i];
+        let next = normalized.get(i + 1).copied();
+        if ch.is_whitespace() {
+            i += 1;
+            continue;
+        }
+        if ch.is_ascii_digit() || (ch == '.' && next.is_some_and(|c| c.is_ascii_digit())) {
+            let start = i;
+            while i < normalized.len() && (normalized[i].is_ascii_digit() || normalized[i] == '.') {
+                i += 1;
+            }
+            tokens.push(TdxToken::Number(normalized[start..i].iter().collect()));
+            continue;
+        }
+        if ch == '_' || ch.is_alphabetic() {
+            let start = i;
+            while i < normalized.len() && (normalized[i] == '_' || normalized[i].is_alphanumeric())
+            {
+                i += 1;
+            }
+            tokens.push(TdxToken::Ident(normalized[start..i].iter().collect()));
+            continue;
+        }
+        if ch == '\'' || ch == '"' {
+            let Some(len) = normalized[i + 1..].iter().position(|c| *c == ch) else {
+                return Err("公式里的引号没有闭合".to_string());
+            };
+            tokens.push(TdxToken::Str(
+                normalized[i + 1..i + 1 + len].iter().collect(),
+            ));
+            i += len + 2;
+            continue;
+        }
+
+        let (token, width) = match (ch, next) {
+            (':', Some('=')) => (TdxToken::Assign, 2),
+            (':', _) => (TdxToken::Colon, 1),
+            (',', _) => (TdxToken::Comma, 1),
+            (';', _) => (TdxToken::Semi, 1),
+            ('(', _) => (TdxToken::LParen, 1),
+            (')', _) => (TdxToken::RParen, 1),
+            ('>', Some('=')) => (TdxToken::Op(">="), 2),
+            ('<', Some('=')) => (TdxToken::Op("<="), 2),
+            ('<', Some('>')) => (TdxToken::Op("<>"), 2),
+            ('!', Some('=')) => (TdxToken::Op("!="), 2),
+            ('=', Some('=')) => (TdxToken::Op("="), 2),
+            ('&', Some('&')) => (TdxToken::Op("&&"), 2),
+            ('|', Some('|')) => (TdxToken::Op("||"), 2),
+            ('>', _) => (TdxToken::Op(">"), 1),
+            ('<', _) => (TdxToken::Op("<"), 1),
+            ('=', _) => (TdxToken::Op("="), 1),
+            ('+', _) => (TdxToken::Op("+"), 1),
+            ('-', _) => (TdxToken::Op("-"), 1),
+            ('*', _) => (TdxToken::Op("*"), 1),
+            ('/', _) => (TdxToken::Op("/"), 1),
+            _ => return Err(format!("公式里有无法识别的字符: {ch}")),
+        };
+        tokens.push(token);
+        i += width;
+    }
+    Ok(tokens)
+}
+
+fn is_ascii_ident(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(first) if first.is_ascii_alphabetic() || first == '_')
+        && chars.all(|ch| ch.is_ascii_alphanumeric() || ch == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn imports_macd_outputs_as_standalone_series() {
+        let source = "DIF:EMA(CLOSE,12)-EMA(CLOSE,26);\n\
+                      DEA:EMA(DIF,9),COLORYELLOW;\n\
+                      MACD:(DIF-DEA)*2,COLORSTICK;";
+        let imported = import_tdx_formula("macd", "MACD", ChartPanelRole::Sub, source)
+            .expect("macd should import");
+
+        let panel = &imported.chart.panels[1];
+        assert_eq!(panel.kind, ChartPanelKind::Bar);
+        let keys = panel
+            .series
+            .iter()
+            .map(|series| series.key.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(keys, vec!["DIF", "DEA", "MACD"]);
+        assert_eq!(panel.series[1].color.as_deref(), Some("#FFFF00"));
+        assert_eq!(panel.series[2].kind, ChartSeriesKind::Bar);
+        assert_eq!(
+            panel.series[1].expr,
+            "DIF := EMA(C, 12) - EMA(C, 26);\nEMA(DIF, 9)"
+        );
+        assert_eq!(imported.ind.len(), 3);
+        assert_eq!(imported.ind[2].name, "MACD");
+        assert!(
+            imported
+                .expression
+                .ends_with("MACD := (DIF - DEA) * 2;\nMACD")
+        );
+        assert!(imported.chart_text.contains("[[panel.series]]"));
+    }
+
+    #[test]
+    fn imports_chinese_names_comments_and_draw_text() {
+        let source = "{均线差} 短期:=MA(收盘,5); 长期:=MA(收盘,20);\n\
+                      趋势:短期-长期,COLOR0000FF,LINETHICK2; // 输出\n\
+                      买点:=CROSS(短期,长期) && 成交量<>0;\n\
+                      DRAWTEXT(买点,L,'B'),COLORRED;";
+        let imported = import_tdx_formula("trend", "均线差", ChartPanelRole::Sub, source)
+            .expect("formula should import");
+
+        let panel = &imported.chart.panels[1];
+        let series = &panel.series[0];
+        assert_eq!(series.key, "OUT1");
+        assert_eq!(series.label.as_deref(), Some("趋势"));
+        assert_eq!(series.color.as_deref(), Some("#FF0000"));
+        assert_eq!(series.line_width, Some(2.0));
+        assert_eq!(
+            series.expr,
+            "T_1 := MA(C, 5);\nT_2 := MA(C, 20);\nT_1 - T_2"
+        );
+
+        let marker = &panel.markers[0];
+        assert_eq!(marker.text.as_deref(), Some("B"));
+        assert_eq!(marker.y.as_deref(), Some("L"));
+        assert_eq!(
+            marker.when,
+            "T_1 := MA(C, 5);\nT_2 := MA(C, 20);\nT_4 := CROSS(T_1, T_2) AND V != 0;\nT_4"
+        );
+        assert!(imported.warnings.is_empty());
+    }
+
+    #[test]
+    fn rewrites_every_and_reports_unsupported_functions() {
+        let imported = import_tdx_formula(
+            "up",
+            "连涨",
+            ChartPanelRole::Sub,
+            "UP:EVERY(C>REF(C,1),3),NODRAW;",
+        )
+        .expect("formula should import");
+        assert_eq!(
+            imported.chart.panels[1].tooltips[0].expr,
+            "COUNT(C > REF(C, 1), 3) == 3"
+        );
+
+        let err = import_tdx_formula("chip", "筹码", ChartPanelRole::Sub, "X:WINNER(C);")
+            .expect_err("WINNER is not supported");
+        assert!(err.contains("WINNER"), "{err}");
+    }
+}
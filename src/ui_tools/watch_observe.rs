@@ -41,6 +41,7 @@ pub struct WatchObserveRow {
     pub post_watch_return_pct: Option<f64>,
     pub today_rank: Option<i64>,
     pub scene_marker: Option<String>,
+    pub risk_marker: Option<String>,
     pub tag: String,
     pub concept: String,
     pub marked_date: Option<String>,
@@ -235,6 +236,58 @@ fn query_optional_scene_marker(
     Ok(None)
 }
 
+// 空头场景当日阶段升级(如 observe -> trigger)时给出提示,降级和新建 fail 不提示
+fn query_optional_risk_marker(
+    conn: &Connection,
+    trade_date: &str,
+    ts_code: &str,
+) -> Result<Option<String>, String> {
+    let mut stmt = conn
+        .prepare(
+            r#"
+            SELECT scene_name, from_stage, to_stage
+            FROM scene_transitions
+            WHERE trade_date = ?
+              AND ts_code = ?
+              AND direction = 'short'
+            ORDER BY scene_name ASC
+            "#,
+        )
+        .map_err(|e| format!("预编译自选风险场景失败: {e}"))?;
+    let mut rows = stmt
+        .query(params![trade_date, ts_code])
+        .map_err(|e| format!("查询自选风险场景失败: {e}"))?;
+
+    let mut markers = Vec::new();
+    while let Some(row) = rows
+        .next()
+        .map_err(|e| format!("读取自选风险场景失败: {e}"))?
+    {
+        let scene_name: String = row
+            .get(0)
+            .map_err(|e| format!("读取自选风险场景名称失败: {e}"))?;
+        let from_stage: Option<String> = row
+            .get(1)
+            .map_err(|e| format!("读取自选风险场景原阶段失败: {e}"))?;
+        let to_stage: Option<String> = row
+            .get(2)
+            .map_err(|e| format!("读取自选风险场景新阶段失败: {e}"))?;
+        if scene_stage_level(to_stage.as_deref()) <= scene_stage_level(from_stage.as_deref())
+            || scene_stage_level(to_stage.as_deref()) <= 0
+        {
+            continue;
+        }
+        markers.push(format!(
+            "{} {}->{}",
+            scene_name.trim(),
+            from_stage.as_deref().unwrap_or("none"),
+            to_stage.as_deref().unwrap_or_default()
+        ));
+    }
+
+    Ok((!markers.is_empty()).then(|| markers.join(" / ")))
+}
+
 fn query_optional_next_open(
     source_conn: &Connection,
     trade_date: &str,
@@ -396,6 +449,7 @@ pub fn hydrate_watch_observe_rows(
                 post_watch_return_pct: None,
                 today_rank: None,
                 scene_marker: None,
+                risk_marker: None,
                 tag: row.tag.clone(),
                 concept: row.concept.clone(),
                 marked_date: row.marked_date.clone(),
@@ -446,6 +500,12 @@ pub fn hydrate_watch_observe_rows(
             .unwrap_or_default(),
             _ => None,
         };
+        let risk_marker = match (result_conn.as_ref(), resolved_rank_trade_date.as_deref()) {
+            (Some(conn), Some(trade_date)) => {
+                query_optional_risk_marker(conn, trade_date, &row.ts_code).unwrap_or_default()
+            }
+            _ => None,
+        };
         let observe_trade_date = row
             .marked_date
             .as_deref()
@@ -469,6 +529,7 @@ pub fn hydrate_watch_observe_rows(
             post_watch_return_pct,
             today_rank,
             scene_marker,
+            risk_marker,
             tag: row.tag.clone(),
             concept,
             marked_date: row.marked_date.clone(),
@@ -591,6 +652,15 @@ pub fn build_watch_observe_snapshot_data(
             .unwrap_or_default(),
             _ => None,
         };
+        let risk_marker = match (
+            result_conn.as_ref(),
+            resolved_reference_trade_date.as_deref(),
+        ) {
+            (Some(conn), Some(trade_date)) => {
+                query_optional_risk_marker(conn, trade_date, &row.ts_code).unwrap_or_default()
+            }
+            _ => None,
+        };
 
         out.push(WatchObserveRow {
             ts_code: row.ts_code.clone(),
@@ -603,6 +673,7 @@ pub fn build_watch_observe_snapshot_data(
             post_watch_return_pct,
             today_rank,
             scene_marker,
+            risk_marker,
             tag: row.tag.clone(),
             concept,
             marked_date: row.marked_date.clone(),
@@ -626,8 +697,8 @@ mod tests {
     use duckdb::{Connection, params};
 
     use super::{
-        calc_return_pct, query_latest_snapshot, query_optional_scene_marker,
-        resolve_watch_date_for_clock,
+        calc_return_pct, query_latest_snapshot, query_optional_risk_marker,
+        query_optional_scene_marker, resolve_watch_date_for_clock,
     };
 
     fn dates() -> Vec<String> {
@@ -715,6 +786,52 @@ mod tests {
         );
     }
 
+    #[test]
+    fn risk_marker_flags_only_escalating_short_scenes() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute(
+            "CREATE TABLE scene_transitions (
+                trade_date VARCHAR,
+                ts_code VARCHAR,
+                scene_name VARCHAR,
+                direction VARCHAR,
+                from_stage VARCHAR,
+                to_stage VARCHAR
+            )",
+            [],
+        )
+        .unwrap();
+        for (scene_name, direction, from_stage, to_stage) in [
+            ("破位", "short", Some("observe"), "trigger"),
+            ("放量下跌", "short", Some("confirm"), "observe"),
+            ("突破", "long", None, "confirm"),
+        ] {
+            conn.execute(
+                "INSERT INTO scene_transitions VALUES (?, ?, ?, ?, ?, ?)",
+                params![
+                    "20260729",
+                    "000001.SZ",
+                    scene_name,
+                    direction,
+                    from_stage,
+                    to_stage
+                ],
+            )
+            .unwrap();
+        }
+
+        assert_eq!(
+            query_optional_risk_marker(&conn, "20260729", "000001.SZ")
+                .unwrap()
+                .as_deref(),
+            Some("破位 observe->trigger")
+        );
+        assert_eq!(
+            query_optional_risk_marker(&conn, "20260728", "000001.SZ").unwrap(),
+            None
+        );
+    }
+
     #[test]
     fn three_day_return_uses_daily_and_realtime_baselines() {
         let conn = Connection::open_in_memory().unwrap();
@@ -1,8 +1,9 @@
 use std::collections::HashMap;
 
 use crate::expr::{
-    eval::ExpressionFunction,
+    eval::{ExpressionFunction, rule_trigger_var},
     parser::{BinaryOp, Expr},
+    period::PeriodFreq,
 };
 
 const EPS: f64 = 1e-12;
@@ -153,6 +154,23 @@ fn eval_dynamic_max_for_warmup(
     Ok(value as usize)
 }
 
+// 周期参数的warmup: 数字、常量或常量算式, 与MA等窗口函数相同
+fn eval_window_for_warmup(
+    fn_name: &str,
+    expr: Expr,
+    consts: &HashMap<String, usize>,
+) -> Result<usize, String> {
+    match expr {
+        Expr::Number(v) => Ok(v as usize),
+        Expr::Ident(name) => Ok(consts.get(&name).copied().unwrap_or(0)),
+        Expr::Binary { op, lhs, rhs } => match eval_binary_for_warmup(&op, &lhs, &rhs, consts)? {
+            Some(v) => Ok(v as usize),
+            None => Err(format!("{fn_name}参数warmup解析错误")),
+        },
+        _ => Err(format!("{fn_name}参数warmup解析错误")),
+    }
+}
+
 pub fn impl_expr_warmup(
     expr: Expr,
     locals: &HashMap<String, usize>,
@@ -426,8 +444,22 @@ pub fn impl_expr_warmup(
                         .ok_or_else(|| format!("{name}缺少第1个参数: src"))?;
                     max_need = impl_expr_warmup(src, locals, consts)?;
                 }
+                ExpressionFunction::Xrank
+                | ExpressionFunction::Xpct
+                | ExpressionFunction::Xzscore
+                | ExpressionFunction::Xmedian => {
+                    // 截面函数只看当天, warmup取决于参数本身
+                    for arg in args {
+                        max_need = max_need.max(impl_expr_warmup(arg, locals, consts)?);
+                    }
+                }
 
-                ExpressionFunction::Max | ExpressionFunction::Min | ExpressionFunction::Div => {
+                ExpressionFunction::Max
+                | ExpressionFunction::Min
+                | ExpressionFunction::Div
+                | ExpressionFunction::Contains
+                | ExpressionFunction::Startswith
+                | ExpressionFunction::Endswith => {
                     let mut it = args.into_iter();
                     let left = it
                         .next()
@@ -511,6 +543,97 @@ pub fn impl_expr_warmup(
 
                     max_need = c_need.max(h_need.max(l_need) + win_need.saturating_sub(1));
                 }
+                ExpressionFunction::Slope
+                | ExpressionFunction::Forcast
+                | ExpressionFunction::Wma
+                | ExpressionFunction::Avedev
+                | ExpressionFunction::Var
+                | ExpressionFunction::Hhvbars
+                | ExpressionFunction::Llvbars => {
+                    let mut it = args.into_iter();
+                    let src = it
+                        .next()
+                        .ok_or_else(|| format!("{name}缺少第1个参数: src"))?;
+                    let win = it
+                        .next()
+                        .ok_or_else(|| format!("{name}缺少第2个参数: win"))?;
+
+                    let src_need = impl_expr_warmup(src, locals, consts)?;
+                    let win_need = eval_window_for_warmup(&name, win, consts)?;
+                    max_need = (src_need + win_need).saturating_sub(1);
+                }
+                ExpressionFunction::Corr | ExpressionFunction::Covar => {
+                    let mut it = args.into_iter();
+                    let x = it.next().ok_or_else(|| format!("{name}缺少第1个参数: x"))?;
+                    let y = it.next().ok_or_else(|| format!("{name}缺少第2个参数: y"))?;
+                    let win = it
+                        .next()
+                        .ok_or_else(|| format!("{name}缺少第3个参数: win"))?;
+
+                    let x_need = impl_expr_warmup(x, locals, consts)?;
+                    let y_need = impl_expr_warmup(y, locals, consts)?;
+                    let win_need = eval_window_for_warmup(&name, win, consts)?;
+                    max_need = (x_need.max(y_need) + win_need).saturating_sub(1);
+                }
+                ExpressionFunction::Filter => {
+                    let mut it = args.into_iter();
+                    let cond = it
+                        .next()
+                        .ok_or_else(|| format!("{name}缺少第1个参数: cond"))?;
+                    let win = it
+                        .next()
+                        .ok_or_else(|| format!("{name}缺少第2个参数: win"))?;
+
+                    let cond_need = impl_expr_warmup(cond, locals, consts)?;
+                    max_need = cond_need + eval_window_for_warmup(&name, win, consts)?;
+                }
+                ExpressionFunction::Sar => {
+                    let mut it = args.into_iter();
+                    let win = it
+                        .next()
+                        .ok_or_else(|| format!("{name}缺少第1个参数: win"))?;
+                    max_need = eval_window_for_warmup(&name, win, consts)?.saturating_sub(1);
+                }
+                ExpressionFunction::Rule => {
+                    // 被引用规则的warmup由依赖图按规则名预先放进locals
+                    if let Some(Expr::Str(rule_name)) = args.first() {
+                        max_need = locals
+                            .get(&rule_trigger_var(rule_name))
+                            .copied()
+                            .unwrap_or(0);
+                    }
+                }
+                ExpressionFunction::Period => {
+                    let mut it = args.into_iter();
+                    let freq = it
+                        .next()
+                        .ok_or_else(|| format!("{name}缺少第1个参数: freq"))?;
+                    let src = it
+                        .next()
+                        .ok_or_else(|| format!("{name}缺少第2个参数: src"))?;
+                    let freq = match &freq {
+                        Expr::Str(text) => PeriodFreq::parse(text),
+                        _ => None,
+                    }
+                    .ok_or_else(|| format!("{name}参数warmup解析错误"))?;
+
+                    // 内部按周期K线计算, 再加上正在走的和对齐滞后的一个周期
+                    let src_need = impl_expr_warmup(src, locals, consts)?;
+                    max_need = (src_need + 2) * freq.bars_per_period();
+                }
+                ExpressionFunction::Dma
+                | ExpressionFunction::Barscount
+                | ExpressionFunction::Barssince
+                | ExpressionFunction::Zig
+                | ExpressionFunction::Peak
+                | ExpressionFunction::Trough
+                | ExpressionFunction::Backset
+                | ExpressionFunction::Valuewhen => {
+                    // 递推或依赖全部历史的函数, 与EMA一样只看参数本身
+                    for arg in args {
+                        max_need = max_need.max(impl_expr_warmup(arg, locals, consts)?);
+                    }
+                }
                 ExpressionFunction::Rsvd => {
                     let mut it = args.into_iter();
                     let c = it.next().ok_or_else(|| format!("{name}缺少第1个参数: c"))?;
@@ -534,7 +657,7 @@ pub fn impl_expr_warmup(
                 }
             }
         }
-        Expr::Number(_) => {}
+        Expr::Number(_) | Expr::Str(_) => {}
         Expr::Ident(name) => {
             if let Some(need) = locals.get(&name) {
                 max_need = *need
@@ -43,11 +43,14 @@ fn hex_val(b: u8) -> Option<u8> {
     }
 }
 
+use lianghua_rs::simulate::robustness::RobustnessConfig;
+use lianghua_rs::simulate::walk_forward::WalkForwardConfig;
 use lianghua_rs::ui_tools::{
     all_market_monitor::{
         get_all_market_monitor_snapshot as core_get_all_market_monitor_snapshot,
         AllMarketMonitorSnapshotData,
     },
+    chart_indicator::ChartPanelRole,
     chart_indicator_settings::{
         get_chart_indicator_settings as core_get_chart_indicator_settings,
         reset_chart_indicator_settings as core_reset_chart_indicator_settings,
@@ -102,8 +105,9 @@ use lianghua_rs::ui_tools::{
         DragonTigerMarketData, DragonTigerSeatStatisticsData, DragonTigerStockDetailData,
     },
     expression::{
-        ExpressionCapabilitiesData,
+        ExpressionCapabilitiesData, ExpressionTraceData,
         get_expression_capabilities as core_get_expression_capabilities,
+        trace_stock_expression as core_trace_stock_expression,
     },
     expression_stock_pick::{
         validate_expression_stock_pick_template_expression as core_validate_expression_stock_pick_template_expression,
@@ -122,7 +126,8 @@ use lianghua_rs::ui_tools::{
     overview::{
         get_scene_rank_overview_page as core_get_scene_rank_overview_page,
         get_scene_rank_trade_date_options as core_get_scene_rank_trade_date_options,
-        SceneOverviewPageData,
+        get_scene_transition_page as core_get_scene_transition_page, SceneOverviewPageData,
+        SceneTransitionPageData,
     },
     overview_classic::{
         get_rank_overview as core_get_rank_overview,
@@ -130,16 +135,25 @@ use lianghua_rs::ui_tools::{
         get_rank_trade_date_options as core_get_rank_trade_date_options, OverviewPageData,
         OverviewRow,
     },
+    param_sweep::{
+        run_rule_param_sweep as core_run_rule_param_sweep,
+        run_strategy_param_sweep as core_run_strategy_param_sweep, RuleParamSweepData,
+        StrategyParamSweepData,
+    },
     ranking_compute::{
+        compare_ranking_score_runs as core_compare_ranking_score_runs,
+        delete_ranking_score_run as core_delete_ranking_score_run,
         get_ranking_compute_status as core_get_ranking_compute_status,
+        list_ranking_score_runs as core_list_ranking_score_runs,
         preview_ranking_score_calculation_warnings as core_preview_ranking_score_calculation_warnings,
         run_concept_performance_compute as core_run_concept_performance_compute,
         run_cyq_chen_compute_with_range_and_progress as core_run_cyq_chen_compute,
         run_cyq_compute_with_range_and_progress as core_run_cyq_compute,
+        run_ranking_incremental_score_calculation as core_run_ranking_incremental_score_calculation,
         run_ranking_score_calculation as core_run_ranking_score_calculation,
         run_ranking_tiebreak_fill as core_run_ranking_tiebreak_fill,
         ConceptPerformanceComputeResult, CyqChenComputeResult, CyqComputeResult,
-        RankComputeRunResult, RankComputeStatus,
+        RankComputeRunResult, RankComputeStatus, RankScoreRunCompareResult, RankScoreRunItem,
     },
     statistics::{
         get_market_analysis as core_get_market_analysis,
@@ -155,6 +169,7 @@ use lianghua_rs::ui_tools::{
         run_rule_expression_validation as core_run_rule_expression_validation,
         run_rule_layer_backtest as core_run_rule_layer_backtest,
         run_scene_layer_backtest as core_run_scene_layer_backtest,
+        run_score_run_rank_layer_compare as core_run_score_run_rank_layer_compare,
         run_transient_rank_layer_backtest as core_run_transient_rank_layer_backtest,
         run_transient_rule_layer_backtest as core_run_transient_rule_layer_backtest,
         run_transient_scene_layer_backtest as core_run_transient_scene_layer_backtest,
@@ -163,7 +178,7 @@ use lianghua_rs::ui_tools::{
         RuleExpressionValidationManualStrategy, RuleLayerBacktestData,
         RuleLayerBacktestDefaultsData, RuleValidationUnknownConfig,
         SceneLayerBacktestData, SceneLayerBacktestDefaultsData, SceneStatisticsPageData,
-        StrategyStatisticsDetailData, StrategyStatisticsPageData, TriggeredStockRow,
+        ScoreRunRankLayerCompareData, StrategyStatisticsDetailData, StrategyStatisticsPageData, TriggeredStockRow,
     },
     stock_pick::{get_stock_pick_options as core_get_stock_pick_options, StockPickOptionsData},
     stock_similarity::{
@@ -189,10 +204,13 @@ use lianghua_rs::ui_tools::{
     strategy_paper_validation::{
         get_strategy_paper_validation_defaults as core_get_strategy_paper_validation_defaults,
         run_strategy_paper_validation as core_run_strategy_paper_validation,
+        run_strategy_portfolio_backtest as core_run_strategy_portfolio_backtest,
         validate_strategy_paper_validation_template_expressions as core_validate_strategy_paper_validation_template_expressions,
         StrategyPaperValidationData, StrategyPaperValidationDefaultsData,
-        StrategyPaperValidationTemplateValidationData,
+        StrategyPaperValidationExitRules, StrategyPaperValidationTemplateValidationData,
+        StrategyPortfolioBacktestData,
     },
+    tdx_import::{import_tdx_formula as core_import_tdx_formula, TdxFormulaImport},
     watch_observe::{
         hydrate_watch_observe_rows as core_hydrate_watch_observe_rows,
         normalize_trade_date as core_normalize_watch_observe_trade_date,
@@ -516,6 +534,16 @@ fn reset_chart_indicator_settings(
     core_reset_chart_indicator_settings(&source_path)
 }
 
+#[tauri::command]
+fn import_tdx_formula(
+    panel_key: String,
+    panel_label: String,
+    role: ChartPanelRole,
+    source: String,
+) -> Result<TdxFormulaImport, String> {
+    core_import_tdx_formula(&panel_key, &panel_label, role, &source)
+}
+
 #[tauri::command]
 fn get_ranking_compute_status(
     source_path: String,
@@ -548,6 +576,7 @@ fn get_rank_overview(
     exclude_st_board: Option<bool>,
     total_mv_min: Option<f64>,
     total_mv_max: Option<f64>,
+    rank_by: Option<String>,
 ) -> Result<Vec<OverviewRow>, String> {
     core_get_rank_overview(
         source_path,
@@ -557,6 +586,7 @@ fn get_rank_overview(
         exclude_st_board,
         total_mv_min,
         total_mv_max,
+        rank_by,
     )
 }
 
@@ -575,6 +605,7 @@ fn get_rank_overview_page(
     exclude_st_board: Option<bool>,
     total_mv_min: Option<f64>,
     total_mv_max: Option<f64>,
+    rank_by: Option<String>,
 ) -> Result<OverviewPageData, String> {
     core_get_rank_overview_page(
         source_path,
@@ -585,6 +616,7 @@ fn get_rank_overview_page(
         exclude_st_board,
         total_mv_min,
         total_mv_max,
+        rank_by,
     )
 }
 
@@ -648,6 +680,16 @@ fn get_scene_rank_overview_page(
     )
 }
 
+#[tauri::command]
+fn get_scene_transition_page(
+    source_path: String,
+    trade_date: Option<String>,
+    scene_name: Option<String>,
+    to_stage: Option<String>,
+) -> Result<SceneTransitionPageData, String> {
+    core_get_scene_transition_page(&source_path, trade_date, scene_name, to_stage)
+}
+
 #[tauri::command]
 fn get_intraday_monitor_page(
     source_path: String,
@@ -718,6 +760,21 @@ fn get_expression_capabilities() -> ExpressionCapabilitiesData {
     core_get_expression_capabilities()
 }
 
+#[tauri::command]
+async fn trace_stock_expression(
+    source_path: String,
+    ts_code: String,
+    start_date: String,
+    end_date: String,
+    expression: String,
+) -> Result<ExpressionTraceData, String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        core_trace_stock_expression(&source_path, ts_code, start_date, end_date, expression)
+    })
+    .await
+    .map_err(|error| error.to_string())?
+}
+
 #[tauri::command]
 fn validate_intraday_monitor_template_expression(
     source_path: Option<String>,
@@ -974,6 +1031,8 @@ async fn run_strategy_paper_validation(
     buy_selection_mode: Option<String>,
     buy_expression: String,
     sell_expression: String,
+    exit_rules: Option<StrategyPaperValidationExitRules>,
+    robustness: Option<RobustnessConfig>,
 ) -> Result<StrategyPaperValidationData, String> {
     tauri::async_runtime::spawn_blocking(move || {
         run_with_heap_trim(|| {
@@ -991,6 +1050,143 @@ async fn run_strategy_paper_validation(
                 buy_selection_mode,
                 buy_expression,
                 sell_expression,
+                exit_rules,
+                robustness,
+            )
+        })
+    })
+    .await
+    .map_err(|error| error.to_string())?
+}
+
+#[tauri::command]
+async fn run_strategy_portfolio_backtest(
+    source_path: String,
+    start_date: Option<String>,
+    end_date: Option<String>,
+    min_listed_trade_days: Option<usize>,
+    index_ts_code: Option<String>,
+    test_ts_code: Option<String>,
+    board: Option<String>,
+    buy_price_basis: String,
+    slippage_pct: Option<f64>,
+    max_position_count: Option<usize>,
+    buy_selection_mode: Option<String>,
+    buy_expression: String,
+    sell_expression: String,
+    exit_rules: Option<StrategyPaperValidationExitRules>,
+    initial_capital: Option<f64>,
+    sizing_mode: Option<String>,
+    robustness: Option<RobustnessConfig>,
+) -> Result<StrategyPortfolioBacktestData, String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        run_with_heap_trim(|| {
+            core_run_strategy_portfolio_backtest(
+                &source_path,
+                start_date,
+                end_date,
+                min_listed_trade_days,
+                index_ts_code,
+                test_ts_code,
+                board,
+                buy_price_basis,
+                slippage_pct,
+                max_position_count,
+                buy_selection_mode,
+                buy_expression,
+                sell_expression,
+                exit_rules,
+                initial_capital,
+                sizing_mode,
+                robustness,
+            )
+        })
+    })
+    .await
+    .map_err(|error| error.to_string())?
+}
+
+#[tauri::command]
+async fn run_strategy_param_sweep(
+    source_path: String,
+    start_date: Option<String>,
+    end_date: Option<String>,
+    min_listed_trade_days: Option<usize>,
+    index_ts_code: Option<String>,
+    board: Option<String>,
+    buy_price_basis: String,
+    slippage_pct: Option<f64>,
+    max_position_count: Option<usize>,
+    buy_selection_mode: Option<String>,
+    buy_expression: String,
+    sell_expression: String,
+    exit_rules: Option<StrategyPaperValidationExitRules>,
+    initial_capital: Option<f64>,
+    sizing_mode: Option<String>,
+    params: Vec<String>,
+    walk_forward: Option<WalkForwardConfig>,
+) -> Result<StrategyParamSweepData, String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        run_with_heap_trim(|| {
+            core_run_strategy_param_sweep(
+                &source_path,
+                start_date,
+                end_date,
+                min_listed_trade_days,
+                index_ts_code,
+                board,
+                buy_price_basis,
+                slippage_pct,
+                max_position_count,
+                buy_selection_mode,
+                buy_expression,
+                sell_expression,
+                exit_rules,
+                initial_capital,
+                sizing_mode,
+                params,
+                walk_forward,
+            )
+        })
+    })
+    .await
+    .map_err(|error| error.to_string())?
+}
+
+#[tauri::command]
+async fn run_rule_param_sweep(
+    source_path: String,
+    import_rule_name: String,
+    when: String,
+    scope_way: Option<String>,
+    scope_windows: Option<usize>,
+    stock_adj_type: Option<String>,
+    index_ts_code: String,
+    start_date: String,
+    end_date: String,
+    min_samples_per_rule_day: Option<usize>,
+    backtest_period: Option<usize>,
+    board: Option<String>,
+    params: Vec<String>,
+    walk_forward: Option<WalkForwardConfig>,
+) -> Result<RuleParamSweepData, String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        run_with_heap_trim(|| {
+            core_run_rule_param_sweep(
+                source_path,
+                import_rule_name,
+                when,
+                scope_way,
+                scope_windows,
+                stock_adj_type,
+                index_ts_code,
+                start_date,
+                end_date,
+                min_samples_per_rule_day,
+                backtest_period,
+                board,
+                params,
+                walk_forward,
             )
         })
     })
@@ -1157,6 +1353,7 @@ async fn run_scene_layer_backtest(
     exclude_st_board: Option<bool>,
     total_mv_min: Option<f64>,
     total_mv_max: Option<f64>,
+    robustness: Option<RobustnessConfig>,
 ) -> Result<SceneLayerBacktestData, String> {
     tauri::async_runtime::spawn_blocking(move || {
         run_with_heap_trim(|| {
@@ -1176,6 +1373,7 @@ async fn run_scene_layer_backtest(
                 exclude_st_board,
                 total_mv_min,
                 total_mv_max,
+                robustness,
             )
         })
     })
@@ -1201,6 +1399,7 @@ async fn run_rule_layer_backtest(
     exclude_st_board: Option<bool>,
     total_mv_min: Option<f64>,
     total_mv_max: Option<f64>,
+    robustness: Option<RobustnessConfig>,
 ) -> Result<RuleLayerBacktestData, String> {
     tauri::async_runtime::spawn_blocking(move || {
         run_with_heap_trim(|| {
@@ -1221,6 +1420,7 @@ async fn run_rule_layer_backtest(
                 exclude_st_board,
                 total_mv_min,
                 total_mv_max,
+                robustness,
             )
         })
     })
@@ -1245,6 +1445,7 @@ async fn run_rank_layer_backtest(
     layer_method: Option<String>,
     board: Option<String>,
     exclude_st_board: Option<bool>,
+    robustness: Option<RobustnessConfig>,
 ) -> Result<RankLayerBacktestData, String> {
     tauri::async_runtime::spawn_blocking(move || {
         run_with_heap_trim(|| {
@@ -1264,6 +1465,54 @@ async fn run_rank_layer_backtest(
                 layer_method,
                 board,
                 exclude_st_board,
+                robustness,
+            )
+        })
+    })
+    .await
+    .map_err(|error| error.to_string())?
+}
+
+#[tauri::command]
+async fn run_score_run_rank_layer_compare(
+    source_path: String,
+    base_run_id: String,
+    target_run_id: String,
+    stock_adj_type: Option<String>,
+    index_ts_code: String,
+    index_beta: Option<f64>,
+    concept_beta: Option<f64>,
+    industry_beta: Option<f64>,
+    start_date: String,
+    end_date: String,
+    min_samples_per_rank_day: Option<usize>,
+    min_listed_trade_days: Option<usize>,
+    backtest_period: Option<usize>,
+    layer_count: Option<usize>,
+    layer_method: Option<String>,
+    board: Option<String>,
+    exclude_st_board: Option<bool>,
+) -> Result<ScoreRunRankLayerCompareData, String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        run_with_heap_trim(|| {
+            core_run_score_run_rank_layer_compare(
+                source_path,
+                base_run_id,
+                target_run_id,
+                stock_adj_type,
+                index_ts_code,
+                index_beta,
+                concept_beta,
+                industry_beta,
+                start_date,
+                end_date,
+                min_samples_per_rank_day,
+                min_listed_trade_days,
+                backtest_period,
+                layer_count,
+                layer_method,
+                board,
+                exclude_st_board,
             )
         })
     })
@@ -1557,6 +1806,7 @@ async fn run_ranking_score_calculation(
     strategy_path: Option<String>,
     start_date: String,
     end_date: String,
+    archive_keep_last: Option<usize>,
 ) -> Result<RankComputeRunResult, String> {
     tauri::async_runtime::spawn_blocking(move || {
         let strategy_file_path =
@@ -1578,6 +1828,74 @@ async fn run_ranking_score_calculation(
             Some(snapshot_strategy_path.as_str()),
             &start_date,
             &end_date,
+            archive_keep_last,
+        )
+    })
+    .await
+    .map_err(|error| error.to_string())?
+}
+
+#[tauri::command]
+async fn run_ranking_incremental_score_calculation(
+    app: tauri::AppHandle,
+    source_path: String,
+    strategy_path: Option<String>,
+    start_date: String,
+    end_date: String,
+    archive_keep_last: Option<usize>,
+) -> Result<RankComputeRunResult, String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        let strategy_file_path =
+            lianghua_rs::data::resolve_strategy_path(&source_path, strategy_path.as_deref());
+        let app_data_root = app
+            .path()
+            .resolve("", tauri::path::BaseDirectory::AppData)
+            .map_err(|error| error.to_string())?;
+        let snapshot_strategy_path = snapshot_rank_compute_strategy(
+            &app_data_root,
+            DEFAULT_MANAGED_SOURCE_DIR,
+            &strategy_file_path,
+            Some(&start_date),
+            Some(&end_date),
+        )?;
+        let snapshot_strategy_path = snapshot_strategy_path.display().to_string();
+        core_run_ranking_incremental_score_calculation(
+            &source_path,
+            Some(snapshot_strategy_path.as_str()),
+            &start_date,
+            &end_date,
+            archive_keep_last,
+        )
+    })
+    .await
+    .map_err(|error| error.to_string())?
+}
+
+#[tauri::command]
+fn list_ranking_score_runs(source_path: String) -> Result<Vec<RankScoreRunItem>, String> {
+    core_list_ranking_score_runs(&source_path)
+}
+
+#[tauri::command]
+fn delete_ranking_score_run(source_path: String, run_id: String) -> Result<(), String> {
+    core_delete_ranking_score_run(&source_path, &run_id)
+}
+
+#[tauri::command]
+async fn compare_ranking_score_runs(
+    source_path: String,
+    base_run_id: String,
+    target_run_id: String,
+    trade_date: Option<String>,
+    limit: Option<usize>,
+) -> Result<RankScoreRunCompareResult, String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        core_compare_ranking_score_runs(
+            &source_path,
+            &base_run_id,
+            &target_run_id,
+            trade_date.as_deref(),
+            limit,
         )
     })
     .await
@@ -2428,10 +2746,12 @@ pub fn run() {
             run_convolution_rank_compute,
             get_scene_rank_trade_date_options,
             get_scene_rank_overview_page,
+            get_scene_transition_page,
             get_intraday_monitor_page,
             refresh_intraday_monitor_realtime,
             refresh_intraday_monitor_template_tags,
             get_expression_capabilities,
+            trace_stock_expression,
             validate_intraday_monitor_template_expression,
             get_all_market_monitor_snapshot,
             get_stock_detail_page,
@@ -2449,6 +2769,9 @@ pub fn run() {
             get_strategy_paper_validation_defaults,
             validate_strategy_paper_validation_template_expressions,
             run_strategy_paper_validation,
+            run_strategy_portfolio_backtest,
+            run_strategy_param_sweep,
+            run_rule_param_sweep,
             get_scene_statistics_page,
             get_strategy_statistics_detail,
             get_strategy_triggered_stocks,
@@ -2460,6 +2783,7 @@ pub fn run() {
             get_dragon_tiger_seat_statistics,
             get_market_contribution,
             run_rank_layer_backtest,
+            run_score_run_rank_layer_compare,
             run_scene_layer_backtest,
             run_rule_layer_backtest,
             run_transient_rank_layer_backtest,
@@ -2470,6 +2794,10 @@ pub fn run() {
             get_ranking_compute_status,
             preview_ranking_score_calculation_warnings,
             run_ranking_score_calculation,
+            run_ranking_incremental_score_calculation,
+            list_ranking_score_runs,
+            delete_ranking_score_run,
+            compare_ranking_score_runs,
             run_concept_performance_compute,
             run_cyq_compute,
             run_cyq_chen_compute,
@@ -2503,6 +2831,7 @@ pub fn run() {
             validate_chart_indicator_settings,
             save_chart_indicator_settings,
             reset_chart_indicator_settings,
+            import_tdx_formula,
             validate_expression_stock_pick_template_expression,
             run_expression_stock_pick,
             run_concept_stock_pick,
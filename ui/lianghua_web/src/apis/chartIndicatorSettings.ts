@@ -123,3 +123,38 @@ export async function saveChartIndicatorSettings(sourcePath: string, text: strin
 export async function resetChartIndicatorSettings(sourcePath: string) {
   return invoke<ChartIndicatorSettingsPayload>('reset_chart_indicator_settings', { sourcePath })
 }
+
+export type TdxImportOutput = {
+  key: string
+  label?: string | null
+  expression: string
+}
+
+export type TdxIndData = {
+  name: string
+  expr: string
+  prec: number
+}
+
+export type TdxFormulaImport = {
+  expression: string
+  outputs: TdxImportOutput[]
+  chart: ChartIndicatorConfigDraft
+  chartText: string
+  ind: TdxIndData[]
+  warnings: string[]
+}
+
+export async function importTdxFormula(
+  panelKey: string,
+  panelLabel: string,
+  role: ChartPanelRole,
+  source: string,
+) {
+  return invoke<TdxFormulaImport>('import_tdx_formula', {
+    panelKey,
+    panelLabel,
+    role,
+    source,
+  })
+}
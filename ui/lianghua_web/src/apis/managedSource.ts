@@ -142,6 +142,26 @@ export const MANAGED_SOURCE_FILES = [
     extensions: ['csv'],
     scanPathHints: [] as string[],
   },
+  {
+    id: 'index-constituents',
+    label: '指数成分股',
+    description: '按指数选股票池时使用的成分股表，表头 index_code,ts_code。',
+    fileName: 'index_constituents.csv',
+    expectedSourcePath: 'source/index_constituents.csv',
+    targetRelativePathSuffix: 'index_constituents.csv',
+    extensions: ['csv'],
+    scanPathHints: ['index'],
+  },
+  {
+    id: 'trade-cost',
+    label: '交易成本配置',
+    description: '模拟验证和组合回测使用的佣金、印花税、过户费与成交模型，不导入时按默认费率（佣金万2.5最低5元、印花税万5、过户费十万分之一）计费。',
+    fileName: 'trade_cost.toml',
+    expectedSourcePath: 'source/trade_cost.toml',
+    targetRelativePathSuffix: 'trade_cost.toml',
+    extensions: ['toml'],
+    scanPathHints: ['cost'],
+  },
 ] as const
 
 export type ManagedSourceFileId = (typeof MANAGED_SOURCE_FILES)[number]['id']
@@ -0,0 +1,154 @@
+import { invoke } from '@tauri-apps/api/core'
+import type {
+  PortfolioMetrics,
+  StrategyPaperValidationExitRules,
+  StrategyPaperValidationSummaryData,
+} from './strategyPaperValidation'
+
+export type SweepParamRange = {
+  name: string
+  start: number
+  end: number
+  step: number
+}
+
+export type SweepParamValue = {
+  name: string
+  value: number
+}
+
+export type SweepCombo = {
+  combo_key: string
+  label: string
+  values: SweepParamValue[]
+}
+
+export type WalkForwardConfig = {
+  train_days?: number
+  test_days?: number
+  step_days?: number | null
+}
+
+export type SweepPeriodMetrics = {
+  day_count: number
+  total_return_pct?: number | null
+  avg_daily_return_pct?: number | null
+  sharpe?: number | null
+  max_drawdown_pct?: number | null
+}
+
+export type WalkForwardWindowResult = {
+  window: {
+    train_start: string
+    train_end: string
+    test_start: string
+    test_end: string
+  }
+  selected_combo_key: string
+  in_sample: SweepPeriodMetrics
+  out_of_sample: SweepPeriodMetrics
+}
+
+export type SweepComboSummary = {
+  combo: SweepCombo
+  full_period: SweepPeriodMetrics
+  selected_window_count: number
+  avg_in_sample_sharpe?: number | null
+  avg_out_of_sample_sharpe?: number | null
+}
+
+export type WalkForwardReport = {
+  windows: WalkForwardWindowResult[]
+  combos: SweepComboSummary[]
+  in_sample_avg_sharpe?: number | null
+  out_of_sample: SweepPeriodMetrics
+  sharpe_efficiency?: number | null
+  best_combo_key?: string | null
+  deflated_sharpe?: number | null
+  pbo?: number | null
+  pbo_split_count: number
+}
+
+export type StrategyParamSweepComboResult = {
+  combo: SweepCombo
+  buy_expression: string
+  sell_expression: string
+  summary: StrategyPaperValidationSummaryData
+  metrics: PortfolioMetrics
+}
+
+export type StrategyParamSweepData = {
+  start_date: string
+  end_date: string
+  params: SweepParamRange[]
+  walk_forward: WalkForwardConfig
+  combo_results: StrategyParamSweepComboResult[]
+  report: WalkForwardReport
+}
+
+export type RuleParamSweepComboResult = {
+  combo: SweepCombo
+  formula: string
+  trigger_samples: number
+  avg_residual_mean?: number | null
+  spread_mean?: number | null
+  icir?: number | null
+}
+
+export type RuleParamSweepData = {
+  import_rule_name: string
+  start_date: string
+  end_date: string
+  backtest_period: number
+  params: SweepParamRange[]
+  walk_forward: WalkForwardConfig
+  combo_results: RuleParamSweepComboResult[]
+  report: WalkForwardReport
+}
+
+export type StrategyParamSweepQuery = {
+  sourcePath: string
+  startDate?: string
+  endDate?: string
+  minListedTradeDays?: number
+  indexTsCode?: string
+  board?: string
+  buyPriceBasis: string
+  slippagePct?: number
+  maxPositionCount?: number
+  buySelectionMode?: string
+  // 方程中用 {N} 占位,如 `C > MA(C, {N})`
+  buyExpression: string
+  sellExpression: string
+  exitRules?: StrategyPaperValidationExitRules
+  initialCapital?: number
+  sizingMode?: string
+  // 如 `N in 5..30 step 5`
+  params: string[]
+  walkForward?: WalkForwardConfig
+}
+
+export type RuleParamSweepQuery = {
+  sourcePath: string
+  importRuleName: string
+  when: string
+  scopeWay?: string
+  scopeWindows?: number
+  stockAdjType?: string
+  indexTsCode: string
+  startDate: string
+  endDate: string
+  minSamplesPerRuleDay?: number
+  backtestPeriod?: number
+  board?: string
+  params: string[]
+  walkForward?: WalkForwardConfig
+}
+
+export async function runStrategyParamSweep(query: StrategyParamSweepQuery) {
+  return invoke<StrategyParamSweepData>('run_strategy_param_sweep', query)
+}
+
+export async function runRuleParamSweep(query: RuleParamSweepQuery) {
+  return invoke<RuleParamSweepData>('run_rule_param_sweep', query)
+}
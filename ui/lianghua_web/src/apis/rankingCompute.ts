@@ -56,6 +56,46 @@ export type RankingComputeRunResult = {
   timings: RankComputeTimingItem[]
   warnings: string[]
   status: RankingComputeStatus
+  runId: string | null
+}
+
+export type RankScoreRunItem = {
+  runId: string
+  strategyHash: string
+  strategyPath: string
+  adjType: string
+  startDate: string
+  endDate: string
+  createdAt: string
+}
+
+export type RankScoreRunStockDiff = {
+  tsCode: string
+  baseScore: number | null
+  targetScore: number | null
+  baseRank: number | null
+  targetRank: number | null
+  rankChange: number | null
+  gainedRules: string[]
+  lostRules: string[]
+}
+
+export type RankScoreRunRuleCountDiff = {
+  ruleName: string
+  baseTriggerCount: number
+  targetTriggerCount: number
+}
+
+export type RankScoreRunCompareResult = {
+  base: RankScoreRunItem
+  target: RankScoreRunItem
+  tradeDate: string
+  rangeStart: string
+  rangeEnd: string
+  stocks: RankScoreRunStockDiff[]
+  rules: RankScoreRunRuleCountDiff[]
+  addedRules: string[]
+  removedRules: string[]
 }
 
 export type ConvolutionRankComputeResult = {
@@ -120,12 +160,56 @@ export async function runRankingScoreCalculation(
   startDate: string,
   endDate: string,
   strategyPath?: string,
+  // 传入时另存评分版本, 只保留最近这么多个
+  archiveKeepLast?: number,
 ) {
   return invoke<RankingComputeRunResult>('run_ranking_score_calculation', {
     sourcePath,
     strategyPath,
     startDate,
     endDate,
+    archiveKeepLast,
+  })
+}
+
+export async function runRankingIncrementalScoreCalculation(
+  sourcePath: string,
+  startDate: string,
+  endDate: string,
+  strategyPath?: string,
+  // 传入时另存评分版本, 只保留最近这么多个
+  archiveKeepLast?: number,
+) {
+  return invoke<RankingComputeRunResult>('run_ranking_incremental_score_calculation', {
+    sourcePath,
+    strategyPath,
+    startDate,
+    endDate,
+    archiveKeepLast,
+  })
+}
+
+export async function listRankingScoreRuns(sourcePath: string) {
+  return invoke<RankScoreRunItem[]>('list_ranking_score_runs', { sourcePath })
+}
+
+export async function deleteRankingScoreRun(sourcePath: string, runId: string) {
+  return invoke<void>('delete_ranking_score_run', { sourcePath, runId })
+}
+
+export async function compareRankingScoreRuns(
+  sourcePath: string,
+  baseRunId: string,
+  targetRunId: string,
+  tradeDate?: string,
+  limit?: number,
+) {
+  return invoke<RankScoreRunCompareResult>('compare_ranking_score_runs', {
+    sourcePath,
+    baseRunId,
+    targetRunId,
+    tradeDate,
+    limit,
   })
 }
 
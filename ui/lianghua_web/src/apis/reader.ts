@@ -9,6 +9,9 @@ export type OverviewRow = {
   total_score?: number;
   tiebreak_j?: number;
   rank?: number | null;
+  risk_score?: number | null;
+  net_score?: number | null;
+  risk_rank?: number | null;
   ref_rank?: number | null;
   post_rank_return_pct?: number | null;
   name?: string;
@@ -26,6 +29,7 @@ export type OverviewQuery = {
   board?: string;
   totalMvMin?: number;
   totalMvMax?: number;
+  rankBy?: "total" | "risk";
 };
 
 export type OverviewPageQuery = {
@@ -37,6 +41,7 @@ export type OverviewPageQuery = {
   excludeStBoard?: boolean;
   totalMvMin?: number;
   totalMvMax?: number;
+  rankBy?: "total" | "risk";
 };
 
 export type OverviewPageData = {
@@ -116,6 +121,31 @@ export type SceneOverviewPageData = {
   resolved_rank_date?: string;
 };
 
+export type SceneTransitionRow = {
+  ts_code: string;
+  trade_date: string;
+  scene_name: string;
+  direction: string;
+  from_stage?: string | null;
+  to_stage: string;
+  prev_stage_days: number;
+  rule_names: string[];
+  name: string;
+  board: string;
+};
+
+export type SceneTransitionPageQuery = {
+  sourcePath: string;
+  tradeDate?: string;
+  sceneName?: string;
+  toStage?: string;
+};
+
+export type SceneTransitionPageData = {
+  rows: SceneTransitionRow[];
+  resolved_trade_date?: string | null;
+};
+
 export type IntradayMonitorRow = {
   rank_mode: string;
   ts_code: string;
@@ -280,6 +310,36 @@ export function getExpressionCapabilities() {
   return expressionCapabilitiesRequest;
 }
 
+export type ExpressionTraceValue = number | boolean | string | null;
+
+export type ExpressionTraceRow = {
+  statementIndex: number;
+  depth: number;
+  expression: string;
+  assignName?: string | null;
+  values: ExpressionTraceValue[];
+  error?: string | null;
+};
+
+export type ExpressionTraceData = {
+  tsCode: string;
+  tradeDates: string[];
+  rows: ExpressionTraceRow[];
+  error?: string | null;
+};
+
+export type ExpressionTraceQuery = {
+  sourcePath: string;
+  tsCode: string;
+  startDate: string;
+  endDate: string;
+  expression: string;
+};
+
+export async function traceStockExpression(query: ExpressionTraceQuery) {
+  return invoke<ExpressionTraceData>("trace_stock_expression", query);
+}
+
 export type StockLookupRow = {
   ts_code: string;
   name: string;
@@ -310,6 +370,10 @@ export async function sceneRankOverviewPage(query: SceneOverviewPageQuery) {
   return invoke<SceneOverviewPageData>("get_scene_rank_overview_page", query);
 }
 
+export async function sceneTransitionPage(query: SceneTransitionPageQuery) {
+  return invoke<SceneTransitionPageData>("get_scene_transition_page", query);
+}
+
 export async function intradayMonitorPage(query: IntradayMonitorPageQuery) {
   return invoke<IntradayMonitorPageData>("get_intraday_monitor_page", query);
 }
@@ -50,6 +50,8 @@ export type StrategyManageRuleItem = {
   points_by_hits?: number[] | null
   max_points?: number | null
   max_bonus_points?: number | null
+  source_file: string
+  override_files: string[]
 }
 
 export type StrategyManageRuleDraft = {
@@ -72,6 +74,7 @@ export type StrategyManageRuleDraft = {
 export type StrategyManagePageData = {
   scenes: StrategyManageSceneItem[]
   rules: StrategyManageRuleItem[]
+  editable: boolean
 }
 
 export type StrategyManageRefactorDraft = {
@@ -1,4 +1,5 @@
 import { invoke } from '@tauri-apps/api/core'
+import type { RobustnessConfig, RobustnessSummary } from './strategyTrigger'
 
 export type StrategyPaperValidationDefaultsData = {
   latest_trade_date?: string | null
@@ -10,6 +11,8 @@ export type StrategyPaperValidationDefaultsData = {
   slippage_pct: number
   max_position_count: number
   buy_selection_mode: string
+  initial_capital: number
+  sizing_mode: string
 }
 
 export type StrategyPaperValidationSummaryData = {
@@ -40,9 +43,57 @@ export type StrategyPaperValidationTradeRow = {
   close_return_pct?: number | null
   realized_return_pct?: number | null
   daily_holding_close_returns: StrategyPaperValidationDailyHoldingCloseReturn[]
+  cost: StrategyPaperValidationTradeCost
+  exit_reason?: StrategyPaperValidationExitReason | null
   status: string
 }
 
+export type StrategyPaperValidationExitReason =
+  | 'sell_expression'
+  | 'stop_loss'
+  | 'atr_stop'
+  | 'trailing_stop'
+  | 'take_profit'
+  | 'max_hold_days'
+  | 'rank_drop'
+
+export type StrategyPaperValidationExitRules = {
+  stop_loss_pct?: number | null
+  take_profit_pct?: number | null
+  atr_stop_multiple?: number | null
+  atr_period?: number | null
+  trailing_stop_pct?: number | null
+  max_hold_days?: number | null
+  max_rank?: number | null
+  intrabar_order?: 'stop_first' | 'target_first' | 'nearest_to_open'
+}
+
+export type TradeCostBreakdown = {
+  amount: number
+  commission: number
+  stamp_duty: number
+  transfer_fee: number
+  total: number
+}
+
+export type StrategyPaperValidationTradeCost = {
+  shares: number
+  buy_fee: TradeCostBreakdown
+  sell_fee?: TradeCostBreakdown | null
+  total_fee: number
+  total_fee_pct?: number | null
+}
+
+export type TradeCostConfig = {
+  commission_rate: number
+  min_commission: number
+  stamp_duty_rate: number
+  transfer_fee_rate: number
+  max_amount_participation_pct?: number | null
+  fill_price: 'basis' | 'vwap'
+  reference_trade_amount: number
+}
+
 export type StrategyPaperValidationDailyHoldingCloseReturn = {
   trade_date: string
   close_return_pct: number
@@ -69,11 +120,67 @@ export type StrategyPaperValidationData = {
   buy_selection_mode: string
   buy_expression: string
   sell_expression: string
+  trade_cost: TradeCostConfig
+  exit_rules: StrategyPaperValidationExitRules
   summary: StrategyPaperValidationSummaryData
+  robustness?: RobustnessSummary | null
   trades: StrategyPaperValidationTradeRow[]
   index_daily_returns: StrategyPaperValidationIndexDailyReturn[]
 }
 
+export type PortfolioFill = {
+  trade_date: string
+  ts_code: string
+  side: 'buy' | 'sell' | string
+  shares: number
+  price: number
+  amount: number
+  fee: TradeCostBreakdown
+}
+
+export type PortfolioNavPoint = {
+  trade_date: string
+  cash: number
+  market_value: number
+  nav: number
+  nav_ratio: number
+  benchmark_nav_ratio?: number | null
+  drawdown_pct: number
+  exposure: number
+  turnover: number
+  position_count: number
+}
+
+export type PortfolioMetrics = {
+  trade_days: number
+  final_nav: number
+  total_return_pct?: number | null
+  annualized_return_pct?: number | null
+  max_drawdown_pct?: number | null
+  sharpe?: number | null
+  sortino?: number | null
+  calmar?: number | null
+  avg_daily_turnover?: number | null
+  total_turnover?: number | null
+  avg_exposure?: number | null
+  benchmark_return_pct?: number | null
+  excess_return_pct?: number | null
+  total_fee: number
+}
+
+export type PortfolioBacktestResult = {
+  initial_capital: number
+  sizing_mode: string
+  metrics: PortfolioMetrics
+  nav_points: PortfolioNavPoint[]
+  fills: PortfolioFill[]
+}
+
+export type StrategyPortfolioBacktestData = {
+  validation: StrategyPaperValidationData
+  portfolio: PortfolioBacktestResult
+}
+
 export type StrategyPaperValidationTemplateValidationData = {
   normalized_buy_expression?: string
   normalized_sell_expression?: string
@@ -97,6 +204,14 @@ export type StrategyPaperValidationQuery = {
   buySelectionMode?: string
   buyExpression: string
   sellExpression: string
+  exitRules?: StrategyPaperValidationExitRules
+  // 随机选股时换种子重跑，否则对已平仓交易做自助重抽样
+  robustness?: RobustnessConfig
+}
+
+export type StrategyPortfolioBacktestQuery = StrategyPaperValidationQuery & {
+  initialCapital?: number
+  sizingMode?: string
 }
 
 export async function getStrategyPaperValidationDefaults(sourcePath: string) {
@@ -109,6 +224,10 @@ export async function runStrategyPaperValidation(query: StrategyPaperValidationQ
   return invoke<StrategyPaperValidationData>('run_strategy_paper_validation', query)
 }
 
+export async function runStrategyPortfolioBacktest(query: StrategyPortfolioBacktestQuery) {
+  return invoke<StrategyPortfolioBacktestData>('run_strategy_portfolio_backtest', query)
+}
+
 export async function validateStrategyPaperValidationTemplateExpressions(
   buyExpression: string,
   sellExpression: string,
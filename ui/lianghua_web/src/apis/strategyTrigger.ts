@@ -142,6 +142,34 @@ export type SceneLayerPoint = {
   ic?: number | null
 }
 
+export type RobustnessConfig = {
+  iterations?: number
+  confidence?: number
+  // 块自助法块长，不填时取 max(持有周期, T^(1/3))
+  block_len?: number | null
+  seed?: number
+}
+
+export type RobustnessInterval = {
+  estimate?: number | null
+  lower?: number | null
+  upper?: number | null
+}
+
+export type RobustnessSummary = {
+  method: 'block_bootstrap' | 'trade_bootstrap' | 'random_entry_order' | string
+  iterations: number
+  confidence: number
+  block_len?: number | null
+  sample_count: number
+  win_rate: RobustnessInterval
+  avg_excess_return: RobustnessInterval
+  // 场景分层按多空价差重抽样时的均值区间，此时 avg_excess_return 为空
+  avg_spread?: RobustnessInterval | null
+  max_drawdown_pct: RobustnessInterval
+  warnings: string[]
+}
+
 export type SceneLayerSceneSummary = {
   scene_name: string
   point_count: number
@@ -150,6 +178,7 @@ export type SceneLayerSceneSummary = {
   ic_std?: number | null
   icir?: number | null
   ic_t_value?: number | null
+  robustness?: RobustnessSummary | null
 }
 
 export type SceneLayerBacktestData = {
@@ -224,6 +253,7 @@ export type RuleLayerRuleSummary = {
   icir?: number | null
   ic_t_value?: number | null
   decay_validations?: RuleDecayValidation[]
+  robustness?: RobustnessSummary | null
 }
 
 export type RuleLayerBacktestData = {
@@ -336,6 +366,7 @@ export type RankTopKSummary = {
   daily_std?: number | null
   hac_t_value?: number | null
   hac_lag: number
+  robustness?: RobustnessSummary | null
 }
 
 export type RankTopKPeriodSummary = {
@@ -670,6 +701,7 @@ export type SceneLayerBacktestQuery = {
   excludeStBoard?: boolean
   totalMvMin?: number
   totalMvMax?: number
+  robustness?: RobustnessConfig
 }
 
 export type RuleLayerBacktestQuery = {
@@ -689,6 +721,7 @@ export type RuleLayerBacktestQuery = {
   excludeStBoard?: boolean
   totalMvMin?: number
   totalMvMax?: number
+  robustness?: RobustnessConfig
 }
 
 export type RankLayerBacktestQuery = {
@@ -707,6 +740,19 @@ export type RankLayerBacktestQuery = {
   layerMethod?: RankLayerMethod
   board?: string
   excludeStBoard?: boolean
+  robustness?: RobustnessConfig
+}
+
+export type ScoreRunRankLayerCompareQuery = RankLayerBacktestQuery & {
+  baseRunId: string
+  targetRunId: string
+}
+
+export type ScoreRunRankLayerCompareData = {
+  base_run_id: string
+  target_run_id: string
+  base: RankLayerBacktestData
+  target: RankLayerBacktestData
 }
 
 export type RuleExpressionValidationQuery = {
@@ -773,6 +819,10 @@ export async function runRankLayerBacktest(query: RankLayerBacktestQuery) {
   return invoke<RankLayerBacktestData>('run_rank_layer_backtest', query)
 }
 
+export async function runScoreRunRankLayerCompare(query: ScoreRunRankLayerCompareQuery) {
+  return invoke<ScoreRunRankLayerCompareData>('run_score_run_rank_layer_compare', query)
+}
+
 export async function runTransientRankLayerBacktest(query: RankLayerBacktestQuery) {
   return invoke<RankLayerBacktestData>('run_transient_rank_layer_backtest', query)
 }
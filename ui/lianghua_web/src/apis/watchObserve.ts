@@ -19,6 +19,7 @@ export type WatchObserveRow = {
   postWatchReturnPct: number | null
   todayRank: number | null
   sceneMarker: string | null
+  riskMarker: string | null
   tag: string
   concept: string
   markedDate: string | null
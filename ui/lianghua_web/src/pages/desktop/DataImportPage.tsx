@@ -38,7 +38,7 @@ const MANAGED_SOURCE_FILE_GROUPS = [
     id: 'base',
     label: '基础数据',
     description: '行情、证券基础信息、交易日历与概念映射。',
-    fileIds: ['source-db', 'stock-list', 'trade-calendar', 'ths-concepts'],
+    fileIds: ['source-db', 'stock-list', 'trade-calendar', 'ths-concepts', 'index-constituents'],
   },
   {
     id: 'derived',
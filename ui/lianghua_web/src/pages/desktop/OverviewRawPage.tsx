@@ -50,6 +50,8 @@ const FIXED_VISIBLE_COLUMNS = [
   "concept",
 ] as const;
 
+type RankBy = "total" | "risk";
+
 type ColumnConfig = {
   label?: string;
   order?: number;
@@ -75,6 +77,7 @@ type PersistedOverviewFilterState = {
   boardFilter: (typeof STOCK_PICK_BOARD_OPTIONS)[number];
   totalMvMinInput: string;
   totalMvMaxInput: string;
+  rankBy: RankBy;
   sortKey: string | null;
   sortDirection: SortDirection;
 };
@@ -100,6 +103,9 @@ const COLUMN_CONFIG: Record<string, ColumnConfig> = {
   board: { label: "板块", order: 50, width: 108 },
   board_category: { label: "板块分类" },
   total_score: { label: "总分", order: 60, width: 84 },
+  risk_score: { label: "风险分", order: 61, width: 84 },
+  net_score: { label: "净分", order: 62, width: 84 },
+  risk_rank: { label: "风险排名", order: 63, width: 96 },
   post_rank_return_pct: { label: "至今涨幅(%)", order: 65, width: 118 },
   ref_rank: { label: "参考日排名", order: 66, width: 110 },
   tiebreak_j: { label: "同分排序J", order: 70, width: 96 },
@@ -180,7 +186,7 @@ function formatCell(
     if (!Number.isFinite(value)) {
       return "--";
     }
-    if (key === "rank" || key === "ref_rank") {
+    if (key === "rank" || key === "ref_rank" || key === "risk_rank") {
       return String(Math.round(value));
     }
     if (key === "post_rank_return_pct") {
@@ -271,6 +277,7 @@ export default function OverviewRawPage() {
         typeof merged.totalMvMaxInput === "string"
           ? merged.totalMvMaxInput
           : "",
+      rankBy: merged.rankBy === "risk" ? "risk" : "total",
       rows: Array.isArray(merged.rows) ? merged.rows : [],
       dateOptions: Array.isArray(merged.dateOptions) ? merged.dateOptions : [],
       lastConfig:
@@ -305,6 +312,9 @@ export default function OverviewRawPage() {
   const [totalMvMaxInput, setTotalMvMaxInput] = useState(
     () => persistedState?.totalMvMaxInput ?? "",
   );
+  const [rankBy, setRankBy] = useState<RankBy>(
+    () => persistedState?.rankBy ?? "total",
+  );
 
   const [rows, setRows] = useState<OverviewRow[]>(
     () => persistedState?.rows ?? [],
@@ -402,6 +412,7 @@ export default function OverviewRawPage() {
         boardFilter,
         totalMvMinInput,
         totalMvMaxInput,
+        rankBy,
         sortKey,
         sortDirection,
       } satisfies PersistedOverviewFilterState,
@@ -414,6 +425,7 @@ export default function OverviewRawPage() {
     boardFilter,
     totalMvMinInput,
     totalMvMaxInput,
+    rankBy,
     sortKey,
     sortDirection,
   ]);
@@ -541,6 +553,7 @@ export default function OverviewRawPage() {
       excludeStBoard: excludeStBoard || undefined,
       totalMvMin,
       totalMvMax,
+      rankBy,
     };
 
     setLoading(true);
@@ -597,6 +610,7 @@ export default function OverviewRawPage() {
     boardFilter,
     excludeStBoard,
     limitInput,
+    rankBy,
     rankDateInput,
     refDateInput,
     sourcePathTrimmed,
@@ -660,6 +674,17 @@ export default function OverviewRawPage() {
             </select>
           </label>
 
+          <label className="overview-field">
+            <span>榜单</span>
+            <select
+              value={rankBy}
+              onChange={(e) => setRankBy(e.target.value as RankBy)}
+            >
+              <option value="total">总分榜</option>
+              <option value="risk">风险榜</option>
+            </select>
+          </label>
+
           <label className="overview-field">
             <span>限制行数</span>
             <input
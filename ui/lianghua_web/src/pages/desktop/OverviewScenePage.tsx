@@ -3,8 +3,10 @@ import { ensureManagedSourcePath } from '../../apis/managedSource'
 import {
   listSceneRankTradeDates,
   sceneRankOverviewPage,
+  sceneTransitionPage,
   type SceneOverviewPageQuery,
   type SceneOverviewRow,
+  type SceneTransitionRow,
 } from '../../apis/reader'
 import {
   filterBoardItems,
@@ -221,6 +223,7 @@ export default function OverviewScenePage() {
   const [lastConfig, setLastConfig] = useState<AppliedConfig | null>(
     () => persistedState?.lastConfig ?? null,
   )
+  const [transitions, setTransitions] = useState<SceneTransitionRow[]>([])
   const [loading, setLoading] = useState(false)
   const [dateOptionsLoading, setDateOptionsLoading] = useState(false)
   const [error, setError] = useState('')
@@ -265,6 +268,11 @@ export default function OverviewScenePage() {
     [rows, selectedSceneName],
   )
 
+  const selectedTransitions = useMemo(
+    () => transitions.filter((row) => row.scene_name === selectedSceneName),
+    [transitions, selectedSceneName],
+  )
+
   const sortDefinitions = useMemo(
     () =>
       Object.fromEntries(
@@ -472,6 +480,12 @@ export default function OverviewScenePage() {
       }
 
       const nextSceneNames = [...new Set(nextRows.map((row) => row.scene_name).filter(Boolean))]
+      // 旧结果库没有阶段变化表时只是不显示这一块
+      const transitionData = await sceneTransitionPage({
+        sourcePath: sourcePathTrimmed,
+        tradeDate: resolvedRankDate ?? undefined,
+      }).catch(() => null)
+      setTransitions(transitionData?.rows ?? [])
       setRows(nextRows)
       setSelectedSceneName((current) =>
         nextSceneNames.includes(current) ? current : (nextSceneNames[0] ?? ''),
@@ -488,6 +502,7 @@ export default function OverviewScenePage() {
     } catch (readError) {
       setError(`读取失败: ${String(readError)}`)
       setRows([])
+      setTransitions([])
       setSelectedSceneName('')
     } finally {
       setLoading(false)
@@ -669,6 +684,49 @@ export default function OverviewScenePage() {
         )}
       </section>
 
+      <section className="overview-card">
+        <h3 className="overview-subtitle">当日阶段变化</h3>
+        {selectedTransitions.length === 0 ? (
+          <div className="overview-empty">当前场景当日没有阶段变化</div>
+        ) : (
+          <div className="overview-table-wrap">
+            <table className="overview-table">
+              <thead>
+                <tr>
+                  <th>代码</th>
+                  <th>名称</th>
+                  <th>阶段变化</th>
+                  <th>前阶段天数</th>
+                  <th>推动规则</th>
+                </tr>
+              </thead>
+              <tbody>
+                {selectedTransitions.map((row) => (
+                  <tr key={`${row.scene_name}-${row.ts_code}-${row.trade_date}`}>
+                    <td>{row.ts_code}</td>
+                    <td>
+                      <DetailsLink
+                        className="overview-stock-link"
+                        tsCode={row.ts_code}
+                        tradeDate={row.trade_date}
+                        sourcePath={sourcePathTrimmed}
+                        title={`查看 ${row.name || row.ts_code} 详情`}
+                        navigationItems={detailNavigationItems}
+                      >
+                        {row.name || '--'}
+                      </DetailsLink>
+                    </td>
+                    <td>{`${row.from_stage ?? '无'} → ${row.to_stage}`}</td>
+                    <td>{row.prev_stage_days}</td>
+                    <td>{row.rule_names.length > 0 ? row.rule_names.join('、') : '--'}</td>
+                  </tr>
+                ))}
+              </tbody>
+            </table>
+          </div>
+        )}
+      </section>
+
       <section className="overview-card">
         <h3 className="overview-subtitle">结果表格</h3>
         {sortedRows.length === 0 ? (
@@ -82,6 +82,13 @@ type DistScoreSummary = {
   pointsMax: number
 }
 
+function formatRuleSourceFile(rule: StrategyManageRuleItem) {
+  if (rule.override_files.length === 0) {
+    return rule.source_file
+  }
+  return `${rule.source_file} ← ${rule.override_files.join(' ← ')}`
+}
+
 function buildDistScoreSummary(items?: StrategyManageDistPoint[] | null): DistScoreSummary | null {
   if (!items || items.length === 0) {
     return null
@@ -753,6 +760,9 @@ export default function StrategyManagePage({ view = 'rules' }: { view?: Strategy
       setSourcePath(resolvedSourcePath)
       setScenes(data.scenes ?? [])
       setRules(data.rules ?? [])
+      if (!data.editable) {
+        setNotice('当前策略使用了 extends/include，这里展示展开后的规则，修改请直接编辑被引用的策略文件。')
+      }
       setSelectedSceneName((current) =>
         data.scenes.some((item) => item.name === current) ? current : '',
       )
@@ -1779,7 +1789,7 @@ export default function StrategyManagePage({ view = 'rules' }: { view?: Strategy
                     <div>
                       <div className="strategy-manage-rule-card-name">{rule.name}</div>
                       <div className="strategy-manage-rule-card-source">
-                        {rule.scene_name} · #{rule.index + 1} · {isCombination ? '组合策略' : '单语句'}
+                        {rule.scene_name} · #{rule.index + 1} · {isCombination ? '组合策略' : '单语句'} · {formatRuleSourceFile(rule)}
                       </div>
                     </div>
                     <div className="strategy-manage-rule-card-actions">
@@ -13,6 +13,7 @@ import {
   getStrategyPaperValidationDefaults,
   runStrategyPaperValidation,
   type StrategyPaperValidationData,
+  type StrategyPaperValidationExitRules,
   type StrategyPaperValidationTradeRow,
 } from '../../apis/strategyPaperValidation'
 import {
@@ -76,6 +77,22 @@ const BUY_SELECTION_MODE_OPTIONS = [
   { value: 'rank_top', label: '排名靠前优先' },
 ] as const
 
+const INTRABAR_ORDER_OPTIONS = [
+  { value: 'stop_first', label: '止损优先' },
+  { value: 'target_first', label: '止盈优先' },
+  { value: 'nearest_to_open', label: '离开盘价近者优先' },
+] as const
+
+const EXIT_REASON_LABELS: Record<string, string> = {
+  sell_expression: '卖点方程',
+  stop_loss: '止损',
+  atr_stop: 'ATR止损',
+  trailing_stop: '移动止损',
+  take_profit: '止盈',
+  max_hold_days: '持有到期',
+  rank_drop: '排名跌出',
+}
+
 type TradeStatusFilter = 'all' | 'closed' | 'open'
 type TradeDetailModalStatus = Exclude<TradeStatusFilter, 'all'>
 type TradePageSize = (typeof TRADE_PAGE_SIZE_OPTIONS)[number]['value']
@@ -163,6 +180,21 @@ type TradeSortKey =
   | 'high_return_pct'
   | 'close_return_pct'
   | 'realized_return_pct'
+  | 'total_fee'
+  | 'exit_reason'
+
+function formatTradeCostTitle(row: StrategyPaperValidationTradeRow) {
+  const cost = row.cost
+  if (!cost) return undefined
+  const sell = cost.sell_fee
+  return [
+    `股数 ${cost.shares}`,
+    `买入佣金 ${cost.buy_fee.commission.toFixed(2)} 过户费 ${cost.buy_fee.transfer_fee.toFixed(2)}`,
+    sell
+      ? `卖出佣金 ${sell.commission.toFixed(2)} 印花税 ${sell.stamp_duty.toFixed(2)} 过户费 ${sell.transfer_fee.toFixed(2)}`
+      : '未卖出',
+  ].join('\n')
+}
 
 function normalizeTemplate(input: unknown): StrategyPaperValidationTemplate | null {
   if (!input || typeof input !== 'object') return null
@@ -241,6 +273,16 @@ function statusLabel(row: StrategyPaperValidationTradeRow) {
   return row.status === 'closed' ? '已平仓' : '未平仓'
 }
 
+function exitReasonLabel(row: StrategyPaperValidationTradeRow) {
+  if (!row.exit_reason) return '--'
+  return EXIT_REASON_LABELS[row.exit_reason] ?? row.exit_reason
+}
+
+function parseOptionalPositive(value: string) {
+  const parsed = Number(value)
+  return value.trim() !== '' && Number.isFinite(parsed) && parsed > 0 ? parsed : undefined
+}
+
 function getTradeHoldingEndDate(row: StrategyPaperValidationTradeRow) {
   const sellDate = row.sell_date?.trim() ?? ''
   if (isCompactTradeDate(sellDate)) {
@@ -1143,6 +1185,8 @@ const StrategyPaperValidationTradesTable = memo(
           high_return_pct: { value: (row) => row.high_return_pct },
           close_return_pct: { value: (row) => row.close_return_pct },
           realized_return_pct: { value: (row) => row.realized_return_pct },
+          total_fee: { value: (row) => row.cost?.total_fee },
+          exit_reason: { value: exitReasonLabel },
         }) satisfies Partial<Record<TradeSortKey, SortDefinition<StrategyPaperValidationTradeRow>>>,
       [],
     )
@@ -1429,12 +1473,14 @@ const StrategyPaperValidationTradesTable = memo(
                 {renderSortableHeader('buy_cost_price', '买入成本')}
                 {renderSortableHeader('sell_price', '卖出价')}
                 {renderSortableHeader('realized_return_pct', '记录收益')}
+                {renderSortableHeader('total_fee', '费用')}
+                {renderSortableHeader('exit_reason', '离场原因')}
               </tr>
             </thead>
             <tbody>
               {visibleTrades.length === 0 ? (
                 <tr>
-                  <td colSpan={10}>没有匹配当前筛选条件的交易。</td>
+                  <td colSpan={12}>没有匹配当前筛选条件的交易。</td>
                 </tr>
               ) : (
                 visibleTrades.map((row, index) => {
@@ -1464,6 +1510,8 @@ const StrategyPaperValidationTradesTable = memo(
                       <td>{formatNumber(row.buy_cost_price)}</td>
                       <td>{formatNumber(row.sell_price)}</td>
                       <td>{renderReturnValue(row.realized_return_pct)}</td>
+                      <td title={formatTradeCostTitle(row)}>{formatNumber(row.cost?.total_fee)}</td>
+                      <td>{exitReasonLabel(row)}</td>
                     </tr>
                   )
                 })
@@ -1736,6 +1784,14 @@ export default function StrategyPaperValidationPage() {
   const [slippagePct, setSlippagePct] = useState('0')
   const [maxPositionCount, setMaxPositionCount] = useState('5')
   const [buySelectionMode, setBuySelectionMode] = useState('random')
+  const [stopLossPct, setStopLossPct] = useState('')
+  const [takeProfitPct, setTakeProfitPct] = useState('')
+  const [atrStopMultiple, setAtrStopMultiple] = useState('')
+  const [trailingStopPct, setTrailingStopPct] = useState('')
+  const [maxHoldDays, setMaxHoldDays] = useState('')
+  const [exitMaxRank, setExitMaxRank] = useState('')
+  const [intrabarOrder, setIntrabarOrder] =
+    useState<NonNullable<StrategyPaperValidationExitRules['intrabar_order']>>('stop_first')
   const [testStockInput, setTestStockInput] = useState('')
   const [stockLookupFocused, setStockLookupFocused] = useState(false)
   const [buyExpression, setBuyExpression] = useState('RANK <= 100')
@@ -1914,6 +1970,17 @@ export default function StrategyPaperValidationPage() {
       return
     }
     const parsedMaxPositionCount = Math.max(0, Math.floor(Number(maxPositionCount) || 0))
+    const parsedMaxHoldDays = parseOptionalPositive(maxHoldDays)
+    const parsedExitMaxRank = parseOptionalPositive(exitMaxRank)
+    const exitRules: StrategyPaperValidationExitRules = {
+      stop_loss_pct: parseOptionalPositive(stopLossPct),
+      take_profit_pct: parseOptionalPositive(takeProfitPct),
+      atr_stop_multiple: parseOptionalPositive(atrStopMultiple),
+      trailing_stop_pct: parseOptionalPositive(trailingStopPct),
+      max_hold_days: parsedMaxHoldDays === undefined ? undefined : Math.floor(parsedMaxHoldDays),
+      max_rank: parsedExitMaxRank === undefined ? undefined : Math.floor(parsedExitMaxRank),
+      intrabar_order: intrabarOrder,
+    }
 
     setResult(null)
     setLoading(true)
@@ -1934,6 +2001,7 @@ export default function StrategyPaperValidationPage() {
         buySelectionMode,
         buyExpression,
         sellExpression,
+        exitRules,
       })
       setResult(data)
       setStartDateInput(compactDateToInput(data.start_date))
@@ -2208,6 +2276,43 @@ export default function StrategyPaperValidationPage() {
               ))}
             </select>
           </label>
+          <label className="strategy-paper-validation-field">
+            <span>止损(%)</span>
+            <input type="number" min="0" step="0.1" value={stopLossPct} placeholder="不启用" onChange={(event) => setStopLossPct(event.target.value)} />
+          </label>
+          <label className="strategy-paper-validation-field">
+            <span>止盈(%)</span>
+            <input type="number" min="0" step="0.1" value={takeProfitPct} placeholder="不启用" onChange={(event) => setTakeProfitPct(event.target.value)} />
+          </label>
+          <label className="strategy-paper-validation-field">
+            <span>ATR止损倍数</span>
+            <input type="number" min="0" step="0.1" value={atrStopMultiple} placeholder="不启用" onChange={(event) => setAtrStopMultiple(event.target.value)} />
+          </label>
+          <label className="strategy-paper-validation-field">
+            <span>移动止损(%)</span>
+            <input type="number" min="0" step="0.1" value={trailingStopPct} placeholder="不启用" onChange={(event) => setTrailingStopPct(event.target.value)} />
+          </label>
+          <label className="strategy-paper-validation-field">
+            <span>最长持有天数</span>
+            <input type="number" min="1" step="1" value={maxHoldDays} placeholder="不启用" onChange={(event) => setMaxHoldDays(event.target.value)} />
+          </label>
+          <label className="strategy-paper-validation-field">
+            <span>排名跌出前N卖出</span>
+            <input type="number" min="1" step="1" value={exitMaxRank} placeholder="不启用" onChange={(event) => setExitMaxRank(event.target.value)} />
+          </label>
+          <label className="strategy-paper-validation-field">
+            <span>同K线止损止盈顺序</span>
+            <select
+              value={intrabarOrder}
+              onChange={(event) => setIntrabarOrder(event.target.value as typeof intrabarOrder)}
+            >
+              {INTRABAR_ORDER_OPTIONS.map((item) => (
+                <option key={item.value} value={item.value}>
+                  {item.label}
+                </option>
+              ))}
+            </select>
+          </label>
           <div className="strategy-paper-validation-field strategy-paper-validation-template-action-field">
             <span>模板管理</span>
             <button
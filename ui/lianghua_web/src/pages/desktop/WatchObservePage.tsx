@@ -746,6 +746,7 @@ export default function WatchObservePage() {
                   <col style={{ width: "116px" }} />
                   <col style={{ width: "116px" }} />
                   <col style={{ width: "170px" }} />
+                  <col style={{ width: "150px" }} />
                   <col style={{ width: "116px" }} />
                   <col />
                 </colgroup>
@@ -843,6 +844,7 @@ export default function WatchObservePage() {
                       />
                     </th>
                     <th>最好场景排名</th>
+                    <th>空头预警</th>
                     <th>标签</th>
                     <th>概念</th>
                   </tr>
@@ -862,7 +864,7 @@ export default function WatchObservePage() {
                       <Fragment key={row.tsCode}>
                         {isGroupStart ? (
                           <tr className="watch-observe-date-group">
-                            <td colSpan={isDeleteMode ? 14 : 13}>
+                            <td colSpan={isDeleteMode ? 15 : 14}>
                               <span>自选日期</span>
                               <strong>{watchDate}</strong>
                               <span>{watchDateCounts.get(watchDate) ?? 0} 只</span>
@@ -1011,6 +1013,12 @@ export default function WatchObservePage() {
                         <td title={row.sceneMarker ?? "--"}>
                           {row.sceneMarker ?? "--"}
                         </td>
+                        <td
+                          className={row.riskMarker ? "watch-observe-value-down" : undefined}
+                          title={row.riskMarker ?? "--"}
+                        >
+                          {row.riskMarker ?? "--"}
+                        </td>
                         <td title={row.tag || "添加标签"}>
                           {isDeleteMode ? (
                             row.tag || "--"
@@ -82,6 +82,26 @@ const FUNCTIONS = [
   'LTOPCOUNT',
   'LRANK',
   'GET',
+  'SLOPE',
+  'FORCAST',
+  'WMA',
+  'DMA',
+  'AVEDEV',
+  'VAR',
+  'CORR',
+  'COVAR',
+  'FILTER',
+  'BARSCOUNT',
+  'BARSSINCE',
+  'HHVBARS',
+  'LLVBARS',
+  'ZIG',
+  'PEAK',
+  'TROUGH',
+  'BACKSET',
+  'VALUEWHEN',
+  'SAR',
+  'PERIOD',
 ]
 
 type EditorMode = 'form' | 'source'
@@ -55,6 +55,27 @@ const SYNTAX_GUIDE_FUNCTIONS: SyntaxGuideFunction[] = [
   { name: 'GTOPCOUNT', signature: 'GTOPCOUNT(x, cond, win, topn)', returns: '数值序列', description: '最近 win 根按 x 从大到小取前 topn，统计其中 cond 成立的个数。', example: 'x=[1,5,3], cond=[真,假,真], win=3, topn=2 -> [空,空,1]' },
   { name: 'LTOPCOUNT', signature: 'LTOPCOUNT(x, cond, win, topn)', returns: '数值序列', description: '最近 win 根按 x 从小到大取前 topn，统计其中 cond 成立的个数。', example: 'x=[1,5,3], cond=[真,假,真], win=3, topn=2 -> [空,空,2]' },
   { name: 'GET', signature: 'GET(cond, x, n)', returns: '数值序列', description: '向前回看最近 n 根，取最后一次 cond 成立时对应的 x；不包含当前这根。', example: '可写 GET(CROSS(C, MA(C, 5)), C, 20) 取最近一次上穿时的收盘价' },
+  { name: 'SLOPE', signature: 'SLOPE(x, n)', returns: '数值序列', description: '最近 n 根的线性回归斜率。', example: 'x=[1,2,4], n=3 -> [空,空,1.5]' },
+  { name: 'FORCAST', signature: 'FORCAST(x, n)', returns: '数值序列', description: '最近 n 根线性回归在当前这根上的预测值。', example: 'x=[1,2,4], n=3 -> [空,空,3.83]' },
+  { name: 'WMA', signature: 'WMA(x, n)', returns: '数值序列', description: '加权移动平均，越近的权重越大，权重依次为 1..n。', example: 'x=[1,2,4], n=3 -> [空,空,2.83]' },
+  { name: 'DMA', signature: 'DMA(x, a)', returns: '数值序列', description: '动态移动平均 Y=a*x+(1-a)*Y\'，a 可以是序列。', example: 'x=[1,2,4], a=0.5 -> [1,1.5,2.75]' },
+  { name: 'AVEDEV', signature: 'AVEDEV(x, n)', returns: '数值序列', description: '最近 n 根的平均绝对偏差。', example: 'x=[1,2,4], n=3 -> [空,空,1.11]' },
+  { name: 'VAR', signature: 'VAR(x, n)', returns: '数值序列', description: '最近 n 根的样本方差（除以 n-1），与 STD 的总体口径不同。', example: 'x=[1,2,4], n=3 -> [空,空,2.33]' },
+  { name: 'CORR', signature: 'CORR(x, y, n)', returns: '数值序列', description: '最近 n 根 x 与 y 的相关系数；任一序列不变时为空。', example: 'x=[1,2,3], y=[3,2,1], n=3 -> [空,空,-1]' },
+  { name: 'COVAR', signature: 'COVAR(x, y, n)', returns: '数值序列', description: '最近 n 根 x 与 y 的协方差（总体口径）。', example: 'x=[1,2,3], y=[2,4,6], n=3 -> [空,空,1.33]' },
+  { name: 'FILTER', signature: 'FILTER(cond, n)', returns: '布尔序列', description: 'cond 成立后，其后 n 根内的信号被过滤。', example: 'cond=[真,真,真,假,真], n=1 -> [真,假,真,假,真]' },
+  { name: 'BARSCOUNT', signature: 'BARSCOUNT(x)', returns: '数值序列', description: '从第一个有效值开始的周期数，第一根为 1。', example: 'x=[空,3,4] -> [空,1,2]' },
+  { name: 'BARSSINCE', signature: 'BARSSINCE(cond)', returns: '数值序列', description: '距离第一次 cond 成立已经过去几根；首次命中前为空。', example: 'cond=[假,真,假] -> [空,0,1]' },
+  { name: 'HHVBARS', signature: 'HHVBARS(x, n)', returns: '数值序列', description: '最近 n 根最高值到当前的距离，n 为 0 表示全部历史；并列取最近一根。', example: 'x=[3,5,2], n=3 -> [空,空,1]' },
+  { name: 'LLVBARS', signature: 'LLVBARS(x, n)', returns: '数值序列', description: '最近 n 根最低值到当前的距离，n 为 0 表示全部历史；并列取最近一根。', example: 'x=[3,5,2], n=0 -> [0,1,0]' },
+  { name: 'ZIG', signature: 'ZIG(k, n)', returns: '数值序列', description: '转向幅度 n% 的之字线；k 为 0~3 依次取开高低收，也可直接传序列。使用了未来数据，只适合画图。', example: 'ZIG(3, 10)' },
+  { name: 'PEAK', signature: 'PEAK(k, n, m)', returns: '数值序列', description: 'ZIG(k, n) 截至当前的前 m 个波峰值；同样使用了未来数据。', example: 'PEAK(3, 10, 1)' },
+  { name: 'TROUGH', signature: 'TROUGH(k, n, m)', returns: '数值序列', description: 'ZIG(k, n) 截至当前的前 m 个波谷值；同样使用了未来数据。', example: 'TROUGH(3, 10, 1)' },
+  { name: 'BACKSET', signature: 'BACKSET(cond, n)', returns: '布尔序列', description: 'cond 成立时，把当前及之前共 n 根置为成立；使用了未来数据。', example: 'cond=[假,假,真], n=2 -> [假,真,真]' },
+  { name: 'VALUEWHEN', signature: 'VALUEWHEN(cond, x)', returns: '数值序列', description: 'cond 成立时取 x 当前值，否则沿用上一次的值；首次成立前为空。', example: 'cond=[假,真,假], x=[1,2,3] -> [空,2,2]' },
+  { name: 'SAR', signature: 'SAR(n, s, m)', returns: '数值序列', description: '抛物线转向，用 H/L 计算；前 n 根确定初始方向，步长 s%，极限 m%。', example: 'SAR(10, 2, 20)' },
+  { name: 'PERIOD', signature: 'PERIOD("W"|"M", x)，简写 W.x / M.x', returns: '数值序列/布尔序列', description: '把日线合成周线(W)或月线(M)后计算 x，再对齐回日线：每根日线取上一个已走完周期的值，不含当前未走完的周期，避免未来数据。开盘取首日、高低取极值、量额换手累加，其余字段取周期末值。', example: '可写 W.CROSS(MA(C, 5), MA(C, 10)) AND C > REF(HHV(H, 20), 1) 表示周线金叉且日线突破' },
+  { name: 'RULE', signature: 'RULE("规则名")', returns: '布尔序列', description: '引用另一条规则按其 scope 判定后的触发序列，只能在评分策略里使用；[[define]] 里写的 name = expr 共享序列可直接用名字引用，相互引用不能成环。', example: '[[define]] name = "TREND_UP", expr = "C > MA(C, 20)"；规则里写 TREND_UP AND NOT RULE("放量滞涨")' },
 ]
 
 const SYNTAX_GUIDE_DYNAMIC_FUNCTIONS: SyntaxGuideDynamicFunction[] = [
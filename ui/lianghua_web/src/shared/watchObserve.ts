@@ -14,6 +14,7 @@ export type WatchObserveRow = {
   postWatchReturnPct: number | null
   todayRank: number | null
   sceneMarker: string | null
+  riskMarker: string | null
   tag: string
   concept: string
   markedDate: string | null
@@ -35,6 +36,7 @@ function mergeWatchObserveRow(primary: WatchObserveRow, secondary: WatchObserveR
     postWatchReturnPct: primary.postWatchReturnPct ?? secondary.postWatchReturnPct,
     todayRank: primary.todayRank ?? secondary.todayRank,
     sceneMarker: primary.sceneMarker ?? secondary.sceneMarker,
+    riskMarker: primary.riskMarker ?? secondary.riskMarker,
     tag: primary.tag || secondary.tag,
     concept: primary.concept || secondary.concept,
     markedDate: primary.markedDate ?? secondary.markedDate,
@@ -78,6 +80,7 @@ function normalizeRowInput(
     todayRank:
       typeof input.todayRank === 'number' && Number.isFinite(input.todayRank) ? input.todayRank : null,
     sceneMarker: input.sceneMarker?.trim() || null,
+    riskMarker: input.riskMarker?.trim() || null,
     tag: input.tag?.trim() ?? '',
     concept: input.concept?.trim() ?? '',
     markedDate: (() => {
@@ -118,6 +121,7 @@ function buildWatchRowFromCacheRecord(record: Record<string, unknown>) {
     postWatchReturnPct: normalizeNumberValue(record.post_watch_return_pct),
     todayRank: normalizeNumberValue(record.today_rank),
     sceneMarker: normalizeTextValue(record.scene_marker) || null,
+    riskMarker: normalizeTextValue(record.risk_marker) || null,
     tag: normalizeTextValue(record.tag),
     concept: normalizeTextValue(record.concept),
     markedDate: markedDate === '' ? null : markedDate,
@@ -172,6 +176,7 @@ export function writeWatchObserveRowsToCache(rows: WatchObserveRow[]) {
     post_watch_return_pct: row.postWatchReturnPct,
     today_rank: row.todayRank,
     scene_marker: row.sceneMarker,
+    risk_marker: row.riskMarker,
     tag: row.tag || undefined,
     concept: row.concept || undefined,
     marked_date: row.markedDate || undefined,